@@ -0,0 +1,189 @@
+//! Checkpoints anchored outside the log.
+//!
+//! A log opened [with checkpoints](crate::AuditLog::with_checkpoints) writes
+//! an [`AuditEvent::Checkpoint`](crate::AuditEvent::Checkpoint) every N
+//! entries, naming the chain hash it follows, and hands the same
+//! [`Checkpoint`] to each [`Anchor`]: a file on other storage, or a remote
+//! service. The chain alone cannot tell a log from a complete replacement
+//! with a fresh, self-consistent chain; [`verify_anchors`] can, because the
+//! anchored hashes must appear in the log.
+
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+
+use crate::event::{AuditEvent, AuditRecord};
+use crate::{segment, split_line, uncanonicalize};
+
+/// What is anchored: one line of an anchor file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub ts_ms: u64,
+    /// The log the checkpoint was taken of.
+    pub log: String,
+    /// Hex chain hash of the entry before the checkpoint.
+    pub hash: String,
+    /// Hex ed25519 signature over the hash's bytes, if the log is signed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// Somewhere checkpoints are kept out of reach of whoever can rewrite the
+/// log.
+pub trait Anchor: Send {
+    fn anchor(&mut self, checkpoint: &Checkpoint) -> Result<(), String>;
+}
+
+/// Appends checkpoints to a JSON lines file, ideally on other storage.
+pub struct FileAnchor {
+    path: PathBuf,
+}
+
+impl FileAnchor {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl Anchor for FileAnchor {
+    fn anchor(&mut self, checkpoint: &Checkpoint) -> Result<(), String> {
+        let line = serde_json::to_string(checkpoint).map_err(|e| e.to_string())?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        writeln!(file, "{line}")
+            .and_then(|()| file.sync_data())
+            .map_err(|e| format!("{}: {}", self.path.display(), e))
+    }
+}
+
+/// Check the checkpoints in `anchors` taken of the log at `path` against
+/// it, returning how many were found. The latest must be in the log; older
+/// ones must be too, unless they predate its oldest surviving entry or were
+/// [pruned](crate::retention). With
+/// `public_key`, signatures are checked as [`verify`](crate::verify) checks
+/// entries': once checkpoints are signed, every later one must be.
+pub fn verify_anchors(
+    path: &Path,
+    anchors: &Path,
+    public_key: Option<&[u8]>,
+) -> Result<u64, String> {
+    let key = public_key.map(|k| UnparsedPublicKey::new(&ED25519, k));
+    let mut hashes = HashSet::new();
+    let mut oldest = None;
+    let mut files = segment::segments(path)?;
+    files.push(path.to_path_buf());
+    for file in files.iter().filter(|f| f.exists()) {
+        for line in segment::lines(file)?.lines() {
+            let line = line.map_err(|e| format!("{}: {}", file.display(), e))?;
+            if let Some((hash, _, entry)) = split_line(&line) {
+                if oldest.is_none() {
+                    let record = AuditRecord::parse(&uncanonicalize(entry));
+                    oldest = match record.event {
+                        // Checkpoints up to the end of a pruned range went
+                        // with it.
+                        AuditEvent::Pruned { until_ms, .. } => Some(until_ms + 1),
+                        _ => (record.ts_ms > 0).then_some(record.ts_ms),
+                    };
+                }
+                hashes.insert(hash.to_string());
+            }
+        }
+    }
+
+    let file = std::fs::File::open(anchors).map_err(|e| format!("{}: {}", anchors.display(), e))?;
+    let log = path.display().to_string();
+    let canonical = std::fs::canonicalize(path).ok();
+    let mut checkpoints = Vec::new();
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("{}: {}", anchors.display(), e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let checkpoint: Checkpoint = serde_json::from_str(&line)
+            .map_err(|e| format!("{}:{}: {}", anchors.display(), n + 1, e))?;
+        // An anchor file may be shared by several logs.
+        if checkpoint.log == log || std::fs::canonicalize(&checkpoint.log).ok() == canonical {
+            checkpoints.push((n + 1, checkpoint));
+        }
+    }
+    let Some(latest) = checkpoints.iter().map(|(_, c)| c.ts_ms).max() else {
+        return Err(format!("{}: no checkpoints of {}", anchors.display(), log));
+    };
+    let mut found = 0;
+    let mut signed = false;
+    for (n, checkpoint) in &checkpoints {
+        let at = format!("{}:{}", anchors.display(), n);
+        if let Some(key) = &key {
+            match checkpoint.signature.as_deref().map(hex::decode) {
+                Some(Ok(sig))
+                    if hex::decode(&checkpoint.hash)
+                        .is_ok_and(|hash| key.verify(&hash, &sig).is_ok()) =>
+                {
+                    signed = true
+                }
+                Some(_) => return Err(format!("{at}: signature does not match the key")),
+                None if signed => {
+                    return Err(format!("{at}: not signed, but earlier checkpoints are"))
+                }
+                None => {}
+            }
+        }
+        if hashes.contains(&checkpoint.hash) {
+            found += 1;
+        } else if checkpoint.ts_ms == latest
+            || oldest.is_some_and(|oldest| checkpoint.ts_ms >= oldest)
+        {
+            return Err(format!(
+                "{at}: checkpoint {} is not in the log; it was replaced or rewritten",
+                checkpoint.hash
+            ));
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AuditLog;
+
+    #[test]
+    fn anchors_catch_a_replaced_log() {
+        let dir = std::env::temp_dir().join(format!("saf-audit-anchor-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("audit.log");
+        let anchors = dir.join("anchors.jsonl");
+
+        let mut log = AuditLog::new(&path)
+            .expect("open")
+            .with_checkpoints(2)
+            .with_anchor(FileAnchor::new(&anchors));
+        for i in 0..5 {
+            log.append(&format!("fs.read docs/{i}.md")).expect("append");
+        }
+        drop(log);
+        // Two checkpoints, after the second and fourth entries.
+        assert_eq!(verify_anchors(&path, &anchors, None), Ok(2));
+        assert_eq!(crate::verify(&path, None).map(|v| v.entries), Ok(7));
+
+        // A fresh, internally consistent log in its place.
+        std::fs::remove_file(&path).expect("remove");
+        std::fs::remove_file(crate::head_path(&path)).expect("remove head");
+        let mut log = AuditLog::new(&path).expect("open");
+        log.append("fs.read docs/0.md").expect("append");
+        drop(log);
+        assert!(crate::verify(&path, None).is_ok());
+        assert!(verify_anchors(&path, &anchors, None)
+            .is_err_and(|e| e.contains("was replaced or rewritten")));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
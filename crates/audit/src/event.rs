@@ -0,0 +1,715 @@
+//! Typed audit entries.
+//!
+//! Every entry is an [`AuditRecord`]: when it happened, its [`Code`], the
+//! outcome and the component responsible, with the event-specific fields
+//! of an [`AuditEvent`] alongside. Entries are stored as one JSON object per
+//! line, e.g.
+//!
+//! ```text
+//! {"schema":1,"ts_ms":1700000000000,"code":"fs.read_text","outcome":"ok","component":"app","event":"fs_read","path":"docs/a.md","bytes":5}
+//! ```
+//!
+//! `schema` is the [version](SCHEMA_VERSION) of this format the entry was
+//! written under. Within a version, fields and events are only added, and
+//! every optional field may be missing, so an entry reads the same in
+//! every later build. Entries from before the version was recorded are
+//! version 1. An entry from a newer version, or naming an event this
+//! build does not know, keeps the fields every record has and reads as
+//! [`AuditEvent::Unrecognized`] rather than being misread.
+//!
+//! Logs written before entries were typed hold `<code> key=value ...`
+//! lines; [`AuditRecord::parse`] reads those as [`AuditEvent::Legacy`].
+
+use std::collections::BTreeMap;
+
+use saf_codes::{Category, Code, Severity};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// The version of the entry format this build writes. It is bumped only
+/// for a change older builds would misread, such as a field changing
+/// meaning, and [`AuditRecord::parse`] then upgrades entries written under
+/// the earlier one.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Ok,
+    Denied,
+    /// Denied by a rule, but let through by a dry run.
+    WouldDeny,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    BrokerLifecycle {
+        version: String,
+    },
+    /// The broker shut down for `reason` during the run; `drained` if the
+    /// run ended before the broker stopped waiting for it.
+    BrokerShutdown {
+        reason: String,
+        drained: bool,
+    },
+    ComponentStart {
+        run_id: String,
+        sha256: String,
+    },
+    ComponentFinish {
+        run_id: String,
+        /// Wasm fuel the run burned, if it got as far as running the guest.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fuel_consumed: Option<u64>,
+    },
+    /// What a run that got as far as running the guest used: the fuel it
+    /// burned, the most linear memory it had, how many host calls it made,
+    /// and the bytes it read from and wrote to the workspace and fetched
+    /// from the network.
+    ComponentResources {
+        run_id: String,
+        fuel_consumed: u64,
+        peak_memory_bytes: u64,
+        host_calls: u64,
+        bytes_read: u64,
+        bytes_written: u64,
+        bytes_fetched: u64,
+    },
+    /// A run's host calls were recorded to the trace at `path`, or, for
+    /// `mode` `replay`, answered from it.
+    ComponentTrace {
+        run_id: String,
+        mode: String,
+        path: String,
+    },
+    /// A run was interrupted at its timeout.
+    ComponentTimedOut {
+        run_id: String,
+        timeout_secs: u64,
+    },
+    /// A strict run was ended at its first denial: `denial` is its code,
+    /// and `request` the host call it was made serving.
+    StrictViolation {
+        run_id: String,
+        denial: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        capability: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request: Option<String>,
+    },
+    /// An isolated run's process exited, with `status`, before the run
+    /// ended.
+    ComponentCrashed {
+        run_id: String,
+        status: String,
+    },
+    /// An isolated run's process was confined by `layers`, such as
+    /// `landlock` or `appcontainer`; `missing` are those that could not be
+    /// applied, with why.
+    ComponentSandboxed {
+        run_id: String,
+        layers: Vec<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        missing: Vec<String>,
+    },
+    /// A run was trapped for reaching a limit of its policy: the limit, as
+    /// a policy field such as `max_fuel`, and its value.
+    LimitExceeded {
+        run_id: String,
+        limit: String,
+        value: u64,
+    },
+    /// A capability manifest was granted, or could not be.
+    ComponentCapabilities {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        interfaces: Vec<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        missing: Vec<String>,
+    },
+    /// Component output recorded when the run has no log of its own.
+    ComponentLog {
+        message: String,
+    },
+    /// A line the component wrote to `stream`, `stdout` or `stderr`.
+    ComponentOutput {
+        stream: String,
+        text: String,
+        /// Bytes cut from the end of an overlong line.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        truncated: Option<u64>,
+        /// Output left out once the run reached its limit; on the stream's
+        /// last entry.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dropped: Option<u64>,
+    },
+    /// A run started by a schedule, with when it was due (unix seconds)
+    /// and the fires since the schedule last ran that were skipped, as its
+    /// run was still going, or missed while the broker was down.
+    ScheduledRun {
+        schedule: String,
+        due_unix: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        overlapped: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        missed: Option<u64>,
+    },
+    /// A run that is one stage of a pipeline, at `position` (from 1) of
+    /// `stages`.
+    PipelineStage {
+        pipeline: String,
+        pipeline_id: String,
+        stage: String,
+        position: u64,
+        stages: u64,
+    },
+    DemoCreated {
+        components: u64,
+    },
+    FsMount {
+        name: String,
+        path: String,
+        read_only: bool,
+    },
+    FsList {
+        path: String,
+    },
+    FsRead {
+        path: String,
+        bytes: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        offset: Option<u64>,
+    },
+    FsWrite {
+        path: String,
+        bytes: u64,
+        append: bool,
+    },
+    NetFetch {
+        url: String,
+        status: u16,
+        bytes: u64,
+    },
+    NetStreamOpen {
+        url: String,
+        stream: u64,
+    },
+    NetStreamClose {
+        stream: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        bytes: Option<u64>,
+        /// Code of the error that ended the stream.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    NetRewritten {
+        from: String,
+        to: String,
+    },
+    NetResolved {
+        host: String,
+        via: String,
+        addr: String,
+    },
+    NetClientCert {
+        domain: String,
+        sha256: String,
+    },
+    NetRateLimited {
+        domain: String,
+        retry_after_ms: u64,
+    },
+    NetOffline {
+        source: String,
+    },
+    WsConnect {
+        url: String,
+        conn: u64,
+    },
+    WsClose {
+        conn: u64,
+        by_peer: bool,
+    },
+    SysinfoRead {
+        field: String,
+    },
+    ConfigRead {
+        key: String,
+        /// Whether the key had a value.
+        found: bool,
+    },
+    /// An operation failed other than by a policy decision, such as on
+    /// an invalid path or a host error; the record's code says how.
+    OperationFailed {
+        capability: String,
+        /// Code the operation is audited under when it succeeds.
+        operation: String,
+        target: String,
+        reason: String,
+    },
+    /// A rule decided against an operation; the record's code says why.
+    PolicyDenied {
+        capability: String,
+        target: String,
+        rule: String,
+    },
+    PolicyAskAnswered {
+        capability: String,
+        target: String,
+        rule: String,
+        answer: String,
+        source: String,
+    },
+    PolicyLoaded {
+        source: String,
+        sha256: String,
+    },
+    PolicyReloaded {
+        source: String,
+        old_sha256: String,
+        new_sha256: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        key: Option<String>,
+    },
+    PolicyReloadFailed {
+        source: String,
+        sha256: String,
+        rejected_sha256: String,
+        error: String,
+    },
+    /// A policy signature was checked: `key` on success, `error` if not.
+    PolicySignature {
+        source: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        key: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    PolicySelected {
+        sha256: String,
+        rule: String,
+    },
+    PolicyTrial {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        runs: Option<u64>,
+    },
+    PolicyNarrowed {
+        domains: Vec<String>,
+        paths: Vec<String>,
+        source: String,
+    },
+    /// An elevated session began or ended, or an operation needed one.
+    Elevation {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        session: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires_ms: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        operation: Option<String>,
+    },
+    /// First entry after a rotation: the segment rotated out and its final
+    /// chain hash, which this entry's hash continues from.
+    Link {
+        previous: String,
+        previous_hash: String,
+    },
+    /// The log continues one handed off from another machine: that machine,
+    /// the log as named there, the chain hash handed over and how many
+    /// entries led to it.
+    Migrated {
+        host: String,
+        log: String,
+        previous_hash: String,
+        entries: u64,
+    },
+    /// The chain hash of the entry before, as anchored outside the log.
+    Checkpoint {
+        hash: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
+    },
+    /// Stands in for segments deleted by retention: the chain hash the
+    /// range continued from, its final hash, and what it held.
+    Pruned {
+        from_hash: String,
+        last_hash: String,
+        files: u64,
+        entries: u64,
+        /// Timestamps of the first and last entries; 0 if unknown.
+        from_ms: u64,
+        until_ms: u64,
+        /// Entries by outcome.
+        outcomes: BTreeMap<String, u64>,
+    },
+    /// An entry from before entries were typed, as written.
+    Legacy {
+        message: String,
+    },
+    /// A typed entry this build cannot read, as written: one from a newer
+    /// schema, or with an event or fields it does not know.
+    Unrecognized {
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// The [version](SCHEMA_VERSION) of the format the entry was written
+    /// under; 0 for legacy entries.
+    #[serde(default = "first_schema")]
+    pub schema: u32,
+    /// Milliseconds since the Unix epoch; 0 for legacy entries.
+    pub ts_ms: u64,
+    pub code: String,
+    pub outcome: Outcome,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub component: String,
+    /// Hex SHA-256 of the component's file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub component_sha256: Option<String>,
+    /// The run of the component the entry belongs to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub component_run: Option<String>,
+    /// Version the component's manifest declares.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub component_version: Option<String>,
+    /// Publisher the component's manifest declares.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub component_publisher: Option<String>,
+    /// The host call the entry was made serving.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request: Option<String>,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+    /// Elevated session in effect, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elevated: Option<String>,
+}
+
+/// Who performs operations: a component, by its installed name (or file
+/// stem), the SHA-256 of its file and the run, or the broker itself. A
+/// component with a manifest also carries the version and publisher it
+/// declares.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ComponentIdentity {
+    pub name: String,
+    pub sha256: Option<String>,
+    pub run_id: Option<String>,
+    pub version: Option<String>,
+    pub publisher: Option<String>,
+}
+
+impl ComponentIdentity {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_sha256(mut self, sha256: &str) -> Self {
+        self.sha256 = Some(sha256.to_string());
+        self
+    }
+
+    pub fn with_run_id(mut self, run_id: &str) -> Self {
+        self.run_id = Some(run_id.to_string());
+        self
+    }
+
+    /// The version and publisher declared for the component.
+    pub fn with_declared(mut self, version: &str, publisher: &str) -> Self {
+        self.version = Some(version.to_string());
+        self.publisher = Some(publisher.to_string());
+        self
+    }
+}
+
+impl AuditRecord {
+    /// A successful `event` happening now.
+    pub fn new(code: Code, event: AuditEvent) -> Self {
+        Self {
+            schema: SCHEMA_VERSION,
+            ts_ms: now_ms(),
+            code: code.as_str().to_string(),
+            outcome: Outcome::Ok,
+            component: String::new(),
+            component_sha256: None,
+            component_run: None,
+            component_version: None,
+            component_publisher: None,
+            request: None,
+            event,
+            elevated: None,
+        }
+    }
+
+    pub fn with_outcome(mut self, outcome: Outcome) -> Self {
+        self.outcome = outcome;
+        self
+    }
+
+    pub fn with_component(mut self, component: &str) -> Self {
+        self.component = component.to_string();
+        self
+    }
+
+    /// Attribute the entry to `identity`: its name, content, run and
+    /// declared version and publisher.
+    pub fn with_identity(mut self, identity: &ComponentIdentity) -> Self {
+        self.component = identity.name.clone();
+        self.component_sha256 = identity.sha256.clone();
+        self.component_run = identity.run_id.clone();
+        self.component_version = identity.version.clone();
+        self.component_publisher = identity.publisher.clone();
+        self
+    }
+
+    pub fn with_request(mut self, request: &str) -> Self {
+        self.request = Some(request.to_string());
+        self
+    }
+
+    pub fn with_elevation(mut self, session: &str) -> Self {
+        self.elevated = Some(session.to_string());
+        self
+    }
+
+    /// Its code's severity, raised to at least [`Severity::Warn`] when
+    /// something was denied or failed. Unknown codes are
+    /// [`Severity::Info`].
+    pub fn severity(&self) -> Severity {
+        let base = Code::parse(&self.code).map_or(Severity::Info, Code::severity);
+        match self.outcome {
+            Outcome::Ok => base,
+            _ => base.max(Severity::Warn),
+        }
+    }
+
+    pub fn category(&self) -> Category {
+        Category::of(&self.code)
+    }
+
+    /// Read a stored entry, typed or legacy.
+    pub fn parse(entry: &str) -> Self {
+        if let Ok(Value::Object(fields)) = serde_json::from_str(entry) {
+            return Self::typed(fields, entry);
+        }
+        let (code, fields) = entry.split_once(' ').unwrap_or((entry, ""));
+        let field = |key: &str| {
+            fields
+                .split(' ')
+                .find_map(|f| f.strip_prefix(key)?.strip_prefix('='))
+        };
+        // Denials were written as `<code> capability=.. target=.. rule=..`.
+        let outcome = if code == Code::PolicyWouldDeny.as_str() {
+            Outcome::WouldDeny
+        } else if field("answer").is_some_and(|a| a.starts_with("deny"))
+            || (fields.starts_with("capability=") && field("answer").is_none())
+        {
+            Outcome::Denied
+        } else {
+            Outcome::Ok
+        };
+        // Dry-run entries named the code that would have applied.
+        let code = match outcome {
+            Outcome::WouldDeny => field("code").unwrap_or(code),
+            _ => code,
+        };
+        Self {
+            schema: 0,
+            ts_ms: 0,
+            code: code.to_string(),
+            outcome,
+            component: field("component").unwrap_or_default().to_string(),
+            component_sha256: None,
+            component_run: None,
+            component_version: None,
+            component_publisher: None,
+            request: None,
+            event: AuditEvent::Legacy {
+                message: entry.to_string(),
+            },
+            elevated: field("elevated").map(str::to_string),
+        }
+    }
+
+    /// The typed entry `entry`, parsed as `fields`.
+    fn typed(fields: Map<String, Value>, entry: &str) -> Self {
+        let schema = fields
+            .get("schema")
+            .map_or(Some(first_schema()), |v| {
+                v.as_u64().and_then(|v| u32::try_from(v).ok())
+            })
+            .unwrap_or(u32::MAX);
+        if schema <= SCHEMA_VERSION {
+            if let Ok(record) = serde_json::from_value(Value::Object(fields.clone())) {
+                return record;
+            }
+        }
+        let text = |key: &str| fields.get(key).and_then(Value::as_str).map(str::to_string);
+        Self {
+            schema,
+            ts_ms: fields.get("ts_ms").and_then(Value::as_u64).unwrap_or(0),
+            code: text("code").unwrap_or_default(),
+            // An outcome added since is at least not a success this build
+            // knows of.
+            outcome: fields
+                .get("outcome")
+                .and_then(|o| serde_json::from_value(o.clone()).ok())
+                .unwrap_or(Outcome::Failed),
+            component: text("component").unwrap_or_default(),
+            component_sha256: text("component_sha256"),
+            component_run: text("component_run"),
+            component_version: text("component_version"),
+            component_publisher: text("component_publisher"),
+            request: text("request"),
+            event: AuditEvent::Unrecognized {
+                message: entry.to_string(),
+            },
+            elevated: text("elevated"),
+        }
+    }
+}
+
+/// The schema of typed entries that do not say.
+fn first_schema() -> u32 {
+    1
+}
+
+pub(crate) fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_entries_round_trip_and_legacy_lines_still_read() {
+        let record = AuditRecord::new(
+            Code::FsReadText,
+            AuditEvent::FsRead {
+                path: "docs/a.md".to_string(),
+                bytes: 5,
+                offset: None,
+            },
+        )
+        .with_component("app");
+        let json = serde_json::to_string(&record).expect("json");
+        assert!(json.contains(r#"{"schema":1,"ts_ms":"#), "{json}");
+        assert!(json.contains(r#""code":"fs.read_text","outcome":"ok","component":"app","event":"fs_read","path":"docs/a.md","bytes":5"#), "{json}");
+        assert_eq!(AuditRecord::parse(&json), record);
+        // Typed entries from before the schema was recorded.
+        let unversioned = json.replacen(r#""schema":1,"#, "", 1);
+        assert_eq!(AuditRecord::parse(&unversioned), record);
+
+        // Entries this build cannot read keep what every record has.
+        for entry in [
+            r#"{"schema":1,"ts_ms":5,"code":"fs.sync","outcome":"ok","component":"app","event":"fs_sync","path":"a"}"#,
+            r#"{"schema":2,"ts_ms":5,"code":"fs.sync","outcome":"ok","component":"app","event":"fs_read","path":"a","bytes":1}"#,
+        ] {
+            let newer = AuditRecord::parse(entry);
+            assert_eq!(
+                (newer.ts_ms, newer.code.as_str(), newer.outcome),
+                (5, "fs.sync", Outcome::Ok)
+            );
+            assert_eq!(newer.component, "app");
+            assert_eq!(
+                newer.event,
+                AuditEvent::Unrecognized {
+                    message: entry.to_string()
+                }
+            );
+        }
+        let odd = AuditRecord::parse(r#"{"schema":2,"code":"fs.sync","outcome":"quarantined"}"#);
+        assert_eq!((odd.schema, odd.outcome), (2, Outcome::Failed));
+
+        // A component's entries carry its content hash and run, apart from
+        // the event's own fields.
+        let start = AuditRecord::new(
+            Code::ComponentStart,
+            AuditEvent::ComponentStart {
+                run_id: "r1".to_string(),
+                sha256: "ab".to_string(),
+            },
+        )
+        .with_identity(
+            &ComponentIdentity::new("app")
+                .with_sha256("ab")
+                .with_run_id("r1"),
+        );
+        let json = serde_json::to_string(&start).expect("json");
+        assert!(json.contains(r#""component":"app","component_sha256":"ab","component_run":"r1","event":"component_start","run_id":"r1""#), "{json}");
+        assert_eq!(AuditRecord::parse(&json), start);
+        let declared = start.with_identity(
+            &ComponentIdentity::new("app")
+                .with_sha256("ab")
+                .with_declared("1.2.0", "Example Ltd"),
+        );
+        let json = serde_json::to_string(&declared).expect("json");
+        assert!(json.contains(r#""component_sha256":"ab","component_version":"1.2.0","component_publisher":"Example Ltd""#), "{json}");
+        assert_eq!(AuditRecord::parse(&json), declared);
+        // Entries from before runs were metered read without their fuel.
+        let finish = AuditRecord::parse(
+            r#"{"schema":1,"ts_ms":1,"code":"component.finish","outcome":"ok","event":"component_finish","run_id":"r1"}"#,
+        );
+        assert_eq!(
+            finish.event,
+            AuditEvent::ComponentFinish {
+                run_id: "r1".to_string(),
+                fuel_consumed: None,
+            }
+        );
+
+        let denied = AuditRecord::parse(
+            "policy.path_denied capability=fs target=secrets/key rule=deny[0] component=app elevated=s1",
+        );
+        assert_eq!(denied.code, "policy.path_denied");
+        assert_eq!(denied.outcome, Outcome::Denied);
+        assert_eq!(denied.component, "app");
+        assert_eq!(denied.elevated.as_deref(), Some("s1"));
+        let dry = AuditRecord::parse(
+            "policy.would_deny code=policy.path_denied capability=fs target=a rule=deny[0] component=app",
+        );
+        assert_eq!(
+            (dry.code.as_str(), dry.outcome),
+            ("policy.path_denied", Outcome::WouldDeny)
+        );
+        let start = AuditRecord::parse("broker.start");
+        assert_eq!(
+            (
+                start.code.as_str(),
+                start.outcome,
+                start.ts_ms,
+                start.schema
+            ),
+            ("broker.start", Outcome::Ok, 0, 0)
+        );
+        assert!(matches!(start.event, AuditEvent::Legacy { .. }));
+        assert_eq!(
+            (start.severity(), start.category()),
+            (Severity::Info, Category::Lifecycle)
+        );
+        let failed = AuditRecord::new(
+            Code::FsReadText,
+            AuditEvent::FsRead {
+                path: "docs/a.md".to_string(),
+                bytes: 0,
+                offset: None,
+            },
+        )
+        .with_outcome(Outcome::Failed);
+        assert_eq!(
+            (failed.severity(), failed.category()),
+            (Severity::Warn, Category::Fs)
+        );
+        assert_eq!(denied.severity(), Severity::Security);
+    }
+}
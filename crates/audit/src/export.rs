@@ -0,0 +1,356 @@
+//! Forwarding audit records to a SIEM.
+//!
+//! An [`Exporter`] sends each record, in addition to the chained file, as
+//! an RFC 5424 syslog message whose MSG is the record's JSON, or as an
+//! ArcSight CEF line. Either goes to `udp://host:port`, `tcp://host:port`
+//! (octet-counted framing, RFC 6587) or is appended to a file.
+//!
+//! The chained file stays the record of truth: a collector that is down
+//! misses records instead of stopping the broker, and a TCP connection is
+//! retried with the next record.
+//!
+//! Other destinations implement [`AuditSink`].
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::{TcpStream, UdpSocket};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use saf_codes::{Code, Severity};
+use serde::Deserialize;
+
+use crate::event::{AuditRecord, Outcome};
+
+const VENDOR: &str = "secure-app-framework";
+const PRODUCT: &str = "broker";
+/// `log audit`.
+const DEFAULT_FACILITY: u8 = 13;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// RFC 5424 syslog.
+    Syslog,
+    /// ArcSight Common Event Format.
+    Cef,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportConfig {
+    pub format: ExportFormat,
+    /// `udp://host:port`, `tcp://host:port`, or a file path.
+    pub target: String,
+    /// Syslog facility, 0 to 23.
+    #[serde(default = "default_facility")]
+    pub facility: u8,
+    /// Host name to report; the local one by default.
+    #[serde(default)]
+    pub hostname: Option<String>,
+}
+
+fn default_facility() -> u8 {
+    DEFAULT_FACILITY
+}
+
+/// Somewhere records go besides the chained file.
+pub trait AuditSink: Send {
+    /// Deliver `record`, or give up on it; the caller does not retry.
+    fn export(&mut self, record: &AuditRecord) -> Result<(), String>;
+}
+
+enum Transport {
+    Udp(UdpSocket),
+    Tcp {
+        addr: String,
+        stream: Option<TcpStream>,
+    },
+    File(File),
+}
+
+pub struct Exporter {
+    format: ExportFormat,
+    facility: u8,
+    hostname: String,
+    transport: Transport,
+}
+
+impl Exporter {
+    pub fn new(config: &ExportConfig) -> Result<Self, String> {
+        if config.facility > 23 {
+            return Err(format!("facility {}: expected 0 to 23", config.facility));
+        }
+        let transport = if let Some(addr) = config.target.strip_prefix("udp://") {
+            let socket = UdpSocket::bind(("0.0.0.0", 0))
+                .and_then(|s| s.connect(addr).map(|()| s))
+                .map_err(|e| format!("{}: {}", config.target, e))?;
+            Transport::Udp(socket)
+        } else if let Some(addr) = config.target.strip_prefix("tcp://") {
+            Transport::Tcp {
+                addr: addr.to_string(),
+                stream: None,
+            }
+        } else if config.target.contains("://") {
+            return Err(format!(
+                "{}: expected udp://, tcp:// or a file path",
+                config.target
+            ));
+        } else {
+            let path = PathBuf::from(&config.target);
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            Transport::File(file)
+        };
+        Ok(Self {
+            format: config.format,
+            facility: config.facility,
+            hostname: config.hostname.clone().unwrap_or_else(local_hostname),
+            transport,
+        })
+    }
+
+    /// `record` as this exporter sends it, without framing.
+    pub fn message(&self, record: &AuditRecord) -> Result<String, String> {
+        match self.format {
+            ExportFormat::Syslog => syslog(record, self.facility, &self.hostname),
+            ExportFormat::Cef => cef(record, &self.hostname),
+        }
+    }
+}
+
+impl AuditSink for Exporter {
+    fn export(&mut self, record: &AuditRecord) -> Result<(), String> {
+        let message = self.message(record)?;
+        match &mut self.transport {
+            Transport::Udp(socket) => socket
+                .send(message.as_bytes())
+                .map(drop)
+                .map_err(|e| e.to_string()),
+            Transport::Tcp { addr, stream } => {
+                let framed = format!("{} {}", message.len(), message);
+                let connected = match stream {
+                    Some(s) => s,
+                    None => stream.insert(connect(addr)?),
+                };
+                connected.write_all(framed.as_bytes()).map_err(|e| {
+                    // Reconnect next time.
+                    *stream = None;
+                    format!("{addr}: {e}")
+                })
+            }
+            Transport::File(file) => writeln!(file, "{message}").map_err(|e| e.to_string()),
+        }
+    }
+}
+
+fn connect(addr: &str) -> Result<TcpStream, String> {
+    use std::net::ToSocketAddrs;
+
+    let mut last = format!("{addr}: no address");
+    for a in addr.to_socket_addrs().map_err(|e| format!("{addr}: {e}"))? {
+        match TcpStream::connect_timeout(&a, CONNECT_TIMEOUT) {
+            Ok(s) => return Ok(s),
+            Err(e) => last = format!("{addr}: {e}"),
+        }
+    }
+    Err(last)
+}
+
+fn local_hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty() && h.chars().all(|c| c.is_ascii_graphic()))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// The severity of a record's code, and whether it reports something
+/// denied or failed, which is reported at least as a warning.
+fn severity(record: &AuditRecord) -> (Severity, bool) {
+    let base = Code::parse(&record.code).map_or(Severity::Info, Code::severity);
+    (base, record.outcome != Outcome::Ok)
+}
+
+/// An RFC 5424 message: `<PRI>1 TIMESTAMP HOST APP PROCID MSGID - MSG`,
+/// with the code as MSGID and the record's JSON as MSG.
+pub fn syslog(record: &AuditRecord, facility: u8, hostname: &str) -> Result<String, String> {
+    let (base, failed) = severity(record);
+    let level = match base {
+        Severity::Info => 6,
+        Severity::Security => 5,
+        Severity::Warn => 4,
+        Severity::Error => 3,
+    };
+    let level = if failed { level.min(4) } else { level };
+    let json = serde_json::to_string(record).map_err(|e| e.to_string())?;
+    let msgid: String = record
+        .code
+        .chars()
+        .filter(char::is_ascii_graphic)
+        .take(32)
+        .collect();
+    Ok(format!(
+        "<{}>1 {} {} {} {} {} - {}",
+        u16::from(facility) * 8 + level,
+        rfc3339(record.ts_ms),
+        hostname,
+        VENDOR,
+        std::process::id(),
+        if msgid.is_empty() { "-" } else { &msgid },
+        json
+    ))
+}
+
+/// A CEF line: the code as signature ID, the event kind as name, and the
+/// event's fields in `msg` as JSON.
+pub fn cef(record: &AuditRecord, hostname: &str) -> Result<String, String> {
+    let (base, failed) = severity(record);
+    let level = match base {
+        Severity::Info => 3,
+        Severity::Warn => 5,
+        Severity::Error => 7,
+        Severity::Security => 8,
+    };
+    let level = if failed { level.max(5) } else { level };
+    let serde_json::Value::Object(mut fields) =
+        serde_json::to_value(&record.event).map_err(|e| e.to_string())?
+    else {
+        return Err("event is not an object".to_string());
+    };
+    let name = match fields.remove("event") {
+        Some(serde_json::Value::String(name)) => name,
+        _ => record.code.clone(),
+    };
+    let outcome = serde_json::to_value(record.outcome)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default();
+    let mut extension = vec![
+        ("rt", record.ts_ms.to_string()),
+        ("dvchost", hostname.to_string()),
+        ("outcome", outcome),
+    ];
+    if !record.component.is_empty() {
+        extension.push(("cs1Label", "component".to_string()));
+        extension.push(("cs1", record.component.clone()));
+    }
+    if let Some(session) = &record.elevated {
+        extension.push(("cs2Label", "elevated".to_string()));
+        extension.push(("cs2", session.clone()));
+    }
+    extension.push(("msg", serde_json::Value::Object(fields).to_string()));
+    let extension: Vec<String> = extension
+        .into_iter()
+        .map(|(k, v)| format!("{k}={}", cef_value(&v)))
+        .collect();
+    Ok(format!(
+        "CEF:0|{}|{}|{}|{}|{}|{}|{}",
+        cef_header(VENDOR),
+        cef_header(PRODUCT),
+        cef_header(env!("CARGO_PKG_VERSION")),
+        cef_header(&record.code),
+        cef_header(&name),
+        level,
+        extension.join(" ")
+    ))
+}
+
+fn cef_header(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace(['\r', '\n'], " ")
+}
+
+fn cef_value(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// `ms` since the Unix epoch as an RFC 3339 UTC timestamp.
+fn rfc3339(ms: u64) -> String {
+    if ms == 0 {
+        return "-".to_string();
+    }
+    let secs = ms / 1000;
+    let (h, m, s) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = secs / 86_400 + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{h:02}:{m:02}:{s:02}.{:03}Z",
+        ms % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AuditEvent;
+
+    #[test]
+    fn syslog_and_cef_lines() {
+        let mut record = AuditRecord::new(
+            Code::PolicyPathDenied,
+            AuditEvent::PolicyDenied {
+                capability: "fs".to_string(),
+                target: "a=b|c".to_string(),
+                rule: "deny[0]".to_string(),
+            },
+        )
+        .with_outcome(Outcome::Denied)
+        .with_component("app");
+        record.ts_ms = 1_700_000_000_123;
+
+        let line = syslog(&record, 13, "host1").expect("syslog");
+        assert!(
+            line.starts_with("<108>1 2023-11-14T22:13:20.123Z host1 secure-app-framework "),
+            "{line}"
+        );
+        assert!(
+            line.contains(" policy.path_denied - {\"schema\":1,\"ts_ms\":1700000000123,"),
+            "{line}"
+        );
+
+        let line = cef(&record, "host1").expect("cef");
+        assert!(
+            line.starts_with("CEF:0|secure-app-framework|broker|"),
+            "{line}"
+        );
+        assert!(
+            line.contains("|policy.path_denied|policy_denied|8|rt=1700000000123 dvchost=host1 outcome=denied cs1Label=component cs1=app msg="),
+            "{line}"
+        );
+        assert!(line.contains(r#""target":"a\=b|c""#), "{line}");
+
+        let path = std::env::temp_dir().join(format!("saf-audit-cef-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut exporter = Exporter::new(&ExportConfig {
+            format: ExportFormat::Cef,
+            target: path.display().to_string(),
+            facility: DEFAULT_FACILITY,
+            hostname: Some("host1".to_string()),
+        })
+        .expect("exporter");
+        exporter.export(&record).expect("export");
+        assert_eq!(
+            std::fs::read_to_string(&path).expect("read"),
+            format!("{line}\n")
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,180 @@
+//! Moving a log to another machine.
+//!
+//! A workspace moved to a new machine keeps its audit chain rather than
+//! starting a fresh one. On the old machine, [`handoff`] verifies the log
+//! and captures its head in a [`Handoff`], signed if the log is. On the new
+//! one, [`AuditLog::continue_from`] checks the handoff and continues the
+//! chain from it with an `audit.migrated` entry naming where it came from:
+//!
+//! - A log that came along with the workspace must end at the handed-off
+//!   head, and the entry is appended to it.
+//! - A log that stayed behind (archived, say) is continued by a new one
+//!   starting with a [link](AuditEvent::Link) to the handed-off head, which
+//!   [`verify`](crate::verify) takes on trust like a link to a segment
+//!   archived elsewhere.
+
+use std::io::Write;
+use std::path::Path;
+
+use ring::signature::{Ed25519KeyPair, UnparsedPublicKey, ED25519};
+use saf_codes::Code;
+use serde::{Deserialize, Serialize};
+
+use crate::event::{AuditEvent, AuditRecord};
+use crate::{event, segments, verify, AuditLog, ChainHash};
+
+/// The state a log hands to its continuation: one JSON document.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Handoff {
+    pub ts_ms: u64,
+    /// The machine the log was handed off from.
+    pub host: String,
+    /// The log, as named there.
+    pub log: String,
+    /// Hex chain hash of its last entry.
+    pub head: String,
+    pub entries: u64,
+    /// Hex ed25519 signature over the head's bytes, if the log is signed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// Verify the chain of the log at `path` on `host` and capture its head,
+/// signed with the ed25519 key in `pkcs8`, if given.
+pub fn handoff(path: &Path, host: &str, pkcs8: Option<&[u8]>) -> Result<Handoff, String> {
+    let verified = verify(path, None)?;
+    if verified.entries == 0 {
+        return Err(format!("{}: no entries to hand off", path.display()));
+    }
+    let head = ChainHash::parse(&verified.head).ok_or("the log's head is not a hash")?;
+    let signature = match pkcs8 {
+        Some(pkcs8) => {
+            let pair = Ed25519KeyPair::from_pkcs8(pkcs8)
+                .map_err(|e| format!("invalid audit signing key: {e}"))?;
+            Some(hex::encode(pair.sign(head.0.as_bytes())))
+        }
+        None => None,
+    };
+    Ok(Handoff {
+        ts_ms: event::now_ms(),
+        host: host.to_string(),
+        log: path.display().to_string(),
+        head: head.hex(),
+        entries: verified.entries,
+        signature,
+    })
+}
+
+impl AuditLog {
+    /// Continue the chain `handoff` captured, in this log: either the
+    /// handed-off log itself or one with no entries yet. With `public_key`,
+    /// the handoff must be signed with its key.
+    pub fn continue_from(
+        &mut self,
+        handoff: &Handoff,
+        public_key: Option<&[u8]>,
+    ) -> Result<(), String> {
+        let head = ChainHash::parse(&handoff.head).ok_or("the handoff's head is not a hash")?;
+        if let Some(key) = public_key {
+            match handoff.signature.as_deref().map(hex::decode) {
+                Some(Ok(sig))
+                    if UnparsedPublicKey::new(&ED25519, key)
+                        .verify(head.0.as_bytes(), &sig)
+                        .is_ok() => {}
+                Some(_) => return Err("the handoff's signature does not match the key".to_string()),
+                None => return Err("the handoff is not signed".to_string()),
+            }
+        }
+        self.locked(|log| {
+            let now = event::now_ms();
+            let fresh =
+                log.size == 0 && log.state == ChainHash::new() && segments(&log.path)?.is_empty();
+            if fresh {
+                log.state = head;
+                let link = AuditRecord::new(
+                    Code::AuditLinked,
+                    AuditEvent::Link {
+                        previous: format!("{}:{}", handoff.host, handoff.log),
+                        previous_hash: head.hex(),
+                    },
+                );
+                log.write(
+                    &serde_json::to_string(&link).map_err(|e| e.to_string())?,
+                    now,
+                )?;
+            } else if log.state != head {
+                return Err(format!(
+                    "{} does not end at the head handed off from {}:{}",
+                    log.path.display(),
+                    handoff.host,
+                    handoff.log
+                ));
+            }
+            log.file.flush().map_err(|e| e.to_string())
+        })?;
+        self.record(&AuditRecord::new(
+            Code::AuditMigrated,
+            AuditEvent::Migrated {
+                host: handoff.host.clone(),
+                log: handoff.log.clone(),
+                previous_hash: head.hex(),
+                entries: handoff.entries,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::KeyPair;
+
+    #[test]
+    fn moved_logs_continue_the_chain_whether_or_not_they_came_along() {
+        let dir = std::env::temp_dir().join(format!("saf-audit-handoff-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let old = dir.join("old").join("audit.log");
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).expect("key");
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).expect("pair");
+        let mut log = AuditLog::new(&old).expect("open");
+        log.append("fs.read docs/a.md").expect("append");
+        log.append("fs.read docs/b.md").expect("append");
+        let head = log.head();
+        drop(log);
+        let handoff = handoff(&old, "ws7", Some(pkcs8.as_ref())).expect("handoff");
+        assert_eq!((handoff.head.as_str(), handoff.entries), (head.as_str(), 2));
+
+        // The log stayed behind.
+        let new = dir.join("new").join("audit.log");
+        let mut log = AuditLog::new(&new).expect("open");
+        let other = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).expect("key");
+        let other = Ed25519KeyPair::from_pkcs8(other.as_ref()).expect("pair");
+        assert_eq!(
+            log.continue_from(&handoff, Some(other.public_key().as_ref())),
+            Err("the handoff's signature does not match the key".to_string())
+        );
+        log.continue_from(&handoff, Some(pair.public_key().as_ref()))
+            .expect("continue");
+        log.append("fs.read docs/c.md").expect("append");
+        drop(log);
+        assert_eq!(verify(&new, None).map(|v| v.entries), Ok(3));
+        let records = crate::read(&new).expect("read");
+        assert_eq!(
+            records[0].event,
+            AuditEvent::Link {
+                previous: format!("ws7:{}", old.display()),
+                previous_hash: head.clone(),
+            }
+        );
+        assert_eq!(records[1].code, Code::AuditMigrated.as_str());
+
+        // The log came along, and must still end where it was handed off.
+        let mut log = AuditLog::new(&old).expect("open");
+        log.continue_from(&handoff, None).expect("continue");
+        drop(log);
+        assert_eq!(verify(&old, None).map(|v| v.entries), Ok(3));
+        let mut log = AuditLog::new(&old).expect("open");
+        assert!(log.continue_from(&handoff, None).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
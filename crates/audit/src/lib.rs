@@ -1,56 +1,1110 @@
 #![forbid(unsafe_code)]
 
-use std::fs::{create_dir_all, OpenOptions};
-use std::hash::{Hash, Hasher};
-use std::io::{BufWriter, Write};
+//! Append-only, hash-chained audit log.
+//!
+//! Each line is `<hash>|<entry>`. `hash` is the hex BLAKE3 digest of the
+//! previous line's hash (32 zero bytes before the first line) followed by
+//! the entry, so editing, dropping or reordering lines breaks every hash
+//! after it. Entries are canonicalized to one line: `\`, CR and LF are
+//! escaped as `\\`, `\r` and `\n`.
+//!
+//! The head (the hash of the last line) is also kept in `<log>.head`, so a
+//! reopened log continues its chain, and a log that no longer reaches its
+//! recorded head is refused rather than silently restarted. The hash of
+//! the active file's first line is kept with it, telling a file that lost
+//! entries from one that was replaced outright.
+//!
+//! Writers of the same log, in this process or others, take turns: each
+//! holds a lock on `<log>.lock` while it appends, and first picks up
+//! whatever the others appended since, so every entry continues the one
+//! before it on disk rather than the last one this writer wrote.
+//!
+//! The active file is only ever opened for appending, and a log opened
+//! [append-only](AuditLog::with_append_only) also has the platform refuse
+//! anything else, where it can.
+//!
+//! A log opened [with a signing key](AuditLog::with_signing_key) also
+//! signs every entry's hash with ed25519 (`<hash>:<signature>|<entry>`), so
+//! rewriting the file and recomputing the chain is not enough to forge it
+//! without the key. [`verify`] given the public key checks the signatures.
+//!
+//! A log opened [with rotation](AuditLog::with_rotation) moves full or old
+//! files aside into [segments](segment), continuing the chain across them.
+//! An [`AuditReader`] queries the entries of all of them. A log opened
+//! [with retention](AuditLog::with_retention) prunes old segments, leaving
+//! a summary that keeps what remains verifiable.
+//!
+//! A log opened [with checkpoints](AuditLog::with_checkpoints) records
+//! and [anchors](checkpoint) its chain hash every N entries, so even a
+//! wholesale replacement of the log is detected. A [`MerkleTree`] over the
+//! entries proves a single one without the rest.
+//!
+//! A workspace moved to another machine [hands off](handoff) its head, so
+//! the log there continues the chain instead of starting a new one.
+//!
+//! Records can also be [exported](export) to syslog or as CEF lines, and
+//! written in batches from a background thread by an [`AuditWriter`]. A
+//! [`Shipper`] forwards the log, at least once, to a remote collector.
+//! Records are streamed live to [subscribers](AuditLog::subscribe) in the
+//! same process, and an [`AuditTail`] follows the log from any other.
+
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use ring::signature::{Ed25519KeyPair, UnparsedPublicKey, ED25519};
+use saf_codes::Code;
+
+pub mod checkpoint;
+pub mod event;
+pub mod export;
+pub mod handoff;
+pub mod merkle;
+pub mod reader;
+pub mod retention;
+pub mod segment;
+pub mod ship;
+pub mod tail;
+pub mod writer;
+
+mod protect;
+
+pub use checkpoint::{verify_anchors, Anchor, Checkpoint, FileAnchor};
+pub use event::{AuditEvent, AuditRecord, ComponentIdentity, Outcome, SCHEMA_VERSION};
+pub use export::{AuditSink, ExportConfig, ExportFormat, Exporter};
+pub use handoff::{handoff, Handoff};
+pub use merkle::{verify_inclusion, InclusionProof, MerkleTree};
+pub use reader::{AuditPage, AuditQuery, AuditReader};
+pub use retention::{Pruned, Retention};
+pub use segment::{segments, Rotation};
+pub use ship::{Destination, ShippedEntry, Shipper, ShipperHandle};
+pub use tail::AuditTail;
+pub use writer::{AuditWriter, Fsync, WriterConfig};
+
+/// The hash the first entry is chained to.
+const GENESIS: [u8; 32] = [0; 32];
 
-/// Placeholder, non-cryptographic hash chain for audit events.
-/// Replace with BLAKE3 in a future milestone.
-#[derive(Debug, Clone, Copy)]
-struct ChainHash(u64);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ChainHash(blake3::Hash);
 
 impl ChainHash {
     fn new() -> Self {
-        Self(0)
+        Self(blake3::Hash::from(GENESIS))
+    }
+
+    fn next(&self, entry: &str) -> Self {
+        let mut h = blake3::Hasher::new();
+        h.update(self.0.as_bytes());
+        h.update(entry.as_bytes());
+        Self(h.finalize())
+    }
+
+    fn parse(hex: &str) -> Option<Self> {
+        blake3::Hash::from_hex(hex.trim()).ok().map(Self)
+    }
+
+    fn hex(&self) -> String {
+        self.0.to_hex().to_string()
     }
-    fn update(&mut self, msg: &str) {
-        let mut h = std::collections::hash_map::DefaultHasher::new();
-        self.0.hash(&mut h);
-        msg.hash(&mut h);
-        self.0 = h.finish();
+}
+
+/// `message` as it is stored and hashed: one line, reversibly escaped.
+pub fn canonicalize(message: &str) -> String {
+    let mut out = String::with_capacity(message.len());
+    for c in message.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
     }
+    out
+}
+
+/// Undo [`canonicalize`].
+pub fn uncanonicalize(entry: &str) -> String {
+    let mut out = String::with_capacity(entry.len());
+    let mut chars = entry.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('\\')) => out.push('\\'),
+            ('\\', Some('n')) => out.push('\n'),
+            ('\\', Some('r')) => out.push('\r'),
+            (c, _) => {
+                out.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    out
+}
+
+/// A stored line split into its hash, signature (if signed) and entry.
+fn split_line(line: &str) -> Option<(&str, Option<&str>, &str)> {
+    let (stamp, entry) = line.split_once('|')?;
+    Some(match stamp.split_once(':') {
+        Some((hash, signature)) => (hash, Some(signature), entry),
+        None => (stamp, None, entry),
+    })
+}
+
+/// Where the head hash of the log at `path` is kept.
+pub fn head_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".head");
+    path.with_file_name(name)
+}
+
+/// `<log>.lock`, which writers of the log hold while they write to it.
+fn lock_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".lock");
+    path.with_file_name(name)
 }
 
 pub struct AuditLog {
-    file: BufWriter<std::fs::File>,
+    file: BufWriter<File>,
+    /// `<log>.lock`, held while writing.
+    lock: File,
     state: ChainHash,
-    _path: PathBuf,
+    path: PathBuf,
+    signer: Option<Ed25519KeyPair>,
+    rotation: Rotation,
+    retention: Retention,
+    /// Size of the active file.
+    size: u64,
+    /// When the first entry of the active file was written.
+    started_ms: u64,
+    exporters: Vec<Box<dyn AuditSink>>,
+    /// Leave flushing and the head to [`sync`](Self::sync).
+    batching: bool,
+    checkpoint_every: Option<u64>,
+    since_checkpoint: u64,
+    anchors: Vec<Box<dyn Anchor>>,
+    subscribers: Vec<Sender<AuditRecord>>,
+    /// Hash of the active file's first line, once it has one.
+    first: Option<ChainHash>,
+    append_only: bool,
 }
 
 impl AuditLog {
+    /// Open (or create) the log at `path`, continuing its chain. A log
+    /// written before hashes were chained is moved aside to
+    /// `<log>.legacy` and a new chain started.
     pub fn new(path: &Path) -> Result<Self, String> {
         if let Some(parent) = path.parent() {
             create_dir_all(parent).map_err(|e| e.to_string())?;
         }
-        let file = OpenOptions::new()
+        let lock = OpenOptions::new()
             .create(true)
-            .append(true)
-            .open(path)
-            .map_err(|e| e.to_string())?;
+            .truncate(false)
+            .write(true)
+            .open(lock_path(path))
+            .map_err(|e| format!("{}: {}", lock_path(path).display(), e))?;
+        take(&lock, path)?;
+        let resumed = resume(path).and_then(|state| Ok((state, Active::open(path)?)));
+        let _ = lock.unlock();
+        let (state, active) = resumed?;
         Ok(Self {
-            file: BufWriter::new(file),
-            state: ChainHash::new(),
-            _path: path.to_path_buf(),
+            file: active.file,
+            lock,
+            state,
+            path: path.to_path_buf(),
+            signer: None,
+            rotation: Rotation::default(),
+            retention: Retention::default(),
+            size: active.size,
+            started_ms: active.started_ms,
+            exporters: Vec::new(),
+            batching: false,
+            checkpoint_every: None,
+            since_checkpoint: 0,
+            anchors: Vec::new(),
+            subscribers: Vec::new(),
+            first: active.first,
+            append_only: false,
         })
     }
 
+    /// Run `f` holding the log's lock, once entries other writers appended
+    /// since this one last wrote are picked up. `f` must not take the lock
+    /// itself.
+    fn locked<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, String>) -> Result<T, String> {
+        take(&self.lock, &self.path)?;
+        let result = self.catch_up().and_then(|()| f(self));
+        let _ = self.lock.unlock();
+        result
+    }
+
+    /// Reload the chain state if the file no longer ends where this writer
+    /// left it: another one appended to it, or rotated it.
+    fn catch_up(&mut self) -> Result<(), String> {
+        let len = match std::fs::metadata(&self.path) {
+            Ok(m) => m.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(format!("{}: {}", self.path.display(), e)),
+        };
+        let last = last_line(&self.path).map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        let unchanged = len == self.size
+            && match last.as_deref().and_then(split_line) {
+                Some((hash, _, _)) => ChainHash::parse(hash) == Some(self.state),
+                // Empty since a rotation, whose end the head records.
+                None => read_head(&self.path)?.is_none_or(|head| head.last == self.state),
+            };
+        if unchanged {
+            return Ok(());
+        }
+        self.state = resume(&self.path)?;
+        let active = Active::open(&self.path)?;
+        self.file = active.file;
+        self.size = active.size;
+        self.started_ms = active.started_ms;
+        self.first = active.first;
+        Ok(())
+    }
+
+    /// Rotate the active file into a segment when it outgrows `rotation`.
+    pub fn with_rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Prune old segments as `retention` says after each rotation.
+    pub fn with_retention(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Also send every [record](Self::record) to `exporter`.
+    pub fn with_exporter(mut self, exporter: impl AuditSink + 'static) -> Self {
+        self.exporters.push(Box::new(exporter));
+        self
+    }
+
+    /// Write a checkpoint after every `every` entries appended from now on.
+    pub fn with_checkpoints(mut self, every: u64) -> Self {
+        self.checkpoint_every = Some(every).filter(|&n| n > 0);
+        self
+    }
+
+    /// Also hand every checkpoint to `anchor`.
+    pub fn with_anchor(mut self, anchor: impl Anchor + 'static) -> Self {
+        self.anchors.push(Box::new(anchor));
+        self
+    }
+
+    /// Sign every entry appended from now on with the ed25519 key in
+    /// `pkcs8`.
+    pub fn with_signing_key(mut self, pkcs8: &[u8]) -> Result<Self, String> {
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8)
+            .map_err(|e| format!("invalid audit signing key: {e}"))?;
+        self.signer = Some(pair);
+        Ok(self)
+    }
+
+    /// Have the platform refuse everything but appends to the active file:
+    /// the `a` attribute on Linux, `uappnd` on macOS and the BSDs, and an
+    /// ACL denying writes and deletion on Windows. The protection is lifted
+    /// only to rotate the file into a segment, and stays on after the log
+    /// is dropped. Setting it may need privileges the process lacks, and
+    /// other platforms cannot; either is an error, naming what was missing.
+    /// Failing to set it again after a rotation is recorded in the log as
+    /// `audit.unprotected` instead.
+    pub fn with_append_only(mut self) -> Result<Self, String> {
+        protect::protect(&self.path)?;
+        self.append_only = true;
+        Ok(self)
+    }
+
     pub fn append(&mut self, message: &str) -> Result<(), String> {
-        self.state.update(message);
-        let line = format!("{}|{}\n", self.state.0, message);
+        self.locked(|log| {
+            log.append_entry(message)?;
+            // Batched or not, the entry must be in the file before the
+            // next writer takes the lock.
+            log.file.flush().map_err(|e| e.to_string())
+        })
+    }
+
+    fn append_entry(&mut self, message: &str) -> Result<(), String> {
+        let now = event::now_ms();
+        if self.rotation.due(self.size, self.started_ms, now) {
+            self.rotate(now)?;
+        }
+        // A file started by a rotation (or left empty by a crash during
+        // one) opens with a link to the segment its chain continues.
+        if self.size == 0 && self.state != ChainHash::new() {
+            let previous = segments(&self.path)?
+                .last()
+                .and_then(|p| p.file_name())
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let link = AuditRecord::new(
+                Code::AuditLinked,
+                AuditEvent::Link {
+                    previous,
+                    previous_hash: self.state.hex(),
+                },
+            );
+            self.write(
+                &serde_json::to_string(&link).map_err(|e| e.to_string())?,
+                now,
+            )?;
+        }
+        self.write(message, now)?;
+        self.since_checkpoint += 1;
+        if self
+            .checkpoint_every
+            .is_some_and(|every| self.since_checkpoint >= every)
+        {
+            self.checkpoint(now)?;
+        }
+        Ok(())
+    }
+
+    /// Record the current chain hash, make it durable and anchor it. An
+    /// anchor that cannot be reached misses the checkpoint; the next one
+    /// covers everything before it.
+    fn checkpoint(&mut self, now: u64) -> Result<(), String> {
+        let checkpoint = Checkpoint {
+            ts_ms: now,
+            log: self.path.display().to_string(),
+            hash: self.state.hex(),
+            signature: self
+                .signer
+                .as_ref()
+                .map(|pair| hex::encode(pair.sign(self.state.0.as_bytes()))),
+        };
+        let record = AuditRecord::new(
+            Code::AuditCheckpoint,
+            AuditEvent::Checkpoint {
+                hash: checkpoint.hash.clone(),
+                signature: checkpoint.signature.clone(),
+            },
+        );
+        self.write(
+            &serde_json::to_string(&record).map_err(|e| e.to_string())?,
+            now,
+        )?;
+        self.since_checkpoint = 0;
+        // An anchored hash must not be lost in a crash.
+        self.sync_head(false)?;
+        for anchor in &mut self.anchors {
+            let _ = anchor.anchor(&checkpoint);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, message: &str, now: u64) -> Result<(), String> {
+        let entry = canonicalize(message);
+        let next = self.state.next(&entry);
+        let line = format_line(&next, self.signer.as_ref(), &entry);
         self.file
             .write_all(line.as_bytes())
             .map_err(|e| e.to_string())?;
-        self.file.flush().map_err(|e| e.to_string())
+        if self.size == 0 {
+            self.started_ms = now;
+            self.first = Some(next);
+        }
+        self.size += line.len() as u64;
+        self.state = next;
+        if self.batching {
+            return Ok(());
+        }
+        self.file.flush().map_err(|e| e.to_string())?;
+        write_head(&self.path, &next, self.first.as_ref())
+    }
+
+    /// Write out buffered entries, forcing them to disk with `fsync`, and
+    /// record the head.
+    pub fn sync(&mut self, fsync: bool) -> Result<(), String> {
+        self.locked(|log| log.sync_head(fsync))
+    }
+
+    fn sync_head(&mut self, fsync: bool) -> Result<(), String> {
+        self.file.flush().map_err(|e| e.to_string())?;
+        if fsync {
+            self.file.get_ref().sync_data().map_err(|e| e.to_string())?;
+        }
+        write_head(&self.path, &self.state, self.first.as_ref())
+    }
+
+    pub(crate) fn set_batching(&mut self, batching: bool) {
+        self.batching = batching;
+    }
+
+    /// Move the active file aside as a segment and start an empty one.
+    fn rotate(&mut self, now: u64) -> Result<(), String> {
+        // The head must name the segment's end before the link is written,
+        // and no first line, as the active file will be missing for a moment.
+        self.first = None;
+        self.sync_head(false)?;
+        let segment = segment::segment_path(&self.path, now);
+        if self.append_only {
+            protect::release(&self.path)?;
+        }
+        std::fs::rename(&self.path, &segment)
+            .map_err(|e| format!("{}: {}", segment.display(), e))?;
+        self.file = BufWriter::new(open_append(&self.path)?);
+        self.size = 0;
+        // The segment is already out of the way, so keep logging to an
+        // unprotected file rather than lose entries, and say so in it.
+        if self.append_only {
+            if let Err(e) = protect::protect(&self.path) {
+                self.record_entry(
+                    &AuditRecord::new(
+                        Code::AuditUnprotected,
+                        AuditEvent::OperationFailed {
+                            capability: "audit".to_string(),
+                            operation: "audit.append_only".to_string(),
+                            target: self.path.display().to_string(),
+                            reason: e,
+                        },
+                    )
+                    .with_outcome(Outcome::Failed),
+                )?;
+            }
+        }
+        if self.rotation.compress {
+            // An uncompressed segment verifies just as well, so a failure
+            // here is not worth losing the entry over.
+            let _ = segment::compress(&segment);
+        }
+        // Nor is a pruning that fails; the next rotation tries again.
+        let _ = retention::prune(&self.path, &self.retention, self.signer.as_ref(), now);
+        Ok(())
+    }
+
+    /// Prune old segments as the log's [retention](Self::with_retention)
+    /// says, now.
+    pub fn prune(&mut self) -> Result<Pruned, String> {
+        self.locked(|log| {
+            retention::prune(
+                &log.path,
+                &log.retention,
+                log.signer.as_ref(),
+                event::now_ms(),
+            )
+        })
+    }
+
+    /// Append `record` as a JSON line, then export it and pass it to
+    /// subscribers. Only the append can fail; an exporter that cannot
+    /// deliver drops the record.
+    pub fn record(&mut self, record: &AuditRecord) -> Result<(), String> {
+        let json = serde_json::to_string(record).map_err(|e| e.to_string())?;
+        self.append(&json)?;
+        self.deliver(record);
+        Ok(())
+    }
+
+    /// [`record`](Self::record), for callers already holding the lock.
+    fn record_entry(&mut self, record: &AuditRecord) -> Result<(), String> {
+        let json = serde_json::to_string(record).map_err(|e| e.to_string())?;
+        self.append_entry(&json)?;
+        self.deliver(record);
+        Ok(())
+    }
+
+    fn deliver(&mut self, record: &AuditRecord) {
+        for exporter in &mut self.exporters {
+            let _ = exporter.export(record);
+        }
+        self.subscribers
+            .retain(|subscriber| subscriber.send(record.clone()).is_ok());
+    }
+
+    /// Every record written from now on, until the receiver is dropped.
+    pub fn subscribe(&mut self) -> Receiver<AuditRecord> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// Hex hash of the last entry written.
+    pub fn head(&self) -> String {
+        self.state.hex()
+    }
+}
+
+/// Wait for the lock on the log at `path`, held through `lock`.
+fn take(lock: &File, path: &Path) -> Result<(), String> {
+    match lock.lock() {
+        Ok(()) => Ok(()),
+        // Nothing to take turns with where the platform has no locks.
+        Err(e) if e.kind() == std::io::ErrorKind::Unsupported => Ok(()),
+        Err(e) => Err(format!("{}: {}", lock_path(path).display(), e)),
+    }
+}
+
+/// The active file, opened for appending, and what the log keeps about it.
+struct Active {
+    file: BufWriter<File>,
+    size: u64,
+    started_ms: u64,
+    first: Option<ChainHash>,
+}
+
+impl Active {
+    fn open(path: &Path) -> Result<Self, String> {
+        let file = open_append(path)?;
+        let size = file.metadata().map_err(|e| e.to_string())?.len();
+        let started_ms = first_line(path)
+            .map(|l| match split_line(&l) {
+                Some((_, _, entry)) => AuditRecord::parse(&uncanonicalize(entry)).ts_ms,
+                None => 0,
+            })
+            .filter(|&ts| ts > 0)
+            .unwrap_or_else(event::now_ms);
+        Ok(Self {
+            file: BufWriter::new(file),
+            size,
+            started_ms,
+            first: first_hash(path),
+        })
+    }
+}
+
+/// `entry` as a line of the log, stamped with its chain hash `next` and
+/// signed with `signer`, if given.
+fn format_line(next: &ChainHash, signer: Option<&Ed25519KeyPair>, entry: &str) -> String {
+    match signer {
+        Some(pair) => format!(
+            "{}:{}|{}\n",
+            next.hex(),
+            hex::encode(pair.sign(next.0.as_bytes())),
+            entry
+        ),
+        None => format!("{}|{}\n", next.hex(), entry),
+    }
+}
+
+/// The chain state to continue from for the log at `path`.
+fn resume(path: &Path) -> Result<ChainHash, String> {
+    let last = last_line(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let head = read_head(path)?;
+    let last_hash = last
+        .as_deref()
+        .and_then(split_line)
+        .and_then(|(hash, _, _)| ChainHash::parse(hash));
+    match (head, last.as_ref(), last_hash) {
+        // Rotated, but the link to the segment is not written yet.
+        (Some(head), None, _) if head.first.is_none() && !segments(path)?.is_empty() => {
+            Ok(head.last)
+        }
+        (None, None, _) => Ok(ChainHash::new()),
+        (None, Some(_), Some(hash)) => Ok(hash),
+        (None, Some(_), None) => {
+            let mut legacy = path.as_os_str().to_os_string();
+            legacy.push(".legacy");
+            std::fs::rename(path, &legacy).map_err(|e| e.to_string())?;
+            Ok(ChainHash::new())
+        }
+        // A crash between writing lines and the head leaves the head behind.
+        (Some(head), _, Some(hash)) if head.last == hash || has_hash(path, &head.last) => Ok(hash),
+        (Some(head), None, _) if head.last == ChainHash::new() => Ok(head.last),
+        (Some(head), _, _) => Err(tampered(path, &head)),
+    }
+}
+
+/// What became of the log at `path`, which no longer reaches `head`.
+fn tampered(path: &Path, head: &Head) -> String {
+    let recorded = head_path(path);
+    if !path.exists() {
+        return format!(
+            "{} is missing, but {} records entries in it; it was deleted",
+            path.display(),
+            recorded.display()
+        );
+    }
+    match (head.first, first_hash(path)) {
+        (Some(expected), Some(found)) if expected != found => format!(
+            "{} does not start with the entry recorded in {}; it was replaced",
+            path.display(),
+            recorded.display()
+        ),
+        _ => format!(
+            "{} does not reach the head recorded in {}; it was truncated or edited",
+            path.display(),
+            recorded.display()
+        ),
+    }
+}
+
+/// Whether a line of the file at `path` carries `hash`.
+fn has_hash(path: &Path, hash: &ChainHash) -> bool {
+    let hex = hash.hex();
+    File::open(path).is_ok_and(|f| {
+        BufReader::new(f)
+            .lines()
+            .map_while(Result::ok)
+            .any(|line| split_line(&line).is_some_and(|(h, _, _)| h == hex))
+    })
+}
+
+fn open_append(path: &Path) -> Result<File, String> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// The first line of the file at `path`, if it has one.
+pub(crate) fn first_line(path: &Path) -> Option<String> {
+    let mut line = String::new();
+    BufReader::new(File::open(path).ok()?)
+        .read_line(&mut line)
+        .ok()?;
+    Some(line.trim_end().to_string()).filter(|l| !l.is_empty())
+}
+
+/// The hash on the first line of the file at `path`, if it has one.
+fn first_hash(path: &Path) -> Option<ChainHash> {
+    let line = first_line(path)?;
+    split_line(&line).and_then(|(hash, _, _)| ChainHash::parse(hash))
+}
+
+/// What `<log>.head` records: the hash of the last entry and, once the
+/// active file has one, of its first.
+struct Head {
+    last: ChainHash,
+    first: Option<ChainHash>,
+}
+
+/// The head recorded for the log at `path`, if there is one. Heads
+/// recorded before the first line was kept are a lone hash.
+fn read_head(path: &Path) -> Result<Option<Head>, String> {
+    let head_path = head_path(path);
+    let content = match std::fs::read_to_string(&head_path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("{}: {}", head_path.display(), e)),
+    };
+    let not_a_hash = || format!("{}: not a hash", head_path.display());
+    let mut hashes = content.split_whitespace().map(ChainHash::parse);
+    let last = hashes.next().flatten().ok_or_else(not_a_hash)?;
+    let first = match hashes.next() {
+        Some(hash) => Some(hash.ok_or_else(not_a_hash)?),
+        None => None,
+    };
+    Ok(Some(Head { last, first }))
+}
+
+/// Record `head`, and the hash of the active file's `first` line, next to
+/// the log, replacing the previous ones atomically.
+fn write_head(path: &Path, head: &ChainHash, first: Option<&ChainHash>) -> Result<(), String> {
+    let head_path = head_path(path);
+    let mut tmp = head_path.as_os_str().to_os_string();
+    tmp.push(".tmp");
+    let content = match first {
+        Some(first) => format!("{} {}", head.hex(), first.hex()),
+        None => head.hex(),
+    };
+    std::fs::write(&tmp, content).map_err(|e| e.to_string())?;
+    std::fs::rename(&tmp, &head_path).map_err(|e| e.to_string())
+}
+
+/// The last non-empty line of the file at `path`, read from the end.
+fn last_line(path: &Path) -> std::io::Result<Option<String>> {
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let len = file.metadata()?.len();
+    let mut tail = Vec::new();
+    let mut start = len;
+    loop {
+        let chunk = start.min(4096);
+        start -= chunk;
+        let mut buf = vec![0; chunk as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut buf)?;
+        buf.extend_from_slice(&tail);
+        tail = buf;
+        let body = tail.strip_suffix(b"\n").unwrap_or(&tail);
+        if let Some(i) = body.iter().rposition(|&b| b == b'\n') {
+            return Ok(Some(String::from_utf8_lossy(&body[i + 1..]).into_owned()));
+        }
+        if start == 0 {
+            return Ok((!body.is_empty()).then(|| String::from_utf8_lossy(body).into_owned()));
+        }
+    }
+}
+
+/// Every entry of the log at `path`, typed or legacy, oldest first. The
+/// chain is not checked; see [`verify`].
+pub fn read(path: &Path) -> Result<Vec<AuditRecord>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| format!("{}: {}", path.display(), e))?;
+        if let Some((_, _, entry)) = split_line(&line) {
+            records.push(AuditRecord::parse(&uncanonicalize(entry)));
+        }
+    }
+    Ok(records)
+}
+
+/// What [`verify`] found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Verified {
+    /// Files checked: rotated segments and the active log.
+    pub files: u64,
+    pub entries: u64,
+    /// Entries carrying a valid signature.
+    pub signed: u64,
+    /// Entries pruned, as the summary in their place says.
+    pub pruned: u64,
+    /// Hex chain hash of the last entry.
+    pub head: String,
+}
+
+/// Replay the chain of the log at `path`, through its rotated segments
+/// and the active file, and check every line's hash, every link between
+/// files and, if one is recorded, the head. The oldest segment left may
+/// start with a link to one archived elsewhere, which is taken on trust,
+/// or be the [summary](retention) of pruned ones. With `public_key`,
+/// signatures are checked too: once entries are signed (a key may be added
+/// to an existing log), every later one must be, through to the last, and
+/// so must a summary before them.
+pub fn verify(path: &Path, public_key: Option<&[u8]>) -> Result<Verified, String> {
+    let key = public_key.map(|k| UnparsedPublicKey::new(&ED25519, k));
+    let mut found = Verified::default();
+    let mut files = segments(path)?;
+    files.push(path.to_path_buf());
+    let head = match read_head(path) {
+        Ok(head) => head.map(|h| Some(h.last)),
+        Err(_) => Some(None),
+    };
+    // The head may lag the last entry after a crash, but must be reached.
+    let mut head_seen = head == Some(Some(ChainHash::new()));
+    // Chain state at the end of the previous file.
+    let mut end: Option<ChainHash> = None;
+    let mut unsigned_summary = false;
+    for file in &files {
+        let name = file.display();
+        let mut state = ChainHash::new();
+        // A summary stands aside from the chain: it hashes from the end of
+        // the pruned range, which the next file links to.
+        let mut summary_end = None;
+        for (n, line) in segment::lines(file)?.lines().enumerate() {
+            let n = n + 1;
+            let line = line.map_err(|e| format!("{name}: {e}"))?;
+            let (hash, signature, entry) =
+                split_line(&line).ok_or_else(|| format!("{name}:{n}: no hash"))?;
+            if n == 1 {
+                let link = match AuditRecord::parse(&uncanonicalize(entry)).event {
+                    AuditEvent::Link { previous_hash, .. } => ChainHash::parse(&previous_hash),
+                    AuditEvent::Pruned {
+                        last_hash, entries, ..
+                    } if end.is_none() => {
+                        summary_end = ChainHash::parse(&last_hash);
+                        unsigned_summary = signature.is_none();
+                        found.pruned = entries;
+                        summary_end
+                    }
+                    _ => None,
+                };
+                state = match (link, end) {
+                    (Some(link), Some(end)) if link != end => {
+                        return Err(format!(
+                            "{name}:1: does not continue the chain of the file before it"
+                        ))
+                    }
+                    (Some(link), _) => link,
+                    (None, Some(_)) => {
+                        return Err(format!("{name}:1: rotated file does not start with a link"))
+                    }
+                    (None, None) => state,
+                };
+            }
+            let next = state.next(entry);
+            if ChainHash::parse(hash) != Some(next) {
+                return Err(format!("{name}:{n}: hash does not match the chain"));
+            }
+            if let Some(key) = &key {
+                match signature.map(hex::decode) {
+                    // The summary is not one of the entries counted.
+                    Some(Ok(sig)) if key.verify(next.0.as_bytes(), &sig).is_ok() => {
+                        found.signed += u64::from(summary_end.is_none())
+                    }
+                    Some(_) => return Err(format!("{name}:{n}: signature does not match the key")),
+                    None if found.signed > 0 => {
+                        return Err(format!("{name}:{n}: not signed, but earlier entries are"))
+                    }
+                    None => {}
+                }
+            }
+            head_seen |= head == Some(Some(next));
+            state = next;
+            if summary_end.is_none() {
+                found.entries += 1;
+            }
+        }
+        found.files += 1;
+        end = Some(match (summary_end, end) {
+            (Some(summary_end), _) => summary_end,
+            // An empty active file continues the previous one.
+            (None, Some(end)) if state == ChainHash::new() => end,
+            _ => state,
+        });
+    }
+    if key.is_some() && found.entries > 0 && found.signed == 0 {
+        return Err("no entry is signed with the key".to_string());
+    }
+    if key.is_some() && unsigned_summary && found.signed > 0 {
+        return Err(format!(
+            "{}: the summary of pruned entries is not signed, but later entries are",
+            files[0].display()
+        ));
+    }
+    found.head = end.unwrap_or_else(ChainHash::new).hex();
+    if head.is_some() && !head_seen {
+        return Err(format!(
+            "the log does not reach the head recorded in {}; it was truncated",
+            head_path(path).display()
+        ));
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain_survives_reopening_and_detects_tampering() {
+        let dir = std::env::temp_dir().join(format!("saf-audit-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("audit.log");
+
+        let mut log = AuditLog::new(&path).expect("open");
+        log.append("fs.read docs/a.md").expect("append");
+        log.append("line one\nline two").expect("append");
+        let head = log.head();
+        drop(log);
+
+        let mut log = AuditLog::new(&path).expect("reopen");
+        assert_eq!(log.head(), head);
+        log.append("net.fetch https://example.org/")
+            .expect("append");
+        drop(log);
+        assert_eq!(verify(&path, None).map(|v| v.entries), Ok(3));
+        let content = std::fs::read_to_string(&path).expect("read");
+        assert_eq!(content.lines().count(), 3);
+        assert!(content.contains("|line one\\nline two\n"));
+
+        // An edited entry breaks the chain.
+        std::fs::write(&path, content.replace("docs/a.md", "docs/b.md")).expect("write");
+        assert_eq!(
+            verify(&path, None).map(|v| v.entries),
+            Err(format!(
+                "{}:1: hash does not match the chain",
+                path.display()
+            ))
+        );
+
+        // Dropping the last entry no longer reaches the recorded head.
+        let truncated: String = content.lines().take(2).map(|l| format!("{l}\n")).collect();
+        std::fs::write(&path, truncated).expect("write");
+        assert!(verify(&path, None).is_err());
+        assert!(AuditLog::new(&path).is_err());
+
+        // A log from before chaining is moved aside.
+        std::fs::remove_file(head_path(&path)).expect("remove head");
+        std::fs::write(&path, "123|old entry\n").expect("write");
+        let mut log = AuditLog::new(&path).expect("legacy");
+        log.append("first").expect("append");
+        assert_eq!(verify(&path, None).map(|v| v.entries), Ok(1));
+        assert!(dir.join("audit.log.legacy").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reopening_tells_a_replaced_log_from_a_truncated_one() {
+        let dir = std::env::temp_dir().join(format!("saf-audit-replaced-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("audit.log");
+        let mut log = AuditLog::new(&path).expect("open");
+        log.append("fs.read docs/a.md").expect("append");
+        log.append("fs.read docs/b.md").expect("append");
+        drop(log);
+        let content = std::fs::read_to_string(&path).expect("read");
+        let head = std::fs::read_to_string(head_path(&path)).expect("head");
+
+        let first_only: String = content.lines().take(1).map(|l| format!("{l}\n")).collect();
+        std::fs::write(&path, first_only).expect("write");
+        let error = AuditLog::new(&path).err().expect("truncated");
+        assert!(error.ends_with("it was truncated or edited"), "{error}");
+
+        // A fresh chain in place of the old one.
+        std::fs::remove_file(&path).expect("remove");
+        std::fs::remove_file(head_path(&path)).expect("remove head");
+        AuditLog::new(&path)
+            .expect("open")
+            .append("fs.read docs/forged.md")
+            .expect("append");
+        std::fs::write(head_path(&path), &head).expect("restore head");
+        let error = AuditLog::new(&path).err().expect("replaced");
+        assert!(error.ends_with("it was replaced"), "{error}");
+
+        std::fs::remove_file(&path).expect("remove");
+        let error = AuditLog::new(&path).err().expect("deleted");
+        assert!(error.ends_with("it was deleted"), "{error}");
+
+        // Heads recorded before the first line was kept still resume.
+        std::fs::write(&path, &content).expect("write");
+        let last = head.split_whitespace().next().expect("last").to_string();
+        std::fs::write(head_path(&path), &last).expect("write head");
+        assert_eq!(AuditLog::new(&path).expect("reopen").head(), last);
+        assert_eq!(verify(&path, None).map(|v| v.entries), Ok(2));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn signed_entries_need_the_key_to_forge() {
+        let dir = std::env::temp_dir().join(format!("saf-audit-signed-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("audit.log");
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).expect("key");
+        let public = ring::signature::KeyPair::public_key(
+            &Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).expect("pair"),
+        )
+        .as_ref()
+        .to_vec();
+
+        // Entries from before the key was added stay valid.
+        let mut log = AuditLog::new(&path).expect("open");
+        log.append("unsigned").expect("append");
+        drop(log);
+        let mut log = AuditLog::new(&path)
+            .and_then(|l| l.with_signing_key(pkcs8.as_ref()))
+            .expect("open signed");
+        log.append("fs.read a").expect("append");
+        log.append("fs.read b").expect("append");
+        drop(log);
+        let found = verify(&path, Some(&public)).expect("verify");
+        assert_eq!((found.entries, found.signed), (3, 2));
+
+        // Recomputing the chain after an edit is not enough.
+        let content = std::fs::read_to_string(&path).expect("read");
+        let mut state = ChainHash::new();
+        let forged: String = content
+            .lines()
+            .map(|line| {
+                let (_, _, entry) = split_line(line).expect("line");
+                let entry = entry.replace("fs.read b", "fs.read c");
+                state = state.next(&entry);
+                format!("{}|{entry}\n", state.hex())
+            })
+            .collect();
+        std::fs::write(&path, forged).expect("write");
+        std::fs::write(head_path(&path), state.hex()).expect("write head");
+        assert!(verify(&path, None).is_ok());
+        assert_eq!(
+            verify(&path, Some(&public)),
+            Err("no entry is signed with the key".to_string())
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rotation_links_segments_into_one_chain() {
+        let dir = std::env::temp_dir().join(format!("saf-audit-rotate-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("audit.log");
+        let rotation = Rotation {
+            max_bytes: Some(200),
+            max_age: None,
+            compress: true,
+        };
+
+        let mut log = AuditLog::new(&path).expect("open").with_rotation(rotation);
+        for i in 0..6 {
+            log.append(&format!("fs.read docs/{i}.md")).expect("append");
+        }
+        drop(log);
+        // Reopening keeps rotating where it left off.
+        let mut log = AuditLog::new(&path)
+            .expect("reopen")
+            .with_rotation(rotation);
+        log.append("fs.read docs/last.md").expect("append");
+        drop(log);
+
+        let rotated = segments(&path).expect("segments");
+        assert!(rotated.len() >= 3, "{rotated:?}");
+        assert!(rotated
+            .iter()
+            .all(|p| p.extension().is_some_and(|e| e == "gz")));
+        let found = verify(&path, None).expect("verify");
+        assert_eq!(found.files, rotated.len() as u64 + 1);
+        let links = found.entries - 7;
+        assert_eq!(links, rotated.len() as u64);
+        let first = read(&path).expect("read").remove(0);
+        assert_eq!(first.code, Code::AuditLinked.as_str());
+
+        // Verification of the rest still works once old segments are
+        // archived away, but not with a segment missing in between.
+        std::fs::remove_file(&rotated[0]).expect("archive");
+        assert!(verify(&path, None).is_ok());
+        std::fs::remove_file(&rotated[2]).expect("remove");
+        assert!(verify(&path, None)
+            .unwrap_err()
+            .contains("does not continue the chain"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn writers_sharing_a_log_continue_each_others_chain() {
+        let dir = std::env::temp_dir().join(format!("saf-audit-shared-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("audit.log");
+
+        // Interleaved, with one of them rotating the file from under the
+        // other.
+        let mut a = AuditLog::new(&path).expect("open a");
+        let mut b = AuditLog::new(&path)
+            .expect("open b")
+            .with_rotation(Rotation {
+                max_bytes: Some(300),
+                max_age: None,
+                compress: false,
+            });
+        for i in 0..4 {
+            a.append(&format!("fs.read a/{i}.md")).expect("append a");
+            b.append(&format!("fs.read b/{i}.md")).expect("append b");
+        }
+        assert!(!segments(&path).expect("segments").is_empty());
+        a.append("fs.read a/last.md").expect("append a");
+        drop((a, b));
+        let found = verify(&path, None).expect("verify");
+        let links = segments(&path).expect("segments").len() as u64;
+        assert_eq!(found.entries, 9 + links);
+
+        // And at the same time, from threads with a handle each.
+        let writers: Vec<_> = (0..4)
+            .map(|w| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let mut log = AuditLog::new(&path).expect("open");
+                    for i in 0..25 {
+                        log.append(&format!("fs.read {w}/{i}.md")).expect("append");
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().expect("writer");
+        }
+        assert_eq!(
+            verify(&path, None).map(|v| v.entries),
+            Ok(found.entries + 100)
+        );
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
@@ -0,0 +1,280 @@
+//! Merkle tree over audit entries, with inclusion proofs.
+//!
+//! The chain proves a whole log; to show a single entry to someone
+//! ("this denial happened") it would have to be shipped entire. A
+//! [`MerkleTree`] over the same entries, built as in RFC 9162 with BLAKE3
+//! (`leaf = H(0x00 || entry)`, `node = H(0x01 || left || right)`), yields
+//! an [`InclusionProof`] of a few hashes instead. The proof carries the
+//! root and tree size, signed with the audit key when there is one, and
+//! [`verify_inclusion`] checks it without the log.
+
+use std::io::BufRead;
+use std::path::Path;
+
+use ring::signature::{Ed25519KeyPair, UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+
+use crate::event::AuditRecord;
+use crate::{segment, split_line, uncanonicalize};
+
+type Hash = [u8; 32];
+
+fn leaf_hash(entry: &str) -> Hash {
+    let mut h = blake3::Hasher::new();
+    h.update(&[0]);
+    h.update(entry.as_bytes());
+    *h.finalize().as_bytes()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut h = blake3::Hasher::new();
+    h.update(&[1]);
+    h.update(left);
+    h.update(right);
+    *h.finalize().as_bytes()
+}
+
+/// The largest power of two below `n` (n > 1).
+fn split(n: usize) -> usize {
+    let mut k = 1;
+    while k * 2 < n {
+        k *= 2;
+    }
+    k
+}
+
+/// What the root signature covers: the root, then the tree size as
+/// big-endian u64.
+fn signed_message(root: &Hash, size: u64) -> Vec<u8> {
+    let mut message = root.to_vec();
+    message.extend_from_slice(&size.to_be_bytes());
+    message
+}
+
+pub struct MerkleTree {
+    leaves: Vec<Hash>,
+    /// Chain hash of each entry, as written in the log.
+    ids: Vec<String>,
+    entries: Vec<String>,
+}
+
+impl MerkleTree {
+    /// The tree over every entry of the log at `path`, through its rotated
+    /// segments, in order.
+    pub fn from_log(path: &Path) -> Result<Self, String> {
+        let mut tree = Self {
+            leaves: Vec::new(),
+            ids: Vec::new(),
+            entries: Vec::new(),
+        };
+        let mut files = segment::segments(path)?;
+        files.push(path.to_path_buf());
+        for file in files.iter().filter(|f| f.exists()) {
+            for line in segment::lines(file)?.lines() {
+                let line = line.map_err(|e| format!("{}: {}", file.display(), e))?;
+                if let Some((hash, _, entry)) = split_line(&line) {
+                    tree.leaves.push(leaf_hash(entry));
+                    tree.ids.push(hash.to_string());
+                    tree.entries.push(entry.to_string());
+                }
+            }
+        }
+        Ok(tree)
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// The index of the entry whose chain hash is `id`, or starts with it.
+    pub fn find(&self, id: &str) -> Result<usize, String> {
+        let id = id.to_ascii_lowercase();
+        let mut found = self
+            .ids
+            .iter()
+            .enumerate()
+            .filter(|(_, hash)| hash.starts_with(&id));
+        match (found.next(), found.next()) {
+            (Some((i, _)), None) if !id.is_empty() => Ok(i),
+            (Some(_), Some(_)) => Err(format!("{id}: names more than one entry")),
+            _ => Err(format!("{id}: no such entry")),
+        }
+    }
+
+    pub fn root(&self) -> Hash {
+        Self::subtree(&self.leaves)
+    }
+
+    fn subtree(leaves: &[Hash]) -> Hash {
+        match leaves {
+            [] => *blake3::hash(&[]).as_bytes(),
+            [leaf] => *leaf,
+            _ => {
+                let k = split(leaves.len());
+                node_hash(&Self::subtree(&leaves[..k]), &Self::subtree(&leaves[k..]))
+            }
+        }
+    }
+
+    /// Sibling hashes from leaf `m` of `leaves` up to the root.
+    fn path(m: usize, leaves: &[Hash]) -> Vec<Hash> {
+        if leaves.len() <= 1 {
+            return Vec::new();
+        }
+        let k = split(leaves.len());
+        let (mut path, sibling) = if m < k {
+            (Self::path(m, &leaves[..k]), Self::subtree(&leaves[k..]))
+        } else {
+            (Self::path(m - k, &leaves[k..]), Self::subtree(&leaves[..k]))
+        };
+        path.push(sibling);
+        path
+    }
+
+    /// Prove entry `index` is in the tree, signing the root with the
+    /// ed25519 key in `pkcs8` if given.
+    pub fn prove(&self, index: usize, pkcs8: Option<&[u8]>) -> Result<InclusionProof, String> {
+        let entry = self
+            .entries
+            .get(index)
+            .ok_or_else(|| format!("entry {index}: the log has {} entries", self.len()))?;
+        let root = self.root();
+        let size = self.len() as u64;
+        let signature = match pkcs8 {
+            Some(pkcs8) => {
+                let pair = Ed25519KeyPair::from_pkcs8(pkcs8)
+                    .map_err(|e| format!("invalid audit signing key: {e}"))?;
+                Some(hex::encode(pair.sign(&signed_message(&root, size))))
+            }
+            None => None,
+        };
+        Ok(InclusionProof {
+            index: index as u64,
+            size,
+            id: self.ids[index].clone(),
+            entry: entry.clone(),
+            path: Self::path(index, &self.leaves)
+                .iter()
+                .map(hex::encode)
+                .collect(),
+            root: hex::encode(root),
+            signature,
+        })
+    }
+}
+
+/// Evidence that one entry is in a log of `size` entries with Merkle root
+/// `root`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    pub index: u64,
+    pub size: u64,
+    /// The entry's chain hash.
+    pub id: String,
+    /// The entry as stored.
+    pub entry: String,
+    /// Hex sibling hashes, leaf to root.
+    pub path: Vec<String>,
+    pub root: String,
+    /// Hex ed25519 signature over the root and size.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// Check `proof` and return the entry it proves. With `public_key`, the
+/// root must be signed with it.
+pub fn verify_inclusion(
+    proof: &InclusionProof,
+    public_key: Option<&[u8]>,
+) -> Result<AuditRecord, String> {
+    let decode = |h: &str| -> Result<Hash, String> {
+        hex::decode(h)
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .ok_or_else(|| format!("{h}: not a hash"))
+    };
+    let root = decode(&proof.root)?;
+    if proof.index >= proof.size {
+        return Err("index is outside the tree".to_string());
+    }
+    // RFC 9162, 2.1.3.2.
+    let (mut f, mut s) = (proof.index, proof.size - 1);
+    let mut r = leaf_hash(&proof.entry);
+    for p in &proof.path {
+        let p = decode(p)?;
+        if s == 0 {
+            return Err("proof is longer than the tree is deep".to_string());
+        }
+        if f & 1 == 1 || f == s {
+            r = node_hash(&p, &r);
+            while f & 1 == 0 && f != 0 {
+                f >>= 1;
+                s >>= 1;
+            }
+        } else {
+            r = node_hash(&r, &p);
+        }
+        f >>= 1;
+        s >>= 1;
+    }
+    if s != 0 || r != root {
+        return Err("entry is not in the tree with this root".to_string());
+    }
+    if let Some(key) = public_key {
+        let signature = proof.signature.as_deref().ok_or("root is not signed")?;
+        let signature = hex::decode(signature).map_err(|_| "signature is not hex")?;
+        UnparsedPublicKey::new(&ED25519, key)
+            .verify(&signed_message(&root, proof.size), &signature)
+            .map_err(|_| "root signature does not match the key".to_string())?;
+    }
+    Ok(AuditRecord::parse(&uncanonicalize(&proof.entry)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AuditLog;
+    use ring::signature::KeyPair;
+
+    #[test]
+    fn every_entry_has_a_proof_that_only_fits_it() {
+        let dir = std::env::temp_dir().join(format!("saf-audit-merkle-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("audit.log");
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).expect("key");
+        let public = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+            .expect("pair")
+            .public_key()
+            .as_ref()
+            .to_vec();
+
+        let mut log = AuditLog::new(&path).expect("open");
+        for i in 0..7 {
+            log.append(&format!("fs.read docs/{i}.md")).expect("append");
+        }
+        drop(log);
+        let tree = MerkleTree::from_log(&path).expect("tree");
+        assert_eq!(tree.len(), 7);
+        for i in 0..7 {
+            let proof = tree.prove(i, Some(pkcs8.as_ref())).expect("prove");
+            let record = verify_inclusion(&proof, Some(&public)).expect("verify");
+            assert_eq!(record.code, "fs.read");
+            assert_eq!(tree.find(&proof.id[..12]), Ok(i));
+        }
+
+        let mut forged = tree.prove(3, Some(pkcs8.as_ref())).expect("prove");
+        forged.entry = "fs.read docs/other.md".to_string();
+        assert!(verify_inclusion(&forged, None).is_err());
+        let mut moved = tree.prove(3, None).expect("prove");
+        moved.index = 2;
+        assert!(verify_inclusion(&moved, None).is_err());
+        let unsigned = tree.prove(3, None).expect("prove");
+        assert!(verify_inclusion(&unsigned, None).is_ok());
+        assert!(verify_inclusion(&unsigned, Some(&public)).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,171 @@
+//! Keeping the active file append-only.
+//!
+//! The log is opened with `O_APPEND`, so its own writes land at the end,
+//! but that does not stop anything else rewriting the file. A log opened
+//! [append-only](crate::AuditLog::with_append_only) also asks the platform
+//! to refuse everything but appends to the active file:
+//!
+//! - Linux: the `a` attribute (`chattr +a`). Setting or clearing it needs
+//!   `CAP_LINUX_IMMUTABLE`, so while it is set not even the file's owner
+//!   can truncate, rewrite, rename or delete the file.
+//! - macOS and the BSDs: the `uappnd` flag (`chflags uappnd`). The owner
+//!   can clear it, so it guards against accidents and tools rewriting the
+//!   file rather than against the owner.
+//! - Windows: an ACL entry denying everyone write-data and delete
+//!   (`icacls /deny *S-1-1-0:(WD,DE)`), which leaves appends; the owner can
+//!   remove it.
+//!
+//! The protection is lifted to rotate the file into a segment and set again
+//! on the file that replaces it; if that fails the log records
+//! `audit.unprotected` and carries on. It stays in place between runs.
+
+use std::path::Path;
+use std::process::Command;
+
+pub(crate) fn protect(path: &Path) -> Result<(), String> {
+    set(path, true)
+}
+
+pub(crate) fn release(path: &Path) -> Result<(), String> {
+    set(path, false)
+}
+
+fn set(path: &Path, append_only: bool) -> Result<(), String> {
+    let mut command =
+        command(path, append_only).ok_or("append-only files are not supported on this platform")?;
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .output()
+        .map_err(|e| format!("{}: {program}: {e}", path.display()))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(format!(
+        "{}: {program}: {}{PRIVILEGE}",
+        path.display(),
+        String::from_utf8_lossy(&output.stderr).trim()
+    ))
+}
+
+/// What a failure most likely means, where the platform needs privileges.
+#[cfg(target_os = "linux")]
+const PRIVILEGE: &str = " (setting or clearing the attribute needs CAP_LINUX_IMMUTABLE)";
+#[cfg(not(target_os = "linux"))]
+const PRIVILEGE: &str = "";
+
+#[cfg(target_os = "linux")]
+fn command(path: &Path, append_only: bool) -> Option<Command> {
+    let mut command = Command::new("chattr");
+    command.arg(if append_only { "+a" } else { "-a" }).arg(path);
+    Some(command)
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+fn command(path: &Path, append_only: bool) -> Option<Command> {
+    let mut command = Command::new("chflags");
+    command
+        .arg(if append_only { "uappnd" } else { "nouappnd" })
+        .arg(path);
+    Some(command)
+}
+
+#[cfg(windows)]
+fn command(path: &Path, append_only: bool) -> Option<Command> {
+    // S-1-1-0 is Everyone, whatever the system's language.
+    let mut command = Command::new("icacls");
+    command.arg(path);
+    if append_only {
+        command.args(["/deny", "*S-1-1-0:(WD,DE)"]);
+    } else {
+        command.args(["/remove:d", "*S-1-1-0"]);
+    }
+    Some(command)
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly",
+    windows
+)))]
+fn command(_path: &Path, _append_only: bool) -> Option<Command> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::AuditLog;
+    use std::io::Write;
+
+    use std::path::Path;
+
+    /// Whether this process may set the append-only attribute: on Linux it
+    /// needs CAP_LINUX_IMMUTABLE, elsewhere the file's owner may.
+    fn privileged() -> bool {
+        if !cfg!(target_os = "linux") {
+            return true;
+        }
+        let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+        status
+            .lines()
+            .find_map(|l| l.strip_prefix("CapEff:"))
+            .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+            .is_some_and(|caps| caps & (1 << 9) != 0)
+    }
+
+    #[test]
+    fn protected_logs_take_appends_and_rotate() {
+        let dir = std::env::temp_dir().join(format!("saf-audit-protect-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("audit.log");
+        let log = AuditLog::new(&path).expect("open");
+        match (log.with_append_only(), privileged()) {
+            (Ok(log), true) => check_protected(log, &path),
+            (Err(e), false) => {
+                // The failure says why, and nothing is left half-protected.
+                assert!(e.starts_with(&path.display().to_string()), "{e}");
+                assert!(e.contains("CAP_LINUX_IMMUTABLE"), "{e}");
+                std::fs::write(&path, b"").expect("the log is still writable");
+            }
+            (Ok(_), false) => panic!("append-only set without CAP_LINUX_IMMUTABLE"),
+            (Err(e), true) => panic!("{e}"),
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn check_protected(mut log: AuditLog, path: &Path) {
+        log.append("fs.read docs/a.md").expect("append");
+        let rewrite = std::fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(path);
+        assert!(rewrite.is_err());
+        assert!(std::fs::remove_file(path).is_err());
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .and_then(|mut f| f.write_all(b""))
+            .expect("appends are let through");
+
+        let mut log = log.with_rotation(crate::Rotation {
+            max_bytes: Some(1),
+            ..crate::Rotation::default()
+        });
+        log.append("fs.read docs/b.md").expect("rotate");
+        assert_eq!(crate::segments(path).expect("segments").len(), 1);
+        assert!(std::fs::remove_file(path).is_err());
+        drop(log);
+        assert_eq!(crate::verify(path, None).map(|v| v.entries), Ok(3));
+
+        super::release(path).expect("release");
+    }
+}
@@ -0,0 +1,269 @@
+//! Filtered, paginated reads of an audit log.
+//!
+//! An [`AuditReader`] reads the rotated [segments](crate::segment) of a log
+//! and then its active file, oldest entry first, and answers an
+//! [`AuditQuery`] with one [`AuditPage`] of matching records. The chain is
+//! not checked; see [`verify`](crate::verify).
+
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::event::{AuditRecord, Outcome};
+use crate::{segment, split_line, uncanonicalize};
+
+/// Which records to return. Every filter that is set must match.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditQuery {
+    /// Earliest `ts_ms`, inclusive. Legacy entries carry no time and are
+    /// left out once a bound is set.
+    pub since_ms: Option<u64>,
+    /// Latest `ts_ms`, exclusive.
+    pub until_ms: Option<u64>,
+    /// A code (`fs.read_text`) or a dotted prefix of one (`fs`, `policy`).
+    pub code: Option<String>,
+    pub component: Option<String>,
+    /// The host call records were made serving.
+    pub request: Option<String>,
+    pub outcome: Option<Outcome>,
+    /// Matching records to skip.
+    pub offset: usize,
+    /// Most records to return; all of them if unset.
+    pub limit: Option<usize>,
+    /// Count `offset` from the newest record and return newest first.
+    pub newest_first: bool,
+}
+
+impl AuditQuery {
+    pub fn with_since(mut self, ms: u64) -> Self {
+        self.since_ms = Some(ms);
+        self
+    }
+
+    pub fn with_until(mut self, ms: u64) -> Self {
+        self.until_ms = Some(ms);
+        self
+    }
+
+    pub fn with_code(mut self, code: &str) -> Self {
+        self.code = Some(code.to_string());
+        self
+    }
+
+    pub fn with_component(mut self, component: &str) -> Self {
+        self.component = Some(component.to_string());
+        self
+    }
+
+    pub fn with_request(mut self, request: &str) -> Self {
+        self.request = Some(request.to_string());
+        self
+    }
+
+    pub fn with_outcome(mut self, outcome: Outcome) -> Self {
+        self.outcome = Some(outcome);
+        self
+    }
+
+    pub fn with_page(mut self, offset: usize, limit: usize) -> Self {
+        self.offset = offset;
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn with_newest_first(mut self) -> Self {
+        self.newest_first = true;
+        self
+    }
+
+    pub fn matches(&self, record: &AuditRecord) -> bool {
+        let code = self.code.as_deref().map(|c| c.trim_end_matches('.'));
+        self.since_ms
+            .is_none_or(|since| record.ts_ms != 0 && record.ts_ms >= since)
+            && self
+                .until_ms
+                .is_none_or(|until| record.ts_ms != 0 && record.ts_ms < until)
+            && code.is_none_or(|c| {
+                record
+                    .code
+                    .strip_prefix(c)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+            })
+            && self
+                .component
+                .as_deref()
+                .is_none_or(|c| record.component == c)
+            && self
+                .request
+                .as_deref()
+                .is_none_or(|r| record.request.as_deref() == Some(r))
+            && self.outcome.is_none_or(|o| record.outcome == o)
+    }
+}
+
+/// One page of an [`AuditQuery`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AuditPage {
+    pub records: Vec<AuditRecord>,
+    /// Records matching the filters, across all pages.
+    pub total: usize,
+    /// Offset of the next page, if there is one.
+    pub next: Option<usize>,
+}
+
+pub struct AuditReader {
+    files: Vec<PathBuf>,
+}
+
+impl AuditReader {
+    /// A reader of the log at `path` and its rotated segments. A log that
+    /// does not exist yet reads as empty.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let mut files = segment::segments(path)?;
+        if path.exists() {
+            files.push(path.to_path_buf());
+        }
+        Ok(Self { files })
+    }
+
+    /// Every record, oldest first.
+    pub fn records(&self) -> impl Iterator<Item = Result<AuditRecord, String>> + '_ {
+        self.files.iter().flat_map(|file| {
+            let lines: Box<dyn Iterator<Item = Result<String, String>>> = match segment::lines(file)
+            {
+                Ok(reader) => Box::new(
+                    reader
+                        .lines()
+                        .map(move |l| l.map_err(|e| format!("{}: {}", file.display(), e))),
+                ),
+                Err(e) => Box::new(std::iter::once(Err(e))),
+            };
+            lines.filter_map(|line| match line {
+                Ok(line) => split_line(&line)
+                    .map(|(_, _, entry)| Ok(AuditRecord::parse(&uncanonicalize(entry)))),
+                Err(e) => Some(Err(e)),
+            })
+        })
+    }
+
+    pub fn query(&self, query: &AuditQuery) -> Result<AuditPage, String> {
+        let mut matching = Vec::new();
+        for record in self.records() {
+            let record = record?;
+            if query.matches(&record) {
+                matching.push(record);
+            }
+        }
+        if query.newest_first {
+            matching.reverse();
+        }
+        let total = matching.len();
+        let end = query
+            .limit
+            .map_or(total, |limit| query.offset.saturating_add(limit).min(total));
+        let records = matching
+            .drain(query.offset.min(end)..end)
+            .collect::<Vec<_>>();
+        Ok(AuditPage {
+            records,
+            total,
+            next: (end < total).then_some(end),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AuditEvent, AuditLog, Rotation};
+    use saf_codes::Code;
+
+    #[test]
+    fn filters_and_pages_across_segments() {
+        let dir = std::env::temp_dir().join(format!("saf-audit-reader-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("audit.log");
+
+        let mut log = AuditLog::new(&path).expect("open").with_rotation(Rotation {
+            max_bytes: Some(1),
+            ..Rotation::default()
+        });
+        for (i, component) in ["app", "tool", "app"].into_iter().enumerate() {
+            let mut record = AuditRecord::new(
+                Code::FsReadText,
+                AuditEvent::FsRead {
+                    path: format!("docs/{i}.md"),
+                    bytes: 1,
+                    offset: None,
+                },
+            )
+            .with_component(component);
+            record.ts_ms = 1_000 + i as u64;
+            log.record(&record).expect("record");
+        }
+        let mut denied = AuditRecord::new(
+            Code::PolicyPathDenied,
+            AuditEvent::PolicyDenied {
+                capability: "fs".to_string(),
+                target: "secrets/key".to_string(),
+                rule: "deny[0]".to_string(),
+            },
+        )
+        .with_outcome(Outcome::Denied)
+        .with_component("app")
+        .with_request("run_1.3");
+        denied.ts_ms = 2_000;
+        log.record(&denied).expect("record");
+        drop(log);
+        assert!(!segment::segments(&path).expect("segments").is_empty());
+
+        let reader = AuditReader::open(&path).expect("reader");
+        let fs = reader
+            .query(&AuditQuery::default().with_code("fs").with_component("app"))
+            .expect("query");
+        assert_eq!(fs.total, 2);
+        assert_eq!(fs.records[0].ts_ms, 1_000);
+
+        let page = reader
+            .query(
+                &AuditQuery::default()
+                    .with_since(1_001)
+                    .with_until(3_000)
+                    .with_page(0, 2),
+            )
+            .expect("query");
+        assert_eq!((page.total, page.next), (3, Some(2)));
+        let last = reader
+            .query(
+                &AuditQuery::default()
+                    .with_since(1_001)
+                    .with_until(3_000)
+                    .with_page(2, 2),
+            )
+            .expect("query");
+        assert_eq!((last.records.len(), last.next), (1, None));
+        assert_eq!(last.records[0], denied);
+
+        let newest = reader
+            .query(
+                &AuditQuery::default()
+                    .with_outcome(Outcome::Denied)
+                    .with_newest_first()
+                    .with_page(0, 1),
+            )
+            .expect("query");
+        assert_eq!(newest.records, vec![denied.clone()]);
+        let traced = reader
+            .query(&AuditQuery::default().with_request("run_1.3"))
+            .expect("query");
+        assert_eq!(traced.records, vec![denied]);
+        assert!(reader
+            .query(&AuditQuery::default().with_code("fs.read"))
+            .expect("query")
+            .records
+            .is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
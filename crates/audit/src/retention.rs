@@ -0,0 +1,273 @@
+//! Deleting old segments without breaking the chain.
+//!
+//! Pruning removes the oldest rotated segments beyond a [`Retention`]
+//! policy and puts a one-line segment in their place: an
+//! [`AuditEvent::Pruned`] summary with the chain hash the range continued
+//! from, its final hash and what it held, signed like any other entry. The
+//! summary line chains from the final hash, and the next segment's link
+//! still names that hash, so [`verify`](crate::verify) checks what remains
+//! end to end; only the summary's word is taken for what was deleted. A
+//! later pruning folds the earlier summary into its own.
+//!
+//! The range is verified before anything is deleted, so pruning cannot be
+//! used to launder an edited segment.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use ring::signature::Ed25519KeyPair;
+use saf_codes::Code;
+
+use crate::event::{AuditEvent, AuditRecord, Outcome};
+use crate::{canonicalize, format_line, segment, split_line, uncanonicalize, ChainHash};
+
+/// How much of the log to keep. With neither limit set nothing is pruned.
+/// The active file is never pruned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// Segments rotated longer ago than this are pruned.
+    pub max_age: Option<Duration>,
+    /// The oldest segments are pruned until the log fits.
+    pub max_bytes: Option<u64>,
+}
+
+/// What a pruning removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Pruned {
+    pub files: u64,
+    pub entries: u64,
+}
+
+/// Prune the log at `path` as `retention` says, signing the summary with
+/// `signer` if given.
+pub(crate) fn prune(
+    path: &Path,
+    retention: &Retention,
+    signer: Option<&Ed25519KeyPair>,
+    now_ms: u64,
+) -> Result<Pruned, String> {
+    let segments = segment::segments(path)?;
+    let size = |p: &Path| std::fs::metadata(p).map_or(0, |m| m.len());
+    let mut total = segments.iter().map(|s| size(s)).sum::<u64>() + size(path);
+    let mut selected = 0;
+    for segment in &segments {
+        let old = retention.max_age.is_some_and(|age| {
+            segment::stamp(segment)
+                .is_some_and(|ms| u128::from(now_ms.saturating_sub(ms)) >= age.as_millis())
+        });
+        let large = retention.max_bytes.is_some_and(|max| total > max);
+        if !(old || large) {
+            break;
+        }
+        total -= size(segment);
+        selected += 1;
+    }
+    let range = &segments[..selected];
+    let Some(last) = range.last() else {
+        return Ok(Pruned::default());
+    };
+    if range.len() == 1 && summary(last)?.is_some() {
+        return Ok(Pruned::default());
+    }
+
+    let mut from = ChainHash::new();
+    let mut state = ChainHash::new();
+    let (mut files, mut entries) = (0, 0);
+    // Already summarized by an earlier pruning.
+    let (mut earlier_files, mut earlier_entries) = (0, 0);
+    let (mut from_ms, mut until_ms) = (0, 0);
+    let mut outcomes = BTreeMap::new();
+    for (i, file) in range.iter().enumerate() {
+        let name = file.display();
+        if let (
+            0,
+            Some(AuditEvent::Pruned {
+                from_hash,
+                last_hash,
+                files: f,
+                entries: e,
+                from_ms: first,
+                until_ms: end,
+                outcomes: o,
+            }),
+        ) = (i, summary(file)?)
+        {
+            from = ChainHash::parse(&from_hash).ok_or_else(|| format!("{name}: not a hash"))?;
+            state = ChainHash::parse(&last_hash).ok_or_else(|| format!("{name}: not a hash"))?;
+            (files, entries, from_ms, until_ms) = (f, e, first, end);
+            (earlier_files, earlier_entries) = (f, e);
+            outcomes = o;
+            continue;
+        }
+        for (n, line) in segment::lines(file)?.lines().enumerate() {
+            let line = line.map_err(|e| format!("{name}: {e}"))?;
+            let (hash, _, entry) =
+                split_line(&line).ok_or_else(|| format!("{name}:{}: no hash", n + 1))?;
+            let record = AuditRecord::parse(&uncanonicalize(entry));
+            if n == 0 {
+                let link = match &record.event {
+                    AuditEvent::Link { previous_hash, .. } => ChainHash::parse(previous_hash),
+                    _ => None,
+                };
+                match link {
+                    Some(link) if i == 0 => (from, state) = (link, link),
+                    Some(link) if link == state => {}
+                    None if i == 0 => {}
+                    _ => {
+                        return Err(format!(
+                            "{name}:1: does not continue the chain of the file before it"
+                        ))
+                    }
+                }
+            }
+            let next = state.next(entry);
+            if ChainHash::parse(hash) != Some(next) {
+                return Err(format!(
+                    "{name}:{}: hash does not match the chain; not pruning",
+                    n + 1
+                ));
+            }
+            state = next;
+            entries += 1;
+            if record.ts_ms > 0 {
+                if from_ms == 0 {
+                    from_ms = record.ts_ms;
+                }
+                until_ms = record.ts_ms;
+            }
+            let outcome = match record.outcome {
+                Outcome::Ok => "ok",
+                Outcome::Denied => "denied",
+                Outcome::WouldDeny => "would_deny",
+                Outcome::Failed => "failed",
+            };
+            *outcomes.entry(outcome.to_string()).or_insert(0) += 1;
+        }
+        files += 1;
+    }
+
+    let record = AuditRecord::new(
+        Code::AuditPruned,
+        AuditEvent::Pruned {
+            from_hash: from.hex(),
+            last_hash: state.hex(),
+            files,
+            entries,
+            from_ms,
+            until_ms,
+            outcomes,
+        },
+    );
+    let entry = canonicalize(&serde_json::to_string(&record).map_err(|e| e.to_string())?);
+    let line = format_line(&state.next(&entry), signer, &entry);
+
+    // Written aside first; a crash part way through deleting leaves the
+    // remaining segments verifiable, starting with a link.
+    let mut tmp = path.as_os_str().to_os_string();
+    tmp.push(".prune.tmp");
+    let tmp = Path::new(&tmp);
+    std::fs::File::create(tmp)
+        .and_then(|mut f| f.write_all(line.as_bytes()).and_then(|()| f.sync_all()))
+        .map_err(|e| format!("{}: {}", tmp.display(), e))?;
+    for file in range {
+        std::fs::remove_file(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+    }
+    let stamp = segment::stamp(last).unwrap_or(now_ms);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let summary_path = path.with_file_name(format!("{name}.{stamp}"));
+    std::fs::rename(tmp, &summary_path)
+        .map_err(|e| format!("{}: {}", summary_path.display(), e))?;
+    Ok(Pruned {
+        files: files - earlier_files,
+        entries: entries - earlier_entries,
+    })
+}
+
+/// The summary a segment consists of, if it is one.
+fn summary(file: &Path) -> Result<Option<AuditEvent>, String> {
+    let Some(line) = segment::lines(file)?.lines().next() else {
+        return Ok(None);
+    };
+    let line = line.map_err(|e| format!("{}: {}", file.display(), e))?;
+    Ok(split_line(&line)
+        .map(|(_, _, entry)| AuditRecord::parse(&uncanonicalize(entry)).event)
+        .filter(|event| matches!(event, AuditEvent::Pruned { .. })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{verify, AuditLog, AuditReader, Rotation};
+    use ring::signature::KeyPair;
+
+    #[test]
+    fn pruned_segments_leave_a_signed_summary_that_verifies() {
+        let dir = std::env::temp_dir().join(format!("saf-audit-prune-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("audit.log");
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).expect("key");
+        let public = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+            .expect("pair")
+            .public_key()
+            .as_ref()
+            .to_vec();
+
+        let mut log = AuditLog::new(&path)
+            .expect("open")
+            .with_rotation(Rotation {
+                max_bytes: Some(400),
+                max_age: None,
+                compress: true,
+            })
+            .with_signing_key(pkcs8.as_ref())
+            .expect("key");
+        for i in 0..12 {
+            log.append(&format!("fs.read docs/{i}.md")).expect("append");
+        }
+        let before = verify(&path, Some(&public)).expect("verify");
+        let segments = segment::segments(&path).expect("segments").len();
+        assert!(segments >= 3);
+
+        // Keep about one segment's worth.
+        let retention = Retention {
+            max_age: None,
+            max_bytes: Some(std::fs::metadata(&path).expect("size").len() + 400),
+        };
+        let pruned = prune(&path, &retention, None, 0).expect("prune");
+        assert!(pruned.files >= 2);
+        let after = verify(&path, None).expect("verify");
+        assert_eq!(after.pruned, pruned.entries);
+        assert_eq!(after.entries + after.pruned, before.entries);
+        // Unsigned, the summary would let anyone claim any history.
+        assert!(verify(&path, Some(&public)).is_err());
+
+        // A later, signed pruning folds in the first summary.
+        for i in 12..20 {
+            log.append(&format!("fs.read docs/{i}.md")).expect("append");
+        }
+        drop(log);
+        let before = verify(&path, None).expect("verify");
+        let everything = Retention {
+            max_age: Some(Duration::ZERO),
+            max_bytes: None,
+        };
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).expect("pair");
+        prune(&path, &everything, Some(&pair), crate::event::now_ms()).expect("prune");
+        assert_eq!(segment::segments(&path).expect("segments").len(), 1);
+        let after = verify(&path, Some(&public)).expect("verify");
+        assert_eq!(after.entries + after.pruned, before.entries + before.pruned);
+        let summary = AuditReader::open(&path)
+            .expect("reader")
+            .records()
+            .filter_map(Result::ok)
+            .find(|r| r.code == "audit.pruned")
+            .expect("summary");
+        let AuditEvent::Pruned { from_hash, .. } = summary.event else {
+            panic!("not a summary");
+        };
+        assert_eq!(from_hash, ChainHash::new().hex());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,118 @@
+//! Rotated segments of an audit log.
+//!
+//! When the active log outgrows its [`Rotation`] limits it is renamed to
+//! `<log>.<ms>` (the rotation time in milliseconds since the Unix epoch),
+//! optionally gzipped to `<log>.<ms>.gz`, and a new file is started whose
+//! first entry is an [`AuditEvent::Link`](crate::AuditEvent::Link) naming
+//! the segment and its final chain hash. The chain runs on unbroken across
+//! files, so segments can be archived elsewhere and still be verified.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+/// When the active log is rotated. With neither limit set it never is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rotation {
+    pub max_bytes: Option<u64>,
+    /// Age of the oldest entry in the active file.
+    pub max_age: Option<Duration>,
+    /// Gzip rotated segments.
+    pub compress: bool,
+}
+
+impl Rotation {
+    /// Whether a file of `size` bytes whose first entry was written at
+    /// `started_ms` is due for rotation at `now_ms`.
+    pub fn due(&self, size: u64, started_ms: u64, now_ms: u64) -> bool {
+        size > 0
+            && (self.max_bytes.is_some_and(|max| size >= max)
+                || self.max_age.is_some_and(|age| {
+                    u128::from(now_ms.saturating_sub(started_ms)) >= age.as_millis()
+                }))
+    }
+}
+
+/// The rotated segments of the log at `path`, oldest first.
+pub fn segments(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Ok(Vec::new());
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let prefix = format!("{}.", name.to_string_lossy());
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("{}: {}", dir.display(), e)),
+    };
+    let mut found = Vec::new();
+    for entry in entries.flatten() {
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        let Some(rest) = file_name.strip_prefix(&prefix) else {
+            continue;
+        };
+        let stamp = rest.strip_suffix(".gz").unwrap_or(rest);
+        if let Ok(ms) = stamp.parse::<u64>() {
+            found.push((ms, path.with_file_name(&file_name)));
+        }
+    }
+    found.sort();
+    Ok(found.into_iter().map(|(_, p)| p).collect())
+}
+
+/// When the segment at `path` was rotated, from its name.
+pub(crate) fn stamp(path: &Path) -> Option<u64> {
+    let name = path.file_name()?.to_string_lossy();
+    let name = name.strip_suffix(".gz").unwrap_or(&name);
+    name.rsplit_once('.')?.1.parse().ok()
+}
+
+/// A fresh segment name for the log at `path` rotated at `now_ms`.
+pub(crate) fn segment_path(path: &Path, now_ms: u64) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut ms = now_ms;
+    loop {
+        let candidate = path.with_file_name(format!("{name}.{ms}"));
+        let mut gz = candidate.as_os_str().to_os_string();
+        gz.push(".gz");
+        if !candidate.exists() && !Path::new(&gz).exists() {
+            return candidate;
+        }
+        ms += 1;
+    }
+}
+
+/// Gzip `path` to `<path>.gz` and remove the original.
+pub(crate) fn compress(path: &Path) -> Result<PathBuf, String> {
+    let mut gz = path.as_os_str().to_os_string();
+    gz.push(".gz");
+    let gz = PathBuf::from(gz);
+    let mut input = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let output = File::create(&gz).map_err(|e| format!("{}: {}", gz.display(), e))?;
+    let mut encoder = GzEncoder::new(output, flate2::Compression::default());
+    std::io::copy(&mut input, &mut encoder).map_err(|e| format!("{}: {}", gz.display(), e))?;
+    encoder
+        .finish()
+        .and_then(|f| f.sync_all())
+        .map_err(|e| format!("{}: {}", gz.display(), e))?;
+    std::fs::remove_file(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(gz)
+}
+
+/// The lines of a segment or active log, decompressing `.gz` files.
+pub(crate) fn lines(path: &Path) -> Result<Box<dyn BufRead>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(if path.extension().is_some_and(|e| e == "gz") {
+        Box::new(BufReader::new(GzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    })
+}
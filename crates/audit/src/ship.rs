@@ -0,0 +1,354 @@
+//! Shipping the log to a remote collector.
+//!
+//! A [`Shipper`] tails the log, through its rotated segments, and hands
+//! entries in batches to a [`Destination`]. The entry last acknowledged is
+//! kept in a cursor file (`<log>.ship` by default), written only after the
+//! destination accepts a batch, so entries are shipped at least once across
+//! restarts and crashes; a receiver deduplicates by [`ShippedEntry::id`].
+//!
+//! The log itself is the queue: nothing is read past the batch in flight,
+//! so an unreachable collector costs retries with backoff, not memory, and
+//! shipping catches up from the cursor once it is back.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::event::AuditRecord;
+use crate::{segment, split_line, uncanonicalize};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// One entry as shipped.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShippedEntry {
+    /// The entry's chain hash.
+    pub id: String,
+    /// The line as stored, so the receiver can check chain and signature.
+    pub line: String,
+    pub record: AuditRecord,
+}
+
+/// Where entries are shipped to. `send` succeeds only once the whole batch
+/// is stored remotely.
+pub trait Destination: Send {
+    fn send(&mut self, entries: &[ShippedEntry]) -> Result<(), String>;
+}
+
+/// `<log>.ship`, where the shipping cursor is kept by default.
+pub fn cursor_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".ship");
+    path.with_file_name(name)
+}
+
+/// Entries to send, each with where its line starts if it is in the active
+/// file.
+type Batch = Vec<(ShippedEntry, Option<u64>)>;
+
+pub struct Shipper {
+    path: PathBuf,
+    cursor_path: PathBuf,
+    destination: Box<dyn Destination>,
+    batch_size: usize,
+    /// Chain hash of the last entry shipped.
+    cursor: Option<String>,
+    /// Where the cursor's line starts in the active file, if it is there.
+    offset: Option<u64>,
+}
+
+impl Shipper {
+    /// Ship the log at `path` to `destination`, from the cursor at
+    /// `cursor_path` or, without one, from the oldest entry.
+    pub fn new(
+        path: &Path,
+        cursor_path: &Path,
+        destination: impl Destination + 'static,
+    ) -> Result<Self, String> {
+        let cursor = match std::fs::read_to_string(cursor_path) {
+            Ok(c) => Some(c.trim().to_string()).filter(|c| !c.is_empty()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(format!("{}: {}", cursor_path.display(), e)),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            cursor_path: cursor_path.to_path_buf(),
+            destination: Box::new(destination),
+            batch_size: 500,
+            cursor,
+            offset: None,
+        })
+    }
+
+    /// Most entries per batch.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Ship every entry written so far, returning how many were shipped.
+    /// Stops at the first batch the destination refuses.
+    pub fn ship_pending(&mut self) -> Result<u64, String> {
+        let mut shipped = 0;
+        loop {
+            let batch = self.next_batch()?;
+            let Some((last, _)) = batch.last() else {
+                return Ok(shipped);
+            };
+            let (id, offset) = (last.id.clone(), batch.last().and_then(|(_, o)| *o));
+            let entries: Vec<_> = batch.into_iter().map(|(e, _)| e).collect();
+            self.destination.send(&entries)?;
+            self.save_cursor(&id)?;
+            self.cursor = Some(id);
+            self.offset = offset;
+            shipped += entries.len() as u64;
+        }
+    }
+
+    /// Keep shipping from a background thread, polling the log every
+    /// `poll` and backing off while the destination is unreachable.
+    pub fn spawn(mut self, poll: Duration) -> Result<ShipperHandle, String> {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let worker = std::thread::Builder::new()
+            .name("audit-shipper".to_string())
+            .spawn(move || {
+                let mut backoff = None;
+                loop {
+                    let wait = match self.ship_pending() {
+                        Ok(_) => {
+                            backoff = None;
+                            poll
+                        }
+                        Err(_) => {
+                            let next =
+                                backoff.map_or(MIN_BACKOFF, |b: Duration| (b * 2).min(MAX_BACKOFF));
+                            backoff = Some(next);
+                            next
+                        }
+                    };
+                    match stopped.recv_timeout(wait) {
+                        Err(RecvTimeoutError::Timeout) => {}
+                        // One last pass for what was written since, unless
+                        // the destination is down anyway.
+                        _ => {
+                            if backoff.is_none() {
+                                let _ = self.ship_pending();
+                            }
+                            return;
+                        }
+                    }
+                }
+            })
+            .map_err(|e| e.to_string())?;
+        Ok(ShipperHandle {
+            stop: Some(stop),
+            worker: Some(worker),
+        })
+    }
+
+    fn save_cursor(&self, id: &str) -> Result<(), String> {
+        let mut tmp = self.cursor_path.as_os_str().to_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, id)
+            .and_then(|()| std::fs::rename(&tmp, &self.cursor_path))
+            .map_err(|e| format!("{}: {}", self.cursor_path.display(), e))
+    }
+
+    /// Up to `batch_size` entries after the cursor.
+    fn next_batch(&mut self) -> Result<Batch, String> {
+        // Usually the cursor is still where it was in the active file.
+        if let (Some(cursor), Some(offset)) = (&self.cursor, self.offset) {
+            if let Ok(mut file) = File::open(&self.path) {
+                if file.seek(SeekFrom::Start(offset)).is_ok() {
+                    let mut reader = BufReader::new(file);
+                    if let Some((0, entry, len)) = read_entry(&mut reader, &self.path)? {
+                        if entry.id == *cursor {
+                            let mut batch = Vec::new();
+                            let mut at = offset + len;
+                            while batch.len() < self.batch_size {
+                                let Some((start, entry, len)) =
+                                    read_entry(&mut reader, &self.path)?
+                                else {
+                                    break;
+                                };
+                                batch.push((entry, Some(at + start)));
+                                at += len;
+                            }
+                            return Ok(batch);
+                        }
+                    }
+                }
+            }
+        }
+        // Otherwise it was rotated into a segment, or this is the first pass.
+        let batch = self.scan(self.cursor.as_deref())?;
+        match batch {
+            Some(batch) => Ok(batch),
+            // The cursor's entry is gone (pruned, or the log replaced):
+            // start over, as at-least-once allows.
+            None => Ok(self.scan(None)?.unwrap_or_default()),
+        }
+    }
+
+    /// The batch after `cursor` (from the start without one), reading every
+    /// file; `None` if `cursor` is not in the log.
+    fn scan(&self, cursor: Option<&str>) -> Result<Option<Batch>, String> {
+        let mut files = segment::segments(&self.path)?;
+        files.push(self.path.clone());
+        let mut found = cursor.is_none();
+        let mut batch = Vec::new();
+        for file in files.iter().filter(|f| f.exists()) {
+            let active = *file == self.path;
+            let mut reader = segment::lines(file)?;
+            let mut at = 0;
+            while let Some((start, entry, len)) = read_entry(&mut reader, file)? {
+                if found {
+                    batch.push((entry, active.then_some(at + start)));
+                    if batch.len() == self.batch_size {
+                        return Ok(Some(batch));
+                    }
+                } else if Some(entry.id.as_str()) == cursor {
+                    found = true;
+                }
+                at += len;
+            }
+        }
+        Ok(found.then_some(batch))
+    }
+}
+
+/// The next complete line of `reader` as an entry, with where it starts and
+/// the bytes read through it. A line still being written is left for the
+/// next pass; blank and unparseable lines are skipped.
+fn read_entry(
+    reader: &mut dyn BufRead,
+    file: &Path,
+) -> Result<Option<(u64, ShippedEntry, u64)>, String> {
+    let mut skipped = 0;
+    loop {
+        let mut line = String::new();
+        let len = reader
+            .read_line(&mut line)
+            .map_err(|e| format!("{}: {}", file.display(), e))? as u64;
+        if len == 0 || !line.ends_with('\n') {
+            return Ok(None);
+        }
+        let line = line.trim_end_matches(['\n', '\r']);
+        if let Some((hash, _, entry)) = split_line(line) {
+            let entry = ShippedEntry {
+                id: hash.to_string(),
+                line: line.to_string(),
+                record: AuditRecord::parse(&uncanonicalize(entry)),
+            };
+            return Ok(Some((skipped, entry, skipped + len)));
+        }
+        skipped += len;
+    }
+}
+
+/// A running [`Shipper`]; dropping it stops the thread after a last pass.
+pub struct ShipperHandle {
+    stop: Option<Sender<()>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Drop for ShipperHandle {
+    fn drop(&mut self) {
+        self.stop = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AuditLog, Rotation};
+    use std::sync::{Arc, Mutex};
+
+    /// Keeps what it is sent, refusing while `down`.
+    #[derive(Clone, Default)]
+    struct Collector {
+        received: Arc<Mutex<Vec<String>>>,
+        down: Arc<Mutex<bool>>,
+    }
+
+    impl Destination for Collector {
+        fn send(&mut self, entries: &[ShippedEntry]) -> Result<(), String> {
+            if *self.down.lock().expect("lock") {
+                return Err("connection refused".to_string());
+            }
+            let mut received = self.received.lock().expect("lock");
+            received.extend(
+                entries
+                    .iter()
+                    .filter_map(|e| split_line(&e.line).map(|(_, _, entry)| entry.to_string())),
+            );
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn shipping_resumes_from_the_cursor_across_rotation_and_outages() {
+        let dir = std::env::temp_dir().join(format!("saf-audit-ship-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("audit.log");
+        let collector = Collector::default();
+        let received = || collector.received.lock().expect("lock").clone();
+        let shipper = |c: &Collector| {
+            Shipper::new(&path, &cursor_path(&path), c.clone())
+                .expect("shipper")
+                .with_batch_size(2)
+        };
+
+        let mut log = AuditLog::new(&path).expect("open").with_rotation(Rotation {
+            max_bytes: Some(200),
+            max_age: None,
+            compress: true,
+        });
+        for i in 0..3 {
+            log.append(&format!("fs.read docs/{i}.md")).expect("append");
+        }
+        assert_eq!(shipper(&collector).ship_pending(), Ok(3));
+
+        // The collector goes down; nothing is lost, and the cursor holds.
+        *collector.down.lock().expect("lock") = true;
+        for i in 3..8 {
+            log.append(&format!("fs.read docs/{i}.md")).expect("append");
+        }
+        let mut running = shipper(&collector);
+        assert!(running.ship_pending().is_err());
+        assert_eq!(received().len(), 3);
+
+        // Back up, after the entries were rotated into segments.
+        assert!(!segment::segments(&path).expect("segments").is_empty());
+        *collector.down.lock().expect("lock") = false;
+        // Five entries, and the links opening each new segment.
+        assert!(running.ship_pending().is_ok_and(|n| n >= 5));
+        log.append("fs.read docs/8.md").expect("append");
+        assert!(running.ship_pending().is_ok_and(|n| n >= 1));
+        drop(log);
+
+        // A fresh shipper starts from the saved cursor.
+        assert_eq!(shipper(&collector).ship_pending(), Ok(0));
+        let messages = received();
+        let reader = crate::AuditReader::open(&path).expect("reader");
+        assert_eq!(messages.len(), reader.records().count());
+        let paths: Vec<_> = (0..9).map(|i| format!("fs.read docs/{i}.md")).collect();
+        assert_eq!(
+            messages
+                .iter()
+                .filter(|m| m.starts_with("fs.read"))
+                .collect::<Vec<_>>(),
+            paths.iter().collect::<Vec<_>>()
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,224 @@
+//! Following the log as it is written.
+//!
+//! An [`AuditTail`] reads the records appended to a log since it was
+//! opened, whichever process writes them, following the active file when
+//! it is rotated into a segment. Within the writing process,
+//! [`AuditLog::subscribe`](crate::AuditLog::subscribe) hands records over
+//! without reading them back.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::event::AuditRecord;
+use crate::{first_line, segment, split_line, uncanonicalize};
+
+pub struct AuditTail {
+    path: PathBuf,
+    reader: Option<BufReader<File>>,
+    /// First line of the file being read, to notice it was rotated away.
+    first: Option<String>,
+    /// A line still being written.
+    partial: String,
+    /// Chain hash of the last entry read.
+    last: Option<String>,
+    /// While catching up after a rotation, the entry already read up to.
+    after: Option<String>,
+}
+
+impl AuditTail {
+    /// Follow the log at `path` from its current end. The log need not
+    /// exist yet.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let reader = match File::open(path) {
+            Ok(mut file) => {
+                file.seek(SeekFrom::End(0))
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+                Some(BufReader::new(file))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            reader,
+            first: first_line(path),
+            partial: String::new(),
+            last: None,
+            after: None,
+        })
+    }
+
+    /// The records appended since the last call, oldest first.
+    pub fn poll(&mut self) -> Result<Vec<AuditRecord>, String> {
+        let mut records = Vec::new();
+        // Looked at before reading, so a file found rotated away was
+        // complete by the time it is read to the end.
+        let current = first_line(&self.path);
+        self.read(&mut records)?;
+        let rotated = match (&current, &self.first) {
+            (Some(current), Some(first)) => current != first,
+            _ => self.reader.is_none() && current.is_some(),
+        };
+        if rotated {
+            self.catch_up(&mut records)?;
+        }
+        Ok(records)
+    }
+
+    /// After a rotation, read on from the last entry through any segments
+    /// rotated since, then switch to the new active file. If that entry is
+    /// gone, as when the log was replaced, only new records follow.
+    fn catch_up(&mut self, records: &mut Vec<AuditRecord>) -> Result<(), String> {
+        let at = |e: std::io::Error| format!("{}: {}", self.path.display(), e);
+        let mut reader = BufReader::new(File::open(&self.path).map_err(at)?);
+        let mut first = String::new();
+        reader.read_line(&mut first).map_err(at)?;
+        self.after = self.last.clone();
+
+        let segments = segment::segments(&self.path)?;
+        let first_of = |segment: &Path| -> Result<Option<String>, String> {
+            Ok(segment::lines(segment)?
+                .lines()
+                .next()
+                .transpose()
+                .map_err(|e| format!("{}: {}", segment.display(), e))?
+                .map(|l| l.trim_end().to_string()))
+        };
+        // The file that was being read is the newest segment to start from.
+        let mut start = 0;
+        for (i, segment) in segments.iter().enumerate().rev() {
+            if self.first.is_some() && first_of(segment)? == self.first {
+                start = i;
+                break;
+            }
+        }
+        for segment in &segments[start..] {
+            // The file just opened, rotated away in the meantime, is read
+            // through `reader`.
+            if first.ends_with('\n') && first_of(segment)?.as_deref() == Some(first.trim_end()) {
+                break;
+            }
+            for line in segment::lines(segment)?.lines() {
+                let line = line.map_err(|e| format!("{}: {}", segment.display(), e))?;
+                self.take(line.trim_end(), records);
+            }
+        }
+
+        self.reader = Some(reader);
+        self.first = None;
+        self.partial = first;
+        if self.partial.ends_with('\n') {
+            let line = std::mem::take(&mut self.partial);
+            self.first = Some(line.trim_end().to_string());
+            self.take(line.trim_end(), records);
+        }
+        self.read(records)?;
+        self.after = None;
+        Ok(())
+    }
+
+    /// Read the complete lines left in the current file.
+    fn read(&mut self, records: &mut Vec<AuditRecord>) -> Result<(), String> {
+        loop {
+            let Some(reader) = &mut self.reader else {
+                return Ok(());
+            };
+            let read = reader
+                .read_line(&mut self.partial)
+                .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+            if read == 0 || !self.partial.ends_with('\n') {
+                return Ok(());
+            }
+            let line = std::mem::take(&mut self.partial);
+            let line = line.trim_end();
+            if self.first.is_none() && !line.is_empty() {
+                self.first = Some(line.to_string());
+            }
+            self.take(line, records);
+        }
+    }
+
+    /// Keep the record on `line`, unless still catching up.
+    fn take(&mut self, line: &str, records: &mut Vec<AuditRecord>) {
+        let Some((hash, _, entry)) = split_line(line) else {
+            return;
+        };
+        if let Some(after) = &self.after {
+            if hash == after {
+                self.after = None;
+            }
+            return;
+        }
+        self.last = Some(hash.to_string());
+        records.push(AuditRecord::parse(&uncanonicalize(entry)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AuditEvent, AuditLog, AuditWriter, Rotation, WriterConfig};
+    use saf_codes::Code;
+
+    #[test]
+    fn followers_see_new_records_across_rotation() {
+        let dir = std::env::temp_dir().join(format!("saf-audit-tail-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("audit.log");
+        let record = |i: u64| {
+            AuditRecord::new(
+                Code::FsReadText,
+                AuditEvent::FsRead {
+                    path: format!("docs/{i}.md"),
+                    bytes: i,
+                    offset: None,
+                },
+            )
+        };
+        let paths = |records: Vec<AuditRecord>| -> Vec<String> {
+            records
+                .into_iter()
+                .filter_map(|r| match r.event {
+                    AuditEvent::FsRead { path, .. } => Some(path),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        let mut tail = AuditTail::open(&path).expect("tail");
+        let mut log = AuditLog::new(&path).expect("open").with_rotation(Rotation {
+            max_bytes: Some(400),
+            max_age: None,
+            compress: true,
+        });
+        let live = log.subscribe();
+        log.record(&record(0)).expect("record");
+        assert_eq!(paths(tail.poll().expect("poll")), ["docs/0.md"]);
+        assert_eq!(tail.poll().expect("poll"), []);
+
+        // Enough to rotate more than once between polls.
+        for i in 1..6 {
+            log.record(&record(i)).expect("record");
+        }
+        assert!(!crate::segments(&path).expect("segments").is_empty());
+        let expected: Vec<_> = (1..6).map(|i| format!("docs/{i}.md")).collect();
+        assert_eq!(paths(tail.poll().expect("poll")), expected[..]);
+        assert_eq!(paths(live.try_iter().collect()).len(), 6);
+        drop(live);
+        log.record(&record(6)).expect("record");
+        assert!(log.subscribers.is_empty());
+
+        // A writer's subscribers get records as they are written.
+        let writer = AuditWriter::spawn(log, WriterConfig::default()).expect("writer");
+        let live = writer.subscribe().expect("subscribe");
+        writer.record(record(7)).expect("record");
+        writer.flush().expect("flush");
+        assert_eq!(paths(live.try_iter().collect()), ["docs/7.md"]);
+        assert_eq!(
+            paths(tail.poll().expect("poll")),
+            ["docs/6.md", "docs/7.md"]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,255 @@
+//! Appending to an audit log from a background thread.
+//!
+//! [`AuditLog::record`] writes, flushes and updates the head for every
+//! entry. An [`AuditWriter`] hands records to a thread that owns the log
+//! instead, through a bounded channel, and syncs them in batches: once
+//! `batch_size` records are waiting or `flush_interval_ms` has passed, and
+//! whenever [`flush`](AuditWriter::flush) is called. Callers block only
+//! when the channel is full, so records are never dropped. Each record
+//! still reaches the file before the log's lock is let go, so other
+//! writers continue after it; only the head and `fsync` wait for the batch.
+//!
+//! A crash of the machine loses at most the records not yet synced; the
+//! head may then lag the file by a batch, which [`AuditLog::new`] and
+//! [`verify`](crate::verify) accept.
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::event::AuditRecord;
+use crate::AuditLog;
+
+/// When flushed entries are forced to disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fsync {
+    /// Leave it to the OS.
+    #[default]
+    Never,
+    /// `fsync` after every batch.
+    Batch,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WriterConfig {
+    /// Records queued before callers wait.
+    pub capacity: usize,
+    /// Records written between flushes.
+    pub batch_size: usize,
+    /// Longest a record waits to be flushed.
+    pub flush_interval_ms: u64,
+    pub fsync: Fsync,
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 4_096,
+            batch_size: 256,
+            flush_interval_ms: 200,
+            fsync: Fsync::Never,
+        }
+    }
+}
+
+enum Message {
+    Record(Box<AuditRecord>),
+    /// Flush, then report the first error since the last report.
+    Flush(Sender<Result<(), String>>),
+    Subscribe(Sender<AuditRecord>),
+}
+
+pub struct AuditWriter {
+    queue: Option<SyncSender<Message>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl AuditWriter {
+    /// Move `log` to a background thread.
+    pub fn spawn(log: AuditLog, config: WriterConfig) -> Result<Self, String> {
+        if config.capacity == 0 || config.batch_size == 0 {
+            return Err("capacity and batch_size must be at least 1".to_string());
+        }
+        let (queue, rx) = mpsc::sync_channel(config.capacity);
+        let worker = std::thread::Builder::new()
+            .name("audit-writer".to_string())
+            .spawn(move || run(log, &config, &rx))
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            queue: Some(queue),
+            worker: Some(worker),
+        })
+    }
+
+    /// Queue `record`, waiting for room if the queue is full. Write errors
+    /// are reported by [`flush`](Self::flush).
+    pub fn record(&self, record: AuditRecord) -> Result<(), String> {
+        self.queue
+            .as_ref()
+            .ok_or("audit writer closed")?
+            .send(Message::Record(Box::new(record)))
+            .map_err(|_| "audit writer stopped".to_string())
+    }
+
+    /// Every record written after those queued so far, until the receiver
+    /// is dropped. Records reach it as they are written, before the batch
+    /// is flushed.
+    pub fn subscribe(&self) -> Result<Receiver<AuditRecord>, String> {
+        let (tx, rx) = mpsc::channel();
+        self.queue
+            .as_ref()
+            .ok_or("audit writer closed")?
+            .send(Message::Subscribe(tx))
+            .map_err(|_| "audit writer stopped".to_string())?;
+        Ok(rx)
+    }
+
+    /// Wait until every record queued so far is written and flushed.
+    pub fn flush(&self) -> Result<(), String> {
+        let (done, result) = mpsc::channel();
+        self.queue
+            .as_ref()
+            .ok_or("audit writer closed")?
+            .send(Message::Flush(done))
+            .map_err(|_| "audit writer stopped".to_string())?;
+        result
+            .recv()
+            .map_err(|_| "audit writer stopped".to_string())?
+    }
+
+    /// Flush and stop the writer thread.
+    pub fn close(mut self) -> Result<(), String> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Result<(), String> {
+        let Some(worker) = self.worker.take() else {
+            return Ok(());
+        };
+        let flushed = self.flush();
+        self.queue = None;
+        worker
+            .join()
+            .map_err(|_| "audit writer panicked".to_string())?;
+        flushed
+    }
+}
+
+impl Drop for AuditWriter {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+fn run(mut log: AuditLog, config: &WriterConfig, rx: &Receiver<Message>) {
+    let interval = Duration::from_millis(config.flush_interval_ms);
+    let fsync = config.fsync == Fsync::Batch;
+    let mut unflushed = 0;
+    let mut first_unflushed = Instant::now();
+    let mut error: Option<String> = None;
+    log.set_batching(true);
+    loop {
+        let message = if unflushed == 0 {
+            rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            rx.recv_timeout((first_unflushed + interval).saturating_duration_since(Instant::now()))
+        };
+        match message {
+            Ok(Message::Record(record)) => {
+                if unflushed == 0 {
+                    first_unflushed = Instant::now();
+                }
+                keep(&mut error, log.record(&record));
+                unflushed += 1;
+                if unflushed >= config.batch_size {
+                    keep(&mut error, log.sync(fsync));
+                    unflushed = 0;
+                }
+            }
+            Ok(Message::Flush(done)) => {
+                keep(&mut error, log.sync(fsync));
+                unflushed = 0;
+                let _ = done.send(error.take().map_or(Ok(()), Err));
+            }
+            Ok(Message::Subscribe(subscriber)) => log.subscribers.push(subscriber),
+            Err(RecvTimeoutError::Timeout) => {
+                keep(&mut error, log.sync(fsync));
+                unflushed = 0;
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                let _ = log.sync(fsync);
+                return;
+            }
+        }
+    }
+}
+
+/// Hold on to the first of several errors.
+fn keep(error: &mut Option<String>, result: Result<(), String>) {
+    if let Err(e) = result {
+        error.get_or_insert(e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{verify, AuditEvent, AuditReader};
+    use saf_codes::Code;
+
+    #[test]
+    fn batches_are_flushed_on_demand_and_on_close() {
+        let dir = std::env::temp_dir().join(format!("saf-audit-writer-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("audit.log");
+        let record = |i: u64| {
+            AuditRecord::new(
+                Code::FsReadText,
+                AuditEvent::FsRead {
+                    path: format!("docs/{i}.md"),
+                    bytes: i,
+                    offset: None,
+                },
+            )
+        };
+
+        let writer = AuditWriter::spawn(
+            AuditLog::new(&path).expect("open"),
+            WriterConfig {
+                capacity: 4,
+                batch_size: 1_000,
+                flush_interval_ms: 60_000,
+                fsync: Fsync::Batch,
+            },
+        )
+        .expect("spawn");
+        for i in 0..10 {
+            writer.record(record(i)).expect("record");
+        }
+        writer.flush().expect("flush");
+        let count = |path| AuditReader::open(path).expect("reader").records().count();
+        assert_eq!(count(&path), 10);
+        assert_eq!(verify(&path, None).map(|v| v.entries), Ok(10));
+
+        writer.record(record(10)).expect("record");
+        writer.close().expect("close");
+        assert_eq!(count(&path), 11);
+
+        // A head left a batch behind by a crash still resumes and verifies.
+        let head = std::fs::read_to_string(crate::head_path(&path)).expect("head");
+        let mut log = AuditLog::new(&path).expect("reopen");
+        log.set_batching(true);
+        for i in 11..14 {
+            log.record(&record(i)).expect("record");
+        }
+        drop(log);
+        std::fs::write(crate::head_path(&path), head).expect("rewind head");
+        assert_eq!(verify(&path, None).map(|v| v.entries), Ok(14));
+        assert!(AuditLog::new(&path).is_ok());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,278 @@
+//! Permission prompts for `ask` rules.
+//!
+//! A host or path listed under `ask_domains` / `ask_paths` (and neither
+//! allowed nor denied outright) is put to the user through a
+//! [`PromptHost`]. Answers hold for the rest of the run; "always" and
+//! "never" are also stored per component identity in
+//! `<data_dir>/secure-app-framework/permissions.json`, outside any
+//! workspace so a component cannot answer for itself. Every answer, asked
+//! or remembered, is audited.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use saf_core::{
+    AuditEvent, AuditRecord, Capability, Code, ComponentIdentity, Denial, LogHost, Outcome,
+    PermissionRequest, PromptAnswer, PromptHost,
+};
+use serde::{Deserialize, Serialize};
+
+/// Stored "always" / "never" answers.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Remembered {
+    /// Whether access is allowed, keyed by component identity and then by
+    /// `<capability>:<subject>`.
+    pub components: BTreeMap<String, BTreeMap<String, bool>>,
+}
+
+impl Remembered {
+    pub fn path() -> Result<PathBuf, String> {
+        Ok(dirs::data_dir()
+            .ok_or("No data directory available")?
+            .join("secure-app-framework")
+            .join("permissions.json"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("invalid permissions {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, content).map_err(|e| e.to_string())
+    }
+}
+
+/// Resolves [`Code::PolicyAsk`] decisions for one run.
+pub struct Asker<'a> {
+    prompt: Box<dyn PromptHost>,
+    log: &'a dyn LogHost,
+    component: &'a ComponentIdentity,
+    /// Key for stored answers: `sha256:<hex>` of the component, or
+    /// `broker` for the built-in demo.
+    identity: String,
+    store: Option<PathBuf>,
+    // Held while prompting, so concurrent calls about the same subject
+    // wait for one answer instead of asking twice.
+    session: Mutex<HashMap<String, bool>>,
+}
+
+impl<'a> Asker<'a> {
+    pub fn new(
+        prompt: Box<dyn PromptHost>,
+        log: &'a dyn LogHost,
+        component: &'a ComponentIdentity,
+        store: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            prompt,
+            log,
+            component,
+            // Answers follow the component's content, not its name.
+            identity: match &component.sha256 {
+                Some(sha256) => format!("sha256:{sha256}"),
+                None => "broker".to_string(),
+            },
+            store,
+            session: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Turn an `ask` decision into allow or [`Code::PolicyAskDeclined`];
+    /// other decisions pass through. `subject` names what the answer covers
+    /// and is only computed when a question is needed.
+    pub fn resolve(
+        &self,
+        capability: Capability,
+        decision: Result<(), Denial>,
+        subject: impl FnOnce() -> String,
+    ) -> Result<(), Denial> {
+        match decision {
+            Err(d) if d.code == Code::PolicyAsk => {
+                if self.confirm(capability, &subject(), &d.rule_id) {
+                    Ok(())
+                } else {
+                    Err(Denial::new(Code::PolicyAskDeclined, d.rule_id))
+                }
+            }
+            other => other,
+        }
+    }
+
+    fn confirm(&self, capability: Capability, subject: &str, rule_id: &str) -> bool {
+        let key = format!("{capability}:{subject}");
+        let mut session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(&allowed) = session.get(&key) {
+            return allowed;
+        }
+        let remembered = self.store.as_deref().and_then(|path| {
+            Remembered::load(path)
+                .ok()?
+                .components
+                .get(&self.identity)?
+                .get(&key)
+                .copied()
+        });
+        let (allowed, answer, source) = match remembered {
+            Some(allowed) => (
+                allowed,
+                if allowed { "allow" } else { "deny" },
+                "remembered",
+            ),
+            None => {
+                let answer = self.prompt.ask(&PermissionRequest {
+                    component: &self.component.name,
+                    capability,
+                    subject,
+                    rule_id,
+                });
+                if answer.is_permanent() {
+                    if let Err(e) = self.remember(&key, answer.allows()) {
+                        eprintln!("Could not store permission answer: {e}");
+                    }
+                }
+                (answer.allows(), answer.as_str(), "prompt")
+            }
+        };
+        self.log.record(
+            AuditRecord::new(
+                Code::PolicyAskAnswered,
+                AuditEvent::PolicyAskAnswered {
+                    capability: capability.to_string(),
+                    target: subject.to_string(),
+                    rule: rule_id.to_string(),
+                    answer: answer.to_string(),
+                    source: source.to_string(),
+                },
+            )
+            .with_identity(self.component)
+            .with_outcome(if allowed {
+                Outcome::Ok
+            } else {
+                Outcome::Denied
+            }),
+        );
+        session.insert(key, allowed);
+        allowed
+    }
+
+    fn remember(&self, key: &str, allowed: bool) -> Result<(), String> {
+        let path = self.store.as_deref().ok_or("no data directory")?;
+        let mut remembered = Remembered::load(path)?;
+        remembered
+            .components
+            .entry(self.identity.clone())
+            .or_default()
+            .insert(key.to_string(), allowed);
+        remembered.save(path)
+    }
+}
+
+/// Asks on the controlling terminal. Without one (e.g. in CI) nothing can
+/// be confirmed, so the answer is "no" for this run.
+pub struct TerminalPrompt;
+
+impl PromptHost for TerminalPrompt {
+    fn ask(&self, request: &PermissionRequest<'_>) -> PromptAnswer {
+        let question = format!(
+            "{} wants {} access to {} ({})",
+            request.component, request.capability, request.subject, request.rule_id
+        );
+        if !std::io::stdin().is_terminal() {
+            eprintln!("{question}; denied, no terminal to ask on");
+            return PromptAnswer::DenySession;
+        }
+        let stdin = std::io::stdin();
+        loop {
+            eprint!("{question}. Allow? [y]es for this run, [a]lways, [n]o, ne[v]er: ");
+            let _ = std::io::stderr().flush();
+            let mut line = String::new();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => return PromptAnswer::DenySession,
+                Ok(_) => {}
+            }
+            match line.trim().to_ascii_lowercase().as_str() {
+                "y" | "yes" => return PromptAnswer::AllowSession,
+                "a" | "always" => return PromptAnswer::AllowAlways,
+                "" | "n" | "no" => return PromptAnswer::DenySession,
+                "v" | "never" => return PromptAnswer::DenyAlways,
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct Scripted {
+        answer: PromptAnswer,
+        asked: Arc<AtomicUsize>,
+    }
+    impl PromptHost for Scripted {
+        fn ask(&self, _request: &PermissionRequest<'_>) -> PromptAnswer {
+            self.asked.fetch_add(1, Ordering::SeqCst);
+            self.answer
+        }
+    }
+
+    struct NoLog;
+    impl LogHost for NoLog {
+        fn record(&self, _record: AuditRecord) {}
+    }
+
+    #[test]
+    fn answers_are_cached_per_run_and_remembered_when_permanent() {
+        let store = std::env::temp_dir().join(format!("saf-ask-{}.json", uuid::Uuid::new_v4()));
+        let asked = Arc::new(AtomicUsize::new(0));
+        let app = ComponentIdentity::new("app").with_sha256("ab");
+        let asker = |answer| {
+            Asker::new(
+                Box::new(Scripted {
+                    answer,
+                    asked: asked.clone(),
+                }),
+                &NoLog,
+                &app,
+                Some(store.clone()),
+            )
+        };
+        let ask = || Err(Denial::new(Code::PolicyAsk, "ask_domains[0]"));
+        let host = || "api.example.org".to_string();
+
+        let run = asker(PromptAnswer::AllowSession);
+        assert!(run.resolve(Capability::Net, ask(), host).is_ok());
+        assert!(run.resolve(Capability::Net, ask(), host).is_ok());
+        assert_eq!(asked.load(Ordering::SeqCst), 1);
+        // Decisions that are not `ask` are untouched.
+        let denied = Denial::new(Code::PolicyDomainDenied, "denied_domains[0]");
+        assert_eq!(
+            run.resolve(Capability::Net, Err(denied.clone()), host),
+            Err(denied)
+        );
+
+        // A session answer is forgotten by the next run; "never" is not.
+        let run = asker(PromptAnswer::DenyAlways);
+        assert_eq!(
+            run.resolve(Capability::Net, ask(), host),
+            Err(Denial::new(Code::PolicyAskDeclined, "ask_domains[0]"))
+        );
+        let run = asker(PromptAnswer::AllowAlways);
+        assert!(run.resolve(Capability::Net, ask(), host).is_err());
+        assert_eq!(asked.load(Ordering::SeqCst), 2);
+        let _ = std::fs::remove_file(&store);
+    }
+}
@@ -0,0 +1,677 @@
+//! The workspace audit log and `broker audit`.
+//!
+//! Entries are signed when the broker holds an audit signing key: the
+//! `audit-signing-key` entry of the [secret store](crate::secrets), created
+//! by `broker audit keygen`. Keep a copy of the printed public key away from
+//! the machine; `broker audit verify --key` checks a log against it, so a
+//! rewritten log is caught even if the local key is replaced.
+//!
+//! The log is rotated into gzipped segments (`audit.log.<ms>.gz`) once it
+//! reaches 10 MiB or a month of entries; `verify` follows the chain through
+//! every segment still present, and `query` reads through them too.
+//! `[audit.retention]` prunes old segments after each rotation, or on
+//! `broker audit prune` in an elevated session, leaving a signed summary in their place (see
+//! [`saf_audit::retention`]).
+//!
+//! Records are also sent to any exporters configured under
+//! `[[audit.export]]` and `[audit.otlp]` in the [broker config](crate::config).
+//! `[audit.checkpoint]` anchors the chain hash every N entries in a file on
+//! other storage or with a remote service; `verify --anchors` then also
+//! catches a log replaced wholesale. With a `tsa_url`, each checkpoint is
+//! also timestamped by that authority (see [`crate::tsa`]), and `verify`
+//! reports the timestamps that cover the log.
+//!
+//! `broker audit prove` shows a single entry to someone without the rest
+//! of the log: it prints a Merkle inclusion proof (see [`saf_audit::merkle`])
+//! for the entry, named by its chain hash (the first field of its line, or
+//! a unique prefix) or as `#<N>`, counting from 0 through every segment.
+//! The root is signed with the audit key; `broker audit check-proof` checks
+//! a proof against the public key.
+//!
+//! With `[audit.ship]` configured, each run also ships the log to a remote
+//! collector (see [`crate::ship`]); `broker audit ship` catches up between
+//! runs.
+//!
+//! A workspace moving to another machine takes its chain along (see
+//! [`saf_audit::handoff`]): `broker audit handoff` on the old machine prints
+//! the log's head, signed with the audit key, and `broker audit import` on
+//! the new one checks it against `--key` (or the configured signing key)
+//! and continues the chain, in the log that came along with the workspace
+//! or in a new one linked to the log left behind.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use clap::{Args, Subcommand, ValueEnum};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use saf_audit::{
+    Anchor, AuditEvent, AuditLog, AuditQuery, AuditReader, AuditWriter, Checkpoint, Exporter,
+    FileAnchor, Handoff, InclusionProof, MerkleTree, Outcome, Pruned, Rotation, Shipper,
+    ShipperHandle,
+};
+use serde_json::json;
+
+use crate::config::{AuditConfig, BrokerConfig};
+use crate::elevation::ElevationStore;
+use crate::otlp::OtlpExporter;
+use crate::policy_sig::fingerprint;
+use crate::secrets::SecretStore;
+use crate::ship::{HttpDestination, ShipConfig};
+use crate::tsa::TsaAnchor;
+
+const SIGNING_KEY: &str = "audit-signing-key";
+
+/// `log audit`, as syslog exporters default to.
+const SYSLOG_FACILITY: u8 = 13;
+
+const ANCHOR_TIMEOUT: Duration = Duration::from_secs(5);
+
+const ROTATION: Rotation = Rotation {
+    max_bytes: Some(10 << 20),
+    max_age: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+    compress: true,
+};
+
+/// Open the audit log at `path`, rotating it, exporting records and
+/// signing entries as configured.
+pub fn open(path: &Path) -> Result<AuditLog, String> {
+    let config_path = BrokerConfig::path()?;
+    let config = BrokerConfig::load(&config_path)?.audit;
+    open_with(path, &config_path, config)
+}
+
+/// Open the audit log at `path` as [`open`] does, written from a background
+/// thread as `[audit.writer]` in the broker config says.
+pub fn writer(path: &Path) -> Result<AuditWriter, String> {
+    let config_path = BrokerConfig::path()?;
+    let config = BrokerConfig::load(&config_path)?.audit;
+    let writer = config.writer.clone();
+    AuditWriter::spawn(open_with(path, &config_path, config)?, writer)
+        .map_err(|e| format!("{}: audit.writer: {}", config_path.display(), e))
+}
+
+/// Ship the log at `path` from a background thread, if `[audit.ship]` is
+/// configured.
+pub fn shipper(path: &Path) -> Result<Option<ShipperHandle>, String> {
+    let config_path = BrokerConfig::path()?;
+    let Some(config) = BrokerConfig::load(&config_path)?.audit.ship else {
+        return Ok(None);
+    };
+    let poll = Duration::from_millis(config.poll_interval_ms);
+    ship_with(path, &config)
+        .and_then(|shipper| shipper.spawn(poll))
+        .map(Some)
+        .map_err(|e| format!("{}: audit.ship: {}", config_path.display(), e))
+}
+
+fn ship_with(path: &Path, config: &ShipConfig) -> Result<Shipper, String> {
+    let cursor = match &config.cursor {
+        Some(c) => c.clone(),
+        None => saf_audit::ship::cursor_path(path),
+    };
+    let destination = HttpDestination::new(config, path.display().to_string())?;
+    Ok(Shipper::new(path, &cursor, destination)?.with_batch_size(config.batch_size))
+}
+
+fn open_with(path: &Path, config_path: &Path, config: AuditConfig) -> Result<AuditLog, String> {
+    let mut log = AuditLog::new(path)?.with_rotation(ROTATION);
+    if config.append_only {
+        log = log
+            .with_append_only()
+            .map_err(|e| format!("{}: audit.append_only: {}", config_path.display(), e))?;
+    }
+    if let Some(retention) = &config.retention {
+        log = log.with_retention(retention.retention());
+    }
+    for export in config.export {
+        let exporter = Exporter::new(&export)
+            .map_err(|e| format!("{}: audit export: {}", config_path.display(), e))?;
+        log = log.with_exporter(exporter);
+    }
+    if let Some(otlp) = config.otlp {
+        let exporter = OtlpExporter::new(otlp)
+            .map_err(|e| format!("{}: audit.otlp: {}", config_path.display(), e))?;
+        log = log.with_exporter(exporter);
+    }
+    if let Some(checkpoint) = config.checkpoint {
+        log = log.with_checkpoints(checkpoint.every);
+        if let Some(file) = &checkpoint.anchor_file {
+            log = log.with_anchor(FileAnchor::new(file));
+        }
+        if let Some(url) = checkpoint.anchor_url {
+            log = log.with_anchor(HttpAnchor::new(url));
+        }
+        if let Some(url) = checkpoint.tsa_url {
+            log = log.with_anchor(TsaAnchor::new(url, path));
+        }
+    }
+    match signing_key()? {
+        Some(pkcs8) => log.with_signing_key(&pkcs8),
+        None => Ok(log),
+    }
+}
+
+/// Posts each checkpoint as JSON to `[audit.checkpoint] anchor_url`.
+struct HttpAnchor {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl HttpAnchor {
+    fn new(url: String) -> Self {
+        Self {
+            url,
+            agent: ureq::Agent::config_builder()
+                .timeout_global(Some(ANCHOR_TIMEOUT))
+                .build()
+                .into(),
+        }
+    }
+}
+
+impl Anchor for HttpAnchor {
+    fn anchor(&mut self, checkpoint: &Checkpoint) -> Result<(), String> {
+        let body = serde_json::to_string(checkpoint).map_err(|e| e.to_string())?;
+        self.agent
+            .post(&self.url)
+            .header("content-type", "application/json")
+            .send(body)
+            .map(drop)
+            .map_err(|e| format!("{}: {}", self.url, e))
+    }
+}
+
+/// The configured signing key, PKCS#8-encoded, if there is one.
+pub fn signing_key() -> Result<Option<Vec<u8>>, String> {
+    SecretStore::new()?.find(SIGNING_KEY)
+}
+
+/// The public half of the configured signing key, if there is one.
+fn public_key() -> Result<Option<Vec<u8>>, String> {
+    let Some(pkcs8) = signing_key()? else {
+        return Ok(None);
+    };
+    let pair = Ed25519KeyPair::from_pkcs8(&pkcs8)
+        .map_err(|e| format!("invalid audit signing key: {e}"))?;
+    Ok(Some(pair.public_key().as_ref().to_vec()))
+}
+
+/// A `--key` argument: a base64 ed25519 public key.
+fn parse_key(key: &str) -> Result<Vec<u8>, String> {
+    BASE64
+        .decode(key.trim())
+        .ok()
+        .filter(|k| k.len() == 32)
+        .ok_or_else(|| "--key: expected a base64 ed25519 public key".to_string())
+}
+
+/// `.saf/audit.log` in the current directory.
+fn default_log() -> Result<PathBuf, String> {
+    Ok(std::env::current_dir()
+        .map_err(|e| e.to_string())?
+        .join(".saf")
+        .join("audit.log"))
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AuditCommand {
+    /// Create the audit signing key and print its public half.
+    Keygen,
+    /// Check the log's chain, signatures, checkpoints and timestamps.
+    Verify {
+        /// The log (default: .saf/audit.log).
+        log: Option<PathBuf>,
+        /// Base64 ed25519 public key to check signatures against (default:
+        /// the configured signing key's).
+        #[arg(long, value_name = "PUBLIC_KEY")]
+        key: Option<String>,
+        /// Anchor file to check checkpoints against (default: the
+        /// configured one).
+        #[arg(long, value_name = "FILE")]
+        anchors: Option<PathBuf>,
+        #[arg(long)]
+        json: bool,
+    },
+    /// List matching entries, oldest first, 100 to a page.
+    Query(QueryArgs),
+    /// Print matching entries one per line, as JSON, syslog or CEF.
+    Export {
+        #[command(flatten)]
+        filter: Filter,
+        #[arg(long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+    },
+    /// Print a Merkle inclusion proof for one entry.
+    Prove {
+        /// The entry's chain hash, a unique prefix of it, or `#<N>`.
+        entry: String,
+        log: Option<PathBuf>,
+    },
+    /// Check an inclusion proof printed by `prove`.
+    CheckProof {
+        file: PathBuf,
+        #[arg(long, value_name = "PUBLIC_KEY")]
+        key: Option<String>,
+    },
+    /// Prune old segments as `[audit.retention]` says (needs `broker
+    /// elevate`).
+    Prune { log: Option<PathBuf> },
+    /// Ship entries not yet shipped to `[audit.ship]`.
+    Ship { log: Option<PathBuf> },
+    /// Print the log's head, signed, for continuing it on another machine.
+    Handoff { log: Option<PathBuf> },
+    /// Continue the chain handed off from another machine.
+    Import {
+        handoff: PathBuf,
+        log: Option<PathBuf>,
+        #[arg(long, value_name = "PUBLIC_KEY")]
+        key: Option<String>,
+    },
+}
+
+/// Which entries `query` and `export` read.
+#[derive(Debug, Clone, Args)]
+pub struct Filter {
+    /// The log (default: .saf/audit.log).
+    pub log: Option<PathBuf>,
+    /// Earliest time, in ms since the epoch.
+    #[arg(long, value_name = "MS")]
+    pub since: Option<u64>,
+    /// Latest time, in ms since the epoch, exclusive.
+    #[arg(long, value_name = "MS")]
+    pub until: Option<u64>,
+    /// A code, or a dotted prefix of one (`fs`, `policy`).
+    #[arg(long)]
+    pub code: Option<String>,
+    #[arg(long, value_name = "ID")]
+    pub component: Option<String>,
+    #[arg(long, value_name = "ID")]
+    pub request: Option<String>,
+    /// ok, denied, would_deny or failed.
+    #[arg(long, value_parser = parse_outcome)]
+    pub outcome: Option<Outcome>,
+}
+
+impl Filter {
+    fn query(&self) -> AuditQuery {
+        AuditQuery {
+            since_ms: self.since,
+            until_ms: self.until,
+            code: self.code.clone(),
+            component: self.component.clone(),
+            request: self.request.clone(),
+            outcome: self.outcome,
+            ..AuditQuery::default()
+        }
+    }
+
+    fn log(&self) -> Result<PathBuf, String> {
+        log_or_default(self.log.clone())
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct QueryArgs {
+    #[command(flatten)]
+    pub filter: Filter,
+    /// Matching entries to skip.
+    #[arg(long, default_value_t = 0)]
+    pub offset: usize,
+    #[arg(long, default_value_t = 100)]
+    pub limit: usize,
+    /// Count `--offset` from the newest entry and list newest first.
+    #[arg(long)]
+    pub newest_first: bool,
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// The entry as logged.
+    Json,
+    /// RFC 5424 syslog.
+    Syslog,
+    /// ArcSight Common Event Format.
+    Cef,
+}
+
+fn parse_outcome(outcome: &str) -> Result<Outcome, String> {
+    serde_json::from_value(serde_json::Value::String(outcome.to_string()))
+        .map_err(|_| format!("unknown outcome {outcome:?}"))
+}
+
+fn log_or_default(log: Option<PathBuf>) -> Result<PathBuf, String> {
+    match log {
+        Some(p) => Ok(p),
+        None => default_log(),
+    }
+}
+
+/// Entry point for `broker audit`. Without a log path, commands use
+/// `.saf/audit.log` in the current directory; without `--key`, signatures
+/// are checked against the configured signing key, if any; without
+/// `--anchors`, against the configured anchor file, if any.
+pub fn main(command: AuditCommand) -> Result<(), String> {
+    match command {
+        AuditCommand::Keygen => {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new())
+                .map_err(|e| format!("key generation failed: {e}"))?;
+            let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).map_err(|e| e.to_string())?;
+            SecretStore::new()?.put(SIGNING_KEY, pkcs8.as_ref())?;
+            let public = pair.public_key().as_ref();
+            println!("Audit entries will be signed from the next run on.");
+            println!("Public key (keep a copy to verify logs with --key):");
+            println!("{}  # {}", BASE64.encode(public), fingerprint(public));
+            Ok(())
+        }
+        AuditCommand::Verify {
+            log,
+            key,
+            anchors,
+            json,
+        } => verify(log_or_default(log)?, key, anchors, json),
+        AuditCommand::Query(args) => query(&args),
+        AuditCommand::Export { filter, format } => export(&filter, format),
+        AuditCommand::Prove { entry, log } => {
+            let path = log_or_default(log)?;
+            let tree = MerkleTree::from_log(&path)?;
+            let index = match entry.strip_prefix('#') {
+                Some(n) => n
+                    .parse()
+                    .map_err(|_| format!("{entry}: expected #<N> or a chain hash"))?,
+                None => tree.find(&entry)?,
+            };
+            let pkcs8 = SecretStore::new()?.find(SIGNING_KEY)?;
+            let proof = tree.prove(index, pkcs8.as_deref())?;
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&proof).map_err(|e| e.to_string())?
+            );
+            Ok(())
+        }
+        AuditCommand::CheckProof { file, key } => {
+            let key = match key {
+                Some(k) => Some(parse_key(&k)?),
+                None => public_key()?,
+            };
+            let content =
+                std::fs::read_to_string(&file).map_err(|e| format!("{}: {e}", file.display()))?;
+            let file = file.display();
+            let proof: InclusionProof =
+                serde_json::from_str(&content).map_err(|e| format!("{file}: {e}"))?;
+            let record = saf_audit::verify_inclusion(&proof, key.as_deref())
+                .map_err(|e| format!("{file}: {e}"))?;
+            let signed = match &key {
+                Some(k) => format!("root signed by {}", fingerprint(k)),
+                None => "no signing key to check the root".to_string(),
+            };
+            println!(
+                "{file}: entry {} of {} is in the log ({signed})",
+                proof.index, proof.size
+            );
+            println!(
+                "{}",
+                serde_json::to_string(&record).map_err(|e| e.to_string())?
+            );
+            Ok(())
+        }
+        AuditCommand::Prune { log } => {
+            let path = log_or_default(log)?;
+            let pruned = prune(&path, &ElevationStore::new()?)?;
+            println!(
+                "{}: pruned {} entries in {} segment(s)",
+                path.display(),
+                pruned.entries,
+                pruned.files
+            );
+            Ok(())
+        }
+        AuditCommand::Ship { log } => {
+            let path = log_or_default(log)?;
+            let config_path = BrokerConfig::path()?;
+            let config = BrokerConfig::load(&config_path)?
+                .audit
+                .ship
+                .ok_or_else(|| {
+                    format!(
+                        "{}: no [audit.ship] endpoint configured",
+                        config_path.display()
+                    )
+                })?;
+            let shipped = ship_with(&path, &config)?.ship_pending()?;
+            println!(
+                "{}: shipped {} entries to {}",
+                path.display(),
+                shipped,
+                config.endpoint
+            );
+            Ok(())
+        }
+        AuditCommand::Handoff { log } => {
+            let path = log_or_default(log)?;
+            let host = crate::sysinfo::hostname().unwrap_or_else(|| "-".to_string());
+            let pkcs8 = SecretStore::new()?.find(SIGNING_KEY)?;
+            let handoff = saf_audit::handoff(&path, &host, pkcs8.as_deref())?;
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&handoff).map_err(|e| e.to_string())?
+            );
+            Ok(())
+        }
+        AuditCommand::Import { handoff, log, key } => {
+            let path = log_or_default(log)?;
+            let key = match key {
+                Some(k) => Some(parse_key(&k)?),
+                None => public_key()?,
+            };
+            let file = handoff.display();
+            let content = std::fs::read_to_string(&handoff).map_err(|e| format!("{file}: {e}"))?;
+            let handoff: Handoff =
+                serde_json::from_str(&content).map_err(|e| format!("{file}: {e}"))?;
+            open(&path)?
+                .continue_from(&handoff, key.as_deref())
+                .map_err(|e| format!("{file}: {e}"))?;
+            let signed = match &key {
+                Some(k) => format!("handoff signed by {}", fingerprint(k)),
+                None => "no key to check the handoff's signature".to_string(),
+            };
+            println!(
+                "{}: continues the chain of {}:{} after {} entries ({signed})",
+                path.display(),
+                handoff.host,
+                handoff.log,
+                handoff.entries
+            );
+            Ok(())
+        }
+    }
+}
+
+/// `broker audit verify`.
+fn verify(
+    path: PathBuf,
+    key: Option<String>,
+    anchors: Option<PathBuf>,
+    json: bool,
+) -> Result<(), String> {
+    let key = match key {
+        Some(k) => Some(parse_key(&k)?),
+        None => public_key()?,
+    };
+    let found = saf_audit::verify(&path, key.as_deref())?;
+    let anchors = match anchors {
+        Some(a) => Some(a),
+        None => BrokerConfig::load(&BrokerConfig::path()?)?
+            .audit
+            .checkpoint
+            .and_then(|c| c.anchor_file)
+            .filter(|f| f.exists()),
+    };
+    let checkpoints = match &anchors {
+        Some(anchors) => Some(saf_audit::verify_anchors(&path, anchors, key.as_deref())?),
+        None => None,
+    };
+    let stamped = if crate::tsa::tokens_dir(&path).is_dir() {
+        Some(crate::tsa::verify_timestamps(&path)?)
+    } else {
+        None
+    };
+    if json {
+        let out = serde_json::to_string_pretty(&json!({
+            "log": path,
+            "files": found.files,
+            "entries": found.entries,
+            "signed": found.signed,
+            "pruned": found.pruned,
+            "head": found.head,
+            "key": key.as_deref().map(fingerprint),
+            "anchors": anchors,
+            "checkpoints": checkpoints,
+            "timestamps": stamped.as_ref().map(|stamped| stamped
+                .iter()
+                .map(|t| json!({"hash": t.hash, "time": t.time}))
+                .collect::<Vec<_>>()),
+        }))
+        .map_err(|e| e.to_string())?;
+        println!("{out}");
+        return Ok(());
+    }
+    match &key {
+        Some(k) => println!(
+            "{}: {} entries in {} file(s), chain intact, {} signed by {}",
+            path.display(),
+            found.entries,
+            found.files,
+            found.signed,
+            fingerprint(k)
+        ),
+        None => println!(
+            "{}: {} entries in {} file(s), chain intact (no signing key to check signatures)",
+            path.display(),
+            found.entries,
+            found.files
+        ),
+    }
+    if found.pruned > 0 {
+        println!(
+            "{}: {} older entries pruned, as summarized in the oldest segment",
+            path.display(),
+            found.pruned
+        );
+    }
+    if let (Some(anchors), Some(found)) = (&anchors, checkpoints) {
+        println!(
+            "{}: {} checkpoint(s) found in the log",
+            anchors.display(),
+            found
+        );
+    }
+    if let Some(stamped) = stamped {
+        match stamped.last() {
+            Some(last) => println!(
+                "{}: {} checkpoint(s) timestamped, the latest at {} ({})",
+                path.display(),
+                stamped.len(),
+                last.time,
+                &last.hash[..16]
+            ),
+            None => println!("{}: no timestamped checkpoints", path.display()),
+        }
+    }
+    Ok(())
+}
+
+/// `broker audit prune`: prune the log at `path` as `[audit.retention]`
+/// says. Entries pruned are gone for good, so it needs an elevated session
+/// in `elevation`; a refusal is audited to the log's workspace.
+pub fn prune(path: &Path, elevation: &ElevationStore) -> Result<Pruned, String> {
+    let workspace = path
+        .parent()
+        .and_then(Path::parent)
+        .unwrap_or(Path::new("."));
+    crate::elevation::require_in(elevation, workspace, "pruning the audit log")?;
+    let config_path = BrokerConfig::path()?;
+    let retention = BrokerConfig::load(&config_path)?
+        .audit
+        .retention
+        .ok_or_else(|| format!("{}: no [audit.retention] configured", config_path.display()))?;
+    let mut log = AuditLog::new(path)?.with_retention(retention.retention());
+    if let Some(pkcs8) = SecretStore::new()?.find(SIGNING_KEY)? {
+        log = log.with_signing_key(&pkcs8)?;
+    }
+    log.prune()
+}
+
+/// `broker audit export`: every matching entry, oldest first, one per line.
+/// Stops quietly once whatever reads the output closes it.
+fn export(filter: &Filter, format: Format) -> Result<(), String> {
+    let page = AuditReader::open(&filter.log()?)?.query(&filter.query())?;
+    let host = crate::sysinfo::hostname().unwrap_or_else(|| "-".to_string());
+    let mut out = std::io::stdout().lock();
+    for record in &page.records {
+        let line = match format {
+            Format::Json => serde_json::to_string(record).map_err(|e| e.to_string())?,
+            Format::Syslog => saf_audit::export::syslog(record, SYSLOG_FACILITY, &host)?,
+            Format::Cef => saf_audit::export::cef(record, &host)?,
+        };
+        match writeln!(out, "{line}") {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => return Ok(()),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Ok(())
+}
+
+/// `broker audit query`: matching entries oldest first, a page at a time.
+fn query(args: &QueryArgs) -> Result<(), String> {
+    let query = AuditQuery {
+        offset: args.offset,
+        limit: Some(args.limit),
+        newest_first: args.newest_first,
+        ..args.filter.query()
+    };
+    let page = AuditReader::open(&args.filter.log()?)?.query(&query)?;
+    if args.json {
+        let out = serde_json::to_string_pretty(&page).map_err(|e| e.to_string())?;
+        println!("{out}");
+        return Ok(());
+    }
+    for record in &page.records {
+        let detail = match &record.event {
+            AuditEvent::Legacy { message } | AuditEvent::Unrecognized { message } => {
+                message.clone()
+            }
+            event => serde_json::to_string(event).map_err(|e| e.to_string())?,
+        };
+        let component = if record.component.is_empty() {
+            "-"
+        } else {
+            &record.component
+        };
+        println!(
+            "{:>13} {:<32} {:<10} {:<16} {}",
+            record.ts_ms,
+            record.code,
+            serde_json::to_value(record.outcome)
+                .ok()
+                .and_then(|v| v.as_str().map(str::to_string))
+                .unwrap_or_default(),
+            component,
+            detail
+        );
+    }
+    match page.next {
+        Some(next) => println!(
+            "{} of {} matching entries; next page: --offset {next}",
+            page.records.len(),
+            page.total
+        ),
+        None => println!("{} matching entries", page.total),
+    }
+    Ok(())
+}
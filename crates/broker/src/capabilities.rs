@@ -0,0 +1,366 @@
+//! Capability manifests: what a component says it needs.
+//!
+//! A component may ship `<name>.caps.toml` beside it, listing the `saf:app`
+//! interfaces it uses and the scopes it needs within them:
+//!
+//! ```toml
+//! [requires]
+//! fs = ["read:docs", "write:exports"]
+//! net = ["api.example.org"]
+//! ws = ["stream.example.org"]
+//! sysinfo = ["hostname"]
+//! config = ["api_url"]
+//! log = []
+//! ```
+//!
+//! At load time every scope is checked against the policy in force. Any the
+//! policy does not grant (or would ask about) stops the run with all of them
+//! listed; otherwise the policy is narrowed to the requested scopes and only
+//! the requested interfaces are linked. A component importing an interface
+//! its manifest leaves out is refused before it is instantiated.
+//!
+//! The same `[requires]` table may instead be part of the component's
+//! `<name>.saf.toml` (see [`crate::component_manifest`]).
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use saf_core::{Code, Denial};
+use saf_policy::glob::has_prefix;
+use saf_policy::{FsAccess, Policy};
+use serde::{Deserialize, Serialize};
+
+use crate::components::{SAF_INTERFACES, WIT_PACKAGE};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CapabilityManifest {
+    /// Scopes keyed by interface name.
+    pub requires: BTreeMap<String, Vec<String>>,
+}
+
+impl CapabilityManifest {
+    /// Where the manifest for `component` lives.
+    pub fn path_for(component: &Path) -> PathBuf {
+        component.with_extension("caps.toml")
+    }
+
+    /// The manifest beside `component`, if it has one.
+    pub fn load_for(component: &Path) -> Result<Option<Self>, String> {
+        let path = Self::path_for(component);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        Self::parse(&content)
+            .map(Some)
+            .map_err(|e| format!("invalid capability manifest {}: {}", path.display(), e))
+    }
+
+    /// A manifest requesting `requires`, if each request is valid.
+    pub fn new(requires: BTreeMap<String, Vec<String>>) -> Result<Self, String> {
+        let manifest = Self { requires };
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let manifest: Self = toml::from_str(content).map_err(|e| e.to_string())?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let mut problems = Vec::new();
+        for (interface, scopes) in &self.requires {
+            if !SAF_INTERFACES.contains(&interface.as_str()) {
+                problems.push(format!("requires.{interface}: unknown interface"));
+                continue;
+            }
+            for scope in scopes {
+                if let Err(e) = Scope::parse(interface, scope) {
+                    problems.push(format!("requires.{interface}: {scope:?}: {e}"));
+                }
+            }
+            // No scopes would leave `allowed_paths` or `allowed_domains`
+            // empty, which means something else entirely to the policy.
+            if scopes.is_empty() && matches!(interface.as_str(), "fs" | "net" | "ws") {
+                problems.push(format!(
+                    "requires.{interface}: at least one scope is required"
+                ));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join("; "))
+        }
+    }
+
+    /// Interfaces to link.
+    pub fn interfaces(&self) -> BTreeSet<String> {
+        self.requires.keys().cloned().collect()
+    }
+
+    /// Everything standing between the manifest and a run under `policy`:
+    /// `saf:app` imports the manifest leaves out, which could not be
+    /// linked, then requested scopes the policy does not grant outright.
+    /// Scopes the user would be asked about count as ungranted; a component
+    /// that needs them should have them in the policy.
+    pub fn unsatisfied(&self, imports: &[String], policy: &Policy) -> Vec<Unsatisfied> {
+        let unrequested = imports.iter().filter_map(|i| {
+            let path = i.split_once('@').map_or(i.as_str(), |(p, _)| p);
+            let interface = path.strip_prefix(WIT_PACKAGE)?.strip_prefix('/')?;
+            (!self.requires.contains_key(interface)).then(|| Unsatisfied {
+                what: path.to_string(),
+                why: "imported but not requested".to_string(),
+            })
+        });
+        let ungranted = self.scopes().filter_map(|scope| {
+            scope.check(policy).err().map(|d| Unsatisfied {
+                what: scope.to_string(),
+                why: format!("{} rule={}", d.code, d.rule_id),
+            })
+        });
+        unrequested.chain(ungranted).collect()
+    }
+
+    /// Each requested scope, with its interface and `policy`'s decision on
+    /// it.
+    pub fn decisions<'a>(&'a self, policy: &Policy) -> Vec<(&'a str, &'a str, Result<(), Denial>)> {
+        self.requires
+            .iter()
+            .flat_map(|(interface, scopes)| {
+                scopes.iter().filter_map(move |s| {
+                    let scope = Scope::parse(interface, s).ok()?;
+                    Some((interface.as_str(), s.as_str(), scope.check(policy)))
+                })
+            })
+            .collect()
+    }
+
+    fn scopes(&self) -> impl Iterator<Item = Scope<'_>> {
+        self.requires.iter().flat_map(|(interface, scopes)| {
+            scopes
+                .iter()
+                .filter_map(move |s| Scope::parse(interface, s).ok())
+        })
+    }
+
+    /// `policy` narrowed to the requested scopes. A read scope containing a
+    /// write scope, or inside one, stays writable.
+    pub fn narrow(&self, policy: &Policy) -> Policy {
+        let mut out = policy.clone();
+        let granted: Vec<Scope<'_>> = self.scopes().filter(|s| s.check(policy).is_ok()).collect();
+        out.allowed_domains = granted
+            .iter()
+            .filter_map(|s| match s {
+                Scope::Net(d) | Scope::Ws(d) => Some(d.to_string()),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        out.ask_domains.clear();
+        let paths = |access: FsAccess| -> Vec<&str> {
+            granted
+                .iter()
+                .filter_map(|s| match s {
+                    Scope::Fs(a, p) if *a == access => Some(*p),
+                    _ => None,
+                })
+                .collect()
+        };
+        let (reads, writes) = (paths(FsAccess::Read), paths(FsAccess::Write));
+        out.allowed_paths = reads
+            .iter()
+            .chain(&writes)
+            .map(|p| p.to_string())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        out.ask_paths.clear();
+        let overlaps = |a: &str, b: &str| has_prefix(a, b) || has_prefix(b, a);
+        for read in reads {
+            if !writes.iter().any(|w| overlaps(read, w)) {
+                out.read_only.push(read.to_string());
+                out.read_only.push(format!("{read}/**"));
+            }
+        }
+        let fields: Vec<&str> = granted
+            .iter()
+            .filter_map(|s| match s {
+                Scope::Sysinfo(f) => Some(*f),
+                _ => None,
+            })
+            .collect();
+        out.sysinfo.hostname &= fields.contains(&"hostname");
+        out.sysinfo.username &= fields.contains(&"username");
+        out.config_keys = granted
+            .iter()
+            .filter_map(|s| match s {
+                Scope::Config(k) => Some(k.to_string()),
+                _ => None,
+            })
+            .collect();
+        out
+    }
+}
+
+/// An import or scope the run cannot have, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unsatisfied {
+    pub what: String,
+    pub why: String,
+}
+
+impl std::fmt::Display for Unsatisfied {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.what, self.why)
+    }
+}
+
+/// One requested scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scope<'a> {
+    Fs(FsAccess, &'a str),
+    Net(&'a str),
+    Ws(&'a str),
+    Sysinfo(&'a str),
+    Config(&'a str),
+}
+
+impl<'a> Scope<'a> {
+    fn parse(interface: &str, scope: &'a str) -> Result<Self, String> {
+        let host = |s: &'a str| {
+            let valid = !s.is_empty()
+                && s.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.'))
+                && !s.starts_with('.')
+                && !s.ends_with('.');
+            if valid {
+                Ok(s)
+            } else {
+                Err("expected a host name like \"api.example.org\"".to_string())
+            }
+        };
+        match interface {
+            "fs" => {
+                let (access, path) = match scope.split_once(':') {
+                    Some(("read", p)) => (FsAccess::Read, p),
+                    Some(("write", p)) => (FsAccess::Write, p),
+                    _ => return Err("expected \"read:<path>\" or \"write:<path>\"".to_string()),
+                };
+                match crate::sanitize_rel_path(path) {
+                    Some(rel) if rel == path => Ok(Self::Fs(access, path)),
+                    _ => Err("expected a workspace-relative path like \"docs\"".to_string()),
+                }
+            }
+            "net" => host(scope).map(Self::Net),
+            "ws" => host(scope).map(Self::Ws),
+            "sysinfo" => match scope {
+                "hostname" | "username" => Ok(Self::Sysinfo(scope)),
+                _ => Err("expected \"hostname\" or \"username\"".to_string()),
+            },
+            "config" if !scope.is_empty() && !scope.contains(['/', '*']) => Ok(Self::Config(scope)),
+            "config" => Err("expected a key name like \"api_url\"".to_string()),
+            _ => Err(format!("{interface} takes no scopes")),
+        }
+    }
+
+    fn check(&self, policy: &Policy) -> Result<(), Denial> {
+        let offline = || Denial::new(Code::NetOffline, "offline");
+        match *self {
+            Self::Fs(access, path) => {
+                policy.check_path(path)?;
+                policy.check_fs_access(path, access)
+            }
+            Self::Net(_) | Self::Ws(_) if policy.offline => Err(offline()),
+            Self::Net(host) => policy.check_url(&format!("https://{host}/")),
+            Self::Ws(host) => policy.check_ws_url(&format!("wss://{host}/")),
+            Self::Sysinfo(field) => {
+                let granted = match field {
+                    "hostname" => policy.sysinfo.hostname,
+                    _ => policy.sysinfo.username,
+                };
+                if granted {
+                    Ok(())
+                } else {
+                    Err(Denial::new(
+                        Code::PolicySysinfoNotGranted,
+                        format!("sysinfo.{field}"),
+                    ))
+                }
+            }
+            Self::Config(key) => policy.check_config_key(key),
+        }
+    }
+}
+
+impl std::fmt::Display for Scope<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fs(FsAccess::Read, p) => write!(f, "fs:read:{p}"),
+            Self::Fs(FsAccess::Write, p) => write!(f, "fs:write:{p}"),
+            Self::Net(h) => write!(f, "net:{h}"),
+            Self::Ws(h) => write!(f, "ws:{h}"),
+            Self::Sysinfo(s) => write!(f, "sysinfo:{s}"),
+            Self::Config(k) => write!(f, "config:{k}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grants_the_intersection_of_manifest_and_policy() {
+        let manifest = CapabilityManifest::parse(
+            r#"
+            [requires]
+            fs = ["read:docs", "write:exports"]
+            net = ["example.org"]
+            log = []
+            "#,
+        )
+        .expect("manifest");
+        let policy = Policy::new()
+            .with_allowed_domains(vec!["example.org".to_string(), "httpbin.org".to_string()]);
+        let imports = ["saf:app/fs@0.1.0".to_string(), "saf:app/log".to_string()];
+        assert_eq!(manifest.unsatisfied(&imports, &policy), []);
+
+        let narrowed = manifest.narrow(&policy);
+        assert!(narrowed.is_url_allowed("https://example.org/"));
+        assert!(!narrowed.is_url_allowed("https://httpbin.org/"));
+        assert!(narrowed.check_fs_access("docs/a", FsAccess::Read).is_ok());
+        assert!(narrowed.check_fs_access("docs/a", FsAccess::Write).is_err());
+        assert!(narrowed
+            .check_fs_access("exports/a", FsAccess::Write)
+            .is_ok());
+        assert!(narrowed.check_path("secrets").is_err());
+
+        let offline = policy.clone().with_offline(true);
+        let ws = ["saf:app/ws@0.1.0".to_string()];
+        let missing: Vec<String> = manifest
+            .unsatisfied(&ws, &offline)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            missing,
+            [
+                "saf:app/ws (imported but not requested)",
+                "net:example.org (net.offline rule=offline)"
+            ]
+        );
+
+        let bad =
+            CapabilityManifest::parse("[requires]\nfs = [\"../etc\"]\ntime = [\"x\"]").unwrap_err();
+        assert!(
+            bad.contains("requires.fs") && bad.contains("requires.time"),
+            "{bad}"
+        );
+    }
+}
@@ -0,0 +1,358 @@
+//! The broker's command line.
+//!
+//! Without a subcommand the broker opens a workspace, picked interactively
+//! or restored with `--workspace-id`, and runs in it with the [`RunArgs`]
+//! given; `broker run` does the same in the current directory without
+//! asking. Every other subcommand is handled by its own module, which
+//! defines its arguments next to the code they drive. Commands that report
+//! something take `--json` to print it as JSON instead.
+
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::hosts::HostRegistry;
+use crate::mounts::Mount;
+use crate::pipeline::PipelineStage;
+use crate::schedule::Scheduled;
+use crate::{
+    audit, components, demo, elevation, net_stats, pipeline, repl, run_log, selftest, serve,
+    status, workspace_picker,
+};
+use crate::{policy_check, policy_explain, policy_sig, policy_sim};
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "broker",
+    version,
+    about = "Secure App Framework broker",
+    args_conflicts_with_subcommands = true,
+    after_help = "Without a subcommand, launches the interactive workspace picker."
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run a component, or explore the current directory, without picking a
+    /// workspace.
+    Run(Box<RunArgs>),
+    /// Stay up and take commands from the UI and other local tools over a
+    /// control socket.
+    Serve(serve::ServeArgs),
+    /// Try host calls by hand, under the policy and audit log a component
+    /// would run with.
+    Repl(repl::ReplArgs),
+    /// Manage the workspaces the broker remembers.
+    #[command(subcommand)]
+    Workspace(workspace_picker::WorkspaceCommand),
+    /// Verify, query and export the workspace audit log.
+    #[command(subcommand)]
+    Audit(audit::AuditCommand),
+    /// Check, explain, simulate, sign and compare policies.
+    #[command(subcommand)]
+    Policy(PolicyCommand),
+    /// Run a component for the broker that started this process; see
+    /// `--isolate`.
+    #[command(name = "isolated-run", hide = true)]
+    IsolatedRun,
+    /// Install, list, check and run components, or keep them running under
+    /// `broker serve`.
+    #[command(subcommand)]
+    Component(components::ComponentCommand),
+    /// Run components in turn, each taking the output of the one before.
+    #[command(subcommand)]
+    Pipeline(pipeline::PipelineCommand),
+    /// Create a throwaway workspace with sample files and components.
+    Demo(demo::DemoArgs),
+    /// Follow the logs of component runs.
+    #[command(subcommand)]
+    Runs(run_log::RunsCommand),
+    /// Show per-component operations and network traffic.
+    Stats(net_stats::StatsArgs),
+    /// Count audit entries by category and severity.
+    Status(status::StatusArgs),
+    /// Check that this build blocks path traversal, oversize writes,
+    /// disallowed URLs, CPU spins and memory bombs, and write a signed
+    /// report.
+    Selftest(selftest::SelftestArgs),
+    /// Re-authenticate and open a short maintenance session.
+    Elevate(elevation::ElevateArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum PolicyCommand {
+    /// Report the errors and warnings in a policy file.
+    Check(policy_check::CheckArgs),
+    /// Walk through the rules deciding one operation.
+    Explain(policy_explain::ExplainArgs),
+    /// Report the decision for each operation listed, one per line.
+    Simulate(policy_sim::SimulateArgs),
+    /// Show what changed between two policies, and whether it widens them.
+    Diff(policy_explain::DiffArgs),
+    /// Generate a key for signing policies.
+    Keygen(policy_sig::KeygenArgs),
+    /// Sign a policy file, writing <POLICY>.sig.
+    Sign(policy_sig::SignArgs),
+    /// Trust a public key for signed policies.
+    Trust(policy_sig::TrustArgs),
+}
+
+/// How to run: the workspace, the component and the policy it runs under.
+#[derive(Debug, Clone, Default, Args)]
+pub struct RunArgs {
+    /// Restore a previously saved workspace.
+    #[arg(long, value_name = "ID")]
+    pub workspace_id: Option<String>,
+    /// Use this directory as the workspace, without the folder chooser or
+    /// saving it; for machines with no desktop to show one.
+    #[arg(long, value_name = "PATH", conflicts_with = "workspace_id")]
+    pub workspace: Option<PathBuf>,
+    /// Mount a directory into the workspace as NAME, read-only with `:ro`;
+    /// may be repeated. The policy sees its files under NAME/.
+    #[arg(long = "mount", value_name = "NAME:PATH[:ro|:rw]", value_parser = Mount::parse)]
+    pub mounts: Vec<Mount>,
+    /// Execute a WASM component. The [requires] of its <name>.saf.toml, or
+    /// its <name>.caps.toml, limits the run to the interfaces and scopes it
+    /// requests.
+    #[arg(long, value_name = "PATH")]
+    pub run_component: Option<PathBuf>,
+    /// Execute the run pinned by a run.toml manifest, under the policy it
+    /// carries.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["run_component", "policy"])]
+    pub manifest: Option<PathBuf>,
+    /// Load the policy from a TOML or JSON file (default: .saf/policy.toml).
+    #[arg(long, value_name = "PATH")]
+    pub policy: Option<PathBuf>,
+    /// Disable all network access for this run.
+    #[arg(long)]
+    pub offline: bool,
+    /// Log policy denials as would-deny and let operations proceed.
+    #[arg(long)]
+    pub policy_dry_run: bool,
+    /// Refuse policy files without a trusted signature (<file>.sig).
+    #[arg(long)]
+    pub require_signed_policy: bool,
+    /// End the run at the component's first denial, as `strict = true` in
+    /// the policy does.
+    #[arg(long)]
+    pub strict: bool,
+    /// Profile the guest into .saf/runs/<id>/profile/, and copy the
+    /// profile to PATH if given (Firefox profiler format; the time spent in
+    /// each host call goes beside it, as <PATH stem>.host_calls.folded).
+    #[arg(long, value_name = "PATH", num_args = 0..=1)]
+    pub profile: Option<Option<PathBuf>>,
+    /// Observe usage over the next N runs and propose a narrower grant.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub trial: Option<u32>,
+    /// Apply the narrowing proposed by a completed trial (requires
+    /// `broker elevate`).
+    #[arg(long)]
+    pub accept_narrowing: bool,
+    /// Run without UI, in the current directory unless --workspace names
+    /// another.
+    #[arg(long)]
+    pub headless: bool,
+    /// Print a component run's report as JSON.
+    #[arg(long)]
+    pub json: bool,
+    /// Serve JSON-RPC requests on stdin and stdout instead of running.
+    #[arg(long, conflicts_with_all = ["run_component", "manifest", "json"])]
+    pub stdio_rpc: bool,
+    /// Run a WASM component, and run it again with a fresh instance each
+    /// time the file changes.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["run_component", "manifest", "stdio_rpc", "trial", "accept_narrowing"]
+    )]
+    pub watch: Option<PathBuf>,
+    /// Copy the files under DIR into the workspace before each --watch run,
+    /// so every build starts from the same input.
+    #[arg(long, value_name = "DIR", requires = "watch")]
+    pub replay: Option<PathBuf>,
+    /// Give the component the broker's stdin, stdout and stderr; otherwise
+    /// its WASI stdin is empty and its output goes to the audit log.
+    #[arg(long, conflicts_with_all = ["json", "stdio_rpc"])]
+    pub inherit_stdio: bool,
+    /// Run the component in a process of its own, so a crash or runaway
+    /// allocation in it cannot take the broker down.
+    #[arg(long, conflicts_with = "inherit_stdio")]
+    pub isolate: bool,
+    /// Record every host call the component makes, with its result, to a
+    /// trace file that --replay-calls can run it against.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["watch", "stdio_rpc"])]
+    pub record_calls: Option<PathBuf>,
+    /// Answer the component's host calls from a trace recorded with
+    /// --record-calls, touching neither the workspace nor the network.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["record_calls", "watch", "stdio_rpc"]
+    )]
+    pub replay_calls: Option<PathBuf>,
+    /// JSON handed to the component's `init` export before `start` (see the
+    /// `app-with-input` world).
+    #[arg(long, value_name = "JSON", value_parser = parse_input, conflicts_with_all = ["manifest", "stdio_rpc"])]
+    pub input: Option<String>,
+    /// A named parameter for the component's `init` export; may be
+    /// repeated.
+    #[arg(long = "arg", value_name = "KEY=VALUE", value_parser = parse_arg, conflicts_with_all = ["manifest", "stdio_rpc"])]
+    pub args: Vec<(String, String)>,
+    /// A value components read through `saf:app/config`, over the one in
+    /// the broker's `[config]`; may be repeated.
+    #[arg(long = "config", value_name = "KEY=VALUE", value_parser = parse_arg)]
+    pub config: Vec<(String, String)>,
+    /// Interrupts a component run once set; for runs `broker serve` manages.
+    #[arg(skip)]
+    pub stop: Option<Arc<AtomicBool>>,
+    /// The schedule that started the run; for runs `broker serve` starts
+    /// on a schedule.
+    #[arg(skip)]
+    pub schedule: Option<Arc<Scheduled>>,
+    /// The pipeline the run is a stage of; for `broker pipeline run`,
+    /// which reports the run itself.
+    #[arg(skip)]
+    pub pipeline: Option<Arc<PipelineStage>>,
+    /// Take commands from stdin instead of running; for `broker repl`.
+    #[arg(skip)]
+    pub repl: bool,
+    /// The host backends `[hosts]` picks from (default: the built-in
+    /// ones); for binaries embedding the broker.
+    #[arg(skip)]
+    pub hosts: Option<Arc<HostRegistry>>,
+}
+
+/// A run's `--input`, which must be JSON.
+pub fn parse_input(input: &str) -> Result<String, String> {
+    serde_json::from_str::<serde_json::Value>(input)
+        .map(|_| input.to_string())
+        .map_err(|e| format!("not JSON: {e}"))
+}
+
+/// A run's `--arg KEY=VALUE`.
+pub fn parse_arg(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("{arg:?}: expected KEY=VALUE")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn commands_parse_and_flags_keep_their_names() {
+        Cli::command().debug_assert();
+        let cli = Cli::try_parse_from(["broker", "--headless", "--offline"]).expect("parse");
+        assert!(cli.command.is_none());
+        assert!(cli.run.headless && cli.run.offline);
+
+        let cli = Cli::try_parse_from(["broker", "run", "--manifest", "run.toml", "--json"])
+            .expect("parse");
+        let Some(Command::Run(run)) = cli.command else {
+            panic!("not run: {cli:?}");
+        };
+        assert_eq!(run.manifest, Some(PathBuf::from("run.toml")));
+        assert!(run.json);
+        assert!(
+            Cli::try_parse_from(["broker", "run", "--manifest", "a", "--policy", "b"]).is_err()
+        );
+        assert!(Cli::try_parse_from(["broker", "--trial", "0"]).is_err());
+        assert!(Cli::try_parse_from(["broker", "--offline", "status"]).is_err());
+        let cli = Cli::try_parse_from(["broker", "--workspace", "notes"]).expect("parse");
+        assert_eq!(cli.run.workspace, Some(PathBuf::from("notes")));
+        assert!(
+            Cli::try_parse_from(["broker", "--workspace", "notes", "--workspace-id", "w"]).is_err()
+        );
+        let cli = Cli::try_parse_from(["broker", "run", "--watch", "app.wasm", "--replay", "in"])
+            .expect("parse");
+        let Some(Command::Run(run)) = cli.command else {
+            panic!("not run: {cli:?}");
+        };
+        assert_eq!(run.watch, Some(PathBuf::from("app.wasm")));
+        assert!(Cli::try_parse_from(["broker", "run", "--replay", "in"]).is_err());
+        assert!(
+            Cli::try_parse_from(["broker", "run", "--watch", "a", "--run-component", "a"]).is_err()
+        );
+        assert!(Cli::try_parse_from(["broker", "run", "--inherit-stdio", "--json"]).is_err());
+        let cli = Cli::try_parse_from([
+            "broker",
+            "run",
+            "--input",
+            r#"{"n": 1}"#,
+            "--arg",
+            "mode=fast",
+            "--arg",
+            "query=a=b",
+        ])
+        .expect("parse");
+        let Some(Command::Run(run)) = cli.command else {
+            panic!("not run: {cli:?}");
+        };
+        assert_eq!(run.input.as_deref(), Some(r#"{"n": 1}"#));
+        assert_eq!(
+            run.args,
+            [
+                ("mode".to_string(), "fast".to_string()),
+                ("query".to_string(), "a=b".to_string())
+            ]
+        );
+        assert!(Cli::try_parse_from(["broker", "run", "--input", "{"]).is_err());
+        assert!(Cli::try_parse_from(["broker", "run", "--arg", "=x"]).is_err());
+        for (args, profile) in [
+            (
+                &["--profile", "out.json"][..],
+                Some(Some(PathBuf::from("out.json"))),
+            ),
+            (&["--profile", "--json"][..], Some(None)),
+            (&["--json"][..], None),
+        ] {
+            let cli = Cli::try_parse_from(["broker", "run"].iter().chain(args)).expect("parse");
+            let Some(Command::Run(run)) = cli.command else {
+                panic!("not run: {cli:?}");
+            };
+            assert_eq!(run.profile, profile, "{args:?}");
+        }
+
+        let cli = Cli::try_parse_from([
+            "broker",
+            "audit",
+            "query",
+            "--outcome",
+            "denied",
+            "--limit",
+            "5",
+            "--json",
+        ])
+        .expect("parse");
+        assert!(matches!(
+            cli.command,
+            Some(Command::Audit(audit::AuditCommand::Query(_)))
+        ));
+        let cli =
+            Cli::try_parse_from(["broker", "component", "run", "app", "--offline"]).expect("parse");
+        let Some(Command::Component(components::ComponentCommand::Run { name, run })) = cli.command
+        else {
+            panic!("not component run: {cli:?}");
+        };
+        assert_eq!(name, "app");
+        assert!(run.offline);
+        let cli = Cli::try_parse_from(["broker", "component", "ps", "--json"]).expect("parse");
+        assert!(matches!(
+            cli.command,
+            Some(Command::Component(components::ComponentCommand::Lifecycle(
+                crate::lifecycle::LifecycleCommand::Ps { json: true, .. }
+            )))
+        ));
+        assert!(Cli::try_parse_from(["broker", "component", "start", "app"]).is_err());
+    }
+}
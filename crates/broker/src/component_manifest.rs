@@ -0,0 +1,297 @@
+//! Component manifests: who a component is and what it expects.
+//!
+//! A component may ship `<name>.saf.toml` beside it:
+//!
+//! ```toml
+//! name = "notes"
+//! version = "1.2.0"
+//! publisher = "Example Ltd"
+//!
+//! [requires]
+//! fs = ["read:docs", "write:exports"]
+//! log = []
+//!
+//! [resources]
+//! memory_bytes = 67108864
+//! fuel = 500000000
+//! timeout_secs = 60
+//! ```
+//!
+//! `[requires]` takes the place of a `<name>.caps.toml` and is checked the
+//! same way (see [`crate::capabilities`]); declaring capabilities in both
+//! is an error. `[resources]` are hints: the policy and broker config
+//! still set the limits, and a run is warned about any hint above them.
+//!
+//! The declared version and publisher are attached to the run's audit
+//! entries. `broker component install` copies the manifest in with the
+//! component and records it, after checking `[requires]` against the
+//! component's imports; installed components can then also be granted a
+//! policy under `<publisher>/<name>`. Manifests of components run straight
+//! from a file are not trusted for that, as anyone can write one.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use saf_policy::Policy;
+use serde::{Deserialize, Serialize};
+
+use crate::capabilities::CapabilityManifest;
+use crate::components::WIT_PACKAGE;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ComponentManifest {
+    pub name: String,
+    /// `MAJOR.MINOR.PATCH`, optionally with a pre-release or build suffix.
+    pub version: String,
+    pub publisher: String,
+    /// Scopes keyed by interface name, as in a capability manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requires: Option<BTreeMap<String, Vec<String>>>,
+    #[serde(default)]
+    pub resources: ResourceHints,
+}
+
+/// What the component expects to use in one run; `None` is unstated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResourceHints {
+    pub memory_bytes: Option<u64>,
+    pub fuel: Option<u64>,
+    pub timeout_secs: Option<u64>,
+}
+
+impl ComponentManifest {
+    /// Where the manifest for `component` lives.
+    pub fn path_for(component: &Path) -> PathBuf {
+        component.with_extension("saf.toml")
+    }
+
+    /// The manifest beside `component`, if it has one.
+    pub fn load_for(component: &Path) -> Result<Option<Self>, String> {
+        let path = Self::path_for(component);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        Self::parse(&content)
+            .map(Some)
+            .map_err(|e| format!("invalid component manifest {}: {}", path.display(), e))
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let manifest: Self = toml::from_str(content).map_err(|e| e.to_string())?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let mut problems = Vec::new();
+        let name_ok = !self.name.is_empty()
+            && self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'));
+        if !name_ok {
+            problems.push("name: expected letters, digits, '-' and '_'".to_string());
+        }
+        if !is_version(&self.version) {
+            problems.push(format!(
+                "version: {:?}: expected MAJOR.MINOR.PATCH",
+                self.version
+            ));
+        }
+        // The publisher is half of a policy key, so it cannot contain the
+        // separator.
+        let publisher = self.publisher.trim();
+        if publisher.is_empty()
+            || publisher != self.publisher
+            || self.publisher.chars().any(|c| c.is_control() || c == '/')
+        {
+            problems.push("publisher: expected a name without '/'".to_string());
+        }
+        if let Err(e) = self.capabilities().transpose() {
+            problems.push(e);
+        }
+        let hints = [
+            ("memory_bytes", self.resources.memory_bytes),
+            ("fuel", self.resources.fuel),
+            ("timeout_secs", self.resources.timeout_secs),
+        ];
+        for (hint, value) in hints {
+            if value == Some(0) {
+                problems.push(format!("resources.{hint}: must be greater than 0"));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join("; "))
+        }
+    }
+
+    /// The capabilities `[requires]` asks for, if it is there.
+    pub fn capabilities(&self) -> Option<Result<CapabilityManifest, String>> {
+        self.requires
+            .as_ref()
+            .map(|requires| CapabilityManifest::new(requires.clone()))
+    }
+
+    /// The key the policy may grant an installed component under.
+    pub fn policy_id(&self) -> String {
+        format!("{}/{}", self.publisher, self.name)
+    }
+
+    /// `saf:app` interfaces imported but not in `[requires]`, and those in
+    /// it that are never imported. Empty without `[requires]`.
+    pub fn mismatched_imports(&self, imports: &[String]) -> Vec<String> {
+        let Some(requires) = &self.requires else {
+            return Vec::new();
+        };
+        let imported: Vec<&str> = imports
+            .iter()
+            .filter_map(|i| {
+                let path = i.split_once('@').map_or(i.as_str(), |(p, _)| p);
+                path.strip_prefix(WIT_PACKAGE)?.strip_prefix('/')
+            })
+            .collect();
+        let unrequested = imported
+            .iter()
+            .filter(|i| !requires.contains_key(**i))
+            .map(|i| format!("{WIT_PACKAGE}/{i} is imported but not in [requires]"));
+        let unused = requires
+            .keys()
+            .filter(|r| !imported.contains(&r.as_str()))
+            .map(|r| format!("requires.{r} is never imported"));
+        unrequested.chain(unused).collect()
+    }
+
+    /// Hints above the limits the run will have.
+    pub fn exceeded_hints(&self, policy: &Policy, timeout: Option<Duration>) -> Vec<String> {
+        let mut exceeded = Vec::new();
+        let limit = policy.memory.max_memory_bytes;
+        if let Some(hint) = self.resources.memory_bytes.filter(|h| *h > limit) {
+            exceeded.push(format!("memory {hint} bytes (limit {limit})"));
+        }
+        if let (Some(hint), Some(limit)) = (self.resources.fuel, policy.max_fuel) {
+            if hint > limit {
+                exceeded.push(format!("fuel {hint} (limit {limit})"));
+            }
+        }
+        if let (Some(hint), Some(limit)) = (self.resources.timeout_secs, timeout) {
+            if hint > limit.as_secs() {
+                exceeded.push(format!("run time {hint}s (timeout {}s)", limit.as_secs()));
+            }
+        }
+        exceeded
+    }
+}
+
+/// The capabilities `component` requests, from its manifest's `[requires]`
+/// or else its capability manifest, with the file they came from.
+pub fn capabilities_for(
+    component: &Path,
+    manifest: Option<&ComponentManifest>,
+) -> Result<Option<(CapabilityManifest, PathBuf)>, String> {
+    let caps_path = CapabilityManifest::path_for(component);
+    match manifest.and_then(ComponentManifest::capabilities) {
+        Some(_) if caps_path.exists() => Err(format!(
+            "{} and the [requires] of {} both request capabilities; keep one",
+            caps_path.display(),
+            ComponentManifest::path_for(component).display()
+        )),
+        Some(caps) => Ok(Some((caps?, ComponentManifest::path_for(component)))),
+        None => Ok(CapabilityManifest::load_for(component)?.map(|caps| (caps, caps_path))),
+    }
+}
+
+fn is_version(version: &str) -> bool {
+    let core = version.split(['-', '+']).next().unwrap_or_default();
+    let suffix = &version[core.len()..];
+    let parts: Vec<&str> = core.split('.').collect();
+    parts.len() == 3
+        && parts
+            .iter()
+            .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))
+        && suffix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.'))
+        && !matches!(suffix, "-" | "+")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifests_are_validated_and_checked_against_imports() {
+        let manifest = ComponentManifest::parse(
+            r#"
+            name = "notes"
+            version = "1.2.0-beta.1"
+            publisher = "Example Ltd"
+
+            [requires]
+            fs = ["read:docs"]
+            net = ["example.org"]
+
+            [resources]
+            memory_bytes = 536870912
+            fuel = 1000
+            "#,
+        )
+        .expect("manifest");
+        assert_eq!(manifest.policy_id(), "Example Ltd/notes");
+        let caps = manifest.capabilities().expect("requires").expect("valid");
+        assert_eq!(caps.interfaces().len(), 2);
+
+        let imports = [
+            "saf:app/fs@0.1.0".to_string(),
+            "saf:app/log".to_string(),
+            "wasi:io/streams@0.2.0".to_string(),
+        ];
+        assert_eq!(
+            manifest.mismatched_imports(&imports),
+            [
+                "saf:app/log is imported but not in [requires]",
+                "requires.net is never imported"
+            ]
+        );
+
+        let policy = Policy::new();
+        assert_eq!(
+            manifest.exceeded_hints(&policy, Some(Duration::from_secs(60))),
+            ["memory 536870912 bytes (limit 268435456)"]
+        );
+
+        let bare = ComponentManifest::parse(
+            "name = \"notes\"\nversion = \"0.1.0\"\npublisher = \"Example Ltd\"",
+        )
+        .expect("manifest");
+        assert!(bare.capabilities().is_none());
+        assert_eq!(bare.mismatched_imports(&imports), Vec::<String>::new());
+
+        let bad = ComponentManifest::parse(
+            r#"
+            name = "a b"
+            version = "1.2"
+            publisher = "x/y"
+            requires = { time = ["now"] }
+            resources = { fuel = 0 }
+            "#,
+        )
+        .unwrap_err();
+        for field in [
+            "name",
+            "version",
+            "publisher",
+            "requires.time",
+            "resources.fuel",
+        ] {
+            assert!(bad.contains(field), "{field}: {bad}");
+        }
+        assert!(ComponentManifest::parse("name = \"a\"\nversion = \"1.0.0\"").is_err());
+    }
+}
@@ -0,0 +1,631 @@
+//! Installed component registry and install-time compatibility checks.
+//!
+//! `broker component install` copies a component into
+//! `<data_dir>/secure-app-framework/components/` after reading its imports
+//! straight from the binary and checking each against what this broker
+//! provides. The resulting matrix is stored in `registry.json` next to the
+//! components; `broker component doctor` recomputes it, e.g. after the
+//! broker was upgraded or rebuilt with different features. A component's
+//! `<name>.saf.toml` is installed and recorded with it, once the interfaces
+//! it requires match the component's imports. Components can also be pulled
+//! from OCI registries (see [`crate::oci`]), and inspected for what they
+//! would be granted before they are run (see [`crate::inspect`]).
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use wasmparser::{Encoding, Parser, Payload};
+
+use crate::cli::RunArgs;
+use crate::component_manifest::ComponentManifest;
+use crate::oci;
+use crate::run_log::now_ms;
+use crate::run_manifest::sha256_file;
+
+/// WIT package of the app world.
+pub const WIT_PACKAGE: &str = "saf:app";
+/// The app world this broker is built against.
+const WORLD_WIT: &str = include_str!("../../wit/world.wit");
+/// Interfaces of the app world this broker links.
+pub const SAF_INTERFACES: &[&str] = &[
+    "fs", "net", "ws", "log", "time", "rand", "sysinfo", "config",
+];
+
+/// How well the broker can satisfy one import.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", content = "reason", rename_all = "snake_case")]
+pub enum Support {
+    Supported,
+    /// Links, but behaves differently from a full deployment.
+    Degraded(String),
+    /// Instantiation will fail.
+    Unsupported(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportCheck {
+    pub import: String,
+    #[serde(flatten)]
+    pub support: Support,
+}
+
+/// A component's imports checked against one broker build.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompatReport {
+    pub broker_version: String,
+    pub features: Vec<String>,
+    /// Whether this build can run components at all.
+    pub runtime: Support,
+    pub imports: Vec<ImportCheck>,
+}
+
+impl CompatReport {
+    pub fn check(imports: &[String]) -> Self {
+        Self {
+            broker_version: env!("CARGO_PKG_VERSION").to_string(),
+            features: broker_features(),
+            runtime: if cfg!(feature = "wasmtime-host") {
+                Support::Supported
+            } else {
+                Support::Unsupported("broker built without the wasmtime-host feature".to_string())
+            },
+            imports: imports
+                .iter()
+                .map(|i| ImportCheck {
+                    import: i.clone(),
+                    support: check_import(i),
+                })
+                .collect(),
+        }
+    }
+
+    /// Imports the component cannot be instantiated with.
+    pub fn unsupported(&self) -> impl Iterator<Item = &ImportCheck> {
+        self.imports
+            .iter()
+            .filter(|c| matches!(c.support, Support::Unsupported(_)))
+    }
+}
+
+fn broker_features() -> Vec<String> {
+    let mut features = Vec::new();
+    if cfg!(feature = "wasmtime-host") {
+        features.push("wasmtime-host".to_string());
+    }
+    features.push("net:http1".to_string());
+    features
+}
+
+/// The version in the world's `package saf:app@<version>;` line; empty for
+/// an unversioned package.
+fn wit_version() -> &'static str {
+    WORLD_WIT
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("package "))
+        .and_then(|p| p.trim_end_matches(';').split_once('@'))
+        .map_or("", |(_, version)| version.trim())
+}
+
+/// Classify an import name such as `saf:app/net` or `wasi:io/streams@0.2.0`.
+fn check_import(name: &str) -> Support {
+    let (path, version) = name.split_once('@').unwrap_or((name, ""));
+    let Some((package, interface)) = path.split_once('/') else {
+        return Support::Unsupported("world-level imports are not provided".to_string());
+    };
+    if package.starts_with("wasi:") {
+        return Support::Unsupported("WASI interfaces are not linked by the broker".to_string());
+    }
+    if package != WIT_PACKAGE {
+        return Support::Unsupported(format!("unknown package {package}"));
+    }
+    // The host defines the interfaces under its own version only.
+    let supported = wit_version();
+    if version != supported {
+        let describe = |v: &str| match v {
+            "" => format!("an unversioned {WIT_PACKAGE}"),
+            v => format!("{WIT_PACKAGE}@{v}"),
+        };
+        return Support::Unsupported(format!(
+            "built against {}, but this broker provides {}",
+            describe(version),
+            describe(supported)
+        ));
+    }
+    match interface {
+        "ws" => Support::Degraded("ws backend is a stub that echoes messages".to_string()),
+        i if SAF_INTERFACES.contains(&i) => Support::Supported,
+        i => Support::Unsupported(format!("{WIT_PACKAGE}/{i} is newer than this broker's WIT")),
+    }
+}
+
+/// Names imported by the outermost component, read without compiling it.
+pub fn component_imports(bytes: &[u8]) -> Result<Vec<String>, String> {
+    let mut imports = Vec::new();
+    // Nested modules and components have their own imports, satisfied
+    // inside the component; only depth 0 reaches the host.
+    let mut depth = 0usize;
+    for payload in Parser::new(0).parse_all(bytes) {
+        match payload.map_err(|e| format!("invalid component: {e}"))? {
+            Payload::Version { encoding, .. } if depth == 0 && encoding != Encoding::Component => {
+                return Err("not a component (core wasm module)".to_string());
+            }
+            Payload::ModuleSection { .. } | Payload::ComponentSection { .. } => depth += 1,
+            Payload::End(_) => depth = depth.saturating_sub(1),
+            Payload::ComponentImportSection(reader) if depth == 0 => {
+                for import in reader {
+                    let import = import.map_err(|e| format!("invalid component: {e}"))?;
+                    imports.push(import.name.0.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(imports)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstalledComponent {
+    pub file: String,
+    pub sha256: String,
+    pub installed_ms: u64,
+    pub compat: CompatReport,
+    /// The manifest installed with it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<ComponentManifest>,
+    /// The OCI artifact it was pulled from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<oci::Origin>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Registry {
+    pub components: BTreeMap<String, InstalledComponent>,
+}
+
+impl Registry {
+    pub fn dir() -> Result<PathBuf, String> {
+        Ok(dirs::data_dir()
+            .ok_or("No data directory available")?
+            .join("secure-app-framework")
+            .join("components"))
+    }
+
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let path = dir.join("registry.json");
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("invalid registry {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), String> {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(dir.join("registry.json"), content).map_err(|e| e.to_string())
+    }
+
+    /// The IDs the policy may grant the component hashing to `sha256`
+    /// under: the hash, the names it is installed as, and the key of each
+    /// manifest it was installed with.
+    pub fn policy_ids(&self, sha256: &str) -> Vec<String> {
+        let installed: Vec<_> = self
+            .components
+            .iter()
+            .filter(|(_, c)| c.sha256.eq_ignore_ascii_case(sha256))
+            .collect();
+        let mut ids = vec![format!("sha256:{sha256}")];
+        ids.extend(installed.iter().map(|(name, _)| name.to_string()));
+        // Only a manifest recorded at install speaks for these bytes.
+        ids.extend(
+            installed
+                .iter()
+                .filter_map(|(_, c)| c.manifest.as_ref())
+                .map(|m| m.policy_id()),
+        );
+        ids
+    }
+
+    /// Check `source` and copy it in as `name`, with its manifest.
+    pub fn install(
+        &mut self,
+        dir: &Path,
+        name: &str,
+        source: &Path,
+        force: bool,
+    ) -> Result<CompatReport, String> {
+        let bytes = std::fs::read(source).map_err(|e| format!("{}: {}", source.display(), e))?;
+        let manifest = ComponentManifest::load_for(source)?;
+        self.install_bytes(dir, name, &bytes, manifest, force)
+    }
+
+    /// Check the component `bytes` and store them as `name`, with
+    /// `manifest`. Fails if any import is unsupported, or the manifest's
+    /// `[requires]` does not match the imports, unless `force` is set.
+    pub fn install_bytes(
+        &mut self,
+        dir: &Path,
+        name: &str,
+        bytes: &[u8],
+        manifest: Option<ComponentManifest>,
+        force: bool,
+    ) -> Result<CompatReport, String> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'));
+        if !valid {
+            return Err(format!("invalid component name {name:?}"));
+        }
+        let imports = component_imports(bytes)?;
+        let compat = CompatReport::check(&imports);
+        let blocking: Vec<String> = compat.unsupported().map(|c| c.import.clone()).collect();
+        if !blocking.is_empty() && !force {
+            return Err(format!(
+                "{name} imports interfaces this broker cannot provide: {}",
+                blocking.join(", ")
+            ));
+        }
+        let mismatched = manifest
+            .as_ref()
+            .map_or_else(Vec::new, |m| m.mismatched_imports(&imports));
+        if !mismatched.is_empty() && !force {
+            return Err(format!(
+                "{name}'s manifest does not match its imports: {}",
+                mismatched.join("; ")
+            ));
+        }
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        let file = format!("{name}.wasm");
+        std::fs::write(dir.join(&file), bytes).map_err(|e| e.to_string())?;
+        let installed_manifest = ComponentManifest::path_for(&dir.join(&file));
+        match &manifest {
+            Some(m) => std::fs::write(
+                &installed_manifest,
+                toml::to_string(m).map_err(|e| e.to_string())?,
+            ),
+            None => std::fs::remove_file(&installed_manifest).or_else(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => Ok(()),
+                _ => Err(e),
+            }),
+        }
+        .map_err(|e| format!("{}: {}", installed_manifest.display(), e))?;
+        let entry = InstalledComponent {
+            sha256: sha256_file(&dir.join(&file))?,
+            file,
+            installed_ms: now_ms(),
+            compat: compat.clone(),
+            manifest,
+            origin: None,
+        };
+        self.components.insert(name.to_string(), entry);
+        Ok(compat)
+    }
+
+    /// Re-check every installed component against this broker. Returns what
+    /// keeps each component that cannot run from running: unsupported
+    /// imports, or a file missing or modified since install.
+    pub fn doctor(&mut self, dir: &Path) -> BTreeMap<String, String> {
+        let mut broken = BTreeMap::new();
+        for (name, entry) in &mut self.components {
+            let path = dir.join(&entry.file);
+            let problem = match sha256_file(&path) {
+                Err(e) => Some(format!("unreadable: {e}")),
+                Ok(sha) if sha != entry.sha256 => Some("modified since install".to_string()),
+                Ok(_) => None,
+            };
+            if let Some(problem) = problem {
+                broken.insert(name.clone(), problem);
+                continue;
+            }
+            match std::fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|b| component_imports(&b))
+            {
+                Ok(imports) => entry.compat = CompatReport::check(&imports),
+                Err(e) => {
+                    broken.insert(name.clone(), e);
+                    continue;
+                }
+            }
+            let blocking: Vec<&str> = entry
+                .compat
+                .unsupported()
+                .map(|c| c.import.as_str())
+                .collect();
+            if !blocking.is_empty() {
+                broken.insert(
+                    name.clone(),
+                    format!("unsupported imports: {}", blocking.join(", ")),
+                );
+            }
+        }
+        broken
+    }
+}
+
+pub fn print_report(name: &str, report: &CompatReport) {
+    println!(
+        "{name} (broker {} [{}])",
+        report.broker_version,
+        report.features.join(", ")
+    );
+    if let Support::Unsupported(reason) = &report.runtime {
+        println!("  warning: {reason}; components cannot run until it is enabled");
+    }
+    for check in &report.imports {
+        match &check.support {
+            Support::Supported => println!("  ok       {}", check.import),
+            Support::Degraded(r) => println!("  degraded {} ({r})", check.import),
+            Support::Unsupported(r) => println!("  FAIL     {} ({r})", check.import),
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ComponentCommand {
+    /// Check a component against this broker and copy it in.
+    Install {
+        /// A component file, or
+        /// oci://<registry>/<repository>[:<tag>][@sha256:<digest>].
+        source: String,
+        /// Name to install it as (default: the file's stem, or the
+        /// repository's last segment).
+        #[arg(long)]
+        name: Option<String>,
+        /// Install even if some imports cannot be provided.
+        #[arg(long)]
+        force: bool,
+        /// Pull only from the local OCI cache.
+        #[arg(long)]
+        offline: bool,
+        /// Require a cosign signature on the OCI artifact by this P-256
+        /// public key (PEM).
+        #[arg(long, value_name = "PATH")]
+        verify_key: Option<PathBuf>,
+        #[arg(long)]
+        json: bool,
+    },
+    /// List installed components and whether they can run.
+    List {
+        #[arg(long)]
+        json: bool,
+    },
+    /// Re-check every installed component against this broker.
+    Doctor {
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show what a component would be granted, without running it.
+    Inspect(crate::inspect::InspectArgs),
+    /// Run an installed component in the current directory.
+    Run {
+        name: String,
+        #[command(flatten)]
+        run: Box<RunArgs>,
+    },
+    #[command(flatten)]
+    Lifecycle(crate::lifecycle::LifecycleCommand),
+}
+
+/// The file of the component installed as `name`.
+pub fn installed_path(name: &str) -> Result<PathBuf, String> {
+    let dir = Registry::dir()?;
+    let registry = Registry::load(&dir)?;
+    let entry = registry
+        .components
+        .get(name)
+        .ok_or_else(|| format!("no component named {name} is installed"))?;
+    Ok(dir.join(&entry.file))
+}
+
+/// Entry point for every `broker component` command but `run`, which
+/// the broker runs like `broker run --run-component`, and the ones that
+/// go to the serving broker (see [`crate::lifecycle`]).
+pub fn main(command: ComponentCommand) -> Result<(), String> {
+    let dir = Registry::dir()?;
+    let mut registry = Registry::load(&dir)?;
+    match command {
+        ComponentCommand::Install {
+            source,
+            name,
+            force,
+            offline,
+            verify_key,
+            json,
+        } => {
+            let (name, compat) = if source.starts_with("oci://") {
+                let reference = oci::Reference::parse(&source)?;
+                let key = verify_key.as_deref().map(oci::load_key).transpose()?;
+                let cache = oci::Cache::new(oci::Cache::dir()?);
+                let mut client = oci::Client::new(&reference.registry);
+                let online = (!offline).then_some(&mut client as &mut dyn oci::Source);
+                let pulled = oci::pull(&reference, &cache, online, key.as_deref())?;
+                let name = name.unwrap_or_else(|| reference.default_name());
+                let compat = registry.install_bytes(&dir, &name, &pulled.bytes, None, force)?;
+                if let Some(entry) = registry.components.get_mut(&name) {
+                    entry.origin = Some(pulled.origin);
+                }
+                (name, compat)
+            } else {
+                if verify_key.is_some() {
+                    return Err("--verify-key applies to oci:// sources".into());
+                }
+                let path = PathBuf::from(&source);
+                let name = name.unwrap_or_else(|| {
+                    path.file_stem()
+                        .map(|s| s.to_string_lossy().into_owned())
+                        .unwrap_or_default()
+                });
+                let compat = registry.install(&dir, &name, &path, force)?;
+                (name, compat)
+            };
+            registry.save(&dir)?;
+            if json {
+                let out = serde_json::to_string_pretty(&compat).map_err(|e| e.to_string())?;
+                println!("{out}");
+                return Ok(());
+            }
+            print_report(&name, &compat);
+            if let Some(origin) = registry
+                .components
+                .get(&name)
+                .and_then(|c| c.origin.as_ref())
+            {
+                let signed = if origin.signed {
+                    ", signature verified"
+                } else {
+                    ""
+                };
+                println!("Pulled {} ({}{signed})", origin.reference, origin.digest);
+            }
+            println!("Installed {name} into {}", dir.display());
+            Ok(())
+        }
+        ComponentCommand::List { json } => {
+            if json {
+                let out = serde_json::to_string_pretty(&registry.components)
+                    .map_err(|e| e.to_string())?;
+                println!("{out}");
+                return Ok(());
+            }
+            if registry.components.is_empty() {
+                println!("No components installed");
+                return Ok(());
+            }
+            println!(
+                "{:<24} {:<12} {:<20} {:<16} {:<12} BROKER",
+                "NAME", "VERSION", "PUBLISHER", "SHA256", "STATUS"
+            );
+            for (name, entry) in &registry.components {
+                let status = if entry.compat.unsupported().next().is_some() {
+                    "unsupported"
+                } else if entry
+                    .compat
+                    .imports
+                    .iter()
+                    .any(|c| matches!(c.support, Support::Degraded(_)))
+                {
+                    "degraded"
+                } else {
+                    "ok"
+                };
+                let (version, publisher) = entry
+                    .manifest
+                    .as_ref()
+                    .map_or(("-", "-"), |m| (m.version.as_str(), m.publisher.as_str()));
+                println!(
+                    "{:<24} {:<12} {:<20} {:<16} {:<12} {}",
+                    name,
+                    version,
+                    publisher,
+                    &entry.sha256[..entry.sha256.len().min(16)],
+                    status,
+                    entry.compat.broker_version
+                );
+            }
+            Ok(())
+        }
+        ComponentCommand::Doctor { json } => {
+            let problems = registry.doctor(&dir);
+            registry.save(&dir)?;
+            if json {
+                #[derive(Serialize)]
+                struct Diagnosis<'a> {
+                    #[serde(skip_serializing_if = "Option::is_none")]
+                    problem: Option<&'a str>,
+                    compat: &'a CompatReport,
+                }
+                let diagnoses: BTreeMap<&str, Diagnosis<'_>> = registry
+                    .components
+                    .iter()
+                    .map(|(name, entry)| {
+                        let problem = problems.get(name).map(String::as_str);
+                        (
+                            name.as_str(),
+                            Diagnosis {
+                                problem,
+                                compat: &entry.compat,
+                            },
+                        )
+                    })
+                    .collect();
+                let out = serde_json::to_string_pretty(&diagnoses).map_err(|e| e.to_string())?;
+                println!("{out}");
+            } else if registry.components.is_empty() {
+                println!("No components installed");
+            } else {
+                for (name, entry) in &registry.components {
+                    match problems.get(name) {
+                        Some(problem) if entry.compat.unsupported().next().is_none() => {
+                            println!("{name}: {problem}")
+                        }
+                        _ => print_report(name, &entry.compat),
+                    }
+                }
+            }
+            if problems.is_empty() {
+                Ok(())
+            } else {
+                let names: Vec<&str> = problems.keys().map(String::as_str).collect();
+                Err(format!("components that cannot run: {}", names.join(", ")))
+            }
+        }
+        ComponentCommand::Inspect(args) => crate::inspect::main(args, &registry),
+        ComponentCommand::Run { .. } | ComponentCommand::Lifecycle(_) => {
+            Err("broker component run, start, stop, restart and ps are run by the broker".into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Component binary importing each name as a resource type.
+    fn component_with_imports(names: &[&str]) -> Vec<u8> {
+        let mut section = vec![names.len() as u8];
+        for name in names {
+            section.push(0x00);
+            section.push(name.len() as u8);
+            section.extend_from_slice(name.as_bytes());
+            // type import bounded by `sub resource`
+            section.extend_from_slice(&[0x03, 0x01]);
+        }
+        let mut bytes = b"\0asm\x0d\x00\x01\x00".to_vec();
+        bytes.push(10);
+        bytes.push(section.len() as u8);
+        bytes.extend(section);
+        bytes
+    }
+
+    #[test]
+    fn imports_are_checked_against_the_broker() {
+        let bytes = component_with_imports(&["saf:app/fs", "saf:app/ws", "wasi:io/streams@0.2.0"]);
+        let imports = component_imports(&bytes).expect("parse");
+        let report = CompatReport::check(&imports);
+        let support: Vec<_> = report.imports.iter().map(|c| &c.support).collect();
+        assert_eq!(support[0], &Support::Supported);
+        assert!(matches!(support[1], Support::Degraded(_)));
+        assert!(matches!(support[2], Support::Unsupported(_)));
+        assert!(matches!(
+            check_import("saf:app/gpu"),
+            Support::Unsupported(_)
+        ));
+
+        // Only the version of the world the broker was built with links.
+        assert_eq!(wit_version(), "");
+        assert_eq!(
+            check_import("saf:app/fs@0.2.0"),
+            Support::Unsupported(
+                "built against saf:app@0.2.0, but this broker provides an unversioned saf:app"
+                    .to_string()
+            )
+        );
+
+        assert!(component_imports(b"\0asm\x01\x00\x00\x00").is_err());
+    }
+}
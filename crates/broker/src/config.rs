@@ -0,0 +1,396 @@
+//! Broker-wide settings, in `<config_dir>/secure-app-framework/broker.toml`.
+//! Every section is optional; a missing file is the defaults.
+//!
+//! `[[audit.export]]` tables forward every audit record to a SIEM as well
+//! as writing it to the workspace log (see [`saf_audit::export`]):
+//!
+//! ```toml
+//! [[audit.export]]
+//! format = "syslog"                    # RFC 5424, or "cef"
+//! target = "udp://siem.example.com:514" # or tcp://host:port, or a file path
+//! facility = 13                        # optional, syslog only
+//! hostname = "workstation-7"           # optional
+//! ```
+//!
+//! `[audit.otlp]` also posts every record as an OpenTelemetry span (see
+//! [`crate::otlp`]); every key is optional:
+//!
+//! ```toml
+//! [audit.otlp]
+//! endpoint = "http://localhost:4318"   # spans go to <endpoint>/v1/traces
+//! headers = { authorization = "Bearer ..." }
+//! service_name = "saf-broker"
+//! batch_size = 256
+//! flush_interval_ms = 2000
+//! max_queue = 4096
+//! ```
+//!
+//! `[audit.writer]` tunes the background thread that appends to the
+//! workspace log (see [`saf_audit::writer`]):
+//!
+//! ```toml
+//! [audit.writer]
+//! capacity = 4096          # records queued before host calls wait
+//! batch_size = 256         # records written between flushes
+//! flush_interval_ms = 200  # longest a record waits to be flushed
+//! fsync = "batch"          # or "never" (the default)
+//! ```
+//!
+//! `[audit.checkpoint]` records and anchors the chain hash every `every`
+//! entries (see [`saf_audit::checkpoint`]), and can have each one
+//! timestamped by an RFC 3161 authority (see [`crate::tsa`]):
+//!
+//! ```toml
+//! [audit.checkpoint]
+//! every = 1000
+//! anchor_file = "/mnt/backup/saf-anchors.jsonl"   # optional
+//! anchor_url = "https://anchor.example.com/saf"   # optional, POSTed as JSON
+//! tsa_url = "https://freetsa.org/tsr"             # optional
+//! ```
+//!
+//! `[audit.retention]` prunes rotated segments, leaving a signed summary
+//! in their place (see [`saf_audit::retention`]); either limit is optional:
+//!
+//! ```toml
+//! [audit.retention]
+//! max_age_days = 365
+//! max_bytes = 1073741824
+//! ```
+//!
+//! `[audit.ship]` sends the log, at least once, to a remote collector (see
+//! [`crate::ship`]):
+//!
+//! ```toml
+//! [audit.ship]
+//! endpoint = "https://audit.example.com/ingest"
+//! headers = { authorization = "Bearer ..." }   # optional
+//! batch_size = 500                             # optional
+//! poll_interval_ms = 1000                      # optional
+//! cursor = "/var/lib/saf/audit.ship"           # optional, <log>.ship by default
+//! ```
+//!
+//! `[audit] append_only` has the platform refuse everything but appends
+//! to the active log file (see [`saf_audit::AuditLog::with_append_only`]).
+//! On Linux that takes `CAP_LINUX_IMMUTABLE`, and the broker will not start
+//! if it cannot:
+//!
+//! ```toml
+//! [audit]
+//! append_only = true
+//! ```
+//!
+//! `[metrics]` serves counters derived from the audit records for
+//! Prometheus to scrape (see [`crate::metrics`]):
+//!
+//! ```toml
+//! [metrics]
+//! listen = "127.0.0.1:9464"
+//! ```
+//!
+//! `[run]` bounds how long a component run may take, five minutes unless
+//! set; a run still going at its timeout is interrupted. `[run.timeouts]`
+//! overrides it by component name, and 0 turns it off. Components
+//! `broker serve` keeps running are not timed out. `isolate` runs every
+//! component in a process of its own, as `--isolate` does (see
+//! [`crate::isolate`]), which `sandbox` has the operating system confine
+//! (see [`crate::sandbox`]).
+//!
+//! ```toml
+//! [run]
+//! timeout_secs = 300
+//! isolate = false
+//! sandbox = "best-effort"   # or "required", or "off"
+//!
+//! [run.timeouts]
+//! indexer = 3600
+//! ```
+//!
+//! `[config]` holds the values components read through `saf:app/config`,
+//! and `broker run --config KEY=VALUE` sets one for a run, over the file's.
+//! A component only sees the keys its policy's `config_keys` grants:
+//!
+//! ```toml
+//! [config]
+//! api_url = "https://api.example.org"
+//! "smtp.host" = "mail.example.org"
+//! ```
+//!
+//! `[hosts]` picks the backends that serve host calls, by name (see
+//! [`crate::hosts`]):
+//!
+//! ```toml
+//! [hosts]
+//! fs = "local"
+//! net = "http"
+//! ws = "none"
+//! log = ["stderr"]
+//! ```
+//!
+//! `[[schedule]]` tables have `broker serve` run installed components on
+//! cron schedules (see [`crate::schedule`]):
+//!
+//! ```toml
+//! [[schedule]]
+//! name = "nightly-index"
+//! component = "indexer"
+//! workspace = "workspace_…"
+//! cron = "30 2 * * *"
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use saf_audit::{ExportConfig, Retention, WriterConfig};
+use saf_policy::Policy;
+use serde::Deserialize;
+
+use crate::hosts::HostsConfig;
+use crate::metrics::MetricsConfig;
+use crate::otlp::OtlpConfig;
+use crate::schedule::ScheduleConfig;
+use crate::ship::ShipConfig;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BrokerConfig {
+    #[serde(default)]
+    pub audit: AuditConfig,
+    pub metrics: Option<MetricsConfig>,
+    #[serde(default)]
+    pub run: RunConfig,
+    #[serde(default, rename = "schedule")]
+    pub schedules: Vec<ScheduleConfig>,
+    /// Values for `saf:app/config`, by key.
+    #[serde(default)]
+    pub config: BTreeMap<String, String>,
+    #[serde(default)]
+    pub hosts: HostsConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RunConfig {
+    /// Longest a run may take, in seconds; 0 for no limit.
+    pub timeout_secs: u64,
+    /// `timeout_secs` for particular components, by name.
+    pub timeouts: BTreeMap<String, u64>,
+    /// Run components in a process of their own.
+    pub isolate: bool,
+    /// How that process is confined.
+    pub sandbox: crate::sandbox::Mode,
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 300,
+            timeouts: BTreeMap::new(),
+            isolate: false,
+            sandbox: crate::sandbox::Mode::default(),
+        }
+    }
+}
+
+impl RunConfig {
+    /// How long a run of `component` may take, if it is limited.
+    pub fn timeout(&self, component: &str) -> Option<Duration> {
+        let secs = self
+            .timeouts
+            .get(component)
+            .copied()
+            .unwrap_or(self.timeout_secs);
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditConfig {
+    #[serde(default)]
+    pub export: Vec<ExportConfig>,
+    pub otlp: Option<OtlpConfig>,
+    #[serde(default)]
+    pub writer: WriterConfig,
+    pub checkpoint: Option<CheckpointConfig>,
+    pub ship: Option<ShipConfig>,
+    pub retention: Option<RetentionConfig>,
+    #[serde(default)]
+    pub append_only: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionConfig {
+    pub max_age_days: Option<u64>,
+    pub max_bytes: Option<u64>,
+}
+
+impl RetentionConfig {
+    pub fn retention(&self) -> Retention {
+        Retention {
+            max_age: self
+                .max_age_days
+                .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+            max_bytes: self.max_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CheckpointConfig {
+    #[serde(default = "default_checkpoint_every")]
+    pub every: u64,
+    pub anchor_file: Option<PathBuf>,
+    pub anchor_url: Option<String>,
+    pub tsa_url: Option<String>,
+}
+
+fn default_checkpoint_every() -> u64 {
+    1_000
+}
+
+/// The config values a run under `policy` may read.
+pub fn granted(values: &BTreeMap<String, String>, policy: &Policy) -> BTreeMap<String, String> {
+    values
+        .iter()
+        .filter(|(key, _)| policy.check_config_key(key).is_ok())
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+impl BrokerConfig {
+    pub fn path() -> Result<PathBuf, String> {
+        Ok(dirs::config_dir()
+            .ok_or("No config directory available")?
+            .join("secure-app-framework")
+            .join("broker.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        toml::from_str(&content).map_err(|e| format!("invalid config {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::Missed;
+    use saf_audit::{ExportFormat, Fsync};
+
+    #[test]
+    fn audit_exports_parse_and_unknown_keys_are_refused() {
+        let config: BrokerConfig = toml::from_str(
+            r#"
+            [audit]
+            append_only = true
+
+            [[audit.export]]
+            format = "syslog"
+            target = "udp://127.0.0.1:514"
+
+            [[audit.export]]
+            format = "cef"
+            target = "/var/log/saf.cef"
+            hostname = "ws7"
+
+            [audit.otlp]
+            endpoint = "http://collector:4318"
+
+            [audit.writer]
+            fsync = "batch"
+
+            [audit.checkpoint]
+            anchor_file = "/mnt/backup/anchors.jsonl"
+
+            [audit.ship]
+            endpoint = "https://audit.example.com/ingest"
+
+            [audit.retention]
+            max_age_days = 2
+
+            [metrics]
+            listen = "127.0.0.1:9464"
+
+            [run.timeouts]
+            indexer = 3600
+            watcher = 0
+
+            [[schedule]]
+            name = "nightly"
+            component = "indexer"
+            workspace = "workspace_1"
+            cron = "30 2 * * *"
+            missed = "run_once"
+            "#,
+        )
+        .expect("config");
+        assert!(config.audit.append_only);
+        let export = &config.audit.export;
+        assert_eq!(export.len(), 2);
+        assert_eq!(
+            (export[0].format, export[0].facility),
+            (ExportFormat::Syslog, 13)
+        );
+        assert_eq!(export[1].hostname.as_deref(), Some("ws7"));
+        let otlp = config.audit.otlp.expect("otlp");
+        assert_eq!(otlp.endpoint, "http://collector:4318");
+        assert_eq!(otlp.batch_size, OtlpConfig::default().batch_size);
+        assert_eq!(config.audit.writer.fsync, Fsync::Batch);
+        let checkpoint = config.audit.checkpoint.expect("checkpoint");
+        assert_eq!(checkpoint.every, 1_000);
+        assert!(checkpoint.anchor_url.is_none());
+        assert!(checkpoint.tsa_url.is_none());
+        let ship = config.audit.ship.expect("ship");
+        assert_eq!((ship.batch_size, ship.cursor), (500, None));
+        let retention = config.audit.retention.expect("retention").retention();
+        assert_eq!(retention.max_age, Some(Duration::from_secs(172_800)));
+        assert_eq!(retention.max_bytes, None);
+        assert_eq!(config.metrics.expect("metrics").listen, "127.0.0.1:9464");
+        assert_eq!(config.run.timeout("app"), Some(Duration::from_secs(300)));
+        assert_eq!(
+            config.run.timeout("indexer"),
+            Some(Duration::from_secs(3600))
+        );
+        assert_eq!(config.run.timeout("watcher"), None);
+        let schedule = &config.schedules[0];
+        assert_eq!(
+            (schedule.missed, schedule.offline),
+            (Missed::RunOnce, false)
+        );
+        assert_eq!(schedule.cron.to_string(), "30 2 * * *");
+        assert!(toml::from_str::<BrokerConfig>(
+            "[[schedule]]\nname = \"a\"\ncomponent = \"b\"\nworkspace = \"c\"\ncron = \"* *\""
+        )
+        .is_err());
+        assert!(toml::from_str::<BrokerConfig>("[audit]\nexprot = []").is_err());
+        assert_eq!(
+            BrokerConfig::load(Path::new("/nonexistent/broker.toml")),
+            Ok(BrokerConfig::default())
+        );
+    }
+
+    #[test]
+    fn components_only_get_the_config_keys_they_are_granted() {
+        let config: BrokerConfig = toml::from_str(
+            "[config]\napi_url = \"https://api.example.org\"\n\"smtp.host\" = \"mail\"\ndb_password = \"x\"\n",
+        )
+        .expect("parse");
+        let policy = Policy {
+            config_keys: vec!["api_url".into(), "smtp.*".into()],
+            ..Policy::new()
+        };
+        let visible = granted(&config.config, &policy);
+        assert_eq!(
+            visible.keys().map(String::as_str).collect::<Vec<_>>(),
+            ["api_url", "smtp.host"]
+        );
+        assert!(granted(&config.config, &Policy::restrictive()).is_empty());
+    }
+}
@@ -0,0 +1,299 @@
+//! `broker demo`: a throwaway workspace for exploring the framework safely.
+//!
+//! The generated workspace holds sample files, copies of any example
+//! components that can be found, and a run manifest per component carrying a
+//! permissive policy. Every access is still audited to `.saf/audit.log`, so
+//! runs, audit, trial mode and the UI can all be tried without touching real
+//! data.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use saf_core::{AuditEvent, AuditRecord, Code};
+use saf_policy::{Policy, PolicyBuilder, PolicyIssue};
+
+use crate::run_manifest::{self, ComponentPin, InputPin, RunManifest};
+
+const SAMPLE_FILES: &[(&str, &str)] = &[
+    (
+        "README.md",
+        "# SAF demo workspace\n\nThis directory was created by `broker demo`. Nothing here is\nimportant; delete it when you are done.\n",
+    ),
+    (
+        "docs/notes.txt",
+        "Components can list, read and append to files in this workspace.\n",
+    ),
+    (
+        "data/sample.json",
+        "{\"items\":[{\"id\":1,\"name\":\"alpha\"},{\"id\":2,\"name\":\"beta\"}]}\n",
+    ),
+    ("data/todo.csv", "id,task,done\n1,run a component,false\n2,read the audit log,false\n"),
+    ("logs/.keep", ""),
+];
+
+/// Inputs pinned by the generated manifests.
+const PINNED_INPUTS: &[&str] = &["data/sample.json"];
+
+/// Where `cargo component build` leaves example components, most
+/// preferred first.
+const BUILD_DIRS: &[&str] = &[
+    "target/wasm32-wasip1/release",
+    "target/wasm32-wasip1/debug",
+    "target/wasm32-wasip2/release",
+    "target/wasm32-wasip2/debug",
+];
+
+pub struct DemoWorkspace {
+    pub root: PathBuf,
+    pub manifests: Vec<PathBuf>,
+}
+
+/// Permissive for the demo: the default domains, the whole workspace and
+/// the default size limits.
+fn demo_policy() -> Result<Policy, String> {
+    PolicyBuilder::new()
+        .with_allowed_domains(["example.org", "httpbin.org"])
+        .build()
+        .map_err(|issues| PolicyIssue::join(&issues))
+}
+
+/// Components to pre-install: explicitly given ones, else every built
+/// example.
+fn discover_components(explicit: &[PathBuf]) -> Vec<PathBuf> {
+    if !explicit.is_empty() {
+        return explicit.to_vec();
+    }
+    built_components(Path::new("."))
+}
+
+/// Every component in the [`BUILD_DIRS`] under `base`, each taken from the
+/// first directory that has a build of it.
+fn built_components(base: &Path) -> Vec<PathBuf> {
+    let mut found: Vec<PathBuf> = Vec::new();
+    for dir in BUILD_DIRS {
+        let Ok(entries) = fs::read_dir(base.join(dir)) else {
+            continue;
+        };
+        let mut built: Vec<PathBuf> = entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "wasm") && p.is_file())
+            .collect();
+        built.sort();
+        for path in built {
+            if !found.iter().any(|f| f.file_name() == path.file_name()) {
+                found.push(path);
+            }
+        }
+    }
+    found
+}
+
+pub fn create(root: &Path, components: &[PathBuf]) -> Result<DemoWorkspace, String> {
+    if root.exists()
+        && fs::read_dir(root)
+            .map_err(|e| format!("failed to read {}: {}", root.display(), e))?
+            .next()
+            .is_some()
+    {
+        return Err(format!("{} exists and is not empty", root.display()));
+    }
+    // Each is installed under its file name, so one would replace another.
+    for (i, src) in components.iter().enumerate() {
+        if components[..i]
+            .iter()
+            .any(|c| c.file_name() == src.file_name())
+        {
+            return Err(format!(
+                "more than one component is named {}",
+                src.file_name().unwrap_or_default().to_string_lossy()
+            ));
+        }
+    }
+
+    for (rel, content) in SAMPLE_FILES {
+        let path = root.join(rel);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("failed to create {}: {}", parent.display(), e))?;
+        }
+        fs::write(&path, content)
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+    }
+
+    let mut audit = crate::audit::open(&root.join(".saf").join("audit.log"))
+        .map_err(|e| format!("failed to initialize audit log: {}", e))?;
+
+    let inputs = PINNED_INPUTS
+        .iter()
+        .map(|rel| {
+            Ok(InputPin {
+                path: rel.to_string(),
+                sha256: run_manifest::sha256_file(&root.join(rel))?,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let components_dir = root.join("components");
+    fs::create_dir_all(&components_dir)
+        .map_err(|e| format!("failed to create {}: {}", components_dir.display(), e))?;
+    let mut manifests = Vec::new();
+    for src in components {
+        let file_name = src
+            .file_name()
+            .ok_or_else(|| format!("invalid component path {}", src.display()))?;
+        let dest = components_dir.join(file_name);
+        fs::copy(src, &dest).map_err(|e| format!("failed to copy {}: {}", src.display(), e))?;
+
+        let manifest = RunManifest {
+            component: ComponentPin {
+                path: Path::new("components").join(file_name),
+                sha256: run_manifest::sha256_file(&dest)?,
+            },
+            args: Vec::new(),
+            input: None,
+            policy: demo_policy()?,
+            inputs: inputs.clone(),
+            rng_seed: Some(42),
+        };
+        let stem = Path::new(file_name)
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let manifest_path = root.join(format!("run-{stem}.toml"));
+        let toml = toml::to_string_pretty(&manifest)
+            .map_err(|e| format!("failed to serialize manifest: {}", e))?;
+        fs::write(&manifest_path, toml)
+            .map_err(|e| format!("failed to write {}: {}", manifest_path.display(), e))?;
+        manifests.push(manifest_path);
+    }
+
+    audit
+        .record(&AuditRecord::new(
+            Code::DemoCreated,
+            AuditEvent::DemoCreated {
+                components: manifests.len() as u64,
+            },
+        ))
+        .map_err(|e| format!("failed to write audit log: {}", e))?;
+
+    Ok(DemoWorkspace {
+        root: root.to_path_buf(),
+        manifests,
+    })
+}
+
+/// Entry point for `broker demo [--dir <PATH>] [--component <PATH>]...`.
+#[derive(Debug, Args)]
+pub struct DemoArgs {
+    /// Where to create the workspace (default: a new directory under the
+    /// system's temporary directory).
+    #[arg(long, value_name = "DIR")]
+    dir: Option<PathBuf>,
+    /// An example component to copy in; may be repeated.
+    #[arg(long = "component", value_name = "PATH")]
+    components: Vec<PathBuf>,
+}
+
+pub fn main(args: DemoArgs) -> Result<(), String> {
+    let DemoArgs { dir, components } = args;
+    let root = dir.unwrap_or_else(|| {
+        std::env::temp_dir().join(format!("saf-demo-{}", uuid::Uuid::new_v4().simple()))
+    });
+
+    let demo = create(&root, &discover_components(&components))?;
+    println!("Demo workspace created: {}", demo.root.display());
+    println!();
+    if demo.manifests.is_empty() {
+        println!("No example component found. Build one with");
+        println!("    cargo component build -p saf-component-demo --release");
+        println!("and re-run with --component <PATH>.");
+    } else {
+        println!("Run an example component:");
+        for m in &demo.manifests {
+            if let Some(name) = m.file_name() {
+                println!(
+                    "    cd {} && broker run --manifest {}",
+                    demo.root.display(),
+                    name.to_string_lossy()
+                );
+            }
+        }
+    }
+    println!("Explore the workspace without a component:");
+    println!("    cd {} && broker --headless", demo.root.display());
+    println!(
+        "Everything is audited to {}",
+        demo.root.join(".saf/audit.log").display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creates_workspace_with_pinned_manifest() {
+        let root = std::env::temp_dir().join(format!("saf-demo-test-{}", uuid::Uuid::new_v4()));
+        let src = std::env::temp_dir().join(format!("saf-demo-comp-{}.wasm", uuid::Uuid::new_v4()));
+        fs::write(&src, b"\0asm").expect("component");
+
+        let demo = create(&root, std::slice::from_ref(&src)).expect("create");
+        assert!(root.join("data/sample.json").is_file());
+        assert_eq!(demo.manifests.len(), 1);
+        let path = &demo.manifests[0];
+        let manifest =
+            RunManifest::parse(path, &RunManifest::read(path).expect("read")).expect("manifest");
+        let component = fs::read(manifest.component_path(path)).expect("component");
+        manifest.verify(path, &component, &root).expect("verify");
+        let err = manifest.verify(path, b"\0asm\x01", &root).unwrap_err();
+        assert!(err.contains("component hash mismatch"), "{err}");
+
+        // Refuses to populate a non-empty directory.
+        assert!(create(&root, &[]).is_err());
+
+        let _ = fs::remove_dir_all(&root);
+        let _ = fs::remove_file(&src);
+    }
+
+    #[test]
+    fn every_built_component_is_installed() {
+        let base = std::env::temp_dir().join(format!("saf-demo-build-{}", uuid::Uuid::new_v4()));
+        for (dir, name) in [
+            ("target/wasm32-wasip1/debug", "first.wasm"),
+            ("target/wasm32-wasip2/release", "first.wasm"),
+            ("target/wasm32-wasip2/release", "second.wasm"),
+            ("target/wasm32-wasip2/release", "notes.txt"),
+        ] {
+            fs::create_dir_all(base.join(dir)).expect("dir");
+            fs::write(base.join(dir).join(name), b"\0asm").expect("component");
+        }
+        let built = built_components(&base);
+        assert_eq!(
+            built,
+            [
+                base.join("target/wasm32-wasip1/debug/first.wasm"),
+                base.join("target/wasm32-wasip2/release/second.wasm"),
+            ]
+        );
+
+        let root = base.join("demo");
+        let demo = create(&root, &built).expect("create");
+        assert_eq!(demo.manifests.len(), 2);
+        assert!(root.join("components/second.wasm").is_file());
+
+        // Two components of the same name cannot both be installed.
+        let again = base.join("again");
+        let same = [
+            base.join("target/wasm32-wasip1/debug/first.wasm"),
+            base.join("target/wasm32-wasip2/release/first.wasm"),
+        ];
+        let err = create(&again, &same).err().expect("refused");
+        assert!(err.contains("first.wasm"), "{err}");
+        assert!(!again.exists());
+
+        let _ = fs::remove_dir_all(&base);
+    }
+}
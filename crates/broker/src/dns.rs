@@ -0,0 +1,174 @@
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+use saf_core::Code;
+use saf_policy::Policy;
+
+/// Hostname resolution used by the NetHost before connecting.
+pub trait Resolve: Send + Sync {
+    fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, String>;
+    /// Short name recorded in audit entries (`via=`).
+    fn name(&self) -> &'static str;
+}
+
+/// The operating system's resolver.
+pub struct SystemResolver;
+
+impl Resolve for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+        (host, port)
+            .to_socket_addrs()
+            .map(Iterator::collect)
+            .map_err(|e| Code::NetFailed.with_message(&format!("resolving {host}: {e}")))
+    }
+    fn name(&self) -> &'static str {
+        "system"
+    }
+}
+
+/// DNS-over-HTTPS using the JSON API (`application/dns-json`) offered by
+/// the common public resolvers. The endpoint must be an `https://` URL
+/// allowed by policy; its own name is resolved by the system resolver.
+pub struct DohResolver {
+    endpoint: String,
+    agent: ureq::Agent,
+}
+
+const DNS_TYPE_A: u64 = 1;
+const DNS_TYPE_AAAA: u64 = 28;
+
+impl DohResolver {
+    pub fn new(endpoint: &str, policy: &Policy) -> Result<Self, String> {
+        // Over plain HTTP anyone on the path could answer with any address.
+        let scheme = url::Url::parse(endpoint)
+            .map(|u| u.scheme().to_string())
+            .map_err(|e| {
+                Code::NetFailed.with_message(&format!("DoH endpoint {endpoint:?}: {e}"))
+            })?;
+        if scheme != "https" {
+            return Err(Code::NetFailed.with_message(&format!(
+                "DoH endpoint {endpoint} must use https, not {scheme}"
+            )));
+        }
+        policy.check_url(endpoint).map_err(|d| {
+            d.code.with_message(&format!(
+                "DoH endpoint {endpoint} not allowed ({})",
+                d.rule_id
+            ))
+        })?;
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(5)))
+            .build()
+            .into();
+        Ok(Self {
+            endpoint: endpoint.to_string(),
+            agent,
+        })
+    }
+
+    fn query(&self, host: &str, rtype: &str) -> Result<Vec<IpAddr>, String> {
+        let body = self
+            .agent
+            .get(&self.endpoint)
+            .header("accept", "application/dns-json")
+            .query("name", host)
+            .query("type", rtype)
+            .call()
+            .and_then(|mut r| r.body_mut().read_to_string())
+            .map_err(|e| Code::NetFailed.with_message(&format!("DoH query for {host}: {e}")))?;
+        parse_answers(&body)
+    }
+}
+
+impl Resolve for DohResolver {
+    fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+        // IP literals need no lookup.
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+        let mut ips = self.query(host, "A")?;
+        ips.extend(self.query(host, "AAAA")?);
+        Ok(ips
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect())
+    }
+    fn name(&self) -> &'static str {
+        "doh"
+    }
+}
+
+/// Addresses from A/AAAA answers; CNAME and other records are skipped since
+/// the resolver has already followed them.
+fn parse_answers(body: &str) -> Result<Vec<IpAddr>, String> {
+    let json: serde_json::Value = serde_json::from_str(body)
+        .map_err(|e| Code::NetFailed.with_message(&format!("invalid DoH response: {e}")))?;
+    let status = json.get("Status").and_then(|s| s.as_u64()).unwrap_or(2);
+    // 3 = NXDOMAIN: no addresses rather than a failure.
+    if status != 0 && status != 3 {
+        return Err(Code::NetFailed.with_message(&format!("DoH server returned status {status}")));
+    }
+    let answers = json
+        .get("Answer")
+        .and_then(|a| a.as_array())
+        .cloned()
+        .unwrap_or_default();
+    Ok(answers
+        .iter()
+        .filter(|a| {
+            matches!(
+                a.get("type").and_then(|t| t.as_u64()),
+                Some(DNS_TYPE_A | DNS_TYPE_AAAA)
+            )
+        })
+        .filter_map(|a| a.get("data")?.as_str()?.parse().ok())
+        .collect())
+}
+
+/// Resolver configured by the policy.
+pub fn from_policy(policy: &Policy) -> Result<Box<dyn Resolve>, String> {
+    match &policy.dns_over_https {
+        Some(endpoint) => Ok(Box::new(DohResolver::new(endpoint, policy)?)),
+        None => Ok(Box::new(SystemResolver)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_json_answers() {
+        let body = r#"{"Status":0,"Answer":[
+            {"name":"example.org.","type":5,"data":"alias.example.org."},
+            {"name":"alias.example.org.","type":1,"data":"93.184.216.34"},
+            {"name":"alias.example.org.","type":28,"data":"2606:2800:220:1::"}
+        ]}"#;
+        let ips = parse_answers(body).expect("parse");
+        assert_eq!(ips.len(), 2);
+        assert!(parse_answers(r#"{"Status":2}"#).is_err());
+        assert!(parse_answers(r#"{"Status":3}"#)
+            .expect("nxdomain")
+            .is_empty());
+    }
+
+    #[test]
+    fn doh_endpoints_must_use_https() {
+        let policy = Policy {
+            allowed_domains: vec!["dns.example.org".to_string()],
+            ..Policy::default()
+        };
+        for endpoint in [
+            "http://dns.example.org/dns-query",
+            "HTTP://dns.example.org/dns-query",
+            "ws://dns.example.org/dns-query",
+            "dns.example.org/dns-query",
+        ] {
+            assert!(
+                DohResolver::new(endpoint, &policy).is_err(),
+                "{endpoint} accepted"
+            );
+        }
+        assert!(DohResolver::new("https://dns.example.org/dns-query", &policy).is_ok());
+    }
+}
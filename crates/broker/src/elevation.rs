@@ -0,0 +1,457 @@
+//! Temporary elevated sessions ("sudo mode") for maintenance operations.
+//!
+//! Operations that change what the broker trusts or forget what it
+//! recorded (accepting a narrowed grant, creating signing keys, signing
+//! policies, trusting a signing key, removing a workspace, pruning an audit
+//! log) require a session opened by `broker elevate`, which re-authenticates
+//! the user with the platform's own prompt. The session lasts a few
+//! minutes and is shared by every broker process of the user; audit events
+//! written while it is active carry the session id in `elevated`.
+//!
+//! The session file carries an HMAC under a key in the broker's
+//! [`SecretStore`], so a session file written by anything that does not
+//! hold the key does not count. The key is only as private as the secret
+//! store, which the user's own processes can read.
+
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use saf_core::{AuditEvent, AuditRecord, Code, Outcome};
+use serde::{Deserialize, Serialize};
+
+use crate::run_log::now_ms;
+use crate::secrets::SecretStore;
+
+pub const DEFAULT_MINUTES: u64 = 5;
+pub const MAX_MINUTES: u64 = 15;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ElevationSession {
+    pub id: String,
+    pub started_ms: u64,
+    pub expires_ms: u64,
+}
+
+impl ElevationSession {
+    pub fn is_active_at(&self, now_ms: u64) -> bool {
+        now_ms < self.expires_ms
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.is_active_at(now_ms())
+    }
+}
+
+/// The secret keying session MACs.
+const MAC_KEY: &str = "elevation.key";
+
+/// A session as written to disk, with its MAC in hex.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Stored {
+    session: ElevationSession,
+    mac: String,
+}
+
+/// Where the current session is recorded:
+/// `<data_dir>/secure-app-framework/elevation.json`.
+pub struct ElevationStore {
+    path: PathBuf,
+    secrets: SecretStore,
+}
+
+impl ElevationStore {
+    pub fn new() -> Result<Self, String> {
+        let path = dirs::data_dir()
+            .ok_or("No data directory available")?
+            .join("secure-app-framework")
+            .join("elevation.json");
+        Ok(Self::at(path, SecretStore::new()?))
+    }
+
+    /// A store at `path`, keyed from `secrets`.
+    pub fn at(path: PathBuf, secrets: SecretStore) -> Self {
+        Self { path, secrets }
+    }
+
+    /// The session in effect, if any. Expired, unreadable and unsigned
+    /// sessions count as no session.
+    pub fn active(&self) -> Option<ElevationSession> {
+        let content = std::fs::read_to_string(&self.path).ok()?;
+        let stored: Stored = serde_json::from_str(&content).ok()?;
+        let key = self.secrets.find(MAC_KEY).ok()??;
+        let mac = hex::decode(&stored.mac).ok()?;
+        hmac::verify(&mac_key(&key), &signed_bytes(&stored.session)?, &mac).ok()?;
+        stored.session.is_active().then_some(stored.session)
+    }
+
+    /// Re-authenticate through `authenticate` and open a session lasting
+    /// `minutes` (capped at [`MAX_MINUTES`]).
+    pub fn elevate(
+        &self,
+        minutes: u64,
+        authenticate: &dyn Fn(&str) -> Result<(), String>,
+    ) -> Result<ElevationSession, String> {
+        let minutes = minutes.clamp(1, MAX_MINUTES);
+        authenticate("Secure App Framework needs to confirm it's you to start maintenance mode")?;
+        let started_ms = now_ms();
+        let session = ElevationSession {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            started_ms,
+            expires_ms: started_ms + minutes * 60_000,
+        };
+        self.save(&session)?;
+        Ok(session)
+    }
+
+    /// End the current session early. Returns the session that was ended.
+    pub fn end(&self) -> Result<Option<ElevationSession>, String> {
+        let session = self.active();
+        if self.path.exists() {
+            std::fs::remove_file(&self.path).map_err(|e| e.to_string())?;
+        }
+        Ok(session)
+    }
+
+    /// The active session, or an `auth.elevation_required` error naming
+    /// `operation`.
+    pub fn require(&self, operation: &str) -> Result<ElevationSession, String> {
+        self.active().ok_or_else(|| {
+            Code::AuthElevationRequired.with_message(&format!(
+                "{operation} requires an elevated session; run `broker elevate` first"
+            ))
+        })
+    }
+
+    fn save(&self, session: &ElevationSession) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let bytes = signed_bytes(session).ok_or("cannot encode the session")?;
+        let stored = Stored {
+            session: session.clone(),
+            mac: hex::encode(hmac::sign(&mac_key(&self.key()?), &bytes)),
+        };
+        let content = serde_json::to_string(&stored).map_err(|e| e.to_string())?;
+        write_private(&self.path, content.as_bytes())
+    }
+
+    /// The MAC key, created on first use.
+    fn key(&self) -> Result<Vec<u8>, String> {
+        if let Some(key) = self.secrets.find(MAC_KEY)? {
+            return Ok(key);
+        }
+        let mut key = vec![0u8; hmac::HMAC_SHA256.digest_algorithm().output_len()];
+        SystemRandom::new()
+            .fill(&mut key)
+            .map_err(|_| "cannot generate the session key".to_string())?;
+        self.secrets.put(MAC_KEY, &key)?;
+        Ok(key)
+    }
+}
+
+fn mac_key(key: &[u8]) -> hmac::Key {
+    hmac::Key::new(hmac::HMAC_SHA256, key)
+}
+
+fn signed_bytes(session: &ElevationSession) -> Option<Vec<u8>> {
+    serde_json::to_vec(session).ok()
+}
+
+/// [`ElevationStore::require`] against the user's store, auditing a
+/// refusal to `workspace`'s log.
+pub fn require(workspace: &Path, operation: &str) -> Result<ElevationSession, String> {
+    require_in(&ElevationStore::new()?, workspace, operation)
+}
+
+/// [`require`] against `store`.
+pub fn require_in(
+    store: &ElevationStore,
+    workspace: &Path,
+    operation: &str,
+) -> Result<ElevationSession, String> {
+    store.require(operation).inspect_err(|_| {
+        audit_to(
+            workspace,
+            AuditRecord::new(
+                Code::AuthElevationRequired,
+                AuditEvent::Elevation {
+                    session: None,
+                    expires_ms: None,
+                    operation: Some(operation.to_string()),
+                },
+            )
+            .with_outcome(Outcome::Denied),
+        );
+    })
+}
+
+/// Write `path` readable by the current user only.
+#[cfg(unix)]
+pub fn write_private(path: &Path, content: &[u8]) -> Result<(), String> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    file.write_all(content).map_err(|e| e.to_string())
+}
+
+/// Write `path` with an ACL granting its owner alone, in place of the
+/// one it would inherit.
+#[cfg(windows)]
+pub fn write_private(path: &Path, content: &[u8]) -> Result<(), String> {
+    use std::io::Write;
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    crate::sandbox::os::restrict_to_owner(path)
+        .map_err(|e| format!("{}: cannot make private: {e}", path.display()))?;
+    file.write_all(content).map_err(|e| e.to_string())
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn write_private(path: &Path, content: &[u8]) -> Result<(), String> {
+    std::fs::write(path, content).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Ask the operating system to re-authenticate the current user.
+///
+/// Each platform's own prompt is used so the broker never sees the
+/// credential: polkit on Linux, an administrator authorization on macOS
+/// and a UAC consent prompt on Windows.
+pub fn platform_authenticate(reason: &str) -> Result<(), String> {
+    use std::process::Command;
+
+    #[cfg(target_os = "linux")]
+    let mut cmd = {
+        let _ = reason;
+        let mut c = Command::new("pkexec");
+        c.arg("/bin/true");
+        c
+    };
+    #[cfg(target_os = "macos")]
+    let mut cmd = {
+        let script = format!(
+            "do shell script \"true\" with prompt \"{}\" with administrator privileges",
+            reason.replace('"', "'")
+        );
+        let mut c = Command::new("osascript");
+        c.args(["-e", &script]);
+        c
+    };
+    #[cfg(windows)]
+    let mut cmd = {
+        let _ = reason;
+        let mut c = Command::new("powershell");
+        c.args([
+            "-NoProfile",
+            "-Command",
+            "try { Start-Process cmd.exe -ArgumentList '/c','exit' -Verb RunAs -Wait -ErrorAction Stop } catch { exit 1 }",
+        ]);
+        c
+    };
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    {
+        let _ = reason;
+        return Err("re-authentication is not supported on this platform".to_string());
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+    {
+        let status = cmd
+            .status()
+            .map_err(|e| format!("cannot start the system authentication prompt: {e}"))?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(Code::AuthElevationRequired.with_message("authentication failed or was cancelled"))
+        }
+    }
+}
+
+/// Audit to `workspace`'s log, if it is a workspace.
+fn audit_to(workspace: &Path, record: AuditRecord) {
+    let saf = workspace.join(".saf");
+    if saf.is_dir() {
+        if let Ok(mut log) = crate::audit::open(&saf.join("audit.log")) {
+            let _ = log.record(&record);
+        }
+    }
+}
+
+/// Entry point for `broker elevate [--minutes <N>] | --status | --end`.
+#[derive(Debug, Args)]
+pub struct ElevateArgs {
+    /// How long the session lasts.
+    #[arg(long, value_name = "N", conflicts_with_all = ["status", "end"])]
+    minutes: Option<u64>,
+    /// Show the active session, if any.
+    #[arg(long, conflicts_with = "end")]
+    status: bool,
+    /// End the active session.
+    #[arg(long)]
+    end: bool,
+    /// Workspace whose audit log records the session (default: the current
+    /// directory).
+    #[arg(long, value_name = "DIR")]
+    workspace: Option<PathBuf>,
+}
+
+pub fn main(args: ElevateArgs) -> Result<(), String> {
+    let store = ElevationStore::new()?;
+    let workspace = args.workspace.clone().unwrap_or_else(|| PathBuf::from("."));
+    match args {
+        ElevateArgs { status: true, .. } => {
+            match store.active() {
+                Some(s) => println!(
+                    "Elevated session {} active for {}s",
+                    s.id,
+                    s.expires_ms.saturating_sub(now_ms()) / 1000
+                ),
+                None => println!("No elevated session"),
+            }
+            Ok(())
+        }
+        ElevateArgs { end: true, .. } => {
+            if let Some(s) = store.end()? {
+                audit_to(
+                    &workspace,
+                    AuditRecord::new(
+                        Code::AuthElevationEnded,
+                        AuditEvent::Elevation {
+                            session: Some(s.id),
+                            expires_ms: None,
+                            operation: None,
+                        },
+                    ),
+                );
+            }
+            println!("Elevated session ended");
+            Ok(())
+        }
+        ElevateArgs { minutes, .. } => {
+            let minutes = minutes.unwrap_or(DEFAULT_MINUTES);
+            let session = store.elevate(minutes, &platform_authenticate)?;
+            audit_to(
+                &workspace,
+                AuditRecord::new(
+                    Code::AuthElevated,
+                    AuditEvent::Elevation {
+                        session: Some(session.id.clone()),
+                        expires_ms: Some(session.expires_ms),
+                        operation: None,
+                    },
+                ),
+            );
+            println!(
+                "Elevated for {} minutes (session {})",
+                (session.expires_ms - session.started_ms) / 60_000,
+                session.id
+            );
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_requires_authentication_and_expires() {
+        let dir = std::env::temp_dir().join(format!("saf-elevation-{}", uuid::Uuid::new_v4()));
+        let store = ElevationStore::at(
+            dir.join("elevation.json"),
+            SecretStore::at(dir.join("secrets")),
+        );
+        assert!(store.require("import").is_err());
+
+        let denied = store.elevate(5, &|_| Err("cancelled".to_string()));
+        assert!(denied.is_err());
+        assert!(store.active().is_none());
+
+        let session = store.elevate(60, &|_| Ok(())).expect("elevate");
+        assert_eq!(
+            session.expires_ms - session.started_ms,
+            MAX_MINUTES * 60_000
+        );
+        assert_eq!(store.require("import").expect("active"), session);
+        assert!(!session.is_active_at(session.expires_ms));
+
+        // A session written without the key is no session.
+        let forged = ElevationSession {
+            expires_ms: session.expires_ms + 60_000,
+            ..session.clone()
+        };
+        let mut stored: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&store.path).unwrap()).unwrap();
+        stored["session"] = serde_json::to_value(&forged).unwrap();
+        std::fs::write(&store.path, stored.to_string()).unwrap();
+        assert!(store.active().is_none());
+        std::fs::write(&store.path, serde_json::to_string(&forged).unwrap()).unwrap();
+        assert!(store.require("import").is_err());
+
+        store.end().expect("end");
+        assert!(store.active().is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn maintenance_is_refused_without_a_session() {
+        use crate::workspace_picker::WorkspaceStore;
+        use saf_policy::trial::{Narrowing, TrialState};
+
+        let dir = std::env::temp_dir().join(format!("saf-elevation-{}", uuid::Uuid::new_v4()));
+        let workspace = dir.join("ws");
+        let log = workspace.join(".saf").join("audit.log");
+        std::fs::create_dir_all(workspace.join(".saf")).unwrap();
+        let secrets = || SecretStore::at(dir.join("secrets"));
+        let elevation = ElevationStore::at(dir.join("elevation.json"), secrets());
+        let workspaces = WorkspaceStore::at(dir.join("workspaces.json"), secrets());
+        workspaces
+            .save_workspace("workspace_a", &workspace, "token")
+            .unwrap();
+        let mut trial = TrialState::new(1);
+        trial.proposed = Some(Narrowing {
+            allowed_domains: vec!["example.com".to_string()],
+            allowed_paths: vec!["notes".to_string()],
+        });
+        trial.save(&crate::trial::trial_path(&workspace)).unwrap();
+
+        let refused = |result: Result<(), String>| {
+            let err = result.unwrap_err();
+            assert!(err.contains("requires an elevated session"), "{err}");
+        };
+        refused(crate::trial::accept(&workspace, "ui", &elevation).map(drop));
+        refused(crate::workspace_picker::remove(&workspaces, &elevation, "workspace_a").map(drop));
+        refused(crate::audit::prune(&log, &elevation).map(drop));
+
+        // Nothing changed, and each refusal is in the workspace's log.
+        let trial = TrialState::load(&crate::trial::trial_path(&workspace))
+            .unwrap()
+            .unwrap();
+        assert!(trial.proposed.is_some() && trial.accepted.is_none());
+        assert_eq!(workspaces.list_workspaces().unwrap().len(), 1);
+        let page = saf_audit::AuditReader::open(&log)
+            .unwrap()
+            .query(&saf_audit::AuditQuery::default())
+            .unwrap();
+        assert_eq!(page.records.len(), 3);
+        assert!(page
+            .records
+            .iter()
+            .all(|r| r.code == Code::AuthElevationRequired.as_str()));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,179 @@
+//! What a component writes to its stdout and stderr.
+//!
+//! Unless the run inherits the broker's stdio, the guest's WASI output is
+//! cut into lines, each audited as `component.output` and attributed to the
+//! run like the rest of its entries, which is also how the UI's console
+//! shows it live. A line longer than [`MAX_LINE_BYTES`] is cut short, its
+//! entry saying by how much, and once a stream has had [`MAX_RUN_BYTES`]
+//! recorded the rest of it is dropped and counted on the stream's last
+//! entry. Output that is not UTF-8 is recorded lossily.
+
+#![cfg_attr(not(feature = "wasmtime-host"), allow(dead_code))]
+
+use saf_core::AuditEvent;
+
+pub const MAX_LINE_BYTES: usize = 4096;
+pub const MAX_RUN_BYTES: u64 = 256 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        }
+    }
+}
+
+/// One run's output, turned into audit events a line at a time.
+#[derive(Debug, Default)]
+pub struct Capture {
+    stdout: Lines,
+    stderr: Lines,
+}
+
+#[derive(Debug, Default)]
+struct Lines {
+    /// The line being written, up to the line limit.
+    pending: Vec<u8>,
+    /// Bytes of it past the limit.
+    cut: u64,
+    recorded: u64,
+    dropped: u64,
+}
+
+impl Capture {
+    /// Events for the lines `bytes` completes on `stream`.
+    pub fn push(&mut self, stream: Stream, bytes: &[u8]) -> Vec<AuditEvent> {
+        let lines = self.lines(stream);
+        let mut events = Vec::new();
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            if lines.recorded >= MAX_RUN_BYTES {
+                lines.dropped += rest.len() as u64;
+                break;
+            }
+            let (part, complete) = match rest.iter().position(|b| *b == b'\n') {
+                Some(end) => {
+                    let part = &rest[..end];
+                    rest = &rest[end + 1..];
+                    (part, true)
+                }
+                None => (std::mem::take(&mut rest), false),
+            };
+            let kept = part.len().min(MAX_LINE_BYTES - lines.pending.len());
+            lines.pending.extend_from_slice(&part[..kept]);
+            lines.cut += (part.len() - kept) as u64;
+            if complete {
+                events.push(lines.take(stream));
+            }
+        }
+        events
+    }
+
+    /// Events for what is still held back: lines never ended, and how much
+    /// was dropped.
+    pub fn finish(&mut self) -> Vec<AuditEvent> {
+        let mut events = Vec::new();
+        for stream in [Stream::Stdout, Stream::Stderr] {
+            let lines = self.lines(stream);
+            if !lines.pending.is_empty() || lines.cut > 0 {
+                events.push(lines.take(stream));
+            }
+            if lines.dropped > 0 {
+                events.push(AuditEvent::ComponentOutput {
+                    stream: stream.as_str().to_string(),
+                    text: String::new(),
+                    truncated: None,
+                    dropped: Some(std::mem::take(&mut lines.dropped)),
+                });
+            }
+        }
+        events
+    }
+
+    fn lines(&mut self, stream: Stream) -> &mut Lines {
+        match stream {
+            Stream::Stdout => &mut self.stdout,
+            Stream::Stderr => &mut self.stderr,
+        }
+    }
+}
+
+impl Lines {
+    fn take(&mut self, stream: Stream) -> AuditEvent {
+        let mut line = std::mem::take(&mut self.pending);
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        self.recorded += line.len() as u64;
+        AuditEvent::ComponentOutput {
+            stream: stream.as_str().to_string(),
+            text: String::from_utf8_lossy(&line).into_owned(),
+            truncated: Some(std::mem::take(&mut self.cut)).filter(|cut| *cut > 0),
+            dropped: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(stream: &str, text: &str, truncated: Option<u64>, dropped: Option<u64>) -> AuditEvent {
+        AuditEvent::ComponentOutput {
+            stream: stream.to_string(),
+            text: text.to_string(),
+            truncated,
+            dropped,
+        }
+    }
+
+    #[test]
+    fn output_is_split_into_lines_within_limits() {
+        let mut capture = Capture::default();
+        assert_eq!(capture.push(Stream::Stdout, b"hel"), []);
+        assert_eq!(
+            capture.push(Stream::Stdout, b"lo\r\nworld\n\npart"),
+            [
+                line("stdout", "hello", None, None),
+                line("stdout", "world", None, None),
+                line("stdout", "", None, None),
+            ]
+        );
+        assert_eq!(
+            capture.push(Stream::Stderr, b"oops\n"),
+            [line("stderr", "oops", None, None)]
+        );
+
+        let long = vec![b'x'; MAX_LINE_BYTES + 10];
+        let events = capture.push(Stream::Stderr, &[long.as_slice(), b"\n"].concat());
+        let AuditEvent::ComponentOutput {
+            text, truncated, ..
+        } = &events[0]
+        else {
+            panic!("not output: {events:?}");
+        };
+        assert_eq!((text.len(), *truncated), (MAX_LINE_BYTES, Some(10)));
+
+        // Past the run's limit, the rest of the stream is only counted.
+        let chunk = [vec![b'y'; MAX_LINE_BYTES - 1], b"\n".to_vec()].concat();
+        let runs = MAX_RUN_BYTES as usize / MAX_LINE_BYTES;
+        for _ in 0..runs {
+            capture.push(Stream::Stderr, &chunk);
+        }
+        assert_eq!(capture.push(Stream::Stderr, b"more\n"), []);
+        assert_eq!(
+            capture.finish(),
+            [
+                line("stdout", "part", None, None),
+                line("stderr", "", None, Some(5)),
+            ]
+        );
+    }
+}
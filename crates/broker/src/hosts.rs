@@ -0,0 +1,427 @@
+//! The host backends a run's host calls are served by, picked by name.
+//!
+//! A [`HostRegistry`] maps names to factories for each kind of host, and
+//! the `[hosts]` section of the broker config picks one of each:
+//!
+//! ```toml
+//! [hosts]
+//! fs = "local"      # the workspace directory and its mounts
+//! net = "http"      # HTTP(S) under the policy's network rules
+//! ws = "none"       # no WebSocket transport: connections are refused
+//! log = ["stderr"]  # also write each audit record to stderr, as JSON
+//! ```
+//!
+//! A backend sits beneath the broker's own checks: the policy, the trial
+//! tracker and the trace see every call whichever backend serves it. The
+//! backend is handed the run's policy and auditor for the rules that are
+//! left to it: `local` decides `allowed_paths`, `ask_paths` and the size
+//! limits itself, and a replacement must do the same. Log backends receive every record the workspace audit log
+//! keeps, in addition to it: the hash-chained log itself cannot be
+//! replaced, since `broker audit verify` depends on it.
+//!
+//! The broker has no WebSocket transport of its own: `none` checks each
+//! connection against the policy and then refuses it, and `echo`, a stub
+//! for testing, answers `wss://example.org/echo` from memory.
+//!
+//! A binary embedding the broker registers its own backends on
+//! [`HostRegistry::builtin`] and passes the registry in [`RunArgs::hosts`].
+//!
+//! [`RunArgs::hosts`]: crate::cli::RunArgs::hosts
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use saf_core::{AuditRecord, FsHost, LogHost, NetHost, WsHost};
+use saf_policy::SharedPolicy;
+use serde::Deserialize;
+
+use crate::mounts::Mounts;
+use crate::net_stats::NetMeter;
+use crate::Auditor;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostsConfig {
+    pub fs: String,
+    pub net: String,
+    pub ws: String,
+    pub log: Vec<String>,
+}
+
+impl Default for HostsConfig {
+    fn default() -> Self {
+        Self {
+            fs: "local".to_string(),
+            net: "http".to_string(),
+            ws: "none".to_string(),
+            log: Vec::new(),
+        }
+    }
+}
+
+/// What a backend is built from, for one run.
+pub struct HostEnv<'a> {
+    pub mounts: &'a Mounts,
+    pub policy: &'a SharedPolicy,
+    /// Attributes the backend's denials to the running component.
+    pub audit: Auditor<'a>,
+    /// Where a network backend counts its traffic, for `broker stats`.
+    pub meter: &'a NetMeter,
+}
+
+type FsFactory =
+    Box<dyn for<'a> Fn(&HostEnv<'a>) -> Result<Box<dyn FsHost + 'a>, String> + Send + Sync>;
+type NetFactory =
+    Box<dyn for<'a> Fn(&HostEnv<'a>) -> Result<Box<dyn NetHost + 'a>, String> + Send + Sync>;
+type WsFactory =
+    Box<dyn for<'a> Fn(&HostEnv<'a>) -> Result<Box<dyn WsHost + 'a>, String> + Send + Sync>;
+type LogFactory = Box<dyn Fn(&Path) -> Result<Box<dyn LogHost>, String> + Send + Sync>;
+
+/// Host backends by name.
+#[derive(Default)]
+pub struct HostRegistry {
+    fs: BTreeMap<String, FsFactory>,
+    net: BTreeMap<String, NetFactory>,
+    ws: BTreeMap<String, WsFactory>,
+    log: BTreeMap<String, LogFactory>,
+}
+
+impl fmt::Debug for HostRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostRegistry")
+            .field("fs", &self.fs.keys().collect::<Vec<_>>())
+            .field("net", &self.net.keys().collect::<Vec<_>>())
+            .field("ws", &self.ws.keys().collect::<Vec<_>>())
+            .field("log", &self.log.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl HostRegistry {
+    /// The broker's own backends: `local` fs, `http` net, `none` and `echo`
+    /// ws and the `stderr` log.
+    pub fn builtin() -> Self {
+        Self::default()
+            .with_fs("local", |env| {
+                Ok(Box::new(crate::StdFsHost {
+                    root: env.mounts.clone(),
+                    policy: env.policy.clone(),
+                    audit: env.audit,
+                }))
+            })
+            .with_net("http", |env| {
+                crate::StdNetHost::new(env.policy.clone(), env.audit, env.meter)
+                    .map(|net| Box::new(net) as Box<dyn NetHost>)
+            })
+            .with_ws("none", |env| {
+                Ok(Box::new(crate::NoWsHost {
+                    policy: env.policy.clone(),
+                    audit: env.audit,
+                }))
+            })
+            .with_ws("echo", |env| {
+                Ok(Box::new(crate::EchoWsHost::new(
+                    env.policy.clone(),
+                    env.audit,
+                )))
+            })
+            .with_log("stderr", |_| Ok(Box::new(Stderr)))
+    }
+
+    pub fn with_fs<F>(mut self, name: &str, factory: F) -> Self
+    where
+        F: for<'a> Fn(&HostEnv<'a>) -> Result<Box<dyn FsHost + 'a>, String> + Send + Sync + 'static,
+    {
+        self.fs.insert(name.to_string(), Box::new(factory));
+        self
+    }
+
+    pub fn with_net<F>(mut self, name: &str, factory: F) -> Self
+    where
+        F: for<'a> Fn(&HostEnv<'a>) -> Result<Box<dyn NetHost + 'a>, String>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.net.insert(name.to_string(), Box::new(factory));
+        self
+    }
+
+    pub fn with_ws<F>(mut self, name: &str, factory: F) -> Self
+    where
+        F: for<'a> Fn(&HostEnv<'a>) -> Result<Box<dyn WsHost + 'a>, String> + Send + Sync + 'static,
+    {
+        self.ws.insert(name.to_string(), Box::new(factory));
+        self
+    }
+
+    /// Register a log backend, built from the workspace directory.
+    pub fn with_log<F>(mut self, name: &str, factory: F) -> Self
+    where
+        F: Fn(&Path) -> Result<Box<dyn LogHost>, String> + Send + Sync + 'static,
+    {
+        self.log.insert(name.to_string(), Box::new(factory));
+        self
+    }
+
+    pub fn fs<'a>(&self, name: &str, env: &HostEnv<'a>) -> Result<Box<dyn FsHost + 'a>, String> {
+        pick("fs", &self.fs, name)?(env)
+    }
+
+    pub fn net<'a>(&self, name: &str, env: &HostEnv<'a>) -> Result<Box<dyn NetHost + 'a>, String> {
+        pick("net", &self.net, name)?(env)
+    }
+
+    pub fn ws<'a>(&self, name: &str, env: &HostEnv<'a>) -> Result<Box<dyn WsHost + 'a>, String> {
+        pick("ws", &self.ws, name)?(env)
+    }
+
+    /// The log backends `names` picks, built for `workspace`.
+    pub fn logs(
+        &self,
+        names: &[String],
+        workspace: &Path,
+    ) -> Result<Vec<Box<dyn LogHost>>, String> {
+        names
+            .iter()
+            .map(|name| pick("log", &self.log, name)?(workspace))
+            .collect()
+    }
+}
+
+fn pick<'r, T>(
+    kind: &str,
+    factories: &'r BTreeMap<String, T>,
+    name: &str,
+) -> Result<&'r T, String> {
+    factories.get(name).ok_or_else(|| {
+        let known: Vec<_> = factories.keys().map(String::as_str).collect();
+        format!(
+            "[hosts] {kind} = {name:?}: no such backend (known: {})",
+            known.join(", ")
+        )
+    })
+}
+
+/// Each record as a line of JSON on stderr, for collectors reading a
+/// container's output.
+struct Stderr;
+
+impl LogHost for Stderr {
+    fn record(&self, record: AuditRecord) {
+        if let Ok(line) = serde_json::to_string(&record) {
+            eprintln!("{line}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ask;
+    use saf_core::{AuditEvent, Code, ComponentIdentity};
+    use saf_policy::Policy;
+    use std::sync::{Arc, Mutex};
+
+    struct Canned;
+
+    impl FsHost for Canned {
+        fn list_dir(&self, _path: &str) -> Result<Vec<String>, String> {
+            Ok(vec!["canned".to_string()])
+        }
+        fn read_text(&self, path: &str) -> Result<String, String> {
+            Ok(format!("canned {path}"))
+        }
+        fn write_text(&self, _path: &str, _content: &str) -> Result<(), String> {
+            Err("read-only".to_string())
+        }
+        fn append_bytes(&self, _path: &str, _content: &[u8]) -> Result<(), String> {
+            Err("read-only".to_string())
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Memory(Arc<Mutex<Vec<AuditRecord>>>);
+
+    impl LogHost for Memory {
+        fn record(&self, record: AuditRecord) {
+            self.0.lock().unwrap().push(record);
+        }
+    }
+
+    #[test]
+    fn backends_are_picked_by_name() {
+        let config: HostsConfig = toml::from_str("fs = \"canned\"\nlog = [\"memory\"]").unwrap();
+        assert_eq!(config.net, "http");
+        assert!(toml::from_str::<HostsConfig>("db = \"x\"").is_err());
+
+        let memory = Memory::default();
+        let registry = HostRegistry::builtin()
+            .with_fs("canned", |_| Ok(Box::new(Canned)))
+            .with_log("memory", {
+                let memory = memory.clone();
+                move |_| Ok(Box::new(memory.clone()))
+            });
+
+        let workspace = Path::new("/ws");
+        let mounts = Mounts::new(workspace.to_path_buf(), Vec::new()).unwrap();
+        let policy = SharedPolicy::new(Policy::new());
+        let log = Memory::default();
+        let component = ComponentIdentity::new("broker");
+        let asker = ask::Asker::new(Box::new(ask::TerminalPrompt), &log, &component, None);
+        let meter = NetMeter::default();
+        let env = HostEnv {
+            mounts: &mounts,
+            policy: &policy,
+            audit: Auditor {
+                log: &log,
+                policy: &policy,
+                component: &component,
+                ask: &asker,
+                dry_run: false,
+            },
+            meter: &meter,
+        };
+        let fs = registry.fs(&config.fs, &env).unwrap();
+        assert_eq!(fs.read_text("a.txt").unwrap(), "canned a.txt");
+        assert!(registry.net(&config.net, &env).is_ok());
+        let err = registry.ws("real", &env).err().unwrap();
+        assert!(err.contains("known: echo, none"), "{err}");
+
+        let sinks = registry.logs(&config.log, workspace).unwrap();
+        sinks[0].record(AuditRecord::new(
+            Code::BrokerStart,
+            AuditEvent::BrokerLifecycle {
+                version: "0".to_string(),
+            },
+        ));
+        assert_eq!(memory.0.lock().unwrap().len(), 1);
+        assert!(registry.logs(&["syslog".to_string()], workspace).is_err());
+    }
+
+    #[test]
+    fn the_local_fs_keeps_out_of_the_saf_directory() {
+        let workspace = std::env::temp_dir().join(format!("saf-hosts-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(workspace.join(".saf")).unwrap();
+        let mounts = Mounts::new(workspace.clone(), Vec::new()).unwrap();
+        // Even a policy that allows every path, in a dry run.
+        let policy = SharedPolicy::new(Policy::new());
+        let log = Memory::default();
+        let component = ComponentIdentity::new("guest");
+        let asker = ask::Asker::new(Box::new(ask::TerminalPrompt), &log, &component, None);
+        let meter = NetMeter::default();
+        let env = HostEnv {
+            mounts: &mounts,
+            policy: &policy,
+            audit: Auditor {
+                log: &log,
+                policy: &policy,
+                component: &component,
+                ask: &asker,
+                dry_run: true,
+            },
+            meter: &meter,
+        };
+        let fs = HostRegistry::builtin().fs("local", &env).unwrap();
+
+        let err = fs.write_text(".saf/policy.toml", "[fs]").unwrap_err();
+        assert!(err.contains("policy.path_denied"), "{err}");
+        assert!(fs.append_bytes("./.SAF/audit.log", b"x").is_err());
+        assert!(!workspace.join(".saf/policy.toml").exists());
+        assert!(!fs.list_dir("").unwrap().contains(&".saf".to_string()));
+        assert_eq!(log.0.lock().unwrap().len(), 2);
+        std::fs::remove_dir_all(&workspace).unwrap();
+    }
+
+    #[test]
+    fn requests_refused_by_policy_leave_the_rate_limit_alone() {
+        let mounts = Mounts::new(Path::new("/ws").to_path_buf(), Vec::new()).unwrap();
+        let once = saf_policy::RateLimit {
+            requests_per_minute: 1,
+            burst: 1,
+        };
+        let policy = SharedPolicy::new(Policy {
+            allowed_domains: vec!["example.org".to_string(), "localhost".to_string()],
+            rate_limits: [
+                ("example.org".to_string(), once),
+                ("localhost".to_string(), once),
+            ]
+            .into(),
+            ..Policy::new()
+        });
+        let log = Memory::default();
+        let component = ComponentIdentity::new("guest");
+        let asker = ask::Asker::new(Box::new(ask::TerminalPrompt), &log, &component, None);
+        let meter = NetMeter::default();
+        let env = HostEnv {
+            mounts: &mounts,
+            policy: &policy,
+            audit: Auditor {
+                log: &log,
+                policy: &policy,
+                component: &component,
+                ask: &asker,
+                dry_run: false,
+            },
+            meter: &meter,
+        };
+        let net = HostRegistry::builtin().net("http", &env).unwrap();
+
+        // Allowed by name, but loopback: refused every time, never throttled.
+        for _ in 0..2 {
+            match net.fetch("https://localhost/") {
+                Err(saf_core::NetError::Failed(e)) => {
+                    assert!(e.contains("policy.ip_denied"), "{e}")
+                }
+                other => panic!("{other:?}"),
+            }
+        }
+        assert!(net.fetch("https://example.org/data.json").is_ok());
+        assert!(matches!(
+            net.fetch("https://example.org/data.json"),
+            Err(saf_core::NetError::RateLimited { .. })
+        ));
+    }
+
+    #[test]
+    fn ws_connections_are_refused_unless_the_echo_stub_is_picked() {
+        let mounts = Mounts::new(Path::new("/ws").to_path_buf(), Vec::new()).unwrap();
+        let policy = SharedPolicy::new(Policy {
+            allowed_domains: vec!["example.org".to_string()],
+            ..Policy::new()
+        });
+        let log = Memory::default();
+        let component = ComponentIdentity::new("guest");
+        let asker = ask::Asker::new(Box::new(ask::TerminalPrompt), &log, &component, None);
+        let meter = NetMeter::default();
+        let env = HostEnv {
+            mounts: &mounts,
+            policy: &policy,
+            audit: Auditor {
+                log: &log,
+                policy: &policy,
+                component: &component,
+                ask: &asker,
+                dry_run: false,
+            },
+            meter: &meter,
+        };
+        let none = HostRegistry::builtin().ws("none", &env).unwrap();
+        let err = none.connect("wss://example.org/echo").unwrap_err();
+        assert!(err.contains("no WebSocket transport"), "{err}");
+        assert!(none.connect("wss://elsewhere.org/").is_err());
+        assert_eq!(HostsConfig::default().ws, "none");
+
+        // The echo never reports an open connection closed.
+        let ws = HostRegistry::builtin().ws("echo", &env).unwrap();
+        let conn = ws.connect("wss://example.org/echo").unwrap();
+        let err = ws.receive(conn).unwrap_err();
+        assert!(err.contains("no message waiting"), "{err}");
+        ws.send(conn, "hi").unwrap();
+        assert_eq!(ws.receive(conn), Ok(Some("hi".to_string())));
+        assert!(ws.receive(conn).is_err());
+        ws.close(conn).unwrap();
+        assert!(ws.receive(conn).unwrap_err().contains("no such connection"));
+    }
+}
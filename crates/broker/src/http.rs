@@ -0,0 +1,370 @@
+//! HTTP(S) transport behind the broker's NetHost.
+//!
+//! One agent serves every request of a run, so keep-alive connections are
+//! pooled and reused instead of paying a TCP and TLS handshake per call.
+//! The agent never looks names up itself: the NetHost resolves and checks
+//! the host first and pins the address here, and connections may only be
+//! made to a pinned address. Redirects are returned to the caller rather
+//! than followed, since the next hop has to pass policy like any other
+//! request. At most `max_per_host` connections are open to one host at a
+//! time. HTTP/2 is not offered; pooled HTTP/1.1 connections cover the
+//! many-small-requests case.
+//!
+//! A request made while serving a component's host call is also cut off
+//! at the run's deadline, if it has one (see [`with_deadline`]), so a run
+//! that times out is not left waiting on the network.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use saf_core::{Code, HttpResponse};
+use saf_policy::ConnectionPool;
+use ureq::config::Config;
+use ureq::http::Uri;
+use ureq::unversioned::resolver::{ResolvedSocketAddrs, Resolver};
+use ureq::unversioned::transport::{DefaultConnector, NextTimeout};
+
+use crate::secrets::ClientIdentity;
+use crate::ssrf::PinnedTarget;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time allowed for the status line and headers, and for a whole
+/// non-streaming body.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Validated addresses keyed by `host:port`.
+type Pins = Arc<Mutex<HashMap<String, SocketAddr>>>;
+/// Connections in use, keyed by `host:port`.
+type InUse = Arc<Mutex<HashMap<String, usize>>>;
+
+thread_local! {
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Run `f` with requests made on this thread, body reads included, ending
+/// by `deadline` at the latest.
+#[cfg_attr(not(feature = "wasmtime-host"), allow(dead_code))]
+pub fn with_deadline<T>(deadline: Option<Instant>, f: impl FnOnce() -> T) -> T {
+    let outer = DEADLINE.replace(deadline);
+    let out = f();
+    DEADLINE.set(outer);
+    out
+}
+
+pub struct Transport {
+    agent: ureq::Agent,
+    pins: Pins,
+    in_use: InUse,
+    max_per_host: usize,
+}
+
+/// One connection counted against its host's limit until dropped.
+struct Slot {
+    in_use: InUse,
+    key: String,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if let Ok(mut in_use) = self.in_use.lock() {
+            if let Some(n) = in_use.get_mut(&self.key) {
+                *n -= 1;
+                if *n == 0 {
+                    in_use.remove(&self.key);
+                }
+            }
+        }
+    }
+}
+
+/// A streamed body, holding its connection's slot until it is dropped.
+struct Held<R> {
+    body: R,
+    _slot: Slot,
+}
+
+impl<R: Read> Read for Held<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.body.read(buf)
+    }
+}
+
+impl Transport {
+    pub fn new(pool: &ConnectionPool) -> Self {
+        let config = ureq::Agent::config_builder()
+            .max_idle_connections(pool.max_idle)
+            .max_idle_connections_per_host(pool.max_idle_per_host.min(pool.max_per_host))
+            .max_idle_age(Duration::from_secs(pool.idle_timeout_secs))
+            .timeout_connect(Some(CONNECT_TIMEOUT))
+            .timeout_recv_response(Some(RESPONSE_TIMEOUT))
+            .http_status_as_error(false)
+            .max_redirects(0)
+            // A proxy from the environment would bypass address pinning.
+            .proxy(None)
+            .build();
+        let pins = Pins::default();
+        let resolver = PinnedResolver { pins: pins.clone() };
+        Self {
+            agent: ureq::Agent::with_parts(config, DefaultConnector::default(), resolver),
+            pins,
+            in_use: InUse::default(),
+            max_per_host: pool.max_per_host,
+        }
+    }
+
+    /// Take one of the connections `key` may have open, or fail if all are
+    /// in use.
+    fn slot(&self, key: String) -> Result<Slot, String> {
+        let mut in_use = self.in_use.lock().map_err(|e| e.to_string())?;
+        let n = in_use.entry(key.clone()).or_default();
+        if *n >= self.max_per_host {
+            return Err(Code::NetFailed.with_message(&format!(
+                "{key} already has {} open connections, the most the policy allows",
+                self.max_per_host
+            )));
+        }
+        *n += 1;
+        Ok(Slot {
+            in_use: self.in_use.clone(),
+            key,
+        })
+    }
+
+    fn get(
+        &self,
+        url: &str,
+        target: &PinnedTarget,
+        identity: Option<&ClientIdentity>,
+        body_timeout: Duration,
+    ) -> Result<(ureq::http::Response<ureq::Body>, Slot), String> {
+        let key = format!("{}:{}", target.host, target.addr.port());
+        let slot = self.slot(key.clone())?;
+        self.pins
+            .lock()
+            .map_err(|e| e.to_string())?
+            .insert(key, target.addr);
+        let mut config = self
+            .agent
+            .get(url)
+            .config()
+            .timeout_recv_body(Some(body_timeout));
+        if let Some(deadline) = DEADLINE.get() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            config = config.timeout_global(Some(left));
+        }
+        if let Some(id) = identity {
+            config = config.tls_config(
+                ureq::tls::TlsConfig::builder()
+                    .client_cert(Some(id.tls_client_cert()?))
+                    .build(),
+            );
+        }
+        let response = config
+            .build()
+            .call()
+            .map_err(|e| Code::NetFailed.with_message(&format!("GET {url}: {e}")))?;
+        Ok((response, slot))
+    }
+
+    /// GET `url` from its pinned address, reading at most `max_body` bytes
+    /// of body.
+    pub fn fetch(
+        &self,
+        url: &str,
+        target: &PinnedTarget,
+        identity: Option<&ClientIdentity>,
+        max_body: u64,
+    ) -> Result<HttpResponse, String> {
+        let (mut response, _slot) = self.get(url, target, identity, RESPONSE_TIMEOUT)?;
+        let status = response.status().as_u16();
+        let headers = headers(&response);
+        let body = response
+            .body_mut()
+            .with_config()
+            .limit(max_body)
+            .read_to_string()
+            .map_err(|e| match e {
+                ureq::Error::BodyExceedsLimit(limit) => Code::PolicySizeLimit
+                    .with_message(&format!("response body exceeds {limit} bytes")),
+                e => Code::NetFailed.with_message(&format!("GET {url}: {e}")),
+            })?;
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+
+    /// GET `url` and hand back the body for incremental reads. The body may
+    /// take up to `max_duration` to arrive in full.
+    pub fn open(
+        &self,
+        url: &str,
+        target: &PinnedTarget,
+        identity: Option<&ClientIdentity>,
+        max_duration: Duration,
+    ) -> Result<Box<dyn Read + Send>, String> {
+        let (response, slot) = self.get(url, target, identity, max_duration)?;
+        if !response.status().is_success() {
+            return Err(Code::NetFailed
+                .with_message(&format!("GET {url}: HTTP {}", response.status().as_u16())));
+        }
+        Ok(Box::new(Held {
+            body: response.into_body().into_reader(),
+            _slot: slot,
+        }))
+    }
+}
+
+fn headers(response: &ureq::http::Response<ureq::Body>) -> Vec<(String, String)> {
+    response
+        .headers()
+        .iter()
+        .filter_map(|(k, v)| Some((k.as_str().to_string(), v.to_str().ok()?.to_string())))
+        .collect()
+}
+
+/// Resolver that only knows addresses the NetHost has already validated.
+#[derive(Debug)]
+struct PinnedResolver {
+    pins: Pins,
+}
+
+impl Resolver for PinnedResolver {
+    fn resolve(
+        &self,
+        uri: &Uri,
+        _config: &Config,
+        _timeout: NextTimeout,
+    ) -> Result<ResolvedSocketAddrs, ureq::Error> {
+        let host = uri.host().ok_or(ureq::Error::HostNotFound)?;
+        let port = uri
+            .port_u16()
+            .or(match uri.scheme_str() {
+                Some("https") => Some(443),
+                Some("http") => Some(80),
+                _ => None,
+            })
+            .ok_or(ureq::Error::HostNotFound)?;
+        let addr = self
+            .pins
+            .lock()
+            .ok()
+            .and_then(|pins| pins.get(&format!("{host}:{port}")).copied())
+            .ok_or(ureq::Error::HostNotFound)?;
+        let mut addrs = self.empty();
+        addrs.push(addr);
+        Ok(addrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A keep-alive server answering every request with `ok`, and the
+    /// count of connections it has accepted.
+    fn serve_ok() -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        std::thread::spawn(move || {
+            for conn in listener.incoming().flatten() {
+                counter.fetch_add(1, Ordering::SeqCst);
+                std::thread::spawn(move || {
+                    let mut reader = BufReader::new(conn.try_clone().expect("clone"));
+                    let mut conn = conn;
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).map(|n| n > 0).unwrap_or(false) {
+                        if line == "\r\n" {
+                            let _ =
+                                conn.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok");
+                        }
+                        line.clear();
+                    }
+                });
+            }
+        });
+        (addr, accepted)
+    }
+
+    #[test]
+    fn requests_to_one_host_share_a_pooled_connection() {
+        let (addr, accepted) = serve_ok();
+        let transport = Transport::new(&ConnectionPool::default());
+        let target = PinnedTarget {
+            host: "pinned.test".to_string(),
+            addr,
+        };
+        let url = format!("http://pinned.test:{}/", addr.port());
+        for _ in 0..2 {
+            let resp = transport.fetch(&url, &target, None, 1024).expect("fetch");
+            assert_eq!((resp.status, resp.body.as_str()), (200, "ok"));
+        }
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+        // Hosts that were never pinned cannot be reached at all.
+        let unpinned = format!("http://other.test:{}/", addr.port());
+        assert!(transport.fetch(&unpinned, &target, None, 1024).is_err());
+
+        // A server that never answers is given up on at the deadline.
+        let silent = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let target = PinnedTarget {
+            host: "silent.test".to_string(),
+            addr: silent.local_addr().expect("addr"),
+        };
+        let url = format!("http://silent.test:{}/", target.addr.port());
+        let start = Instant::now();
+        let deadline = Some(start + Duration::from_millis(200));
+        let result = with_deadline(deadline, || transport.fetch(&url, &target, None, 1024));
+        assert!(result.is_err());
+        assert!(start.elapsed() < RESPONSE_TIMEOUT / 2);
+    }
+
+    #[test]
+    fn connections_to_one_host_are_capped() {
+        let (addr, _) = serve_ok();
+        let transport = Transport::new(&ConnectionPool {
+            max_per_host: 1,
+            ..ConnectionPool::default()
+        });
+        let target = PinnedTarget {
+            host: "pinned.test".to_string(),
+            addr,
+        };
+        let url = format!("http://pinned.test:{}/", addr.port());
+        let limit = Duration::from_secs(5);
+
+        // An open stream holds the host's only connection...
+        let mut stream = transport.open(&url, &target, None, limit).expect("open");
+        let err = transport.fetch(&url, &target, None, 1024).unwrap_err();
+        assert!(err.contains("open connections"), "{err}");
+        assert!(transport.open(&url, &target, None, limit).is_err());
+
+        // ...even once its body is read, until it is dropped.
+        let mut body = String::new();
+        stream.read_to_string(&mut body).expect("read");
+        assert_eq!(body, "ok");
+        assert!(transport.fetch(&url, &target, None, 1024).is_err());
+        drop(stream);
+        let resp = transport.fetch(&url, &target, None, 1024).expect("fetch");
+        assert_eq!(resp.body, "ok");
+
+        // Other hosts have slots of their own.
+        let other = PinnedTarget {
+            host: "other.test".to_string(),
+            addr,
+        };
+        let _held = transport.open(&url, &target, None, limit).expect("open");
+        let url = format!("http://other.test:{}/", addr.port());
+        assert!(transport.fetch(&url, &other, None, 1024).is_ok());
+    }
+}
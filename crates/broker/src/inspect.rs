@@ -0,0 +1,436 @@
+//! `broker component inspect`: what a component would be granted, worked
+//! out without running any of it.
+//!
+//! The component's imports are read straight from the binary, as at
+//! install, and its manifest and capability manifest from beside it. The
+//! policy is the one a run in the workspace would get: `--policy`, else the
+//! workspace's `.saf/policy.toml`, else the built-in default, as it applies
+//! to the component's hash, installed names and manifest key. For each
+//! `saf:app` interface the component imports or requests, the report lists
+//! the scopes it would be granted, those the user would be asked about, and
+//! those it would be refused, then anything that would stop the run.
+//!
+//! With a capability manifest, the grant is the requested scopes the policy
+//! allows, and any it does not, or an imported interface left unrequested,
+//! would refuse the run. Without one, every imported interface is linked
+//! with all the policy grants it.
+
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use saf_core::Code;
+use saf_policy::{Denial, Policy};
+use serde::Serialize;
+
+use crate::capabilities::CapabilityManifest;
+use crate::component_manifest::{self, ComponentManifest};
+use crate::components::{self, CompatReport, Registry, SAF_INTERFACES, WIT_PACKAGE};
+use crate::run_manifest::sha256_file;
+
+#[derive(Debug, Clone, Args)]
+pub struct InspectArgs {
+    /// The component file.
+    pub component: PathBuf,
+    /// Policy to inspect against (default: the workspace's
+    /// .saf/policy.toml, else the built-in default).
+    #[arg(long, value_name = "PATH")]
+    pub policy: Option<PathBuf>,
+    /// Workspace whose policy applies (default: the current directory).
+    #[arg(long, value_name = "DIR")]
+    pub workspace: Option<PathBuf>,
+    /// Inspect as `broker run --offline` would run it.
+    #[arg(long)]
+    pub offline: bool,
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// What a component would be granted, and whether it could run.
+#[derive(Debug, Clone, Serialize)]
+pub struct Inspection {
+    pub component: String,
+    pub sha256: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<ComponentManifest>,
+    /// The file the capabilities came from, if any were declared.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<String>,
+    /// The policy file, or `built-in default`.
+    pub policy: String,
+    /// The policy entry selected for the component; `None` is the
+    /// restrictive default.
+    pub policy_entry: Option<String>,
+    pub offline: bool,
+    pub compat: CompatReport,
+    pub interfaces: Vec<InterfaceGrant>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// What would stop the run.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub problems: Vec<String>,
+}
+
+/// One `saf:app` interface as the run would have it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct InterfaceGrant {
+    pub interface: String,
+    pub imported: bool,
+    /// Scopes the capability manifest requests; `None` without one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requested: Option<Vec<String>>,
+    /// Whether the interface would be linked.
+    pub linked: bool,
+    pub granted: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ask: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub denied: Vec<String>,
+}
+
+/// Entry point for `broker component inspect <PATH>`.
+pub fn main(args: InspectArgs, registry: &Registry) -> Result<(), String> {
+    let inspection = inspect(&args, registry)?;
+    if args.json {
+        let out = serde_json::to_string_pretty(&inspection).map_err(|e| e.to_string())?;
+        println!("{out}");
+    } else {
+        print_inspection(&inspection);
+    }
+    if inspection.problems.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} would not run: {}",
+            inspection.component,
+            inspection.problems.join("; ")
+        ))
+    }
+}
+
+fn inspect(args: &InspectArgs, registry: &Registry) -> Result<Inspection, String> {
+    let component = &args.component;
+    let bytes = std::fs::read(component).map_err(|e| format!("{}: {}", component.display(), e))?;
+    let imports = components::component_imports(&bytes)?;
+    let compat = CompatReport::check(&imports);
+    let sha256 = sha256_file(component)?;
+    let manifest = ComponentManifest::load_for(component)?;
+    let capabilities = component_manifest::capabilities_for(component, manifest.as_ref())?;
+
+    let mut warnings = Vec::new();
+    let workspace_policy = args
+        .workspace
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".saf")
+        .join("policy.toml");
+    let policy_file = args
+        .policy
+        .clone()
+        .or_else(|| workspace_policy.exists().then_some(workspace_policy));
+    let (base, source) = match &policy_file {
+        Some(path) => {
+            let policy = Policy::from_file(path)?;
+            warnings.extend(
+                policy
+                    .issues()
+                    .iter()
+                    .filter(|i| !i.is_error())
+                    .map(|i| format!("{}: {i}", path.display())),
+            );
+            (policy, path.display().to_string())
+        }
+        None => (crate::default_policy()?, "built-in default".to_string()),
+    };
+    let (mut policy, policy_entry) = base.for_component(&registry.policy_ids(&sha256));
+    policy.offline |= args.offline;
+
+    if let Some(m) = &manifest {
+        warnings.extend(
+            m.exceeded_hints(&policy, None)
+                .into_iter()
+                .map(|hint| format!("resource hint above the limit: {hint}")),
+        );
+    }
+    let mut problems: Vec<String> = compat
+        .unsupported()
+        .map(|c| format!("{} cannot be provided", c.import))
+        .collect();
+    if let Some((caps, _)) = &capabilities {
+        problems.extend(
+            caps.unsatisfied(&imports, &policy)
+                .iter()
+                .map(ToString::to_string),
+        );
+    }
+    Ok(Inspection {
+        component: component.display().to_string(),
+        sha256,
+        manifest,
+        capabilities: capabilities
+            .as_ref()
+            .map(|(_, path)| path.display().to_string()),
+        policy: source,
+        policy_entry,
+        offline: policy.offline,
+        interfaces: interfaces(&imports, capabilities.as_ref().map(|(c, _)| c), &policy),
+        compat,
+        warnings,
+        problems,
+    })
+}
+
+/// The `saf:app` interfaces the component imports or requests, each with
+/// what `policy` would grant, ask about and refuse.
+fn interfaces(
+    imports: &[String],
+    capabilities: Option<&CapabilityManifest>,
+    policy: &Policy,
+) -> Vec<InterfaceGrant> {
+    let imported: Vec<&str> = imports
+        .iter()
+        .filter_map(|i| {
+            let path = i.split_once('@').map_or(i.as_str(), |(p, _)| p);
+            path.strip_prefix(WIT_PACKAGE)?.strip_prefix('/')
+        })
+        .collect();
+    let decisions = capabilities.map(|c| c.decisions(policy));
+    SAF_INTERFACES
+        .iter()
+        .filter(|i| {
+            imported.contains(i) || capabilities.is_some_and(|c| c.requires.contains_key(**i))
+        })
+        .map(|interface| {
+            let mut grant = InterfaceGrant {
+                interface: interface.to_string(),
+                imported: imported.contains(interface),
+                ..InterfaceGrant::default()
+            };
+            let (Some(caps), Some(decisions)) = (capabilities, &decisions) else {
+                grant.linked = true;
+                policy_grants(&mut grant, policy);
+                return grant;
+            };
+            grant.requested = caps.requires.get(*interface).cloned();
+            grant.linked = grant.requested.is_some();
+            if !grant.linked {
+                grant.denied.push("imported but not requested".to_string());
+            }
+            for (_, scope, decision) in decisions.iter().filter(|(i, ..)| i == interface) {
+                match decision {
+                    Ok(()) => grant.granted.push(scope.to_string()),
+                    Err(d) => grant.denied.push(refused(scope, d)),
+                }
+            }
+            grant
+        })
+        .collect()
+}
+
+/// Everything `policy` grants `grant`'s interface, for a component that
+/// does not narrow it with a capability manifest.
+fn policy_grants(grant: &mut InterfaceGrant, policy: &Policy) {
+    // Each entry of a refusing rule, named as its field and index.
+    let rules = |field: &str, entries: &[String], code: Code, prefix: &str| -> Vec<String> {
+        entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                refused(
+                    &format!("{prefix}{entry}"),
+                    &Denial::new(code, format!("{field}[{i}]")),
+                )
+            })
+            .collect()
+    };
+    match grant.interface.as_str() {
+        "fs" => {
+            grant.granted = if policy.allowed_paths.is_empty() {
+                vec!["(whole workspace)".to_string()]
+            } else {
+                policy.allowed_paths.clone()
+            };
+            grant.ask = policy.ask_paths.clone();
+            grant.denied = [
+                rules(
+                    "denied_paths",
+                    &policy.denied_paths,
+                    Code::PolicyPathDenied,
+                    "",
+                ),
+                rules("deny", &policy.deny, Code::PolicyPathDenied, ""),
+                rules(
+                    "read_only",
+                    &policy.read_only,
+                    Code::PolicyPathReadOnly,
+                    "write:",
+                ),
+            ]
+            .concat();
+            if policy.writable_only {
+                let outside = format!("write outside {}", policy.writable.join(", "));
+                grant.denied.push(refused(
+                    outside.trim_end(),
+                    &Denial::new(Code::PolicyPathReadOnly, "writable"),
+                ));
+            }
+        }
+        "net" | "ws" if policy.offline => {
+            grant
+                .denied
+                .push(refused("*", &Denial::new(Code::NetOffline, "offline")));
+        }
+        "net" | "ws" => {
+            grant.granted = policy.allowed_domains.clone();
+            grant.ask = policy.ask_domains.clone();
+            grant.denied = rules(
+                "denied_domains",
+                &policy.denied_domains,
+                Code::PolicyDomainDenied,
+                "",
+            );
+        }
+        "sysinfo" => {
+            for (field, granted) in [
+                ("hostname", policy.sysinfo.hostname),
+                ("username", policy.sysinfo.username),
+            ] {
+                if granted {
+                    grant.granted.push(field.to_string());
+                } else {
+                    grant.denied.push(refused(
+                        field,
+                        &Denial::new(Code::PolicySysinfoNotGranted, format!("sysinfo.{field}")),
+                    ));
+                }
+            }
+        }
+        "config" if policy.config_keys.is_empty() => {
+            grant.denied.push(refused(
+                "*",
+                &Denial::new(Code::PolicyConfigNotAllowed, "config_keys"),
+            ));
+        }
+        "config" => grant.granted = policy.config_keys.clone(),
+        // `log`, `time` and `rand` take no scopes.
+        _ => {}
+    }
+}
+
+/// `what`, refused by `denial`.
+fn refused(what: &str, denial: &Denial) -> String {
+    format!("{what} ({} rule={})", denial.code, denial.rule_id)
+}
+
+fn print_inspection(inspection: &Inspection) {
+    let name = Path::new(&inspection.component)
+        .file_name()
+        .map_or(inspection.component.clone(), |n| {
+            n.to_string_lossy().into_owned()
+        });
+    components::print_report(&name, &inspection.compat);
+    println!("  sha256 {}", inspection.sha256);
+    if let Some(m) = &inspection.manifest {
+        println!("  manifest {} {} by {}", m.name, m.version, m.publisher);
+    }
+    let entry = inspection
+        .policy_entry
+        .as_deref()
+        .unwrap_or("restrictive default");
+    println!("  policy {} (entry: {entry})", inspection.policy);
+    match &inspection.capabilities {
+        Some(path) => println!("  capabilities {path}"),
+        None => println!("  no capability manifest; imported interfaces get all the policy grants"),
+    }
+    if inspection.offline {
+        println!("  offline");
+    }
+    for interface in &inspection.interfaces {
+        let mut notes = Vec::new();
+        if !interface.imported {
+            notes.push("not imported");
+        }
+        if !interface.linked {
+            notes.push("not linked");
+        }
+        if notes.is_empty() {
+            println!("{WIT_PACKAGE}/{}", interface.interface);
+        } else {
+            println!(
+                "{WIT_PACKAGE}/{} ({})",
+                interface.interface,
+                notes.join(", ")
+            );
+        }
+        if let Some(requested) = &interface.requested {
+            for scope in requested {
+                println!("  request {scope}");
+            }
+        }
+        for scope in &interface.granted {
+            println!("  grant   {scope}");
+        }
+        for scope in &interface.ask {
+            println!("  ask     {scope}");
+        }
+        for scope in &interface.denied {
+            println!("  deny    {scope}");
+        }
+    }
+    for warning in &inspection.warnings {
+        println!("warning: {warning}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interfaces_show_what_the_policy_grants_and_refuses() {
+        let mut policy = Policy::new().with_allowed_domains(vec!["example.org".to_string()]);
+        policy.allowed_paths = vec!["docs".to_string()];
+        policy.deny = vec!["docs/private/**".to_string()];
+        let imports = [
+            "saf:app/fs@0.1.0".to_string(),
+            "saf:app/net@0.1.0".to_string(),
+            "wasi:io/streams@0.2.0".to_string(),
+        ];
+
+        // Without a capability manifest, imports get the whole grant.
+        let all = interfaces(&imports, None, &policy);
+        let names: Vec<&str> = all.iter().map(|i| i.interface.as_str()).collect();
+        assert_eq!(names, ["fs", "net"]);
+        assert!(all.iter().all(|i| i.linked && i.requested.is_none()));
+        assert_eq!(all[0].granted, ["docs"]);
+        assert_eq!(
+            all[0].denied,
+            ["docs/private/** (policy.path_denied rule=deny[0])"]
+        );
+        assert_eq!(all[1].granted, ["example.org"]);
+
+        // With one, only what it requests, and only what the policy allows.
+        let caps = CapabilityManifest::parse(
+            r#"
+            [requires]
+            fs = ["read:docs", "write:exports"]
+            log = []
+            "#,
+        )
+        .unwrap();
+        let narrowed = interfaces(&imports, Some(&caps), &policy.clone().with_offline(true));
+        let fs = &narrowed[0];
+        assert_eq!(fs.granted, ["read:docs"]);
+        assert_eq!(
+            fs.denied,
+            ["write:exports (policy.path_not_allowed rule=allowed_paths)"]
+        );
+        let net = &narrowed[1];
+        assert!(net.imported && !net.linked);
+        assert_eq!(net.denied, ["imported but not requested"]);
+        let log = &narrowed[2];
+        assert_eq!(
+            (log.interface.as_str(), log.imported, log.linked),
+            ("log", false, true)
+        );
+    }
+}
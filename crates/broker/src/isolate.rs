@@ -0,0 +1,875 @@
+//! Running a component in a process of its own.
+//!
+//! With `--isolate` (or `isolate = true` under `[run]` in the broker
+//! config), the guest is run by a child broker process, so a wasmtime bug,
+//! an out-of-memory abort or anything else that takes the process down
+//! ends that one run rather than the broker, its audit log and the other
+//! components it is running. The child is `broker isolated-run`; it runs
+//! the component as the broker would, but its hosts forward every
+//! filesystem, network and WebSocket call, and every audit record, to the
+//! broker over its stdin and stdout, one JSON message per line:
+//!
+//! ```text
+//! --> {"type":"start","component":"/…/notes.wasm","bytes":"AGFzbQ0AAQA…","policy":{…},…}
+//! <-- {"type":"call","id":1,"request":"run_….1","call":{"op":"read_text","path":"docs/a.md"}}
+//! --> {"type":"reply","id":1,"result":{"Ok":"# Notes\n"}}
+//! <-- {"type":"record","record":{"code":"fs.read_text",…}}
+//! <-- {"type":"finished","result":{"Ok":{"output":{"Ok":"done"},…}}}
+//! ```
+//!
+//! The child runs the component's bytes as the broker read and hashed
+//! them; it never opens the file. The broker makes the calls with its own
+//! hosts, under its own policy and attributed to the call's request ID,
+//! and writes the records to the run's log; the child checks the policy it
+//! was started with, so a reload during the run does not reach it. The
+//! child's stderr is the broker's, and a run cannot inherit stdio. The
+//! operating system confines the child to little more than its pipe to the
+//! broker (see [`crate::sandbox`]).
+//!
+//! Stopping the run is passed on to the child. A child still going
+//! [`KILL_GRACE`] after it was asked to stop, or after the run's timeout,
+//! is killed. A child that exits without finishing the run is audited as
+//! `component.crashed` with its exit status, and the run fails.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use saf_core::{
+    AuditEvent, AuditRecord, Budget, Code, ComponentIdentity, Context, FsHost, HttpResponse,
+    LogHost, NetError, NetHost, Outcome, Transfer, Usage, WsHost,
+};
+use saf_policy::{MemoryLimits, Policy, SharedPolicy};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::requests::Requests;
+use crate::sandbox::{self, Confinement, Grants, Process};
+use crate::sysinfo::SysInfo;
+use crate::wasmtime_host::{self, CoreCtx, Finished, LimitExceeded, RunOptions};
+
+/// The hidden subcommand a child runs.
+pub const SUBCOMMAND: &str = "isolated-run";
+
+/// How long a child has to end once asked to stop, or once its run is past
+/// its timeout, before it is killed.
+pub const KILL_GRACE: Duration = Duration::from_secs(2);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Everything the child needs to run the component.
+#[derive(Debug, Serialize, Deserialize)]
+struct Start {
+    /// Names the component; the child is never granted the file.
+    component: PathBuf,
+    /// The component itself, base64: the bytes the broker hashed and
+    /// checked, not a fresh read of the file.
+    bytes: String,
+    name: String,
+    sha256: Option<String>,
+    run_id: Option<String>,
+    version: Option<String>,
+    publisher: Option<String>,
+    policy: Policy,
+    dry_run: bool,
+    rng_seed: Option<u64>,
+    profile_dir: Option<PathBuf>,
+    log_path: Option<PathBuf>,
+    sysinfo: SysInfo,
+    config: BTreeMap<String, String>,
+    interfaces: Option<BTreeSet<String>>,
+    max_fuel: Option<u64>,
+    memory: MemoryLimits,
+    input: Option<String>,
+    args: Vec<(String, String)>,
+    timeout_ms: Option<u64>,
+    sandbox: sandbox::Mode,
+    grants: Grants,
+    /// What the child was started under.
+    confinement: Confinement,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ToChild {
+    Start(Box<Start>),
+    Reply {
+        id: u64,
+        result: Result<Value, Failure>,
+    },
+    Stop,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum FromChild {
+    Call {
+        id: u64,
+        /// The host call the component is making, for attribution.
+        request: Option<String>,
+        call: Call,
+    },
+    Record {
+        record: Box<AuditRecord>,
+    },
+    Finished {
+        result: Result<Ended, String>,
+    },
+}
+
+/// A host call, by the method of the host trait it is made on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Call {
+    ListDir { path: String },
+    ReadText { path: String },
+    ReadRange { path: String, offset: u64, len: u64 },
+    WriteText { path: String, content: String },
+    AppendText { path: String, content: String },
+    AppendBytes { path: String, base64: String },
+    Fetch { url: String },
+    EffectiveUrl { url: String },
+    RewritingActive,
+    OpenStream { url: String },
+    NextChunk { stream: u64 },
+    CloseStream { stream: u64 },
+    WsConnect { url: String },
+    WsSend { conn: u64, message: String },
+    WsReceive { conn: u64 },
+    WsClose { conn: u64 },
+}
+
+/// Why a host call failed, as each host trait reports it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Failure {
+    Failed(String),
+    RateLimited { domain: String, retry_after_ms: u64 },
+    Offline,
+}
+
+impl From<NetError> for Failure {
+    fn from(e: NetError) -> Self {
+        match e {
+            NetError::RateLimited {
+                domain,
+                retry_after_ms,
+            } => Self::RateLimited {
+                domain,
+                retry_after_ms,
+            },
+            NetError::Offline => Self::Offline,
+            NetError::Failed(message) => Self::Failed(message),
+        }
+    }
+}
+
+impl From<Failure> for NetError {
+    fn from(e: Failure) -> Self {
+        match e {
+            Failure::RateLimited {
+                domain,
+                retry_after_ms,
+            } => Self::RateLimited {
+                domain,
+                retry_after_ms,
+            },
+            Failure::Offline => Self::Offline,
+            Failure::Failed(message) => Self::Failed(message),
+        }
+    }
+}
+
+impl From<Failure> for String {
+    fn from(e: Failure) -> Self {
+        match NetError::from(e) {
+            NetError::Failed(message) => message,
+            other => saf_core::CoreError::from(other).to_string(),
+        }
+    }
+}
+
+/// How the child's run ended, with what it counted against the budgets.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Ended {
+    output: Result<String, String>,
+    fuel_consumed: u64,
+    peak_memory_bytes: u64,
+    host_calls: BTreeMap<String, u64>,
+    limit: Option<(String, u64)>,
+    timed_out: bool,
+    usage: Vec<(String, u64)>,
+    /// Bytes read, written and fetched.
+    transfer: (u64, u64, u64),
+}
+
+const BUDGETS: [Budget; 4] = [
+    Budget::FsReads,
+    Budget::FsWrites,
+    Budget::NetRequests,
+    Budget::WsMessages,
+];
+
+/// Limits the host traps a guest for; a name outside these is `max_fuel`.
+const LIMITS: [&str; 3] = [
+    "max_fuel",
+    "memory.max_memory_bytes",
+    "memory.max_table_elements",
+];
+
+impl Ended {
+    fn new(finished: Finished, usage: &Usage) -> Self {
+        Self {
+            output: finished.output,
+            fuel_consumed: finished.fuel_consumed,
+            peak_memory_bytes: finished.peak_memory_bytes,
+            host_calls: finished.host_calls,
+            limit: finished.limit.map(|hit| (hit.limit.to_string(), hit.value)),
+            timed_out: finished.timed_out,
+            usage: BUDGETS
+                .iter()
+                .map(|b| (b.as_str().to_string(), usage.get(*b)))
+                .collect(),
+            transfer: {
+                let t = usage.transfer();
+                (t.read, t.written, t.fetched)
+            },
+        }
+    }
+
+    /// The run as the broker reports it, adding its usage to `usage`.
+    fn finished(self, usage: &Usage) -> Finished {
+        for (name, count) in &self.usage {
+            if let Some(budget) = BUDGETS.iter().find(|b| b.as_str() == name) {
+                usage.add(*budget, *count);
+            }
+        }
+        let (read, written, fetched) = self.transfer;
+        usage.add_transfer(Transfer {
+            read,
+            written,
+            fetched,
+        });
+        Finished {
+            output: self.output,
+            fuel_consumed: self.fuel_consumed,
+            peak_memory_bytes: self.peak_memory_bytes,
+            host_calls: self.host_calls,
+            limit: self.limit.map(|(limit, value)| LimitExceeded {
+                limit: LIMITS
+                    .iter()
+                    .find(|l| **l == limit)
+                    .copied()
+                    .unwrap_or(LIMITS[0]),
+                value,
+            }),
+            timed_out: self.timed_out,
+        }
+    }
+}
+
+/// Run `bytes`, the component read from `component`, in a child process,
+/// serving its host calls with `core`.
+pub async fn run_component(
+    component: &Path,
+    bytes: &[u8],
+    core: CoreCtx<'_>,
+    options: &RunOptions,
+) -> Result<Finished, String> {
+    wasmtime_host::blocking(|| supervise(component, bytes, &core, options))
+}
+
+fn supervise(
+    component: &Path,
+    bytes: &[u8],
+    core: &CoreCtx<'_>,
+    options: &RunOptions,
+) -> Result<Finished, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("cannot find the broker: {e}"))?;
+    let grants = Grants::for_run(options.log_path.as_deref(), options.profile_dir.as_deref())?;
+    let runner = sandbox::spawn(&exe, &grants, options.sandbox)?;
+    let stdin = Arc::new(Mutex::new(runner.stdin));
+    let stdout = BufReader::new(runner.stdout);
+    let child = Arc::new(Mutex::new(runner.process));
+    let done = Arc::new(AtomicBool::new(false));
+    let watcher = {
+        let (stdin, child, done) = (stdin.clone(), child.clone(), done.clone());
+        let stop = options.stop.clone();
+        let timeout = options.timeout;
+        std::thread::spawn(move || watch(&stdin, &child, &done, stop, timeout))
+    };
+
+    let start = Start::new(
+        component,
+        bytes,
+        &core.ctx,
+        options,
+        grants,
+        runner.confinement,
+    );
+    let start = ToChild::Start(Box::new(start));
+    let mut ended = None;
+    if send(&stdin, &start).is_ok() {
+        for line in stdout.lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            match serde_json::from_str::<FromChild>(&line) {
+                Ok(FromChild::Call { id, request, call }) => {
+                    if let Some(request) = &request {
+                        core.requests.resume(request);
+                    }
+                    let ctx = &core.ctx;
+                    let result = serve(ctx.fs, ctx.net, ctx.ws, call);
+                    core.requests.end();
+                    if send(&stdin, &ToChild::Reply { id, result }).is_err() {
+                        break;
+                    }
+                }
+                Ok(FromChild::Record { record }) => core.ctx.log.record(*record),
+                Ok(FromChild::Finished { result }) => {
+                    ended = Some(result);
+                    break;
+                }
+                Err(e) => {
+                    eprintln!("isolated run: unexpected message: {e}");
+                    break;
+                }
+            }
+        }
+    }
+    done.store(true, Ordering::Relaxed);
+    let _ = watcher.join();
+    let mut child = child.lock().unwrap_or_else(|e| e.into_inner());
+    if ended.is_none() {
+        // Whatever state it is in, it is of no more use.
+        let _ = child.kill();
+    }
+    let status = child.wait().map_err(|e| e.to_string())?;
+    match ended {
+        Some(result) => result.map(|ended| ended.finished(core.ctx.usage)),
+        None => {
+            core.ctx.log.record(
+                AuditRecord::new(
+                    Code::ComponentCrashed,
+                    AuditEvent::ComponentCrashed {
+                        run_id: core.ctx.component.run_id.clone().unwrap_or_default(),
+                        status: status.clone(),
+                    },
+                )
+                .with_identity(core.ctx.component)
+                .with_outcome(Outcome::Failed),
+            );
+            Err(format!(
+                "{}: the component's process ended before the run did ({status})",
+                Code::ComponentCrashed
+            ))
+        }
+    }
+}
+
+/// Pass a stop on to the child, and kill it if it does not end in time.
+fn watch(
+    stdin: &Pipe,
+    child: &Mutex<Box<dyn Process>>,
+    done: &AtomicBool,
+    stop: Option<Arc<AtomicBool>>,
+    timeout: Option<Duration>,
+) {
+    let past_timeout = timeout.map(|t| Instant::now() + t + KILL_GRACE);
+    let mut kill_at = None;
+    while !done.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if kill_at.is_none() && stop.as_ref().is_some_and(|s| s.load(Ordering::Relaxed)) {
+            let _ = send(stdin, &ToChild::Stop);
+            kill_at = Some(now + KILL_GRACE);
+        }
+        if past_timeout.is_some_and(|t| now >= t) || kill_at.is_some_and(|t| now >= t) {
+            eprintln!("isolated run: the component's process did not stop; killing it");
+            let _ = child.lock().unwrap_or_else(|e| e.into_inner()).kill();
+            return;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// The child's stdin.
+type Pipe = Mutex<Box<dyn Write + Send>>;
+
+fn send(stdin: &Pipe, message: &ToChild) -> Result<(), String> {
+    let line = serde_json::to_string(message).map_err(|e| e.to_string())?;
+    let mut stdin = stdin.lock().map_err(|e| e.to_string())?;
+    writeln!(stdin, "{line}")
+        .and_then(|()| stdin.flush())
+        .map_err(|e| e.to_string())
+}
+
+impl Start {
+    fn new(
+        component: &Path,
+        bytes: &[u8],
+        ctx: &Context<'_>,
+        options: &RunOptions,
+        grants: Grants,
+        confinement: Confinement,
+    ) -> Self {
+        let identity = ctx.component;
+        Self {
+            component: component.to_path_buf(),
+            bytes: BASE64.encode(bytes),
+            name: identity.name.clone(),
+            sha256: identity.sha256.clone(),
+            run_id: identity.run_id.clone(),
+            version: identity.version.clone(),
+            publisher: identity.publisher.clone(),
+            policy: (*ctx.policy.current()).clone(),
+            dry_run: ctx.dry_run,
+            rng_seed: options.rng_seed,
+            profile_dir: options.profile_dir.clone(),
+            log_path: options.log_path.clone(),
+            sysinfo: options.sysinfo.clone(),
+            config: options.config.clone(),
+            interfaces: options.interfaces.clone(),
+            max_fuel: options.max_fuel,
+            memory: options.memory,
+            input: options.input.clone(),
+            args: options.args.clone(),
+            timeout_ms: options.timeout.map(|t| t.as_millis() as u64),
+            sandbox: options.sandbox,
+            grants,
+            confinement,
+        }
+    }
+
+    fn identity(&self) -> ComponentIdentity {
+        ComponentIdentity {
+            name: self.name.clone(),
+            sha256: self.sha256.clone(),
+            run_id: self.run_id.clone(),
+            version: self.version.clone(),
+            publisher: self.publisher.clone(),
+        }
+    }
+}
+
+/// Make `call` with the broker's hosts.
+pub fn serve(
+    fs: &dyn FsHost,
+    net: &dyn NetHost,
+    ws: &dyn WsHost,
+    call: Call,
+) -> Result<Value, Failure> {
+    let failed = Failure::Failed;
+    let bytes = |b: Vec<u8>| json!(BASE64.encode(b));
+    match call {
+        Call::ListDir { path } => fs.list_dir(&path).map(|e| json!(e)).map_err(failed),
+        Call::ReadText { path } => fs.read_text(&path).map(Value::String).map_err(failed),
+        Call::ReadRange { path, offset, len } => {
+            fs.read_range(&path, offset, len).map(bytes).map_err(failed)
+        }
+        Call::WriteText { path, content } => fs
+            .write_text(&path, &content)
+            .map(|()| Value::Null)
+            .map_err(failed),
+        Call::AppendText { path, content } => fs
+            .append_text(&path, &content)
+            .map(|()| Value::Null)
+            .map_err(failed),
+        Call::AppendBytes { path, base64 } => {
+            let content = BASE64.decode(base64).map_err(|e| failed(e.to_string()))?;
+            fs.append_bytes(&path, &content)
+                .map(|()| Value::Null)
+                .map_err(failed)
+        }
+        Call::Fetch { url } => net
+            .fetch(&url)
+            .map(|r| json!({"status": r.status, "headers": r.headers, "body": r.body}))
+            .map_err(Failure::from),
+        Call::EffectiveUrl { url } => Ok(json!(net.effective_url(&url))),
+        Call::RewritingActive => Ok(json!(net.rewriting_active())),
+        Call::OpenStream { url } => net.open_stream(&url).map(|s| json!(s)).map_err(Into::into),
+        Call::NextChunk { stream } => net
+            .next_chunk(stream)
+            .map(|chunk| chunk.map_or(Value::Null, bytes))
+            .map_err(Into::into),
+        Call::CloseStream { stream } => net
+            .close_stream(stream)
+            .map(|n| json!(n))
+            .map_err(Into::into),
+        Call::WsConnect { url } => ws.connect(&url).map(|c| json!(c)).map_err(failed),
+        Call::WsSend { conn, message } => ws
+            .send(conn, &message)
+            .map(|()| Value::Null)
+            .map_err(failed),
+        Call::WsReceive { conn } => ws.receive(conn).map(|m| json!(m)).map_err(failed),
+        Call::WsClose { conn } => ws.close(conn).map(|()| Value::Null).map_err(failed),
+    }
+}
+
+/// Entry point for `broker isolated-run`, started by the broker: run the
+/// component it is sent, forwarding host calls and audit records. Called
+/// before anything has started a thread, so the process can be confined.
+pub fn child_main() -> Result<(), String> {
+    let mut lines = BufReader::new(std::io::stdin()).lines();
+    let first = lines
+        .next()
+        .ok_or("no run was sent")?
+        .map_err(|e| e.to_string())?;
+    let ToChild::Start(start) = serde_json::from_str(&first).map_err(|e| e.to_string())? else {
+        return Err("expected the run to start".to_string());
+    };
+    // Built first, as it opens what it needs; it starts no threads.
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let replies = Arc::new(Mutex::new(HashMap::new()));
+    let requests = Requests::default();
+    let remote = Remote {
+        out: Mutex::new(std::io::stdout()),
+        replies: replies.clone(),
+        next: AtomicU64::new(0),
+        requests: &requests,
+    };
+    let identity = start.identity();
+    let confinement = sandbox::confine(&start.grants, start.sandbox, start.confinement.clone());
+    if start.sandbox != sandbox::Mode::Off {
+        remote.record(
+            AuditRecord::new(
+                Code::ComponentSandboxed,
+                AuditEvent::ComponentSandboxed {
+                    run_id: identity.run_id.clone().unwrap_or_default(),
+                    layers: confinement.layers.clone(),
+                    missing: confinement.missing.clone(),
+                },
+            )
+            .with_identity(&identity),
+        );
+    }
+    if let Err(e) = confinement.check(start.sandbox) {
+        return remote.send(&FromChild::Finished { result: Err(e) });
+    }
+    let stop = Arc::new(AtomicBool::new(false));
+    {
+        let stop = stop.clone();
+        std::thread::spawn(move || read_replies(lines, &stop, &replies));
+    }
+    let policy = SharedPolicy::new(start.policy.clone());
+    let usage = Usage::default();
+    let hosts = Forwarding(&remote);
+    let core = CoreCtx {
+        ctx: Context {
+            fs: &hosts,
+            net: &hosts,
+            ws: &hosts,
+            log: &remote,
+            policy: &policy,
+            component: &identity,
+            dry_run: start.dry_run,
+            usage: &usage,
+        },
+        requests: &requests,
+    };
+    let options = RunOptions {
+        rng_seed: start.rng_seed,
+        profile_dir: start.profile_dir.clone(),
+        log_path: start.log_path.clone(),
+        sysinfo: start.sysinfo.clone(),
+        config: start.config.clone(),
+        interfaces: start.interfaces.clone(),
+        stop: Some(stop),
+        max_fuel: start.max_fuel,
+        memory: start.memory,
+        input: start.input.clone(),
+        args: start.args.clone(),
+        timeout: start.timeout_ms.map(Duration::from_millis),
+        ..RunOptions::default()
+    };
+    let result = match BASE64.decode(&start.bytes) {
+        Ok(bytes) => runtime
+            .block_on(wasmtime_host::run_component(
+                &start.component,
+                &bytes,
+                core,
+                &options,
+            ))
+            .map(|finished| Ended::new(finished, &usage)),
+        Err(e) => Err(format!("{}: {e}", start.component.display())),
+    };
+    remote.send(&FromChild::Finished { result })
+}
+
+/// Hand each reply to the call waiting for it, and note a stop. Once the
+/// broker goes away, the run is stopped and waiting calls fail.
+fn read_replies(
+    lines: impl Iterator<Item = std::io::Result<String>>,
+    stop: &AtomicBool,
+    replies: &Mutex<Waiting>,
+) {
+    for line in lines.map_while(Result::ok) {
+        match serde_json::from_str(&line) {
+            Ok(ToChild::Reply { id, result }) => {
+                let waiting = replies.lock().ok().and_then(|mut r| r.remove(&id));
+                if let Some(waiting) = waiting {
+                    let _ = waiting.send(result);
+                }
+            }
+            Ok(ToChild::Stop) => stop.store(true, Ordering::Relaxed),
+            Ok(ToChild::Start(_)) | Err(_) => {}
+        }
+    }
+    stop.store(true, Ordering::Relaxed);
+    if let Ok(mut replies) = replies.lock() {
+        replies.clear();
+    }
+}
+
+/// Calls sent to the broker, by ID, and where each one's reply goes.
+type Waiting = HashMap<u64, Sender<Result<Value, Failure>>>;
+
+/// The child's hosts: each call is made by the broker.
+struct Remote<'a> {
+    out: Mutex<std::io::Stdout>,
+    replies: Arc<Mutex<Waiting>>,
+    next: AtomicU64,
+    requests: &'a Requests,
+}
+
+impl Remote<'_> {
+    fn send(&self, message: &FromChild) -> Result<(), String> {
+        let line = serde_json::to_string(message).map_err(|e| e.to_string())?;
+        let mut out = self.out.lock().map_err(|e| e.to_string())?;
+        writeln!(out, "{line}")
+            .and_then(|()| out.flush())
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Answer for Remote<'_> {
+    fn answer(&self, call: Call) -> Result<Value, Failure> {
+        let gone = || Failure::Failed("the broker is no longer serving this run".to_string());
+        let id = self.next.fetch_add(1, Ordering::Relaxed) + 1;
+        let (reply, result) = mpsc::channel();
+        self.replies.lock().map_err(|_| gone())?.insert(id, reply);
+        let request = self.requests.current();
+        self.send(&FromChild::Call { id, request, call })
+            .map_err(Failure::Failed)?;
+        result.recv().map_err(|_| gone())?
+    }
+}
+
+/// Where host calls made as [`Call`]s are answered: by the broker, for a
+/// child, or by a trace (see [`crate::trace`]).
+pub trait Answer {
+    fn answer(&self, call: Call) -> Result<Value, Failure>;
+}
+
+impl<A: Answer + ?Sized> Answer for &A {
+    fn answer(&self, call: Call) -> Result<Value, Failure> {
+        (**self).answer(call)
+    }
+}
+
+/// Hosts that make each call as a [`Call`] for `A` to answer.
+pub struct Forwarding<A>(pub A);
+
+impl<A: Answer> Forwarding<A> {
+    fn call<T: serde::de::DeserializeOwned>(&self, call: Call) -> Result<T, Failure> {
+        let value = self.0.answer(call)?;
+        serde_json::from_value(value).map_err(|e| Failure::Failed(e.to_string()))
+    }
+
+    fn bytes(&self, call: Call) -> Result<Option<Vec<u8>>, Failure> {
+        self.call::<Option<String>>(call)?
+            .map(|b| BASE64.decode(b).map_err(|e| Failure::Failed(e.to_string())))
+            .transpose()
+    }
+}
+
+impl<A: Answer + Send + Sync> FsHost for Forwarding<A> {
+    fn list_dir(&self, path: &str) -> Result<Vec<String>, String> {
+        let path = path.to_string();
+        Ok(self.call(Call::ListDir { path })?)
+    }
+
+    fn read_text(&self, path: &str) -> Result<String, String> {
+        let path = path.to_string();
+        Ok(self.call(Call::ReadText { path })?)
+    }
+
+    fn read_range(&self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>, String> {
+        let path = path.to_string();
+        let bytes = self.bytes(Call::ReadRange { path, offset, len })?;
+        Ok(bytes.unwrap_or_default())
+    }
+
+    fn write_text(&self, path: &str, content: &str) -> Result<(), String> {
+        let (path, content) = (path.to_string(), content.to_string());
+        Ok(self.call(Call::WriteText { path, content })?)
+    }
+
+    fn append_text(&self, path: &str, content: &str) -> Result<(), String> {
+        let (path, content) = (path.to_string(), content.to_string());
+        Ok(self.call(Call::AppendText { path, content })?)
+    }
+
+    fn append_bytes(&self, path: &str, content: &[u8]) -> Result<(), String> {
+        let (path, base64) = (path.to_string(), BASE64.encode(content));
+        Ok(self.call(Call::AppendBytes { path, base64 })?)
+    }
+}
+
+#[derive(Deserialize)]
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl<A: Answer + Send + Sync> NetHost for Forwarding<A> {
+    fn fetch(&self, url: &str) -> Result<HttpResponse, NetError> {
+        let url = url.to_string();
+        let r: Response = self.call(Call::Fetch { url })?;
+        Ok(HttpResponse {
+            status: r.status,
+            headers: r.headers,
+            body: r.body,
+        })
+    }
+
+    fn effective_url(&self, url: &str) -> String {
+        let call = Call::EffectiveUrl {
+            url: url.to_string(),
+        };
+        self.call(call).unwrap_or_else(|_| url.to_string())
+    }
+
+    fn rewriting_active(&self) -> bool {
+        self.call(Call::RewritingActive).unwrap_or(false)
+    }
+
+    fn open_stream(&self, url: &str) -> Result<u64, NetError> {
+        let url = url.to_string();
+        Ok(self.call(Call::OpenStream { url })?)
+    }
+
+    fn next_chunk(&self, stream: u64) -> Result<Option<Vec<u8>>, NetError> {
+        Ok(self.bytes(Call::NextChunk { stream })?)
+    }
+
+    fn close_stream(&self, stream: u64) -> Result<u64, NetError> {
+        Ok(self.call(Call::CloseStream { stream })?)
+    }
+}
+
+impl<A: Answer + Send + Sync> WsHost for Forwarding<A> {
+    fn connect(&self, url: &str) -> Result<u64, String> {
+        let url = url.to_string();
+        Ok(self.call(Call::WsConnect { url })?)
+    }
+
+    fn send(&self, conn: u64, message: &str) -> Result<(), String> {
+        let message = message.to_string();
+        Ok(self.call(Call::WsSend { conn, message })?)
+    }
+
+    fn receive(&self, conn: u64) -> Result<Option<String>, String> {
+        Ok(self.call(Call::WsReceive { conn })?)
+    }
+
+    fn close(&self, conn: u64) -> Result<(), String> {
+        Ok(self.call(Call::WsClose { conn })?)
+    }
+}
+
+impl LogHost for Remote<'_> {
+    fn record(&self, record: AuditRecord) {
+        // Stamped here, as the broker cannot tell which call it came from.
+        let record = match self.requests.current() {
+            Some(id) if record.request.is_none() => record.with_request(&id),
+            _ => record,
+        };
+        let record = Box::new(record);
+        let _ = self.send(&FromChild::Record { record });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip_and_runs_carry_their_usage_back() {
+        let call = FromChild::Call {
+            id: 3,
+            request: Some("run_1.3".to_string()),
+            call: Call::AppendBytes {
+                path: "log.txt".to_string(),
+                base64: BASE64.encode(b"\x00\xff"),
+            },
+        };
+        let line = serde_json::to_string(&call).expect("encode");
+        assert!(line.starts_with(
+            r#"{"type":"call","id":3,"request":"run_1.3","call":{"op":"append_bytes""#
+        ));
+        let FromChild::Call { call, .. } = serde_json::from_str(&line).expect("decode") else {
+            panic!("not a call: {line}");
+        };
+        let Call::AppendBytes { base64, .. } = call else {
+            panic!("not an append: {call:?}");
+        };
+        assert_eq!(BASE64.decode(base64).expect("base64"), b"\x00\xff");
+
+        let limited = Failure::from(NetError::RateLimited {
+            domain: "example.org".to_string(),
+            retry_after_ms: 500,
+        });
+        let reply = ToChild::Reply {
+            id: 3,
+            result: Err(limited.clone()),
+        };
+        let line = serde_json::to_string(&reply).expect("encode");
+        let ToChild::Reply { result, .. } = serde_json::from_str(&line).expect("decode") else {
+            panic!("not a reply: {line}");
+        };
+        assert_eq!(result, Err(limited));
+        assert!(String::from(Failure::Offline).contains("offline"));
+
+        let child_usage = Usage::default();
+        child_usage.add(Budget::FsReads, 2);
+        child_usage.add_transfer(Transfer {
+            read: 7,
+            written: 0,
+            fetched: 3,
+        });
+        let finished = Finished {
+            output: Ok("done".to_string()),
+            fuel_consumed: 10,
+            peak_memory_bytes: 65536,
+            host_calls: BTreeMap::from([("fs.read_text".to_string(), 2)]),
+            limit: Some(LimitExceeded {
+                limit: "memory.max_memory_bytes",
+                value: 64,
+            }),
+            timed_out: false,
+        };
+        let ended: Ended =
+            serde_json::from_value(json!(Ended::new(finished, &child_usage))).expect("round trip");
+        let usage = Usage::default();
+        let finished = ended.finished(&usage);
+        assert_eq!(finished.output, Ok("done".to_string()));
+        assert_eq!(
+            finished.limit.map(|l| l.limit),
+            Some("memory.max_memory_bytes")
+        );
+        assert_eq!(usage.get(Budget::FsReads), 2);
+        assert_eq!(usage.transfer(), child_usage.transfer());
+        assert_eq!(finished.host_calls["fs.read_text"], 2);
+    }
+}
@@ -0,0 +1,574 @@
+//! Components `broker serve` keeps running.
+//!
+//! `component.start` hands a component to the serving broker, which runs it
+//! in a workspace under an ID of its own until it is stopped. A component
+//! that fails is restarted after a pause that doubles with each failure in
+//! a row, from one second up to a minute; one that ran for a minute or more
+//! before failing starts over at a second. A component that finishes
+//! cleanly is left exited.
+//!
+//! Stopping a component sets the flag its run checks on each epoch tick, so
+//! the guest is interrupted at the next one and the run ends like a failed
+//! one: its `component.finish` entry, run log and report are still written.
+//! A guest blocked in a host call is interrupted once the call returns.
+//!
+//! Runs take turns per workspace, since each appends to the workspace's
+//! audit chain from a log of its own; a managed component holds its
+//! workspace's turn for as long as it runs, and waits for it when another
+//! run has it.
+//!
+//! `broker component start|stop|restart|ps` send these commands to the
+//! serving broker:
+//!
+//! ```text
+//! $ broker component ps
+//! ID   NAME             STATE        UPTIME    RESTARTS  WORKSPACE
+//! 1    notes            running      12m04s    0         workspace_…
+//! 2    feed             backing_off  -         3         workspace_…
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use clap::{Args, Subcommand};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::cli::RunArgs;
+use crate::runs::{self, RunOutcome};
+use crate::serve;
+
+/// Longest pause before restarting a component that keeps failing.
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// The components managed by this broker.
+pub static MANAGED: Lifecycle = Lifecycle::new(run_once, Duration::from_secs(1));
+
+/// Run a component once, until it finishes or `stop` is set.
+pub type Runner = fn(&Spec, Arc<AtomicBool>) -> Result<RunOutcome, String>;
+
+/// What to run, and how.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Spec {
+    pub name: String,
+    pub workspace: String,
+    pub component: PathBuf,
+    #[serde(default)]
+    pub offline: bool,
+    #[serde(default)]
+    pub policy_dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    /// Waiting for its workspace's turn.
+    Waiting,
+    Running,
+    /// Asked to stop, and not yet interrupted.
+    Stopping,
+    /// Failed, and will be restarted after a pause.
+    BackingOff,
+    Stopped,
+    /// Finished cleanly.
+    Exited,
+}
+
+impl State {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            State::Waiting => "waiting",
+            State::Running => "running",
+            State::Stopping => "stopping",
+            State::BackingOff => "backing_off",
+            State::Stopped => "stopped",
+            State::Exited => "exited",
+        }
+    }
+}
+
+/// One managed component, as `component ps` shows it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Process {
+    pub id: u64,
+    #[serde(flatten)]
+    pub spec: Spec,
+    pub state: State,
+    /// When its current run started, in unix seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_unix: Option<u64>,
+    pub restarts: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+impl Process {
+    /// Seconds its current run has been going, at `now` (unix seconds).
+    pub fn uptime(&self, now: u64) -> Option<u64> {
+        match self.state {
+            State::Running | State::Stopping => self.started_unix.map(|s| now.saturating_sub(s)),
+            _ => None,
+        }
+    }
+}
+
+struct Managed {
+    process: Process,
+    /// Set to interrupt the current run.
+    interrupt: Arc<AtomicBool>,
+    stop: bool,
+    restart: bool,
+    /// Whether a thread is running it.
+    supervised: bool,
+}
+
+struct Table {
+    next_id: u64,
+    managed: BTreeMap<u64, Managed>,
+}
+
+/// Tracks managed components and restarts the ones that fail.
+pub struct Lifecycle {
+    table: Mutex<Table>,
+    /// Signalled when a component is asked to stop or restart.
+    changed: Condvar,
+    run: Runner,
+    backoff: Duration,
+}
+
+impl Lifecycle {
+    /// Run components with `run`, pausing `backoff` before the first
+    /// restart of a failing one.
+    pub const fn new(run: Runner, backoff: Duration) -> Self {
+        Self {
+            table: Mutex::new(Table {
+                next_id: 0,
+                managed: BTreeMap::new(),
+            }),
+            changed: Condvar::new(),
+            run,
+            backoff,
+        }
+    }
+
+    /// Start managing `spec`, returning its ID.
+    pub fn start(&'static self, spec: Spec) -> Result<u64, String> {
+        let mut table = self.table.lock().map_err(|e| e.to_string())?;
+        table.next_id += 1;
+        let id = table.next_id;
+        table.managed.insert(
+            id,
+            Managed {
+                process: Process {
+                    id,
+                    spec,
+                    state: State::Waiting,
+                    started_unix: None,
+                    restarts: 0,
+                    last_error: None,
+                },
+                interrupt: Arc::new(AtomicBool::new(false)),
+                stop: false,
+                restart: false,
+                supervised: true,
+            },
+        );
+        drop(table);
+        std::thread::spawn(move || self.supervise(id));
+        Ok(id)
+    }
+
+    /// Ask component `id` to stop; it stops at its run's next epoch tick.
+    pub fn stop(&self, id: u64) -> Result<Process, String> {
+        let mut table = self.table.lock().map_err(|e| e.to_string())?;
+        let managed = Self::get(&mut table, id)?;
+        if managed.process.state != State::Stopped && managed.process.state != State::Exited {
+            managed.stop = true;
+            managed.interrupt.store(true, Ordering::Relaxed);
+            if managed.process.state == State::Running {
+                managed.process.state = State::Stopping;
+            }
+            self.changed.notify_all();
+        }
+        Ok(managed.process.clone())
+    }
+
+    /// Restart component `id` now: interrupting its current run, skipping
+    /// the pause after a failure, or starting it again once it has stopped.
+    pub fn restart(&'static self, id: u64) -> Result<Process, String> {
+        let mut table = self.table.lock().map_err(|e| e.to_string())?;
+        let managed = Self::get(&mut table, id)?;
+        managed.stop = false;
+        if managed.supervised {
+            managed.restart = true;
+            managed.interrupt.store(true, Ordering::Relaxed);
+            self.changed.notify_all();
+        } else {
+            managed.supervised = true;
+            managed.process.state = State::Waiting;
+            managed.process.restarts += 1;
+            std::thread::spawn(move || self.supervise(id));
+        }
+        Ok(managed.process.clone())
+    }
+
+    /// Ask every component to stop, as the broker is shutting down.
+    pub fn stop_all(&self) -> Result<(), String> {
+        for process in self.ps()? {
+            self.stop(process.id)?;
+        }
+        Ok(())
+    }
+
+    /// Every managed component, by ID.
+    pub fn ps(&self) -> Result<Vec<Process>, String> {
+        let table = self.table.lock().map_err(|e| e.to_string())?;
+        Ok(table.managed.values().map(|m| m.process.clone()).collect())
+    }
+
+    fn get(table: &mut Table, id: u64) -> Result<&mut Managed, String> {
+        table
+            .managed
+            .get_mut(&id)
+            .ok_or_else(|| format!("no managed component has ID {id}"))
+    }
+
+    /// Run component `id` until it exits or is stopped, restarting it when
+    /// it fails.
+    fn supervise(&self, id: u64) {
+        let mut backoff = self.backoff;
+        loop {
+            let Some((spec, interrupt)) =
+                self.update(id, |m| (m.process.spec.clone(), m.interrupt.clone()))
+            else {
+                return;
+            };
+            let turn = serve::turn(&spec.workspace);
+            let result = {
+                let _turn = turn.lock().unwrap_or_else(|e| e.into_inner());
+                let started = runs::now_unix_seconds();
+                let proceed = self.update(id, |m| {
+                    if m.stop {
+                        return false;
+                    }
+                    // A restart asked for while it waited is this run.
+                    m.restart = false;
+                    m.interrupt.store(false, Ordering::Relaxed);
+                    m.process.state = State::Running;
+                    m.process.started_unix = Some(started);
+                    true
+                });
+                if proceed != Some(true) {
+                    None
+                } else {
+                    let result = (self.run)(&spec, interrupt);
+                    Some((result, runs::now_unix_seconds().saturating_sub(started)))
+                }
+            };
+            let next = self.update(id, |m| {
+                m.process.started_unix = None;
+                if let Some((result, _)) = &result {
+                    m.process.last_error = match result {
+                        Ok(RunOutcome::Ok { .. }) => None,
+                        Ok(RunOutcome::Failed { error }) | Err(error) => Some(error.clone()),
+                    };
+                }
+                if m.stop {
+                    m.process.state = State::Stopped;
+                    m.supervised = false;
+                    return None;
+                }
+                if m.restart {
+                    m.process.restarts += 1;
+                    return Some(Duration::ZERO);
+                }
+                match &result {
+                    Some((Ok(RunOutcome::Ok { .. }), _)) => {
+                        m.process.state = State::Exited;
+                        m.supervised = false;
+                        None
+                    }
+                    Some((_, ran_secs)) => {
+                        if Duration::from_secs(*ran_secs) >= BACKOFF_MAX {
+                            backoff = self.backoff;
+                        }
+                        m.process.state = State::BackingOff;
+                        m.process.restarts += 1;
+                        let pause = backoff;
+                        backoff = (backoff * 2).min(BACKOFF_MAX);
+                        Some(pause)
+                    }
+                    None => Some(Duration::ZERO),
+                }
+            });
+            match next {
+                Some(Some(pause)) if self.pause(id, pause) => {}
+                _ => return,
+            }
+        }
+    }
+
+    /// Wait `pause` before restarting `id`, or less if it is asked to stop
+    /// or restart; false if it was stopped.
+    fn pause(&self, id: u64, pause: Duration) -> bool {
+        let Ok(table) = self.table.lock() else {
+            return false;
+        };
+        let waiting = |t: &mut Table| t.managed.get(&id).is_some_and(|m| !m.stop && !m.restart);
+        let Ok((mut table, _)) = self.changed.wait_timeout_while(table, pause, waiting) else {
+            return false;
+        };
+        match table.managed.get_mut(&id) {
+            Some(m) if m.stop => {
+                m.process.state = State::Stopped;
+                m.supervised = false;
+                false
+            }
+            Some(m) => {
+                m.process.state = State::Waiting;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Apply `f` to component `id` under the lock; `None` if it is gone.
+    fn update<T>(&self, id: u64, f: impl FnOnce(&mut Managed) -> T) -> Option<T> {
+        let mut table = self.table.lock().ok()?;
+        table.managed.get_mut(&id).map(f)
+    }
+}
+
+/// Run `spec` in its workspace as `broker serve` runs a component.
+fn run_once(spec: &Spec, stop: Arc<AtomicBool>) -> Result<RunOutcome, String> {
+    let args = RunArgs {
+        workspace_id: Some(spec.workspace.clone()),
+        run_component: Some(spec.component.clone()),
+        offline: spec.offline,
+        policy_dry_run: spec.policy_dry_run,
+        json: true,
+        stop: Some(stop),
+        ..RunArgs::default()
+    };
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| e.to_string())?
+        .block_on(crate::run(args, false))
+        .map_err(|e| e.to_string())?
+        .map(|report| report.outcome)
+        .ok_or_else(|| "the run did not produce a report".to_string())
+}
+
+#[derive(Debug, Subcommand)]
+pub enum LifecycleCommand {
+    /// Start an installed component under `broker serve`, restarting it
+    /// whenever it fails.
+    Start {
+        name: String,
+        /// The workspace to run it in.
+        #[arg(long, value_name = "ID")]
+        workspace_id: String,
+        /// Disable all network access for its runs.
+        #[arg(long)]
+        offline: bool,
+        /// Log policy denials as would-deny and let operations proceed.
+        #[arg(long)]
+        policy_dry_run: bool,
+        #[command(flatten)]
+        daemon: Daemon,
+    },
+    /// Stop a component `broker serve` manages.
+    Stop {
+        id: u64,
+        #[command(flatten)]
+        daemon: Daemon,
+    },
+    /// Restart a component `broker serve` manages.
+    Restart {
+        id: u64,
+        #[command(flatten)]
+        daemon: Daemon,
+    },
+    /// Show the components `broker serve` manages, with their state and
+    /// uptime.
+    Ps {
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        daemon: Daemon,
+    },
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct Daemon {
+    /// The socket (or pipe) the broker is serving on.
+    #[arg(long, value_name = "PATH")]
+    pub socket: Option<PathBuf>,
+}
+
+/// Entry point for `broker component start|stop|restart|ps`.
+pub fn main(command: LifecycleCommand) -> Result<(), String> {
+    match command {
+        LifecycleCommand::Start {
+            name,
+            workspace_id,
+            offline,
+            policy_dry_run,
+            daemon,
+        } => {
+            let result = serve::request(
+                daemon.socket,
+                json!({
+                    "command": "component.start",
+                    "workspace": workspace_id,
+                    "component": name,
+                    "offline": offline,
+                    "policy_dry_run": policy_dry_run,
+                }),
+            )?;
+            println!("Started {name} as {}", result["id"]);
+            Ok(())
+        }
+        LifecycleCommand::Stop { id, daemon } => {
+            serve::request(
+                daemon.socket,
+                json!({"command": "component.stop", "id": id}),
+            )?;
+            println!("Stopping {id}");
+            Ok(())
+        }
+        LifecycleCommand::Restart { id, daemon } => {
+            serve::request(
+                daemon.socket,
+                json!({"command": "component.restart", "id": id}),
+            )?;
+            println!("Restarting {id}");
+            Ok(())
+        }
+        LifecycleCommand::Ps { json, daemon } => {
+            let result = serve::request(daemon.socket, json!({"command": "component.ps"}))?;
+            if json {
+                let out = serde_json::to_string_pretty(&result).map_err(|e| e.to_string())?;
+                println!("{out}");
+                return Ok(());
+            }
+            let processes: Vec<Process> =
+                serde_json::from_value(result).map_err(|e| e.to_string())?;
+            print_ps(&processes, runs::now_unix_seconds());
+            Ok(())
+        }
+    }
+}
+
+pub fn print_ps(processes: &[Process], now: u64) {
+    if processes.is_empty() {
+        println!("No components are managed");
+        return;
+    }
+    println!(
+        "{:<4} {:<16} {:<12} {:<9} {:<9} WORKSPACE",
+        "ID", "NAME", "STATE", "UPTIME", "RESTARTS"
+    );
+    for p in processes {
+        println!(
+            "{:<4} {:<16} {:<12} {:<9} {:<9} {}",
+            p.id,
+            p.spec.name,
+            p.state.as_str(),
+            p.uptime(now).map_or_else(|| "-".to_string(), format_uptime),
+            p.restarts,
+            p.spec.workspace
+        );
+    }
+}
+
+/// `2h03m`, `4m05s` or `12s`.
+pub fn format_uptime(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(name: &str) -> Spec {
+        Spec {
+            name: name.to_string(),
+            workspace: format!("lifecycle-test-{name}"),
+            component: PathBuf::from(format!("{name}.wasm")),
+            offline: false,
+            policy_dry_run: false,
+        }
+    }
+
+    /// Fails at once, except `service`, which runs until it is stopped.
+    fn fake(spec: &Spec, stop: Arc<AtomicBool>) -> Result<RunOutcome, String> {
+        if spec.name != "service" {
+            return Err("trap: unreachable".to_string());
+        }
+        while !stop.load(Ordering::Relaxed) {
+            std::thread::yield_now();
+        }
+        Ok(RunOutcome::Failed {
+            error: "stopped by the broker".to_string(),
+        })
+    }
+
+    fn wait_for(lifecycle: &Lifecycle, id: u64, ok: impl Fn(&Process) -> bool) -> Process {
+        loop {
+            let ps = lifecycle.ps().expect("ps");
+            let process = ps.into_iter().find(|p| p.id == id).expect("managed");
+            if ok(&process) {
+                return process;
+            }
+            std::thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn components_run_until_stopped_and_failures_restart() {
+        static LIFECYCLE: Lifecycle = Lifecycle::new(fake, Duration::from_millis(1));
+        let service = LIFECYCLE.start(spec("service")).expect("start");
+        let crashing = LIFECYCLE.start(spec("crashing")).expect("start");
+        assert_eq!(
+            LIFECYCLE.stop(9).map(drop),
+            Err("no managed component has ID 9".into())
+        );
+
+        let running = wait_for(&LIFECYCLE, service, |p| p.state == State::Running);
+        assert_eq!(
+            running.uptime(running.started_unix.expect("started") + 5),
+            Some(5)
+        );
+        let crashed = wait_for(&LIFECYCLE, crashing, |p| p.restarts >= 3);
+        assert_eq!(crashed.last_error.as_deref(), Some("trap: unreachable"));
+
+        LIFECYCLE.stop(crashing).expect("stop");
+        LIFECYCLE.stop(service).expect("stop");
+        let stopped = wait_for(&LIFECYCLE, service, |p| p.state == State::Stopped);
+        assert_eq!((stopped.restarts, stopped.uptime(0)), (0, None));
+        assert_eq!(stopped.last_error.as_deref(), Some("stopped by the broker"));
+        wait_for(&LIFECYCLE, crashing, |p| p.state == State::Stopped);
+
+        LIFECYCLE.restart(service).expect("restart");
+        let restarted = wait_for(&LIFECYCLE, service, |p| p.state == State::Running);
+        assert_eq!(restarted.restarts, 1);
+        LIFECYCLE.stop(service).expect("stop");
+        wait_for(&LIFECYCLE, service, |p| p.state == State::Stopped);
+    }
+
+    #[test]
+    fn uptimes_read_at_a_glance() {
+        assert_eq!(format_uptime(12), "12s");
+        assert_eq!(format_uptime(245), "4m05s");
+        assert_eq!(format_uptime(7380), "2h03m");
+    }
+}
@@ -1,14 +1,85 @@
 use std::env;
-use std::fs::{create_dir_all, read_dir, File};
-use std::io::{Read, Write};
+use std::fs::{create_dir_all, read_dir, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Component, Path, PathBuf};
 
-use saf_audit::AuditLog;
-use saf_core::{fetch_json, list_dir as core_list_dir, Context, FsHost, LogHost, NetHost};
-use saf_policy::Policy;
+use saf_audit::AuditWriter;
+use saf_core::{
+    fetch_json, list_dir as core_list_dir, AuditEvent, AuditRecord, Capability, Code,
+    ComponentIdentity, Context, Denial, FsHost, HttpResponse, LogHost, NetError, NetHost, Outcome,
+    Violation, WsHost,
+};
+use saf_policy::{Policy, PolicyBuilder, PolicyIssue, SharedPolicy};
+
+/// Print a status line: to stdout, or to stderr when stdout carries JSON.
+macro_rules! status {
+    ($quiet:expr, $($arg:tt)*) => {
+        if $quiet {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+mod ask;
+mod audit;
+mod capabilities;
+mod cli;
+mod component_manifest;
+mod components;
+mod config;
+mod demo;
+mod dns;
+mod elevation;
+mod guest_output;
+mod hosts;
+mod http;
+mod inspect;
+mod isolate;
+mod lifecycle;
+mod metrics;
+mod mounts;
+mod net_stats;
+mod oci;
+mod otlp;
+mod pipeline;
+mod policy_check;
+mod policy_explain;
+mod policy_sig;
+mod policy_sim;
+mod policy_watch;
+mod rate_limit;
+mod repl;
+mod requests;
+mod rpc;
+mod run_log;
+mod run_manifest;
+mod runs;
+mod sandbox;
+mod schedule;
+mod secrets;
+mod selftest;
+mod serve;
+mod ship;
+mod shutdown;
+mod ssrf;
+mod status;
+mod strict;
+mod sysinfo;
+mod trace;
+mod trial;
+mod tsa;
 mod wasmtime_host;
+mod watch;
 mod workspace_picker;
 
+use clap::Parser;
+use cli::{Command, PolicyCommand, RunArgs};
+use run_manifest::RunManifest;
+use runs::{RunOutcome, RunReport};
+use saf_policy::trial::TrialState;
+
 #[cfg(feature = "ui")]
 use tauri::{AppHandle, Manager};
 
@@ -35,13 +106,86 @@ fn sanitize_rel_path(path: &str) -> Option<String> {
     Some(parts.join("/"))
 }
 
-struct StdFsHost {
-    root: PathBuf,
+/// Attributes denials made by the broker's hosts to the running component.
+#[derive(Clone, Copy)]
+struct Auditor<'a> {
+    log: &'a dyn LogHost,
+    /// Source of the `redaction` rules applied to audited targets.
+    policy: &'a SharedPolicy,
+    component: &'a ComponentIdentity,
+    ask: &'a ask::Asker<'a>,
+    /// `--policy-dry-run`: rule denials are audited but not enforced.
+    dry_run: bool,
 }
-impl FsHost for StdFsHost {
-    fn list_dir(&self, path: &str) -> Result<Vec<String>, String> {
+impl Auditor<'_> {
+    /// `target` as the audit log may show it.
+    fn audited(&self, capability: Capability, target: &str) -> String {
+        saf_core::redact(&self.policy.current().redaction, capability, target)
+    }
+    /// Audit `event`, attributed to the running component.
+    fn event(&self, code: Code, event: AuditEvent) {
+        self.log
+            .record(AuditRecord::new(code, event).with_identity(self.component));
+    }
+    fn record(&self, capability: Capability, target: &str, denial: &Denial) {
+        let target = self.audited(capability, target);
+        self.log
+            .violation(&Violation::new(denial, capability, &target, self.component));
+    }
+    /// Record `denial` and render it as the host error string.
+    fn deny(&self, capability: Capability, target: &str, denial: Denial) -> String {
+        self.record(capability, target, &denial);
+        denial.to_string()
+    }
+    /// Apply a policy decision about `target`, asking the user first if an
+    /// `ask` rule matched. `subject` names what the answer covers.
+    fn decide(
+        &self,
+        capability: Capability,
+        target: &str,
+        decision: Result<(), Denial>,
+        subject: impl FnOnce() -> String,
+    ) -> Result<(), String> {
+        if self.dry_run {
+            // Nobody is asked either: an `ask` is reported like a denial.
+            if let Err(d) = &decision {
+                let target = self.audited(capability, target);
+                self.log
+                    .would_deny(&Violation::new(d, capability, &target, self.component));
+            }
+            return Ok(());
+        }
+        self.ask
+            .resolve(capability, decision, subject)
+            .map_err(|d| self.deny(capability, target, d))
+    }
+}
+
+struct StdFsHost<'a> {
+    root: mounts::Mounts,
+    policy: SharedPolicy,
+    audit: Auditor<'a>,
+}
+impl StdFsHost<'_> {
+    fn resolve(&self, path: &str) -> Result<PathBuf, String> {
         let rel = sanitize_rel_path(path).ok_or_else(|| "invalid path".to_string())?;
-        let dir = self.root.join(rel);
+        if saf_core::is_reserved(&rel) {
+            let denial = Denial::new(Code::PolicyPathDenied, saf_core::RESERVED_DIR);
+            return Err(self.audit.deny(Capability::Fs, &rel, denial));
+        }
+        let policy = self.policy.current();
+        // An answer covers the whole `ask_paths` entry, not just this file.
+        self.audit
+            .decide(Capability::Fs, &rel, policy.check_path(&rel), || {
+                let prefix = policy.ask_path_prefix(&rel).unwrap_or(&rel);
+                self.root.resolve(prefix).display().to_string()
+            })?;
+        Ok(self.root.resolve(&rel))
+    }
+}
+impl FsHost for StdFsHost<'_> {
+    fn list_dir(&self, path: &str) -> Result<Vec<String>, String> {
+        let dir = self.resolve(path)?;
         let mut out = Vec::new();
         let entries = read_dir(&dir).map_err(|e| e.to_string())?;
         for ent in entries {
@@ -50,96 +194,750 @@ impl FsHost for StdFsHost {
                 out.push(name.to_string());
             }
         }
+        let rel = sanitize_rel_path(path).unwrap_or_default();
+        if rel.is_empty() {
+            out.retain(|name| !saf_core::is_reserved(name));
+        }
+        // A mount hides whatever is on disk under its name.
+        for name in self.root.listed_in(&rel) {
+            if !out.iter().any(|e| e == name) {
+                out.push(name.to_string());
+            }
+        }
         Ok(out)
     }
     fn read_text(&self, path: &str) -> Result<String, String> {
-        let rel = sanitize_rel_path(path).ok_or_else(|| "invalid path".to_string())?;
-        let p = self.root.join(rel);
-        let mut f = File::open(&p).map_err(|e| e.to_string())?;
+        let p = self.resolve(path)?;
+        let f = File::open(&p).map_err(|e| e.to_string())?;
+        // Read one byte past the limit to tell a full-size file from a
+        // larger one without loading the rest.
+        let max = self.policy.current().max_read_bytes;
         let mut s = String::new();
-        f.read_to_string(&mut s).map_err(|e| e.to_string())?;
+        f.take(max.saturating_add(1))
+            .read_to_string(&mut s)
+            .map_err(|e| e.to_string())?;
+        if s.len() as u64 > max {
+            self.audit.record(
+                Capability::Fs,
+                path,
+                &Denial::new(Code::PolicySizeLimit, "max_read_bytes"),
+            );
+            return Err(Code::PolicySizeLimit.with_message(&format!(
+                "file is larger than the read limit of {max} bytes"
+            )));
+        }
         Ok(s)
     }
+    fn read_range(&self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>, String> {
+        let p = self.resolve(path)?;
+        let mut f = File::open(&p).map_err(|e| e.to_string())?;
+        f.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+        let mut buf = Vec::new();
+        f.take(len.min(self.policy.current().max_read_bytes))
+            .read_to_end(&mut buf)
+            .map_err(|e| e.to_string())?;
+        Ok(buf)
+    }
     fn write_text(&self, path: &str, content: &str) -> Result<(), String> {
-        let rel = sanitize_rel_path(path).ok_or_else(|| "invalid path".to_string())?;
-        let p = self.root.join(&rel);
+        let p = self.resolve(path)?;
         if let Some(parent) = p.parent() {
             create_dir_all(parent).map_err(|e| e.to_string())?;
         }
         let mut f = File::create(&p).map_err(|e| e.to_string())?;
         f.write_all(content.as_bytes()).map_err(|e| e.to_string())
     }
+    fn append_bytes(&self, path: &str, content: &[u8]) -> Result<(), String> {
+        let p = self.resolve(path)?;
+        if let Some(parent) = p.parent() {
+            create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        // O_APPEND: every write lands at the current end of file, so concurrent
+        // appenders never clobber each other's data.
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&p)
+            .map_err(|e| e.to_string())?;
+        let current = f.metadata().map_err(|e| e.to_string())?.len();
+        let max_bytes = self.policy.current().max_write_bytes;
+        if current.saturating_add(content.len() as u64) > max_bytes {
+            self.audit.record(
+                Capability::Fs,
+                path,
+                &Denial::new(Code::PolicySizeLimit, "max_write_bytes"),
+            );
+            return Err(Code::PolicySizeLimit.with_message(&format!(
+                "append would exceed file size cap of {max_bytes} bytes"
+            )));
+        }
+        f.write_all(content).map_err(|e| e.to_string())
+    }
 }
 
 struct StdLogHost {
-    inner: std::sync::Mutex<AuditLog>,
+    inner: AuditWriter,
+    /// Elevated session in effect when the broker started; events are
+    /// marked until it expires.
+    elevation: Option<elevation::ElevationSession>,
+    /// Whose `audit_min_severity` drops records, once it is loaded;
+    /// everything before is kept.
+    policy: std::sync::OnceLock<SharedPolicy>,
+    /// Counts every record, including those kept out of the log.
+    metrics: std::sync::Arc<metrics::Metrics>,
+    /// The host call being served; records made serving it carry its ID.
+    requests: requests::Requests,
+    /// The `[hosts] log` backends, once the config is loaded; they get
+    /// every record the log keeps.
+    sinks: std::sync::OnceLock<Vec<Box<dyn LogHost>>>,
+    /// Ends a strict run at its first denial, whatever the log keeps.
+    strict: std::sync::OnceLock<std::sync::Arc<strict::Tripwire>>,
 }
 impl LogHost for StdLogHost {
-    fn event(&self, message: &str) {
-        if let Ok(mut g) = self.inner.lock() {
-            let _ = g.append(message);
+    fn record(&self, record: AuditRecord) {
+        self.metrics.observe(&record);
+        let record = match self.requests.current() {
+            Some(id) if record.request.is_none() => record.with_request(&id),
+            _ => record,
+        };
+        if let Some(tripwire) = self.strict.get() {
+            tripwire.observe(&record);
         }
+        if let Some(policy) = self.policy.get() {
+            if !policy
+                .current()
+                .audits(record.category(), record.severity())
+            {
+                return;
+            }
+        }
+        let record = match self.elevation.as_ref().filter(|s| s.is_active()) {
+            Some(s) => record.with_elevation(&s.id),
+            None => record,
+        };
+        for sink in self.sinks.get().into_iter().flatten() {
+            sink.record(record.clone());
+        }
+        let _ = self.inner.record(record);
+    }
+}
+
+/// Waits for the audit writer to flush when the run ends, however it ends.
+struct FlushAudit(std::sync::Arc<StdLogHost>);
+
+impl Drop for FlushAudit {
+    fn drop(&mut self) {
+        let _ = self.0.inner.flush();
     }
 }
 
-struct StubNetHost {
-    policy: Policy,
+/// NetHost enforcing policy in front of the pooled HTTP transport. The
+/// built-in demo endpoints on example.org are served without a connection.
+struct StdNetHost<'a> {
+    policy: SharedPolicy,
+    limiter: rate_limit::RateLimiter,
+    audit: Auditor<'a>,
+    next_stream: std::sync::atomic::AtomicU64,
+    streams: std::sync::Mutex<std::collections::HashMap<u64, NetStream>>,
+    meter: &'a net_stats::NetMeter,
+    resolver: Box<dyn dns::Resolve>,
+    // mTLS identities by domain, loaded from the secret store on first use
+    // and kept alongside the entry they were loaded for, since a policy
+    // reload may point a domain at a different certificate.
+    identities: std::sync::Mutex<
+        std::collections::HashMap<
+            String,
+            (
+                saf_policy::ClientCert,
+                std::sync::Arc<secrets::ClientIdentity>,
+            ),
+        >,
+    >,
+    transport: http::Transport,
 }
-impl NetHost for StubNetHost {
-    fn get_text(&self, url: &str) -> Result<String, String> {
-        if !self.policy.is_url_allowed(url) {
-            return Err("blocked by policy".to_string());
+
+/// An open streaming response and what it has delivered so far.
+struct NetStream {
+    domain: String,
+    opened: std::time::Instant,
+    bytes: u64,
+    source: StreamSource,
+}
+
+enum StreamSource {
+    Canned(std::collections::VecDeque<Vec<u8>>),
+    Live(Box<dyn std::io::Read + Send>),
+}
+
+/// Largest chunk handed to a component per `next_chunk` call.
+const STREAM_CHUNK_BYTES: usize = 16 * 1024;
+
+impl<'a> StdNetHost<'a> {
+    /// The resolver and connection pool are fixed for the host's lifetime;
+    /// everything else is read from the current policy on each call.
+    fn new(
+        policy: SharedPolicy,
+        audit: Auditor<'a>,
+        meter: &'a net_stats::NetMeter,
+    ) -> Result<Self, String> {
+        let initial = policy.current();
+        Ok(Self {
+            resolver: dns::from_policy(&initial)?,
+            transport: http::Transport::new(&initial.connection_pool),
+            policy,
+            limiter: rate_limit::RateLimiter::default(),
+            audit,
+            next_stream: std::sync::atomic::AtomicU64::new(1),
+            streams: std::sync::Mutex::new(std::collections::HashMap::new()),
+            meter,
+            identities: std::sync::Mutex::new(std::collections::HashMap::new()),
+        })
+    }
+
+    /// The client certificate policy assigns to `domain`, if any. Key
+    /// material stays in the broker; only the fingerprint is audited.
+    fn client_identity(
+        &self,
+        domain: &str,
+    ) -> Result<Option<std::sync::Arc<secrets::ClientIdentity>>, NetError> {
+        let policy = self.policy.current();
+        let Some(cert) = policy.client_certs.get(domain) else {
+            return Ok(None);
+        };
+        let mut identities = self.identities.lock().map_err(|e| e.to_string())?;
+        if let Some((_, id)) = identities.get(domain).filter(|(loaded, _)| loaded == cert) {
+            return Ok(Some(id.clone()));
         }
-        if url == "https://example.org/data.json" {
-            return Ok("{\"example\":true}".to_string());
+        let identity = secrets::SecretStore::new()
+            .and_then(|store| secrets::ClientIdentity::load(&store, &cert.cert, &cert.key))
+            .map_err(|e| Code::NetClientCertFailed.with_message(&e))?;
+        self.audit.event(
+            Code::NetClientCert,
+            AuditEvent::NetClientCert {
+                domain: domain.to_string(),
+                sha256: identity.fingerprint(),
+            },
+        );
+        let identity = std::sync::Arc::new(identity);
+        identities.insert(domain.to_string(), (cert.clone(), identity.clone()));
+        Ok(Some(identity))
+    }
+
+    /// Offline check, mirror rewriting and allowlist, in that order.
+    /// Returns the URL to actually contact and its host.
+    fn admit(&self, url: &str) -> Result<(String, String), NetError> {
+        let policy = self.policy.current();
+        if policy.offline {
+            return Err(NetError::Offline);
         }
-        Err("network not implemented".to_string())
+        // Mirror rewriting happens first so the allowlist applies to the
+        // host actually contacted.
+        let rewritten = self.effective_url(url);
+        if rewritten != url {
+            self.audit.event(
+                Code::NetRewritten,
+                AuditEvent::NetRewritten {
+                    from: self.audit.audited(Capability::Net, url),
+                    to: self.audit.audited(Capability::Net, &rewritten),
+                },
+            );
+        }
+        let domain = url::Url::parse(&rewritten)
+            .ok()
+            .and_then(|u| u.host_str().map(str::to_string))
+            .unwrap_or_default();
+        // The host only issues GETs.
+        self.audit.decide(
+            Capability::Net,
+            &rewritten,
+            policy.check_request("GET", &rewritten),
+            || domain.clone(),
+        )?;
+        Ok((rewritten, domain))
     }
-}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Parse command line arguments
-    let args: Vec<String> = env::args().collect();
-    let mut workspace_id = None;
-    let mut run_component = None;
-    let mut interactive = true;
-
-    let mut i = 1;
-    while i < args.len() {
-        match args[i].as_str() {
-            "--workspace-id" => {
-                if i + 1 < args.len() {
-                    workspace_id = Some(args[i + 1].clone());
-                    i += 2;
-                } else {
-                    eprintln!("--workspace-id requires an argument");
-                    std::process::exit(1);
-                }
-            }
-            "--run-component" => {
-                if i + 1 < args.len() {
-                    run_component = Some(PathBuf::from(&args[i + 1]));
-                    i += 2;
-                } else {
-                    eprintln!("--run-component requires an argument");
-                    std::process::exit(1);
-                }
+    /// Take a request from `domain`'s rate limit. Only called once every
+    /// check, the address check included, has passed, so refused requests
+    /// leave the limit alone.
+    fn throttle(&self, domain: &str) -> Result<(), NetError> {
+        if let Some(limit) = self.policy.current().rate_limits.get(domain) {
+            if let Err(wait) = self
+                .limiter
+                .acquire(domain, limit, std::time::Instant::now())
+            {
+                return Err(NetError::RateLimited {
+                    domain: domain.to_string(),
+                    retry_after_ms: u64::try_from(wait.as_millis()).unwrap_or(u64::MAX),
+                });
             }
-            "--headless" => {
-                interactive = false;
-                i += 1;
+        }
+        Ok(())
+    }
+
+    /// Charge response bytes to `domain`, refusing delivery once the run's
+    /// byte budget would be exceeded.
+    fn charge_response(&self, domain: &str, bytes: u64) -> Result<(), NetError> {
+        if let Some(budget) = self.policy.current().max_net_bytes {
+            if self.meter.total_bytes() + bytes > budget {
+                self.audit.record(
+                    Capability::Net,
+                    domain,
+                    &Denial::new(Code::PolicyNetBudget, "max_net_bytes"),
+                );
+                return Err(Code::PolicyNetBudget
+                    .with_message(&format!("run exceeds max_net_bytes ({budget})"))
+                    .into());
             }
-            "--help" | "-h" => {
-                print_help();
-                return Ok(());
+        }
+        self.meter.record_response(domain, bytes);
+        Ok(())
+    }
+
+    /// Resolve once, validate against the SSRF deny set and pin the address
+    /// the transport will connect to, along with the client certificate for
+    /// the host, if any.
+    fn connect(
+        &self,
+        url: &str,
+    ) -> Result<
+        (
+            ssrf::PinnedTarget,
+            Option<std::sync::Arc<secrets::ClientIdentity>>,
+        ),
+        NetError,
+    > {
+        let parsed =
+            url::Url::parse(url).map_err(|e| Code::NetFailed.with_message(&e.to_string()))?;
+        let target = ssrf::resolve_pinned(&parsed, &self.policy.current(), self.resolver.as_ref())
+            .map_err(|e| match e {
+                ssrf::PinError::Denied(denial, msg) => {
+                    self.audit.record(
+                        Capability::Net,
+                        parsed.host_str().unwrap_or_default(),
+                        &denial,
+                    );
+                    msg
+                }
+                ssrf::PinError::Failed(msg) => msg,
+            })?;
+        self.audit.event(
+            Code::NetResolved,
+            AuditEvent::NetResolved {
+                host: target.host.clone(),
+                via: self.resolver.name().to_string(),
+                addr: target.addr.ip().to_string(),
+            },
+        );
+        let identity = self.client_identity(&target.host)?;
+        Ok((target, identity))
+    }
+}
+impl NetHost for StdNetHost<'_> {
+    fn effective_url(&self, url: &str) -> String {
+        self.policy
+            .current()
+            .rewrite_url(url)
+            .unwrap_or_else(|| url.to_string())
+    }
+    fn rewriting_active(&self) -> bool {
+        !self.policy.current().url_rewrites.is_empty()
+    }
+    fn fetch(&self, url: &str) -> Result<HttpResponse, NetError> {
+        let (url, domain) = self.admit(url)?;
+        if url == "https://example.org/data.json" {
+            self.throttle(&domain)?;
+            let resp = HttpResponse {
+                status: 200,
+                headers: vec![("content-type".to_string(), "application/json".to_string())],
+                body: "{\"example\":true}".to_string(),
+            };
+            // Request bytes are the GET request line; responses count
+            // headers and body.
+            self.meter.record_request("example.org", url.len() as u64);
+            let header_bytes: usize = resp.headers.iter().map(|(k, v)| k.len() + v.len()).sum();
+            self.charge_response("example.org", (header_bytes + resp.body.len()) as u64)?;
+            return Ok(resp);
+        }
+        let (target, identity) = self.connect(&url)?;
+        self.throttle(&domain)?;
+        self.meter.record_request(&target.host, url.len() as u64);
+        let resp = self
+            .transport
+            .fetch(
+                &url,
+                &target,
+                identity.as_deref(),
+                self.policy.current().max_response_bytes,
+            )
+            .inspect_err(|e| {
+                if Code::from_message(e) == Some(Code::PolicySizeLimit) {
+                    self.audit.record(
+                        Capability::Net,
+                        &url,
+                        &Denial::new(Code::PolicySizeLimit, "max_response_bytes"),
+                    );
+                }
+            })?;
+        let header_bytes: usize = resp.headers.iter().map(|(k, v)| k.len() + v.len()).sum();
+        self.charge_response(&target.host, (header_bytes + resp.body.len()) as u64)?;
+        Ok(resp)
+    }
+    fn open_stream(&self, url: &str) -> Result<u64, NetError> {
+        let (url, domain) = self.admit(url)?;
+        let (domain, source) = if url == "https://example.org/events" {
+            self.throttle(&domain)?;
+            let pending = (1..=3)
+                .map(|n| format!("event: tick\ndata: {n}\n\n").into_bytes())
+                .collect();
+            ("example.org".to_string(), StreamSource::Canned(pending))
+        } else {
+            let (target, identity) = self.connect(&url)?;
+            self.throttle(&domain)?;
+            let body = self.transport.open(
+                &url,
+                &target,
+                identity.as_deref(),
+                std::time::Duration::from_secs(self.policy.current().max_stream_secs),
+            )?;
+            (target.host, StreamSource::Live(body))
+        };
+        self.meter.record_request(&domain, url.len() as u64);
+        let stream = self
+            .next_stream
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let mut streams = self.streams.lock().map_err(|e| e.to_string())?;
+        streams.insert(
+            stream,
+            NetStream {
+                domain,
+                opened: std::time::Instant::now(),
+                bytes: 0,
+                source,
+            },
+        );
+        Ok(stream)
+    }
+    fn next_chunk(&self, stream: u64) -> Result<Option<Vec<u8>>, NetError> {
+        let policy = self.policy.current();
+        let mut streams = self.streams.lock().map_err(|e| e.to_string())?;
+        let state = streams
+            .get_mut(&stream)
+            .ok_or_else(|| Code::NetFailed.with_message("no such stream"))?;
+        if state.opened.elapsed().as_secs() >= policy.max_stream_secs {
+            self.audit.record(
+                Capability::Net,
+                &state.domain,
+                &Denial::new(Code::PolicyTimeLimit, "max_stream_secs"),
+            );
+            streams.remove(&stream);
+            return Err(Code::PolicyTimeLimit
+                .with_message("stream exceeds max_stream_secs")
+                .into());
+        }
+        let chunk = match &mut state.source {
+            StreamSource::Canned(pending) => pending.pop_front(),
+            StreamSource::Live(body) => {
+                let mut buf = vec![0; STREAM_CHUNK_BYTES];
+                match body.read(&mut buf) {
+                    Ok(0) => None,
+                    Ok(n) => {
+                        buf.truncate(n);
+                        Some(buf)
+                    }
+                    Err(e) => {
+                        let timed_out = e.kind() == std::io::ErrorKind::TimedOut;
+                        if timed_out {
+                            self.audit.record(
+                                Capability::Net,
+                                &state.domain,
+                                &Denial::new(Code::PolicyTimeLimit, "max_stream_secs"),
+                            );
+                        }
+                        streams.remove(&stream);
+                        return Err(if timed_out {
+                            Code::PolicyTimeLimit.with_message("stream exceeds max_stream_secs")
+                        } else {
+                            Code::NetFailed.with_message(&e.to_string())
+                        }
+                        .into());
+                    }
+                }
             }
-            _ => {
-                eprintln!("Unknown argument: {}", args[i]);
-                print_help();
-                std::process::exit(1);
+        };
+        let Some(chunk) = chunk else {
+            return Ok(None);
+        };
+        state.bytes += chunk.len() as u64;
+        if state.bytes > policy.max_stream_bytes {
+            self.audit.record(
+                Capability::Net,
+                &state.domain,
+                &Denial::new(Code::PolicySizeLimit, "max_stream_bytes"),
+            );
+            streams.remove(&stream);
+            return Err(Code::PolicySizeLimit
+                .with_message("stream exceeds max_stream_bytes")
+                .into());
+        }
+        if let Err(e) = self.charge_response(&state.domain, chunk.len() as u64) {
+            streams.remove(&stream);
+            return Err(e);
+        }
+        Ok(Some(chunk))
+    }
+    fn close_stream(&self, stream: u64) -> Result<u64, NetError> {
+        let mut streams = self.streams.lock().map_err(|e| e.to_string())?;
+        streams
+            .remove(&stream)
+            .map(|s| s.bytes)
+            .ok_or_else(|| Code::NetFailed.with_message("no such stream").into())
+    }
+}
+
+/// Check a WebSocket `url` against the policy, auditing a denial.
+fn admit_ws(policy: &SharedPolicy, audit: &Auditor<'_>, url: &str) -> Result<(), String> {
+    let policy = policy.current();
+    if policy.offline {
+        audit.record(
+            Capability::Ws,
+            url,
+            &Denial::new(Code::NetOffline, "offline"),
+        );
+        return Err(Code::NetOffline.with_message("network access is disabled"));
+    }
+    audit.decide(Capability::Ws, url, policy.check_ws_url(url), || {
+        url::Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(str::to_string))
+            .unwrap_or_default()
+    })
+}
+
+/// The broker's WebSocket host, `none`: the broker has no WebSocket
+/// transport of its own, so every connection the policy admits is refused
+/// as unavailable. A binary embedding the broker registers a real one (see
+/// [`hosts`]).
+struct NoWsHost<'a> {
+    policy: SharedPolicy,
+    audit: Auditor<'a>,
+}
+impl WsHost for NoWsHost<'_> {
+    fn connect(&self, url: &str) -> Result<u64, String> {
+        admit_ws(&self.policy, &self.audit, url)?;
+        Err(Code::NetUnavailable
+            .with_message("no WebSocket transport is configured ([hosts] ws = \"none\")"))
+    }
+    fn send(&self, _conn: u64, _message: &str) -> Result<(), String> {
+        Err(Code::WsUnknownConnection.with_message("no such connection"))
+    }
+    fn receive(&self, _conn: u64) -> Result<Option<String>, String> {
+        Err(Code::WsUnknownConnection.with_message("no such connection"))
+    }
+    fn close(&self, _conn: u64) -> Result<(), String> {
+        Err(Code::WsUnknownConnection.with_message("no such connection"))
+    }
+}
+
+/// A stub WebSocket host, `echo`, for exercising a component's connection
+/// lifecycle in testing: nothing leaves the machine. After the same policy
+/// and size checks as a real host, `wss://example.org/echo` is served by an
+/// in-memory echo and every other URL is refused.
+struct EchoWsHost<'a> {
+    policy: SharedPolicy,
+    audit: Auditor<'a>,
+    next_conn: std::sync::atomic::AtomicU64,
+    conns: std::sync::Mutex<std::collections::HashMap<u64, std::collections::VecDeque<String>>>,
+}
+impl<'a> EchoWsHost<'a> {
+    fn new(policy: SharedPolicy, audit: Auditor<'a>) -> Self {
+        Self {
+            policy,
+            audit,
+            next_conn: std::sync::atomic::AtomicU64::new(1),
+            conns: std::sync::Mutex::new(std::collections::HashMap::new()),
+        }
+    }
+    fn check_size(&self, conn: u64, message: &str) -> Result<(), String> {
+        let limit = self.policy.current().max_ws_message_bytes;
+        if message.len() as u64 > limit {
+            self.audit.record(
+                Capability::Ws,
+                &format!("conn={conn}"),
+                &Denial::new(Code::PolicySizeLimit, "max_ws_message_bytes"),
+            );
+            return Err(Code::PolicySizeLimit
+                .with_message(&format!("message exceeds limit of {limit} bytes")));
+        }
+        Ok(())
+    }
+}
+impl WsHost for EchoWsHost<'_> {
+    fn connect(&self, url: &str) -> Result<u64, String> {
+        admit_ws(&self.policy, &self.audit, url)?;
+        if url != "wss://example.org/echo" {
+            return Err(Code::NetUnavailable
+                .with_message("the echo stub only serves wss://example.org/echo"));
+        }
+        let conn = self
+            .next_conn
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let mut conns = self.conns.lock().map_err(|e| e.to_string())?;
+        conns.insert(conn, std::collections::VecDeque::new());
+        Ok(conn)
+    }
+    fn send(&self, conn: u64, message: &str) -> Result<(), String> {
+        self.check_size(conn, message)?;
+        let mut conns = self.conns.lock().map_err(|e| e.to_string())?;
+        let queue = conns
+            .get_mut(&conn)
+            .ok_or_else(|| Code::WsUnknownConnection.with_message("no such connection"))?;
+        queue.push_back(message.to_string());
+        Ok(())
+    }
+    fn receive(&self, conn: u64) -> Result<Option<String>, String> {
+        let mut conns = self.conns.lock().map_err(|e| e.to_string())?;
+        let queue = conns
+            .get_mut(&conn)
+            .ok_or_else(|| Code::WsUnknownConnection.with_message("no such connection"))?;
+        // `None` would tell the component the peer closed the connection,
+        // and this peer never does.
+        let msg = queue.pop_front().ok_or_else(|| {
+            Code::WsReceive
+                .with_message("no message waiting; the echo stub only echoes what is sent")
+        })?;
+        self.check_size(conn, &msg)?;
+        Ok(Some(msg))
+    }
+    fn close(&self, conn: u64) -> Result<(), String> {
+        let mut conns = self.conns.lock().map_err(|e| e.to_string())?;
+        conns
+            .remove(&conn)
+            .map(|_| ())
+            .ok_or_else(|| Code::WsUnknownConnection.with_message("no such connection"))
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = cli::Cli::parse();
+    if let Some(Command::IsolatedRun) = cli.command {
+        // Without a runtime, whose threads could not be confined.
+        return isolate::child_main().map_err(Into::into);
+    }
+    broker_main(cli)
+}
+
+#[tokio::main]
+async fn broker_main(cli: cli::Cli) -> Result<(), Box<dyn std::error::Error>> {
+    tokio::spawn(shutdown::on_signal());
+    let Some(command) = cli.command else {
+        if cli.run.watch.is_some() {
+            return watch::main(cli.run).map_err(Into::into);
+        }
+        let interactive = !cli.run.headless && !cli.run.stdio_rpc;
+        return run(cli.run, interactive).await.map(drop);
+    };
+    match command {
+        Command::Run(args) if args.watch.is_some() => watch::main(*args),
+        Command::Run(args) => return run(*args, false).await.map(drop),
+        Command::Serve(args) => return serve::main(args).await,
+        Command::Repl(args) => return run(args.into(), false).await.map(drop),
+        Command::Pipeline(command) => return pipeline::main(command).await,
+        Command::Selftest(args) => return selftest::main(args).await,
+        Command::Component(components::ComponentCommand::Run { name, mut run }) => {
+            if run.run_component.is_some() || run.manifest.is_some() || run.watch.is_some() {
+                return Err(
+                    "broker component run takes the component by name, not --run-component, --manifest or --watch"
+                        .into(),
+                );
             }
+            run.run_component = Some(components::installed_path(&name)?);
+            return crate::run(*run, false).await.map(drop);
+        }
+        Command::Workspace(command) => workspace_picker::main(command),
+        Command::Audit(command) => audit::main(command),
+        Command::Policy(command) => match command {
+            PolicyCommand::Check(args) => policy_check::main(args),
+            PolicyCommand::Explain(args) => policy_explain::main(args),
+            PolicyCommand::Simulate(args) => policy_sim::main(args),
+            PolicyCommand::Diff(args) => policy_explain::diff_main(args),
+            PolicyCommand::Keygen(args) => policy_sig::keygen(args),
+            PolicyCommand::Sign(args) => policy_sig::sign_main(args),
+            PolicyCommand::Trust(args) => policy_sig::trust(args),
+        },
+        Command::Component(components::ComponentCommand::Lifecycle(command)) => {
+            lifecycle::main(command)
         }
+        Command::Component(command) => components::main(command),
+        Command::Demo(args) => demo::main(args),
+        Command::Runs(command) => run_log::main(command),
+        Command::Stats(args) => net_stats::main(args),
+        Command::Status(args) => status::main(args),
+        Command::Elevate(args) => elevation::main(args),
+        Command::IsolatedRun => unreachable!("handled above"),
+    }
+    .map_err(Into::into)
+}
+
+/// The policy a run gets with neither `--policy` nor `.saf/policy.toml`.
+fn default_policy() -> Result<Policy, String> {
+    PolicyBuilder::new()
+        .with_allowed_domains(["example.org", "httpbin.org"])
+        .build()
+        .map_err(|issues| PolicyIssue::join(&issues))
+}
+
+/// Open the workspace and run in it: the component given, or the UI when
+/// `interactive`, or the built-in demo. Returns the component's run report.
+async fn run(
+    args: RunArgs,
+    interactive: bool,
+) -> Result<Option<RunReport>, Box<dyn std::error::Error>> {
+    let RunArgs {
+        workspace_id,
+        workspace: workspace_path,
+        mounts,
+        mut run_component,
+        manifest: manifest_path,
+        policy: policy_path,
+        offline,
+        policy_dry_run: dry_run,
+        require_signed_policy: require_signed,
+        strict,
+        profile,
+        trial: trial_runs,
+        accept_narrowing,
+        headless: _,
+        json,
+        stdio_rpc,
+        watch: _,
+        replay: _,
+        inherit_stdio,
+        isolate,
+        record_calls,
+        replay_calls,
+        input,
+        args: input_args,
+        config: config_overrides,
+        stop,
+        schedule,
+        pipeline,
+        repl,
+        hosts,
+    } = args;
+    // Stdout carries JSON only; status lines go to stderr instead.
+    let quiet = json || stdio_rpc;
+
+    // The manifest is read once, so the bytes whose signature is checked
+    // are the bytes that run.
+    let manifest_bytes = match &manifest_path {
+        Some(p) => Some(RunManifest::read(p)?),
+        None => None,
+    };
+    let manifest = match (&manifest_path, &manifest_bytes) {
+        (Some(p), Some(bytes)) => Some(RunManifest::parse(p, bytes)?),
+        _ => None,
+    };
+    if let (Some(m), Some(p)) = (&manifest, &manifest_path) {
+        run_component = Some(m.component_path(p));
     }
 
     // Initialize workspace store
@@ -167,7 +965,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .into());
         }
 
-        println!("Restored workspace: {}", path.display());
+        status!(quiet, "Restored workspace: {}", path.display());
+        path
+    } else if let Some(path) = workspace_path {
+        // Given, where a desktop would show the folder chooser.
+        let path = path
+            .canonicalize()
+            .map_err(|e| format!("--workspace {}: {e}", path.display()))?;
+        if !path.is_dir() {
+            return Err(format!("--workspace {}: not a directory", path.display()).into());
+        }
         path
     } else if interactive {
         // Pick new workspace interactively
@@ -181,86 +988,888 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .save_workspace(&id, &path, &token)
             .map_err(|e| format!("Failed to save workspace: {}", e))?;
 
-        println!("Selected workspace: {} (ID: {})", path.display(), id);
+        status!(quiet, "Selected workspace: {} (ID: {})", path.display(), id);
         path
     } else {
         // Use current directory in headless mode
         env::current_dir().unwrap_or(PathBuf::from("."))
     };
 
+    // Runs in a workspace take turns, whichever process starts them.
+    let _turn = runs::take_turn(&workspace, || {
+        status!(
+            quiet,
+            "Waiting for the run in progress in {} to end",
+            workspace.display()
+        )
+    })?;
+
     // Initialize audit log
     let audit_path = workspace.join(".saf").join("audit.log");
     let audit_log =
-        AuditLog::new(&audit_path).map_err(|e| format!("Failed to initialize audit log: {}", e))?;
+        audit::writer(&audit_path).map_err(|e| format!("Failed to initialize audit log: {}", e))?;
+    // Dropped after the writer is flushed, so its last pass ships the end
+    // of the run.
+    let _audit_shipper = audit::shipper(&audit_path)
+        .map_err(|e| format!("Failed to start audit shipping: {}", e))?;
+
+    let elevation_store = elevation::ElevationStore::new()?;
+    // Shared with the policy watcher thread.
+    let log = std::sync::Arc::new(StdLogHost {
+        inner: audit_log,
+        elevation: elevation_store.active(),
+        policy: std::sync::OnceLock::new(),
+        metrics: std::sync::Arc::default(),
+        requests: requests::Requests::default(),
+        sinks: std::sync::OnceLock::new(),
+        strict: std::sync::OnceLock::new(),
+    });
+    let _flush_audit = FlushAudit(log.clone());
+    // Dropped first, so a shutdown's entry is written before the flush.
+    let managed = stop.is_some();
+    let stop = stop.unwrap_or_default();
+    let _registration = shutdown::register(stop.clone(), {
+        let log = log.clone();
+        Box::new(move |reason, drained| {
+            log.record(AuditRecord::new(
+                Code::BrokerShutdown,
+                AuditEvent::BrokerShutdown {
+                    reason: reason.as_str().to_string(),
+                    drained,
+                },
+            ));
+            let _ = log.inner.flush();
+        })
+    });
+    let config_path = config::BrokerConfig::path()?;
+    let broker_config = config::BrokerConfig::load(&config_path)?;
+    let mut config_values = broker_config.config.clone();
+    config_values.extend(config_overrides);
+    let hosts = hosts.unwrap_or_else(|| std::sync::Arc::new(hosts::HostRegistry::builtin()));
+    let _ = log
+        .sinks
+        .set(hosts.logs(&broker_config.hosts.log, &workspace)?);
+    if let Some(config) = &broker_config.metrics {
+        let addr = metrics::serve(config, log.metrics.clone())
+            .map_err(|e| format!("{}: metrics: {}", config_path.display(), e))?;
+        status!(quiet, "Serving metrics on http://{}/metrics", addr);
+    }
+
+    log.record(AuditRecord::new(
+        Code::BrokerStart,
+        AuditEvent::BrokerLifecycle {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        },
+    ));
+    if let Some(scheduled) = schedule {
+        log.record(AuditRecord::new(
+            Code::ScheduledRun,
+            AuditEvent::ScheduledRun {
+                schedule: scheduled.name.clone(),
+                due_unix: scheduled.due_unix,
+                overlapped: Some(scheduled.overlapped).filter(|n| *n > 0),
+                missed: Some(scheduled.missed).filter(|n| *n > 0),
+            },
+        ));
+    }
+    if let Some(stage) = &pipeline {
+        log.record(AuditRecord::new(
+            Code::PipelineStage,
+            AuditEvent::PipelineStage {
+                pipeline: stage.pipeline.clone(),
+                pipeline_id: stage.pipeline_id.clone(),
+                stage: stage.stage.clone(),
+                position: stage.position,
+                stages: stage.stages,
+            },
+        ));
+    }
 
-    let log = StdLogHost {
-        inner: std::sync::Mutex::new(audit_log),
+    // A manifest carries its own policy snapshot so the run is reproducible.
+    // Otherwise `--policy` wins over the workspace's `.saf/policy.toml`, and
+    // the built-in default applies when neither exists.
+    let workspace_policy = workspace.join(".saf").join("policy.toml");
+    let policy_file = policy_path.or_else(|| workspace_policy.exists().then_some(workspace_policy));
+    let verifier = policy_sig::Verifier::load(&policy_sig::Verifier::keys_path()?, require_signed)?;
+    let check_signature = |path: &Path, content: &[u8]| match verifier.verify_file(path, content) {
+        Ok(Some(key)) => {
+            log.record(AuditRecord::new(
+                Code::PolicySignatureVerified,
+                AuditEvent::PolicySignature {
+                    source: path.display().to_string(),
+                    key: Some(key),
+                    error: None,
+                },
+            ));
+            Ok(())
+        }
+        Ok(None) => Ok(()),
+        Err(e) => {
+            log.record(
+                AuditRecord::new(
+                    Code::PolicySignatureRejected,
+                    AuditEvent::PolicySignature {
+                        source: path.display().to_string(),
+                        key: None,
+                        error: Some(e.clone()),
+                    },
+                )
+                .with_outcome(Outcome::Denied),
+            );
+            Err(e)
+        }
+    };
+    let mut policy_file_sha256 = None;
+    let base_policy = match (&manifest, &policy_file) {
+        (Some(m), _) => {
+            // The manifest carries the policy, so the manifest is what gets
+            // signed.
+            if let (Some(p), Some(bytes)) = (&manifest_path, &manifest_bytes) {
+                check_signature(p, bytes)?;
+            }
+            m.policy.clone()
+        }
+        (None, Some(p)) => {
+            // Verify and parse the same bytes.
+            let content = std::fs::read_to_string(p)
+                .map_err(|e| format!("cannot read policy {}: {}", p.display(), e))?;
+            check_signature(p, content.as_bytes())?;
+            // Bases named by `extends` need signatures of their own.
+            let policy = Policy::from_file_content_checked(p, &content, &check_signature)?;
+            for issue in policy.issues().iter().filter(|i| !i.is_error()) {
+                eprintln!("warning: {}: {issue}", p.display());
+            }
+            let sha256 = run_manifest::sha256_hex(content.as_bytes());
+            log.record(AuditRecord::new(
+                Code::PolicyLoaded,
+                AuditEvent::PolicyLoaded {
+                    source: p.display().to_string(),
+                    sha256: sha256.clone(),
+                },
+            ));
+            policy_file_sha256 = Some(sha256);
+            policy
+        }
+        (None, None) if require_signed => {
+            return Err(
+                "--require-signed-policy needs a policy file (--policy or .saf/policy.toml)".into(),
+            )
+        }
+        (None, None) => default_policy()?,
     };
 
-    let fs = StdFsHost {
-        root: workspace.clone(),
+    // The component is read once, so the bytes hashed for its identity
+    // and its policy are the bytes that run.
+    let component_bytes = match &run_component {
+        Some(comp) => Some(
+            std::fs::read(comp).map_err(|e| format!("failed to open {}: {}", comp.display(), e))?,
+        ),
+        None => None,
     };
+    let component_sha256 = component_bytes.as_deref().map(run_manifest::sha256_hex);
 
-    let policy = Policy::new()
-        .with_allowed_domains(vec!["example.org".to_string(), "httpbin.org".to_string()]);
+    let declared = match &run_component {
+        Some(comp) => component_manifest::ComponentManifest::load_for(comp)?,
+        None => None,
+    };
 
-    let net = StubNetHost { policy };
+    // Components run without a manifest get the grant keyed by their
+    // identity, or nothing at all.
+    let component_ids = match (&manifest, &component_sha256) {
+        (None, Some(sha256)) => {
+            let sha256 = sha256.clone();
+            let ids = components::Registry::dir()
+                .and_then(|d| components::Registry::load(&d))
+                .unwrap_or_default()
+                .policy_ids(&sha256);
+            let (_, key) = base_policy.for_component(&ids);
+            log.record(AuditRecord::new(
+                Code::PolicySelected,
+                AuditEvent::PolicySelected {
+                    sha256,
+                    rule: key.as_deref().unwrap_or("restrictive-default").to_string(),
+                },
+            ));
+            if key.is_none() {
+                status!(
+                    quiet,
+                    "No policy entry for this component; running with the restrictive default"
+                );
+            }
+            Some(ids)
+        }
+        _ => None,
+    };
 
-    let ctx = Context {
-        fs: &fs,
-        net: &net,
-        log: &log,
+    // A capability manifest beside the component must be satisfiable by the
+    // policy, and then limits the grant to what it requests.
+    let (capabilities, capabilities_path) = match &run_component {
+        Some(comp) => component_manifest::capabilities_for(comp, declared.as_ref())?.unzip(),
+        None => (None, None),
     };
+    let interfaces = capabilities.as_ref().map(|c| c.interfaces());
+    let checked_capabilities = capabilities.clone();
 
-    log.event("broker.start");
+    // Trial mode: observe usage of a broad grant and propose narrowing it.
+    let trial_path = trial::trial_path(&workspace);
+    let mut trial_state = TrialState::load(&trial_path)?;
+    if let Some(runs) = trial_runs {
+        trial_state = Some(TrialState::new(runs));
+        log.record(AuditRecord::new(
+            Code::PolicyTrialStart,
+            AuditEvent::PolicyTrial {
+                runs: Some(runs.into()),
+            },
+        ));
+    }
+    if accept_narrowing {
+        // Accepting a narrowing changes the grant every later run gets.
+        if let Err(e) = elevation_store.require("accepting a narrowed grant") {
+            log.record(
+                AuditRecord::new(
+                    Code::AuthElevationRequired,
+                    AuditEvent::Elevation {
+                        session: None,
+                        expires_ms: None,
+                        operation: Some("accept_narrowing".to_string()),
+                    },
+                )
+                .with_outcome(Outcome::Denied),
+            );
+            return Err(e.into());
+        }
+        let state = trial_state.as_mut().ok_or("no trial to accept")?;
+        let narrowing = state
+            .accept()
+            .ok_or("trial has no narrowing proposal to accept")?;
+        log.record(AuditRecord::new(
+            Code::PolicyNarrowed,
+            AuditEvent::PolicyNarrowed {
+                domains: narrowing.allowed_domains.clone(),
+                paths: narrowing.allowed_paths.clone(),
+                source: "cli".to_string(),
+            },
+        ));
+        state.save(&trial_path)?;
+        status!(quiet, "Narrowed grant accepted");
+    }
+    let narrowing = match manifest {
+        Some(_) => None,
+        None => trial_state.as_ref().and_then(|t| t.accepted.clone()),
+    };
 
-    // Handle component execution
-    if let Some(comp_path) = run_component {
-        #[cfg(feature = "wasmtime-host")]
-        {
-            let core_ctx = wasmtime_host::CoreCtx { ctx };
-            wasmtime_host::run_component(&comp_path, core_ctx)
-                .map_err(|e| format!("Component execution failed: {}", e))?;
-            return Ok(());
+    let mounts = mounts::Mounts::new(workspace.clone(), mounts)?;
+    for mount in mounts.mounts() {
+        log.record(AuditRecord::new(
+            Code::FsMount,
+            AuditEvent::FsMount {
+                name: mount.name.clone(),
+                path: mount.path.display().to_string(),
+                read_only: mount.read_only,
+            },
+        ));
+    }
+
+    // Everything applied on top of the policy file, so a reload yields the
+    // same kind of grant as startup did.
+    let restrict = mounts.clone();
+    let derive = move |base: &Policy| {
+        let mut policy = match &component_ids {
+            Some(ids) => base.for_component(ids).0,
+            None => base.clone(),
+        };
+        // The flag can only tighten a policy, so it also applies over manifests.
+        policy.offline |= offline;
+        policy.strict |= strict;
+        if let Some(n) = &narrowing {
+            policy = policy.narrowed(n);
         }
-        #[cfg(not(feature = "wasmtime-host"))]
-        {
-            return Err(
-                "--run-component requires building with the 'wasmtime-host' feature".into(),
+        if let Some(caps) = &capabilities {
+            policy = caps.narrow(&policy);
+        }
+        restrict.restrict(&mut policy);
+        policy
+    };
+    let policy = SharedPolicy::new(derive(&base_policy));
+    let _ = log.policy.set(policy.clone());
+    if let (Some(caps), Some(bytes), Some(caps_path)) =
+        (&checked_capabilities, &component_bytes, &capabilities_path)
+    {
+        let imports = components::component_imports(bytes)?;
+        // Narrowing keeps every granted scope, so the narrowed policy
+        // reports the same gaps as the one it came from.
+        let unsatisfied = caps.unsatisfied(&imports, &policy.current());
+        if !unsatisfied.is_empty() {
+            log.record(
+                AuditRecord::new(
+                    Code::ComponentCapabilitiesUnsatisfied,
+                    AuditEvent::ComponentCapabilities {
+                        interfaces: Vec::new(),
+                        missing: unsatisfied.iter().map(|u| u.what.clone()).collect(),
+                    },
+                )
+                .with_outcome(Outcome::Denied),
             );
+            let reasons: Vec<String> = unsatisfied.iter().map(ToString::to_string).collect();
+            return Err(Code::ComponentCapabilitiesUnsatisfied
+                .with_message(&format!(
+                    "{} cannot be satisfied: {}",
+                    caps_path.display(),
+                    reasons.join("; ")
+                ))
+                .into());
         }
+        log.record(AuditRecord::new(
+            Code::ComponentCapabilities,
+            AuditEvent::ComponentCapabilities {
+                interfaces: caps.interfaces().into_iter().collect(),
+                missing: Vec::new(),
+            },
+        ));
+    }
+    if policy.current().offline {
+        log.record(AuditRecord::new(
+            Code::NetOffline,
+            AuditEvent::NetOffline {
+                source: "startup".to_string(),
+            },
+        ));
+        status!(quiet, "Offline mode: network access is disabled");
     }
+    let _watcher = match (&manifest, policy_file, policy_file_sha256) {
+        (None, Some(path), Some(sha256)) => Some(policy_watch::PolicyWatcher::spawn(
+            path,
+            sha256,
+            policy.clone(),
+            log.clone(),
+            verifier.clone(),
+            Box::new(derive),
+        )),
+        _ => None,
+    };
+    let tracker = trial::Tracker::new(trial_state.filter(TrialState::is_active));
+
+    // Traffic and operations are attributed to the component: its file's
+    // stem, the file's hash, this run and what its manifest declares. The
+    // built-in demo runs as the broker itself.
+    let component_name = run_component
+        .as_deref()
+        .and_then(Path::file_stem)
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "broker".to_string());
+    let mut component = match &component_sha256 {
+        Some(sha256) => ComponentIdentity::new(&component_name)
+            .with_sha256(sha256)
+            .with_run_id(&runs::new_run_id()),
+        None => ComponentIdentity::new(&component_name),
+    };
+    if let Some(d) = &declared {
+        component = component.with_declared(&d.version, &d.publisher);
+    }
+    let asker = ask::Asker::new(
+        Box::new(ask::TerminalPrompt),
+        &*log,
+        &component,
+        ask::Remembered::path().ok(),
+    );
+    let audit = Auditor {
+        log: &*log,
+        policy: &policy,
+        component: &component,
+        ask: &asker,
+        dry_run,
+    };
+    if dry_run {
+        status!(
+            quiet,
+            "Policy dry run: denials are logged as policy.would_deny, not enforced"
+        );
+    }
+
+    let meter = net_stats::NetMeter::default();
+    let env = hosts::HostEnv {
+        mounts: &mounts,
+        policy: &policy,
+        audit,
+        meter: &meter,
+    };
+    let fs = hosts.fs(&broker_config.hosts.fs, &env)?;
+    let net = hosts.net(&broker_config.hosts.net, &env)?;
+    let ws = hosts.ws(&broker_config.hosts.ws, &env)?;
+
+    // Host calls are recorded to a trace, or answered from one, beneath
+    // everything else that sees them.
+    let trace = match (&record_calls, &replay_calls) {
+        (Some(_), _) | (_, Some(_)) if run_component.is_none() => {
+            return Err("--record-calls and --replay-calls need a component to run".into());
+        }
+        (Some(path), _) => {
+            let seed = manifest
+                .as_ref()
+                .and_then(|m| m.rng_seed)
+                .unwrap_or_else(|| uuid::Uuid::new_v4().as_u64_pair().0);
+            Some((
+                "record",
+                path,
+                trace::Trace::record(path, component.sha256.as_deref(), seed)?,
+            ))
+        }
+        (None, Some(path)) => {
+            let trace = trace::Trace::replay(path)?;
+            if let trace::Trace::Replay(r) = &trace {
+                if r.sha256.is_some() && r.sha256 != component.sha256 {
+                    status!(
+                        quiet,
+                        "warning: {} was recorded from a different build of the component",
+                        path.display()
+                    );
+                }
+            }
+            Some(("replay", path, trace))
+        }
+        (None, None) => None,
+    };
+    let trace = trace.map(|(mode, path, trace)| {
+        log.record(
+            AuditRecord::new(
+                Code::ComponentTrace,
+                AuditEvent::ComponentTrace {
+                    run_id: component.run_id.clone().unwrap_or_default(),
+                    mode: mode.to_string(),
+                    path: path.display().to_string(),
+                },
+            )
+            .with_identity(&component),
+        );
+        std::sync::Arc::new(trace)
+    });
+    let traced = trace.as_deref().map(|trace| {
+        isolate::Forwarding(trace::Traced {
+            fs: &*fs,
+            net: &*net,
+            ws: &*ws,
+            trace,
+        })
+    });
+    let traced_log = trace.as_deref().map(|trace| trace::TracedLog {
+        inner: &*log,
+        trace,
+    });
+    let (inner_fs, inner_net, inner_ws): (&dyn FsHost, &dyn NetHost, &dyn WsHost) = match &traced {
+        Some(traced) => (traced, traced, traced),
+        None => (&*fs, &*net, &*ws),
+    };
+
+    let tracking_fs = trial::TrackingFs {
+        inner: inner_fs,
+        tracker: &tracker,
+    };
+    let tracking_net = trial::TrackingNet {
+        inner: inner_net,
+        tracker: &tracker,
+    };
+    let tracking_ws = trial::TrackingWs {
+        inner: inner_ws,
+        tracker: &tracker,
+    };
+
+    let usage = saf_core::Usage::default();
+    let ctx = Context {
+        fs: &tracking_fs,
+        net: &tracking_net,
+        ws: &tracking_ws,
+        log: match &traced_log {
+            Some(traced) => traced,
+            None => &*log,
+        },
+        policy: &policy,
+        component: &component,
+        dry_run,
+        usage: &usage,
+    };
+
+    let net_stats_path = net_stats::stats_path(&workspace);
 
-    // Launch UI or run demo
-    if interactive {
+    let result = if stdio_rpc {
+        let session = rpc::Session {
+            ctx,
+            requests: &log.requests,
+            audit: &log.inner,
+            workspace: &workspace,
+            profile: profile.is_some(),
+            run_config: &broker_config.run,
+            config: &config_values,
+        };
+        let output: rpc::Output = std::sync::Arc::new(std::sync::Mutex::new(std::io::stdout()));
+        rpc::serve(&session, std::io::stdin().lock(), &output)
+            .map(|()| None)
+            .map_err(Into::into)
+    } else if repl {
+        let prompt = std::io::IsTerminal::is_terminal(&std::io::stdin());
+        repl::serve(
+            &ctx,
+            &log.requests,
+            std::io::stdin().lock(),
+            std::io::stdout(),
+            prompt,
+        )
+        .map(|()| None)
+        .map_err(Into::into)
+    } else if let (Some(comp_path), Some(bytes)) = (run_component, component_bytes) {
+        // Handle component execution
+        if let (Some(m), Some(p)) = (&manifest, &manifest_path) {
+            m.verify(p, &bytes, &workspace)?;
+        }
+        let sysinfo = sysinfo::SysInfo::collect(&policy.current());
+        let core = wasmtime_host::CoreCtx {
+            ctx,
+            requests: &log.requests,
+        };
+        if !json {
+            let run_id = component.run_id.as_deref().unwrap_or_default();
+            println!("run id: {run_id} (follow with `broker runs tail {run_id} --follow`)");
+        }
+        // Runs `broker serve` manages go on until they are stopped.
+        let timeout = if managed {
+            None
+        } else {
+            broker_config.run.timeout(&component_name)
+        };
+        let exceeded = declared
+            .as_ref()
+            .map_or_else(Vec::new, |d| d.exceeded_hints(&policy.current(), timeout));
+        if !exceeded.is_empty() {
+            status!(
+                quiet,
+                "The component's manifest expects more than this run allows: {}",
+                exceeded.join(", ")
+            );
+        }
+        let strict = policy.current().strict.then(|| {
+            status!(
+                quiet,
+                "Strict mode: the run ends at the component's first denial"
+            );
+            let run_id = component.run_id.as_deref().unwrap_or_default();
+            let tripwire = std::sync::Arc::new(strict::Tripwire::new(run_id, stop.clone()));
+            let _ = log.strict.set(tripwire.clone());
+            tripwire
+        });
+        let options = wasmtime_host::RunOptions {
+            sysinfo,
+            interfaces,
+            timeout,
+            stop: Some(stop),
+            strict,
+            inherit_stdio,
+            isolate: isolate || broker_config.run.isolate,
+            sandbox: broker_config.run.sandbox,
+            input,
+            args: input_args,
+            config: config::granted(&config_values, &policy.current()),
+            trace: trace.clone(),
+            ..wasmtime_host::RunOptions::default()
+        };
+        let profiled = profile.is_some();
+        execute_component(
+            &workspace, &comp_path, &bytes, core, manifest, profiled, options,
+        )
+        .await
+        .and_then(|(report, path)| {
+            if let Some(Some(out)) = &profile {
+                if export_profile(&workspace, &report.run_id, out)? {
+                    status!(quiet, "profile: {}", out.display());
+                }
+            }
+            match pipeline {
+                // The pipeline reports its stages, failed or not.
+                Some(_) => Ok(Some(report)),
+                None => print_run(&report, &path, json).map(|()| Some(report)),
+            }
+        })
+    } else if interactive {
+        // Launch UI or run demo
         #[cfg(feature = "ui")]
         {
-            launch_ui(workspace, ctx).await?;
+            launch_ui(workspace, ctx).await.map(|()| None)
         }
         #[cfg(not(feature = "ui"))]
         {
-            run_demo(workspace, ctx).await?;
+            run_demo(workspace, ctx, &log.requests).await.map(|()| None)
         }
     } else {
-        run_demo(workspace, ctx).await?;
+        run_demo(workspace, ctx, &log.requests).await.map(|()| None)
+    };
+
+    let traffic = meter.snapshot();
+    if !traffic.is_empty() {
+        let mut stats = net_stats::NetStats::load(&net_stats_path)?;
+        stats.merge(&component_name, &traffic);
+        stats.save(&net_stats_path)?;
     }
 
-    Ok(())
+    if let Some(mut state) = tracker.into_inner() {
+        state.finish_run();
+        state.proposed = state.proposal(&policy.current());
+        state.save(&trial_path)?;
+        if let Some(n) = &state.proposed {
+            log.record(AuditRecord::new(
+                Code::PolicyTrialComplete,
+                AuditEvent::PolicyTrial { runs: None },
+            ));
+            status!(
+                quiet,
+                "Trial complete. Usage suggests narrowing the grant to:"
+            );
+            status!(quiet, "  domains: {}", n.allowed_domains.join(", "));
+            if !n.allowed_paths.is_empty() {
+                status!(quiet, "  paths:   {}", n.allowed_paths.join(", "));
+            }
+            status!(quiet, "Re-run with --accept-narrowing to apply it.");
+        }
+    }
+
+    result
 }
 
-fn print_help() {
-    println!("Secure App Framework Broker");
-    println!();
-    println!("USAGE:");
-    println!("    broker [OPTIONS]");
-    println!();
-    println!("OPTIONS:");
-    println!("    --workspace-id <ID>    Restore a previously saved workspace");
-    println!("    --run-component <PATH> Execute a WASM component");
-    println!("    --headless             Run without UI");
-    println!("    --help, -h             Show this help message");
-    println!();
-    println!("Without arguments, launches the interactive workspace picker.");
+/// Run a component once and record a report under `.saf/runs/<id>/`,
+/// returning it and where it was written. `bytes` are the component, read
+/// from `comp_path` once by the caller. The run's seed, profile and log
+/// locations and its fuel budget in `options` are filled in here, and a
+/// manifest's input replaces any given.
+async fn execute_component(
+    workspace: &Path,
+    comp_path: &Path,
+    bytes: &[u8],
+    core: wasmtime_host::CoreCtx<'_>,
+    manifest: Option<RunManifest>,
+    profile: bool,
+    options: wasmtime_host::RunOptions,
+) -> Result<(RunReport, PathBuf), Box<dyn std::error::Error>> {
+    let ctx = &core.ctx;
+    let component_sha256 = match &ctx.component.sha256 {
+        Some(sha256) => sha256.clone(),
+        None => run_manifest::sha256_hex(bytes),
+    };
+    let run_id = ctx
+        .component
+        .run_id
+        .clone()
+        .unwrap_or_else(runs::new_run_id);
+    let (input, args) = match &manifest {
+        Some(m) => (
+            m.input.as_deref().map(cli::parse_input).transpose()?,
+            m.args
+                .iter()
+                .map(|a| cli::parse_arg(a))
+                .collect::<Result<_, _>>()?,
+        ),
+        None => (options.input.clone(), options.args.clone()),
+    };
+    let options = wasmtime_host::RunOptions {
+        rng_seed: match &options.trace {
+            Some(trace) => Some(trace.rng_seed()),
+            None => manifest.as_ref().and_then(|m| m.rng_seed),
+        },
+        input,
+        args,
+        profile_dir: profile.then(|| runs::run_dir(workspace, &run_id).join("profile")),
+        log_path: Some(run_log::log_path(workspace, &run_id)),
+        max_fuel: ctx.policy.current().max_fuel,
+        memory: ctx.policy.current().memory,
+        ..options
+    };
+
+    ctx.audit(
+        Code::ComponentStart,
+        AuditEvent::ComponentStart {
+            run_id: run_id.clone(),
+            sha256: component_sha256.clone(),
+        },
+    );
+    let started_unix = runs::now_unix_seconds();
+    let mut finished = if options.isolate {
+        isolate::run_component(comp_path, bytes, core.clone(), &options).await
+    } else {
+        wasmtime_host::run_component(comp_path, bytes, core.clone(), &options).await
+    };
+    // A replay that strayed from its trace, or a recording that could not
+    // be written, fails the run.
+    if let Some(Err(e)) = options.trace.as_deref().map(trace::Trace::finish) {
+        match &mut finished {
+            Ok(finished) => {
+                finished.output = Err(match &finished.output {
+                    Ok(_) => e,
+                    Err(guest) => format!("{guest} ({e})"),
+                })
+            }
+            Err(error) => *error = format!("{error} ({e})"),
+        }
+    }
+    // Read before the records below, which may themselves be denials.
+    if let Some(violation) = options
+        .strict
+        .as_deref()
+        .and_then(strict::Tripwire::violation)
+    {
+        let error = strict::error(&violation);
+        match &mut finished {
+            Ok(finished) => finished.output = Err(error),
+            Err(e) => *e = error,
+        }
+        ctx.log.record(violation.with_identity(ctx.component));
+    }
+    let (result, resources) = match finished {
+        Ok(finished) => {
+            if let (true, Some(timeout)) = (finished.timed_out, options.timeout) {
+                ctx.log.record(
+                    AuditRecord::new(
+                        Code::ComponentTimedOut,
+                        AuditEvent::ComponentTimedOut {
+                            run_id: run_id.clone(),
+                            timeout_secs: timeout.as_secs(),
+                        },
+                    )
+                    .with_identity(ctx.component)
+                    .with_outcome(Outcome::Failed),
+                );
+            }
+            if let Some(hit) = finished.limit {
+                ctx.log.record(
+                    AuditRecord::new(
+                        hit.code(),
+                        AuditEvent::LimitExceeded {
+                            run_id: run_id.clone(),
+                            limit: hit.limit.to_string(),
+                            value: hit.value,
+                        },
+                    )
+                    .with_identity(ctx.component)
+                    .with_outcome(Outcome::Denied),
+                );
+            }
+            let transfer = ctx.usage.transfer();
+            let resources = runs::Resources {
+                fuel_consumed: finished.fuel_consumed,
+                peak_memory_bytes: finished.peak_memory_bytes,
+                host_calls: finished.host_calls,
+                bytes_read: transfer.read,
+                bytes_written: transfer.written,
+                bytes_fetched: transfer.fetched,
+            };
+            ctx.audit(
+                Code::ComponentResources,
+                AuditEvent::ComponentResources {
+                    run_id: run_id.clone(),
+                    fuel_consumed: resources.fuel_consumed,
+                    peak_memory_bytes: resources.peak_memory_bytes,
+                    host_calls: resources.total_host_calls(),
+                    bytes_read: resources.bytes_read,
+                    bytes_written: resources.bytes_written,
+                    bytes_fetched: resources.bytes_fetched,
+                },
+            );
+            (finished.output, Some(resources))
+        }
+        Err(e) => (Err(e), None),
+    };
+    let fuel_consumed = resources.as_ref().map(|r| r.fuel_consumed);
+    let outcome = match &result {
+        Ok(output) => RunOutcome::Ok {
+            output: output.clone(),
+        },
+        Err(e) => RunOutcome::Failed { error: e.clone() },
+    };
+    ctx.log.record(
+        AuditRecord::new(
+            Code::ComponentFinish,
+            AuditEvent::ComponentFinish {
+                run_id: run_id.clone(),
+                fuel_consumed,
+            },
+        )
+        .with_identity(ctx.component)
+        .with_outcome(if result.is_ok() {
+            Outcome::Ok
+        } else {
+            Outcome::Failed
+        }),
+    );
+    run_log::RunLog::open(&run_log::log_path(workspace, &run_id))?.append(
+        &run_log::RunLogEntry::Log {
+            ts_ms: run_log::now_ms(),
+            message: match &result {
+                Ok(_) => "run finished".to_string(),
+                Err(e) => format!("run failed: {e}"),
+            },
+        },
+    )?;
+
+    let report = RunReport {
+        run_id,
+        component: comp_path.display().to_string(),
+        component_sha256,
+        started_unix,
+        finished_unix: runs::now_unix_seconds(),
+        outcome,
+        fuel_consumed,
+        resources,
+        manifest,
+    };
+    let report_path = report.write(workspace)?;
+    Ok((report, report_path))
+}
+
+/// Print how a run went, as its report in JSON or as where the report is
+/// and what the component returned; a failed run is an error either way.
+/// Copy the profile of the run `run_id` to `out`, and the time its host
+/// calls took beside it, for `--profile PATH`; false if the run, failing
+/// before the guest was called, has none.
+fn export_profile(workspace: &Path, run_id: &str, out: &Path) -> Result<bool, String> {
+    let dir = runs::run_dir(workspace, run_id).join("profile");
+    let copy = |from: &str, to: &Path| {
+        std::fs::copy(dir.join(from), to)
+            .map(|_| ())
+            .map_err(|e| format!("profile: {}: {}", to.display(), e))
+    };
+    if !dir.join("guest.json").exists() {
+        return Ok(false);
+    }
+    copy("guest.json", out)?;
+    copy(
+        "host_calls.folded",
+        &out.with_extension("host_calls.folded"),
+    )?;
+    Ok(true)
+}
+
+fn print_run(
+    report: &RunReport,
+    path: &Path,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(report)?);
+    } else {
+        println!("run report: {}", path.display());
+        if let Some(fuel) = report.fuel_consumed {
+            println!("fuel consumed: {fuel}");
+        }
+        if let Some(resources) = &report.resources {
+            println!("resources: {}", resources.summary());
+        }
+    }
+    match &report.outcome {
+        RunOutcome::Ok { output } => {
+            if !json {
+                println!("component.start: {}", output);
+            }
+            Ok(())
+        }
+        RunOutcome::Failed { error } => {
+            Err(format!("Component execution failed: {}", error).into())
+        }
+    }
 }
 
 #[cfg(feature = "ui")]
@@ -273,9 +1882,18 @@ async fn launch_ui(workspace: PathBuf, ctx: Context<'_>) -> Result<(), Box<dyn s
     Ok(())
 }
 
-async fn run_demo(workspace: PathBuf, ctx: Context<'_>) -> Result<(), Box<dyn std::error::Error>> {
+async fn run_demo(
+    workspace: PathBuf,
+    ctx: Context<'_>,
+    requests: &requests::Requests,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let run_id = ctx.component.run_id.as_deref();
+
     // Demo: list workspace root
-    match core_list_dir(&ctx, "") {
+    let request = requests.begin(run_id);
+    let listed = core_list_dir(&ctx, "");
+    requests.end();
+    match listed {
         Ok(entries) => {
             println!(
                 "workspace: {} ({} entries)",
@@ -286,13 +1904,24 @@ async fn run_demo(workspace: PathBuf, ctx: Context<'_>) -> Result<(), Box<dyn st
                 println!("  {}", entry);
             }
         }
-        Err(e) => eprintln!("list_dir error: {}", e),
+        Err(e) => eprintln!(
+            "list_dir error [{}]: {}",
+            e.code(),
+            requests::traced(&e, &request)
+        ),
     }
 
     // Demo: try a fetch to allowed example URL
-    match fetch_json(&ctx, "https://httpbin.org/json") {
+    let request = requests.begin(run_id);
+    let fetched = fetch_json(&ctx, "https://httpbin.org/json");
+    requests.end();
+    match fetched {
         Ok(body) => println!("fetched httpbin.org: {} bytes", body.len()),
-        Err(e) => eprintln!("fetch error: {}", e),
+        Err(e) => eprintln!(
+            "fetch error [{}]: {}",
+            e.code(),
+            requests::traced(&e, &request)
+        ),
     }
 
     Ok(())
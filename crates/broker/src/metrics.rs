@@ -0,0 +1,279 @@
+//! Operation counters derived from audit records.
+//!
+//! Every record the broker writes is counted by the component it is
+//! attributed to: operations per category, denials, and bytes read,
+//! written and fetched. With `[metrics]` in the [broker config](crate::config)
+//! the live counters are served for Prometheus to scrape:
+//!
+//! ```toml
+//! [metrics]
+//! listen = "127.0.0.1:9464"   # loopback only; scraped at /metrics
+//! ```
+//!
+//! `broker stats` derives the same counters from the workspace audit log,
+//! so records a policy's `audit_min_severity` keeps out of the log are
+//! only counted live.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use saf_audit::{AuditEvent, AuditReader, AuditRecord, Outcome};
+use serde::{Deserialize, Serialize};
+
+/// Longest a scrape may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest request read from a scraper.
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Address the scrape endpoint listens on; must be a loopback address.
+    pub listen: String,
+}
+
+/// Totals for one component.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ComponentMetrics {
+    /// Records by category.
+    pub operations: BTreeMap<&'static str, u64>,
+    pub denials: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Response bytes of fetches and streams.
+    pub bytes_fetched: u64,
+}
+
+/// Live counters: component name -> totals.
+#[derive(Default)]
+pub struct Metrics {
+    components: Mutex<BTreeMap<String, ComponentMetrics>>,
+}
+
+impl Metrics {
+    /// Counters for the log at `path` and its segments.
+    pub fn from_log(path: &Path) -> Result<Self, String> {
+        let metrics = Self::default();
+        if path.exists() {
+            for record in AuditReader::open(path)?.records() {
+                metrics.observe(&record?);
+            }
+        }
+        Ok(metrics)
+    }
+
+    pub fn observe(&self, record: &AuditRecord) {
+        let Ok(mut components) = self.components.lock() else {
+            return;
+        };
+        let component = match record.component.as_str() {
+            "" => "broker",
+            name => name,
+        };
+        let m = components.entry(component.to_string()).or_default();
+        *m.operations.entry(record.category().as_str()).or_insert(0) += 1;
+        if record.outcome == Outcome::Denied {
+            m.denials += 1;
+        }
+        if record.outcome != Outcome::Ok {
+            return;
+        }
+        match &record.event {
+            AuditEvent::FsRead { bytes, .. } => m.bytes_read += bytes,
+            AuditEvent::FsWrite { bytes, .. } => m.bytes_written += bytes,
+            AuditEvent::NetFetch { bytes, .. } => m.bytes_fetched += bytes,
+            AuditEvent::NetStreamClose {
+                bytes: Some(bytes), ..
+            } => m.bytes_fetched += bytes,
+            _ => {}
+        }
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, ComponentMetrics> {
+        self.components
+            .lock()
+            .map(|c| c.clone())
+            .unwrap_or_default()
+    }
+
+    /// The counters in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let components = self.snapshot();
+        let mut out = String::new();
+        let mut family = |name: &str, help: &str, samples: Vec<(String, u64)>| {
+            out.push_str(&format!("# HELP {name} {help}\n# TYPE {name} counter\n"));
+            for (labels, value) in samples {
+                out.push_str(&format!("{name}{{{labels}}} {value}\n"));
+            }
+        };
+        let per_component = |value: fn(&ComponentMetrics) -> u64| {
+            components
+                .iter()
+                .map(|(name, m)| (format!("component=\"{}\"", escape(name)), value(m)))
+                .collect()
+        };
+        family(
+            "saf_operations_total",
+            "Audited operations, by component and category.",
+            components
+                .iter()
+                .flat_map(|(name, m)| {
+                    m.operations.iter().map(move |(category, n)| {
+                        let labels =
+                            format!("component=\"{}\",category=\"{category}\"", escape(name));
+                        (labels, *n)
+                    })
+                })
+                .collect(),
+        );
+        family(
+            "saf_denials_total",
+            "Operations denied by policy.",
+            per_component(|m| m.denials),
+        );
+        family(
+            "saf_bytes_read_total",
+            "Bytes read from the workspace.",
+            per_component(|m| m.bytes_read),
+        );
+        family(
+            "saf_bytes_written_total",
+            "Bytes written to the workspace.",
+            per_component(|m| m.bytes_written),
+        );
+        family(
+            "saf_bytes_fetched_total",
+            "Response bytes received from the network.",
+            per_component(|m| m.bytes_fetched),
+        );
+        out
+    }
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serve `metrics` at `/metrics` on `config.listen` from a background
+/// thread, returning the address bound.
+pub fn serve(config: &MetricsConfig, metrics: Arc<Metrics>) -> Result<SocketAddr, String> {
+    let addr: SocketAddr = config
+        .listen
+        .parse()
+        .map_err(|e| format!("listen {:?}: {}", config.listen, e))?;
+    if !addr.ip().is_loopback() {
+        return Err(format!(
+            "listen {:?}: must be a loopback address",
+            config.listen
+        ));
+    }
+    let listener = TcpListener::bind(addr).map_err(|e| format!("{addr}: {e}"))?;
+    let bound = listener.local_addr().map_err(|e| e.to_string())?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = respond(stream, &metrics);
+        }
+    });
+    Ok(bound)
+}
+
+fn respond(mut stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_BYTES {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut line = request.lines().next().unwrap_or_default().split(' ');
+    let (status, body) = match (line.next(), line.next()) {
+        (Some("GET"), Some(path)) if path.split('?').next() == Some("/metrics") => {
+            ("200 OK", metrics.render())
+        }
+        (Some("GET"), _) => ("404 Not Found", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use saf_core::{Code, ComponentIdentity};
+
+    #[test]
+    fn records_are_counted_and_served_for_scraping() {
+        let demo = ComponentIdentity::new("demo");
+        let metrics = Arc::new(Metrics::default());
+        let read = |outcome| {
+            AuditRecord::new(
+                Code::FsReadText,
+                AuditEvent::FsRead {
+                    path: "docs/a.md".to_string(),
+                    bytes: 10,
+                    offset: None,
+                },
+            )
+            .with_outcome(outcome)
+            .with_identity(&demo)
+        };
+        metrics.observe(&read(Outcome::Ok));
+        metrics.observe(&read(Outcome::Denied));
+        metrics.observe(
+            &AuditRecord::new(
+                Code::NetGetText,
+                AuditEvent::NetFetch {
+                    url: "https://example.org/".to_string(),
+                    status: 200,
+                    bytes: 512,
+                },
+            )
+            .with_identity(&demo),
+        );
+        metrics.observe(&AuditRecord::new(
+            Code::BrokerStart,
+            AuditEvent::BrokerLifecycle {
+                version: "0".to_string(),
+            },
+        ));
+        let snapshot = metrics.snapshot();
+        let m = &snapshot["demo"];
+        assert_eq!((m.operations["fs"], m.operations["net"]), (2, 1));
+        assert_eq!((m.denials, m.bytes_read, m.bytes_fetched), (1, 10, 512));
+        assert_eq!(snapshot["broker"].operations["lifecycle"], 1);
+
+        let config = |listen: &str| MetricsConfig {
+            listen: listen.to_string(),
+        };
+        assert!(serve(&config("0.0.0.0:0"), metrics.clone()).is_err());
+        let addr = serve(&config("127.0.0.1:0"), metrics).expect("serve");
+        let scrape = |path: &str| {
+            let mut stream = TcpStream::connect(addr).expect("connect");
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").expect("request");
+            let mut response = String::new();
+            stream.read_to_string(&mut response).expect("response");
+            response
+        };
+        let response = scrape("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("saf_operations_total{component=\"demo\",category=\"fs\"} 2\n"));
+        assert!(response.contains("saf_bytes_fetched_total{component=\"demo\"} 512\n"));
+        assert!(scrape("/").starts_with("HTTP/1.1 404"));
+    }
+}
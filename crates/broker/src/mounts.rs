@@ -0,0 +1,166 @@
+//! Directories mounted into a run's workspace under names.
+//!
+//! `--mount input:/data/in:ro` makes `/data/in` appear to the component as
+//! the directory `input` at the workspace root: `input/a.csv` is
+//! `/data/in/a.csv`. A path is routed by its first segment, after it has
+//! been sanitized and checked against the policy, so the policy's rules see
+//! mounted files under the mount's name, as `input/a.csv`, and can allow,
+//! deny or ask about them like any other path. A mount is no grant of its
+//! own: it must be allowed by the policy like the rest of the workspace.
+//!
+//! A read-only mount adds its name to the policy's `read_only` rules, so
+//! writes under it are refused and audited as `policy.path_read_only`,
+//! whatever the policy file says. Mounts are read-write otherwise.
+
+use std::path::PathBuf;
+
+use saf_policy::Policy;
+
+/// One `--mount NAME:PATH[:ro|:rw]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    pub name: String,
+    pub path: PathBuf,
+    pub read_only: bool,
+}
+
+impl Mount {
+    /// Parse `NAME:PATH`, optionally followed by `:ro` or `:rw`.
+    pub fn parse(arg: &str) -> Result<Self, String> {
+        let (name, rest) = arg
+            .split_once(':')
+            .ok_or_else(|| format!("{arg:?}: expected NAME:PATH[:ro|:rw]"))?;
+        let (path, read_only) = match rest.rsplit_once(':') {
+            Some((path, "ro")) => (path, true),
+            Some((path, "rw")) => (path, false),
+            _ => (rest, false),
+        };
+        if name.is_empty()
+            || name.starts_with('.')
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        {
+            return Err(format!(
+                "{name:?}: a mount name is letters, digits, '-', '_' and '.', and does not start with '.'"
+            ));
+        }
+        if path.is_empty() {
+            return Err(format!("{arg:?}: the mount {name} has no path"));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            path: PathBuf::from(path),
+            read_only,
+        })
+    }
+}
+
+/// A run's workspace: its root and the directories mounted into it.
+#[derive(Debug, Clone)]
+pub struct Mounts {
+    root: PathBuf,
+    mounts: Vec<Mount>,
+}
+
+impl Mounts {
+    /// Check each mount names an existing directory, and no two share a
+    /// name.
+    pub fn new(root: PathBuf, mounts: Vec<Mount>) -> Result<Self, String> {
+        for (i, mount) in mounts.iter().enumerate() {
+            if mounts[..i].iter().any(|m| m.name == mount.name) {
+                return Err(format!("{} is mounted twice", mount.name));
+            }
+            if !mount.path.is_dir() {
+                return Err(format!(
+                    "cannot mount {}: {} is not a directory",
+                    mount.name,
+                    mount.path.display()
+                ));
+            }
+        }
+        Ok(Self { root, mounts })
+    }
+
+    pub fn mounts(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    /// Where the sanitized path `rel` is on disk.
+    pub fn resolve(&self, rel: &str) -> PathBuf {
+        let (first, rest) = rel.split_once('/').unwrap_or((rel, ""));
+        match self.mounts.iter().find(|m| m.name == first) {
+            Some(mount) if rest.is_empty() => mount.path.clone(),
+            Some(mount) => mount.path.join(rest),
+            None => self.root.join(rel),
+        }
+    }
+
+    /// The mount names to list in the directory `rel` besides what is on
+    /// disk there: all of them at the workspace root.
+    pub fn listed_in(&self, rel: &str) -> Vec<&str> {
+        match rel {
+            "" => self.mounts.iter().map(|m| m.name.as_str()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Make the read-only mounts read-only in `policy`.
+    pub fn restrict(&self, policy: &mut Policy) {
+        for mount in self.mounts.iter().filter(|m| m.read_only) {
+            policy.read_only.push(mount.name.clone());
+            policy.read_only.push(format!("{}/**", mount.name));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use saf_policy::FsAccess;
+
+    #[test]
+    fn paths_are_routed_by_mount_name() {
+        assert_eq!(
+            Mount::parse("input:/data/in:ro"),
+            Ok(Mount {
+                name: "input".to_string(),
+                path: PathBuf::from("/data/in"),
+                read_only: true,
+            })
+        );
+        assert_eq!(
+            Mount::parse(r"output:C:\out").map(|m| (m.path, m.read_only)),
+            Ok((PathBuf::from(r"C:\out"), false))
+        );
+        assert!(Mount::parse("input").is_err());
+        assert!(Mount::parse(".saf:/tmp").is_err());
+        assert!(Mount::parse("a/b:/tmp").is_err());
+
+        let dir = std::env::temp_dir().join(format!("saf-mounts-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = Mount::parse(&format!("input:{}:ro", dir.display())).unwrap();
+        assert!(Mounts::new(PathBuf::from("/ws"), vec![input.clone(), input.clone()]).is_err());
+        let missing = Mount::parse("output:/nonexistent/saf-mounts").unwrap();
+        assert!(Mounts::new(PathBuf::from("/ws"), vec![missing]).is_err());
+
+        let mounts = Mounts::new(PathBuf::from("/ws"), vec![input]).unwrap();
+        assert_eq!(mounts.resolve("input/a/b.csv"), dir.join("a/b.csv"));
+        assert_eq!(mounts.resolve("input"), dir);
+        assert_eq!(mounts.resolve("inputs/a"), PathBuf::from("/ws/inputs/a"));
+        assert_eq!(mounts.resolve(""), PathBuf::from("/ws/"));
+        assert_eq!(mounts.listed_in(""), ["input"]);
+        assert_eq!(mounts.listed_in("docs").len(), 0);
+
+        let mut policy = Policy::new();
+        mounts.restrict(&mut policy);
+        assert!(policy
+            .check_fs_access("input/a.csv", FsAccess::Write)
+            .is_err());
+        assert!(policy
+            .check_fs_access("input/a.csv", FsAccess::Read)
+            .is_ok());
+        assert!(policy.check_fs_access("out/a.csv", FsAccess::Write).is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,247 @@
+//! Network byte accounting per component and domain.
+//!
+//! The NetHost meters traffic for the current run; when the run ends the
+//! totals are folded into `<workspace>/.saf/net_stats.json`, which
+//! `broker stats` and the UI read. `broker stats` also shows the
+//! [counters](crate::metrics) derived from the workspace audit log.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+use crate::metrics::{ComponentMetrics, Metrics};
+
+pub fn stats_path(workspace: &Path) -> PathBuf {
+    workspace.join(".saf").join("net_stats.json")
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DomainTraffic {
+    pub requests: u64,
+    pub request_bytes: u64,
+    pub response_bytes: u64,
+}
+
+impl DomainTraffic {
+    fn add(&mut self, other: &DomainTraffic) {
+        self.requests += other.requests;
+        self.request_bytes += other.request_bytes;
+        self.response_bytes += other.response_bytes;
+    }
+}
+
+/// Accumulated traffic: component name -> domain -> totals.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetStats {
+    pub components: BTreeMap<String, BTreeMap<String, DomainTraffic>>,
+}
+
+impl NetStats {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("invalid net stats {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, content).map_err(|e| e.to_string())
+    }
+
+    pub fn merge(&mut self, component: &str, run: &BTreeMap<String, DomainTraffic>) {
+        let entry = self.components.entry(component.to_string()).or_default();
+        for (domain, traffic) in run {
+            entry.entry(domain.clone()).or_default().add(traffic);
+        }
+    }
+}
+
+/// Live counters for the current run.
+#[derive(Default)]
+pub struct NetMeter {
+    domains: Mutex<BTreeMap<String, DomainTraffic>>,
+}
+
+impl NetMeter {
+    pub fn record_request(&self, domain: &str, bytes: u64) {
+        if let Ok(mut d) = self.domains.lock() {
+            let t = d.entry(domain.to_string()).or_default();
+            t.requests += 1;
+            t.request_bytes += bytes;
+        }
+    }
+
+    pub fn record_response(&self, domain: &str, bytes: u64) {
+        if let Ok(mut d) = self.domains.lock() {
+            d.entry(domain.to_string()).or_default().response_bytes += bytes;
+        }
+    }
+
+    /// Bytes moved in either direction so far this run.
+    pub fn total_bytes(&self) -> u64 {
+        self.domains
+            .lock()
+            .map(|d| d.values().map(|t| t.request_bytes + t.response_bytes).sum())
+            .unwrap_or(0)
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, DomainTraffic> {
+        self.domains.lock().map(|d| d.clone()).unwrap_or_default()
+    }
+}
+
+/// Entry point for `broker stats [--json] [--workspace DIR]`.
+#[derive(Debug, Args)]
+pub struct StatsArgs {
+    #[arg(long)]
+    json: bool,
+    /// Workspace to report on (default: the current directory).
+    #[arg(long, value_name = "DIR")]
+    workspace: Option<PathBuf>,
+}
+
+pub fn main(args: StatsArgs) -> Result<(), String> {
+    let workspace = args.workspace.unwrap_or_else(|| PathBuf::from("."));
+    report(&workspace, args.json, &mut std::io::stdout().lock())
+}
+
+/// Write the workspace's counters and network totals to `out`, as a table or
+/// as JSON.
+pub fn report(workspace: &Path, json: bool, out: &mut dyn Write) -> Result<(), String> {
+    let stats = NetStats::load(&stats_path(workspace))?;
+    let metrics = Metrics::from_log(&workspace.join(".saf").join("audit.log"))?.snapshot();
+    if json {
+        #[derive(Serialize)]
+        struct Stats<'a> {
+            #[serde(flatten)]
+            net: &'a NetStats,
+            metrics: &'a BTreeMap<String, ComponentMetrics>,
+        }
+        let stats = Stats {
+            net: &stats,
+            metrics: &metrics,
+        };
+        let json = serde_json::to_string_pretty(&stats).map_err(|e| e.to_string())?;
+        return writeln!(out, "{json}").map_err(|e| e.to_string());
+    }
+    write_tables(workspace, &stats, &metrics, out).map_err(|e| e.to_string())
+}
+
+fn write_tables(
+    workspace: &Path,
+    stats: &NetStats,
+    metrics: &BTreeMap<String, ComponentMetrics>,
+    out: &mut dyn Write,
+) -> std::io::Result<()> {
+    if !metrics.is_empty() {
+        writeln!(
+            out,
+            "{:<24} {:>10} {:>8} {:>12} {:>12} {:>12}",
+            "COMPONENT", "OPERATIONS", "DENIED", "READ", "WRITTEN", "FETCHED"
+        )?;
+        for (component, m) in metrics {
+            writeln!(
+                out,
+                "{:<24} {:>10} {:>8} {:>12} {:>12} {:>12}",
+                component,
+                m.operations.values().sum::<u64>(),
+                m.denials,
+                m.bytes_read,
+                m.bytes_written,
+                m.bytes_fetched
+            )?;
+        }
+        writeln!(out)?;
+    }
+    if stats.components.is_empty() {
+        return writeln!(
+            out,
+            "No network activity recorded in {}",
+            workspace.display()
+        );
+    }
+    writeln!(
+        out,
+        "{:<24} {:<32} {:>8} {:>12} {:>12}",
+        "COMPONENT", "DOMAIN", "REQUESTS", "SENT", "RECEIVED"
+    )?;
+    for (component, domains) in &stats.components {
+        for (domain, t) in domains {
+            writeln!(
+                out,
+                "{:<24} {:<32} {:>8} {:>12} {:>12}",
+                component, domain, t.requests, t.request_bytes, t.response_bytes
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_accumulates_per_component_and_domain() {
+        let meter = NetMeter::default();
+        meter.record_request("example.org", 30);
+        meter.record_response("example.org", 100);
+        assert_eq!(meter.total_bytes(), 130);
+
+        let mut stats = NetStats::default();
+        stats.merge("demo", &meter.snapshot());
+        stats.merge("demo", &meter.snapshot());
+        let t = stats.components["demo"]["example.org"];
+        assert_eq!(
+            (t.requests, t.request_bytes, t.response_bytes),
+            (2, 60, 200)
+        );
+    }
+
+    #[test]
+    fn report_reads_the_given_workspace() {
+        let workspace =
+            std::env::temp_dir().join(format!("saf-net-stats-{}", uuid::Uuid::new_v4()));
+        let mut out = Vec::new();
+        report(&workspace, false, &mut out).expect("empty workspace");
+        let text = String::from_utf8(out).expect("utf8");
+        assert!(
+            text.starts_with("No network activity recorded in"),
+            "{text}"
+        );
+
+        let meter = NetMeter::default();
+        meter.record_request("example.org", 30);
+        let mut stats = NetStats::default();
+        stats.merge("demo", &meter.snapshot());
+        stats.save(&stats_path(&workspace)).expect("save");
+
+        let mut out = Vec::new();
+        report(&workspace, false, &mut out).expect("table");
+        let text = String::from_utf8(out).expect("utf8");
+        assert!(
+            text.contains("demo") && text.contains("example.org"),
+            "{text}"
+        );
+
+        let mut out = Vec::new();
+        report(&workspace, true, &mut out).expect("json");
+        let json: serde_json::Value = serde_json::from_slice(&out).expect("json");
+        assert_eq!(
+            json["components"]["demo"]["example.org"]["request_bytes"],
+            30
+        );
+
+        let _ = std::fs::remove_dir_all(&workspace);
+    }
+}
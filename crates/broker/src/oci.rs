@@ -0,0 +1,752 @@
+//! Components pulled from OCI registries.
+//!
+//! `broker component install oci://<registry>/<repository>[:<tag>][@sha256:<digest>]`
+//! fetches a component published as an OCI artifact, following the
+//! conventions for WASM components: an image manifest with exactly one
+//! `application/wasm` layer, the component itself (the config is usually
+//! `application/vnd.wasm.config.v0+json`). The tag defaults to `latest`;
+//! image indexes are not followed.
+//!
+//! Every manifest and blob is checked against its digest and kept in
+//! `<cache_dir>/secure-app-framework/oci/`: blobs under `blobs/sha256/`,
+//! and the digest each tag last resolved to in `tags.json`. A reference
+//! pinned by digest installs from the cache without touching the network
+//! once pulled; with `--offline` tags resolve from the cache as well, and
+//! anything not cached fails instead of connecting. The reference and the
+//! manifest digest it resolved to are recorded in the component registry.
+//!
+//! With `--verify-key <cosign.pub>` the artifact must carry a cosign
+//! signature by that key: the `sha256-<hex>.sig` tag in the same
+//! repository, one of whose layers is a simple-signing payload naming the
+//! manifest digest, signed (ECDSA P-256, SHA-256) in its
+//! `dev.cosignproject.cosign/signature` annotation. Keyless signatures,
+//! which need Fulcio and Rekor, are not checked.
+//!
+//! Registries are reached over HTTPS (plain HTTP only on loopback),
+//! anonymously or with the bearer token their `WWW-Authenticate` challenge
+//! hands out for pulls.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_ASN1};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::run_manifest::sha256_hex;
+
+const MANIFEST_TYPES: &str =
+    "application/vnd.oci.image.manifest.v1+json, application/vnd.docker.distribution.manifest.v2+json";
+const INDEX_TYPES: &[&str] = &[
+    "application/vnd.oci.image.index.v1+json",
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+];
+const WASM_LAYER: &str = "application/wasm";
+const COSIGN_SIGNATURE: &str = "dev.cosignproject.cosign/signature";
+/// DER prefix of a SubjectPublicKeyInfo holding an uncompressed P-256 point.
+const P256_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a,
+    0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+const MAX_MANIFEST: u64 = 4 * 1024 * 1024;
+const MAX_BLOB: u64 = 256 * 1024 * 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `oci://<registry>/<repository>[:<tag>][@sha256:<digest>]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub registry: String,
+    pub repository: String,
+    pub tag: Option<String>,
+    pub digest: Option<String>,
+}
+
+impl Reference {
+    pub fn parse(reference: &str) -> Result<Self, String> {
+        let invalid = |why: &str| format!("invalid OCI reference {reference:?}: {why}");
+        let rest = reference
+            .strip_prefix("oci://")
+            .ok_or_else(|| invalid("expected oci://<registry>/<repository>[:<tag>]"))?;
+        let (rest, digest) = match rest.split_once('@') {
+            Some((r, d)) if is_digest(d) => (r, Some(d.to_string())),
+            Some(_) => return Err(invalid("expected a digest like sha256:<64 hex digits>")),
+            None => (rest, None),
+        };
+        let (registry, path) = rest
+            .split_once('/')
+            .ok_or_else(|| invalid("expected oci://<registry>/<repository>[:<tag>]"))?;
+        let (repository, tag) = match path.rsplit_once(':') {
+            Some((r, t)) => (r, Some(t.to_string())),
+            None => (path, None),
+        };
+        let registry_ok = !registry.is_empty()
+            && registry
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'));
+        if !registry_ok {
+            return Err(invalid("bad registry host"));
+        }
+        let repository_ok = repository.split('/').all(|part| {
+            !part.is_empty()
+                && part.chars().all(|c| {
+                    c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '_' | '-')
+                })
+                && part.starts_with(|c: char| c.is_ascii_alphanumeric())
+        });
+        if !repository_ok {
+            return Err(invalid("repository names are lowercase path segments"));
+        }
+        if let Some(tag) = &tag {
+            let tag_ok = tag.len() <= 128
+                && tag.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+                && tag
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+            if !tag_ok {
+                return Err(invalid("bad tag"));
+            }
+        }
+        Ok(Self {
+            registry: registry.to_string(),
+            repository: repository.to_string(),
+            tag,
+            digest,
+        })
+    }
+
+    /// The tag or digest a pull resolves.
+    fn target(&self) -> &str {
+        self.digest
+            .as_deref()
+            .or(self.tag.as_deref())
+            .unwrap_or("latest")
+    }
+
+    /// Name to install under by default: the repository's last segment.
+    pub fn default_name(&self) -> String {
+        let last = self.repository.rsplit('/').next().unwrap_or_default();
+        last.replace('.', "-")
+    }
+}
+
+impl std::fmt::Display for Reference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "oci://{}/{}", self.registry, self.repository)?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{tag}")?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@{digest}")?;
+        }
+        Ok(())
+    }
+}
+
+fn is_digest(s: &str) -> bool {
+    s.strip_prefix("sha256:").is_some_and(|hex| {
+        hex.len() == 64 && hex.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+    })
+}
+
+/// Where an installed component was pulled from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Origin {
+    pub reference: String,
+    /// Digest of the manifest the reference resolved to.
+    pub digest: String,
+    /// Whether a cosign signature was verified.
+    #[serde(default)]
+    pub signed: bool,
+}
+
+/// What a registry serves: manifests by tag or digest, blobs by digest.
+pub trait Source {
+    fn manifest(&mut self, repository: &str, target: &str) -> Result<Vec<u8>, String>;
+    fn blob(&mut self, repository: &str, digest: &str) -> Result<Vec<u8>, String>;
+}
+
+/// Pulled manifests and blobs, by digest.
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn dir() -> Result<PathBuf, String> {
+        Ok(dirs::cache_dir()
+            .ok_or("No cache directory available")?
+            .join("secure-app-framework")
+            .join("oci"))
+    }
+
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn blob_path(&self, digest: &str) -> PathBuf {
+        let hex = digest.strip_prefix("sha256:").unwrap_or(digest);
+        self.dir.join("blobs").join("sha256").join(hex)
+    }
+
+    /// The cached blob, unless it is missing or no longer matches.
+    fn blob(&self, digest: &str) -> Option<Vec<u8>> {
+        let bytes = std::fs::read(self.blob_path(digest)).ok()?;
+        (format!("sha256:{}", sha256_hex(&bytes)) == digest).then_some(bytes)
+    }
+
+    fn put(&self, digest: &str, bytes: &[u8]) -> Result<(), String> {
+        let path = self.blob_path(digest);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("{}: {}", parent.display(), e))?;
+        }
+        std::fs::write(&path, bytes).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn tags(&self) -> Result<BTreeMap<String, String>, String> {
+        let path = self.dir.join("tags.json");
+        match std::fs::read_to_string(&path) {
+            Ok(c) => serde_json::from_str(&c).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    fn set_tag(&self, key: String, digest: &str) -> Result<(), String> {
+        let mut tags = self.tags()?;
+        tags.insert(key, digest.to_string());
+        let path = self.dir.join("tags.json");
+        std::fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+        let content = serde_json::to_string_pretty(&tags).map_err(|e| e.to_string())?;
+        std::fs::write(&path, content).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// A pulled component.
+#[derive(Debug)]
+pub struct Pulled {
+    pub bytes: Vec<u8>,
+    pub origin: Origin,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ImageManifest {
+    #[serde(default)]
+    media_type: Option<String>,
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    media_type: String,
+    digest: String,
+    #[serde(default)]
+    annotations: BTreeMap<String, String>,
+}
+
+/// Pull the component `reference` names through `cache`, from `source`
+/// unless offline, requiring a signature by `key` if one is given.
+pub fn pull(
+    reference: &Reference,
+    cache: &Cache,
+    mut source: Option<&mut (dyn Source + '_)>,
+    key: Option<&[u8]>,
+) -> Result<Pulled, String> {
+    let (digest, manifest) = manifest(reference, reference.target(), cache, source.as_deref_mut())?;
+    let layers: Vec<&Descriptor> = manifest
+        .layers
+        .iter()
+        .filter(|l| l.media_type == WASM_LAYER)
+        .collect();
+    let [layer] = layers[..] else {
+        return Err(format!(
+            "{reference}: expected one {WASM_LAYER} layer, found {}",
+            layers.len()
+        ));
+    };
+    let bytes = blob(reference, &layer.digest, cache, source.as_deref_mut())?;
+    if let Some(key) = key {
+        verify_signature(reference, &digest, cache, source, key)?;
+    }
+    Ok(Pulled {
+        bytes,
+        origin: Origin {
+            reference: reference.to_string(),
+            digest,
+            signed: key.is_some(),
+        },
+    })
+}
+
+/// The manifest `target` (a tag or digest) resolves to, and its digest.
+fn manifest(
+    reference: &Reference,
+    target: &str,
+    cache: &Cache,
+    source: Option<&mut (dyn Source + '_)>,
+) -> Result<(String, ImageManifest), String> {
+    let (digest, bytes) = if is_digest(target) {
+        let bytes = match cache.blob(target) {
+            Some(bytes) => bytes,
+            None => fetch(reference, target, cache, source)?.1,
+        };
+        (target.to_string(), bytes)
+    } else {
+        let key = format!("{}/{}:{}", reference.registry, reference.repository, target);
+        match source {
+            Some(source) => {
+                let (digest, bytes) = fetch(reference, target, cache, Some(source))?;
+                cache.set_tag(key, &digest)?;
+                (digest, bytes)
+            }
+            None => {
+                let digest = cache.tags()?.remove(&key).ok_or_else(|| {
+                    format!("{reference}: {target} is not cached; pull it without --offline first")
+                })?;
+                let bytes = cache.blob(&digest).ok_or_else(|| {
+                    format!("{reference}: {digest} is not cached; pull it without --offline first")
+                })?;
+                (digest, bytes)
+            }
+        }
+    };
+    let manifest: ImageManifest = serde_json::from_slice(&bytes)
+        .map_err(|e| format!("{reference}: invalid manifest {digest}: {e}"))?;
+    if manifest
+        .media_type
+        .as_deref()
+        .is_some_and(|t| INDEX_TYPES.contains(&t))
+    {
+        return Err(format!(
+            "{reference}: {digest} is an image index; name a single manifest by digest"
+        ));
+    }
+    Ok((digest, manifest))
+}
+
+/// Fetch the manifest `target` and cache it, checking it against `target`
+/// when that is a digest.
+fn fetch(
+    reference: &Reference,
+    target: &str,
+    cache: &Cache,
+    source: Option<&mut (dyn Source + '_)>,
+) -> Result<(String, Vec<u8>), String> {
+    let source = source
+        .ok_or_else(|| format!("{reference}: {target} is not cached and --offline is set"))?;
+    let bytes = source.manifest(&reference.repository, target)?;
+    let digest = format!("sha256:{}", sha256_hex(&bytes));
+    if is_digest(target) && digest != target {
+        return Err(format!("{reference}: manifest does not match {target}"));
+    }
+    cache.put(&digest, &bytes)?;
+    Ok((digest, bytes))
+}
+
+/// The blob `digest`, from the cache or fetched and checked.
+fn blob(
+    reference: &Reference,
+    digest: &str,
+    cache: &Cache,
+    source: Option<&mut (dyn Source + '_)>,
+) -> Result<Vec<u8>, String> {
+    if !is_digest(digest) {
+        return Err(format!("{reference}: unsupported digest {digest}"));
+    }
+    if let Some(bytes) = cache.blob(digest) {
+        return Ok(bytes);
+    }
+    let source = source
+        .ok_or_else(|| format!("{reference}: blob {digest} is not cached and --offline is set"))?;
+    let bytes = source.blob(&reference.repository, digest)?;
+    if format!("sha256:{}", sha256_hex(&bytes)) != digest {
+        return Err(format!("{reference}: blob does not match {digest}"));
+    }
+    cache.put(digest, &bytes)?;
+    Ok(bytes)
+}
+
+/// Check a cosign signature by `key` names the manifest `digest`.
+fn verify_signature(
+    reference: &Reference,
+    digest: &str,
+    cache: &Cache,
+    mut source: Option<&mut (dyn Source + '_)>,
+    key: &[u8],
+) -> Result<(), String> {
+    let tag = format!("{}.sig", digest.replacen(':', "-", 1));
+    let (_, signatures) = manifest(reference, &tag, cache, source.as_deref_mut())
+        .map_err(|e| format!("no signature found: {e}"))?;
+    let key = UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, key);
+    for layer in &signatures.layers {
+        let Some(signature) = layer.annotations.get(COSIGN_SIGNATURE) else {
+            continue;
+        };
+        let Ok(signature) = BASE64.decode(signature) else {
+            continue;
+        };
+        let payload = blob(reference, &layer.digest, cache, source.as_deref_mut())?;
+        if key.verify(&payload, &signature).is_ok() && names_digest(&payload, digest) {
+            return Ok(());
+        }
+    }
+    Err(format!(
+        "{reference}: no signature by the given key for {digest}"
+    ))
+}
+
+/// Whether a simple-signing payload is about the manifest `digest`.
+fn names_digest(payload: &[u8], digest: &str) -> bool {
+    serde_json::from_slice::<Value>(payload).is_ok_and(|p| {
+        p.pointer("/critical/image/docker-manifest-digest")
+            .and_then(Value::as_str)
+            == Some(digest)
+    })
+}
+
+/// The P-256 public key in a PEM file, as cosign writes them.
+pub fn load_key(path: &Path) -> Result<Vec<u8>, String> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse_key(&content).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn parse_key(pem: &str) -> Result<Vec<u8>, String> {
+    let body: String = pem
+        .lines()
+        .skip_while(|l| l.trim() != "-----BEGIN PUBLIC KEY-----")
+        .skip(1)
+        .take_while(|l| l.trim() != "-----END PUBLIC KEY-----")
+        .map(str::trim)
+        .collect();
+    let der = BASE64
+        .decode(body)
+        .map_err(|_| "expected a PEM public key".to_string())?;
+    match der.strip_prefix(P256_SPKI_PREFIX) {
+        Some(point) if point.len() == 65 => Ok(point.to_vec()),
+        _ => Err("expected an ECDSA P-256 public key".to_string()),
+    }
+}
+
+/// A registry's HTTP API.
+pub struct Client {
+    base: String,
+    agent: ureq::Agent,
+    token: Option<String>,
+}
+
+impl Client {
+    pub fn new(registry: &str) -> Self {
+        let host = registry.split(':').next().unwrap_or_default();
+        let loopback = host == "localhost" || host == "127.0.0.1" || registry.starts_with("[::1]");
+        // Docker Hub's API lives apart from its name.
+        let registry = match registry {
+            "docker.io" => "registry-1.docker.io",
+            r => r,
+        };
+        Self {
+            base: format!(
+                "{}://{registry}/v2",
+                if loopback { "http" } else { "https" }
+            ),
+            agent: ureq::Agent::config_builder()
+                .timeout_connect(Some(CONNECT_TIMEOUT))
+                .timeout_recv_response(Some(RESPONSE_TIMEOUT))
+                .http_status_as_error(false)
+                .build()
+                .into(),
+            token: None,
+        }
+    }
+
+    fn get(&mut self, url: &str, accept: &str, limit: u64) -> Result<Vec<u8>, String> {
+        let send = |token: Option<&str>| {
+            let mut request = self.agent.get(url).header("accept", accept);
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {token}"));
+            }
+            request.call().map_err(|e| format!("{url}: {e}"))
+        };
+        let mut response = send(self.token.as_deref())?;
+        if response.status().as_u16() == 401 && self.token.is_none() {
+            let challenge = response
+                .headers()
+                .get("www-authenticate")
+                .and_then(|v| v.to_str().ok())
+                .ok_or_else(|| format!("{url}: HTTP 401 without a challenge"))?
+                .to_string();
+            let token = self.authenticate(&challenge)?;
+            self.token = Some(token);
+            response = send(self.token.as_deref())?;
+        }
+        let status = response.status().as_u16();
+        if status != 200 {
+            return Err(format!("{url}: HTTP {status}"));
+        }
+        response
+            .body_mut()
+            .with_config()
+            .limit(limit)
+            .read_to_vec()
+            .map_err(|e| format!("{url}: {e}"))
+    }
+
+    /// The anonymous pull token a `Bearer` challenge points to.
+    fn authenticate(&self, challenge: &str) -> Result<String, String> {
+        let params = parse_challenge(challenge)
+            .ok_or_else(|| format!("unsupported registry challenge {challenge:?}"))?;
+        let realm = params
+            .get("realm")
+            .ok_or("registry challenge has no realm")?;
+        let query: Vec<(&str, &str)> = ["service", "scope"]
+            .iter()
+            .filter_map(|k| Some((*k, params.get(*k)?.as_str())))
+            .collect();
+        let url = url::Url::parse_with_params(realm, query)
+            .map_err(|e| format!("registry realm {realm:?}: {e}"))?;
+        let loopback = matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"));
+        if url.scheme() != "https" && !(url.scheme() == "http" && loopback) {
+            return Err(format!("registry realm {realm:?}: expected https://"));
+        }
+        let body = self
+            .agent
+            .get(url.as_str())
+            .call()
+            .map_err(|e| format!("{url}: {e}"))?
+            .body_mut()
+            .with_config()
+            .limit(MAX_MANIFEST)
+            .read_to_vec()
+            .map_err(|e| format!("{url}: {e}"))?;
+        let token: Value = serde_json::from_slice(&body).map_err(|e| format!("{url}: {e}"))?;
+        token
+            .get("token")
+            .or_else(|| token.get("access_token"))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| format!("{url}: no token in response"))
+    }
+}
+
+impl Source for Client {
+    fn manifest(&mut self, repository: &str, target: &str) -> Result<Vec<u8>, String> {
+        let url = format!("{}/{repository}/manifests/{target}", self.base);
+        self.get(&url, MANIFEST_TYPES, MAX_MANIFEST)
+    }
+
+    fn blob(&mut self, repository: &str, digest: &str) -> Result<Vec<u8>, String> {
+        let url = format!("{}/{repository}/blobs/{digest}", self.base);
+        self.get(&url, "*/*", MAX_BLOB)
+    }
+}
+
+/// The parameters of a `Bearer k="v", ...` challenge.
+fn parse_challenge(challenge: &str) -> Option<BTreeMap<String, String>> {
+    let (scheme, mut rest) = challenge.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+    let mut params = BTreeMap::new();
+    loop {
+        rest = rest.trim_start_matches([' ', ',']);
+        if rest.is_empty() {
+            return Some(params);
+        }
+        let (key, after) = rest.split_once('=')?;
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let (v, a) = quoted.split_once('"')?;
+                (v, a)
+            }
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+        params.insert(key.trim().to_ascii_lowercase(), value.to_string());
+        rest = after;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+    use serde_json::json;
+
+    #[derive(Default)]
+    struct FakeRegistry {
+        manifests: BTreeMap<String, Vec<u8>>,
+        blobs: BTreeMap<String, Vec<u8>>,
+        requests: usize,
+    }
+
+    impl FakeRegistry {
+        fn blob(&mut self, bytes: &[u8]) -> String {
+            let digest = format!("sha256:{}", sha256_hex(bytes));
+            self.blobs.insert(digest.clone(), bytes.to_vec());
+            digest
+        }
+
+        fn manifest(&mut self, target: &str, layers: Value) -> String {
+            let bytes = serde_json::to_vec(&json!({
+                "schemaVersion": 2,
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "config": {"mediaType": "application/vnd.wasm.config.v0+json", "digest": "sha256:00", "size": 2},
+                "layers": layers,
+            }))
+            .unwrap();
+            let digest = format!("sha256:{}", sha256_hex(&bytes));
+            self.manifests.insert(target.to_string(), bytes.clone());
+            self.manifests.insert(digest.clone(), bytes);
+            digest
+        }
+    }
+
+    impl Source for FakeRegistry {
+        fn manifest(&mut self, _: &str, target: &str) -> Result<Vec<u8>, String> {
+            self.requests += 1;
+            self.manifests.get(target).cloned().ok_or("HTTP 404".into())
+        }
+
+        fn blob(&mut self, _: &str, digest: &str) -> Result<Vec<u8>, String> {
+            self.requests += 1;
+            self.blobs.get(digest).cloned().ok_or("HTTP 404".into())
+        }
+    }
+
+    #[test]
+    fn references_parse() {
+        let r = Reference::parse("oci://ghcr.io/acme/tools/notes:1.2.0").expect("parse");
+        assert_eq!(
+            (r.registry.as_str(), r.repository.as_str(), r.target()),
+            ("ghcr.io", "acme/tools/notes", "1.2.0")
+        );
+        assert_eq!(r.default_name(), "notes");
+        let digest = format!("sha256:{}", "ab".repeat(32));
+        let pinned = format!("oci://localhost:5000/notes@{digest}");
+        let r = Reference::parse(&pinned).expect("parse");
+        assert_eq!(
+            (r.registry.as_str(), r.target()),
+            ("localhost:5000", digest.as_str())
+        );
+        assert_eq!(r.to_string(), pinned);
+        assert_eq!(
+            Reference::parse("oci://ghcr.io/notes").unwrap().target(),
+            "latest"
+        );
+        for bad in [
+            "ghcr.io/notes",
+            "oci://ghcr.io",
+            "oci://ghcr.io/Notes",
+            "oci://ghcr.io/notes@sha256:ab",
+            "oci://ghcr.io/notes:-x",
+        ] {
+            assert!(Reference::parse(bad).is_err(), "{bad}");
+        }
+        let challenge = parse_challenge(
+            r#"Bearer realm="https://ghcr.io/token",service="ghcr.io",scope="repository:acme/notes:pull,push""#,
+        )
+        .expect("challenge");
+        assert_eq!(challenge["scope"], "repository:acme/notes:pull,push");
+        assert_eq!(challenge["realm"], "https://ghcr.io/token");
+    }
+
+    #[test]
+    fn pulls_are_checked_cached_and_signed() {
+        let dir = std::env::temp_dir().join(format!("saf-oci-{}", uuid::Uuid::new_v4()));
+        let cache = Cache::new(dir.clone());
+        let mut registry = FakeRegistry::default();
+        let wasm = b"\0asm\x0d\x00\x01\x00".to_vec();
+        let layer = registry.blob(&wasm);
+        let digest = registry.manifest(
+            "1.0",
+            json!([{"mediaType": WASM_LAYER, "digest": layer, "size": wasm.len()}]),
+        );
+
+        // Sign the manifest the way cosign does.
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+        let mut spki = P256_SPKI_PREFIX.to_vec();
+        spki.extend_from_slice(pair.public_key().as_ref());
+        let pem = format!(
+            "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n",
+            BASE64.encode(&spki)
+        );
+        let key = parse_key(&pem).expect("key");
+        let payload = serde_json::to_vec(&json!({
+            "critical": {
+                "identity": {"docker-reference": "ghcr.io/acme/notes"},
+                "image": {"docker-manifest-digest": digest},
+                "type": "cosign container image signature"
+            }
+        }))
+        .unwrap();
+        let signature = BASE64.encode(pair.sign(&rng, &payload).unwrap());
+        let payload_digest = registry.blob(&payload);
+        registry.manifest(
+            &format!("{}.sig", digest.replacen(':', "-", 1)),
+            json!([{
+                "mediaType": "application/vnd.dev.cosign.simplesigning.v1+json",
+                "digest": payload_digest,
+                "size": payload.len(),
+                "annotations": {COSIGN_SIGNATURE: signature},
+            }]),
+        );
+
+        let tagged = Reference::parse("oci://ghcr.io/acme/notes:1.0").unwrap();
+        let pulled = pull(&tagged, &cache, Some(&mut registry), Some(&key)).expect("pull");
+        assert_eq!(pulled.bytes, wasm);
+        assert_eq!(pulled.origin.digest, digest);
+        assert!(pulled.origin.signed);
+
+        // Offline, the tag and the signature resolve from the cache.
+        let offline = pull(&tagged, &cache, None, Some(&key)).expect("offline");
+        assert_eq!(offline.bytes, wasm);
+        let requests = registry.requests;
+        let pinned = Reference::parse(&format!("oci://ghcr.io/acme/notes@{digest}")).unwrap();
+        pull(&pinned, &cache, Some(&mut registry), None).expect("pinned");
+        assert_eq!(
+            registry.requests, requests,
+            "pinned pulls come from the cache"
+        );
+
+        let other = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let other = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, other.as_ref(), &rng)
+            .unwrap();
+        let err = pull(&tagged, &cache, None, Some(other.public_key().as_ref())).unwrap_err();
+        assert!(err.contains("no signature by the given key"), "{err}");
+
+        // A tampered blob is neither used nor cached.
+        let mut evil = FakeRegistry::default();
+        evil.blobs.insert(layer.clone(), b"\0asm evil".to_vec());
+        let evil_digest = evil.manifest(
+            "2.0",
+            json!([{"mediaType": WASM_LAYER, "digest": layer, "size": 9}]),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        let err = pull(
+            &Reference::parse("oci://ghcr.io/acme/notes:2.0").unwrap(),
+            &cache,
+            Some(&mut evil),
+            None,
+        )
+        .unwrap_err();
+        assert!(err.contains("blob does not match"), "{err}");
+        assert!(cache.blob(&layer).is_none());
+        let err = pull(
+            &Reference::parse(&format!("oci://ghcr.io/acme/notes@{evil_digest}")).unwrap(),
+            &cache,
+            None,
+            None,
+        )
+        .unwrap_err();
+        assert!(err.contains("blob") && err.contains("--offline"), "{err}");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
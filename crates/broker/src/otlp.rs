@@ -0,0 +1,316 @@
+//! OpenTelemetry export of audit records.
+//!
+//! With `[audit.otlp]` in the [broker config](crate::config), every audit
+//! record — each host call, policy decision and lifecycle event — also
+//! becomes a span posted as OTLP/HTTP JSON to `<endpoint>/v1/traces`. The
+//! record's fields are `saf.*` attributes (`saf.path`, `saf.url`,
+//! `saf.bytes`, ...), its outcome is `saf.decision`, and denials and
+//! failures carry an error status. All spans of one broker process share a
+//! trace, so a run can be followed end to end.
+//!
+//! Spans are queued and posted in batches from a background thread. The
+//! broker never waits on the collector: while it is down, batches are
+//! retried with backoff and the oldest spans are dropped once `max_queue`
+//! is reached.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use saf_audit::{AuditRecord, AuditSink, Outcome};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OtlpConfig {
+    /// Collector base URL.
+    pub endpoint: String,
+    /// Sent with every request, e.g. an `authorization` header.
+    pub headers: BTreeMap<String, String>,
+    pub service_name: String,
+    /// Most spans per request.
+    pub batch_size: usize,
+    /// Longest a span waits before its batch is sent.
+    pub flush_interval_ms: u64,
+    /// Most spans held while the collector is unreachable.
+    pub max_queue: usize,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: "http://localhost:4318".to_string(),
+            headers: BTreeMap::new(),
+            service_name: "saf-broker".to_string(),
+            batch_size: 256,
+            flush_interval_ms: 2_000,
+            max_queue: 4_096,
+        }
+    }
+}
+
+pub struct OtlpExporter {
+    queue: Option<SyncSender<Value>>,
+    worker: Option<JoinHandle<()>>,
+    trace_id: String,
+    rng: SystemRandom,
+    dropped: Arc<AtomicU64>,
+}
+
+impl OtlpExporter {
+    pub fn new(config: OtlpConfig) -> Result<Self, String> {
+        let url = url::Url::parse(&config.endpoint)
+            .map_err(|e| format!("endpoint {:?}: {}", config.endpoint, e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!(
+                "endpoint {:?}: expected http:// or https://",
+                config.endpoint
+            ));
+        }
+        if config.batch_size == 0 || config.max_queue == 0 {
+            return Err("batch_size and max_queue must be at least 1".to_string());
+        }
+        let rng = SystemRandom::new();
+        let trace_id = random_hex(&rng, 16)?;
+        let dropped = Arc::new(AtomicU64::new(0));
+        let (queue, rx) = mpsc::sync_channel(config.max_queue);
+        let worker = Worker {
+            url: format!("{}/v1/traces", config.endpoint.trim_end_matches('/')),
+            config,
+            dropped: dropped.clone(),
+            agent: ureq::Agent::config_builder()
+                .timeout_global(Some(REQUEST_TIMEOUT))
+                .build()
+                .into(),
+        };
+        let worker = std::thread::Builder::new()
+            .name("otlp-export".to_string())
+            .spawn(move || worker.run(rx))
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            queue: Some(queue),
+            worker: Some(worker),
+            trace_id,
+            rng,
+            dropped,
+        })
+    }
+}
+
+impl AuditSink for OtlpExporter {
+    fn export(&mut self, record: &AuditRecord) -> Result<(), String> {
+        let span = span(record, &self.trace_id, &random_hex(&self.rng, 8)?)?;
+        let queue = self.queue.as_ref().ok_or("exporter stopped")?;
+        match queue.try_send(span) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                Err("OTLP queue full; span dropped".to_string())
+            }
+            Err(TrySendError::Disconnected(_)) => Err("OTLP exporter stopped".to_string()),
+        }
+    }
+}
+
+impl Drop for OtlpExporter {
+    /// Send what is queued, once, before the broker exits.
+    fn drop(&mut self) {
+        self.queue = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+struct Worker {
+    url: String,
+    config: OtlpConfig,
+    dropped: Arc<AtomicU64>,
+    agent: ureq::Agent,
+}
+
+impl Worker {
+    fn run(self, rx: Receiver<Value>) {
+        let interval = Duration::from_millis(self.config.flush_interval_ms);
+        let mut pending = VecDeque::new();
+        let mut last_flush = Instant::now();
+        let mut backoff = MIN_BACKOFF;
+        let mut retry_at = None;
+        loop {
+            if pending.is_empty() {
+                last_flush = Instant::now();
+            }
+            let deadline = retry_at.unwrap_or(last_flush + interval);
+            let open = match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(span) => {
+                    pending.push_back(span);
+                    pending.extend(rx.try_iter());
+                    true
+                }
+                Err(RecvTimeoutError::Timeout) => true,
+                Err(RecvTimeoutError::Disconnected) => false,
+            };
+            while pending.len() > self.config.max_queue {
+                pending.pop_front();
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            let now = Instant::now();
+            let due =
+                !open || pending.len() >= self.config.batch_size || now >= last_flush + interval;
+            if due && (!open || retry_at.is_none_or(|t| now >= t)) {
+                last_flush = now;
+                retry_at = None;
+                while !pending.is_empty() {
+                    let n = pending.len().min(self.config.batch_size);
+                    if self.send(pending.iter().take(n)).is_err() {
+                        retry_at = Some(Instant::now() + backoff);
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                        break;
+                    }
+                    pending.drain(..n);
+                    backoff = MIN_BACKOFF;
+                }
+            }
+            if !open {
+                return;
+            }
+        }
+    }
+
+    fn send<'a>(&self, spans: impl Iterator<Item = &'a Value>) -> Result<(), String> {
+        let body = json!({
+            "resourceSpans": [{
+                "resource": {"attributes": [
+                    attribute("service.name", &json!(self.config.service_name)),
+                    attribute("saf.spans_dropped", &json!(self.dropped.load(Ordering::Relaxed))),
+                ]},
+                "scopeSpans": [{
+                    "scope": {"name": "saf-broker", "version": env!("CARGO_PKG_VERSION")},
+                    "spans": spans.collect::<Vec<_>>(),
+                }],
+            }],
+        });
+        let mut request = self.agent.post(&self.url);
+        for (name, value) in &self.config.headers {
+            request = request.header(name, value);
+        }
+        request
+            .header("content-type", "application/json")
+            .send(body.to_string())
+            .map(drop)
+            .map_err(|e| format!("{}: {}", self.url, e))
+    }
+}
+
+/// `record` as an OTLP/JSON span.
+fn span(record: &AuditRecord, trace_id: &str, span_id: &str) -> Result<Value, String> {
+    let Value::Object(fields) = serde_json::to_value(record).map_err(|e| e.to_string())? else {
+        return Err("record is not an object".to_string());
+    };
+    let mut attributes = Vec::new();
+    for (key, value) in &fields {
+        let key = match key.as_str() {
+            "ts_ms" | "code" => continue,
+            "outcome" => "saf.decision".to_string(),
+            key => format!("saf.{key}"),
+        };
+        attributes.push(attribute(&key, value));
+    }
+    let nanos = (u128::from(record.ts_ms) * 1_000_000).to_string();
+    let status = match record.outcome {
+        Outcome::Denied | Outcome::Failed => json!({"code": 2, "message": record.code}),
+        Outcome::Ok | Outcome::WouldDeny => json!({"code": 0}),
+    };
+    Ok(json!({
+        "traceId": trace_id,
+        "spanId": span_id,
+        "name": record.code,
+        // SPAN_KIND_INTERNAL
+        "kind": 1,
+        "startTimeUnixNano": nanos,
+        "endTimeUnixNano": nanos,
+        "attributes": attributes,
+        "status": status,
+    }))
+}
+
+fn attribute(key: &str, value: &Value) -> Value {
+    json!({"key": key, "value": any_value(value)})
+}
+
+/// A JSON value as an OTLP `AnyValue`; 64-bit integers are strings.
+fn any_value(value: &Value) -> Value {
+    match value {
+        Value::Bool(b) => json!({"boolValue": b}),
+        Value::Number(n) if n.is_u64() || n.is_i64() => json!({"intValue": n.to_string()}),
+        Value::Number(n) => json!({"doubleValue": n.as_f64()}),
+        Value::String(s) => json!({"stringValue": s}),
+        Value::Array(items) => {
+            json!({"arrayValue": {"values": items.iter().map(any_value).collect::<Vec<_>>()}})
+        }
+        Value::Null | Value::Object(_) => json!({"stringValue": value.to_string()}),
+    }
+}
+
+fn random_hex(rng: &SystemRandom, len: usize) -> Result<String, String> {
+    let mut bytes = vec![0; len];
+    rng.fill(&mut bytes)
+        .map_err(|_| "no randomness available".to_string())?;
+    Ok(hex::encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use saf_audit::AuditEvent;
+    use saf_core::Code;
+
+    #[test]
+    fn records_become_spans_with_field_attributes() {
+        let mut record = AuditRecord::new(
+            Code::PolicyPathDenied,
+            AuditEvent::PolicyDenied {
+                capability: "fs".to_string(),
+                target: "secrets/key".to_string(),
+                rule: "deny[0]".to_string(),
+            },
+        )
+        .with_outcome(Outcome::Denied)
+        .with_component("app");
+        record.ts_ms = 1_700_000_000_123;
+        let span = span(&record, "ab".repeat(16).as_str(), "cd".repeat(8).as_str()).expect("span");
+        assert_eq!(span["name"], "policy.path_denied");
+        assert_eq!(span["startTimeUnixNano"], "1700000000123000000");
+        assert_eq!(span["status"]["code"], 2);
+        let attributes = span["attributes"].as_array().expect("attributes");
+        assert!(attributes
+            .contains(&json!({"key": "saf.decision", "value": {"stringValue": "denied"}})));
+        assert!(attributes
+            .contains(&json!({"key": "saf.target", "value": {"stringValue": "secrets/key"}})));
+        assert!(
+            attributes.contains(&json!({"key": "saf.component", "value": {"stringValue": "app"}}))
+        );
+
+        let read = AuditRecord::new(
+            Code::FsReadText,
+            AuditEvent::FsRead {
+                path: "docs/a.md".to_string(),
+                bytes: 5,
+                offset: None,
+            },
+        );
+        let span = super::span(&read, "", "").expect("span");
+        let attributes = span["attributes"].as_array().expect("attributes");
+        assert!(attributes.contains(&json!({"key": "saf.bytes", "value": {"intValue": "5"}})));
+        assert_eq!(span["status"]["code"], 0);
+    }
+}
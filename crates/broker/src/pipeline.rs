@@ -0,0 +1,408 @@
+//! `broker pipeline run`: components run one after another, each taking
+//! the output of the one before as its input.
+//!
+//! A pipeline manifest lists the stages in order:
+//!
+//! ```toml
+//! name = "nightly-etl"
+//! input = '{"since": "2024-01-01"}'   # optional: the first stage's input
+//!
+//! [[stage]]
+//! name = "extract"                    # optional: the component's name
+//! component = "extract.wasm"          # relative to the manifest
+//! policy = "policies/extract.toml"    # optional: default, the workspace's
+//! offline = false                     # optional
+//! args = { source = "inbox" }         # optional: as `broker run --arg`
+//! config = { region = "eu" }          # optional: as `broker run --config`
+//!
+//! [[stage]]
+//! installed = "loader"                # an installed component
+//! ```
+//!
+//! Each stage is a run of its own, in the same workspace, under its own
+//! policy: it is audited, reported under `.saf/runs/` and limited by its
+//! component's manifest like any other run, and records a `pipeline.stage`
+//! entry naming the pipeline it is part of. What a stage's `start` returns
+//! is handed to the next stage's `init` (see the `app-with-input` world),
+//! so it must be JSON. The pipeline stops at the first stage that fails.
+//!
+//! The pipeline's report, listing each stage's run, outcome and resources,
+//! is written to `.saf/pipelines/<id>/report.json`.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::{Args, Subcommand};
+use serde::{Deserialize, Serialize};
+
+use crate::cli::{self, RunArgs};
+use crate::components;
+use crate::runs::{self, Resources, RunOutcome};
+use crate::workspace_picker::WorkspaceStore;
+
+#[derive(Debug, Subcommand)]
+pub enum PipelineCommand {
+    /// Run the stages of a pipeline manifest in order.
+    Run(PipelineArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct PipelineArgs {
+    /// The pipeline manifest.
+    #[arg(value_name = "PATH")]
+    pub manifest: PathBuf,
+    /// Restore a previously saved workspace (default: the current
+    /// directory).
+    #[arg(long, value_name = "ID")]
+    pub workspace_id: Option<String>,
+    /// Disable all network access for every stage.
+    #[arg(long)]
+    pub offline: bool,
+    /// Log policy denials as would-deny and let operations proceed.
+    #[arg(long)]
+    pub policy_dry_run: bool,
+    /// Refuse policy files without a trusted signature (<file>.sig).
+    #[arg(long)]
+    pub require_signed_policy: bool,
+    /// Print the pipeline's report as JSON.
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// A pipeline manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pipeline {
+    pub name: String,
+    /// JSON handed to the first stage.
+    #[serde(default)]
+    pub input: Option<String>,
+    #[serde(rename = "stage", default)]
+    pub stages: Vec<Stage>,
+}
+
+/// One `[[stage]]`: a component file or an installed component.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stage {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// A component file, relative to the manifest unless absolute.
+    #[serde(default)]
+    pub component: Option<PathBuf>,
+    /// The name of an installed component.
+    #[serde(default)]
+    pub installed: Option<String>,
+    /// The policy file for this stage, relative to the manifest unless
+    /// absolute.
+    #[serde(default)]
+    pub policy: Option<PathBuf>,
+    #[serde(default)]
+    pub offline: bool,
+    #[serde(default)]
+    pub args: BTreeMap<String, String>,
+    #[serde(default)]
+    pub config: BTreeMap<String, String>,
+}
+
+impl Pipeline {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read pipeline {}: {}", path.display(), e))?;
+        Self::parse(&content).map_err(|e| format!("invalid pipeline {}: {}", path.display(), e))
+    }
+
+    fn parse(content: &str) -> Result<Self, String> {
+        let pipeline: Self = toml::from_str(content).map_err(|e| e.to_string())?;
+        if pipeline.stages.is_empty() {
+            return Err("a pipeline needs at least one [[stage]]".to_string());
+        }
+        if let Some(input) = &pipeline.input {
+            cli::parse_input(input).map_err(|e| format!("input: {e}"))?;
+        }
+        for (i, stage) in pipeline.stages.iter().enumerate() {
+            if stage.component.is_some() == stage.installed.is_some() {
+                return Err(format!(
+                    "stage {}: give either `component` or `installed`",
+                    i + 1
+                ));
+            }
+            let name = stage.name();
+            if pipeline.stages[..i].iter().any(|s| s.name() == name) {
+                return Err(format!("two stages are named {name}"));
+            }
+        }
+        Ok(pipeline)
+    }
+}
+
+impl Stage {
+    /// The stage's name: as given, or its component's.
+    pub fn name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        match (&self.installed, &self.component) {
+            (Some(installed), _) => installed.clone(),
+            (None, Some(path)) => path
+                .file_stem()
+                .map_or_else(String::new, |s| s.to_string_lossy().into_owned()),
+            (None, None) => String::new(),
+        }
+    }
+
+    /// The component file this stage runs.
+    fn component_path(&self, manifest: &Path) -> Result<PathBuf, String> {
+        match (&self.installed, &self.component) {
+            (Some(installed), _) => components::installed_path(installed),
+            (None, Some(path)) => Ok(relative_to(manifest, path)),
+            (None, None) => Err(format!("stage {} has no component", self.name())),
+        }
+    }
+}
+
+fn relative_to(manifest: &Path, path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        return path.to_path_buf();
+    }
+    manifest
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(path)
+}
+
+/// The pipeline a run is a stage of, for its `pipeline.stage` entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipelineStage {
+    pub pipeline: String,
+    pub pipeline_id: String,
+    pub stage: String,
+    /// Where the stage is in the pipeline, counting from 1.
+    pub position: u64,
+    pub stages: u64,
+}
+
+/// How a pipeline went, written to `report.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipelineReport {
+    pub pipeline_id: String,
+    pub name: String,
+    pub started_unix: u64,
+    pub finished_unix: u64,
+    /// The stages that were run, in order; those after a failed one are
+    /// not.
+    pub stages: Vec<StageReport>,
+    /// The last stage's output, or the first failure.
+    pub outcome: RunOutcome,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageReport {
+    pub name: String,
+    /// The stage's run, unless it failed before it got one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    pub outcome: RunOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<Resources>,
+}
+
+impl PipelineReport {
+    pub fn write(&self, workspace: &Path) -> Result<PathBuf, String> {
+        let dir = workspace
+            .join(".saf")
+            .join("pipelines")
+            .join(&self.pipeline_id);
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let path = dir.join("report.json");
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(&path, content).map_err(|e| e.to_string())?;
+        Ok(path)
+    }
+}
+
+/// The input of the stage after one that returned `output`.
+fn next_input(stage: &str, output: &str) -> Result<String, String> {
+    cli::parse_input(output).map_err(|e| {
+        format!("stage {stage} returned output that is {e}, so it cannot be passed on")
+    })
+}
+
+pub async fn main(command: PipelineCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let PipelineCommand::Run(args) = command;
+    let pipeline = Pipeline::load(&args.manifest)?;
+    let workspace = match &args.workspace_id {
+        Some(id) => WorkspaceStore::new()?.load_workspace(id)?.0,
+        None => std::env::current_dir()?,
+    };
+
+    let pipeline_id = format!("pipe_{}", uuid::Uuid::new_v4().simple());
+    let started_unix = runs::now_unix_seconds();
+    let mut stages = Vec::new();
+    let mut input = pipeline.input.clone();
+    let mut outcome = RunOutcome::Ok {
+        output: String::new(),
+    };
+    for (i, stage) in pipeline.stages.iter().enumerate() {
+        let name = stage.name();
+        let run = match stage.component_path(&args.manifest) {
+            Ok(component) => {
+                let run_args = RunArgs {
+                    workspace_id: args.workspace_id.clone(),
+                    run_component: Some(component),
+                    policy: stage
+                        .policy
+                        .as_deref()
+                        .map(|p| relative_to(&args.manifest, p)),
+                    offline: args.offline || stage.offline,
+                    policy_dry_run: args.policy_dry_run,
+                    require_signed_policy: args.require_signed_policy,
+                    headless: true,
+                    json: true,
+                    input: input.take(),
+                    args: stage.args.clone().into_iter().collect(),
+                    config: stage.config.clone().into_iter().collect(),
+                    pipeline: Some(Arc::new(PipelineStage {
+                        pipeline: pipeline.name.clone(),
+                        pipeline_id: pipeline_id.clone(),
+                        stage: name.clone(),
+                        position: i as u64 + 1,
+                        stages: pipeline.stages.len() as u64,
+                    })),
+                    ..RunArgs::default()
+                };
+                crate::run(run_args, false).await.map_err(|e| e.to_string())
+            }
+            Err(e) => Err(e),
+        };
+        let report = match run {
+            Ok(Some(report)) => StageReport {
+                name: name.clone(),
+                run_id: Some(report.run_id),
+                outcome: report.outcome,
+                resources: report.resources,
+            },
+            Ok(None) => StageReport {
+                name: name.clone(),
+                run_id: None,
+                outcome: RunOutcome::Failed {
+                    error: "the run did not produce a report".to_string(),
+                },
+                resources: None,
+            },
+            Err(error) => StageReport {
+                name: name.clone(),
+                run_id: None,
+                outcome: RunOutcome::Failed { error },
+                resources: None,
+            },
+        };
+        if !args.json {
+            match &report.outcome {
+                RunOutcome::Ok { .. } => println!("stage {name}: ok"),
+                RunOutcome::Failed { error } => println!("stage {name}: failed: {error}"),
+            }
+        }
+        outcome = match &report.outcome {
+            RunOutcome::Ok { output } if i + 1 < pipeline.stages.len() => {
+                match next_input(&name, output) {
+                    Ok(next) => {
+                        input = Some(next);
+                        report.outcome.clone()
+                    }
+                    Err(error) => RunOutcome::Failed { error },
+                }
+            }
+            RunOutcome::Ok { .. } => report.outcome.clone(),
+            RunOutcome::Failed { error } => RunOutcome::Failed {
+                error: format!("stage {name}: {error}"),
+            },
+        };
+        stages.push(report);
+        if matches!(outcome, RunOutcome::Failed { .. }) {
+            break;
+        }
+    }
+
+    let report = PipelineReport {
+        pipeline_id,
+        name: pipeline.name,
+        started_unix,
+        finished_unix: runs::now_unix_seconds(),
+        stages,
+        outcome,
+    };
+    let path = report.write(&workspace)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("pipeline report: {}", path.display());
+    }
+    match report.outcome {
+        RunOutcome::Ok { output } => {
+            if !args.json {
+                println!("output: {output}");
+            }
+            Ok(())
+        }
+        RunOutcome::Failed { error } => Err(format!("Pipeline failed: {error}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages_are_checked_and_chained() {
+        let pipeline = Pipeline::parse(
+            r#"
+            name = "etl"
+            input = '{"since": "2024-01-01"}'
+
+            [[stage]]
+            component = "bin/extract.wasm"
+            policy = "/etc/saf/extract.toml"
+            args = { source = "inbox" }
+
+            [[stage]]
+            name = "load"
+            installed = "loader"
+            offline = true
+            "#,
+        )
+        .expect("pipeline");
+        let names: Vec<_> = pipeline.stages.iter().map(Stage::name).collect();
+        assert_eq!(names, ["extract", "load"]);
+        let manifest = Path::new("/work/etl.toml");
+        assert_eq!(
+            pipeline.stages[0].component_path(manifest),
+            Ok(PathBuf::from("/work/bin/extract.wasm"))
+        );
+        assert_eq!(
+            relative_to(manifest, pipeline.stages[0].policy.as_deref().unwrap()),
+            PathBuf::from("/etc/saf/extract.toml")
+        );
+
+        assert!(Pipeline::parse("name = \"empty\"").is_err());
+        assert!(
+            Pipeline::parse("name = \"x\"\ninput = \"{\"\n[[stage]]\ncomponent = \"a.wasm\"")
+                .is_err()
+        );
+        assert!(Pipeline::parse(
+            "name = \"x\"\n[[stage]]\ncomponent = \"a.wasm\"\ninstalled = \"a\""
+        )
+        .is_err());
+        assert!(Pipeline::parse(
+            "name = \"x\"\n[[stage]]\ncomponent = \"a.wasm\"\n[[stage]]\ncomponent = \"b/a.wasm\""
+        )
+        .is_err());
+
+        assert_eq!(
+            next_input("extract", r#"{"rows": 3}"#),
+            Ok(r#"{"rows": 3}"#.to_string())
+        );
+        assert!(next_input("extract", "3 rows").is_err());
+    }
+}
@@ -0,0 +1,57 @@
+//! `broker policy check`: the problems in a policy file.
+//!
+//! Loads the policy the way a run would, following `extends`, so a policy
+//! that fails here fails at launch too. A policy that loads may still carry
+//! warnings, e.g. rules other rules make unreachable; those are listed and
+//! do not fail the check.
+
+use std::path::PathBuf;
+
+use clap::Args;
+use saf_policy::Policy;
+use serde_json::json;
+
+#[derive(Debug, Clone, Args)]
+pub struct CheckArgs {
+    #[arg(default_value = ".saf/policy.toml")]
+    pub policy: PathBuf,
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Entry point for `broker policy check [<POLICY>] [--json]`.
+pub fn main(args: CheckArgs) -> Result<(), String> {
+    let policy = Policy::from_file(&args.policy);
+    if args.json {
+        let report = match &policy {
+            Ok(policy) => json!({
+                "policy": args.policy,
+                "valid": true,
+                "issues": policy
+                    .issues()
+                    .iter()
+                    .map(|i| json!({
+                        "severity": i.severity.as_str(),
+                        "field": i.field,
+                        "message": i.message,
+                    }))
+                    .collect::<Vec<_>>(),
+            }),
+            Err(e) => json!({"policy": args.policy, "valid": false, "error": e}),
+        };
+        let out = serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?;
+        println!("{out}");
+        return policy.map(drop);
+    }
+    let policy = policy?;
+    let issues = policy.issues();
+    for issue in &issues {
+        println!("{}: {issue}", issue.severity.as_str());
+    }
+    println!(
+        "{}: valid, {} warning(s)",
+        args.policy.display(),
+        issues.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,224 @@
+//! `broker policy explain` and `broker policy diff`.
+//!
+//! `explain` lists the rules matching one operation in the order the
+//! broker consults them, then the decision `broker policy simulate` would
+//! report. `diff` lists what changed between two policy files and whether
+//! each change widens or narrows a component's permissions.
+
+use std::path::PathBuf;
+
+use clap::Args;
+use saf_core::Code;
+use saf_policy::explain::{diff, Effect, Match};
+use saf_policy::Policy;
+use serde_json::json;
+
+use crate::policy_sim::{self, Operation};
+
+/// The matched rules and decision for `op`, one line each.
+fn explain(policy: &Policy, op: &Operation, component: &str, now: u64) -> Vec<String> {
+    let matches: Vec<Match> = match op {
+        Operation::Fs { access, path, .. } => match policy_sim::fs_path(path) {
+            Ok(rel) => policy.explain_fs(
+                &rel,
+                *access,
+                &policy_sim::attributes(op, &rel, component, now),
+            ),
+            Err(_) => Vec::new(),
+        },
+        Operation::Net { method, url } => policy.explain_request(
+            Some(method),
+            url,
+            &policy_sim::attributes(op, "", component, now),
+        ),
+        Operation::Ws { url } => {
+            policy.explain_request(None, url, &policy_sim::attributes(op, "", component, now))
+        }
+        Operation::Ip(_) => Vec::new(),
+    };
+    let mut lines = vec!["matched rules, in order:".to_string()];
+    if matches.is_empty() {
+        lines.push("  (none)".to_string());
+    }
+    lines.extend(
+        matches
+            .iter()
+            .enumerate()
+            .map(|(i, m)| format!("  {}. {m}", i + 1)),
+    );
+    lines.push(match policy_sim::decide(policy, op, component, now) {
+        Ok(()) => "decision: allow".to_string(),
+        Err(d) if d.code == Code::PolicyAsk => format!("decision: ask rule={}", d.rule_id),
+        Err(d) => format!("decision: deny {} rule={}", d.code, d.rule_id),
+    });
+    lines
+}
+
+/// The operation line `broker policy simulate` takes for the flags given
+/// to `explain`.
+fn operation_line(
+    op: &str,
+    path: Option<&str>,
+    url: Option<&str>,
+    method: Option<&str>,
+    size: Option<&str>,
+) -> Result<String, String> {
+    fn need<'a>(value: Option<&'a str>, op: &str, flag: &str) -> Result<&'a str, String> {
+        value.ok_or_else(|| format!("--op {op} needs {flag}"))
+    }
+    Ok(match op {
+        "fs.read" | "fs.write" | "fs.list" => {
+            let access = op.trim_start_matches("fs.");
+            let line = format!("fs {access} {}", need(path, op, "--path")?);
+            match size {
+                Some(size) => format!("{line} {size}"),
+                None => line,
+            }
+        }
+        "net" => format!(
+            "net {} {}",
+            method.unwrap_or("GET"),
+            need(url, op, "--url")?
+        ),
+        "ws" => format!("ws {}", need(url, op, "--url")?),
+        _ => {
+            return Err(format!(
+                "unknown operation {op:?}; expected fs.read, fs.write, fs.list, net or ws"
+            ))
+        }
+    })
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ExplainArgs {
+    /// fs.read, fs.write, fs.list, net or ws.
+    #[arg(long)]
+    pub op: String,
+    #[arg(long)]
+    pub path: Option<String>,
+    #[arg(long)]
+    pub url: Option<String>,
+    /// For `net` (default: GET).
+    #[arg(long)]
+    pub method: Option<String>,
+    /// For `fs.write`, in bytes.
+    #[arg(long, value_name = "BYTES")]
+    pub size: Option<String>,
+    #[arg(long, default_value = ".saf/policy.toml")]
+    pub policy: PathBuf,
+    /// Explain the policy as it applies to this component.
+    #[arg(long, value_name = "ID")]
+    pub component: Option<String>,
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct DiffArgs {
+    pub old: PathBuf,
+    pub new: PathBuf,
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Entry point for `broker policy explain`.
+pub fn main(args: ExplainArgs) -> Result<(), String> {
+    let line = operation_line(
+        &args.op,
+        args.path.as_deref(),
+        args.url.as_deref(),
+        args.method.as_deref(),
+        args.size.as_deref(),
+    )?;
+    let op = policy_sim::parse(&line)?.ok_or("--op: expected an operation")?;
+    let component = args.component.as_deref();
+    let (policy, notes) = policy_sim::load(&args.policy, component)?;
+    let lines = explain(
+        &policy,
+        &op,
+        component.unwrap_or_default(),
+        policy_sim::now(),
+    );
+    if args.json {
+        let out = serde_json::to_string_pretty(&json!({
+            "operation": line,
+            "notes": notes,
+            "lines": lines,
+        }))
+        .map_err(|e| e.to_string())?;
+        println!("{out}");
+        return Ok(());
+    }
+    for note in notes {
+        println!("# {note}");
+    }
+    println!("operation: {line}");
+    for l in lines {
+        println!("{l}");
+    }
+    Ok(())
+}
+
+/// Entry point for `broker policy diff`.
+pub fn diff_main(args: DiffArgs) -> Result<(), String> {
+    let changes = diff(
+        &Policy::from_file(&args.old)?,
+        &Policy::from_file(&args.new)?,
+    );
+    let wider = changes.iter().filter(|c| c.effect == Effect::Wider).count();
+    if args.json {
+        let out = serde_json::to_string_pretty(&json!({
+            "changes": changes
+                .iter()
+                .map(|c| json!({
+                    "effect": c.effect.as_str(),
+                    "field": c.field,
+                    "change": c.change,
+                }))
+                .collect::<Vec<_>>(),
+            "wider": wider,
+        }))
+        .map_err(|e| e.to_string())?;
+        println!("{out}");
+        return Ok(());
+    }
+    if changes.is_empty() {
+        println!("no changes");
+    }
+    for change in &changes {
+        println!("{change}");
+    }
+    if wider > 0 {
+        println!("{wider} change(s) widen what components may do");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explains_rules_in_order_with_the_decision() {
+        let policy = Policy::from_toml_str(
+            r#"
+            allowed_paths = ["exports"]
+            read_only = ["exports/*.txt"]
+            "#,
+        )
+        .expect("policy");
+        let line =
+            operation_line("fs.write", Some("exports/a.txt"), None, None, None).expect("line");
+        let op = policy_sim::parse(&line).expect("parse").expect("op");
+        assert_eq!(
+            explain(&policy, &op, "app", 0),
+            [
+                "matched rules, in order:",
+                "  1. read_only[0] \"exports/*.txt\"",
+                "  2. allowed_paths[0] \"exports\"",
+                "decision: deny policy.path_read_only rule=read_only[0]",
+            ]
+        );
+        assert!(operation_line("net", None, None, None, None).is_err());
+    }
+}
@@ -0,0 +1,427 @@
+//! Signed policy files.
+//!
+//! A policy file may carry a detached ed25519 signature in `<file>.sig`:
+//! a sequence number and the base64 signature over that number and the
+//! file's exact bytes. It is checked against the trusted keys in
+//! `<config_dir>/secure-app-framework/trusted_policy_keys`, one base64
+//! public key per line, `#` starting a comment. The highest sequence each
+//! key has signed for a file is kept beside the keys, in
+//! `policy_sequences.json`, and an older one is refused, so a file cannot
+//! be put back to a version signed before. A signature that
+//! is present but does not verify is always fatal; with
+//! `--require-signed-policy` unsigned files are refused too, for managed
+//! deployments where only the administrator's policies may load. Hot
+//! reloads are held to the same rule.
+//!
+//! `broker policy keygen` and `broker policy sign` create a key pair and
+//! signatures in this format, and `broker policy trust` adds a public key
+//! to the trusted keys. All three need an elevated session.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use clap::Args;
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use saf_core::Code;
+use serde::{Deserialize, Serialize};
+
+use crate::run_manifest::sha256_hex;
+
+#[derive(Debug, Clone)]
+pub struct Verifier {
+    keys: Vec<Vec<u8>>,
+    keys_path: PathBuf,
+    /// The highest sequence accepted per key and file.
+    sequences_path: PathBuf,
+    require: bool,
+}
+
+/// The last signature accepted for one key and file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Accepted {
+    sequence: u64,
+    sha256: String,
+}
+
+impl Verifier {
+    pub fn keys_path() -> Result<PathBuf, String> {
+        Ok(dirs::config_dir()
+            .ok_or("No config directory available")?
+            .join("secure-app-framework")
+            .join("trusted_policy_keys"))
+    }
+
+    /// Read the trusted keys at `path`; a missing file trusts no keys.
+    pub fn load(path: &Path, require: bool) -> Result<Self, String> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        let mut keys = Vec::new();
+        for (n, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            match BASE64.decode(line) {
+                Ok(key) if key.len() == 32 => keys.push(key),
+                _ => {
+                    return Err(format!(
+                        "{}:{}: expected a base64 ed25519 public key",
+                        path.display(),
+                        n + 1
+                    ))
+                }
+            }
+        }
+        Ok(Self {
+            keys,
+            keys_path: path.to_path_buf(),
+            sequences_path: path.with_file_name("policy_sequences.json"),
+            require,
+        })
+    }
+
+    pub fn sig_path(policy: &Path) -> PathBuf {
+        let mut name = policy.as_os_str().to_owned();
+        name.push(".sig");
+        PathBuf::from(name)
+    }
+
+    /// Check `content` read from `policy` against `signature`, the contents
+    /// of its `.sig` file if there is one, and record its sequence. Returns
+    /// the fingerprint of the signing key, or `None` for an unsigned file
+    /// that may load anyway.
+    pub fn verify(
+        &self,
+        policy: &Path,
+        content: &[u8],
+        signature: Option<&[u8]>,
+    ) -> Result<Option<String>, String> {
+        let rejected = |why: String| {
+            Code::PolicySignatureRejected.with_message(&format!("{}: {why}", policy.display()))
+        };
+        let Some(signature) = signature else {
+            return if self.require {
+                Err(rejected(
+                    "not signed, and --require-signed-policy is set".to_string(),
+                ))
+            } else {
+                Ok(None)
+            };
+        };
+        let (sequence, signature) = std::str::from_utf8(signature)
+            .ok()
+            .and_then(|s| s.trim().split_once(' '))
+            .and_then(|(n, sig)| Some((n.parse::<u64>().ok()?, BASE64.decode(sig).ok()?)))
+            .ok_or_else(|| {
+                rejected(
+                    "signature file is not a sequence number and a base64 signature; \
+                     sign the file again with `broker policy sign`"
+                        .to_string(),
+                )
+            })?;
+        if self.keys.is_empty() {
+            return Err(rejected(format!(
+                "signed, but no trusted keys are configured in {}",
+                self.keys_path.display()
+            )));
+        }
+        let message = signed_message(sequence, content);
+        let key = self
+            .keys
+            .iter()
+            .find(|key| {
+                UnparsedPublicKey::new(&ED25519, key)
+                    .verify(&message, &signature)
+                    .is_ok()
+            })
+            .map(|key| fingerprint(key))
+            .ok_or_else(|| rejected("signature does not match any trusted key".to_string()))?;
+        self.accept(policy, &key, sequence, content)
+            .map_err(rejected)?;
+        Ok(Some(key))
+    }
+
+    /// Record `sequence` as signed by `key` for `policy`, unless an older
+    /// one than already seen, or the same one over different bytes.
+    fn accept(
+        &self,
+        policy: &Path,
+        key: &str,
+        sequence: u64,
+        content: &[u8],
+    ) -> Result<(), String> {
+        let mut seen: BTreeMap<String, Accepted> = match std::fs::read(&self.sequences_path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| format!("{}: {e}", self.sequences_path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(format!("{}: {e}", self.sequences_path.display())),
+        };
+        let file = policy
+            .canonicalize()
+            .unwrap_or_else(|_| policy.to_path_buf());
+        let entry = format!("{key} {}", file.display());
+        let sha256 = sha256_hex(content);
+        match seen.get(&entry) {
+            Some(last) if last.sequence > sequence => {
+                return Err(format!(
+                    "signature sequence {sequence} is older than {} already accepted",
+                    last.sequence
+                ))
+            }
+            Some(last) if last.sequence == sequence && last.sha256 != sha256 => {
+                return Err(format!(
+                    "signature sequence {sequence} was already accepted for other contents"
+                ))
+            }
+            Some(last) if last.sequence == sequence => return Ok(()),
+            _ => {}
+        }
+        seen.insert(entry, Accepted { sequence, sha256 });
+        if let Some(parent) = self.sequences_path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("{}: {e}", parent.display()))?;
+        }
+        let json = serde_json::to_vec_pretty(&seen).map_err(|e| e.to_string())?;
+        std::fs::write(&self.sequences_path, json)
+            .map_err(|e| format!("{}: {e}", self.sequences_path.display()))
+    }
+
+    /// [`Verifier::verify`] with the signature read from beside `policy`.
+    pub fn verify_file(&self, policy: &Path, content: &[u8]) -> Result<Option<String>, String> {
+        let signature = std::fs::read(Self::sig_path(policy)).ok();
+        self.verify(policy, content, signature.as_deref())
+    }
+}
+
+/// Short, stable name for a public key in audit events.
+pub fn fingerprint(key: &[u8]) -> String {
+    format!("sha256:{}", &sha256_hex(key)[..16])
+}
+
+/// The base64 signature over `content` with the PKCS#8 key `pkcs8`.
+pub fn sign(pkcs8: &[u8], content: &[u8]) -> Result<String, String> {
+    let pair =
+        Ed25519KeyPair::from_pkcs8(pkcs8).map_err(|e| format!("invalid signing key: {e}"))?;
+    Ok(BASE64.encode(pair.sign(content)))
+}
+
+/// `<file>.sig` for `content` at `sequence`, signed with `pkcs8`.
+pub fn sign_file(pkcs8: &[u8], sequence: u64, content: &[u8]) -> Result<String, String> {
+    Ok(format!(
+        "{sequence} {}\n",
+        sign(pkcs8, &signed_message(sequence, content))?
+    ))
+}
+
+/// What a policy signature covers: the sequence, then the file.
+fn signed_message(sequence: u64, content: &[u8]) -> Vec<u8> {
+    let mut message = format!("saf-policy-signature {sequence}\n").into_bytes();
+    message.extend_from_slice(content);
+    message
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct KeygenArgs {
+    /// Where to write the private key; must not exist yet.
+    pub key_file: PathBuf,
+    /// Workspace whose audit log records a refusal (default: the current
+    /// directory).
+    #[arg(long, value_name = "DIR")]
+    pub workspace: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct SignArgs {
+    pub policy: PathBuf,
+    pub key_file: PathBuf,
+    /// The signature's sequence number, higher than any earlier signature
+    /// of the file (default: the current time in seconds).
+    #[arg(long, value_name = "N")]
+    pub sequence: Option<u64>,
+    /// Workspace whose audit log records a refusal (default: the current
+    /// directory).
+    #[arg(long, value_name = "DIR")]
+    pub workspace: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct TrustArgs {
+    /// The base64 public key `broker policy keygen` printed.
+    pub public_key: String,
+    /// Workspace whose audit log records a refusal (default: the current
+    /// directory).
+    #[arg(long, value_name = "DIR")]
+    pub workspace: Option<PathBuf>,
+}
+
+/// Entry point for `broker policy keygen <KEY_FILE>`.
+pub fn keygen(args: KeygenArgs) -> Result<(), String> {
+    let key_path = &args.key_file;
+    let workspace = args.workspace.unwrap_or_else(|| PathBuf::from("."));
+    if key_path.exists() {
+        return Err(format!("{} already exists", key_path.display()));
+    }
+    crate::elevation::require(&workspace, "creating a policy signing key")?;
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new())
+        .map_err(|e| format!("key generation failed: {e}"))?;
+    let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).map_err(|e| e.to_string())?;
+    crate::elevation::write_private(key_path, pkcs8.as_ref())?;
+    let public = pair.public_key().as_ref();
+    println!("Wrote signing key to {}", key_path.display());
+    println!(
+        "Add this line to {} on managed machines:",
+        Verifier::keys_path()?.display()
+    );
+    println!("{}  # {}", BASE64.encode(public), fingerprint(public));
+    Ok(())
+}
+
+/// Entry point for `broker policy sign <POLICY> <KEY_FILE>`.
+pub fn sign_main(args: SignArgs) -> Result<(), String> {
+    let policy = &args.policy;
+    let workspace = args.workspace.unwrap_or_else(|| PathBuf::from("."));
+    crate::elevation::require(&workspace, "signing a policy")?;
+    let content = std::fs::read(policy).map_err(|e| format!("{}: {}", policy.display(), e))?;
+    let pkcs8 =
+        std::fs::read(&args.key_file).map_err(|e| format!("{}: {e}", args.key_file.display()))?;
+    let sequence = args.sequence.unwrap_or_else(crate::runs::now_unix_seconds);
+    let sig_path = Verifier::sig_path(policy);
+    std::fs::write(&sig_path, sign_file(&pkcs8, sequence, &content)?)
+        .map_err(|e| format!("{}: {}", sig_path.display(), e))?;
+    println!("Wrote {} (sequence {sequence})", sig_path.display());
+    Ok(())
+}
+
+/// Entry point for `broker policy trust <PUBLIC_KEY>`.
+pub fn trust(args: TrustArgs) -> Result<(), String> {
+    let key = BASE64
+        .decode(args.public_key.trim())
+        .ok()
+        .filter(|k| k.len() == 32)
+        .ok_or("expected a base64 ed25519 public key")?;
+    let workspace = args.workspace.unwrap_or_else(|| PathBuf::from("."));
+    crate::elevation::require(&workspace, "trusting a policy signing key")?;
+    let path = Verifier::keys_path()?;
+    if add_trusted(&path, &key)? {
+        println!("Trusted {} in {}", fingerprint(&key), path.display());
+    } else {
+        println!("{} is already trusted", fingerprint(&key));
+    }
+    Ok(())
+}
+
+/// Add `key` to the trusted keys at `path`. Returns whether it was new.
+fn add_trusted(path: &Path, key: &[u8]) -> Result<bool, String> {
+    if Verifier::load(path, false)?.keys.iter().any(|k| k == key) {
+        return Ok(false);
+    }
+    let mut content = match std::fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&format!("{}  # {}\n", BASE64.encode(key), fingerprint(key)));
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("{}: {}", parent.display(), e))?;
+    }
+    std::fs::write(path, content).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_must_come_from_a_trusted_key() {
+        let rng = ring::rand::SystemRandom::new();
+        let trusted = Ed25519KeyPair::generate_pkcs8(&rng).expect("key");
+        let other = Ed25519KeyPair::generate_pkcs8(&rng).expect("key");
+        let public = Ed25519KeyPair::from_pkcs8(trusted.as_ref())
+            .expect("pair")
+            .public_key()
+            .as_ref()
+            .to_vec();
+
+        let dir = std::env::temp_dir().join(format!("saf-keys-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("dir");
+        let keys = dir.join("trusted_policy_keys");
+        std::fs::write(
+            &keys,
+            format!("# ops\n{}  # laptop\n", BASE64.encode(&public)),
+        )
+        .expect("write");
+        let verifier = Verifier::load(&keys, false).expect("load");
+        let strict = Verifier::load(&keys, true).expect("load");
+        let other_public = Ed25519KeyPair::from_pkcs8(other.as_ref())
+            .expect("pair")
+            .public_key()
+            .as_ref()
+            .to_vec();
+        assert_eq!(add_trusted(&keys, &public), Ok(false));
+        assert_eq!(add_trusted(&keys, &other_public), Ok(true));
+        assert_eq!(Verifier::load(&keys, false).expect("load").keys.len(), 2);
+        std::fs::write(
+            &keys,
+            format!("# ops\n{}  # laptop\n", BASE64.encode(&public)),
+        )
+        .expect("write");
+
+        let path = dir.join("policy.toml");
+        let path = path.as_path();
+        let content = b"allowed_domains = [\"example.org\"]\n";
+        let good = sign_file(trusted.as_ref(), 2, content).expect("sign");
+        assert_eq!(
+            verifier.verify(path, content, Some(good.as_bytes())),
+            Ok(Some(fingerprint(&public)))
+        );
+        let tampered = b"allowed_domains = [\"evil.test\"]\n";
+        assert!(verifier
+            .verify(path, tampered, Some(good.as_bytes()))
+            .is_err());
+        let untrusted = sign_file(other.as_ref(), 3, content).expect("sign");
+        assert!(verifier
+            .verify(path, content, Some(untrusted.as_bytes()))
+            .is_err());
+        // A bare signature, with no sequence, is not enough.
+        let bare = sign(trusted.as_ref(), content).expect("sign");
+        assert!(verifier
+            .verify(path, content, Some(bare.as_bytes()))
+            .is_err());
+
+        // Once sequence 2 is accepted, an older signed version is refused,
+        // as is another file under the same sequence.
+        let old = b"allowed_domains = [\"*\"]\n";
+        let replayed = sign_file(trusted.as_ref(), 1, old).expect("sign");
+        let err = verifier
+            .verify(path, old, Some(replayed.as_bytes()))
+            .unwrap_err();
+        assert!(err.contains("older than 2"), "{err}");
+        let reused = sign_file(trusted.as_ref(), 2, old).expect("sign");
+        assert!(verifier.verify(path, old, Some(reused.as_bytes())).is_err());
+        assert!(verifier
+            .verify(path, content, Some(good.as_bytes()))
+            .is_ok());
+        let newer = sign_file(trusted.as_ref(), 5, old).expect("sign");
+        assert!(verifier.verify(path, old, Some(newer.as_bytes())).is_ok());
+        // Other files keep sequences of their own.
+        assert!(verifier
+            .verify(&dir.join("other.toml"), old, Some(replayed.as_bytes()))
+            .is_ok());
+
+        assert_eq!(verifier.verify(path, content, None), Ok(None));
+        let err = strict.verify(path, content, None).unwrap_err();
+        assert_eq!(
+            Code::from_message(&err),
+            Some(Code::PolicySignatureRejected)
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,315 @@
+//! `broker policy simulate`: decisions for hypothetical operations.
+//!
+//! Reads operations one per line and reports what a run under the policy
+//! would decide, with the rule that decided it, using the same checks in
+//! the same order as the broker's hosts. Blank lines and lines starting
+//! with `#` are skipped. Operations:
+//!
+//! ```text
+//! fs read|write|list <PATH> [<SIZE>]
+//! net [<METHOD>] <URL>
+//! ws <URL>
+//! ip <ADDRESS>
+//! ```
+//!
+//! `conditions` are evaluated at the current time, with `size` taken from
+//! the operation when given.
+
+use std::io::Read;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use saf_core::{Code, Denial};
+use saf_policy::expr::Attributes;
+use saf_policy::{FsAccess, Policy};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Operation {
+    Fs {
+        access: FsAccess,
+        path: String,
+        size: Option<u64>,
+    },
+    Net {
+        method: String,
+        url: String,
+    },
+    Ws {
+        url: String,
+    },
+    Ip(IpAddr),
+}
+
+/// Parse one line; `None` for blank lines and comments.
+pub(crate) fn parse(line: &str) -> Result<Option<Operation>, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let op = match words.as_slice() {
+        [] => return Ok(None),
+        [first, ..] if first.starts_with('#') => return Ok(None),
+        ["fs", access, path, size @ ..] if size.len() <= 1 => Operation::Fs {
+            access: match *access {
+                "read" | "list" => FsAccess::Read,
+                "write" => FsAccess::Write,
+                other => return Err(format!("unknown fs access {other:?}")),
+            },
+            path: path.to_string(),
+            size: match size.first() {
+                Some(n) => Some(n.parse().map_err(|_| format!("invalid size {n:?}"))?),
+                None => None,
+            },
+        },
+        ["net", url] => Operation::Net {
+            method: "GET".to_string(),
+            url: url.to_string(),
+        },
+        ["net", method, url] => Operation::Net {
+            method: method.to_ascii_uppercase(),
+            url: url.to_string(),
+        },
+        ["ws", url] => Operation::Ws {
+            url: url.to_string(),
+        },
+        ["ip", addr] => Operation::Ip(
+            addr.parse()
+                .map_err(|_| format!("invalid IP address {addr:?}"))?,
+        ),
+        _ => return Err("expected `fs`, `net`, `ws` or `ip` and its arguments".to_string()),
+    };
+    Ok(Some(op))
+}
+
+/// What `conditions` see for `op`; `rel` is the sanitized path of an fs
+/// operation.
+pub(crate) fn attributes<'a>(
+    op: &'a Operation,
+    rel: &'a str,
+    component: &'a str,
+    now: u64,
+) -> Attributes<'a> {
+    let attrs = Attributes {
+        component,
+        now,
+        ..Attributes::default()
+    };
+    match op {
+        Operation::Fs { access, size, .. } => Attributes {
+            capability: "fs",
+            access: Some(match access {
+                FsAccess::Read => "read",
+                FsAccess::Write => "write",
+            }),
+            path: Some(rel),
+            size: *size,
+            ..attrs
+        },
+        Operation::Net { method, url } => Attributes {
+            capability: "net",
+            method: Some(method),
+            url: Some(url),
+            ..attrs
+        },
+        Operation::Ws { url } => Attributes {
+            capability: "ws",
+            url: Some(url),
+            ..attrs
+        },
+        Operation::Ip(_) => attrs,
+    }
+}
+
+/// The sanitized path of an fs operation.
+pub(crate) fn fs_path(path: &str) -> Result<String, Denial> {
+    crate::sanitize_rel_path(path)
+        .ok_or_else(|| Denial::new(Code::FsInvalidPath, "builtin:workspace-relative"))
+}
+
+pub(crate) fn decide(
+    policy: &Policy,
+    op: &Operation,
+    component: &str,
+    now: u64,
+) -> Result<(), Denial> {
+    let offline = || Denial::new(Code::NetOffline, "offline");
+    match op {
+        Operation::Fs { access, path, size } => {
+            let rel = fs_path(path)?;
+            policy.check_fs_access(&rel, *access)?;
+            policy.check_conditions(&attributes(op, &rel, component, now))?;
+            if *access == FsAccess::Write && size.is_some_and(|s| s > policy.max_write_bytes) {
+                return Err(Denial::new(Code::PolicySizeLimit, "max_write_bytes"));
+            }
+            policy.check_path(&rel)
+        }
+        Operation::Net { method, url } => {
+            policy.check_conditions(&attributes(op, "", component, now))?;
+            if policy.offline {
+                return Err(offline());
+            }
+            let url = policy.rewrite_url(url).unwrap_or_else(|| url.clone());
+            policy.check_request(method, &url)
+        }
+        Operation::Ws { url } => {
+            policy.check_conditions(&attributes(op, "", component, now))?;
+            if policy.offline {
+                return Err(offline());
+            }
+            policy.check_ws_url(url)
+        }
+        Operation::Ip(ip) => policy.check_ip(ip),
+    }
+}
+
+/// One report line: the verdict, the operation, and for anything but an
+/// allow the code and deciding rule.
+fn report(
+    policy: &Policy,
+    component: &str,
+    now: u64,
+    line: &str,
+) -> Result<Option<String>, String> {
+    let Some(op) = parse(line)? else {
+        return Ok(None);
+    };
+    let line = line.trim();
+    Ok(Some(match decide(policy, &op, component, now) {
+        Ok(()) => format!("allow  {line}"),
+        Err(d) if d.code == Code::PolicyAsk => format!("ask    {line}  rule={}", d.rule_id),
+        Err(d) => format!("deny   {line}  {} rule={}", d.code, d.rule_id),
+    }))
+}
+
+/// The policy at `path`, as `component` would get it, with notes on its
+/// warnings and on the component it was selected for.
+pub(crate) fn load(path: &Path, component: Option<&str>) -> Result<(Policy, Vec<String>), String> {
+    let policy = Policy::from_file(path)?;
+    let mut notes: Vec<String> = policy
+        .issues()
+        .iter()
+        .filter(|i| !i.is_error())
+        .map(|issue| format!("warning: {issue}"))
+        .collect();
+    let Some(id) = component else {
+        return Ok((policy, notes));
+    };
+    let (selected, key) = policy.for_component(&[id.to_string()]);
+    if key.is_none() {
+        notes.push(format!(
+            "no policy entry for {id}; using the restrictive default"
+        ));
+    }
+    Ok((selected, notes))
+}
+
+/// Seconds since the Unix epoch, for time-based conditions.
+pub(crate) fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct SimulateArgs {
+    pub policy: PathBuf,
+    /// File of operations, one per line (default: stdin).
+    pub operations: Option<String>,
+    /// Decide as the policy applies to this component.
+    #[arg(long, value_name = "ID")]
+    pub component: Option<String>,
+}
+
+/// Entry point for `broker policy simulate <POLICY> [<OPERATIONS>]
+/// [--component <ID>]`. Operations are read from stdin when no file (or
+/// `-`) is given.
+pub fn main(args: SimulateArgs) -> Result<(), String> {
+    let SimulateArgs {
+        policy: policy_path,
+        operations: ops_path,
+        component,
+    } = args;
+    let (policy, notes) = load(&policy_path, component.as_deref())?;
+    for note in notes {
+        println!("# {note}");
+    }
+    let component = component.unwrap_or_default();
+    let now = now();
+    let operations = match ops_path.as_deref() {
+        None | Some("-") => {
+            let mut s = String::new();
+            std::io::stdin()
+                .read_to_string(&mut s)
+                .map_err(|e| e.to_string())?;
+            s
+        }
+        Some(path) => std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?,
+    };
+
+    let mut errors = 0;
+    for (n, line) in operations.lines().enumerate() {
+        match report(&policy, &component, now, line) {
+            Ok(Some(r)) => println!("{r}"),
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("line {}: {e}", n + 1);
+                errors += 1;
+            }
+        }
+    }
+    if errors == 0 {
+        Ok(())
+    } else {
+        Err(format!("{errors} operation(s) could not be parsed"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_the_deciding_rule_for_each_operation() {
+        let policy = Policy::from_toml_str(
+            r#"
+            allowed_domains = ["example.org"]
+            ask_domains = ["*.example.com"]
+            allowed_paths = ["docs"]
+            read_only = ["docs/readme.*"]
+
+            [[conditions]]
+            when = "access == 'write'"
+            require = "size != null && size < 5"
+            "#,
+        )
+        .expect("policy");
+        let lines: Vec<String> = [
+            "# comment",
+            "fs read docs/readme.txt",
+            "fs write docs/readme.txt",
+            "fs read ../etc/passwd",
+            "fs write docs/a.txt 4",
+            "fs write docs/a.txt 5",
+            "net https://example.org/x",
+            "net POST https://other.org/",
+            "ws wss://api.example.com/",
+            "ip 10.0.0.1",
+        ]
+        .iter()
+        .filter_map(|l| report(&policy, "app", 0, l).expect("parse"))
+        .collect();
+        assert_eq!(
+            lines,
+            [
+                "allow  fs read docs/readme.txt",
+                "deny   fs write docs/readme.txt  policy.path_read_only rule=read_only[0]",
+                "deny   fs read ../etc/passwd  fs.invalid_path rule=builtin:workspace-relative",
+                "allow  fs write docs/a.txt 4",
+                "deny   fs write docs/a.txt 5  policy.condition_failed rule=conditions[0]",
+                "allow  net https://example.org/x",
+                "deny   net POST https://other.org/  policy.domain_not_allowed rule=allowed_domains",
+                "ask    ws wss://api.example.com/  rule=ask_domains[0]",
+                "deny   ip 10.0.0.1  policy.ip_denied rule=denied_ip_ranges[1]",
+            ]
+        );
+        assert!(report(&policy, "app", 0, "fs exec a").is_err());
+    }
+}
@@ -0,0 +1,295 @@
+//! Hot reload of the active policy file.
+//!
+//! The watcher polls the file's contents. When they change it parses and
+//! validates the new policy, derives the grant this run is entitled to (the
+//! same component selection, `--offline` and accepted narrowing as at
+//! start) and swaps it into the [`SharedPolicy`] every host reads from, so
+//! running components see it on their next host call. A change that fails
+//! to parse or validate leaves the running policy in force. Both outcomes
+//! are audited with the SHA-256 of the file in force before and after.
+//! Reloads must pass the same signature check as the policy loaded at
+//! start; the signature file is watched too, so re-signing is picked up.
+//!
+//! Components cannot write the workspace's `.saf/policy.toml`, but anything
+//! else running as the user can, so an unsigned reload may only narrow the
+//! running grant. One that [`explain::diff`] finds wider in any field is
+//! refused until a trusted key signs it or the broker is restarted, which
+//! both take the operator.
+//!
+//! `connection_pool` and `dns_over_https` are read once when the NetHost is
+//! built and only take effect on the next start.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use saf_core::{AuditEvent, AuditRecord, Code, LogHost, Outcome};
+use saf_policy::explain::{self, Effect};
+use saf_policy::{Policy, SharedPolicy};
+
+use crate::policy_sig::Verifier;
+use crate::run_manifest::sha256_hex;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Turns the policy read from the file into the grant for this run.
+pub type Derive = Box<dyn Fn(&Policy) -> Policy + Send>;
+
+/// Background watcher; stops when dropped.
+pub struct PolicyWatcher {
+    // Dropping the sender wakes the thread immediately.
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PolicyWatcher {
+    /// Watch `path`, whose contents hashed to `sha256` when the running
+    /// policy was loaded from it.
+    pub fn spawn(
+        path: PathBuf,
+        sha256: String,
+        shared: SharedPolicy,
+        log: Arc<dyn LogHost>,
+        verifier: Verifier,
+        derive: Derive,
+    ) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let signature = std::fs::read(Verifier::sig_path(&path)).ok();
+        let mut watch = Watch {
+            seen: seen_key(&sha256, signature.as_deref()),
+            path,
+            shared,
+            log,
+            verifier,
+            derive,
+            active_sha256: sha256,
+        };
+        let thread = std::thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(POLL_INTERVAL) {
+                watch.poll();
+            }
+        });
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for PolicyWatcher {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Watch {
+    path: PathBuf,
+    shared: SharedPolicy,
+    log: Arc<dyn LogHost>,
+    verifier: Verifier,
+    derive: Derive,
+    /// Digest of the file the running policy came from.
+    active_sha256: String,
+    /// The last contents and signature looked at, so a rejected edit is
+    /// only reported once.
+    seen: String,
+}
+
+fn seen_key(sha256: &str, signature: Option<&[u8]>) -> String {
+    match signature {
+        Some(sig) => format!("{sha256}:{}", sha256_hex(sig)),
+        None => sha256.to_string(),
+    }
+}
+
+impl Watch {
+    fn poll(&mut self) {
+        // A file that is missing or mid-rename is simply retried next tick.
+        let Ok(content) = std::fs::read_to_string(&self.path) else {
+            return;
+        };
+        let sha256 = sha256_hex(content.as_bytes());
+        let signature = std::fs::read(Verifier::sig_path(&self.path)).ok();
+        let seen = seen_key(&sha256, signature.as_deref());
+        if seen == self.seen {
+            return;
+        }
+        self.seen = seen;
+        let loaded = self
+            .verifier
+            .verify(&self.path, content.as_bytes(), signature.as_deref())
+            .and_then(|key| {
+                let check =
+                    |base: &Path, bytes: &[u8]| self.verifier.verify_file(base, bytes).map(drop);
+                Ok((
+                    Policy::from_file_content_checked(&self.path, &content, &check)?,
+                    key,
+                ))
+            })
+            .and_then(|(policy, key)| {
+                let grant = (self.derive)(&policy);
+                if key.is_none() {
+                    let wider: Vec<String> = explain::diff(&self.shared.current(), &grant)
+                        .iter()
+                        .filter(|c| c.effect == Effect::Wider)
+                        .map(|c| format!("{} {}", c.field, c.change))
+                        .collect();
+                    if !wider.is_empty() {
+                        return Err(format!(
+                            "the unsigned change widens access ({}); sign it with a trusted \
+                             key or restart the broker to apply it",
+                            wider.join(", ")
+                        ));
+                    }
+                }
+                Ok((grant, key))
+            });
+        match loaded {
+            Ok((grant, key)) => {
+                self.shared.replace(grant);
+                self.log.record(AuditRecord::new(
+                    Code::PolicyReloaded,
+                    AuditEvent::PolicyReloaded {
+                        source: self.path.display().to_string(),
+                        old_sha256: self.active_sha256.clone(),
+                        new_sha256: sha256.clone(),
+                        key,
+                    },
+                ));
+                println!("Policy reloaded from {}", self.path.display());
+                self.active_sha256 = sha256;
+            }
+            Err(e) => {
+                self.log.record(
+                    AuditRecord::new(
+                        Code::PolicyReloadFailed,
+                        AuditEvent::PolicyReloadFailed {
+                            source: self.path.display().to_string(),
+                            sha256: self.active_sha256.clone(),
+                            rejected_sha256: sha256,
+                            error: e.clone(),
+                        },
+                    )
+                    .with_outcome(Outcome::Failed),
+                );
+                eprintln!(
+                    "Keeping the running policy; not reloading {}: {e}",
+                    self.path.display()
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy_sig::{fingerprint, sign_file};
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use base64::Engine;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MemLog(Mutex<Vec<AuditRecord>>);
+    impl LogHost for MemLog {
+        fn record(&self, record: AuditRecord) {
+            if let Ok(mut g) = self.0.lock() {
+                g.push(record);
+            }
+        }
+    }
+
+    #[test]
+    fn narrowing_or_signed_changes_are_swapped_in_and_others_kept_out() {
+        let dir = std::env::temp_dir().join(format!("saf-policy-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("dir");
+        let path = dir.join("policy.toml");
+        let original = "allowed_domains = [\"example.org\", \"httpbin.org\"]\n";
+        std::fs::write(&path, original).expect("write");
+        // Stands in for e.g. `--offline`, which must survive reloads.
+        let derive: Derive = Box::new(|p| Policy {
+            offline: true,
+            ..p.clone()
+        });
+        let shared = SharedPolicy::new(derive(&Policy::from_file(&path).expect("load")));
+
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).expect("key");
+        let public = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+            .expect("pair")
+            .public_key()
+            .as_ref()
+            .to_vec();
+        let keys = dir.join("trusted_policy_keys");
+        std::fs::write(&keys, BASE64.encode(&public)).expect("write");
+
+        let log = Arc::new(MemLog::default());
+        let mut watch = Watch {
+            path: path.clone(),
+            shared: shared.clone(),
+            log: log.clone(),
+            verifier: Verifier::load(&keys, false).expect("keys"),
+            derive,
+            active_sha256: sha256_hex(original.as_bytes()),
+            seen: sha256_hex(original.as_bytes()),
+        };
+
+        watch.poll();
+        assert!(log.0.lock().expect("log").is_empty());
+
+        std::fs::write(&path, "allowed_domains = [\"httpbin.org\"]\n").expect("write");
+        watch.poll();
+        let policy = shared.current();
+        assert!(policy.is_url_allowed("https://httpbin.org/"));
+        assert!(!policy.is_url_allowed("https://example.org/"));
+        assert!(policy.offline);
+
+        // Unsigned, a change may only narrow what is granted.
+        let wider = "allowed_domains = [\"httpbin.org\", \"evil.test\"]\n";
+        std::fs::write(&path, wider).expect("write");
+        watch.poll();
+        assert!(!shared.current().is_url_allowed("https://evil.test/"));
+
+        std::fs::write(&path, "allowed_domain = [\"evil.test\"]\n").expect("write");
+        watch.poll();
+        watch.poll();
+        assert!(shared.current().is_url_allowed("https://httpbin.org/"));
+
+        // Signed by a trusted key, it is the operator's to make.
+        std::fs::write(&path, wider).expect("write");
+        let signature = sign_file(pkcs8.as_ref(), 1, wider.as_bytes()).expect("sign");
+        std::fs::write(Verifier::sig_path(&path), signature).expect("write");
+        watch.poll();
+        assert!(shared.current().is_url_allowed("https://evil.test/"));
+
+        let events = log.0.lock().expect("log").clone();
+        assert_eq!(events.len(), 4, "{events:?}");
+        assert!(matches!(
+            &events[0].event,
+            AuditEvent::PolicyReloaded { old_sha256, .. } if *old_sha256 == sha256_hex(original.as_bytes())
+        ));
+        for failed in &events[1..3] {
+            assert_eq!(failed.code, Code::PolicyReloadFailed.as_str());
+            assert_eq!(failed.outcome, Outcome::Failed);
+        }
+        assert!(
+            matches!(
+                &events[1].event,
+                AuditEvent::PolicyReloadFailed { error, .. } if error.contains("allowed_domains + \"evil.test\"")
+            ),
+            "{:?}",
+            events[1].event
+        );
+        assert!(matches!(
+            &events[3].event,
+            AuditEvent::PolicyReloaded { key: Some(key), .. } if *key == fingerprint(&public)
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use saf_policy::RateLimit;
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Per-domain token buckets enforcing `Policy::rate_limits`.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Take a token for `domain`, or return how long until one is available.
+    pub fn acquire(&self, domain: &str, limit: &RateLimit, now: Instant) -> Result<(), Duration> {
+        let capacity = f64::from(limit.burst.max(1));
+        let per_sec = f64::from(limit.requests_per_minute) / 60.0;
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let bucket = buckets.entry(domain.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            last_refill: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.last_refill);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * per_sec).min(capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        if per_sec <= 0.0 {
+            // A zero rate never refills; report a minute so callers back off.
+            return Err(Duration::from_secs(60));
+        }
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_sec))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_allows_burst_then_refills() {
+        let limiter = RateLimiter::default();
+        let limit = RateLimit {
+            requests_per_minute: 60,
+            burst: 2,
+        };
+        let t0 = Instant::now();
+        assert!(limiter.acquire("example.org", &limit, t0).is_ok());
+        assert!(limiter.acquire("example.org", &limit, t0).is_ok());
+        let wait = limiter
+            .acquire("example.org", &limit, t0)
+            .expect_err("bucket empty");
+        assert!(wait <= Duration::from_secs(1));
+
+        // Other domains have their own bucket.
+        assert!(limiter.acquire("httpbin.org", &limit, t0).is_ok());
+        // One second refills one token at 60/min.
+        assert!(limiter
+            .acquire("example.org", &limit, t0 + Duration::from_secs(1))
+            .is_ok());
+    }
+}
@@ -0,0 +1,339 @@
+//! `broker repl`: host calls typed by hand.
+//!
+//! The broker opens the workspace as for a run, under the same policy and
+//! audit log, then reads one command per line and performs it as a host
+//! call of the broker's own, through the checks a component's calls go
+//! through. What a command prints is what a component would have been
+//! given; a refused or failed call prints its error, ending in the request
+//! ID its audit records carry. The session ends at `exit` or end of input.
+//!
+//! ```text
+//! saf> ls docs
+//! readme.txt
+//! saf> cat secrets/key.pem
+//! error: fs error: policy.path_not_allowed: ... (request broker.2)
+//! ```
+//!
+//! Commands:
+//!
+//! - `ls [PATH]`: the entries of a workspace directory (its root without
+//!   `PATH`).
+//! - `cat PATH`: a workspace file's contents.
+//! - `write PATH TEXT`: replace a workspace file's contents with `TEXT`,
+//!   the rest of the line.
+//! - `append PATH TEXT`: add `TEXT` and a newline to a workspace file.
+//! - `fetch URL`: GET `URL`, printing the status and then the body.
+//! - `ws URL [MESSAGE]`: connect to a WebSocket, send `MESSAGE` if given
+//!   and print the reply, then close the connection.
+//! - `help`, and `exit` or `quit`.
+//!
+//! Paths and URLs are the rest of the line, so paths may contain spaces.
+
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+use clap::Args;
+use saf_core::{Context, CoreError};
+
+use crate::cli::RunArgs;
+use crate::mounts::Mount;
+use crate::requests::{self, Requests};
+
+const HELP: &str = "\
+ls [PATH]            list a workspace directory
+cat PATH             print a workspace file
+write PATH TEXT      replace a workspace file's contents
+append PATH TEXT     add a line to a workspace file
+fetch URL            GET a URL
+ws URL [MESSAGE]     connect to a WebSocket, send and print the reply
+exit                 end the session";
+
+#[derive(Debug, Args)]
+pub struct ReplArgs {
+    /// Restore a previously saved workspace (default: the current
+    /// directory).
+    #[arg(long, value_name = "ID")]
+    pub workspace_id: Option<String>,
+    /// Mount a directory into the workspace as NAME, read-only with `:ro`;
+    /// may be repeated.
+    #[arg(long = "mount", value_name = "NAME:PATH[:ro|:rw]", value_parser = Mount::parse)]
+    pub mounts: Vec<Mount>,
+    /// Load the policy from a TOML or JSON file (default: .saf/policy.toml).
+    #[arg(long, value_name = "PATH")]
+    pub policy: Option<PathBuf>,
+    /// Disable all network access for the session.
+    #[arg(long)]
+    pub offline: bool,
+    /// Log policy denials as would-deny and let operations proceed.
+    #[arg(long)]
+    pub policy_dry_run: bool,
+    /// Refuse policy files without a trusted signature (<file>.sig).
+    #[arg(long)]
+    pub require_signed_policy: bool,
+}
+
+impl From<ReplArgs> for RunArgs {
+    fn from(args: ReplArgs) -> Self {
+        RunArgs {
+            workspace_id: args.workspace_id,
+            mounts: args.mounts,
+            policy: args.policy,
+            offline: args.offline,
+            policy_dry_run: args.policy_dry_run,
+            require_signed_policy: args.require_signed_policy,
+            headless: true,
+            repl: true,
+            ..RunArgs::default()
+        }
+    }
+}
+
+/// One line of input.
+#[derive(Debug, PartialEq, Eq)]
+enum Command<'a> {
+    Ls(&'a str),
+    Cat(&'a str),
+    Write(&'a str, &'a str),
+    Append(&'a str, &'a str),
+    Fetch(&'a str),
+    Ws(&'a str, Option<&'a str>),
+    Help,
+    Exit,
+}
+
+/// The command on `line`, or `None` for a blank one.
+fn parse(line: &str) -> Result<Option<Command<'_>>, String> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let (name, rest) = line
+        .split_once(char::is_whitespace)
+        .map_or((line, ""), |(name, rest)| (name, rest.trim()));
+    let required = |what: &str| match rest {
+        "" => Err(format!("{name}: expected {what}")),
+        rest => Ok(rest),
+    };
+    let split = |what: &str| {
+        required(what).map(|rest| {
+            rest.split_once(char::is_whitespace)
+                .map_or((rest, ""), |(first, text)| (first, text.trim_start()))
+        })
+    };
+    let command = match name {
+        "ls" => Command::Ls(rest),
+        "cat" => Command::Cat(required("a path")?),
+        "write" => {
+            let (path, text) = split("a path and text")?;
+            Command::Write(path, text)
+        }
+        "append" => {
+            let (path, text) = split("a path and text")?;
+            Command::Append(path, text)
+        }
+        "fetch" => Command::Fetch(required("a URL")?),
+        "ws" => {
+            let (url, message) = split("a URL")?;
+            Command::Ws(url, Some(message).filter(|m| !m.is_empty()))
+        }
+        "help" | "?" => Command::Help,
+        "exit" | "quit" => Command::Exit,
+        _ => return Err(format!("unknown command {name:?}; try `help`")),
+    };
+    Ok(Some(command))
+}
+
+/// Perform commands from `input`, writing what they print to `output`,
+/// until `exit` or the end of `input`, prompting for each when `prompt`.
+pub fn serve(
+    ctx: &Context<'_>,
+    requests: &Requests,
+    input: impl BufRead,
+    mut output: impl Write,
+    prompt: bool,
+) -> Result<(), String> {
+    let io = |e: std::io::Error| e.to_string();
+    if prompt {
+        writeln!(output, "Type `help` for the commands, `exit` to leave.").map_err(io)?;
+    }
+    let mut lines = input.lines();
+    loop {
+        if prompt {
+            write!(output, "saf> ")
+                .and_then(|()| output.flush())
+                .map_err(io)?;
+        }
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let line = line.map_err(io)?;
+        let printed = match parse(&line) {
+            Ok(None) => continue,
+            Ok(Some(Command::Exit)) => break,
+            Ok(Some(Command::Help)) => Ok(HELP.to_string()),
+            Ok(Some(command)) => perform(ctx, requests, command),
+            Err(e) => Err(e),
+        };
+        match printed {
+            Ok(text) if text.is_empty() => Ok(()),
+            Ok(text) => writeln!(output, "{}", text.strip_suffix('\n').unwrap_or(&text)),
+            Err(e) => writeln!(output, "error: {e}"),
+        }
+        .and_then(|()| output.flush())
+        .map_err(io)?;
+    }
+    Ok(())
+}
+
+/// Perform one command as a host call of its own, so its audit records
+/// and any error carry its request ID.
+fn perform(ctx: &Context<'_>, requests: &Requests, command: Command<'_>) -> Result<String, String> {
+    let request = requests.begin(ctx.component.run_id.as_deref());
+    let result = call(ctx, command);
+    requests.end();
+    result.map_err(|e| requests::traced(&e, &request))
+}
+
+fn call(ctx: &Context<'_>, command: Command<'_>) -> Result<String, CoreError> {
+    match command {
+        Command::Ls(path) => saf_core::list_dir(ctx, path).map(|entries| entries.join("\n")),
+        Command::Cat(path) => saf_core::read_text(ctx, path),
+        Command::Write(path, text) => {
+            saf_core::write_text(ctx, path, text).map(|()| format!("wrote {path}"))
+        }
+        Command::Append(path, text) => saf_core::append_text(ctx, path, &format!("{text}\n"))
+            .map(|()| format!("appended to {path}")),
+        Command::Fetch(url) => saf_core::fetch(ctx, url).map(|resp| {
+            format!(
+                "HTTP {} ({} bytes)\n{}",
+                resp.status,
+                resp.body.len(),
+                resp.body
+            )
+        }),
+        Command::Ws(url, message) => {
+            let conn = saf_core::ws_connect(ctx, url)?;
+            let reply = match message {
+                Some(message) => saf_core::ws_send(ctx, conn, message)
+                    .and_then(|()| saf_core::ws_receive(ctx, conn))
+                    .map(|reply| reply.unwrap_or_else(|| "(closed by peer)".to_string())),
+                None => Ok(format!("connected ({conn})")),
+            };
+            let closed = saf_core::ws_close(ctx, conn);
+            reply.and_then(|reply| closed.map(|()| reply))
+        }
+        Command::Help | Command::Exit => Ok(String::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use saf_core::{
+        AuditRecord, ComponentIdentity, FsHost, HttpResponse, LogHost, NetError, NetHost, Usage,
+        WsHost,
+    };
+    use saf_policy::{Policy, SharedPolicy};
+
+    #[test]
+    fn commands_take_the_rest_of_the_line() {
+        assert_eq!(parse("  "), Ok(None));
+        assert_eq!(parse("ls"), Ok(Some(Command::Ls(""))));
+        assert_eq!(
+            parse("cat my docs/a b.txt "),
+            Ok(Some(Command::Cat("my docs/a b.txt")))
+        );
+        assert_eq!(
+            parse("write notes.txt hello  world"),
+            Ok(Some(Command::Write("notes.txt", "hello  world")))
+        );
+        assert_eq!(
+            parse("ws wss://example.org/chat"),
+            Ok(Some(Command::Ws("wss://example.org/chat", None)))
+        );
+        assert_eq!(parse("quit"), Ok(Some(Command::Exit)));
+        assert!(parse("cat").is_err());
+        assert!(parse("rm a.txt").is_err());
+    }
+
+    struct Docs;
+
+    impl FsHost for Docs {
+        fn list_dir(&self, _path: &str) -> Result<Vec<String>, String> {
+            Ok(vec!["a.txt".to_string(), "b.txt".to_string()])
+        }
+        fn read_text(&self, path: &str) -> Result<String, String> {
+            Ok(format!("contents of {path}\n"))
+        }
+        fn write_text(&self, _path: &str, _content: &str) -> Result<(), String> {
+            Ok(())
+        }
+        fn append_bytes(&self, _path: &str, _content: &[u8]) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    struct Offline;
+
+    impl NetHost for Offline {
+        fn fetch(&self, _url: &str) -> Result<HttpResponse, NetError> {
+            Err(NetError::Offline)
+        }
+    }
+
+    impl WsHost for Offline {
+        fn connect(&self, _url: &str) -> Result<u64, String> {
+            Err("offline".to_string())
+        }
+        fn send(&self, _conn: u64, _message: &str) -> Result<(), String> {
+            Err("offline".to_string())
+        }
+        fn receive(&self, _conn: u64) -> Result<Option<String>, String> {
+            Err("offline".to_string())
+        }
+        fn close(&self, _conn: u64) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    struct Discard;
+
+    impl LogHost for Discard {
+        fn record(&self, _record: AuditRecord) {}
+    }
+
+    #[test]
+    fn commands_go_through_the_policy() {
+        let mut policy = Policy::new();
+        policy.deny = vec!["secrets/**".to_string()];
+        let policy = SharedPolicy::new(policy);
+        let component = ComponentIdentity::new("broker");
+        let usage = Usage::default();
+        let ctx = Context {
+            fs: &Docs,
+            net: &Offline,
+            ws: &Offline,
+            log: &Discard,
+            policy: &policy,
+            component: &component,
+            dry_run: false,
+            usage: &usage,
+        };
+        let mut output = Vec::new();
+        let input = "ls docs\n\ncat docs/a.txt\ncat secrets/key.pem\nfetch https://example.org/\nexit\nls docs\n";
+        serve(
+            &ctx,
+            &Requests::default(),
+            input.as_bytes(),
+            &mut output,
+            false,
+        )
+        .expect("serve");
+        let output = String::from_utf8(output).expect("utf-8");
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[..3], ["a.txt", "b.txt", "contents of docs/a.txt"]);
+        assert!(lines[3].starts_with("error: ") && lines[3].ends_with("(request broker.3)"));
+        assert!(lines[4].starts_with("error: ") && lines[4].ends_with("(request broker.4)"));
+        assert_eq!(lines.len(), 5);
+    }
+}
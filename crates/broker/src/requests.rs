@@ -0,0 +1,84 @@
+//! Correlation IDs for host calls.
+//!
+//! Each call a component makes into the broker is given an ID, `<run>.<n>`
+//! for the n-th call of the run. Every audit record made while serving the
+//! call carries it as `request`, whether the broker's hosts or core made
+//! it, and an error the call returns to the component ends with
+//! `(request <id>)`, so a failure shown in the UI or a run report leads to
+//! its audit lines with `broker audit query --request <id>`.
+
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread::ThreadId;
+
+#[derive(Debug, Default)]
+pub struct Requests {
+    next: AtomicU64,
+    /// The call being served and the thread serving it; records made on
+    /// other threads, such as a policy reload's, are not part of it.
+    current: Mutex<Option<(ThreadId, String)>>,
+}
+
+impl Requests {
+    /// Start serving a call of the run `run_id` (the broker's own calls
+    /// have none), returning its ID.
+    pub fn begin(&self, run_id: Option<&str>) -> String {
+        let n = self.next.fetch_add(1, Ordering::Relaxed) + 1;
+        let id = format!("{}.{n}", run_id.unwrap_or("broker"));
+        if let Ok(mut current) = self.current.lock() {
+            *current = Some((std::thread::current().id(), id.clone()));
+        }
+        id
+    }
+
+    /// Serve, on this thread, a call that was begun elsewhere as `id`.
+    pub fn resume(&self, id: &str) {
+        if let Ok(mut current) = self.current.lock() {
+            *current = Some((std::thread::current().id(), id.to_string()));
+        }
+    }
+
+    pub fn end(&self) {
+        if let Ok(mut current) = self.current.lock() {
+            *current = None;
+        }
+    }
+
+    /// The call being served on this thread, if any.
+    pub fn current(&self) -> Option<String> {
+        let current = self.current.lock().ok()?;
+        let (thread, id) = current.as_ref()?;
+        (*thread == std::thread::current().id()).then(|| id.clone())
+    }
+}
+
+/// `message`, as returned to the component by the call `request`.
+pub fn traced(message: impl Display, request: &str) -> String {
+    format!("{message} (request {request})")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use saf_core::Code;
+
+    #[test]
+    fn calls_are_numbered_per_run_and_seen_only_on_their_thread() {
+        let requests = Requests::default();
+        assert_eq!(requests.current(), None);
+        assert_eq!(requests.begin(Some("run_1")), "run_1.1");
+        assert_eq!(requests.current().as_deref(), Some("run_1.1"));
+        std::thread::scope(|s| {
+            s.spawn(|| assert_eq!(requests.current(), None));
+        });
+        requests.end();
+        assert_eq!(requests.current(), None);
+        assert_eq!(requests.begin(None), "broker.2");
+
+        // The code still leads the message.
+        let error = traced(Code::FsFailed.with_message("docs/a.md: gone"), "run_1.1");
+        assert_eq!(error, "fs.failed: docs/a.md: gone (request run_1.1)");
+        assert_eq!(Code::from_message(&error), Some(Code::FsFailed));
+    }
+}
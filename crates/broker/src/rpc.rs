@@ -0,0 +1,393 @@
+//! `broker --stdio-rpc`: the broker driven over JSON-RPC 2.0 on stdio.
+//!
+//! The broker opens the workspace as for a run, under the same policy and
+//! audit log, then reads one request per line from stdin and writes one
+//! response per line to stdout. Everything else it would print goes to
+//! stderr, so stdout carries only JSON-RPC messages. The session ends when
+//! stdin is closed.
+//!
+//! ```text
+//! --> {"jsonrpc": "2.0", "id": 1, "method": "read_text", "params": {"path": "docs/a.md"}}
+//! <-- {"jsonrpc": "2.0", "id": 1, "result": "# Notes\n"}
+//! ```
+//!
+//! Methods:
+//!
+//! - `list_dir {path}`: the entries of a workspace directory (`""` for
+//!   its root).
+//! - `read_text {path}`: a workspace file's contents.
+//! - `write_text {path, content}`: replace a workspace file's contents.
+//! - `run_component {path}`: run the component at `path` in the workspace
+//!   and return its run report, whatever the outcome. It runs under the
+//!   session's policy, limited to the interfaces its `<name>.caps.toml`
+//!   or `<name>.saf.toml` asks for, if it has one, and within the broker's
+//!   `[run]` timeout.
+//! - `subscribe_audit`: from now on, send each audit record as an `audit`
+//!   notification, `{"jsonrpc": "2.0", "method": "audit", "params":
+//!   <record>}`, until `unsubscribe_audit`.
+//!
+//! File operations go through the policy checks and audit log a component's
+//! would, attributed to the broker. A failed operation is answered with
+//! error code -32000, its message ending in the request ID its audit
+//! records carry, and `data` holding the error's code and that ID:
+//!
+//! ```text
+//! <-- {"jsonrpc": "2.0", "id": 2, "error": {"code": -32000, "message": "fs error: policy.path_not_allowed: ... (request broker.2)",
+//!      "data": {"code": "policy.path_not_allowed", "request": "broker.2"}}}
+//! ```
+//!
+//! Malformed messages get the standard codes: -32700 for a line that is
+//! not JSON, -32600 for one that is not a request, -32601 for an unknown
+//! method and -32602 for bad params. Requests without an `id` are
+//! notifications and are not answered.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use saf_audit::AuditWriter;
+use saf_core::{Context, CoreError};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::runtime::Handle;
+
+use crate::component_manifest::{self, ComponentManifest};
+use crate::config::{self, RunConfig};
+use crate::requests::{self, Requests};
+use crate::{run_manifest, runs, sysinfo, wasmtime_host};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// An operation the broker refused or that failed.
+const OPERATION_FAILED: i64 = -32000;
+
+/// Where responses and notifications are written, a line at a time.
+pub type Output = Arc<Mutex<dyn Write + Send>>;
+
+/// What the session serves requests with.
+pub struct Session<'a> {
+    pub ctx: Context<'a>,
+    pub requests: &'a Requests,
+    pub audit: &'a AuditWriter,
+    pub workspace: &'a Path,
+    /// Profile components run in the session.
+    pub profile: bool,
+    /// How long components run in the session may take.
+    pub run_config: &'a RunConfig,
+    /// Values for `saf:app/config`, before the policy filters them.
+    pub config: &'a BTreeMap<String, String>,
+}
+
+#[derive(Debug, PartialEq)]
+struct RpcError {
+    code: i64,
+    message: String,
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PathParams {
+    path: String,
+}
+
+#[derive(Deserialize)]
+struct WriteParams {
+    path: String,
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct RunParams {
+    path: PathBuf,
+}
+
+/// Answer requests from `input` on `output` until `input` ends.
+pub fn serve(session: &Session<'_>, input: impl BufRead, output: &Output) -> Result<(), String> {
+    // Set while audit records are forwarded; the forwarding thread stops
+    // at the next record once it is cleared.
+    let mut forwarding: Option<Arc<AtomicBool>> = None;
+    for line in input.lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        let response = respond(&line, |method, params| {
+            session.call(method, params, output, &mut forwarding)
+        });
+        if let Some(response) = response {
+            send(output, &response)?;
+        }
+    }
+    if let Some(active) = forwarding {
+        active.store(false, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+fn send(output: &Output, message: &Value) -> Result<(), String> {
+    let mut output = output.lock().map_err(|e| e.to_string())?;
+    writeln!(output, "{message}")
+        .and_then(|()| output.flush())
+        .map_err(|e| e.to_string())
+}
+
+/// The response to one line, or `None` for a notification or blank line.
+fn respond(
+    line: &str,
+    mut call: impl FnMut(&str, Value) -> Result<Value, RpcError>,
+) -> Option<Value> {
+    if line.trim().is_empty() {
+        return None;
+    }
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => {
+            return Some(error(
+                Value::Null,
+                RpcError::new(PARSE_ERROR, e.to_string()),
+            ))
+        }
+    };
+    let id = request.get("id").cloned();
+    let method = match (request.get("jsonrpc"), request.get("method")) {
+        (Some(v), Some(Value::String(method))) if v == "2.0" => method.clone(),
+        _ => {
+            let e = RpcError::new(INVALID_REQUEST, "expected a JSON-RPC 2.0 request");
+            return Some(error(id.unwrap_or(Value::Null), e));
+        }
+    };
+    let params = request.get("params").cloned().unwrap_or(json!({}));
+    let result = call(&method, params);
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(e) => error(id, e),
+    })
+}
+
+fn error(id: Value, e: RpcError) -> Value {
+    let mut error = json!({"code": e.code, "message": e.message});
+    if let Some(data) = e.data {
+        error["data"] = data;
+    }
+    json!({"jsonrpc": "2.0", "id": id, "error": error})
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+impl Session<'_> {
+    fn call(
+        &self,
+        method: &str,
+        params: Value,
+        output: &Output,
+        forwarding: &mut Option<Arc<AtomicBool>>,
+    ) -> Result<Value, RpcError> {
+        match method {
+            "list_dir" => {
+                let p: PathParams = parse_params(params)?;
+                self.traced(|ctx| saf_core::list_dir(ctx, &p.path).map(|e| json!(e)))
+            }
+            "read_text" => {
+                let p: PathParams = parse_params(params)?;
+                self.traced(|ctx| saf_core::read_text(ctx, &p.path).map(Value::String))
+            }
+            "write_text" => {
+                let p: WriteParams = parse_params(params)?;
+                self.traced(|ctx| {
+                    saf_core::write_text(ctx, &p.path, &p.content).map(|()| Value::Null)
+                })
+            }
+            "run_component" => {
+                let p: RunParams = parse_params(params)?;
+                self.run_component(&p.path)
+            }
+            "subscribe_audit" => {
+                if forwarding.is_none() {
+                    *forwarding = Some(self.forward_audit(output)?);
+                }
+                Ok(Value::Null)
+            }
+            "unsubscribe_audit" => {
+                if let Some(active) = forwarding.take() {
+                    active.store(false, Ordering::Relaxed);
+                }
+                Ok(Value::Null)
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method {method:?}"),
+            )),
+        }
+    }
+
+    /// Perform one operation as a host call of its own, so its audit
+    /// records and any error carry its request ID.
+    fn traced(
+        &self,
+        operation: impl FnOnce(&Context<'_>) -> Result<Value, CoreError>,
+    ) -> Result<Value, RpcError> {
+        let request = self.requests.begin(self.ctx.component.run_id.as_deref());
+        let result = operation(&self.ctx);
+        self.requests.end();
+        result.map_err(|e| RpcError {
+            code: OPERATION_FAILED,
+            message: requests::traced(&e, &request),
+            data: Some(json!({"code": e.code().as_str(), "request": request})),
+        })
+    }
+
+    fn run_component(&self, path: &Path) -> Result<Value, RpcError> {
+        let failed = |e: String| RpcError::new(OPERATION_FAILED, e);
+        let path = self.workspace.join(path);
+        let name = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .ok_or_else(|| failed(format!("{}: not a component file", path.display())))?;
+        let bytes = std::fs::read(&path)
+            .map_err(|e| failed(format!("failed to open {}: {}", path.display(), e)))?;
+        let mut identity = saf_core::ComponentIdentity::new(&name)
+            .with_sha256(&run_manifest::sha256_hex(&bytes))
+            .with_run_id(&runs::new_run_id());
+        let declared = ComponentManifest::load_for(&path).map_err(failed)?;
+        if let Some(d) = &declared {
+            identity = identity.with_declared(&d.version, &d.publisher);
+        }
+        let interfaces = component_manifest::capabilities_for(&path, declared.as_ref())
+            .map_err(failed)?
+            .map(|(caps, _)| caps.interfaces());
+        let core = wasmtime_host::CoreCtx {
+            ctx: Context {
+                component: &identity,
+                ..self.ctx.clone()
+            },
+            requests: self.requests,
+        };
+        let sysinfo = sysinfo::SysInfo::collect(&self.ctx.policy.current());
+        let options = wasmtime_host::RunOptions {
+            sysinfo,
+            interfaces,
+            timeout: self.run_config.timeout(&name),
+            config: config::granted(self.config, &self.ctx.policy.current()),
+            ..wasmtime_host::RunOptions::default()
+        };
+        // The session is served from the broker's runtime, which drives the
+        // run while this thread waits on it.
+        let run = crate::execute_component(
+            self.workspace,
+            &path,
+            &bytes,
+            core,
+            None,
+            self.profile,
+            options,
+        );
+        let (report, _) = tokio::task::block_in_place(|| Handle::current().block_on(run))
+            .map_err(|e| failed(e.to_string()))?;
+        serde_json::to_value(report).map_err(|e| failed(e.to_string()))
+    }
+
+    /// Send every audit record written from now on to `output`, until the
+    /// returned flag is cleared.
+    fn forward_audit(&self, output: &Output) -> Result<Arc<AtomicBool>, RpcError> {
+        let records = self
+            .audit
+            .subscribe()
+            .map_err(|e| RpcError::new(OPERATION_FAILED, e))?;
+        let active = Arc::new(AtomicBool::new(true));
+        let (output, still) = (output.clone(), active.clone());
+        std::thread::spawn(move || {
+            for record in records {
+                if !still.load(Ordering::Relaxed) {
+                    break;
+                }
+                let notification = json!({"jsonrpc": "2.0", "method": "audit", "params": record});
+                if send(&output, &notification).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(active)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo(method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "echo" => Ok(params),
+            "read_text" => {
+                let p: PathParams = parse_params(params)?;
+                Err(RpcError {
+                    code: OPERATION_FAILED,
+                    message: format!("fs.not_found: {}", p.path),
+                    data: None,
+                })
+            }
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, "unknown method")),
+        }
+    }
+
+    #[test]
+    fn requests_get_json_rpc_responses_and_notifications_none() {
+        let code = |response: Option<Value>| response.map(|r| r["error"]["code"].clone());
+        assert_eq!(
+            respond(
+                r#"{"jsonrpc": "2.0", "id": 7, "method": "echo", "params": [1]}"#,
+                echo
+            ),
+            Some(json!({"jsonrpc": "2.0", "id": 7, "result": [1]}))
+        );
+        assert_eq!(
+            respond(r#"{"jsonrpc": "2.0", "method": "echo"}"#, echo),
+            None
+        );
+        assert_eq!(respond("  ", echo), None);
+        assert_eq!(code(respond("{", echo)), Some(json!(PARSE_ERROR)));
+        assert_eq!(
+            code(respond(r#"{"id": 1, "method": "echo"}"#, echo)),
+            Some(json!(INVALID_REQUEST))
+        );
+        assert_eq!(
+            code(respond(
+                r#"{"jsonrpc": "2.0", "id": 1, "method": "launch"}"#,
+                echo
+            )),
+            Some(json!(METHOD_NOT_FOUND))
+        );
+        assert_eq!(
+            code(respond(
+                r#"{"jsonrpc": "2.0", "id": 1, "method": "read_text", "params": {"file": "a"}}"#,
+                echo
+            )),
+            Some(json!(INVALID_PARAMS))
+        );
+        assert_eq!(
+            respond(
+                r#"{"jsonrpc": "2.0", "id": "r", "method": "read_text", "params": {"path": "a"}}"#,
+                echo
+            ),
+            Some(json!({
+                "jsonrpc": "2.0",
+                "id": "r",
+                "error": {"code": OPERATION_FAILED, "message": "fs.not_found: a"}
+            }))
+        );
+    }
+}
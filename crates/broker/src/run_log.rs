@@ -0,0 +1,248 @@
+//! Structured per-run log stream: `.saf/runs/<id>/log.jsonl`.
+//!
+//! Component log lines, progress reports and host-call summaries go here
+//! rather than into the audit log, which keeps only security-relevant
+//! entries. One JSON object per line so the file can be tailed while the run
+//! is still writing it.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+
+use crate::runs;
+
+pub fn log_path(workspace: &Path, run_id: &str) -> PathBuf {
+    runs::run_dir(workspace, run_id).join("log.jsonl")
+}
+
+/// Calls to one host function during a run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostCallSummary {
+    pub calls: u64,
+    pub total_us: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RunLogEntry {
+    Log {
+        ts_ms: u64,
+        message: String,
+    },
+    Progress {
+        ts_ms: u64,
+        done: u64,
+        total: u64,
+        message: String,
+    },
+    HostCalls {
+        ts_ms: u64,
+        calls: BTreeMap<String, HostCallSummary>,
+    },
+}
+
+impl std::fmt::Display for RunLogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Log { ts_ms, message } => write!(f, "{ts_ms} log      {message}"),
+            Self::Progress {
+                ts_ms,
+                done,
+                total,
+                message,
+            } => write!(f, "{ts_ms} progress {done}/{total} {message}"),
+            Self::HostCalls { ts_ms, calls } => {
+                write!(f, "{ts_ms} host-calls")?;
+                for (name, s) in calls {
+                    write!(f, "\n    {name}: {} calls, {} us", s.calls, s.total_us)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+pub fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+        .unwrap_or_default()
+}
+
+/// Appending writer shared by the host functions of one run.
+pub struct RunLog {
+    file: Mutex<File>,
+}
+
+impl RunLog {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("failed to open run log {}: {}", path.display(), e))?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn append(&self, entry: &RunLogEntry) -> Result<(), String> {
+        let mut line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+        line.push('\n');
+        let mut file = self.file.lock().map_err(|e| e.to_string())?;
+        file.write_all(line.as_bytes()).map_err(|e| e.to_string())
+    }
+}
+
+/// Complete entries after byte `offset`, and the offset to resume from. A
+/// trailing line still being written is left for the next call.
+pub fn read_from(path: &Path, offset: u64) -> Result<(Vec<RunLogEntry>, u64), String> {
+    let mut file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    file.seek(SeekFrom::Start(offset))
+        .map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(file);
+    let mut entries = Vec::new();
+    let mut pos = offset;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let n = reader.read_line(&mut line).map_err(|e| e.to_string())?;
+        if n == 0 || !line.ends_with('\n') {
+            break;
+        }
+        pos += n as u64;
+        let entry = serde_json::from_str(line.trim_end())
+            .map_err(|e| format!("malformed run log entry at byte {}: {}", pos, e))?;
+        entries.push(entry);
+    }
+    Ok((entries, pos))
+}
+
+/// Write a run's log to `out`, optionally following it until the run
+/// finishes.
+pub fn tail(
+    workspace: &Path,
+    run_id: &str,
+    follow: bool,
+    out: &mut dyn Write,
+) -> Result<(), String> {
+    let path = log_path(workspace, run_id);
+    let report = runs::run_dir(workspace, run_id).join("report.json");
+    let mut offset = 0;
+    loop {
+        // The report is written last, so checking it first means nothing
+        // logged before it appeared can be missed.
+        let finished = report.exists();
+        if path.exists() {
+            let (entries, next) = read_from(&path, offset)?;
+            for entry in entries {
+                writeln!(out, "{entry}").map_err(|e| e.to_string())?;
+            }
+            offset = next;
+        } else if finished || !follow {
+            return Err(format!("no log for run {run_id}"));
+        }
+        if finished || !follow {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(250));
+    }
+}
+
+/// Entry point for `broker runs tail <RUN_ID> [--follow] [--workspace DIR]`.
+#[derive(Debug, Subcommand)]
+pub enum RunsCommand {
+    /// Print a run's log, and with --follow keep printing it until the run
+    /// ends.
+    Tail {
+        run_id: String,
+        #[arg(short, long)]
+        follow: bool,
+        /// Workspace the run belongs to (default: the current directory).
+        #[arg(long, value_name = "DIR")]
+        workspace: Option<PathBuf>,
+    },
+}
+
+pub fn main(command: RunsCommand) -> Result<(), String> {
+    match command {
+        RunsCommand::Tail {
+            run_id,
+            follow,
+            workspace,
+        } => {
+            let workspace = workspace.unwrap_or_else(|| PathBuf::from("."));
+            tail(&workspace, &run_id, follow, &mut std::io::stdout().lock())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_from_resumes_and_skips_partial_lines() {
+        let path = std::env::temp_dir().join(format!("saf-run-log-{}.jsonl", uuid::Uuid::new_v4()));
+        let log = RunLog::open(&path).expect("open");
+        log.append(&RunLogEntry::Log {
+            ts_ms: 1,
+            message: "hello".to_string(),
+        })
+        .expect("append");
+
+        let (entries, offset) = read_from(&path, 0).expect("read");
+        assert_eq!(entries.len(), 1);
+
+        // A half-written line is not returned until it is complete.
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .and_then(|mut f| f.write_all(b"{\"kind\":\"log\""))
+            .expect("partial");
+        let (entries, again) = read_from(&path, offset).expect("read");
+        assert!(entries.is_empty());
+        assert_eq!(again, offset);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn tail_reads_the_given_workspace() {
+        let workspace =
+            std::env::temp_dir().join(format!("saf-run-log-ws-{}", uuid::Uuid::new_v4()));
+        let mut out = Vec::new();
+        let err = tail(&workspace, "run_1", false, &mut out).expect_err("no log yet");
+        assert!(err.contains("no log for run run_1"), "{err}");
+
+        let log = RunLog::open(&log_path(&workspace, "run_1")).expect("open");
+        log.append(&RunLogEntry::Log {
+            ts_ms: 7,
+            message: "started".to_string(),
+        })
+        .expect("append");
+        tail(&workspace, "run_1", false, &mut out).expect("tail");
+        assert_eq!(
+            String::from_utf8(out).expect("utf8"),
+            "7 log      started\n"
+        );
+
+        // Following a finished run prints what it logged and returns.
+        std::fs::write(runs::run_dir(&workspace, "run_1").join("report.json"), "{}")
+            .expect("report");
+        let mut out = Vec::new();
+        tail(&workspace, "run_1", true, &mut out).expect("follow");
+        assert!(!out.is_empty());
+
+        let _ = std::fs::remove_dir_all(&workspace);
+    }
+}
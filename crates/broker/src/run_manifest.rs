@@ -0,0 +1,128 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use saf_policy::Policy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Portable description of a component run (`run.toml`).
+///
+/// A manifest pins everything that influences a run: the exact component
+/// bytes, the arguments and input payload, the policy in force, the
+/// workspace inputs the run depends on and the RNG seed handed to the
+/// guest. Executing the same
+/// manifest against a workspace with matching inputs reproduces the run on
+/// any machine.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunManifest {
+    pub component: ComponentPin,
+    /// `KEY=VALUE` parameters, as given to `broker run --arg`.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// JSON payload, as given to `broker run --input`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<String>,
+    #[serde(default)]
+    pub policy: Policy,
+    #[serde(default)]
+    pub inputs: Vec<InputPin>,
+    #[serde(default)]
+    pub rng_seed: Option<u64>,
+}
+
+/// Component file and the SHA-256 digest it must match.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComponentPin {
+    /// Path to the component, relative to the manifest file unless absolute.
+    pub path: PathBuf,
+    pub sha256: String,
+}
+
+/// Workspace-relative input file and the SHA-256 digest it must match.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputPin {
+    pub path: String,
+    pub sha256: String,
+}
+
+impl RunManifest {
+    /// The bytes of the manifest at `path`, for [`RunManifest::parse`]
+    /// once their signature is checked.
+    pub fn read(path: &Path) -> Result<Vec<u8>, String> {
+        std::fs::read(path)
+            .map_err(|e| format!("failed to read manifest {}: {}", path.display(), e))
+    }
+
+    /// Parse `content`, read from `path`.
+    pub fn parse(path: &Path, content: &[u8]) -> Result<Self, String> {
+        std::str::from_utf8(content)
+            .map_err(|e| e.to_string())
+            .and_then(|s| toml::from_str(s).map_err(|e| e.to_string()))
+            .map_err(|e| format!("invalid manifest {}: {}", path.display(), e))
+    }
+
+    /// Resolve the pinned component path against the manifest location.
+    pub fn component_path(&self, manifest_path: &Path) -> PathBuf {
+        if self.component.path.is_absolute() {
+            return self.component.path.clone();
+        }
+        manifest_path
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join(&self.component.path)
+    }
+
+    /// Check `component`, the bytes read from the pinned path and the ones
+    /// that run, and every pinned input against their digests.
+    pub fn verify(
+        &self,
+        manifest_path: &Path,
+        component: &[u8],
+        workspace: &Path,
+    ) -> Result<(), String> {
+        let actual = sha256_hex(component);
+        if !actual.eq_ignore_ascii_case(&self.component.sha256) {
+            return Err(format!(
+                "component hash mismatch for {}: expected {}, got {}",
+                self.component_path(manifest_path).display(),
+                self.component.sha256,
+                actual
+            ));
+        }
+
+        for input in &self.inputs {
+            let rel = crate::sanitize_rel_path(&input.path)
+                .ok_or_else(|| format!("invalid input path in manifest: {}", input.path))?;
+            let actual = sha256_file(&workspace.join(rel))?;
+            if !actual.eq_ignore_ascii_case(&input.sha256) {
+                return Err(format!(
+                    "input fingerprint mismatch for {}: expected {}, got {}",
+                    input.path, input.sha256, actual
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Hex-encoded SHA-256 digest of a file's contents.
+pub fn sha256_file(path: &Path) -> Result<String, String> {
+    let mut f =
+        File::open(path).map_err(|e| format!("failed to open {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = f.read(&mut buf).map_err(|e| e.to_string())?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Hex-encoded SHA-256 digest of `bytes`.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::run_manifest::RunManifest;
+
+/// Directory holding per-run artifacts: `<workspace>/.saf/runs/<id>/`.
+pub fn run_dir(workspace: &Path, run_id: &str) -> PathBuf {
+    workspace.join(".saf").join("runs").join(run_id)
+}
+
+pub fn new_run_id() -> String {
+    format!("run_{}", uuid::Uuid::new_v4().simple())
+}
+
+pub fn now_unix_seconds() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RunOutcome {
+    Ok { output: String },
+    Failed { error: String },
+}
+
+/// Summary of a single component run, written to `report.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunReport {
+    pub run_id: String,
+    pub component: String,
+    pub component_sha256: String,
+    pub started_unix: u64,
+    pub finished_unix: u64,
+    pub outcome: RunOutcome,
+    /// Wasm fuel the run burned, if it got as far as running the guest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fuel_consumed: Option<u64>,
+    /// What the run used, if it got as far as running the guest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<Resources>,
+    /// The manifest the run was executed from, so it can be reproduced elsewhere.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<RunManifest>,
+}
+
+/// What a run used of the machine and its hosts.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resources {
+    pub fuel_consumed: u64,
+    /// The most linear memory the guest had.
+    pub peak_memory_bytes: u64,
+    /// Calls to each host function, such as `fs.read_text`.
+    pub host_calls: BTreeMap<String, u64>,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub bytes_fetched: u64,
+}
+
+impl Resources {
+    pub fn total_host_calls(&self) -> u64 {
+        self.host_calls.values().sum()
+    }
+
+    /// One line for the end of a run, such as
+    /// `peak memory 1.1 MiB, 12 host calls, read 4.0 KiB, wrote 0 B, fetched 0 B`.
+    pub fn summary(&self) -> String {
+        format!(
+            "peak memory {}, {} host calls, read {}, wrote {}, fetched {}",
+            format_bytes(self.peak_memory_bytes),
+            self.total_host_calls(),
+            format_bytes(self.bytes_read),
+            format_bytes(self.bytes_written),
+            format_bytes(self.bytes_fetched),
+        )
+    }
+}
+
+/// `bytes` in the largest binary unit that keeps it at least 1.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// Take `workspace`'s turn for a run, calling `waiting` first if another
+/// run, in this process or any other, has it. The turn is the lock on
+/// `.saf/run.lock`, and lasts until the returned file is dropped.
+pub fn take_turn(workspace: &Path, waiting: impl FnOnce()) -> Result<File, String> {
+    let dir = workspace.join(".saf");
+    std::fs::create_dir_all(&dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    let path = dir.join("run.lock");
+    let failed = |e: std::io::Error| format!("{}: {e}", path.display());
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .map_err(failed)?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            waiting();
+            file.lock().map_err(failed)?;
+        }
+        // Nothing to take turns with where the platform has no locks.
+        Err(TryLockError::Error(e)) if e.kind() == std::io::ErrorKind::Unsupported => {}
+        Err(TryLockError::Error(e)) => return Err(failed(e)),
+    }
+    Ok(file)
+}
+
+/// The reports of the workspace's runs, newest first, at most `limit` of
+/// them. Reports that cannot be read are skipped.
+pub fn list_reports(workspace: &Path, limit: usize) -> Result<Vec<RunReport>, String> {
+    let dir = workspace.join(".saf").join("runs");
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("{}: {e}", dir.display())),
+    };
+    let mut reports: Vec<RunReport> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path().join("report.json");
+            serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()
+        })
+        .collect();
+    reports.sort_by(|a, b| {
+        (b.started_unix, b.finished_unix, &b.run_id).cmp(&(
+            a.started_unix,
+            a.finished_unix,
+            &a.run_id,
+        ))
+    });
+    reports.truncate(limit);
+    Ok(reports)
+}
+
+impl RunReport {
+    pub fn write(&self, workspace: &Path) -> Result<PathBuf, String> {
+        let dir = run_dir(workspace, &self.run_id);
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let path = dir.join("report.json");
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(&path, content).map_err(|e| e.to_string())?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(run_id: &str, started_unix: u64) -> RunReport {
+        RunReport {
+            run_id: run_id.to_string(),
+            component: "app.wasm".to_string(),
+            component_sha256: "00".to_string(),
+            started_unix,
+            finished_unix: started_unix + 1,
+            outcome: RunOutcome::Ok {
+                output: String::new(),
+            },
+            fuel_consumed: Some(10),
+            resources: Some(Resources {
+                fuel_consumed: 10,
+                peak_memory_bytes: 3 << 19,
+                host_calls: BTreeMap::from([
+                    ("fs.read_text".to_string(), 2),
+                    ("log.info".to_string(), 1),
+                ]),
+                bytes_read: 4096,
+                ..Resources::default()
+            }),
+            manifest: None,
+        }
+    }
+
+    #[test]
+    fn reports_are_listed_newest_first_with_their_resources() {
+        let dir = std::env::temp_dir().join(format!("saf-runs-{}", uuid::Uuid::new_v4()));
+        assert!(list_reports(&dir, 10).unwrap().is_empty());
+        for (id, started) in [("run_a", 100), ("run_c", 300), ("run_b", 200)] {
+            report(id, started).write(&dir).unwrap();
+        }
+        std::fs::create_dir_all(run_dir(&dir, "run_partial")).unwrap();
+
+        let reports = list_reports(&dir, 2).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        let ids: Vec<_> = reports.iter().map(|r| r.run_id.as_str()).collect();
+        assert_eq!(ids, ["run_c", "run_b"]);
+        assert_eq!(reports[0].resources, report("run_c", 300).resources);
+        assert_eq!(
+            reports[0].resources.as_ref().unwrap().summary(),
+            "peak memory 1.5 MiB, 3 host calls, read 4.0 KiB, wrote 0 B, fetched 0 B"
+        );
+        assert_eq!(format_bytes(1023), "1023 B");
+    }
+
+    #[test]
+    fn runs_in_a_workspace_take_turns() {
+        let dir = std::env::temp_dir().join(format!("saf-runs-{}", uuid::Uuid::new_v4()));
+        let first = take_turn(&dir, || panic!("nothing else is running")).unwrap();
+        let (waiting, waited) = std::sync::mpsc::channel();
+        let second = {
+            let dir = dir.clone();
+            std::thread::spawn(move || take_turn(&dir, || waiting.send(()).unwrap()).map(drop))
+        };
+        waited.recv().unwrap();
+        assert!(!second.is_finished());
+        drop(first);
+        second.join().unwrap().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,1043 @@
+//! Confining the process an isolated run is in.
+//!
+//! A run under `--isolate` is in a process of its own (see
+//! [`crate::isolate`]), which the broker also has the operating system
+//! confine, so that a guest escaping wasmtime is left with the broker's
+//! pipe and not much else:
+//!
+//! - On Linux the child confines itself before it starts any threads.
+//!   Landlock limits it to writing the run's directory (the component
+//!   comes over the pipe, so it reads no files), and from ABI 4 denies it TCP. A seccomp filter refuses
+//!   sockets, running programs, starting processes, tracing or reaching
+//!   into other processes (pidfds, `kcmp`, `process_madvise`), namespaces,
+//!   mounts, kernel modules, BPF, io_uring and changing system settings.
+//! - On macOS it is started under `sandbox-exec` with a profile denying
+//!   everything but reading the system libraries and writing the run's
+//!   directory.
+//! - On Windows it is started in an AppContainer, `saf.isolated-run`, which
+//!   has no network capabilities and is granted the broker and the run's
+//!   directory with `icacls`. The grants stay in place.
+//!
+//! `[run] sandbox` in the broker config decides what happens when a layer
+//! cannot be applied, on an older kernel say: `best-effort`, the default,
+//! runs without it, `required` fails the run instead, and `off` skips
+//! confinement altogether. The layers applied, and those missing, are
+//! audited as `component.sandboxed`.
+//!
+//! Confinement takes raw system calls, so this is the one module of the
+//! broker allowed unsafe code; the broker's other calls that need it are
+//! in [`os`].
+#![allow(unsafe_code)]
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use serde::{Deserialize, Serialize};
+
+use crate::isolate::SUBCOMMAND;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    Off,
+    #[default]
+    BestEffort,
+    Required,
+}
+
+/// What a run's process may touch once confined.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Grants {
+    pub read: Vec<PathBuf>,
+    pub write: Vec<PathBuf>,
+}
+
+impl Grants {
+    /// The run's directories to write, which are created here. Nothing is
+    /// read: the component is sent to the child, not opened by it.
+    pub fn for_run(log_path: Option<&Path>, profile_dir: Option<&Path>) -> Result<Self, String> {
+        let real = |path: &Path| {
+            std::fs::canonicalize(path).map_err(|e| format!("{}: {}", path.display(), e))
+        };
+        let mut write = Vec::new();
+        for dir in log_path
+            .and_then(Path::parent)
+            .into_iter()
+            .chain(profile_dir)
+        {
+            std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+            write.push(real(dir)?);
+        }
+        Ok(Self {
+            read: Vec::new(),
+            write,
+        })
+    }
+}
+
+/// The layers of confinement a process is under, and those it could not
+/// be put under, with why.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Confinement {
+    pub layers: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+impl Confinement {
+    fn applied(&mut self, layer: &str, result: Result<(), String>) {
+        match result {
+            Ok(()) => self.layers.push(layer.to_string()),
+            Err(e) => self.missing.push(format!("{layer}: {e}")),
+        }
+    }
+
+    /// Fails a run `mode` requires to be confined if a layer is missing.
+    pub fn check(&self, mode: Mode) -> Result<(), String> {
+        match (mode, self.missing.is_empty()) {
+            (Mode::Required, false) => Err(format!(
+                "the run's sandbox is required, but {} could not be applied",
+                self.missing.join("; ")
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A started run process.
+pub trait Process: Send {
+    fn kill(&mut self) -> std::io::Result<()>;
+    /// Wait for it to exit, returning how it did.
+    fn wait(&mut self) -> std::io::Result<String>;
+}
+
+impl Process for std::process::Child {
+    fn kill(&mut self) -> std::io::Result<()> {
+        std::process::Child::kill(self)
+    }
+
+    fn wait(&mut self) -> std::io::Result<String> {
+        std::process::Child::wait(self).map(|status| status.to_string())
+    }
+}
+
+pub struct Runner {
+    pub stdin: Box<dyn Write + Send>,
+    pub stdout: Box<dyn Read + Send>,
+    pub process: Box<dyn Process>,
+    /// Layers applied as the process was started.
+    pub confinement: Confinement,
+}
+
+/// Start `exe isolated-run`, under the layer this platform applies as a
+/// process starts, if it has one.
+pub fn spawn(exe: &Path, grants: &Grants, mode: Mode) -> Result<Runner, String> {
+    let mut confinement = Confinement::default();
+    if mode != Mode::Off {
+        match launch(exe, grants) {
+            Some(Ok(runner)) => return Ok(runner),
+            Some(Err(e)) => {
+                confinement.missing.push(e);
+                confinement.check(mode)?;
+            }
+            None => {}
+        }
+    }
+    let mut command = Command::new(exe);
+    command.arg(SUBCOMMAND);
+    start(command, confinement)
+}
+
+#[cfg(target_os = "macos")]
+fn launch(exe: &Path, grants: &Grants) -> Option<Result<Runner, String>> {
+    let confinement = Confinement {
+        layers: vec!["seatbelt".to_string()],
+        missing: Vec::new(),
+    };
+    let command = seatbelt(exe, grants).map_err(|e| format!("seatbelt: {e}"));
+    Some(command.and_then(|command| start(command, confinement)))
+}
+
+#[cfg(windows)]
+fn launch(exe: &Path, grants: &Grants) -> Option<Result<Runner, String>> {
+    Some(appcontainer::spawn(exe, grants).map_err(|e| format!("appcontainer: {e}")))
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn launch(_exe: &Path, _grants: &Grants) -> Option<Result<Runner, String>> {
+    None
+}
+
+fn start(mut command: Command, confinement: Confinement) -> Result<Runner, String> {
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    // Out of the broker's process group, so Ctrl+C at a terminal reaches
+    // only the broker, which stops the run.
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    #[cfg(windows)]
+    std::os::windows::process::CommandExt::creation_flags(&mut command, 0x200);
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("cannot start the component's process: {e}"))?;
+    let stdin = child.stdin.take().ok_or("the child has no stdin")?;
+    let stdout = child.stdout.take().ok_or("the child has no stdout")?;
+    Ok(Runner {
+        stdin: Box::new(stdin),
+        stdout: Box::new(stdout),
+        process: Box::new(child),
+        confinement,
+    })
+}
+
+/// Confine this process, which must not have started any threads yet, for
+/// `mode`. `launched` is what it was started under.
+pub fn confine(grants: &Grants, mode: Mode, launched: Confinement) -> Confinement {
+    let mut confinement = launched;
+    if mode == Mode::Off {
+        return confinement;
+    }
+    #[cfg(target_os = "linux")]
+    match linux::no_new_privs() {
+        Ok(()) => {
+            confinement.applied("landlock", linux::landlock(grants));
+            confinement.applied("seccomp", linux::seccomp());
+        }
+        Err(e) => confinement.applied("no_new_privs", Err(e)),
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    confinement.applied("sandbox", Err("not available on this platform".to_string()));
+    let _ = grants;
+    confinement
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::fs::File;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::Path;
+
+    use super::Grants;
+
+    const CREATE_RULESET_VERSION: libc::c_uint = 1;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+
+    const ACCESS_EXECUTE: u64 = 1 << 0;
+    const ACCESS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_READ_FILE: u64 = 1 << 2;
+    const ACCESS_READ_DIR: u64 = 1 << 3;
+    const ACCESS_TRUNCATE: u64 = 1 << 14;
+    const ACCESS_IOCTL_DEV: u64 = 1 << 15;
+    /// The rights that apply to a file rather than a directory.
+    const ACCESS_FILE: u64 =
+        ACCESS_EXECUTE | ACCESS_WRITE_FILE | ACCESS_READ_FILE | ACCESS_TRUNCATE | ACCESS_IOCTL_DEV;
+    const ACCESS_NET_BIND_TCP: u64 = 1 << 0;
+    const ACCESS_NET_CONNECT_TCP: u64 = 1 << 1;
+    const SCOPE_ABSTRACT_UNIX_SOCKET: u64 = 1 << 0;
+    const SCOPE_SIGNAL: u64 = 1 << 1;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+        handled_access_net: u64,
+        scoped: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneath {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    fn last_error() -> String {
+        std::io::Error::last_os_error().to_string()
+    }
+
+    pub fn no_new_privs() -> Result<(), String> {
+        // SAFETY: prctl with integer arguments only.
+        match unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } {
+            0 => Ok(()),
+            _ => Err(last_error()),
+        }
+    }
+
+    /// Handle every filesystem right the kernel knows of, and TCP and
+    /// scoping where it has them, granting only what `grants` lists.
+    pub fn landlock(grants: &Grants) -> Result<(), String> {
+        // SAFETY: asks for the ABI version; no memory is passed.
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0usize,
+                CREATE_RULESET_VERSION,
+            )
+        };
+        if abi < 1 {
+            return Err(format!("not available ({})", last_error()));
+        }
+        let fs = match abi {
+            1 => (1 << 13) - 1,
+            2 => (1 << 14) - 1,
+            3 | 4 => (1 << 15) - 1,
+            _ => (1 << 16) - 1,
+        };
+        let attr = RulesetAttr {
+            handled_access_fs: fs,
+            handled_access_net: if abi >= 4 {
+                ACCESS_NET_BIND_TCP | ACCESS_NET_CONNECT_TCP
+            } else {
+                0
+            },
+            scoped: if abi >= 6 {
+                SCOPE_ABSTRACT_UNIX_SOCKET | SCOPE_SIGNAL
+            } else {
+                0
+            },
+        };
+        // SAFETY: `attr` outlives the call, which reads its size in bytes.
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        };
+        if fd < 0 {
+            return Err(last_error());
+        }
+        // SAFETY: the kernel just handed us this descriptor.
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+        for path in &grants.read {
+            allow(&ruleset, path, (ACCESS_READ_FILE | ACCESS_READ_DIR) & fs)?;
+        }
+        for path in &grants.write {
+            allow(&ruleset, path, fs)?;
+        }
+        // SAFETY: integer arguments only.
+        match unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) } {
+            0 => Ok(()),
+            _ => Err(last_error()),
+        }
+    }
+
+    fn allow(ruleset: &OwnedFd, path: &Path, access: u64) -> Result<(), String> {
+        let file = File::options()
+            .read(true)
+            .custom_flags(libc::O_PATH)
+            .open(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let is_dir = file.metadata().is_ok_and(|m| m.is_dir());
+        let rule = PathBeneath {
+            allowed_access: if is_dir { access } else { access & ACCESS_FILE },
+            parent_fd: file.as_raw_fd(),
+        };
+        // SAFETY: `rule` outlives the call, and `file` stays open through it.
+        let added = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset.as_raw_fd(),
+                RULE_PATH_BENEATH,
+                &rule as *const PathBeneath,
+                0,
+            )
+        };
+        match added {
+            0 => Ok(()),
+            _ => Err(format!("{}: {}", path.display(), last_error())),
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xC000_003E;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xC000_00B7;
+
+    /// Calls refused with EPERM.
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    const DENIED: &[libc::c_long] = &[
+        libc::SYS_socket,
+        libc::SYS_socketpair,
+        libc::SYS_connect,
+        libc::SYS_bind,
+        libc::SYS_listen,
+        libc::SYS_accept,
+        libc::SYS_accept4,
+        libc::SYS_execve,
+        libc::SYS_execveat,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_fork,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_vfork,
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_process_madvise,
+        libc::SYS_process_mrelease,
+        libc::SYS_pidfd_open,
+        libc::SYS_pidfd_getfd,
+        libc::SYS_pidfd_send_signal,
+        libc::SYS_kcmp,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_open_tree,
+        libc::SYS_move_mount,
+        libc::SYS_fsopen,
+        libc::SYS_fsconfig,
+        libc::SYS_fsmount,
+        libc::SYS_fspick,
+        libc::SYS_mount_setattr,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_unshare,
+        libc::SYS_setns,
+        libc::SYS_kexec_load,
+        libc::SYS_kexec_file_load,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_reboot,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_acct,
+        libc::SYS_quotactl,
+        libc::SYS_syslog,
+        libc::SYS_settimeofday,
+        libc::SYS_clock_settime,
+        libc::SYS_clock_adjtime,
+        libc::SYS_adjtimex,
+        libc::SYS_sethostname,
+        libc::SYS_setdomainname,
+        libc::SYS_personality,
+        libc::SYS_vhangup,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_iopl,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_ioperm,
+        libc::SYS_userfaultfd,
+        libc::SYS_io_uring_setup,
+        libc::SYS_io_uring_enter,
+        libc::SYS_io_uring_register,
+        libc::SYS_fanotify_init,
+        libc::SYS_name_to_handle_at,
+        libc::SYS_open_by_handle_at,
+    ];
+
+    /// Refuse [`DENIED`], and `clone` other than for a thread; `clone3`,
+    /// whose flags cannot be inspected, says it does not exist, so threads
+    /// are made with `clone`. Any other architecture's calls kill the
+    /// process.
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub fn seccomp() -> Result<(), String> {
+        use libc::{
+            sock_filter, sock_fprog, BPF_ABS, BPF_JEQ, BPF_JGE, BPF_JMP, BPF_JSET, BPF_K, BPF_LD,
+            BPF_RET, BPF_W, SECCOMP_RET_ALLOW, SECCOMP_RET_ERRNO, SECCOMP_RET_KILL_PROCESS,
+        };
+        let stmt = |code: u32, k: u32| sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        };
+        let jump = |code: u32, k: u32, jt: u8, jf: u8| sock_filter {
+            code: code as u16,
+            jt,
+            jf,
+            k,
+        };
+        let refuse = |errno: i32| stmt(BPF_RET | BPF_K, SECCOMP_RET_ERRNO | errno as u32);
+        // Offsets into `struct seccomp_data`.
+        let (nr, arch, arg0) = (0, 4, 16);
+
+        let mut program = vec![
+            stmt(BPF_LD | BPF_W | BPF_ABS, arch),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH, 1, 0),
+            stmt(BPF_RET | BPF_K, SECCOMP_RET_KILL_PROCESS),
+            stmt(BPF_LD | BPF_W | BPF_ABS, nr),
+            // The x32 ABI's numbers, on x86_64.
+            jump(BPF_JMP | BPF_JGE | BPF_K, 0x4000_0000, 0, 1),
+            refuse(libc::EPERM),
+        ];
+        for call in DENIED {
+            program.push(jump(BPF_JMP | BPF_JEQ | BPF_K, *call as u32, 0, 1));
+            program.push(refuse(libc::EPERM));
+        }
+        program.extend([
+            jump(BPF_JMP | BPF_JEQ | BPF_K, libc::SYS_clone3 as u32, 0, 1),
+            refuse(libc::ENOSYS),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, libc::SYS_clone as u32, 0, 3),
+            stmt(BPF_LD | BPF_W | BPF_ABS, arg0),
+            jump(BPF_JMP | BPF_JSET | BPF_K, libc::CLONE_THREAD as u32, 1, 0),
+            refuse(libc::EPERM),
+            stmt(BPF_RET | BPF_K, SECCOMP_RET_ALLOW),
+        ]);
+        let fprog = sock_fprog {
+            len: program.len() as u16,
+            filter: program.as_mut_ptr(),
+        };
+        // SAFETY: `fprog` and the program it points to outlive the call.
+        let installed = unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                0,
+                &fprog as *const sock_fprog,
+            )
+        };
+        match installed {
+            0 => Ok(()),
+            _ => Err(last_error()),
+        }
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn seccomp() -> Result<(), String> {
+        Err("not available on this architecture".to_string())
+    }
+}
+
+/// The profile for `sandbox-exec`, with the paths as parameters; grants
+/// are appended.
+#[cfg(target_os = "macos")]
+const SEATBELT_PROFILE: &str = r#"(version 1)
+(deny default)
+(allow process-exec (literal (param "EXE")))
+(allow file-read* (literal (param "EXE")) (subpath "/usr/lib") (subpath "/System")
+    (subpath "/Library/Apple") (subpath "/private/var/db/dyld"))
+(allow file-read-metadata)
+(allow sysctl-read)
+(allow signal (target self))
+"#;
+
+#[cfg(target_os = "macos")]
+fn seatbelt(exe: &Path, grants: &Grants) -> Result<Command, String> {
+    const SANDBOX_EXEC: &str = "/usr/bin/sandbox-exec";
+    if !Path::new(SANDBOX_EXEC).exists() {
+        return Err(format!("{SANDBOX_EXEC} not found"));
+    }
+    let mut profile = SEATBELT_PROFILE.to_string();
+    let mut command = Command::new(SANDBOX_EXEC);
+    command.arg("-D").arg(format!("EXE={}", exe.display()));
+    for (i, path) in grants.read.iter().enumerate() {
+        command.arg("-D").arg(format!("READ{i}={}", path.display()));
+        profile.push_str(&format!(
+            "(allow file-read* (literal (param \"READ{i}\")))\n"
+        ));
+    }
+    for (i, path) in grants.write.iter().enumerate() {
+        command
+            .arg("-D")
+            .arg(format!("WRITE{i}={}", path.display()));
+        profile.push_str(&format!(
+            "(allow file-read* file-write* (subpath (param \"WRITE{i}\")))\n"
+        ));
+    }
+    command.arg("-p").arg(profile).arg(exe).arg(SUBCOMMAND);
+    Ok(command)
+}
+
+#[cfg(windows)]
+mod appcontainer {
+    use std::fs::File;
+    use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle};
+    use std::path::Path;
+    use std::process::Command;
+
+    use windows::core::{HSTRING, PWSTR};
+    use windows::Win32::Foundation::{
+        CloseHandle, LocalFree, SetHandleInformation, HANDLE, HANDLE_FLAG_INHERIT, HLOCAL,
+    };
+    use windows::Win32::Security::Authorization::ConvertSidToStringSidW;
+    use windows::Win32::Security::Isolation::{
+        CreateAppContainerProfile, DeriveAppContainerSidFromAppContainerName,
+    };
+    use windows::Win32::Security::{FreeSid, PSID, SECURITY_ATTRIBUTES, SECURITY_CAPABILITIES};
+    use windows::Win32::System::Console::{GetStdHandle, STD_ERROR_HANDLE};
+    use windows::Win32::System::Pipes::CreatePipe;
+    use windows::Win32::System::Threading::{
+        CreateProcessW, DeleteProcessThreadAttributeList, GetExitCodeProcess,
+        InitializeProcThreadAttributeList, TerminateProcess, UpdateProcThreadAttribute,
+        WaitForSingleObject, CREATE_NEW_PROCESS_GROUP, EXTENDED_STARTUPINFO_PRESENT, INFINITE,
+        LPPROC_THREAD_ATTRIBUTE_LIST, PROCESS_INFORMATION,
+        PROC_THREAD_ATTRIBUTE_SECURITY_CAPABILITIES, STARTF_USESTDHANDLES, STARTUPINFOEXW,
+    };
+
+    use super::{Confinement, Grants, Process, Runner, SUBCOMMAND};
+
+    const NAME: &str = "saf.isolated-run";
+
+    /// A process in the AppContainer.
+    struct Contained(OwnedHandle);
+
+    impl Process for Contained {
+        fn kill(&mut self) -> std::io::Result<()> {
+            // SAFETY: the handle is the process's, open until dropped.
+            unsafe { TerminateProcess(HANDLE(self.0.as_raw_handle()), 1) }.map_err(Into::into)
+        }
+
+        fn wait(&mut self) -> std::io::Result<String> {
+            let process = HANDLE(self.0.as_raw_handle());
+            let mut code = 0u32;
+            // SAFETY: as above; `code` outlives the call.
+            unsafe {
+                WaitForSingleObject(process, INFINITE);
+                GetExitCodeProcess(process, &mut code)?;
+            }
+            Ok(format!("exit code: {code}"))
+        }
+    }
+
+    pub fn spawn(exe: &Path, grants: &Grants) -> Result<Runner, String> {
+        let sid = container_sid()?;
+        let result = sid_string(sid).and_then(|name| {
+            grant(&name, exe, "(RX)")?;
+            for path in &grants.read {
+                grant(&name, path, "(RX)")?;
+            }
+            for path in &grants.write {
+                grant(&name, path, "(OI)(CI)(M)")?;
+            }
+            start(exe, sid)
+        });
+        // SAFETY: the SID was allocated for us, and is no longer used.
+        unsafe { FreeSid(sid) };
+        result
+    }
+
+    fn container_sid() -> Result<PSID, String> {
+        let name = HSTRING::from(NAME);
+        let description = HSTRING::from("Secure App Framework isolated runs");
+        // SAFETY: the strings outlive the calls.
+        unsafe {
+            CreateAppContainerProfile(&name, &name, &description, None)
+                .or_else(|_| DeriveAppContainerSidFromAppContainerName(&name))
+        }
+        .map_err(|e| format!("cannot create the AppContainer: {e}"))
+    }
+
+    fn sid_string(sid: PSID) -> Result<String, String> {
+        let mut string = PWSTR::null();
+        // SAFETY: `sid` is valid, and the string it writes is freed below.
+        unsafe {
+            ConvertSidToStringSidW(sid, &mut string).map_err(|e| e.to_string())?;
+            let name = string.to_string().map_err(|e| e.to_string());
+            LocalFree(HLOCAL(string.0.cast()));
+            name
+        }
+    }
+
+    /// Let the AppContainer `sid` at `path`, with icacls `rights`.
+    fn grant(sid: &str, path: &Path, rights: &str) -> Result<(), String> {
+        let status = Command::new("icacls")
+            .arg(path)
+            .arg("/grant")
+            .arg(format!("*{sid}:{rights}"))
+            .stdout(std::process::Stdio::null())
+            .status()
+            .map_err(|e| format!("icacls: {e}"))?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(format!("icacls could not grant {}", path.display()))
+        }
+    }
+
+    /// A pipe whose `child` end the process inherits.
+    fn pipe(child_reads: bool) -> Result<(HANDLE, HANDLE), String> {
+        let attributes = SECURITY_ATTRIBUTES {
+            nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+            bInheritHandle: true.into(),
+            ..Default::default()
+        };
+        let (mut read, mut write) = (HANDLE::default(), HANDLE::default());
+        // SAFETY: the handles are written by the call and owned by us after.
+        unsafe {
+            CreatePipe(&mut read, &mut write, Some(&attributes), 0).map_err(|e| e.to_string())?;
+            let ours = if child_reads { write } else { read };
+            SetHandleInformation(ours, HANDLE_FLAG_INHERIT.0, Default::default())
+                .map_err(|e| e.to_string())?;
+        }
+        // (the child's end, ours)
+        Ok(if child_reads {
+            (read, write)
+        } else {
+            (write, read)
+        })
+    }
+
+    fn start(exe: &Path, sid: PSID) -> Result<Runner, String> {
+        let (child_stdin, stdin) = pipe(true)?;
+        let (child_stdout, stdout) = pipe(false)?;
+        let mut capabilities = SECURITY_CAPABILITIES {
+            AppContainerSid: sid,
+            ..Default::default()
+        };
+        let mut size = 0usize;
+        // SAFETY: the first call only reports the size the list needs; the
+        // list, the capabilities and the startup info outlive CreateProcessW.
+        unsafe {
+            let _ = InitializeProcThreadAttributeList(
+                LPPROC_THREAD_ATTRIBUTE_LIST::default(),
+                1,
+                0,
+                &mut size,
+            );
+            let mut buffer = vec![0u8; size];
+            let list = LPPROC_THREAD_ATTRIBUTE_LIST(buffer.as_mut_ptr().cast());
+            InitializeProcThreadAttributeList(list, 1, 0, &mut size).map_err(|e| e.to_string())?;
+            let started = (|| {
+                UpdateProcThreadAttribute(
+                    list,
+                    0,
+                    PROC_THREAD_ATTRIBUTE_SECURITY_CAPABILITIES as usize,
+                    Some(&mut capabilities as *mut SECURITY_CAPABILITIES as *const _),
+                    std::mem::size_of::<SECURITY_CAPABILITIES>(),
+                    None,
+                    None,
+                )?;
+                let mut info = STARTUPINFOEXW::default();
+                info.StartupInfo.cb = std::mem::size_of::<STARTUPINFOEXW>() as u32;
+                info.StartupInfo.dwFlags = STARTF_USESTDHANDLES;
+                info.StartupInfo.hStdInput = child_stdin;
+                info.StartupInfo.hStdOutput = child_stdout;
+                info.StartupInfo.hStdError = GetStdHandle(STD_ERROR_HANDLE)?;
+                info.lpAttributeList = list;
+                let mut line: Vec<u16> = format!("\"{}\" {SUBCOMMAND}", exe.display())
+                    .encode_utf16()
+                    .chain([0])
+                    .collect();
+                let mut process = PROCESS_INFORMATION::default();
+                CreateProcessW(
+                    &HSTRING::from(exe.as_os_str()),
+                    PWSTR(line.as_mut_ptr()),
+                    None,
+                    None,
+                    true,
+                    EXTENDED_STARTUPINFO_PRESENT | CREATE_NEW_PROCESS_GROUP,
+                    None,
+                    None,
+                    &info.StartupInfo,
+                    &mut process,
+                )?;
+                let _ = CloseHandle(process.hThread);
+                Ok::<_, windows::core::Error>(process.hProcess)
+            })();
+            DeleteProcThreadAttributeList(list);
+            let _ = CloseHandle(child_stdin);
+            let _ = CloseHandle(child_stdout);
+            let process = started.map_err(|e| format!("cannot start the AppContainer: {e}"))?;
+            Ok(Runner {
+                stdin: Box::new(File::from_raw_handle(stdin.0)),
+                stdout: Box::new(File::from_raw_handle(stdout.0)),
+                process: Box::new(Contained(OwnedHandle::from_raw_handle(process.0))),
+                confinement: Confinement {
+                    layers: vec!["appcontainer".to_string()],
+                    missing: Vec::new(),
+                },
+            })
+        }
+    }
+}
+
+/// The broker's other calls into the operating system that take unsafe
+/// code, kept here so that this is the one module allowed any.
+pub mod os {
+    #[cfg(windows)]
+    use std::path::Path;
+
+    /// The user the broker runs as.
+    #[cfg(unix)]
+    pub fn effective_uid() -> u32 {
+        // SAFETY: geteuid has no preconditions and cannot fail.
+        unsafe { libc::geteuid() }
+    }
+
+    /// Replace the ACL of the file at `path` with one granting its owner
+    /// alone, in place of the one it would inherit.
+    #[cfg(windows)]
+    pub fn restrict_to_owner(path: &Path) -> windows::core::Result<()> {
+        use windows::core::{w, HSTRING};
+        use windows::Win32::Foundation::{LocalFree, HLOCAL};
+        use windows::Win32::Security::Authorization::{
+            ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+        };
+        use windows::Win32::Security::{
+            SetFileSecurityW, DACL_SECURITY_INFORMATION, PROTECTED_DACL_SECURITY_INFORMATION,
+            PSECURITY_DESCRIPTOR,
+        };
+
+        let mut descriptor = PSECURITY_DESCRIPTOR::default();
+        // SAFETY: the SDDL is a static string; the descriptor it allocates
+        // is only used until it is freed below.
+        unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                w!("D:P(A;;FA;;;OW)"),
+                SDDL_REVISION_1,
+                &mut descriptor,
+                None,
+            )
+            .and_then(|()| {
+                let set = SetFileSecurityW(
+                    &HSTRING::from(path.as_os_str()),
+                    DACL_SECURITY_INFORMATION | PROTECTED_DACL_SECURITY_INFORMATION,
+                    descriptor,
+                );
+                LocalFree(HLOCAL(descriptor.0));
+                set
+            })
+        }
+    }
+
+    /// The owner and ACL of the file at `path`, in SDDL form.
+    #[cfg(windows)]
+    pub fn owner_and_acl(path: &Path) -> windows::core::Result<String> {
+        use windows::core::{HSTRING, PWSTR};
+        use windows::Win32::Foundation::{LocalFree, E_INVALIDARG, HLOCAL};
+        use windows::Win32::Security::Authorization::{
+            ConvertSecurityDescriptorToStringSecurityDescriptorW, GetNamedSecurityInfoW,
+            SDDL_REVISION_1, SE_FILE_OBJECT,
+        };
+        use windows::Win32::Security::{
+            DACL_SECURITY_INFORMATION, OWNER_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR,
+        };
+
+        let info = DACL_SECURITY_INFORMATION | OWNER_SECURITY_INFORMATION;
+        let mut descriptor = PSECURITY_DESCRIPTOR::default();
+        let mut sddl = PWSTR::null();
+        // SAFETY: the descriptor and its SDDL form are allocated by the
+        // calls that fill them in, and freed once the SDDL has been copied
+        // out.
+        unsafe {
+            GetNamedSecurityInfoW(
+                &HSTRING::from(path.as_os_str()),
+                SE_FILE_OBJECT,
+                info,
+                None,
+                None,
+                None,
+                None,
+                &mut descriptor,
+            )
+            .ok()?;
+            let text = ConvertSecurityDescriptorToStringSecurityDescriptorW(
+                descriptor,
+                SDDL_REVISION_1,
+                info,
+                &mut sddl,
+                None,
+            )
+            .and_then(|()| {
+                sddl.to_string()
+                    .map_err(|e| windows::core::Error::new(E_INVALIDARG, e.to_string()))
+            });
+            LocalFree(HLOCAL(sddl.0.cast()));
+            LocalFree(HLOCAL(descriptor.0));
+            text
+        }
+    }
+
+    /// Open the directory at `path` with `access`, which Windows refuses
+    /// unless its ACL grants it to this user, and close it again. (Backup
+    /// semantics are only what opening a directory takes; they bypass the
+    /// ACL only for a token with the backup privilege enabled.)
+    #[cfg(windows)]
+    pub fn try_open_directory(
+        path: &Path,
+        access: windows::Win32::Storage::FileSystem::FILE_ACCESS_RIGHTS,
+    ) -> windows::core::Result<()> {
+        use windows::core::HSTRING;
+        use windows::Win32::Foundation::{CloseHandle, HANDLE};
+        use windows::Win32::Storage::FileSystem::{
+            CreateFileW, FILE_FLAG_BACKUP_SEMANTICS, FILE_SHARE_DELETE, FILE_SHARE_READ,
+            FILE_SHARE_WRITE, OPEN_EXISTING,
+        };
+
+        // SAFETY: the path string outlives the call, and the handle it
+        // returns is closed here.
+        unsafe {
+            let handle = CreateFileW(
+                &HSTRING::from(path.as_os_str()),
+                access.0,
+                FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+                None,
+                OPEN_EXISTING,
+                FILE_FLAG_BACKUP_SEMANTICS,
+                HANDLE::default(),
+            )?;
+            let _ = CloseHandle(handle);
+        }
+        Ok(())
+    }
+
+    /// Ask the user for a folder with the shell's file dialog titled
+    /// `title`; `None` if they cancel it. The calling thread must not be a
+    /// COM apartment already.
+    #[cfg(windows)]
+    pub fn choose_folder(title: &str) -> windows::core::Result<Option<String>> {
+        use windows::core::HSTRING;
+        use windows::Win32::Foundation::{ERROR_CANCELLED, E_INVALIDARG, HWND};
+        use windows::Win32::System::Com::{
+            CoCreateInstance, CoInitializeEx, CoTaskMemFree, CoUninitialize, CLSCTX_INPROC_SERVER,
+            COINIT_APARTMENTTHREADED, COINIT_DISABLE_OLE1DDE,
+        };
+        use windows::Win32::UI::Shell::{
+            FileOpenDialog, IFileOpenDialog, FOS_FORCEFILESYSTEM, FOS_PICKFOLDERS,
+            SIGDN_FILESYSPATH,
+        };
+
+        // SAFETY: COM is initialised for this thread until the
+        // CoUninitialize below, and the dialog and the item it returns are
+        // released before it; the path string is ours to free.
+        unsafe {
+            CoInitializeEx(None, COINIT_APARTMENTTHREADED | COINIT_DISABLE_OLE1DDE).ok()?;
+            let chosen = (|| {
+                let dialog: IFileOpenDialog =
+                    CoCreateInstance(&FileOpenDialog, None, CLSCTX_INPROC_SERVER)?;
+                dialog.SetOptions(dialog.GetOptions()? | FOS_PICKFOLDERS | FOS_FORCEFILESYSTEM)?;
+                dialog.SetTitle(&HSTRING::from(title))?;
+                match dialog.Show(HWND::default()) {
+                    Err(e) if e.code() == ERROR_CANCELLED.to_hresult() => return Ok(None),
+                    result => result?,
+                }
+                let name = dialog.GetResult()?.GetDisplayName(SIGDN_FILESYSPATH)?;
+                let path = name.to_string();
+                CoTaskMemFree(Some(name.0.cast()));
+                path.map(Some)
+                    .map_err(|e| windows::core::Error::new(E_INVALIDARG, e.to_string()))
+            })();
+            CoUninitialize();
+            chosen
+        }
+    }
+
+    /// Ask the user for a folder with an `NSOpenPanel` showing `message`;
+    /// `Ok(None)` if they cancel it. AppKit is only usable from the main
+    /// thread, so from any other this fails.
+    #[cfg(target_os = "macos")]
+    pub fn choose_folder(message: &str) -> Result<Option<String>, String> {
+        use cocoa::appkit::{NSApp, NSApplication, NSApplicationActivationPolicy};
+        use cocoa::base::{id, nil, BOOL, NO, YES};
+        use cocoa::foundation::{NSAutoreleasePool, NSInteger, NSString};
+        use objc::{class, msg_send, sel, sel_impl};
+        use std::ffi::CStr;
+
+        /// `NSModalResponseOK`
+        const OK: NSInteger = 1;
+
+        // SAFETY: AppKit is only touched from the main thread, which is
+        // checked first; the objects made here are autoreleased and drained
+        // with the pool.
+        unsafe {
+            let main: BOOL = msg_send![class!(NSThread), isMainThread];
+            if main == NO {
+                return Err("the folder chooser must be opened from the main thread".to_string());
+            }
+            let pool = NSAutoreleasePool::new(nil);
+            // Show the panel in front, without a Dock icon for the broker.
+            let app = NSApp();
+            app.setActivationPolicy_(
+                NSApplicationActivationPolicy::NSApplicationActivationPolicyAccessory,
+            );
+            app.activateIgnoringOtherApps_(YES);
+            let panel: id = msg_send![class!(NSOpenPanel), openPanel];
+            let _: () = msg_send![panel, setCanChooseDirectories: YES];
+            let _: () = msg_send![panel, setCanChooseFiles: NO];
+            let _: () = msg_send![panel, setCanCreateDirectories: YES];
+            let _: () = msg_send![panel, setAllowsMultipleSelection: NO];
+            let message = NSString::alloc(nil).init_str(message).autorelease();
+            let _: () = msg_send![panel, setMessage: message];
+            let response: NSInteger = msg_send![panel, runModal];
+            let path = (response == OK).then(|| {
+                let url: id = msg_send![panel, URL];
+                let path: id = msg_send![url, path];
+                CStr::from_ptr(path.UTF8String())
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            pool.drain();
+            Ok(path)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grants_are_made_for_the_run_and_required_sandboxes_fail_runs_missing_a_layer() {
+        let dir = std::env::temp_dir().join(format!("saf-sandbox-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let run = dir.join(".saf/runs/run_1");
+        let grants = Grants::for_run(Some(&run.join("log.jsonl")), None).unwrap();
+        assert!(run.is_dir());
+        assert!(grants.read.is_empty());
+        assert_eq!(grants.write, [std::fs::canonicalize(&run).unwrap()]);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut confinement = Confinement::default();
+        confinement.applied("landlock", Ok(()));
+        assert_eq!(confinement.check(Mode::Required), Ok(()));
+        confinement.applied("seccomp", Err("not available".to_string()));
+        assert_eq!(confinement.layers, ["landlock"]);
+        assert_eq!(confinement.check(Mode::BestEffort), Ok(()));
+        let refused = confinement.check(Mode::Required).unwrap_err();
+        assert!(refused.contains("seccomp: not available"), "{refused}");
+
+        let config: toml::Value = toml::from_str(r#"sandbox = "best-effort""#).unwrap();
+        let mode: Mode = config["sandbox"].clone().try_into().unwrap();
+        assert_eq!(mode, Mode::BestEffort);
+    }
+
+    // Landlock and seccomp confine the thread they are applied on, so the
+    // rest of the tests go on unconfined.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn a_confined_thread_reaches_only_what_it_was_granted() {
+        let dir = std::env::temp_dir().join(format!("saf-confined-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let component = dir.join("app.wasm");
+        std::fs::write(&component, b"\0asm").unwrap();
+        std::fs::write(dir.join("secret.txt"), "not granted").unwrap();
+        let run = dir.join("runs/run_1");
+        let grants = Grants::for_run(Some(&run.join("log.jsonl")), None).unwrap();
+        let confined = dir.clone();
+        std::thread::spawn(move || {
+            let dir = confined;
+            let confinement = confine(&grants, Mode::BestEffort, Confinement::default());
+            let layers = confinement.layers;
+            if layers.iter().any(|l| l == "landlock") {
+                assert!(std::fs::read(&component).is_err());
+                assert!(std::fs::read(dir.join("secret.txt")).is_err());
+                assert!(std::fs::write(run.join("log.jsonl"), "{}").is_ok());
+                assert!(std::fs::write(dir.join("escaped.txt"), "").is_err());
+            }
+            if layers.iter().any(|l| l == "seccomp") {
+                let connect = std::net::TcpStream::connect("127.0.0.1:9").unwrap_err();
+                assert_eq!(connect.kind(), std::io::ErrorKind::PermissionDenied);
+                assert!(Command::new("true").status().is_err());
+                // Refused outright, rather than failing on the bad
+                // descriptor as it would unconfined.
+                // SAFETY: integer arguments only, naming no descriptor.
+                let taken = unsafe { libc::syscall(libc::SYS_pidfd_getfd, -1, 0, 0) };
+                assert_eq!(taken, -1);
+                assert_eq!(
+                    std::io::Error::last_os_error().raw_os_error(),
+                    Some(libc::EPERM)
+                );
+            }
+        })
+        .join()
+        .unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,490 @@
+//! Components `broker serve` runs on a schedule.
+//!
+//! Each `[[schedule]]` table of the broker config runs an installed
+//! component in a workspace whenever its cron expression comes due:
+//!
+//! ```toml
+//! [[schedule]]
+//! name = "nightly-index"
+//! component = "indexer"
+//! workspace = "workspace_…"
+//! cron = "30 2 * * 1-5"   # minute hour day-of-month month day-of-week, UTC
+//! missed = "run_once"     # or "skip" (the default)
+//! offline = true          # optional
+//! policy_dry_run = false  # optional
+//! ```
+//!
+//! Fields take `*`, a number, a range `a-b`, a step `*/n` or `a-b/n`, or a
+//! list of these; Sunday is 0 or 7. When both day fields are restricted, a
+//! day matching either one is due, as in cron.
+//!
+//! A fire that comes due while the schedule's last run is still going is
+//! skipped rather than run alongside it. The last fire of each schedule is
+//! kept in `<data_dir>/secure-app-framework/schedule.json`, so fires that
+//! fell while the broker was down are noticed when it starts again:
+//! `missed = "skip"` leaves them, and `"run_once"` runs the component once
+//! for all of them. Runs take their workspace's turn like any other (see
+//! [`crate::serve`]).
+//!
+//! Each scheduled run records a `schedule.run` entry in its workspace log
+//! naming the schedule and when the run was due, and counting the fires
+//! skipped or missed since the schedule last ran.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::cli::RunArgs;
+use crate::components;
+use crate::runs::{self, RunOutcome};
+use crate::serve;
+use crate::shutdown;
+use crate::workspace_picker::WorkspaceStore;
+
+/// How late a fire may be noticed and still count as on time.
+const GRACE_SECS: u64 = 60;
+/// Longest a schedule sleeps before looking at the clock again.
+const NAP: Duration = Duration::from_secs(60);
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    pub name: String,
+    /// An installed component.
+    pub component: String,
+    /// A workspace ID.
+    pub workspace: String,
+    pub cron: Cron,
+    #[serde(default)]
+    pub missed: Missed,
+    #[serde(default)]
+    pub offline: bool,
+    #[serde(default)]
+    pub policy_dry_run: bool,
+}
+
+/// What to do with fires that fell while the broker was down.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Missed {
+    #[default]
+    Skip,
+    RunOnce,
+}
+
+/// The schedule a run was started by, for its `schedule.run` entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scheduled {
+    pub name: String,
+    /// When the fire that started it was due, in unix seconds.
+    pub due_unix: u64,
+    /// Fires skipped since the schedule last ran, as its run was still going.
+    pub overlapped: u64,
+    /// Fires that fell while the broker was down, and were not run.
+    pub missed: u64,
+}
+
+/// A five-field cron expression, evaluated in UTC.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cron {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether a day must match both day fields, as when either is `*`.
+    both_days: bool,
+}
+
+impl Cron {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = source.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "{source:?}: expected minute, hour, day of month, month and day of week"
+            ));
+        };
+        let mut weekdays = field(weekday, 0, 7, "day of week")?;
+        // Sunday is 0 or 7.
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        let cron = Self {
+            source: source.to_string(),
+            minutes: field(minute, 0, 59, "minute")?,
+            hours: field(hour, 0, 23, "hour")?,
+            days: field(day, 1, 31, "day of month")?,
+            months: field(month, 1, 12, "month")?,
+            weekdays,
+            both_days: day.starts_with('*') || weekday.starts_with('*'),
+        };
+        if cron.next_after(0).is_none() {
+            return Err(format!("{source:?}: never comes due"));
+        }
+        Ok(cron)
+    }
+
+    /// The first minute after `unix` (seconds) that is due, if one is due
+    /// within 28 years.
+    pub fn next_after(&self, unix: u64) -> Option<u64> {
+        let mut t = (unix / 60 + 1) * 60;
+        let end = t + 28 * 366 * DAY_SECS;
+        while t < end {
+            let days = t / DAY_SECS;
+            let (_, month, day) = civil(days);
+            let weekday = (days + 4) % 7;
+            let in_month = self.days & (1 << day) != 0;
+            let in_week = self.weekdays & (1 << weekday) != 0;
+            let day_due = if self.both_days {
+                in_month && in_week
+            } else {
+                in_month || in_week
+            };
+            if self.months & (1 << month) == 0 || !day_due {
+                t = (days + 1) * DAY_SECS;
+            } else if self.hours & (1 << (t % DAY_SECS / 3600)) == 0 {
+                t = (t / 3600 + 1) * 3600;
+            } else if self.minutes & (1 << (t % 3600 / 60)) == 0 {
+                t += 60;
+            } else {
+                return Some(t);
+            }
+        }
+        None
+    }
+}
+
+impl TryFrom<String> for Cron {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, String> {
+        Self::parse(&source)
+    }
+}
+
+impl std::fmt::Display for Cron {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// The values one cron field allows, as bits.
+fn field(field: &str, min: u64, max: u64, name: &str) -> Result<u64, String> {
+    let invalid = || format!("{name}: {field:?}: expected values from {min} to {max}");
+    let number = |s: &str| {
+        s.parse::<u64>()
+            .ok()
+            .filter(|n| (min..=max).contains(n))
+            .ok_or_else(invalid)
+    };
+    let mut bits = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u64>().map_err(|_| invalid())?),
+            None => (item, 1),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((a, b)) => (number(a)?, number(b)?),
+            // `n/step` runs from n to the end of the field.
+            None if item.contains('/') => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if step == 0 || first > last {
+            return Err(invalid());
+        }
+        bits |= (first..=last)
+            .step_by(step as usize)
+            .fold(0, |bits, n| bits | (1 << n));
+    }
+    Ok(bits)
+}
+
+/// The year, month and day `days` after 1970-01-01.
+fn civil(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// What a schedule does at `now`, having last come due at `last`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Due {
+    /// The latest fire due by now, which becomes the schedule's last.
+    latest: u64,
+    /// Fires that fell too long ago to count as on time.
+    missed: u64,
+    /// Whether to run now, for the latest fire.
+    run: bool,
+}
+
+fn due(cron: &Cron, missed: Missed, last: u64, now: u64) -> Option<Due> {
+    let mut latest = None;
+    let mut fires = 0;
+    let mut t = last;
+    while let Some(next) = cron.next_after(t).filter(|next| *next <= now) {
+        latest = Some(next);
+        fires += 1;
+        t = next;
+    }
+    let latest = latest?;
+    let on_time = now - latest < GRACE_SECS;
+    let late = fires - u64::from(on_time);
+    Some(Due {
+        latest,
+        missed: late,
+        run: on_time || (late > 0 && missed == Missed::RunOnce),
+    })
+}
+
+/// Start a thread for each schedule, checking each one's workspace first.
+pub fn start(schedules: Vec<ScheduleConfig>) -> Result<(), String> {
+    let mut names = std::collections::BTreeSet::new();
+    let store = WorkspaceStore::new()?;
+    for schedule in &schedules {
+        if !names.insert(&schedule.name) {
+            return Err(format!("schedule {} is defined twice", schedule.name));
+        }
+        store
+            .load_workspace(&schedule.workspace)
+            .map_err(|e| format!("schedule {}: {e}", schedule.name))?;
+    }
+    let ledger = Arc::new(Ledger::new()?);
+    for schedule in schedules {
+        println!("Scheduled {} ({})", schedule.name, schedule.cron);
+        let ledger = ledger.clone();
+        std::thread::spawn(move || keep(schedule, &ledger));
+    }
+    Ok(())
+}
+
+/// Run `schedule` each time it comes due, for as long as the broker serves.
+fn keep(schedule: ScheduleConfig, ledger: &Ledger) {
+    let now = runs::now_unix_seconds();
+    let mut last = ledger.last(&schedule.name).unwrap_or_else(|| {
+        // Nothing before the broker first knew of the schedule is missed.
+        ledger.record(&schedule.name, now);
+        now
+    });
+    let (mut overlapped, mut missed) = (0, 0);
+    let mut running: Option<JoinHandle<()>> = None;
+    while shutdown::reason().is_none() {
+        let now = runs::now_unix_seconds();
+        let Some(due) = due(&schedule.cron, schedule.missed, last, now) else {
+            let wait = schedule
+                .cron
+                .next_after(last)
+                .map_or(NAP, |next| Duration::from_secs(next.saturating_sub(now)));
+            std::thread::sleep(wait.clamp(Duration::from_secs(1), NAP));
+            continue;
+        };
+        last = due.latest;
+        ledger.record(&schedule.name, last);
+        missed += due.missed;
+        if !due.run {
+            eprintln!(
+                "schedule {}: skipped {} missed runs",
+                schedule.name, due.missed
+            );
+        } else if running.as_ref().is_some_and(|run| !run.is_finished()) {
+            eprintln!(
+                "schedule {}: skipped; its last run is still going",
+                schedule.name
+            );
+            overlapped += 1;
+        } else {
+            let scheduled = Scheduled {
+                name: schedule.name.clone(),
+                due_unix: due.latest,
+                overlapped: std::mem::take(&mut overlapped),
+                missed: std::mem::take(&mut missed),
+            };
+            let schedule = schedule.clone();
+            running = Some(std::thread::spawn(move || {
+                match run_once(&schedule, scheduled) {
+                    Ok(RunOutcome::Ok { .. }) => {}
+                    Ok(RunOutcome::Failed { error }) | Err(error) => {
+                        eprintln!("schedule {}: {error}", schedule.name)
+                    }
+                }
+            }));
+        }
+    }
+}
+
+/// Run the component of `schedule` once, in its workspace's turn.
+fn run_once(schedule: &ScheduleConfig, scheduled: Scheduled) -> Result<RunOutcome, String> {
+    let component = components::installed_path(&schedule.component)?;
+    let args = RunArgs {
+        workspace_id: Some(schedule.workspace.clone()),
+        run_component: Some(component),
+        offline: schedule.offline,
+        policy_dry_run: schedule.policy_dry_run,
+        json: true,
+        schedule: Some(Arc::new(scheduled)),
+        ..RunArgs::default()
+    };
+    let turn = serve::turn(&schedule.workspace);
+    let _turn = turn.lock().unwrap_or_else(|e| e.into_inner());
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| e.to_string())?
+        .block_on(crate::run(args, false))
+        .map_err(|e| e.to_string())?
+        .map(|report| report.outcome)
+        .ok_or_else(|| "the run did not produce a report".to_string())
+}
+
+/// When each schedule last came due, kept across restarts.
+struct Ledger {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl Ledger {
+    fn new() -> Result<Self, String> {
+        let path = dirs::data_dir()
+            .ok_or("No data directory available")?
+            .join("secure-app-framework")
+            .join("schedule.json");
+        Ok(Self::at(path))
+    }
+
+    fn at(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn last(&self, name: &str) -> Option<u64> {
+        let _lock = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.load().get(name).copied()
+    }
+
+    /// Note that `name` came due at `unix`; a failure to is only reported,
+    /// as the schedule keeps running either way.
+    fn record(&self, name: &str, unix: u64) {
+        let _lock = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut fires = self.load();
+        fires.insert(name.to_string(), unix);
+        if let Err(e) = self.save(&fires) {
+            eprintln!("schedule {name}: {}: {e}", self.path.display());
+        }
+    }
+
+    fn load(&self) -> BTreeMap<String, u64> {
+        std::fs::read(&self.path)
+            .ok()
+            .and_then(|content| serde_json::from_slice(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, fires: &BTreeMap<String, u64>) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let content = serde_json::to_vec_pretty(fires).map_err(|e| e.to_string())?;
+        std::fs::write(&self.path, content).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2025-01-06T00:00:00Z, a Monday.
+    const MONDAY: u64 = 1_736_121_600;
+
+    #[test]
+    fn crons_come_due_and_missed_fires_follow_the_policy() {
+        assert_eq!(civil(MONDAY / DAY_SECS), (2025, 1, 6));
+        let weekdays = Cron::parse("30 2 * * 1-5").expect("cron");
+        assert_eq!(weekdays.next_after(MONDAY), Some(MONDAY + 2 * 3600 + 1800));
+        // Friday's run, then Monday's.
+        let friday = MONDAY + 4 * DAY_SECS + 2 * 3600 + 1800;
+        assert_eq!(weekdays.next_after(friday), Some(friday + 3 * DAY_SECS));
+
+        let quarter = Cron::parse("*/15 9-17 1,15 * 0").expect("cron");
+        // The 6th is neither the 1st, the 15th nor a Sunday.
+        let sunday = MONDAY - DAY_SECS;
+        assert_eq!(
+            quarter.next_after(MONDAY),
+            Some(MONDAY + 6 * DAY_SECS + 9 * 3600)
+        );
+        assert_eq!(
+            quarter.next_after(sunday + 9 * 3600),
+            Some(sunday + 9 * 3600 + 900)
+        );
+        let sundays = Cron::parse("0 0 * * 7").expect("cron");
+        assert_eq!(sundays.next_after(MONDAY), Some(MONDAY + 6 * DAY_SECS));
+        for bad in [
+            "* * * *",
+            "60 * * * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "0 0 30 2 *",
+        ] {
+            assert!(Cron::parse(bad).is_err(), "{bad}");
+        }
+
+        let hourly = Cron::parse("0 * * * *").expect("cron");
+        let noon = MONDAY + 12 * 3600;
+        assert_eq!(due(&hourly, Missed::Skip, noon, noon + 3599), None);
+        assert_eq!(
+            due(&hourly, Missed::Skip, noon, noon + 3610),
+            Some(Due {
+                latest: noon + 3600,
+                missed: 0,
+                run: true
+            })
+        );
+        // Down from noon until half past three.
+        let back = noon + 3 * 3600 + 1800;
+        assert_eq!(
+            due(&hourly, Missed::Skip, noon, back),
+            Some(Due {
+                latest: noon + 3 * 3600,
+                missed: 3,
+                run: false
+            })
+        );
+        assert_eq!(
+            due(&hourly, Missed::RunOnce, noon, back).map(|d| d.run),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn the_ledger_keeps_each_schedules_last_fire() {
+        let path = std::env::temp_dir()
+            .join(format!("saf-schedule-{}", uuid::Uuid::new_v4()))
+            .join("schedule.json");
+        let ledger = Ledger::at(path.clone());
+        assert_eq!(ledger.last("nightly"), None);
+        ledger.record("nightly", 60);
+        ledger.record("hourly", 120);
+        ledger.record("nightly", 180);
+        let reopened = Ledger::at(path.clone());
+        assert_eq!(
+            (reopened.last("nightly"), reopened.last("hourly")),
+            (Some(180), Some(120))
+        );
+        let _ = std::fs::remove_dir_all(path.parent().expect("dir"));
+    }
+}
@@ -0,0 +1,277 @@
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+/// Broker-held secrets, one file per entry under
+/// `<data_dir>/secure-app-framework/secrets/<name>`.
+///
+/// Secrets are read only by the broker; nothing in here is ever handed to a
+/// component. An entry other users can open is refused: on Unix one with
+/// group or other mode bits, on Windows one whose ACL lets in anyone but its
+/// owner, SYSTEM or Administrators.
+///
+/// Entries are plain files, not platform keychain items, so their
+/// permissions are all that guards them: any process running as the user
+/// can read them.
+pub struct SecretStore {
+    dir: PathBuf,
+}
+
+impl SecretStore {
+    pub fn new() -> Result<Self, String> {
+        let dir = dirs::data_dir()
+            .ok_or("No data directory available")?
+            .join("secure-app-framework")
+            .join("secrets");
+        Ok(Self::at(dir))
+    }
+
+    /// A store kept in `dir` instead.
+    pub fn at(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn get(&self, name: &str) -> Result<Vec<u8>, String> {
+        let path = self.path(name)?;
+        check_private(&path)?;
+        std::fs::read(&path).map_err(|e| format!("secret {name}: {e}"))
+    }
+
+    /// Like [`SecretStore::get`], but `None` when there is no such entry.
+    pub fn find(&self, name: &str) -> Result<Option<Vec<u8>>, String> {
+        if self.path(name)?.exists() {
+            self.get(name).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Store a new entry, readable only by the current user.
+    pub fn put(&self, name: &str, content: &[u8]) -> Result<(), String> {
+        let path = self.path(name)?;
+        if path.exists() {
+            return Err(format!("secret {name} already exists"));
+        }
+        std::fs::create_dir_all(&self.dir).map_err(|e| format!("{}: {}", self.dir.display(), e))?;
+        crate::elevation::write_private(&path, content)
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, String> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            && !name.starts_with('.');
+        if !valid {
+            return Err(format!("invalid secret name {name:?}"));
+        }
+        Ok(self.dir.join(name))
+    }
+}
+
+#[cfg(unix)]
+fn check_private(path: &Path) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = std::fs::metadata(path)
+        .map_err(|e| format!("secret {}: {}", path.display(), e))?
+        .permissions()
+        .mode();
+    if mode & 0o077 != 0 {
+        return Err(format!(
+            "secret {} is accessible to other users (mode {:o})",
+            path.display(),
+            mode & 0o777
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn check_private(path: &Path) -> Result<(), String> {
+    let sddl = crate::sandbox::os::owner_and_acl(path)
+        .map_err(|e| format!("secret {}: {}", path.display(), e))?;
+    check_private_sddl(path, &sddl)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn check_private(_path: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+/// Refuse a security descriptor, in SDDL form with its owner and DACL, that
+/// allows anyone but the owner, SYSTEM or Administrators in. A missing
+/// (NULL) DACL allows everyone.
+#[cfg(any(windows, test))]
+fn check_private_sddl(path: &Path, sddl: &str) -> Result<(), String> {
+    let (head, dacl) = sddl.split_once("D:").unwrap_or((sddl, ""));
+    let owner = head.strip_prefix("O:").unwrap_or_default();
+    let (flags, _) = dacl.split_once('(').unwrap_or((dacl, ""));
+    if dacl.is_empty() || flags.contains("NO_ACCESS_CONTROL") {
+        return Err(format!(
+            "secret {} has no access control list, so every user can open it",
+            path.display()
+        ));
+    }
+    let trusted = [
+        "OW",
+        "SY",
+        "BA",
+        "S-1-3-4",
+        "S-1-5-18",
+        "S-1-5-32-544",
+        owner,
+    ];
+    let others: Vec<&str> = sddl_aces(dacl)
+        .into_iter()
+        .filter_map(|ace| {
+            let fields: Vec<&str> = ace.split(';').collect();
+            let (kind, ace_flags, trustee) = (fields[0], fields.get(1)?, fields.get(5)?);
+            let allows = matches!(kind, "A" | "OA" | "XA" | "ZA");
+            // Inherit-only entries apply to children, not to this file.
+            let applies = !ace_flags.contains("IO");
+            (allows && applies && !trusted.contains(trustee)).then_some(*trustee)
+        })
+        .collect();
+    if !others.is_empty() {
+        return Err(format!(
+            "secret {} is accessible to other users (ACL allows {})",
+            path.display(),
+            others.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// The entries of an SDDL DACL, without their parentheses. Conditional
+/// entries nest parentheses of their own.
+#[cfg(any(windows, test))]
+fn sddl_aces(dacl: &str) -> Vec<&str> {
+    let mut aces = Vec::new();
+    let (mut depth, mut start) = (0usize, 0);
+    for (i, c) in dacl.char_indices() {
+        match c {
+            '(' => {
+                if depth == 0 {
+                    start = i + 1;
+                }
+                depth += 1;
+            }
+            ')' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    aces.push(&dacl[start..i]);
+                }
+            }
+            _ => {}
+        }
+    }
+    aces
+}
+
+/// PEM certificate chain and private key presented for mutual TLS.
+pub struct ClientIdentity {
+    cert_pem: Vec<u8>,
+    key_pem: Vec<u8>,
+}
+
+impl ClientIdentity {
+    pub fn load(store: &SecretStore, cert: &str, key: &str) -> Result<Self, String> {
+        let cert_pem = store.get(cert)?;
+        let key_pem = store.get(key)?;
+        if !contains(&cert_pem, b"-----BEGIN CERTIFICATE-----") {
+            return Err(format!("secret {cert} is not a PEM certificate"));
+        }
+        if !contains(&key_pem, b"PRIVATE KEY-----") {
+            return Err(format!("secret {key} is not a PEM private key"));
+        }
+        Ok(Self { cert_pem, key_pem })
+    }
+
+    /// The chain and key in the form the TLS transport presents them.
+    pub fn tls_client_cert(&self) -> Result<ureq::tls::ClientCert, String> {
+        let mut chain = Vec::new();
+        for item in ureq::tls::parse_pem(&self.cert_pem) {
+            if let ureq::tls::PemItem::Certificate(c) = item.map_err(|e| e.to_string())? {
+                chain.push(c);
+            }
+        }
+        let key = ureq::tls::parse_pem(&self.key_pem)
+            .find_map(|item| match item {
+                Ok(ureq::tls::PemItem::PrivateKey(k)) => Some(k),
+                _ => None,
+            })
+            .ok_or("client key secret holds no usable private key")?;
+        Ok(ureq::tls::ClientCert::new_with_certs(&chain, key))
+    }
+
+    /// SHA-256 of the certificate PEM, safe to audit.
+    pub fn fingerprint(&self) -> String {
+        hex::encode(Sha256::digest(&self.cert_pem))
+    }
+}
+
+// Never print key material, even by accident.
+impl std::fmt::Debug for ClientIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientIdentity")
+            .field("fingerprint", &self.fingerprint())
+            .finish_non_exhaustive()
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn entries_other_users_can_read_are_refused() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("saf-secrets-{}", uuid::Uuid::new_v4()));
+        let store = SecretStore::at(dir.clone());
+        store.put("token", b"s3cret").expect("put");
+        assert_eq!(store.get("token").expect("private"), b"s3cret");
+
+        std::fs::set_permissions(dir.join("token"), std::fs::Permissions::from_mode(0o640))
+            .expect("chmod");
+        let err = store.get("token").expect_err("group-readable");
+        assert!(
+            err.contains("accessible to other users (mode 640)"),
+            "{err}"
+        );
+        assert!(store.find("missing").expect("find").is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn acls_admitting_other_users_are_refused() {
+        let path = Path::new("token");
+        let user = "S-1-5-21-1-2-3-1001";
+        for private in [
+            "O:S-1-5-21-1-2-3-1001D:P(A;;FA;;;OW)".to_string(),
+            format!("O:{user}D:PAI(A;;FA;;;{user})(A;;FA;;;SY)(A;;FA;;;BA)"),
+            format!("O:{user}D:(A;OICIIO;FA;;;WD)(D;;FA;;;WD)(A;;FA;;;{user})"),
+        ] {
+            check_private_sddl(path, &private).expect(&private);
+        }
+
+        let err = check_private_sddl(path, &format!("O:{user}D:(A;;FR;;;BU)(A;;FA;;;{user})"))
+            .expect_err("users group");
+        assert!(err.contains("ACL allows BU"), "{err}");
+        let err = check_private_sddl(
+            path,
+            &format!("O:{user}D:(XA;;FR;;;WD;(@User.team == \"x\"))"),
+        )
+        .expect_err("conditional");
+        assert!(err.contains("ACL allows WD"), "{err}");
+        for open in [format!("O:{user}"), format!("O:{user}D:NO_ACCESS_CONTROL")] {
+            let err = check_private_sddl(path, &open).expect_err(&open);
+            assert!(err.contains("no access control list"), "{err}");
+        }
+    }
+}
@@ -0,0 +1,476 @@
+//! `broker selftest`: check that this build, on this machine, blocks what
+//! a hostile component would try.
+//!
+//! The self-test sets up a throwaway workspace in the system temp
+//! directory, with a canary file beside it, and makes these attempts, each
+//! of which must be refused:
+//!
+//! - `path-traversal` and `absolute-path`: reading the canary through
+//!   `..`, and by its absolute path;
+//! - `oversize-write` and `oversize-append`: writing a file, and growing
+//!   one, past `max_write_bytes`;
+//! - `disallowed-url`: fetching a domain the policy does not allow;
+//! - `metadata-endpoint`: fetching the cloud metadata address, which the
+//!   policy allows by name but `denied_ip_ranges` does not;
+//! - `cpu-spin`: a component looping forever, which `max_fuel` must stop;
+//! - `memory-bomb`: a component growing its memory without end, which
+//!   `memory.max_memory_bytes` must stop.
+//!
+//! The file and network attempts are host calls made the way a
+//! component's are, through the same checks and the broker's own hosts;
+//! none of them reaches the network. The last two are components bundled
+//! with the broker, run as any other would be. A broker built without the
+//! `wasmtime-host` feature cannot run them, and reports them as skipped.
+//!
+//! The report lists each attempt and how it ended. It is written to
+//! `--out`, `saf-selftest.json` unless given, and signed like a policy
+//! file (see [`crate::policy_sig`]), in `<file>.sig`, with the key file
+//! given as `--key` or else the audit signing key (`broker audit keygen`);
+//! without either it is left unsigned. The command fails if any attempt
+//! was not blocked.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use clap::Args;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use saf_core::{AuditRecord, Code, ComponentIdentity, Context, CoreResult, LogHost, Usage};
+use saf_policy::{Policy, PolicyBuilder, PolicyIssue, SharedPolicy};
+use serde::{Deserialize, Serialize};
+
+use crate::hosts::{HostEnv, HostRegistry};
+use crate::mounts::Mounts;
+use crate::net_stats::NetMeter;
+use crate::requests::Requests;
+use crate::wasmtime_host::{self, CoreCtx, RunOptions};
+use crate::{ask, audit, policy_sig, runs, Auditor};
+
+/// Largest write the self-test policy allows.
+const MAX_WRITE_BYTES: u64 = 4096;
+/// Fuel the bundled components may burn.
+const MAX_FUEL: u64 = 50_000_000;
+/// Linear memory the bundled components may have.
+const MAX_MEMORY_BYTES: u64 = 16 << 20;
+/// Backstop for a bundled component that is not stopped by its limits.
+const GUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// The link-local address cloud instances serve their metadata on.
+const METADATA_HOST: &str = "169.254.169.254";
+const CANARY: &str = "canary.txt";
+
+#[derive(Debug, Args)]
+pub struct SelftestArgs {
+    /// Where to write the report (default: saf-selftest.json).
+    #[arg(long, value_name = "PATH")]
+    pub out: Option<PathBuf>,
+    /// Sign the report with this key file, as made by `broker policy
+    /// keygen` (default: the audit signing key).
+    #[arg(long, value_name = "KEY_FILE")]
+    pub key: Option<PathBuf>,
+    /// Print the report as JSON.
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Blocked,
+    NotBlocked,
+    Skipped,
+}
+
+/// One attempt and how it ended.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attempt {
+    pub name: String,
+    pub verdict: Verdict,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelftestReport {
+    pub broker_version: String,
+    pub os: String,
+    pub arch: String,
+    pub finished_unix: u64,
+    pub attempts: Vec<Attempt>,
+    /// The base64 ed25519 public key the report is signed with, if it is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed_by: Option<String>,
+}
+
+impl SelftestReport {
+    /// Whether every attempt that was made was blocked.
+    pub fn passed(&self) -> bool {
+        self.attempts
+            .iter()
+            .all(|a| a.verdict != Verdict::NotBlocked)
+    }
+}
+
+/// The policy the attempts are made under: the whole workspace, two
+/// domains and small limits.
+fn selftest_policy() -> Result<Policy, String> {
+    let mut policy = PolicyBuilder::new()
+        .with_allowed_domains(["example.org", METADATA_HOST])
+        .build()
+        .map_err(|issues| PolicyIssue::join(&issues))?;
+    policy.max_write_bytes = MAX_WRITE_BYTES;
+    policy.max_fuel = Some(MAX_FUEL);
+    policy.memory.max_memory_bytes = MAX_MEMORY_BYTES;
+    Ok(policy)
+}
+
+/// Keeps the records the attempts make; the self-test has no workspace
+/// log of its own.
+#[derive(Default)]
+struct Records(std::sync::Mutex<Vec<AuditRecord>>);
+
+impl LogHost for Records {
+    fn record(&self, record: AuditRecord) {
+        if let Ok(mut records) = self.0.lock() {
+            records.push(record);
+        }
+    }
+}
+
+/// An attempt that must fail with `expected`.
+fn refused<T>(name: &str, result: CoreResult<T>, expected: Code) -> Attempt {
+    let (verdict, detail) = match result {
+        Ok(_) => (Verdict::NotBlocked, "the call succeeded".to_string()),
+        Err(e) if e.code() == expected => (Verdict::Blocked, e.to_string()),
+        Err(e) => (
+            Verdict::NotBlocked,
+            format!("failed, but not with {expected}: {e}"),
+        ),
+    };
+    Attempt {
+        name: name.to_string(),
+        verdict,
+        detail,
+    }
+}
+
+/// The file and network attempts, in a workspace with the canary at
+/// `canary`, outside it.
+fn host_attempts(ctx: &Context<'_>, canary: &Path) -> Vec<Attempt> {
+    let fill = "x".repeat(MAX_WRITE_BYTES as usize);
+    vec![
+        refused(
+            "path-traversal",
+            saf_core::read_text(ctx, &format!("../outside/{CANARY}")),
+            Code::FsInvalidPath,
+        ),
+        refused(
+            "absolute-path",
+            saf_core::read_text(ctx, &canary.display().to_string()),
+            Code::FsInvalidPath,
+        ),
+        refused(
+            "oversize-write",
+            saf_core::write_text(ctx, "big.txt", &format!("{fill}x")),
+            Code::PolicySizeLimit,
+        ),
+        refused(
+            "oversize-append",
+            saf_core::append_text(ctx, "grown.txt", &fill)
+                .and_then(|()| saf_core::append_text(ctx, "grown.txt", "x")),
+            Code::PolicySizeLimit,
+        ),
+        refused(
+            "disallowed-url",
+            saf_core::fetch(ctx, "https://selftest.invalid/"),
+            Code::PolicyDomainNotAllowed,
+        ),
+        refused(
+            "metadata-endpoint",
+            saf_core::fetch(ctx, &format!("https://{METADATA_HOST}/latest/meta-data/")),
+            Code::PolicyIpDenied,
+        ),
+    ]
+}
+
+/// A core module exporting `memory` and `start: func() -> i32`, whose
+/// body (without its local declarations) is `code`.
+fn module(code: &[u8]) -> Vec<u8> {
+    let mut body = vec![0x00];
+    body.extend_from_slice(code);
+    body.push(0x0b);
+    let mut bytes = b"\0asm\x01\0\0\0".to_vec();
+    section(&mut bytes, 1, &[0x01, 0x60, 0x00, 0x01, 0x7f]);
+    section(&mut bytes, 3, &[0x01, 0x00]);
+    section(&mut bytes, 5, &[0x01, 0x00, 0x01]);
+    let mut exports = vec![0x02];
+    exports.extend(name("memory"));
+    exports.extend([0x02, 0x00]);
+    exports.extend(name("start"));
+    exports.extend([0x00, 0x00]);
+    section(&mut bytes, 7, &exports);
+    let mut function = vec![0x01];
+    function.extend(leb(body.len()));
+    function.extend(body);
+    section(&mut bytes, 10, &function);
+    bytes
+}
+
+/// A component exporting `start: func() -> string`, lifted from the
+/// `start` of the core module `core`, as in the `app` world.
+fn component(core: &[u8]) -> Vec<u8> {
+    let mut bytes = b"\0asm\x0d\0\x01\0".to_vec();
+    section(&mut bytes, 1, core);
+    // Instantiate it, with no imports.
+    section(&mut bytes, 2, &[0x01, 0x00, 0x00, 0x00]);
+    // Alias its `start` function and its memory.
+    let mut aliases = vec![0x02, 0x00, 0x00, 0x01, 0x00];
+    aliases.extend(name("start"));
+    aliases.extend([0x00, 0x02, 0x01, 0x00]);
+    aliases.extend(name("memory"));
+    section(&mut bytes, 6, &aliases);
+    // func() -> string
+    section(&mut bytes, 7, &[0x01, 0x40, 0x00, 0x00, 0x73]);
+    // Lift it, UTF-8, reading the result from that memory.
+    section(
+        &mut bytes,
+        8,
+        &[0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x03, 0x00, 0x00],
+    );
+    let mut exports = vec![0x01, 0x00];
+    exports.extend(name("start"));
+    exports.extend([0x01, 0x00, 0x00]);
+    section(&mut bytes, 11, &exports);
+    bytes
+}
+
+fn section(bytes: &mut Vec<u8>, id: u8, content: &[u8]) {
+    bytes.push(id);
+    bytes.extend(leb(content.len()));
+    bytes.extend_from_slice(content);
+}
+
+fn name(name: &str) -> Vec<u8> {
+    let mut bytes = leb(name.len());
+    bytes.extend_from_slice(name.as_bytes());
+    bytes
+}
+
+fn leb(mut n: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// `(loop (br 0)) unreachable`
+const SPIN: &[u8] = &[0x03, 0x40, 0x0c, 0x00, 0x0b, 0x00];
+/// `(loop (br_if 0 (i32.ne (memory.grow (i32.const 16)) (i32.const -1))))
+/// unreachable`: a mebibyte at a time, until growing fails.
+const GROW: &[u8] = &[
+    0x03, 0x40, 0x41, 0x10, 0x40, 0x00, 0x41, 0x7f, 0x47, 0x0d, 0x00, 0x0b, 0x00,
+];
+
+/// Run the bundled component `name`, which must be stopped at `limit`.
+async fn guest_attempt(name: &str, code: &[u8], limit: &str, core: CoreCtx<'_>) -> Attempt {
+    let attempt = |verdict, detail: String| Attempt {
+        name: name.to_string(),
+        verdict,
+        detail,
+    };
+    if !cfg!(feature = "wasmtime-host") {
+        return attempt(
+            Verdict::Skipped,
+            "the broker was built without the 'wasmtime-host' feature".to_string(),
+        );
+    }
+    let path = PathBuf::from(format!("{name}.wasm"));
+    let bytes = component(&module(code));
+    let options = RunOptions {
+        max_fuel: Some(MAX_FUEL),
+        memory: core.ctx.policy.current().memory,
+        timeout: Some(GUEST_TIMEOUT),
+        ..RunOptions::default()
+    };
+    match wasmtime_host::run_component(&path, &bytes, core, &options).await {
+        Ok(finished) => match (finished.limit, finished.output) {
+            (Some(hit), Err(_)) if hit.limit == limit => attempt(Verdict::Blocked, hit.to_string()),
+            (_, Err(e)) if finished.timed_out => attempt(
+                Verdict::NotBlocked,
+                format!("{limit} did not stop it; the run timed out: {e}"),
+            ),
+            (_, Err(e)) => attempt(Verdict::NotBlocked, format!("not stopped at {limit}: {e}")),
+            (_, Ok(output)) => attempt(Verdict::NotBlocked, format!("it returned {output:?}")),
+        },
+        Err(e) => attempt(Verdict::NotBlocked, format!("could not run it: {e}")),
+    }
+}
+
+/// Make every attempt in a throwaway directory under `base`.
+async fn attempts(base: &Path) -> Result<Vec<Attempt>, String> {
+    let workspace = base.join("workspace");
+    let outside = base.join("outside");
+    for dir in [&workspace, &outside] {
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    }
+    let canary = outside.join(CANARY);
+    std::fs::write(&canary, "the self-test must not read this\n").map_err(|e| e.to_string())?;
+
+    let policy = SharedPolicy::new(selftest_policy()?);
+    let log = Records::default();
+    let component = ComponentIdentity::new("selftest").with_run_id(&runs::new_run_id());
+    let asker = ask::Asker::new(Box::new(ask::TerminalPrompt), &log, &component, None);
+    let mounts = Mounts::new(workspace.clone(), Vec::new())?;
+    let meter = NetMeter::default();
+    let env = HostEnv {
+        mounts: &mounts,
+        policy: &policy,
+        audit: Auditor {
+            log: &log,
+            policy: &policy,
+            component: &component,
+            ask: &asker,
+            dry_run: false,
+        },
+        meter: &meter,
+    };
+    let registry = HostRegistry::builtin();
+    let (fs, net, ws) = (
+        registry.fs("local", &env)?,
+        registry.net("http", &env)?,
+        registry.ws("none", &env)?,
+    );
+    let usage = Usage::default();
+    let ctx = Context {
+        fs: &*fs,
+        net: &*net,
+        ws: &*ws,
+        log: &log,
+        policy: &policy,
+        component: &component,
+        dry_run: false,
+        usage: &usage,
+    };
+    let requests = Requests::default();
+
+    let mut attempts = host_attempts(&ctx, &canary);
+    for (name, code, limit) in [
+        ("cpu-spin", SPIN, "max_fuel"),
+        ("memory-bomb", GROW, "memory.max_memory_bytes"),
+    ] {
+        let core = CoreCtx {
+            ctx: ctx.clone(),
+            requests: &requests,
+        };
+        attempts.push(guest_attempt(name, code, limit, core).await);
+    }
+    Ok(attempts)
+}
+
+pub async fn main(args: SelftestArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let base = std::env::temp_dir().join(format!("saf-selftest-{}", uuid::Uuid::new_v4()));
+    let attempts = attempts(&base).await;
+    let _ = std::fs::remove_dir_all(&base);
+
+    let pkcs8 = match &args.key {
+        Some(path) => Some(std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?),
+        None => audit::signing_key()?,
+    };
+    let pair = pkcs8
+        .as_deref()
+        .map(Ed25519KeyPair::from_pkcs8)
+        .transpose()
+        .map_err(|e| format!("invalid signing key: {e}"))?;
+    let report = SelftestReport {
+        broker_version: env!("CARGO_PKG_VERSION").to_string(),
+        os: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        finished_unix: runs::now_unix_seconds(),
+        attempts: attempts?,
+        signed_by: pair.as_ref().map(|p| BASE64.encode(p.public_key())),
+    };
+    let out = args
+        .out
+        .unwrap_or_else(|| PathBuf::from("saf-selftest.json"));
+    let content = serde_json::to_string_pretty(&report)?;
+    std::fs::write(&out, &content).map_err(|e| format!("{}: {e}", out.display()))?;
+    if let Some(pkcs8) = &pkcs8 {
+        let sig_path = policy_sig::Verifier::sig_path(&out);
+        std::fs::write(
+            &sig_path,
+            policy_sig::sign(pkcs8, content.as_bytes())? + "\n",
+        )
+        .map_err(|e| format!("{}: {e}", sig_path.display()))?;
+    }
+
+    if args.json {
+        println!("{content}");
+    } else {
+        for attempt in &report.attempts {
+            let verdict = match attempt.verdict {
+                Verdict::Blocked => "blocked",
+                Verdict::NotBlocked => "NOT BLOCKED",
+                Verdict::Skipped => "skipped",
+            };
+            println!("{verdict:<12} {:<18} {}", attempt.name, attempt.detail);
+        }
+        match &pair {
+            Some(pair) => println!(
+                "report: {} (signed by {})",
+                out.display(),
+                policy_sig::fingerprint(pair.public_key().as_ref())
+            ),
+            None => println!(
+                "report: {} (unsigned: give --key, or create the audit signing key with `broker audit keygen`)",
+                out.display()
+            ),
+        }
+    }
+    if report.passed() {
+        Ok(())
+    } else {
+        let failed = report
+            .attempts
+            .iter()
+            .filter(|a| a.verdict == Verdict::NotBlocked)
+            .count();
+        Err(format!(
+            "Self-test failed: {failed} of {} attempts were not blocked",
+            report.attempts.len()
+        )
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmparser::{Validator, WasmFeatures};
+
+    #[test]
+    fn bundled_components_are_valid() {
+        for code in [SPIN, GROW] {
+            let bytes = component(&module(code));
+            Validator::new_with_features(WasmFeatures::all())
+                .validate_all(&bytes)
+                .expect("valid component");
+        }
+        assert_eq!(leb(300), [0xac, 0x02]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn host_attempts_are_blocked() {
+        let base = std::env::temp_dir().join(format!("saf-selftest-{}", uuid::Uuid::new_v4()));
+        let attempts = attempts(&base).await.expect("attempts");
+        std::fs::remove_dir_all(&base).expect("cleanup");
+        for attempt in &attempts[..6] {
+            assert_eq!(attempt.verdict, Verdict::Blocked, "{attempt:?}");
+        }
+        assert_eq!(attempts.len(), 8);
+    }
+}
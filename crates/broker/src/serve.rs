@@ -0,0 +1,644 @@
+//! `broker serve`: one long-lived broker shared by the UI and other tools.
+//!
+//! The broker listens on a local control socket: a Unix domain socket,
+//! `broker.sock` in the runtime directory (or the data directory where
+//! there is none), or on Windows the named pipe
+//! `\\.\pipe\secure-app-framework-broker`. Clients write one JSON request
+//! per line and read one JSON response per line, in order:
+//!
+//! ```text
+//! {"id": 1, "command": "workspace.list"}
+//! {"id": 1, "ok": true, "result": [{"id": "workspace_…", "path": "/home/me/notes", "created": 1760000000}]}
+//! {"id": 2, "command": "run", "workspace": "workspace_…", "component": "notes"}
+//! {"id": 2, "ok": false, "error": "no component named notes is installed"}
+//! ```
+//!
+//! Commands: `ping`; `workspace.list`, `workspace.add` (`path`, absolute)
+//! and `workspace.remove` (`id`, in an elevated session); `audit.query` (`workspace` and an
+//! optional `query` with the fields of [`AuditQuery`], 100 entries to a page
+//! unless `limit` says otherwise); `trial.accept` (`workspace`, in an
+//! elevated session), which accepts the narrowed grant its trial proposes
+//! and answers with it;
+//! `runs.list` (`workspace`), the reports
+//! of its runs, newest first and 100 of them unless `limit` says otherwise,
+//! each with the fuel, peak memory, host calls and bytes the run used; and
+//! `run` (`workspace`, and either `component`, the name of an installed
+//! component, or `path`, with optional `offline` and `policy_dry_run`),
+//! which answers with the run's report once the component finishes. Each
+//! connection is served on its own, so a long run does not hold up other
+//! clients' requests; runs in the same workspace take turns, with each
+//! other and with runs other processes start there (see
+//! [`crate::runs::take_turn`]). A request line longer than
+//! [`MAX_REQUEST_LINE`] bytes is answered with an error, and the
+//! connection closed.
+//!
+//! `component.list` answers with the installed components, keyed by name:
+//! each one's file, hash, compatibility report and, if it has one, its
+//! manifest (name, version, publisher, requirements and resource hints).
+//!
+//! `component.start` (with the fields of `run`) instead hands the
+//! component to the broker to keep running, and answers with the ID it is
+//! managed under; `component.stop` and `component.restart` (`id`) and
+//! `component.ps` manage it from there. See [`crate::lifecycle`].
+//!
+//! `status` answers with the broker's health: its workspaces and whether a
+//! run is going in each, their policy hashes, audit chain heads and
+//! network, the components it manages and what it is using. See
+//! [`crate::status`].
+//!
+//! Once it is listening, the broker also runs the components scheduled in
+//! its config. See [`crate::schedule`]. Asked to shut down, it stops its
+//! runs and closes their audit logs before exiting; see
+//! [`crate::shutdown`].
+//!
+//! Only the broker's own user may connect. On Unix the socket is bound in
+//! a `0700` directory the user owns, made `0600` itself, and each
+//! connection's peer credentials are checked against the broker's user;
+//! connections from other users are refused and logged. On Windows
+//! the pipe refuses remote clients, and its default ACL only lets the
+//! broker's user, administrators and SYSTEM write requests to it.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, TryLockError};
+
+use clap::Args;
+use saf_audit::{AuditQuery, AuditReader};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::cli::RunArgs;
+use crate::components;
+use crate::config::BrokerConfig;
+use crate::elevation::ElevationStore;
+use crate::lifecycle::{self, Spec};
+use crate::schedule::{self, ScheduleConfig};
+use crate::status;
+use crate::workspace_picker::{self, WorkspaceStore};
+
+/// The longest request line a client may send.
+pub const MAX_REQUEST_LINE: usize = 1 << 20;
+
+/// Each workspace's turn, held for the length of a run in it.
+static TURNS: Mutex<BTreeMap<String, Arc<Mutex<()>>>> = Mutex::new(BTreeMap::new());
+
+/// When this broker started serving, in unix seconds.
+static STARTED: OnceLock<u64> = OnceLock::new();
+
+/// The lock a run in `workspace` holds while it runs.
+pub fn turn(workspace: &str) -> Arc<Mutex<()>> {
+    let mut turns = TURNS.lock().unwrap_or_else(|e| e.into_inner());
+    turns.entry(workspace.to_string()).or_default().clone()
+}
+
+/// Whether a run holds the turn of `workspace`.
+pub fn busy(workspace: &str) -> bool {
+    let turns = TURNS.lock().unwrap_or_else(|e| e.into_inner());
+    turns
+        .get(workspace)
+        .is_some_and(|turn| matches!(turn.try_lock(), Err(TryLockError::WouldBlock)))
+}
+
+/// Seconds since this broker started serving, if it is.
+pub fn uptime() -> Option<u64> {
+    let started = STARTED.get()?;
+    Some(crate::runs::now_unix_seconds().saturating_sub(*started))
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ServeArgs {
+    /// The socket (or pipe) to listen on.
+    #[arg(long, value_name = "PATH")]
+    pub socket: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command")]
+enum Request {
+    #[serde(rename = "ping")]
+    Ping,
+    #[serde(rename = "status")]
+    Status,
+    #[serde(rename = "workspace.list")]
+    WorkspaceList,
+    #[serde(rename = "workspace.add")]
+    WorkspaceAdd { path: PathBuf },
+    #[serde(rename = "workspace.remove")]
+    WorkspaceRemove { id: String },
+    #[serde(rename = "audit.query")]
+    AuditQuery {
+        workspace: String,
+        #[serde(default)]
+        query: AuditQuery,
+    },
+    #[serde(rename = "trial.accept")]
+    TrialAccept { workspace: String },
+    #[serde(rename = "runs.list")]
+    RunsList {
+        workspace: String,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    #[serde(rename = "run")]
+    Run {
+        workspace: String,
+        component: Option<String>,
+        path: Option<PathBuf>,
+        #[serde(default)]
+        offline: bool,
+        #[serde(default)]
+        policy_dry_run: bool,
+    },
+    #[serde(rename = "component.list")]
+    ComponentList,
+    #[serde(rename = "component.start")]
+    ComponentStart {
+        workspace: String,
+        component: Option<String>,
+        path: Option<PathBuf>,
+        #[serde(default)]
+        offline: bool,
+        #[serde(default)]
+        policy_dry_run: bool,
+    },
+    #[serde(rename = "component.stop")]
+    ComponentStop { id: u64 },
+    #[serde(rename = "component.restart")]
+    ComponentRestart { id: u64 },
+    #[serde(rename = "component.ps")]
+    ComponentPs,
+}
+
+/// Entry point for `broker serve [--socket <PATH>]`.
+pub async fn main(args: ServeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let socket = match args.socket {
+        Some(s) => s,
+        None => default_socket()?,
+    };
+    let config = BrokerConfig::load(&BrokerConfig::path()?)?;
+    STARTED.get_or_init(crate::runs::now_unix_seconds);
+    listen(&socket, config.schedules).await
+}
+
+#[cfg(unix)]
+fn default_socket() -> Result<PathBuf, String> {
+    let dir = dirs::runtime_dir()
+        .or_else(dirs::data_dir)
+        .ok_or("No runtime or data directory available")?;
+    Ok(dir.join("secure-app-framework").join("broker.sock"))
+}
+
+#[cfg(not(unix))]
+fn default_socket() -> Result<PathBuf, String> {
+    Ok(PathBuf::from(r"\\.\pipe\secure-app-framework-broker"))
+}
+
+#[cfg(unix)]
+async fn listen(
+    socket: &Path,
+    schedules: Vec<ScheduleConfig>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::net::{UnixListener, UnixStream};
+
+    let owner = crate::sandbox::os::effective_uid();
+    if let Some(dir) = socket.parent() {
+        private_dir(dir, owner)?;
+    }
+    if socket.exists() {
+        if UnixStream::connect(socket).await.is_ok() {
+            return Err(format!("{}: another broker is serving here", socket.display()).into());
+        }
+        // Left behind by a broker that did not shut down cleanly.
+        std::fs::remove_file(socket).map_err(|e| format!("{}: {e}", socket.display()))?;
+    }
+    let listener = UnixListener::bind(socket).map_err(|e| format!("{}: {e}", socket.display()))?;
+    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o600))
+        .map_err(|e| format!("{}: {e}", socket.display()))?;
+    println!("Serving on {}", socket.display());
+    schedule::start(schedules)?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        match stream.peer_cred() {
+            Ok(cred) if cred.uid() == owner => {
+                tokio::spawn(async move {
+                    if let Err(e) = handle(stream).await {
+                        eprintln!("serve: {e}");
+                    }
+                });
+            }
+            Ok(cred) => eprintln!(
+                "serve: refused a connection from uid {} (pid {})",
+                cred.uid(),
+                cred.pid()
+                    .map_or_else(|| "-".to_string(), |p| p.to_string())
+            ),
+            Err(e) => eprintln!("serve: refused a connection without peer credentials: {e}"),
+        }
+    }
+}
+
+/// Create the socket's directory `0700`, or close the one there to
+/// everyone but `owner`, its owner. The socket is bound inside it, so it is
+/// never reachable by another user, even before its own mode is set.
+#[cfg(unix)]
+fn private_dir(dir: &Path, owner: u32) -> Result<(), String> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+    let failed = |e: std::io::Error| format!("{}: {e}", dir.display());
+    if let Some(parent) = dir.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("{}: {e}", parent.display()))?;
+    }
+    match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => return Err(failed(e)),
+        _ => {}
+    }
+    let meta = std::fs::symlink_metadata(dir).map_err(failed)?;
+    if !meta.is_dir() || meta.uid() != owner {
+        return Err(format!(
+            "{}: not a directory owned by the current user",
+            dir.display()
+        ));
+    }
+    if meta.permissions().mode() & 0o077 != 0 {
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700)).map_err(failed)?;
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+async fn listen(
+    socket: &Path,
+    schedules: Vec<ScheduleConfig>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let name = socket.as_os_str();
+    let create = |first: bool| {
+        ServerOptions::new()
+            .first_pipe_instance(first)
+            .reject_remote_clients(true)
+            .create(name)
+            .map_err(|e| format!("{}: {e}", socket.display()))
+    };
+    let mut server = create(true)?;
+    println!("Serving on {}", socket.display());
+    schedule::start(schedules)?;
+    loop {
+        server.connect().await?;
+        let client = std::mem::replace(&mut server, create(false)?);
+        tokio::spawn(async move {
+            if let Err(e) = handle(client).await {
+                eprintln!("serve: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+async fn listen(
+    _socket: &Path,
+    _schedules: Vec<ScheduleConfig>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("control sockets are not supported on this platform".into())
+}
+
+/// Answer the requests on one connection until the client closes it, or
+/// sends a line longer than [`MAX_REQUEST_LINE`].
+async fn handle<S: AsyncRead + AsyncWrite>(stream: S) -> std::io::Result<()> {
+    let (read, mut write) = tokio::io::split(stream);
+    let mut read = BufReader::new(read);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let limit = MAX_REQUEST_LINE as u64 + 1;
+        if (&mut read).take(limit).read_until(b'\n', &mut line).await? == 0 {
+            return Ok(());
+        }
+        let too_long = line.len() > MAX_REQUEST_LINE && line.last() != Some(&b'\n');
+        let mut response = if too_long {
+            let error = format!("invalid request: longer than {MAX_REQUEST_LINE} bytes");
+            json!({"id": null, "ok": false, "error": error}).to_string()
+        } else {
+            let line = std::str::from_utf8(&line)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            respond(line).await.to_string()
+        };
+        response.push('\n');
+        write.write_all(response.as_bytes()).await?;
+        if too_long {
+            return Ok(());
+        }
+    }
+}
+
+/// The response to one request line, carrying the request's `id`.
+async fn respond(line: &str) -> Value {
+    let (id, result) = match serde_json::from_str::<Value>(line) {
+        Ok(value) => {
+            let id = value.get("id").cloned().unwrap_or(Value::Null);
+            let result = match serde_json::from_value::<Request>(value) {
+                Ok(request) => execute(request).await,
+                Err(e) => Err(format!("invalid request: {e}")),
+            };
+            (id, result)
+        }
+        Err(e) => (Value::Null, Err(format!("invalid request: {e}"))),
+    };
+    match result {
+        Ok(result) => json!({"id": id, "ok": true, "result": result}),
+        Err(error) => json!({"id": id, "ok": false, "error": error}),
+    }
+}
+
+async fn execute(request: Request) -> Result<Value, String> {
+    match request {
+        Request::Ping => Ok(json!({"version": env!("CARGO_PKG_VERSION")})),
+        Request::Status => to_value(&status::Health::collect(true)?),
+        Request::WorkspaceList => to_value(&WorkspaceStore::new()?.list_workspaces()?),
+        Request::WorkspaceAdd { path } => {
+            if !path.is_absolute() {
+                return Err(format!("{}: expected an absolute path", path.display()));
+            }
+            to_value(&WorkspaceStore::new()?.add_workspace(&path)?)
+        }
+        Request::WorkspaceRemove { id } => {
+            let path =
+                workspace_picker::remove(&WorkspaceStore::new()?, &ElevationStore::new()?, &id)?;
+            Ok(json!({"id": id, "path": path}))
+        }
+        Request::AuditQuery {
+            workspace,
+            mut query,
+        } => {
+            let (path, _) = WorkspaceStore::new()?.load_workspace(&workspace)?;
+            query.limit.get_or_insert(100);
+            let log = path.join(".saf").join("audit.log");
+            to_value(&AuditReader::open(&log)?.query(&query)?)
+        }
+        Request::TrialAccept { workspace } => {
+            let (path, _) = WorkspaceStore::new()?.load_workspace(&workspace)?;
+            to_value(&crate::trial::accept(&path, "ui", &ElevationStore::new()?)?)
+        }
+        Request::RunsList { workspace, limit } => {
+            let (path, _) = WorkspaceStore::new()?.load_workspace(&workspace)?;
+            to_value(&crate::runs::list_reports(&path, limit.unwrap_or(100))?)
+        }
+        Request::Run {
+            workspace,
+            component,
+            path,
+            offline,
+            policy_dry_run,
+        } => {
+            let (_, component) = component_path(component, path)?;
+            let turn = turn(&workspace);
+            let args = RunArgs {
+                workspace_id: Some(workspace),
+                run_component: Some(component),
+                offline,
+                policy_dry_run,
+                json: true,
+                ..RunArgs::default()
+            };
+            // A run holds the broker's hosts for as long as the component
+            // runs, so it gets a thread and runtime of its own.
+            let report = tokio::task::spawn_blocking(move || {
+                let _turn = turn.lock().map_err(|e| e.to_string())?;
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map_err(|e| e.to_string())?
+                    .block_on(crate::run(args, false))
+                    .map_err(|e| e.to_string())
+            })
+            .await
+            .map_err(|e| e.to_string())??;
+            to_value(&report)
+        }
+        Request::ComponentStart {
+            workspace,
+            component,
+            path,
+            offline,
+            policy_dry_run,
+        } => {
+            let (name, component) = component_path(component, path)?;
+            WorkspaceStore::new()?.load_workspace(&workspace)?;
+            let id = lifecycle::MANAGED.start(Spec {
+                name,
+                workspace,
+                component,
+                offline,
+                policy_dry_run,
+            })?;
+            Ok(json!({ "id": id }))
+        }
+        Request::ComponentList => {
+            to_value(&components::Registry::load(&components::Registry::dir()?)?.components)
+        }
+        Request::ComponentStop { id } => to_value(&lifecycle::MANAGED.stop(id)?),
+        Request::ComponentRestart { id } => to_value(&lifecycle::MANAGED.restart(id)?),
+        Request::ComponentPs => to_value(&lifecycle::MANAGED.ps()?),
+    }
+}
+
+/// The name and file of the component a `run` or `component.start` names:
+/// an installed `component`, or the one at `path`.
+fn component_path(
+    component: Option<String>,
+    path: Option<PathBuf>,
+) -> Result<(String, PathBuf), String> {
+    match (component, path) {
+        (Some(name), None) => Ok((name.clone(), components::installed_path(&name)?)),
+        (None, Some(path)) => {
+            let name = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            Ok((name, path))
+        }
+        _ => Err("run takes either component or path".to_string()),
+    }
+}
+
+/// Send one request to the broker serving on `socket` (default: the
+/// usual socket) and return its result.
+pub fn request(socket: Option<PathBuf>, request: Value) -> Result<Value, String> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let socket = match socket {
+        Some(s) => s,
+        None => default_socket()?,
+    };
+    let unreachable =
+        |e: std::io::Error| format!("{}: {e} (is `broker serve` running?)", socket.display());
+    #[cfg(unix)]
+    let stream = std::os::unix::net::UnixStream::connect(&socket).map_err(unreachable)?;
+    #[cfg(not(unix))]
+    let stream = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&socket)
+        .map_err(unreachable)?;
+    let mut line = request.to_string();
+    line.push('\n');
+    (&stream)
+        .write_all(line.as_bytes())
+        .map_err(|e| e.to_string())?;
+    let mut response = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut response)
+        .map_err(|e| e.to_string())?;
+    let response: Value = serde_json::from_str(&response)
+        .map_err(|e| format!("{}: unexpected response: {e}", socket.display()))?;
+    if response["ok"] == true {
+        Ok(response["result"].clone())
+    } else {
+        Err(response["error"]
+            .as_str()
+            .unwrap_or("request failed")
+            .to_string())
+    }
+}
+
+fn to_value<T: Serialize>(value: &T) -> Result<Value, String> {
+    serde_json::to_value(value).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn exchange<S: AsyncRead + AsyncWrite>(stream: S, requests: &str) -> Vec<Value> {
+        let (read, mut write) = tokio::io::split(stream);
+        write.write_all(requests.as_bytes()).await.expect("write");
+        write.shutdown().await.expect("shutdown");
+        let mut responses = Vec::new();
+        let mut lines = BufReader::new(read).lines();
+        while let Some(line) = lines.next_line().await.expect("read") {
+            responses.push(serde_json::from_str(&line).expect("json"));
+        }
+        responses
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_socket_directory_is_private() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let base = std::env::temp_dir().join(format!("saf-serve-{}", uuid::Uuid::new_v4()));
+        let dir = base.join("run");
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        // What this process creates is its user's.
+        std::fs::create_dir_all(&base).unwrap();
+        let owner = std::fs::metadata(&base).unwrap().uid();
+        private_dir(&dir, owner).expect("created");
+        assert_eq!(mode(&dir), 0o700);
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        private_dir(&dir, owner).expect("tightened");
+        assert_eq!(mode(&dir), 0o700);
+        let err = private_dir(&dir, owner + 1).unwrap_err();
+        assert!(err.contains("owned by the current user"), "{err}");
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn answers_each_request_line_in_order() {
+        let (client, server) = tokio::io::duplex(4096);
+        let served = tokio::spawn(handle(server));
+        let responses = exchange(
+            client,
+            "{\"id\": 1, \"command\": \"ping\"}\n\nnot json\n{\"id\": \"x\", \"command\": \"launch\"}\n\
+             {\"id\": 2, \"command\": \"run\", \"workspace\": \"w\"}\n",
+        )
+        .await;
+        served.await.expect("join").expect("serve");
+        assert_eq!(responses.len(), 4);
+        assert_eq!(
+            responses[0],
+            json!({"id": 1, "ok": true, "result": {"version": env!("CARGO_PKG_VERSION")}})
+        );
+        assert_eq!(
+            (&responses[1]["id"], &responses[1]["ok"]),
+            (&Value::Null, &json!(false))
+        );
+        assert_eq!(responses[2]["id"], "x");
+        let error = responses[2]["error"].as_str().unwrap_or_default();
+        assert!(error.contains("unknown variant `launch`"), "{error}");
+        assert_eq!(
+            responses[3],
+            json!({"id": 2, "ok": false, "error": "run takes either component or path"})
+        );
+    }
+
+    #[tokio::test]
+    async fn request_lines_over_the_limit_end_the_connection() {
+        let (client, server) = tokio::io::duplex(4096);
+        let served = tokio::spawn(handle(server));
+        let (read, mut write) = tokio::io::split(client);
+        let sent = tokio::spawn(async move {
+            let mut line = "{\"id\": 1, \"command\": \"ping\"}\n".to_string();
+            line.push_str(&" ".repeat(MAX_REQUEST_LINE + 1));
+            // The broker stops reading partway through.
+            let _ = write.write_all(line.as_bytes()).await;
+            write
+        });
+        let mut lines = BufReader::new(read).lines();
+        let ping: Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(ping["ok"], true);
+        let refused: Value =
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(
+            refused,
+            json!({
+                "id": null,
+                "ok": false,
+                "error": format!("invalid request: longer than {MAX_REQUEST_LINE} bytes"),
+            })
+        );
+        assert_eq!(lines.next_line().await.unwrap(), None);
+        served.await.expect("join").expect("serve");
+        drop(sent.await);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_its_socket_once() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("saf-serve-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let socket = dir.join("broker.sock");
+        let path = socket.clone();
+        let server =
+            tokio::spawn(async move { listen(&path, Vec::new()).await.map_err(|e| e.to_string()) });
+        let stream = loop {
+            match tokio::net::UnixStream::connect(&socket).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::task::yield_now().await,
+            }
+        };
+        let mode = std::fs::metadata(&socket)
+            .expect("socket")
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let responses = exchange(stream, "{\"id\": 1, \"command\": \"ping\"}\n").await;
+        assert_eq!(responses[0]["ok"], true);
+        let second = listen(&socket, Vec::new()).await.map_err(|e| e.to_string());
+        assert_eq!(
+            second,
+            Err(format!(
+                "{}: another broker is serving here",
+                socket.display()
+            ))
+        );
+        server.abort();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
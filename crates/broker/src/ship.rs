@@ -0,0 +1,123 @@
+//! Shipping the audit log to a remote collector.
+//!
+//! With `[audit.ship]` in the [broker config](crate::config), a
+//! [`Shipper`](saf_audit::Shipper) tails the workspace log during each run
+//! and POSTs its entries in batches to `endpoint`, so the audit trail
+//! survives the loss or compromise of the machine. Each request is a JSON
+//! object `{"log": ..., "entries": [{"id", "line", "record"}, ...]}`; any
+//! 2xx response acknowledges the batch. Entries are sent at least once, so
+//! the collector should deduplicate by `id`. `broker audit ship` catches up
+//! without starting a run.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use saf_audit::{Destination, ShippedEntry};
+use serde::Deserialize;
+use serde_json::json;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShipConfig {
+    /// Collector URL; https://, or http:// to a loopback address.
+    pub endpoint: String,
+    /// Sent with every request, e.g. an `authorization` header.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Most entries per request.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// How often the log is checked for new entries.
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /// Where the last acknowledged entry is kept; `<log>.ship` by default.
+    pub cursor: Option<PathBuf>,
+}
+
+fn default_batch_size() -> usize {
+    500
+}
+
+fn default_poll_interval_ms() -> u64 {
+    1_000
+}
+
+pub struct HttpDestination {
+    url: String,
+    headers: BTreeMap<String, String>,
+    log: String,
+    agent: ureq::Agent,
+}
+
+impl HttpDestination {
+    /// POST entries of the log named `log` as `config` says.
+    pub fn new(config: &ShipConfig, log: String) -> Result<Self, String> {
+        let url = url::Url::parse(&config.endpoint)
+            .map_err(|e| format!("endpoint {:?}: {}", config.endpoint, e))?;
+        let loopback = match url.host() {
+            Some(url::Host::Domain(d)) => d == "localhost",
+            Some(url::Host::Ipv4(ip)) => ip.is_loopback(),
+            Some(url::Host::Ipv6(ip)) => ip.is_loopback(),
+            None => false,
+        };
+        match url.scheme() {
+            "https" => {}
+            "http" if loopback => {}
+            _ => {
+                return Err(format!(
+                    "endpoint {:?}: expected https:// (http:// only to localhost)",
+                    config.endpoint
+                ))
+            }
+        }
+        Ok(Self {
+            url: config.endpoint.clone(),
+            headers: config.headers.clone(),
+            log,
+            agent: ureq::Agent::config_builder()
+                .timeout_global(Some(REQUEST_TIMEOUT))
+                .build()
+                .into(),
+        })
+    }
+}
+
+impl Destination for HttpDestination {
+    fn send(&mut self, entries: &[ShippedEntry]) -> Result<(), String> {
+        let body = json!({"log": self.log, "entries": entries});
+        let mut request = self.agent.post(&self.url);
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        request
+            .header("content-type", "application/json")
+            .send(body.to_string())
+            .map(drop)
+            .map_err(|e| format!("{}: {}", self.url, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_http_is_only_allowed_to_loopback() {
+        let config = |endpoint: &str| ShipConfig {
+            endpoint: endpoint.to_string(),
+            headers: BTreeMap::new(),
+            batch_size: default_batch_size(),
+            poll_interval_ms: default_poll_interval_ms(),
+            cursor: None,
+        };
+        let destination = |endpoint| HttpDestination::new(&config(endpoint), String::new());
+        assert!(destination("https://audit.example.com/ingest").is_ok());
+        assert!(destination("http://127.0.0.1:8080/ingest").is_ok());
+        assert!(destination("http://localhost/ingest").is_ok());
+        assert!(destination("http://audit.example.com/ingest").is_err());
+        assert!(destination("ftp://audit.example.com/").is_err());
+    }
+}
@@ -0,0 +1,232 @@
+//! Shutting the broker down without leaving an audit log mid-write.
+//!
+//! On SIGINT or SIGTERM (on Windows, Ctrl+C, Ctrl+Break, the console being
+//! closed, or the system shutting down) the broker stops starting runs:
+//! the components `broker serve` manages are stopped rather than restarted,
+//! schedules stop firing, and every run going is asked to stop, as
+//! `component.stop` would. Each run is interrupted at its next epoch tick,
+//! and ends the usual way, writing its `component.finish` entry, run log
+//! and report, then a `broker.shutdown` entry with the reason, and flushes
+//! its audit writer.
+//!
+//! Runs get [`GRACE`] to drain. The logs of any still going then have the
+//! `broker.shutdown` entry appended, marked as not drained, and are flushed
+//! before the broker exits, with 130 for an interrupt and 143 otherwise.
+//! The grace period is short enough for the few seconds Windows allows a
+//! console that is being closed.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::lifecycle;
+
+/// How long runs have to end once the broker is asked to shut down.
+pub const GRACE: Duration = Duration::from_secs(5);
+
+/// Why the broker is shutting down, once it is.
+static REASON: OnceLock<Reason> = OnceLock::new();
+
+static ACTIVE: Mutex<Active> = Mutex::new(Active {
+    next_id: 0,
+    runs: BTreeMap::new(),
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    Interrupt,
+    Terminate,
+    #[cfg_attr(not(windows), allow(dead_code))]
+    ConsoleClosed,
+    #[cfg_attr(not(windows), allow(dead_code))]
+    SystemShutdown,
+}
+
+impl Reason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Reason::Interrupt => "interrupt",
+            Reason::Terminate => "terminate",
+            Reason::ConsoleClosed => "console_closed",
+            Reason::SystemShutdown => "system_shutdown",
+        }
+    }
+
+    fn exit_code(self) -> i32 {
+        match self {
+            Reason::Interrupt => 130,
+            _ => 143,
+        }
+    }
+}
+
+/// Records `broker.shutdown` in a run's log, with the reason and whether
+/// the run ended in time, and flushes it.
+pub type Close = Box<dyn Fn(Reason, bool) + Send + Sync>;
+
+struct Active {
+    next_id: u64,
+    runs: BTreeMap<u64, Run>,
+}
+
+struct Run {
+    stop: Arc<AtomicBool>,
+    close: Close,
+}
+
+/// A run going in this process, until dropped. Dropping it during a
+/// shutdown closes the run's log.
+pub struct Registration(u64);
+
+/// Why the broker is shutting down, if it is.
+pub fn reason() -> Option<Reason> {
+    REASON.get().copied()
+}
+
+/// Note a run that `stop` interrupts, and whose log `close` finishes. A run
+/// registered once the broker is shutting down is stopped at once.
+pub fn register(stop: Arc<AtomicBool>, close: Close) -> Registration {
+    if reason().is_some() {
+        stop.store(true, Ordering::Relaxed);
+    }
+    let mut active = ACTIVE.lock().unwrap_or_else(|e| e.into_inner());
+    active.next_id += 1;
+    let id = active.next_id;
+    active.runs.insert(id, Run { stop, close });
+    Registration(id)
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let run = {
+            let mut active = ACTIVE.lock().unwrap_or_else(|e| e.into_inner());
+            active.runs.remove(&self.0)
+        };
+        if let (Some(run), Some(reason)) = (run, reason()) {
+            (run.close)(reason, true);
+        }
+    }
+}
+
+/// Stop every run for `reason`, wait up to `grace` for them to end, and
+/// close the logs of those that did not. Returns how many did not.
+pub fn drain(reason: Reason, grace: Duration) -> usize {
+    let _ = REASON.set(reason);
+    let reason = REASON.get().copied().unwrap_or(reason);
+    let _ = lifecycle::MANAGED.stop_all();
+    {
+        let active = ACTIVE.lock().unwrap_or_else(|e| e.into_inner());
+        for run in active.runs.values() {
+            run.stop.store(true, Ordering::Relaxed);
+        }
+    }
+    let deadline = Instant::now() + grace;
+    loop {
+        let empty = ACTIVE
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .runs
+            .is_empty();
+        if empty || Instant::now() >= deadline {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let left = std::mem::take(&mut ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).runs);
+    for run in left.values() {
+        (run.close)(reason, false);
+    }
+    left.len()
+}
+
+/// Wait for a signal to shut down, then drain and exit.
+pub async fn on_signal() {
+    let Some(reason) = signal().await else {
+        return;
+    };
+    eprintln!("Shutting down ({})", reason.as_str());
+    let left = tokio::task::spawn_blocking(move || drain(reason, GRACE))
+        .await
+        .unwrap_or_default();
+    if left > 0 {
+        eprintln!("{left} runs did not stop in time; their audit logs were closed");
+    }
+    std::process::exit(reason.exit_code());
+}
+
+/// The first shutdown signal; `None` if they cannot be listened for.
+#[cfg(unix)]
+async fn signal() -> Option<Reason> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt()).ok()?;
+    let mut terminate = signal(SignalKind::terminate()).ok()?;
+    tokio::select! {
+        _ = interrupt.recv() => Some(Reason::Interrupt),
+        _ = terminate.recv() => Some(Reason::Terminate),
+    }
+}
+
+#[cfg(windows)]
+async fn signal() -> Option<Reason> {
+    use tokio::signal::windows;
+
+    let mut c = windows::ctrl_c().ok()?;
+    let mut brk = windows::ctrl_break().ok()?;
+    let mut close = windows::ctrl_close().ok()?;
+    let mut shutdown = windows::ctrl_shutdown().ok()?;
+    tokio::select! {
+        _ = c.recv() => Some(Reason::Interrupt),
+        _ = brk.recv() => Some(Reason::Interrupt),
+        _ = close.recv() => Some(Reason::ConsoleClosed),
+        _ = shutdown.recv() => Some(Reason::SystemShutdown),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+async fn signal() -> Option<Reason> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shutdown_stops_runs_and_closes_the_logs_of_stragglers() {
+        let closed = Arc::new(Mutex::new(Vec::new()));
+        let run = |name: &'static str| {
+            let stop = Arc::new(AtomicBool::new(false));
+            let closed = closed.clone();
+            let close: Close = Box::new(move |reason, drained| {
+                closed.lock().unwrap().push((name, reason, drained));
+            });
+            (stop.clone(), register(stop, close))
+        };
+        let (quick_stop, quick) = run("quick");
+        let (_, stuck) = run("stuck");
+        let ends = std::thread::spawn(move || {
+            while !quick_stop.load(Ordering::Relaxed) {
+                std::thread::yield_now();
+            }
+            drop(quick);
+        });
+
+        assert_eq!(drain(Reason::Terminate, Duration::from_millis(200)), 1);
+        ends.join().unwrap();
+        assert_eq!(reason(), Some(Reason::Terminate));
+        assert_eq!(
+            *closed.lock().unwrap(),
+            [
+                ("quick", Reason::Terminate, true),
+                ("stuck", Reason::Terminate, false)
+            ]
+        );
+        // Closed already, so not again.
+        drop(stuck);
+        let (late_stop, _late) = run("late");
+        assert!(late_stop.load(Ordering::Relaxed));
+        assert_eq!(closed.lock().unwrap().len(), 2);
+    }
+}
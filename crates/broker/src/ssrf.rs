@@ -0,0 +1,67 @@
+use std::net::SocketAddr;
+
+use saf_core::Code;
+use saf_policy::{Denial, Policy};
+
+use crate::dns::Resolve;
+
+/// A request target whose address has been resolved once and validated.
+/// The transport must connect to `addr` rather than re-resolving `host`, so
+/// a DNS answer that changes between check and connect (rebinding) has no
+/// effect.
+#[derive(Debug, Clone)]
+pub struct PinnedTarget {
+    pub host: String,
+    pub addr: SocketAddr,
+}
+
+/// Why a target could not be pinned.
+#[derive(Debug)]
+pub enum PinError {
+    /// An answer fell in a denied range; the message names the address.
+    Denied(Denial, String),
+    Failed(String),
+}
+
+impl From<String> for PinError {
+    fn from(msg: String) -> Self {
+        Self::Failed(msg)
+    }
+}
+
+/// Resolve the URL's host and reject it if any answer falls in the policy's
+/// denied IP ranges.
+pub fn resolve_pinned(
+    url: &url::Url,
+    policy: &Policy,
+    resolver: &dyn Resolve,
+) -> Result<PinnedTarget, PinError> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| Code::NetFailed.with_message("URL has no host"))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| Code::NetFailed.with_message("URL has no port"))?;
+    // Strip IPv6 literal brackets before resolving.
+    let bare = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs = resolver.resolve(bare, port)?;
+
+    // Any denied answer rejects the host: a round-robin record mixing public
+    // and private addresses is a rebinding attempt.
+    for addr in &addrs {
+        if let Err(denial) = policy.check_ip(&addr.ip()) {
+            let msg = denial
+                .code
+                .with_message(&format!("{host} resolves to denied address {}", addr.ip()));
+            return Err(PinError::Denied(denial, msg));
+        }
+    }
+    let addr = addrs
+        .first()
+        .copied()
+        .ok_or_else(|| Code::NetFailed.with_message(&format!("{host} did not resolve")))?;
+    Ok(PinnedTarget {
+        host: host.to_string(),
+        addr,
+    })
+}
@@ -0,0 +1,422 @@
+//! `broker status`: what the workspace audit log holds, by severity and
+//! category, and how the broker is doing.
+//!
+//! Severity is the record's code's (see [`saf_core::Severity`]), raised to
+//! `warn` for anything denied or failed; policies can keep less severe
+//! records out of the log per category with `audit_min_severity`.
+//!
+//! The broker's health comes from `broker serve` when one is serving (its
+//! `status` command), and is otherwise read from disk: for each workspace
+//! the broker remembers, whether a run is going in it, the hash of its
+//! policy file, the head of its audit chain and the network its policy
+//! allows; then the components the broker manages and the memory and
+//! threads it is using.
+//!
+//! ```text
+//! Broker: serving 0.1.0, up 2h03m, 14 threads, 38.2 MiB resident
+//! workspace_…  /home/me/notes  running
+//!   policy   sha256:9f86d081…
+//!   audit    head 2c26b46b…
+//!   network  system resolver, 2 allowed domains
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use saf_audit::AuditReader;
+use saf_core::{Category, Severity};
+use saf_policy::Policy;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::lifecycle::{self, Process};
+use crate::workspace_picker::WorkspaceStore;
+use crate::{run_manifest, runs, serve};
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+struct Status {
+    log: String,
+    entries: u64,
+    severity: BTreeMap<&'static str, u64>,
+    /// Severity counts per category.
+    category: BTreeMap<&'static str, BTreeMap<&'static str, u64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    broker: Option<Health>,
+}
+
+/// How the broker is doing, as `broker status` and the `status` command of
+/// `broker serve` report it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Health {
+    /// Whether this came from a serving broker; otherwise nothing is
+    /// running and only what is on disk is reported.
+    pub serving: bool,
+    pub version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uptime_secs: Option<u64>,
+    pub workspaces: Vec<WorkspaceHealth>,
+    /// Components the broker manages (see [`crate::lifecycle`]).
+    #[serde(default)]
+    pub components: Vec<Process>,
+    pub resources: Resources,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkspaceHealth {
+    pub id: String,
+    pub path: PathBuf,
+    /// Whether a run holds the workspace's turn.
+    pub active: bool,
+    /// Hash of `.saf/policy.toml`; without one, runs get the built-in
+    /// default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy_sha256: Option<String>,
+    /// Hash of the last entry of `.saf/audit.log`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit_head: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<Network>,
+    /// Why the policy could not be read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The network a workspace's policy gives its runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Network {
+    pub offline: bool,
+    /// `system`, or the DNS-over-HTTPS endpoint.
+    pub resolver: String,
+    pub allowed_domains: usize,
+}
+
+/// What the serving broker's process is using; unknown off Linux.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resources {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resident_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threads: Option<u64>,
+}
+
+impl Health {
+    /// The broker's health as this process sees it; `serving` if it is the
+    /// one serving.
+    pub fn collect(serving: bool) -> Result<Self, String> {
+        let workspaces = WorkspaceStore::new()?
+            .list_workspaces()?
+            .into_iter()
+            .map(|saved| {
+                let mut health = WorkspaceHealth::read(&saved.path);
+                health.id = saved.id;
+                health.active = serving && serve::busy(&health.id);
+                health
+            })
+            .collect();
+        Ok(Self {
+            serving,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime_secs: serving.then(serve::uptime).flatten(),
+            workspaces,
+            components: if serving {
+                lifecycle::MANAGED.ps()?
+            } else {
+                Vec::new()
+            },
+            resources: if serving {
+                Resources::read()
+            } else {
+                Resources::default()
+            },
+        })
+    }
+}
+
+impl WorkspaceHealth {
+    /// What is on disk for the workspace at `path`.
+    fn read(path: &Path) -> Self {
+        let saf = path.join(".saf");
+        let mut health = Self {
+            path: path.to_path_buf(),
+            audit_head: read_head(&saf.join("audit.log")),
+            ..Self::default()
+        };
+        let policy_path = saf.join("policy.toml");
+        let content = match std::fs::read_to_string(&policy_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return health,
+            Err(e) => {
+                health.error = Some(format!("{}: {e}", policy_path.display()));
+                return health;
+            }
+        };
+        health.policy_sha256 = Some(run_manifest::sha256_hex(content.as_bytes()));
+        match Policy::from_file_content(&policy_path, &content) {
+            Ok(policy) => {
+                health.network = Some(Network {
+                    offline: policy.offline,
+                    resolver: policy
+                        .dns_over_https
+                        .clone()
+                        .unwrap_or_else(|| "system".to_string()),
+                    allowed_domains: policy.allowed_domains.len(),
+                })
+            }
+            Err(e) => health.error = Some(e),
+        }
+        health
+    }
+}
+
+/// The head hash recorded beside the log at `path`, if it has entries.
+fn read_head(path: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(saf_audit::head_path(path)).ok()?;
+    content.split_whitespace().next().map(str::to_string)
+}
+
+impl Resources {
+    #[cfg(target_os = "linux")]
+    fn read() -> Self {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+        let field = |name: &str| {
+            status.lines().find_map(|line| {
+                let value = line.strip_prefix(name)?.strip_prefix(':')?;
+                value.split_whitespace().next()?.parse::<u64>().ok()
+            })
+        };
+        Self {
+            resident_bytes: field("VmRSS").map(|kib| kib * 1024),
+            threads: field("Threads"),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn read() -> Self {
+        Self::default()
+    }
+}
+
+impl Status {
+    fn count(path: &Path) -> Result<Self, String> {
+        let mut status = Self {
+            log: path.display().to_string(),
+            ..Self::default()
+        };
+        for category in Category::ALL {
+            let counts = Severity::ALL.iter().map(|s| (s.as_str(), 0)).collect();
+            status.category.insert(category.as_str(), counts);
+        }
+        status.severity = Severity::ALL.iter().map(|s| (s.as_str(), 0)).collect();
+        if !path.exists() {
+            return Ok(status);
+        }
+        for record in AuditReader::open(path)?.records() {
+            let record = record?;
+            let severity = record.severity().as_str();
+            status.entries += 1;
+            *status.severity.entry(severity).or_default() += 1;
+            *status
+                .category
+                .entry(record.category().as_str())
+                .or_default()
+                .entry(severity)
+                .or_default() += 1;
+        }
+        Ok(status)
+    }
+}
+
+/// Entry point for `broker status [<LOG>] [--json]`. Without a path, uses
+/// `.saf/audit.log` in the current directory.
+#[derive(Debug, Args)]
+pub struct StatusArgs {
+    /// The audit log (default: .saf/audit.log).
+    log: Option<PathBuf>,
+    #[arg(long)]
+    json: bool,
+    #[command(flatten)]
+    daemon: lifecycle::Daemon,
+}
+
+pub fn main(args: StatusArgs) -> Result<(), String> {
+    let StatusArgs {
+        log: path,
+        json,
+        daemon,
+    } = args;
+    let path = match path {
+        Some(p) => p,
+        None => std::env::current_dir()
+            .map_err(|e| e.to_string())?
+            .join(".saf")
+            .join("audit.log"),
+    };
+    let mut status = Status::count(&path)?;
+    let health = match serve::request(daemon.socket, json!({"command": "status"})) {
+        Ok(result) => serde_json::from_value(result).map_err(|e| e.to_string())?,
+        Err(_) => Health::collect(false)?,
+    };
+    status.broker = Some(health);
+    if json {
+        let out = serde_json::to_string_pretty(&status).map_err(|e| e.to_string())?;
+        println!("{out}");
+        return Ok(());
+    }
+    println!("{}: {} entries", status.log, status.entries);
+    print!("{:<12}", "CATEGORY");
+    for severity in Severity::ALL {
+        print!(" {:>9}", severity.as_str().to_uppercase());
+    }
+    println!();
+    for category in Category::ALL {
+        print!("{:<12}", category.as_str());
+        for severity in Severity::ALL {
+            let n = status.category[category.as_str()][severity.as_str()];
+            print!(" {n:>9}");
+        }
+        println!();
+    }
+    print!("{:<12}", "total");
+    for severity in Severity::ALL {
+        print!(" {:>9}", status.severity[severity.as_str()]);
+    }
+    println!();
+    if let Some(health) = &status.broker {
+        println!();
+        print_health(health);
+    }
+    Ok(())
+}
+
+fn print_health(health: &Health) {
+    let mut broker = if health.serving {
+        format!("serving {}", health.version)
+    } else {
+        "not serving".to_string()
+    };
+    if let Some(secs) = health.uptime_secs {
+        broker.push_str(&format!(", up {}", lifecycle::format_uptime(secs)));
+    }
+    if let Some(threads) = health.resources.threads {
+        broker.push_str(&format!(", {threads} threads"));
+    }
+    if let Some(bytes) = health.resources.resident_bytes {
+        let mib = bytes as f64 / (1024.0 * 1024.0);
+        broker.push_str(&format!(", {mib:.1} MiB resident"));
+    }
+    println!("Broker: {broker}");
+    for workspace in &health.workspaces {
+        let state = if workspace.active { "running" } else { "idle" };
+        println!("{}  {}  {state}", workspace.id, workspace.path.display());
+        let policy = workspace
+            .policy_sha256
+            .as_ref()
+            .map_or("built-in default".to_string(), |h| format!("sha256:{h}"));
+        println!("  policy   {policy}");
+        let head = workspace
+            .audit_head
+            .as_ref()
+            .map_or("empty".to_string(), |h| format!("head {h}"));
+        println!("  audit    {head}");
+        if let Some(network) = &workspace.network {
+            let resolver = match network.resolver.as_str() {
+                "system" => "system resolver".to_string(),
+                doh => format!("DNS over HTTPS via {doh}"),
+            };
+            let reach = if network.offline {
+                "offline".to_string()
+            } else {
+                format!("{} allowed domains", network.allowed_domains)
+            };
+            println!("  network  {resolver}, {reach}");
+        }
+        if let Some(error) = &workspace.error {
+            println!("  error    {error}");
+        }
+    }
+    if !health.components.is_empty() {
+        lifecycle::print_ps(&health.components, runs::now_unix_seconds());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use saf_audit::{AuditEvent, AuditLog, AuditRecord, Outcome};
+    use saf_core::Code;
+
+    #[test]
+    fn records_are_counted_by_severity_and_category() {
+        let dir = std::env::temp_dir().join(format!("saf-status-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("audit.log");
+        let read = |outcome| {
+            AuditRecord::new(
+                Code::FsReadText,
+                AuditEvent::FsRead {
+                    path: "docs/a.md".to_string(),
+                    bytes: 1,
+                    offset: None,
+                },
+            )
+            .with_outcome(outcome)
+        };
+        let mut log = AuditLog::new(&path).expect("open");
+        log.record(&read(Outcome::Ok)).expect("record");
+        log.record(&read(Outcome::Failed)).expect("record");
+        log.record(&AuditRecord::new(
+            Code::BrokerStart,
+            AuditEvent::BrokerLifecycle {
+                version: "0".to_string(),
+            },
+        ))
+        .expect("record");
+        drop(log);
+
+        let status = Status::count(&path).expect("count");
+        assert_eq!(status.entries, 3);
+        assert_eq!(status.category["fs"]["info"], 1);
+        assert_eq!(status.category["fs"]["warn"], 1);
+        assert_eq!(status.category["lifecycle"]["info"], 1);
+        assert_eq!(status.severity["security"], 0);
+        assert_eq!(
+            Status::count(&dir.join("none.log")).map(|s| s.entries),
+            Ok(0)
+        );
+
+        let workspace = dir.join("ws");
+        std::fs::create_dir_all(workspace.join(".saf")).expect("mkdir");
+        assert_eq!(
+            WorkspaceHealth::read(&workspace),
+            WorkspaceHealth {
+                path: workspace.clone(),
+                ..WorkspaceHealth::default()
+            }
+        );
+        let mut log = AuditLog::new(&workspace.join(".saf/audit.log")).expect("open");
+        log.append("broker.start").expect("append");
+        let head = log.head();
+        drop(log);
+        let policy =
+            "allowed_domains = [\"example.org\"]\ndns_over_https = \"https://dns.example/q\"\n";
+        std::fs::write(workspace.join(".saf/policy.toml"), policy).expect("write");
+        let health = WorkspaceHealth::read(&workspace);
+        assert_eq!(health.audit_head, Some(head));
+        assert_eq!(
+            health.policy_sha256,
+            Some(run_manifest::sha256_hex(policy.as_bytes()))
+        );
+        assert_eq!(
+            health.network,
+            Some(Network {
+                offline: false,
+                resolver: "https://dns.example/q".to_string(),
+                allowed_domains: 1,
+            })
+        );
+        std::fs::write(workspace.join(".saf/policy.toml"), "allowed_domains = 3").expect("write");
+        let health = WorkspaceHealth::read(&workspace);
+        assert!(health.network.is_none() && health.error.is_some());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
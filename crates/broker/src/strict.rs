@@ -0,0 +1,154 @@
+//! Strict mode: a component's run ends at its first denial.
+//!
+//! Under `strict = true` in the policy, or `broker run --strict`, a denied
+//! host call does not hand the guest an error it could ignore. The
+//! [`Tripwire`] notes the first record of the run that has the `denied`
+//! outcome and stops the run, so the guest is trapped as the call returns.
+//! The run fails, and a `component.strict_violation` record names the
+//! denial that ended it: its code, the capability and target it was for,
+//! and the request it was made serving.
+//!
+//! A dry run's denials are `would_deny` and do not trip it. An isolated
+//! run is stopped once the broker has written the denial to the log, which
+//! the guest may outlive by a host call or two. Components run through
+//! `--stdio-rpc` requests are not covered yet: their denials are returned
+//! to the guest as usual.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use saf_core::{AuditEvent, AuditRecord, Code, Outcome};
+
+/// Stops a run at its first denial.
+#[derive(Debug)]
+pub struct Tripwire {
+    run_id: String,
+    stop: Arc<AtomicBool>,
+    tripped: OnceLock<AuditRecord>,
+}
+
+impl Tripwire {
+    /// Arm the tripwire for the run `run_id`, which `stop` ends.
+    pub fn new(run_id: &str, stop: Arc<AtomicBool>) -> Self {
+        Self {
+            run_id: run_id.to_string(),
+            stop,
+            tripped: OnceLock::new(),
+        }
+    }
+
+    /// Stop the run if `record` is its first denial.
+    pub fn observe(&self, record: &AuditRecord) {
+        if record.outcome != Outcome::Denied
+            || record.component_run.as_deref() != Some(self.run_id.as_str())
+        {
+            return;
+        }
+        if self.tripped.set(record.clone()).is_ok() {
+            self.stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// The `component.strict_violation` record for the denial that ended
+    /// the run, if one did.
+    pub fn violation(&self) -> Option<AuditRecord> {
+        let denial = self.tripped.get()?;
+        let (capability, target) = match &denial.event {
+            AuditEvent::PolicyDenied {
+                capability, target, ..
+            } => (Some(capability.clone()), Some(target.clone())),
+            _ => (None, None),
+        };
+        Some(
+            AuditRecord::new(
+                Code::ComponentStrictViolation,
+                AuditEvent::StrictViolation {
+                    run_id: self.run_id.clone(),
+                    denial: denial.code.clone(),
+                    capability,
+                    target,
+                    request: denial.request.clone(),
+                },
+            )
+            .with_outcome(Outcome::Failed),
+        )
+    }
+}
+
+/// Why a run the tripwire in `violation` ended failed.
+pub fn error(violation: &AuditRecord) -> String {
+    let AuditEvent::StrictViolation {
+        denial, request, ..
+    } = &violation.event
+    else {
+        return Code::ComponentStrictViolation.with_message("run ended at a denial");
+    };
+    let request = request
+        .as_deref()
+        .map_or_else(String::new, |r| format!(" (request {r})"));
+    Code::ComponentStrictViolation
+        .with_message(&format!("run ended at its first denial, {denial}{request}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use saf_core::ComponentIdentity;
+
+    fn denied(component: &ComponentIdentity, target: &str) -> AuditRecord {
+        AuditRecord::new(
+            Code::PolicyPathNotAllowed,
+            AuditEvent::PolicyDenied {
+                capability: "fs".to_string(),
+                target: target.to_string(),
+                rule: "deny[0]".to_string(),
+            },
+        )
+        .with_outcome(Outcome::Denied)
+        .with_identity(component)
+    }
+
+    #[test]
+    fn the_first_denial_of_the_run_stops_it() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let tripwire = Tripwire::new("run_1", stop.clone());
+        let run = ComponentIdentity::new("notes").with_run_id("run_1");
+        let other = ComponentIdentity::new("notes").with_run_id("run_2");
+
+        tripwire.observe(
+            &AuditRecord::new(
+                Code::ComponentStart,
+                AuditEvent::ComponentStart {
+                    run_id: "run_1".to_string(),
+                    sha256: String::new(),
+                },
+            )
+            .with_identity(&run),
+        );
+        tripwire.observe(&denied(&other, "secrets/b.pem"));
+        tripwire.observe(&denied(&run, "secrets/a.pem").with_outcome(Outcome::WouldDeny));
+        assert!(!stop.load(Ordering::Relaxed));
+        assert!(tripwire.violation().is_none());
+
+        tripwire.observe(&denied(&run, "secrets/a.pem").with_request("run_1.4"));
+        tripwire.observe(&denied(&run, "secrets/c.pem"));
+        assert!(stop.load(Ordering::Relaxed));
+        let violation = tripwire.violation().expect("tripped");
+        assert_eq!(
+            violation.event,
+            AuditEvent::StrictViolation {
+                run_id: "run_1".to_string(),
+                denial: Code::PolicyPathNotAllowed.to_string(),
+                capability: Some("fs".to_string()),
+                target: Some("secrets/a.pem".to_string()),
+                request: Some("run_1.4".to_string()),
+            }
+        );
+        let error = error(&violation);
+        assert!(error.starts_with("component.strict_violation: "), "{error}");
+        assert!(
+            error.ends_with("policy.path_not_allowed (request run_1.4)"),
+            "{error}"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::path::Path;
+
+use saf_policy::Policy;
+use serde::{Deserialize, Serialize};
+
+/// The environment facts a component may see, collected once per run.
+///
+/// Locale, timezone, OS family and framework version are always available;
+/// identifying details are only filled in when the policy grants them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SysInfo {
+    pub locale: String,
+    pub timezone: String,
+    pub os_family: String,
+    pub framework_version: String,
+    pub hostname: Option<String>,
+    pub username: Option<String>,
+}
+
+impl SysInfo {
+    pub fn collect(policy: &Policy) -> Self {
+        Self {
+            locale: locale(),
+            timezone: timezone(),
+            os_family: std::env::consts::FAMILY.to_string(),
+            framework_version: env!("CARGO_PKG_VERSION").to_string(),
+            hostname: policy.sysinfo.hostname.then(hostname).flatten(),
+            username: policy.sysinfo.username.then(username).flatten(),
+        }
+    }
+}
+
+fn env_nonempty(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+/// POSIX locale (`en_GB.UTF-8@euro`) reduced to a BCP 47 tag (`en-GB`).
+fn locale() -> String {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .find_map(|v| env_nonempty(v))
+        .map(|raw| {
+            let base = raw.split(['.', '@']).next().unwrap_or_default();
+            base.replace('_', "-")
+        })
+        .filter(|tag| !tag.is_empty() && tag != "C" && tag != "POSIX")
+        .unwrap_or_else(|| "und".to_string())
+}
+
+/// IANA zone name from `TZ`, `/etc/timezone` or the `/etc/localtime` link.
+fn timezone() -> String {
+    if let Some(tz) = env_nonempty("TZ") {
+        return tz.trim_start_matches(':').to_string();
+    }
+    if let Ok(tz) = std::fs::read_to_string("/etc/timezone") {
+        let tz = tz.trim();
+        if !tz.is_empty() {
+            return tz.to_string();
+        }
+    }
+    std::fs::read_link("/etc/localtime")
+        .ok()
+        .and_then(|target| zone_from_path(&target))
+        .unwrap_or_else(|| "UTC".to_string())
+}
+
+fn zone_from_path(path: &Path) -> Option<String> {
+    let s = path.to_string_lossy();
+    s.split_once("zoneinfo/").map(|(_, zone)| zone.to_string())
+}
+
+pub fn hostname() -> Option<String> {
+    std::fs::read_to_string("/etc/hostname")
+        .ok()
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .or_else(|| env_nonempty("HOSTNAME"))
+        .or_else(|| env_nonempty("COMPUTERNAME"))
+}
+
+fn username() -> Option<String> {
+    env_nonempty("USER").or_else(|| env_nonempty("USERNAME"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifying_fields_need_grants() {
+        let info = SysInfo::collect(&Policy::new());
+        assert_eq!(info.hostname, None);
+        assert_eq!(info.username, None);
+        assert!(!info.locale.is_empty());
+        assert_eq!(
+            zone_from_path(Path::new("/usr/share/zoneinfo/Europe/London")).as_deref(),
+            Some("Europe/London")
+        );
+    }
+}
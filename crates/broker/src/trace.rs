@@ -0,0 +1,417 @@
+//! Recording a run's host calls, and replaying them.
+//!
+//! With `--record-calls <PATH>`, every filesystem, network and WebSocket
+//! call the component makes is written to a trace file with its result,
+//! along with each reading it takes of the clock and the audit records its
+//! calls write. With `--replay-calls <PATH>`, the component is run against
+//! the trace instead: each call is answered with the recorded result, and
+//! neither the workspace nor the network is touched, so a run that went
+//! wrong on one machine can be repeated exactly on another, or in CI. The
+//! policy is still checked before each call. The run's random seed is
+//! recorded too (one is picked if the run has none) and used again.
+//!
+//! A trace is one JSON object per line:
+//!
+//! ```text
+//! {"type":"start","version":1,"sha256":"…","rng_seed":720134}
+//! {"type":"call","call":{"op":"read_text","path":"docs/a.md"},"result":{"Ok":"# Notes\n"}}
+//! {"type":"time","unix_seconds":1760000000}
+//! {"type":"record","record":{"code":"fs.read_text",…}}
+//! ```
+//!
+//! A replayed component has to make the calls it made before, in the same
+//! order. The first call that differs from the trace fails, as does every
+//! call after it, and so does the run, saying where it diverged; a run
+//! that ends with calls left in the trace fails too. Isolated runs have
+//! their calls recorded and replayed the same way, but read the clock
+//! themselves.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use saf_core::{AuditRecord, FsHost, LogHost, NetHost, WsHost};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::isolate::{self, Answer, Call, Failure};
+
+/// Version of the trace format.
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Entry {
+    Start {
+        version: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sha256: Option<String>,
+        rng_seed: u64,
+    },
+    Call {
+        call: Call,
+        result: Result<Value, Failure>,
+    },
+    Time {
+        unix_seconds: u64,
+    },
+    Record {
+        record: Box<AuditRecord>,
+    },
+}
+
+/// A run's trace, being written or read back.
+#[derive(Debug)]
+pub enum Trace {
+    Record(Recorder),
+    Replay(Replayer),
+}
+
+#[derive(Debug)]
+pub struct Recorder {
+    rng_seed: u64,
+    out: Mutex<BufWriter<File>>,
+    /// The first write that failed.
+    error: Mutex<Option<String>>,
+}
+
+#[derive(Debug)]
+pub struct Replayer {
+    rng_seed: u64,
+    /// The component the trace was recorded from.
+    pub sha256: Option<String>,
+    calls: Mutex<VecDeque<(Call, Result<Value, Failure>)>>,
+    #[cfg_attr(not(feature = "wasmtime-host"), allow(dead_code))]
+    times: Mutex<VecDeque<u64>>,
+    made: Mutex<usize>,
+    /// Where the run stopped following the trace.
+    diverged: Mutex<Option<String>>,
+}
+
+impl Trace {
+    /// Record to `path` a run of the component hashing to `sha256`, seeded
+    /// with `rng_seed`.
+    pub fn record(path: &Path, sha256: Option<&str>, rng_seed: u64) -> Result<Self, String> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+        }
+        let file = File::create(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let recorder = Recorder {
+            rng_seed,
+            out: Mutex::new(BufWriter::new(file)),
+            error: Mutex::new(None),
+        };
+        recorder.write(&Entry::Start {
+            version: VERSION,
+            sha256: sha256.map(str::to_string),
+            rng_seed,
+        });
+        Ok(Self::Record(recorder))
+    }
+
+    /// Read back the trace at `path`.
+    pub fn replay(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::read(BufReader::new(file)).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    fn read(input: impl BufRead) -> Result<Self, String> {
+        let mut lines = input.lines().enumerate();
+        let (rng_seed, sha256) = match lines.next() {
+            Some((_, line)) => match serde_json::from_str(&line.map_err(|e| e.to_string())?) {
+                Ok(Entry::Start {
+                    version: VERSION,
+                    sha256,
+                    rng_seed,
+                }) => (rng_seed, sha256),
+                Ok(Entry::Start { version, .. }) => {
+                    return Err(format!("trace version {version} is not supported"))
+                }
+                _ => return Err("not a trace of host calls".to_string()),
+            },
+            None => return Err("empty trace".to_string()),
+        };
+        let (mut calls, mut times) = (VecDeque::new(), VecDeque::new());
+        for (i, line) in lines {
+            let line = line.map_err(|e| e.to_string())?;
+            match serde_json::from_str(&line).map_err(|e| format!("line {}: {e}", i + 1))? {
+                Entry::Call { call, result } => calls.push_back((call, result)),
+                Entry::Time { unix_seconds } => times.push_back(unix_seconds),
+                Entry::Record { .. } => {}
+                Entry::Start { .. } => return Err(format!("line {}: a second start", i + 1)),
+            }
+        }
+        Ok(Self::Replay(Replayer {
+            rng_seed,
+            sha256,
+            calls: Mutex::new(calls),
+            times: Mutex::new(times),
+            made: Mutex::new(0),
+            diverged: Mutex::new(None),
+        }))
+    }
+
+    /// The seed the run's randomness is drawn from.
+    pub fn rng_seed(&self) -> u64 {
+        match self {
+            Trace::Record(r) => r.rng_seed,
+            Trace::Replay(r) => r.rng_seed,
+        }
+    }
+
+    /// The time to give the component, `live` being the clock's reading.
+    /// A replay that has run out of readings goes by the clock.
+    #[cfg_attr(not(feature = "wasmtime-host"), allow(dead_code))]
+    pub fn now(&self, live: u64) -> u64 {
+        match self {
+            Trace::Record(r) => {
+                r.write(&Entry::Time { unix_seconds: live });
+                live
+            }
+            Trace::Replay(r) => lock(&r.times).pop_front().unwrap_or(live),
+        }
+    }
+
+    /// Flush a recording, or check a replay followed its trace to the end.
+    pub fn finish(&self) -> Result<(), String> {
+        match self {
+            Trace::Record(r) => {
+                let flushed = lock(&r.out).flush().map_err(|e| e.to_string());
+                match lock(&r.error).take() {
+                    Some(e) => Err(format!("the trace is incomplete: {e}")),
+                    None => flushed,
+                }
+            }
+            Trace::Replay(r) => {
+                if let Some(diverged) = lock(&r.diverged).clone() {
+                    return Err(diverged);
+                }
+                let left = lock(&r.calls).len();
+                if left > 0 {
+                    return Err(format!(
+                        "replay diverged: the component made {} calls, and the trace has {left} more",
+                        lock(&r.made)
+                    ));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Recorder {
+    fn write(&self, entry: &Entry) {
+        let written = serde_json::to_string(entry)
+            .map_err(|e| e.to_string())
+            .and_then(|line| writeln!(lock(&self.out), "{line}").map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            lock(&self.error).get_or_insert(e);
+        }
+    }
+}
+
+impl Replayer {
+    /// The recorded result of `call`, if it is the call the trace has next.
+    fn next(&self, call: Call) -> Result<Value, Failure> {
+        let mut diverged = lock(&self.diverged);
+        if let Some(diverged) = &*diverged {
+            return Err(Failure::Failed(diverged.clone()));
+        }
+        let mut made = lock(&self.made);
+        *made += 1;
+        let describe = |call: &Call| serde_json::to_string(call).unwrap_or_default();
+        let expected = match lock(&self.calls).pop_front() {
+            Some((recorded, result)) if recorded == call => return result,
+            Some((recorded, _)) => describe(&recorded),
+            None => "no more calls".to_string(),
+        };
+        let message = format!(
+            "replay diverged at call {made}: the trace has {expected}, the component made {}",
+            describe(&call)
+        );
+        *diverged = Some(message.clone());
+        Err(Failure::Failed(message))
+    }
+}
+
+/// The run's hosts, with each call recorded to or answered from `trace`.
+pub struct Traced<'a> {
+    pub fs: &'a dyn FsHost,
+    pub net: &'a dyn NetHost,
+    pub ws: &'a dyn WsHost,
+    pub trace: &'a Trace,
+}
+
+impl Answer for Traced<'_> {
+    fn answer(&self, call: Call) -> Result<Value, Failure> {
+        match self.trace {
+            Trace::Record(recorder) => {
+                let result = isolate::serve(self.fs, self.net, self.ws, call.clone());
+                recorder.write(&Entry::Call {
+                    call,
+                    result: result.clone(),
+                });
+                result
+            }
+            Trace::Replay(replayer) => replayer.next(call),
+        }
+    }
+}
+
+/// The run's audit log, with what is recorded to it also written to a
+/// trace being recorded.
+pub struct TracedLog<'a> {
+    pub inner: &'a dyn LogHost,
+    pub trace: &'a Trace,
+}
+
+impl LogHost for TracedLog<'_> {
+    fn record(&self, record: AuditRecord) {
+        if let Trace::Record(recorder) = self.trace {
+            recorder.write(&Entry::Record {
+                record: Box::new(record.clone()),
+            });
+        }
+        self.inner.record(record);
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isolate::Forwarding;
+    use saf_core::{AuditEvent, Code};
+
+    /// A workspace of one file that remembers what was written to it.
+    #[derive(Default)]
+    struct Files(Mutex<Vec<String>>);
+
+    impl FsHost for Files {
+        fn list_dir(&self, _path: &str) -> Result<Vec<String>, String> {
+            Ok(vec!["a.txt".to_string()])
+        }
+        fn read_text(&self, path: &str) -> Result<String, String> {
+            match path {
+                "a.txt" => Ok("first".to_string()),
+                _ => Err(format!("{path}: not found")),
+            }
+        }
+        fn write_text(&self, path: &str, _content: &str) -> Result<(), String> {
+            lock(&self.0).push(path.to_string());
+            Ok(())
+        }
+        fn append_bytes(&self, path: &str, _content: &[u8]) -> Result<(), String> {
+            lock(&self.0).push(path.to_string());
+            Ok(())
+        }
+    }
+
+    struct Offline;
+
+    impl NetHost for Offline {
+        fn fetch(&self, _url: &str) -> Result<saf_core::HttpResponse, saf_core::NetError> {
+            Err(saf_core::NetError::Offline)
+        }
+    }
+
+    impl WsHost for Offline {
+        fn connect(&self, _url: &str) -> Result<u64, String> {
+            Err("offline".to_string())
+        }
+        fn send(&self, _conn: u64, _message: &str) -> Result<(), String> {
+            Err("offline".to_string())
+        }
+        fn receive(&self, _conn: u64) -> Result<Option<String>, String> {
+            Err("offline".to_string())
+        }
+        fn close(&self, _conn: u64) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    struct Discard;
+
+    impl LogHost for Discard {
+        fn record(&self, _record: AuditRecord) {}
+    }
+
+    #[test]
+    fn a_recorded_run_replays_without_touching_its_hosts() {
+        let path = std::env::temp_dir().join(format!("saf-trace-{}.jsonl", uuid::Uuid::new_v4()));
+        let files = Files::default();
+        let trace = Trace::record(&path, Some("abc"), 7).expect("record");
+        let hosts = Forwarding(Traced {
+            fs: &files,
+            net: &Offline,
+            ws: &Offline,
+            trace: &trace,
+        });
+        assert_eq!(hosts.read_text("a.txt"), Ok("first".to_string()));
+        assert!(hosts.read_text("b.txt").is_err());
+        hosts.write_text("out.txt", "x").expect("write");
+        assert!(matches!(
+            hosts.fetch("https://example.org/"),
+            Err(saf_core::NetError::Offline)
+        ));
+        assert_eq!(trace.now(1_760_000_000), 1_760_000_000);
+        TracedLog {
+            inner: &Discard,
+            trace: &trace,
+        }
+        .record(AuditRecord::new(
+            Code::ComponentLog,
+            AuditEvent::ComponentLog {
+                message: "hi".to_string(),
+            },
+        ));
+        trace.finish().expect("flush");
+
+        // Replayed against hosts that would answer differently.
+        let nothing = Files(Mutex::new(Vec::new()));
+        let replay = Trace::replay(&path).expect("replay");
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(replay.rng_seed(), 7);
+        let hosts = Forwarding(Traced {
+            fs: &nothing,
+            net: &Offline,
+            ws: &Offline,
+            trace: &replay,
+        });
+        assert_eq!(hosts.read_text("a.txt"), Ok("first".to_string()));
+        assert_eq!(
+            hosts.read_text("b.txt"),
+            Err("b.txt: not found".to_string())
+        );
+        hosts.write_text("out.txt", "x").expect("write");
+        assert!(lock(&nothing.0).is_empty());
+        assert!(replay.finish().is_err(), "a call is left");
+        assert!(matches!(
+            hosts.fetch("https://example.org/"),
+            Err(saf_core::NetError::Offline)
+        ));
+        assert_eq!(replay.now(5), 1_760_000_000);
+        assert_eq!(replay.now(5), 5);
+        replay.finish().expect("followed to the end");
+
+        // A different call ends the replay.
+        let Trace::Replay(r) = &replay else {
+            unreachable!()
+        };
+        lock(&r.calls).push_back((
+            Call::ListDir {
+                path: String::new(),
+            },
+            Ok(Value::Null),
+        ));
+        let diverged = hosts.read_text("a.txt").unwrap_err();
+        assert!(diverged.contains("replay diverged at call 5"), "{diverged}");
+        assert!(hosts.list_dir("").is_err());
+        assert_eq!(replay.finish(), Err(diverged));
+    }
+}
@@ -0,0 +1,168 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use saf_core::{AuditEvent, AuditRecord, Code, FsHost, HttpResponse, NetError, NetHost, WsHost};
+use saf_policy::trial::{Narrowing, TrialState};
+
+use crate::elevation::ElevationStore;
+use crate::sanitize_rel_path;
+
+pub fn trial_path(workspace: &Path) -> PathBuf {
+    workspace.join(".saf").join("trial.json")
+}
+
+/// Accept the narrowing the trial in `workspace` proposes, for a client
+/// other than the CLI (`source`), and audit it to the workspace's log.
+/// Like `--accept-narrowing`, it needs an elevated session in `elevation`.
+pub fn accept(
+    workspace: &Path,
+    source: &str,
+    elevation: &ElevationStore,
+) -> Result<Narrowing, String> {
+    crate::elevation::require_in(elevation, workspace, "accepting a narrowed grant")?;
+    let path = trial_path(workspace);
+    let mut trial = TrialState::load(&path)?.ok_or("no trial in progress")?;
+    let narrowing = trial
+        .accept()
+        .cloned()
+        .ok_or("no narrowing proposal to accept")?;
+    trial.save(&path)?;
+    let mut log = crate::audit::open(&workspace.join(".saf").join("audit.log"))?;
+    log.record(&AuditRecord::new(
+        Code::PolicyNarrowed,
+        AuditEvent::PolicyNarrowed {
+            domains: narrowing.allowed_domains.clone(),
+            paths: narrowing.allowed_paths.clone(),
+            source: source.to_string(),
+        },
+    ))?;
+    Ok(narrowing)
+}
+
+/// Shared usage recorder for the trial-mode host wrappers.
+pub struct Tracker {
+    state: Mutex<Option<TrialState>>,
+}
+
+impl Tracker {
+    pub fn new(state: Option<TrialState>) -> Self {
+        Self {
+            state: Mutex::new(state),
+        }
+    }
+
+    pub fn into_inner(self) -> Option<TrialState> {
+        self.state.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record_path(&self, path: &str, is_dir: bool) {
+        let Some(rel) = sanitize_rel_path(path) else {
+            return;
+        };
+        // Files are attributed to their directory; root-level files to themselves.
+        let scope = if is_dir {
+            rel
+        } else {
+            match rel.rsplit_once('/') {
+                Some((dir, _)) => dir.to_string(),
+                None => rel,
+            }
+        };
+        if let Ok(mut g) = self.state.lock() {
+            if let Some(t) = g.as_mut() {
+                t.record_path(&scope);
+            }
+        }
+    }
+
+    fn record_url(&self, url: &str) {
+        let Some(host) = url::Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(str::to_string))
+        else {
+            return;
+        };
+        if let Ok(mut g) = self.state.lock() {
+            if let Some(t) = g.as_mut() {
+                t.record_domain(&host);
+            }
+        }
+    }
+}
+
+pub struct TrackingFs<'a> {
+    pub inner: &'a dyn FsHost,
+    pub tracker: &'a Tracker,
+}
+
+impl FsHost for TrackingFs<'_> {
+    fn list_dir(&self, path: &str) -> Result<Vec<String>, String> {
+        self.tracker.record_path(path, true);
+        self.inner.list_dir(path)
+    }
+    fn read_text(&self, path: &str) -> Result<String, String> {
+        self.tracker.record_path(path, false);
+        self.inner.read_text(path)
+    }
+    fn read_range(&self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>, String> {
+        self.tracker.record_path(path, false);
+        self.inner.read_range(path, offset, len)
+    }
+    fn write_text(&self, path: &str, content: &str) -> Result<(), String> {
+        self.tracker.record_path(path, false);
+        self.inner.write_text(path, content)
+    }
+    fn append_bytes(&self, path: &str, content: &[u8]) -> Result<(), String> {
+        self.tracker.record_path(path, false);
+        self.inner.append_bytes(path, content)
+    }
+}
+
+pub struct TrackingNet<'a> {
+    pub inner: &'a dyn NetHost,
+    pub tracker: &'a Tracker,
+}
+
+impl NetHost for TrackingNet<'_> {
+    fn fetch(&self, url: &str) -> Result<HttpResponse, NetError> {
+        self.tracker.record_url(&self.inner.effective_url(url));
+        self.inner.fetch(url)
+    }
+    fn effective_url(&self, url: &str) -> String {
+        self.inner.effective_url(url)
+    }
+    fn rewriting_active(&self) -> bool {
+        self.inner.rewriting_active()
+    }
+    fn open_stream(&self, url: &str) -> Result<u64, NetError> {
+        self.tracker.record_url(&self.inner.effective_url(url));
+        self.inner.open_stream(url)
+    }
+    fn next_chunk(&self, stream: u64) -> Result<Option<Vec<u8>>, NetError> {
+        self.inner.next_chunk(stream)
+    }
+    fn close_stream(&self, stream: u64) -> Result<u64, NetError> {
+        self.inner.close_stream(stream)
+    }
+}
+
+pub struct TrackingWs<'a> {
+    pub inner: &'a dyn WsHost,
+    pub tracker: &'a Tracker,
+}
+
+impl WsHost for TrackingWs<'_> {
+    fn connect(&self, url: &str) -> Result<u64, String> {
+        self.tracker.record_url(url);
+        self.inner.connect(url)
+    }
+    fn send(&self, conn: u64, message: &str) -> Result<(), String> {
+        self.inner.send(conn, message)
+    }
+    fn receive(&self, conn: u64) -> Result<Option<String>, String> {
+        self.inner.receive(conn)
+    }
+    fn close(&self, conn: u64) -> Result<(), String> {
+        self.inner.close(conn)
+    }
+}
@@ -0,0 +1,412 @@
+//! RFC 3161 timestamps for audit checkpoints.
+//!
+//! With `tsa_url` under `[audit.checkpoint]` in the
+//! [broker config](crate::config), each checkpoint's chain hash is sent to
+//! that time-stamping authority, and the signed response is kept as
+//! `<log>.tsa/<hash>.tsr`. A token shows the hash, and so every entry up to
+//! it, existed by the TSA's time, which neither the machine's clock nor
+//! whoever holds the audit key can move.
+//!
+//! The timestamped data is the hash's 32 bytes, imprinted with SHA-256.
+//! `broker audit verify` checks each token names a hash in the log; the
+//! TSA's signature is checked with its certificate, e.g.
+//! `openssl ts -verify -in <hash>.tsr -data <(xxd -r -p <<< <hash>) -CAfile tsa.pem`.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use saf_audit::{Anchor, Checkpoint, MerkleTree};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const TAG_BOOLEAN: u8 = 0x01;
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_NULL: u8 = 0x05;
+const TAG_OID: u8 = 0x06;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_EXPLICIT_0: u8 = 0xa0;
+
+/// 2.16.840.1.101.3.4.2.1
+const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+/// 1.2.840.113549.1.7.2
+const OID_SIGNED_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+/// 1.2.840.113549.1.9.16.1.4
+const OID_TST_INFO: &[u8] = &[
+    0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x10, 0x01, 0x04,
+];
+
+/// `<log>.tsa`, where tokens are kept.
+pub fn tokens_dir(log: &Path) -> PathBuf {
+    let mut name = log.file_name().unwrap_or_default().to_os_string();
+    name.push(".tsa");
+    log.with_file_name(name)
+}
+
+/// Timestamps each checkpoint with the TSA at `url`.
+pub struct TsaAnchor {
+    url: String,
+    dir: PathBuf,
+    agent: ureq::Agent,
+}
+
+impl TsaAnchor {
+    /// Keep tokens for checkpoints of the log at `log`.
+    pub fn new(url: String, log: &Path) -> Self {
+        Self {
+            url,
+            dir: tokens_dir(log),
+            agent: ureq::Agent::config_builder()
+                .timeout_global(Some(TIMEOUT))
+                .build()
+                .into(),
+        }
+    }
+}
+
+impl Anchor for TsaAnchor {
+    fn anchor(&mut self, checkpoint: &Checkpoint) -> Result<(), String> {
+        let imprint = imprint(&checkpoint.hash)?;
+        let mut nonce = [0; 8];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| "no randomness for a nonce".to_string())?;
+        let response = self
+            .agent
+            .post(&self.url)
+            .header("content-type", "application/timestamp-query")
+            .send(&request(&imprint, &nonce)[..])
+            .map_err(|e| format!("{}: {}", self.url, e))?
+            .body_mut()
+            .read_to_vec()
+            .map_err(|e| format!("{}: {}", self.url, e))?;
+        let token = parse_response(&response).map_err(|e| format!("{}: {}", self.url, e))?;
+        if token.imprint != imprint {
+            return Err(format!("{}: token is for other data", self.url));
+        }
+        if token.nonce.as_deref() != Some(unsigned(&nonce)) {
+            return Err(format!("{}: token does not echo the nonce", self.url));
+        }
+        std::fs::create_dir_all(&self.dir)
+            .and_then(|()| {
+                std::fs::write(self.dir.join(format!("{}.tsr", checkpoint.hash)), &response)
+            })
+            .map_err(|e| format!("{}: {}", self.dir.display(), e))
+    }
+}
+
+/// A timestamp that checked out against the log.
+pub struct Timestamped {
+    pub hash: String,
+    /// The TSA's time, as `YYYY-MM-DDTHH:MM:SSZ`.
+    pub time: String,
+}
+
+/// Check every token kept for the log at `path`, returning those for hashes
+/// still in the log, oldest first.
+pub fn verify_timestamps(path: &Path) -> Result<Vec<Timestamped>, String> {
+    let dir = tokens_dir(path);
+    let entries = std::fs::read_dir(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let tree = MerkleTree::from_log(path)?;
+    let mut found = Vec::new();
+    for entry in entries {
+        let file = entry
+            .map_err(|e| format!("{}: {}", dir.display(), e))?
+            .path();
+        let Some(hash) = file
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.strip_suffix(".tsr"))
+        else {
+            continue;
+        };
+        let at = file.display();
+        let response = std::fs::read(&file).map_err(|e| format!("{at}: {e}"))?;
+        let token = parse_response(&response).map_err(|e| format!("{at}: {e}"))?;
+        if token.imprint != imprint(hash)? {
+            return Err(format!("{at}: token is not for {hash}"));
+        }
+        // Tokens outlive the checkpoints they name once those are pruned.
+        if tree.find(hash).is_err() {
+            continue;
+        }
+        found.push(Timestamped {
+            hash: hash.to_string(),
+            time: token.time,
+        });
+    }
+    found.sort_by(|a, b| a.time.cmp(&b.time));
+    Ok(found)
+}
+
+/// SHA-256 of a hex chain hash's bytes.
+fn imprint(hash: &str) -> Result<Vec<u8>, String> {
+    let bytes = hex::decode(hash).map_err(|_| format!("{hash}: not a hash"))?;
+    Ok(digest(&SHA256, &bytes).as_ref().to_vec())
+}
+
+/// A DER encoding of `content` under `tag`.
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|&b| b == 0)
+            .collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend(bytes);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+/// `value` as a non-negative DER INTEGER's content.
+fn unsigned(value: &[u8]) -> &[u8] {
+    let start = value
+        .iter()
+        .position(|&b| b != 0)
+        .unwrap_or(value.len().saturating_sub(1));
+    &value[start..]
+}
+
+fn integer(value: &[u8]) -> Vec<u8> {
+    let value = unsigned(value);
+    let mut content = Vec::new();
+    if value.first().is_some_and(|&b| b & 0x80 != 0) {
+        content.push(0);
+    }
+    content.extend_from_slice(value);
+    der(TAG_INTEGER, &content)
+}
+
+/// A TimeStampReq for a SHA-256 `imprint`, asking for the TSA's
+/// certificate in the token.
+fn request(imprint: &[u8], nonce: &[u8]) -> Vec<u8> {
+    let algorithm = der(
+        TAG_SEQUENCE,
+        &[der(TAG_OID, OID_SHA256), der(TAG_NULL, &[])].concat(),
+    );
+    let message_imprint = der(
+        TAG_SEQUENCE,
+        &[algorithm, der(TAG_OCTET_STRING, imprint)].concat(),
+    );
+    der(
+        TAG_SEQUENCE,
+        &[
+            integer(&[1]),
+            message_imprint,
+            integer(nonce),
+            der(TAG_BOOLEAN, &[0xff]),
+        ]
+        .concat(),
+    )
+}
+
+/// The first DER value of `input`: its tag, content and what follows it.
+fn read(input: &[u8]) -> Result<(u8, &[u8], &[u8]), String> {
+    let malformed = || "malformed DER".to_string();
+    let (&tag, rest) = input.split_first().ok_or_else(malformed)?;
+    let (&first, rest) = rest.split_first().ok_or_else(malformed)?;
+    let (len, rest) = if first < 0x80 {
+        (usize::from(first), rest)
+    } else {
+        let n = usize::from(first & 0x7f);
+        if n == 0 || n > 4 || rest.len() < n {
+            return Err(malformed());
+        }
+        let len = rest[..n]
+            .iter()
+            .fold(0usize, |len, &b| (len << 8) | usize::from(b));
+        (len, &rest[n..])
+    };
+    if rest.len() < len {
+        return Err(malformed());
+    }
+    Ok((tag, &rest[..len], &rest[len..]))
+}
+
+/// The next value of `input`, which must be tagged `tag`.
+fn expect<'a>(input: &mut &'a [u8], tag: u8, what: &str) -> Result<&'a [u8], String> {
+    let (found, content, rest) = read(input)?;
+    if found != tag {
+        return Err(format!("expected {what}"));
+    }
+    *input = rest;
+    Ok(content)
+}
+
+/// What the broker reads from a token; the TSA's signature over it is left
+/// to tools holding the TSA's certificate.
+struct Token {
+    imprint: Vec<u8>,
+    time: String,
+    nonce: Option<Vec<u8>>,
+}
+
+/// The token in a granted TimeStampResp.
+fn parse_response(response: &[u8]) -> Result<Token, String> {
+    let mut resp = expect(&mut &response[..], TAG_SEQUENCE, "a TimeStampResp")?;
+    let mut status_info = expect(&mut resp, TAG_SEQUENCE, "a PKIStatusInfo")?;
+    match expect(&mut status_info, TAG_INTEGER, "a PKIStatus")? {
+        // granted, grantedWithMods
+        [0] | [1] => {}
+        status => return Err(format!("timestamp refused (status {status:?})")),
+    }
+    let mut content_info = expect(&mut resp, TAG_SEQUENCE, "a timeStampToken")?;
+    if expect(&mut content_info, TAG_OID, "a content type")? != OID_SIGNED_DATA {
+        return Err("token is not SignedData".to_string());
+    }
+    let mut explicit = expect(&mut content_info, TAG_EXPLICIT_0, "SignedData")?;
+    let mut signed_data = expect(&mut explicit, TAG_SEQUENCE, "SignedData")?;
+    expect(&mut signed_data, TAG_INTEGER, "a version")?;
+    expect(&mut signed_data, TAG_SET, "digest algorithms")?;
+    let mut encap = expect(&mut signed_data, TAG_SEQUENCE, "encapsulated content")?;
+    if expect(&mut encap, TAG_OID, "a content type")? != OID_TST_INFO {
+        return Err("token does not hold a TSTInfo".to_string());
+    }
+    let mut explicit = expect(&mut encap, TAG_EXPLICIT_0, "a TSTInfo")?;
+    let mut tst_info = expect(&mut explicit, TAG_OCTET_STRING, "a TSTInfo")?;
+
+    let mut tst = expect(&mut tst_info, TAG_SEQUENCE, "a TSTInfo")?;
+    expect(&mut tst, TAG_INTEGER, "a version")?;
+    expect(&mut tst, TAG_OID, "a policy")?;
+    let mut message_imprint = expect(&mut tst, TAG_SEQUENCE, "a message imprint")?;
+    let mut algorithm = expect(&mut message_imprint, TAG_SEQUENCE, "a hash algorithm")?;
+    if expect(&mut algorithm, TAG_OID, "a hash algorithm")? != OID_SHA256 {
+        return Err("token is not for a SHA-256 imprint".to_string());
+    }
+    let imprint = expect(&mut message_imprint, TAG_OCTET_STRING, "an imprint")?.to_vec();
+    expect(&mut tst, TAG_INTEGER, "a serial number")?;
+    let time = expect(&mut tst, TAG_GENERALIZED_TIME, "a time")?;
+    let time = std::str::from_utf8(time).map_err(|_| "time is not text".to_string())?;
+    // YYYYMMDDHHMMSS[.fff]Z
+    let time = match (
+        time.get(..4),
+        time.get(4..6),
+        time.get(6..8),
+        time.get(8..10),
+    ) {
+        (Some(y), Some(mo), Some(d), Some(h)) => match (time.get(10..12), time.get(12..14)) {
+            (Some(mi), Some(s)) => format!("{y}-{mo}-{d}T{h}:{mi}:{s}Z"),
+            _ => return Err(format!("{time}: not a time")),
+        },
+        _ => return Err(format!("{time}: not a time")),
+    };
+    // The optional accuracy and ordering come before the nonce.
+    let mut nonce = None;
+    while !tst.is_empty() {
+        let (tag, content, rest) = read(tst)?;
+        if tag == TAG_INTEGER {
+            nonce = Some(unsigned(content).to_vec());
+            break;
+        }
+        tst = rest;
+    }
+    Ok(Token {
+        imprint,
+        time,
+        nonce,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A granted response as a TSA would send it, less the signer info.
+    fn response(imprint: &[u8], nonce: &[u8]) -> Vec<u8> {
+        let algorithm = der(
+            TAG_SEQUENCE,
+            &[der(TAG_OID, OID_SHA256), der(TAG_NULL, &[])].concat(),
+        );
+        let tst_info = der(
+            TAG_SEQUENCE,
+            &[
+                integer(&[1]),
+                der(TAG_OID, &[0x2a, 0x03, 0x04]),
+                der(
+                    TAG_SEQUENCE,
+                    &[algorithm.clone(), der(TAG_OCTET_STRING, imprint)].concat(),
+                ),
+                integer(&[0x42]),
+                der(TAG_GENERALIZED_TIME, b"20261016093000.5Z"),
+                der(TAG_SEQUENCE, &integer(&[1])),
+                integer(nonce),
+            ]
+            .concat(),
+        );
+        let signed_data = der(
+            TAG_SEQUENCE,
+            &[
+                integer(&[3]),
+                der(TAG_SET, &algorithm),
+                der(
+                    TAG_SEQUENCE,
+                    &[
+                        der(TAG_OID, OID_TST_INFO),
+                        der(TAG_EXPLICIT_0, &der(TAG_OCTET_STRING, &tst_info)),
+                    ]
+                    .concat(),
+                ),
+                der(TAG_SET, &[]),
+            ]
+            .concat(),
+        );
+        der(
+            TAG_SEQUENCE,
+            &[
+                der(TAG_SEQUENCE, &integer(&[0])),
+                der(
+                    TAG_SEQUENCE,
+                    &[
+                        der(TAG_OID, OID_SIGNED_DATA),
+                        der(TAG_EXPLICIT_0, &signed_data),
+                    ]
+                    .concat(),
+                ),
+            ]
+            .concat(),
+        )
+    }
+
+    #[test]
+    fn requests_encode_and_tokens_parse() {
+        let hash = "ab".repeat(32);
+        let imprint = imprint(&hash).expect("imprint");
+        let nonce = [0x80, 1, 2, 3, 4, 5, 6, 7];
+        let req = request(&imprint, &nonce);
+        // One-byte lengths throughout, and the nonce padded to stay
+        // positive.
+        assert_eq!(req[..2], [TAG_SEQUENCE, req.len() as u8 - 2]);
+        assert!(req
+            .windows(11)
+            .any(|w| w == [TAG_INTEGER, 9, 0, 0x80, 1, 2, 3, 4, 5, 6, 7]));
+
+        let token = parse_response(&response(&imprint, &nonce)).expect("token");
+        assert_eq!(token.imprint, imprint);
+        assert_eq!(token.time, "2026-10-16T09:30:00Z");
+        assert_eq!(token.nonce.as_deref(), Some(&nonce[..]));
+
+        let mut refused = response(&imprint, &nonce);
+        // PKIStatus rejection(2).
+        let status = refused
+            .windows(3)
+            .position(|w| w == [TAG_INTEGER, 1, 0])
+            .expect("status");
+        refused[status + 2] = 2;
+        assert!(parse_response(&refused).is_err_and(|e| e.contains("refused")));
+        assert!(parse_response(&response(&imprint, &nonce)[..40]).is_err());
+        assert_eq!(
+            der(TAG_OCTET_STRING, &[0; 300])[..4],
+            [0x04, 0x82, 0x01, 0x2c]
+        );
+    }
+}
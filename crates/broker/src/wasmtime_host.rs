@@ -3,6 +3,18 @@
 // This module is compiled only when the `wasmtime-host` feature is enabled.
 // It implements the entrypoint for running a WASM component and wiring host
 // implementations from the broker to the component-generated bindings.
+//
+// Bindings are async: the guest runs on a fiber that yields back to the
+// runtime every so often, so runs can share a runtime, and the broker's
+// blocking hosts are called from a thread the runtime has let go of.
+//
+// WASI Preview 2 is linked too, so components built against the standard
+// worlds run, but with nothing to reach through it: no preopened
+// directories, environment, arguments or sockets. Clocks and random are
+// the host's, random seeded like `saf:app/rand` on reproducible runs, and
+// stdin is empty unless the run inherits the broker's stdio. Otherwise
+// stdout and stderr are piped back to the run and audited line by line
+// (see `crate::guest_output`).
 
 #[cfg(feature = "wasmtime-host")]
 mod bindings {
@@ -10,155 +22,1061 @@ mod bindings {
         path: "../wit",
         world: "app",
         trappable_imports: true,
-        async: false,
+        async: true,
     });
 }
 
 #[cfg(feature = "wasmtime-host")]
 mod impls {
     use super::*;
+    use crate::guest_output::{Capture, Stream};
+    use crate::requests::traced;
+    use crate::run_log::{self, HostCallSummary, RunLog, RunLogEntry};
+    use crate::sysinfo::SysInfo;
     use crate::wasmtime_host::bindings;
     use anyhow::Result;
+    use bindings::saf::app::net::{
+        HttpResponse as WitHttpResponse, NetError as WitNetError, ResponseStream,
+    };
+    use saf_core::{AuditEvent, Capability, Code, Denial};
+    use saf_policy::MemoryLimits;
+    use std::collections::BTreeMap;
     use std::fs;
+    use std::future::Future;
     use std::path::Path;
-    use wasmtime::component::{Component, Linker};
-    use wasmtime::{Config, Engine, Store};
-    use wasmtime_wasi::{WasiCtx, WasiCtxBuilder};
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::task::Poll;
+    use std::time::{Duration, Instant};
+    use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+    use wasmtime::component::{Component, Instance, Linker, Resource, ResourceTable, TypedFunc};
+    use wasmtime::{AsContextMut, Config, Engine, GuestProfiler, Store, Trap, UpdateDeadline};
+    use wasmtime_wasi::pipe::AsyncWriteStream;
+    use wasmtime_wasi::{AsyncStdoutStream, WasiCtx, WasiCtxBuilder, WasiView};
 
     // Host adapter implementing imported interfaces, delegating to core hosts.
     struct Host<'a> {
         core: CoreCtx<'a>,
+        rng: rand::rngs::StdRng,
+        // Call count and accumulated wall time per host function, summarised
+        // into the run log and, when profiling, the folded host-call stacks.
+        spans: BTreeMap<&'static str, (u64, Duration)>,
+        run_log: Option<RunLog>,
+        sysinfo: SysInfo,
+        // The `saf:app/config` values the policy granted at the start.
+        config: BTreeMap<String, String>,
+        // Where clock readings are recorded to or replayed from.
+        trace: Option<Arc<crate::trace::Trace>>,
+        // Per-stream carry-over for `response-stream.next-text`.
+        decoders: std::collections::HashMap<u32, saf_core::Utf8Decoder>,
+        // ID of the host call being served, named in the errors it returns.
+        request: String,
+        // Ends the run once set, or once the deadline has passed; the
+        // deadline also cuts off the network requests host calls make.
+        stop: Option<Arc<AtomicBool>>,
+        deadline: Option<Instant>,
+        timed_out: bool,
+    }
+
+    impl<'a> Host<'a> {
+        /// Serve a host call, off the runtime's worker threads, and trap the
+        /// guest afterwards if the run should end.
+        fn timed<T>(
+            &mut self,
+            name: &'static str,
+            f: impl FnOnce(&mut Self) -> Result<T>,
+        ) -> Result<T> {
+            let start = Instant::now();
+            let requests = self.core.requests;
+            self.request = requests.begin(self.core.ctx.component.run_id.as_deref());
+            let deadline = self.deadline;
+            let out = blocking(|| crate::http::with_deadline(deadline, || f(self)));
+            requests.end();
+            let entry = self.spans.entry(name).or_default();
+            entry.0 += 1;
+            entry.1 += start.elapsed();
+            self.interrupted()?;
+            out
+        }
+
+        /// Fails once the run has been stopped or has timed out.
+        fn interrupted(&mut self) -> Result<()> {
+            if self
+                .stop
+                .as_ref()
+                .is_some_and(|s| s.load(Ordering::Relaxed))
+            {
+                return Err(anyhow::anyhow!("stopped by the broker"));
+            }
+            if self.deadline.is_some_and(|d| Instant::now() >= d) {
+                self.timed_out = true;
+                return Err(anyhow::anyhow!("run timed out"));
+            }
+            Ok(())
+        }
+
+        fn error(&self, e: impl std::fmt::Display) -> anyhow::Error {
+            anyhow::anyhow!(traced(e, &self.request))
+        }
+
+        fn net_error(&self, e: saf_core::CoreError) -> WitNetError {
+            match wit_net_error(e) {
+                WitNetError::Failed(message) => WitNetError::Failed(traced(message, &self.request)),
+                other => other,
+            }
+        }
+
+        // Component log output belongs to the run, not the audit trail; the
+        // audit log is the fallback only when no run log is attached.
+        fn run_log(&self, entry: RunLogEntry) {
+            match &self.run_log {
+                Some(log) => {
+                    let _ = log.append(&entry);
+                }
+                None => self.core.ctx.audit(
+                    Code::ComponentLog,
+                    AuditEvent::ComponentLog {
+                        message: entry.to_string(),
+                    },
+                ),
+            }
+        }
     }
 
     // fs
+    #[async_trait::async_trait]
     impl<'a> bindings::saf::app::fs::Host for Host<'a> {
-        fn list_dir(&mut self, path: String) -> Result<Vec<String>> {
-            self.core
-                .ctx
-                .fs
-                .list_dir(&path)
-                .map_err(|e| anyhow::anyhow!(e))
+        async fn list_dir(&mut self, path: String) -> Result<Vec<String>> {
+            self.timed("saf:app/fs#list-dir", |h| {
+                h.core.ctx.fs.list_dir(&path).map_err(|e| h.error(e))
+            })
+        }
+        async fn read_text(&mut self, path: String) -> Result<String> {
+            self.timed("saf:app/fs#read-text", |h| {
+                h.core.ctx.fs.read_text(&path).map_err(|e| h.error(e))
+            })
         }
-        fn read_text(&mut self, path: String) -> Result<String> {
-            self.core
-                .ctx
-                .fs
-                .read_text(&path)
-                .map_err(|e| anyhow::anyhow!(e))
+        async fn read_text_range(
+            &mut self,
+            path: String,
+            offset: u64,
+            len: u64,
+        ) -> Result<bindings::saf::app::fs::TextRange> {
+            self.timed("saf:app/fs#read-text-range", |h| {
+                let r = saf_core::read_text_range(&h.core.ctx, &path, offset, len)
+                    .map_err(|e| h.error(e))?;
+                Ok(bindings::saf::app::fs::TextRange {
+                    text: r.text,
+                    start: r.start,
+                    end: r.end,
+                })
+            })
         }
-        fn write_text(&mut self, path: String, content: String) -> Result<()> {
-            self.core
-                .ctx
-                .fs
-                .write_text(&path, &content)
-                .map_err(|e| anyhow::anyhow!(e))
+        async fn write_text(&mut self, path: String, content: String) -> Result<()> {
+            self.timed("saf:app/fs#write-text", |h| {
+                h.core
+                    .ctx
+                    .fs
+                    .write_text(&path, &content)
+                    .map_err(|e| h.error(e))
+            })
+        }
+        async fn append_text(&mut self, path: String, content: String) -> Result<()> {
+            self.timed("saf:app/fs#append-text", |h| {
+                saf_core::append_text(&h.core.ctx, &path, &content).map_err(|e| h.error(e))
+            })
+        }
+        async fn append_bytes(&mut self, path: String, content: Vec<u8>) -> Result<()> {
+            self.timed("saf:app/fs#append-bytes", |h| {
+                saf_core::append_bytes(&h.core.ctx, &path, &content).map_err(|e| h.error(e))
+            })
         }
     }
 
     // net
+    fn wit_net_error(e: saf_core::CoreError) -> WitNetError {
+        match e {
+            saf_core::CoreError::RateLimited { retry_after_ms, .. } => {
+                WitNetError::RateLimited(retry_after_ms)
+            }
+            saf_core::CoreError::Offline => WitNetError::Offline,
+            other => WitNetError::Failed(format!("{}: {}", other.code(), other)),
+        }
+    }
+
+    #[async_trait::async_trait]
     impl<'a> bindings::saf::app::net::Host for Host<'a> {
-        fn get_text(&mut self, url: String) -> Result<String> {
-            self.core
-                .ctx
-                .net
-                .get_text(&url)
-                .map_err(|e| anyhow::anyhow!(e))
+        async fn get_text(&mut self, url: String) -> Result<Result<String, WitNetError>> {
+            self.timed("saf:app/net#get-text", |h| {
+                Ok(saf_core::fetch_json(&h.core.ctx, &url).map_err(|e| h.net_error(e)))
+            })
+        }
+        async fn fetch(&mut self, url: String) -> Result<Result<WitHttpResponse, WitNetError>> {
+            self.timed("saf:app/net#fetch", |h| {
+                Ok(saf_core::fetch(&h.core.ctx, &url)
+                    .map(|r| WitHttpResponse {
+                        status: r.status,
+                        headers: r.headers,
+                        body: r.body,
+                    })
+                    .map_err(|e| h.net_error(e)))
+            })
+        }
+        async fn get_stream(
+            &mut self,
+            url: String,
+        ) -> Result<Result<Resource<ResponseStream>, WitNetError>> {
+            self.timed("saf:app/net#get-stream", |h| {
+                // The host handle doubles as the resource rep.
+                Ok(saf_core::stream_open(&h.core.ctx, &url)
+                    .map_err(|e| h.net_error(e))
+                    .and_then(|id| {
+                        u32::try_from(id).map(Resource::new_own).map_err(|_| {
+                            let message = Code::NetFailed.with_message("stream handle overflow");
+                            WitNetError::Failed(traced(message, &h.request))
+                        })
+                    }))
+            })
+        }
+        async fn rewriting_active(&mut self) -> Result<bool> {
+            Ok(saf_core::net_rewriting_active(&self.core.ctx))
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl<'a> bindings::saf::app::net::HostResponseStream for Host<'a> {
+        async fn next(
+            &mut self,
+            stream: Resource<ResponseStream>,
+        ) -> Result<Result<Option<Vec<u8>>, WitNetError>> {
+            self.timed("saf:app/net#[method]response-stream.next", |h| {
+                Ok(saf_core::stream_next(&h.core.ctx, u64::from(stream.rep()))
+                    .map_err(|e| h.net_error(e)))
+            })
+        }
+        async fn next_text(
+            &mut self,
+            stream: Resource<ResponseStream>,
+        ) -> Result<Result<Option<String>, WitNetError>> {
+            self.timed("saf:app/net#[method]response-stream.next-text", |h| {
+                let id = stream.rep();
+                let chunk = match saf_core::stream_next(&h.core.ctx, u64::from(id)) {
+                    Ok(c) => c,
+                    Err(e) => return Ok(Err(h.net_error(e))),
+                };
+                let decoder = h.decoders.entry(id).or_default();
+                let decoded = match chunk {
+                    Some(bytes) => decoder.push(&bytes).map(Some),
+                    // End of stream: anything still held back is truncated.
+                    None => std::mem::take(decoder).finish().map(|()| None),
+                };
+                Ok(decoded.map_err(|e| {
+                    WitNetError::Failed(traced(Code::NetFailed.with_message(&e), &h.request))
+                }))
+            })
+        }
+        async fn drop(&mut self, stream: Resource<ResponseStream>) -> Result<()> {
+            self.decoders.remove(&stream.rep());
+            // Already gone if the host ended it on a policy limit.
+            let _ = saf_core::stream_close(&self.core.ctx, u64::from(stream.rep()));
+            Ok(())
+        }
+    }
+
+    // ws (lifecycle audited by core)
+    #[async_trait::async_trait]
+    impl<'a> bindings::saf::app::ws::Host for Host<'a> {
+        async fn connect(&mut self, url: String) -> Result<u64> {
+            self.timed("saf:app/ws#connect", |h| {
+                saf_core::ws_connect(&h.core.ctx, &url).map_err(|e| h.error(e))
+            })
+        }
+        async fn send(&mut self, conn: u64, message: String) -> Result<()> {
+            self.timed("saf:app/ws#send", |h| {
+                saf_core::ws_send(&h.core.ctx, conn, &message).map_err(|e| h.error(e))
+            })
+        }
+        async fn receive(&mut self, conn: u64) -> Result<Option<String>> {
+            self.timed("saf:app/ws#receive", |h| {
+                saf_core::ws_receive(&h.core.ctx, conn).map_err(|e| h.error(e))
+            })
+        }
+        async fn close(&mut self, conn: u64) -> Result<()> {
+            self.timed("saf:app/ws#close", |h| {
+                saf_core::ws_close(&h.core.ctx, conn).map_err(|e| h.error(e))
+            })
         }
     }
 
     // log
+    #[async_trait::async_trait]
     impl<'a> bindings::saf::app::log::Host for Host<'a> {
-        fn event(&mut self, message: String) -> Result<()> {
-            self.core.ctx.log.event(&message);
-            Ok(())
+        async fn event(&mut self, message: String) -> Result<()> {
+            self.timed("saf:app/log#event", |h| {
+                h.run_log(RunLogEntry::Log {
+                    ts_ms: run_log::now_ms(),
+                    message,
+                });
+                Ok(())
+            })
+        }
+        async fn progress(&mut self, done: u64, total: u64, message: String) -> Result<()> {
+            self.timed("saf:app/log#progress", |h| {
+                h.run_log(RunLogEntry::Progress {
+                    ts_ms: run_log::now_ms(),
+                    done,
+                    total,
+                    message,
+                });
+                Ok(())
+            })
         }
     }
 
-    // time (stub: use system time seconds)
+    // time (stub: use system time seconds, or the trace's when replaying)
+    #[async_trait::async_trait]
     impl<'a> bindings::saf::app::time::Host for Host<'a> {
-        fn now_unix_seconds(&mut self) -> Result<u64> {
-            Ok(std::time::SystemTime::now()
+        async fn now_unix_seconds(&mut self) -> Result<u64> {
+            let now = std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap_or_default()
-                .as_secs())
+                .as_secs();
+            Ok(self.trace.as_ref().map_or(now, |t| t.now(now)))
         }
     }
 
-    // rand (deterministic stub for testing; production should use OS RNG)
+    // rand (seeded from RunOptions for reproducible runs, OS entropy otherwise)
+    #[async_trait::async_trait]
     impl<'a> bindings::saf::app::rand::Host for Host<'a> {
-        fn fill(&mut self, len: u32) -> Result<Vec<u8>> {
-            // Use deterministic RNG for reproducible testing
-            use rand::{rngs::StdRng, RngCore, SeedableRng};
-            let mut rng = StdRng::from_entropy();
+        async fn fill(&mut self, len: u32) -> Result<Vec<u8>> {
+            use rand::RngCore;
             let mut buf = vec![0u8; len as usize];
-            rng.fill_bytes(&mut buf);
+            self.rng.fill_bytes(&mut buf);
             Ok(buf)
         }
     }
 
-    pub fn run_component(component_path: &Path, core: CoreCtx) -> Result<(), String> {
-        // Engine with component model enabled
+    // sysinfo (collected once per run, already filtered by policy)
+    impl<'a> Host<'a> {
+        fn granted(&self, field: &str, value: Option<String>) -> Result<String, String> {
+            let ctx = &self.core.ctx;
+            match value {
+                Some(v) => {
+                    ctx.audit(
+                        Code::SysinfoRead,
+                        AuditEvent::SysinfoRead {
+                            field: field.to_string(),
+                        },
+                    );
+                    Ok(v)
+                }
+                None => {
+                    let denial =
+                        Denial::new(Code::PolicySysinfoNotGranted, format!("sysinfo.{field}"));
+                    ctx.violation(&denial, Capability::Sysinfo, field);
+                    let message = denial
+                        .code
+                        .with_message(&format!("{field} requires a sysinfo grant"));
+                    Err(traced(message, &self.request))
+                }
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl<'a> bindings::saf::app::sysinfo::Host for Host<'a> {
+        async fn locale(&mut self) -> Result<String> {
+            Ok(self.sysinfo.locale.clone())
+        }
+        async fn timezone(&mut self) -> Result<String> {
+            Ok(self.sysinfo.timezone.clone())
+        }
+        async fn os_family(&mut self) -> Result<String> {
+            Ok(self.sysinfo.os_family.clone())
+        }
+        async fn framework_version(&mut self) -> Result<String> {
+            Ok(self.sysinfo.framework_version.clone())
+        }
+        async fn hostname(&mut self) -> Result<Result<String, String>> {
+            self.timed("saf:app/sysinfo#hostname", |h| {
+                Ok(h.granted("hostname", h.sysinfo.hostname.clone()))
+            })
+        }
+        async fn username(&mut self) -> Result<Result<String, String>> {
+            self.timed("saf:app/sysinfo#username", |h| {
+                Ok(h.granted("username", h.sysinfo.username.clone()))
+            })
+        }
+    }
+
+    // config (granted values collected once per run; each read is checked
+    // against the policy in force and audited)
+    #[async_trait::async_trait]
+    impl<'a> bindings::saf::app::config::Host for Host<'a> {
+        async fn get(&mut self, key: String) -> Result<Result<Option<String>, String>> {
+            self.timed("saf:app/config#get", |h| {
+                let ctx = &h.core.ctx;
+                if let Err(denial) = ctx.policy.current().check_config_key(&key) {
+                    ctx.violation(&denial, Capability::Config, &key);
+                    let message = denial
+                        .code
+                        .with_message(&format!("{key} is not in the policy's config_keys"));
+                    return Ok(Err(traced(message, &h.request)));
+                }
+                let value = h.config.get(&key).cloned();
+                ctx.audit(
+                    Code::ConfigRead,
+                    AuditEvent::ConfigRead {
+                        key: ctx.audited(Capability::Config, &key),
+                        found: value.is_some(),
+                    },
+                );
+                Ok(Ok(value))
+            })
+        }
+    }
+
+    // Store data: host adapter, WASI context and its resources, resource
+    // limiter and the optional guest profiler.
+    struct State<'a> {
+        host: Host<'a>,
+        wasi: WasiCtx,
+        table: ResourceTable,
+        limiter: Limiter,
+        profiler: Option<GuestProfiler>,
+    }
+
+    impl WasiView for State<'_> {
+        fn table(&mut self) -> &mut ResourceTable {
+            &mut self.table
+        }
+
+        fn ctx(&mut self) -> &mut WasiCtx {
+            &mut self.wasi
+        }
+    }
+
+    /// A WASI context granting nothing beyond clocks, random and stdio:
+    /// the broker's if asked for, or else output sent to `output`.
+    fn wasi_ctx(options: &RunOptions, output: UnboundedSender<Chunk>) -> WasiCtx {
+        use rand::{rngs::StdRng, SeedableRng};
+        let mut builder = WasiCtxBuilder::new();
+        builder
+            .allow_tcp(false)
+            .allow_udp(false)
+            .allow_ip_name_lookup(false);
+        if let Some(seed) = options.rng_seed {
+            builder.secure_random(StdRng::seed_from_u64(seed));
+            builder.insecure_random(StdRng::seed_from_u64(seed.wrapping_add(1)));
+            builder.insecure_random_seed(u128::from(seed));
+        }
+        if options.inherit_stdio {
+            builder.inherit_stdio();
+        } else {
+            let pipe = |stream, tx| {
+                AsyncStdoutStream::new(AsyncWriteStream::new(
+                    OUTPUT_BUFFER,
+                    OutputPipe { stream, tx },
+                ))
+            };
+            builder.stdout(pipe(Stream::Stdout, output.clone()));
+            builder.stderr(pipe(Stream::Stderr, output));
+        }
+        builder.build()
+    }
+
+    /// Bytes the guest wrote to one of its output streams.
+    type Chunk = (Stream, Vec<u8>);
+
+    /// Bytes a guest output stream may have in flight to the pipe.
+    const OUTPUT_BUFFER: usize = 64 * 1024;
+
+    /// The write end of a guest output stream.
+    struct OutputPipe {
+        stream: Stream,
+        tx: UnboundedSender<Chunk>,
+    }
+
+    impl tokio::io::AsyncWrite for OutputPipe {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            // Once the run has stopped listening, output goes nowhere.
+            let _ = self.tx.send((self.stream, buf.to_vec()));
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// The read end: guest output, audited as it arrives.
+    struct Output<'a> {
+        ctx: saf_core::Context<'a>,
+        rx: UnboundedReceiver<Chunk>,
+        capture: Capture,
+    }
+
+    impl Output<'_> {
+        fn record(&self, events: Vec<AuditEvent>) {
+            for event in events {
+                self.ctx.audit(Code::ComponentOutput, event);
+            }
+        }
+
+        /// Drive `run` to completion, auditing output meanwhile.
+        async fn during<T>(&mut self, run: impl Future<Output = T>) -> T {
+            tokio::pin!(run);
+            loop {
+                tokio::select! {
+                    out = &mut run => return out,
+                    Some((stream, bytes)) = self.rx.recv() => {
+                        let events = self.capture.push(stream, &bytes);
+                        self.record(events);
+                    }
+                }
+            }
+        }
+
+        /// Audit what is left once the guest has returned, whose writes
+        /// have all reached the pipe by then.
+        fn finish(&mut self) {
+            while let Ok((stream, bytes)) = self.rx.try_recv() {
+                let events = self.capture.push(stream, &bytes);
+                self.record(events);
+            }
+            let events = self.capture.finish();
+            self.record(events);
+        }
+    }
+
+    /// Enforces the policy's `memory` limits, remembering the one the guest
+    /// hit so the run can report it, and how much memory it has grown to.
+    struct Limiter {
+        limits: MemoryLimits,
+        hit: Option<LimitExceeded>,
+        /// Linear memory across the guest's instances, which never shrinks,
+        /// so is also its peak.
+        memory_bytes: u64,
+    }
+
+    impl Limiter {
+        fn exceeded(&mut self, limit: &'static str, value: u64) -> Result<bool> {
+            let hit = LimitExceeded { limit, value };
+            self.hit = Some(hit);
+            Err(anyhow::anyhow!(hit.to_string()))
+        }
+    }
+
+    // Refusing growth with an error traps the guest, rather than have
+    // `memory.grow` return -1 for it to handle.
+    impl wasmtime::ResourceLimiter for Limiter {
+        fn memory_growing(
+            &mut self,
+            current: usize,
+            desired: usize,
+            _maximum: Option<usize>,
+        ) -> Result<bool> {
+            let max = self.limits.max_memory_bytes;
+            if u64::try_from(desired).unwrap_or(u64::MAX) > max {
+                return self.exceeded("memory.max_memory_bytes", max);
+            }
+            self.memory_bytes += desired.saturating_sub(current) as u64;
+            Ok(true)
+        }
+
+        fn table_growing(
+            &mut self,
+            _current: u32,
+            desired: u32,
+            _maximum: Option<u32>,
+        ) -> Result<bool> {
+            let max = self.limits.max_table_elements;
+            if desired > max {
+                return self.exceeded("memory.max_table_elements", max.into());
+            }
+            Ok(true)
+        }
+
+        fn instances(&self) -> usize {
+            self.limits.max_instances as usize
+        }
+
+        fn tables(&self) -> usize {
+            self.limits.max_tables as usize
+        }
+
+        fn memories(&self) -> usize {
+            self.limits.max_memories as usize
+        }
+    }
+
+    // Give the profiler a short-lived `&mut` alongside the store it samples.
+    fn with_profiler(
+        mut store: wasmtime::StoreContextMut<'_, State<'_>>,
+        f: impl FnOnce(&mut GuestProfiler, wasmtime::StoreContextMut<'_, State<'_>>),
+    ) {
+        if let Some(mut profiler) = store.data_mut().profiler.take() {
+            f(&mut profiler, store.as_context_mut());
+            store.data_mut().profiler = Some(profiler);
+        }
+    }
+
+    /// Run `bytes`, the component read from `component_path`, which only
+    /// names it here: the file is not read again.
+    pub async fn run_component(
+        component_path: &Path,
+        bytes: &[u8],
+        core: CoreCtx<'_>,
+        options: &RunOptions,
+    ) -> Result<Finished, String> {
+        use rand::{rngs::StdRng, SeedableRng};
+        // Async engine with component model enabled and fuel metered;
+        // profiling samples on epoch ticks, and a stop request or timeout
+        // is noticed on the next one.
         let mut cfg = Config::new();
         cfg.wasm_component_model(true);
+        cfg.async_support(true);
+        cfg.consume_fuel(true);
+        let profiling = options.profile_dir.is_some();
+        let interruptible = options.stop.is_some() || options.timeout.is_some();
+        if profiling || interruptible {
+            cfg.epoch_interruption(true);
+        }
         let engine = Engine::new(&cfg).map_err(|e| e.to_string())?;
 
-        if !component_path.exists() {
-            return Err(format!("component not found: {}", component_path.display()));
-        }
+        let component = Component::from_binary(&engine, bytes).map_err(|e| e.to_string())?;
+
+        let profiler = options.profile_dir.as_ref().map(|_| {
+            let name = component_path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "component".to_string());
+            GuestProfiler::new_component(
+                &name,
+                PROFILE_INTERVAL,
+                component.clone(),
+                std::iter::empty(),
+            )
+        });
 
-        // Load component
-        let bytes = fs::read(component_path).map_err(|e| e.to_string())?;
-        let component = Component::from_binary(&engine, &bytes).map_err(|e| e.to_string())?;
+        let (output_tx, output_rx) = mpsc::unbounded_channel();
+        let mut output = Output {
+            ctx: core.ctx.clone(),
+            rx: output_rx,
+            capture: Capture::default(),
+        };
 
         // Store + linker with host stored in state
-        struct State<'a> {
-            host: Host<'a>,
-        }
         let mut store: Store<State> = Store::new(
             &engine,
             State {
-                host: Host { core },
+                host: Host {
+                    core,
+                    rng: match options.rng_seed {
+                        Some(seed) => StdRng::seed_from_u64(seed),
+                        None => StdRng::from_entropy(),
+                    },
+                    spans: BTreeMap::new(),
+                    sysinfo: options.sysinfo.clone(),
+                    config: options.config.clone(),
+                    trace: options.trace.clone(),
+                    decoders: std::collections::HashMap::new(),
+                    request: String::new(),
+                    run_log: options.log_path.as_deref().map(RunLog::open).transpose()?,
+                    stop: options.stop.clone(),
+                    deadline: options.timeout.map(|t| Instant::now() + t),
+                    timed_out: false,
+                },
+                wasi: wasi_ctx(options, output_tx),
+                table: ResourceTable::new(),
+                limiter: Limiter {
+                    limits: options.memory,
+                    hit: None,
+                    memory_bytes: 0,
+                },
+                profiler,
             },
         );
-        let mut linker: Linker<State> = Linker::new(&engine);
-
-        // Instantiate bindings and provide host implementations
-        bindings::saf::app::fs::add_to_linker(&mut linker, |s: &mut State| &mut s.host)
-            .map_err(|e| e.to_string())?;
-        bindings::saf::app::net::add_to_linker(&mut linker, |s: &mut State| &mut s.host)
-            .map_err(|e| e.to_string())?;
-        bindings::saf::app::log::add_to_linker(&mut linker, |s: &mut State| &mut s.host)
-            .map_err(|e| e.to_string())?;
-        bindings::saf::app::time::add_to_linker(&mut linker, |s: &mut State| &mut s.host)
-            .map_err(|e| e.to_string())?;
-        bindings::saf::app::rand::add_to_linker(&mut linker, |s: &mut State| &mut s.host)
+        store.limiter(|s| &mut s.limiter);
+        let fuel = options.max_fuel.unwrap_or(u64::MAX);
+        store.set_fuel(fuel).map_err(|e| e.to_string())?;
+        store
+            .fuel_async_yield_interval(Some(FUEL_YIELD_INTERVAL))
             .map_err(|e| e.to_string())?;
 
-        // Instantiate component
-        let (exports, _instance) = bindings::App::instantiate(&mut store, &component, &linker)
-            .map_err(|e| e.to_string())?;
+        // Sample on every epoch tick and mark host-call boundaries so samples
+        // can be attributed to guest code vs. time spent in the broker. A
+        // requested stop, or the run's timeout, traps the guest at the next
+        // tick; a guest blocked in a host call is trapped once it returns,
+        // which for network calls is by the deadline.
+        let ticker = if profiling || interruptible {
+            store.set_epoch_deadline(1);
+            store.epoch_deadline_callback(|mut store| {
+                store.data_mut().host.interrupted()?;
+                with_profiler(store.as_context_mut(), |p, store| {
+                    p.sample(&store, Duration::ZERO)
+                });
+                Ok(UpdateDeadline::Yield(1))
+            });
+            if profiling {
+                store.call_hook(|mut store, kind| {
+                    with_profiler(store.as_context_mut(), |p, store| p.call_hook(&store, kind));
+                    Ok(())
+                });
+            }
+            let interval = if profiling {
+                PROFILE_INTERVAL
+            } else {
+                CHECK_INTERVAL
+            };
+            Some(EpochTicker::start(engine.clone(), interval))
+        } else {
+            None
+        };
+
+        let mut linker: Linker<State> = Linker::new(&engine);
+        wasmtime_wasi::add_to_linker_async(&mut linker).map_err(|e| e.to_string())?;
+
+        // Instantiate bindings and provide host implementations, only for
+        // the interfaces the capability manifest was granted.
+        let linked = |name: &str| {
+            options
+                .interfaces
+                .as_ref()
+                .is_none_or(|granted| granted.contains(name))
+        };
+        if linked("fs") {
+            bindings::saf::app::fs::add_to_linker(&mut linker, |s: &mut State| &mut s.host)
+                .map_err(|e| e.to_string())?;
+        }
+        if linked("net") {
+            bindings::saf::app::net::add_to_linker(&mut linker, |s: &mut State| &mut s.host)
+                .map_err(|e| e.to_string())?;
+        }
+        if linked("ws") {
+            bindings::saf::app::ws::add_to_linker(&mut linker, |s: &mut State| &mut s.host)
+                .map_err(|e| e.to_string())?;
+        }
+        if linked("log") {
+            bindings::saf::app::log::add_to_linker(&mut linker, |s: &mut State| &mut s.host)
+                .map_err(|e| e.to_string())?;
+        }
+        if linked("time") {
+            bindings::saf::app::time::add_to_linker(&mut linker, |s: &mut State| &mut s.host)
+                .map_err(|e| e.to_string())?;
+        }
+        if linked("rand") {
+            bindings::saf::app::rand::add_to_linker(&mut linker, |s: &mut State| &mut s.host)
+                .map_err(|e| e.to_string())?;
+        }
+        if linked("sysinfo") {
+            bindings::saf::app::sysinfo::add_to_linker(&mut linker, |s: &mut State| &mut s.host)
+                .map_err(|e| e.to_string())?;
+        }
+        if linked("config") {
+            bindings::saf::app::config::add_to_linker(&mut linker, |s: &mut State| &mut s.host)
+                .map_err(|e| e.to_string())?;
+        }
 
-        // Call exported start function
-        match exports.call_start(&mut store) {
-            Ok(s) => {
-                // Print or log the returned string for demo
-                println!("component.start: {}", s);
+        // Instantiate the component and call its exported start function,
+        // auditing its output as it goes. Instantiation can already hit a
+        // limit, which counts as the run's.
+        let run = async {
+            let (exports, instance) =
+                bindings::App::instantiate_async(&mut store, &component, &linker).await?;
+            let started = if options.input.is_some() || !options.args.is_empty() {
+                init(&mut store, &instance, options).await
+            } else {
                 Ok(())
+            };
+            Ok::<_, anyhow::Error>(match started {
+                Ok(()) => exports.call_start(&mut store).await,
+                Err(e) => Err(e),
+            })
+        };
+        let instantiated = output.during(run).await;
+        output.finish();
+        let result = match instantiated {
+            Ok(result) => result,
+            Err(e) if store.data().limiter.hit.is_some() => Err(e),
+            Err(e) => return Err(e.to_string()),
+        };
+        let fuel_consumed = fuel - store.get_fuel().unwrap_or(0);
+        let out_of_fuel =
+            matches!(&result, Err(e) if e.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel));
+        let limit = match store.data().limiter.hit {
+            Some(hit) => Some(hit),
+            None if out_of_fuel => Some(LimitExceeded {
+                limit: "max_fuel",
+                value: fuel,
+            }),
+            None => None,
+        };
+        let timed_out = store.data().host.timed_out;
+        let result = result.map_err(|e| match (limit, options.timeout) {
+            (Some(hit), _) => hit.to_string(),
+            (None, Some(timeout)) if timed_out => format!(
+                "{}: run exceeded its {}s timeout",
+                Code::ComponentTimedOut,
+                timeout.as_secs()
+            ),
+            _ => format!("Component execution failed: {}", e),
+        });
+
+        drop(ticker);
+        let state = store.into_data();
+        let host_calls = state
+            .host
+            .spans
+            .iter()
+            .map(|(name, (calls, _))| (name.to_string(), *calls))
+            .collect();
+        if let Some(log) = &state.host.run_log {
+            let calls = state
+                .host
+                .spans
+                .iter()
+                .map(|(name, (calls, total))| {
+                    let summary = HostCallSummary {
+                        calls: *calls,
+                        total_us: u64::try_from(total.as_micros()).unwrap_or(u64::MAX),
+                    };
+                    (name.to_string(), summary)
+                })
+                .collect();
+            log.append(&RunLogEntry::HostCalls {
+                ts_ms: run_log::now_ms(),
+                calls,
+            })?;
+        }
+        if let Some(dir) = &options.profile_dir {
+            write_profile(dir, state.profiler, state.host.spans)?;
+        }
+        Ok(Finished {
+            output: result,
+            fuel_consumed,
+            peak_memory_bytes: state.limiter.memory_bytes,
+            host_calls,
+            limit,
+            timed_out,
+        })
+    }
+
+    /// Hand the run's input to the component's `init` export (from the
+    /// `app-with-input` world), failing the run if it has none or refuses.
+    async fn init(
+        store: &mut Store<State<'_>>,
+        instance: &Instance,
+        options: &RunOptions,
+    ) -> Result<()> {
+        type Init = TypedFunc<(String, Vec<(String, String)>), (Result<(), String>,)>;
+        let init: Init = instance.get_typed_func(&mut *store, "init").map_err(|_| {
+            anyhow::anyhow!("the component takes no input: it has no `init` export")
+        })?;
+        let input = options.input.clone().unwrap_or_else(|| "null".to_string());
+        let (result,) = init
+            .call_async(&mut *store, (input, options.args.clone()))
+            .await?;
+        init.post_return_async(&mut *store).await?;
+        result.map_err(|e| anyhow::anyhow!("init: {e}"))
+    }
+
+    const PROFILE_INTERVAL: Duration = Duration::from_millis(1);
+    /// Fuel the guest burns between yields to the runtime.
+    const FUEL_YIELD_INTERVAL: u64 = 100_000;
+    /// How often a stoppable or timed run checks whether it should end.
+    const CHECK_INTERVAL: Duration = Duration::from_millis(20);
+
+    /// Background thread advancing the engine epoch while a profiled,
+    /// stoppable or timed run is live.
+    struct EpochTicker {
+        stop: Arc<AtomicBool>,
+        handle: Option<std::thread::JoinHandle<()>>,
+    }
+
+    impl EpochTicker {
+        fn start(engine: Engine, interval: Duration) -> Self {
+            let stop = Arc::new(AtomicBool::new(false));
+            let flag = stop.clone();
+            let handle = std::thread::spawn(move || {
+                while !flag.load(Ordering::Relaxed) {
+                    std::thread::sleep(interval);
+                    engine.increment_epoch();
+                }
+            });
+            Self {
+                stop,
+                handle: Some(handle),
+            }
+        }
+    }
+
+    impl Drop for EpochTicker {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(h) = self.handle.take() {
+                let _ = h.join();
             }
-            Err(e) => Err(format!("Component execution failed: {}", e)),
         }
     }
+
+    /// Write `guest.json` (Firefox profiler format, viewable as a flame graph)
+    /// and `host_calls.folded` (collapsed stacks for flamegraph.pl/inferno).
+    fn write_profile(
+        dir: &Path,
+        profiler: Option<GuestProfiler>,
+        spans: BTreeMap<&'static str, (u64, Duration)>,
+    ) -> Result<(), String> {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        if let Some(profiler) = profiler {
+            let file = fs::File::create(dir.join("guest.json")).map_err(|e| e.to_string())?;
+            profiler
+                .finish(std::io::BufWriter::new(file))
+                .map_err(|e| e.to_string())?;
+        }
+        let mut folded = String::new();
+        for (name, (_calls, total)) in &spans {
+            folded.push_str(&format!("start;{} {}\n", name, total.as_micros()));
+        }
+        fs::write(dir.join("host_calls.folded"), folded).map_err(|e| e.to_string())
+    }
+}
+
+/// Run blocking host work. On a multi-threaded runtime the worker first
+/// hands its other tasks to the rest of the pool; a current-thread runtime
+/// only ever serves the one run, so it just blocks.
+pub fn blocking<T>(f: impl FnOnce() -> T) -> T {
+    use tokio::runtime::{Handle, RuntimeFlavor};
+    match Handle::try_current().map(|h| h.runtime_flavor()) {
+        Ok(RuntimeFlavor::MultiThread) => tokio::task::block_in_place(f),
+        _ => f(),
+    }
 }
 
 #[derive(Clone)]
 pub struct CoreCtx<'a> {
     pub ctx: saf_core::Context<'a>,
+    /// Issues the ID of each host call the component makes.
+    pub requests: &'a crate::requests::Requests,
+}
+
+/// Per-run knobs for component execution.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Seed for the guest-visible RNG; `None` draws from OS entropy.
+    pub rng_seed: Option<u64>,
+    /// Enables guest profiling; profile files are written into this directory.
+    pub profile_dir: Option<std::path::PathBuf>,
+    /// Structured per-run log stream (`log.jsonl`) for component output.
+    pub log_path: Option<std::path::PathBuf>,
+    /// What `saf:app/sysinfo` reports, already filtered by policy.
+    pub sysinfo: crate::sysinfo::SysInfo,
+    /// What `saf:app/config` can return, already filtered by policy.
+    pub config: std::collections::BTreeMap<String, String>,
+    /// `saf:app` interfaces to link, from the capability manifest; `None`
+    /// links them all.
+    pub interfaces: Option<std::collections::BTreeSet<String>>,
+    /// Once set, the guest is interrupted and the run fails; checked every
+    /// few milliseconds of guest execution and after each host call.
+    pub stop: Option<std::sync::Arc<std::sync::atomic::AtomicBool>>,
+    /// Fuel the guest may burn before it is trapped; `None` is unlimited.
+    pub max_fuel: Option<u64>,
+    /// What the guest's instances may allocate.
+    pub memory: saf_policy::MemoryLimits,
+    /// JSON payload for the component's `init` export.
+    pub input: Option<String>,
+    /// Named parameters for the component's `init` export, in order.
+    pub args: Vec<(String, String)>,
+    /// Hand the guest the broker's stdin, stdout and stderr through WASI;
+    /// otherwise its stdin is empty and its output is audited.
+    pub inherit_stdio: bool,
+    /// Run the guest in a child process (see `crate::isolate`).
+    pub isolate: bool,
+    /// How the process an isolated run is in is confined.
+    pub sandbox: crate::sandbox::Mode,
+    /// Longest the run may take before the guest is interrupted, along with
+    /// any network request it is waiting on; `None` lets it run until it
+    /// finishes.
+    pub timeout: Option<std::time::Duration>,
+    /// The trace the run's host calls and clock readings are recorded to or
+    /// replayed from (see `crate::trace`).
+    pub trace: Option<std::sync::Arc<crate::trace::Trace>>,
+    /// Ends the run, through `stop`, at its first denial (see
+    /// `crate::strict`).
+    pub strict: Option<std::sync::Arc<crate::strict::Tripwire>>,
+}
+
+/// A policy limit the guest reached, trapping the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitExceeded {
+    /// The policy field, such as `max_fuel` or `memory.max_memory_bytes`.
+    pub limit: &'static str,
+    pub value: u64,
+}
+
+impl LimitExceeded {
+    pub fn code(&self) -> saf_core::Code {
+        if self.limit == "max_fuel" {
+            saf_core::Code::PolicyFuelExhausted
+        } else {
+            saf_core::Code::PolicyMemoryLimit
+        }
+    }
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: run exceeded {} ({})",
+            self.code(),
+            self.limit,
+            self.value
+        )
+    }
+}
+
+/// How a run that got as far as calling the guest ended.
+#[derive(Debug, Clone)]
+pub struct Finished {
+    /// What the guest's `start` returned, or why it failed.
+    pub output: Result<String, String>,
+    /// Fuel the guest burned, instantiation included.
+    pub fuel_consumed: u64,
+    /// The most linear memory the guest's instances had.
+    pub peak_memory_bytes: u64,
+    /// Calls the guest made to each host function.
+    pub host_calls: std::collections::BTreeMap<String, u64>,
+    /// The limit the guest was trapped for reaching, if any.
+    pub limit: Option<LimitExceeded>,
+    /// The guest was interrupted at the run's timeout.
+    pub timed_out: bool,
 }
 
 #[cfg(feature = "wasmtime-host")]
 pub use impls::run_component;
 
 #[cfg(not(feature = "wasmtime-host"))]
-pub fn run_component(_component_path: &std::path::Path, _core: CoreCtx) -> Result<(), String> {
+pub async fn run_component(
+    _component_path: &std::path::Path,
+    _bytes: &[u8],
+    _core: CoreCtx<'_>,
+    _options: &RunOptions,
+) -> Result<Finished, String> {
     Err("Component execution requires the 'wasmtime-host' feature".to_string())
 }
@@ -0,0 +1,176 @@
+//! `broker run --watch <PATH>`: rerun a component each time it is rebuilt.
+//!
+//! The component runs as with `--run-component`, and its file is polled
+//! meanwhile. Once the file has settled on new contents (the same hash on
+//! two polls in a row, so a build still being written is not picked up),
+//! the running instance is stopped the way `broker serve` stops the
+//! components it manages, its run is torn down and audited, and the new
+//! build is compiled and instantiated afresh. A run that ends on its own
+//! waits for the next change. Watched runs have no timeout.
+//!
+//! With `--replay <DIR>`, the files under DIR are copied into the
+//! workspace, over any of the same name, before every run, so each build
+//! starts from the same input. A `.saf` directory in DIR is skipped, as
+//! are symlinks.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::cli::RunArgs;
+use crate::run_manifest::sha256_file;
+use crate::workspace_picker::WorkspaceStore;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Entry point for `broker run --watch`; runs until interrupted.
+pub fn main(args: RunArgs) -> Result<(), String> {
+    let component = args.watch.clone().ok_or("--watch needs a component")?;
+    let quiet = args.json;
+    let workspace = match &args.workspace_id {
+        Some(id) => WorkspaceStore::new()?.load_workspace(id)?.0,
+        None => std::env::current_dir().map_err(|e| e.to_string())?,
+    };
+    let mut changes = Changes::new(component.clone());
+    loop {
+        if let Some(dir) = &args.replay {
+            let copied = replay(dir, &workspace)?;
+            status!(quiet, "Replayed {copied} files from {}", dir.display());
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let run_args = RunArgs {
+            run_component: Some(component.clone()),
+            watch: None,
+            replay: None,
+            stop: Some(stop.clone()),
+            ..args.clone()
+        };
+        let mut run = Some(std::thread::spawn(move || run_once(run_args)));
+        while changes.poll().is_none() {
+            if run.as_ref().is_some_and(JoinHandle::is_finished) {
+                finish(run.take(), false);
+                status!(quiet, "Waiting for {} to change", component.display());
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        status!(quiet, "{} changed; restarting", component.display());
+        stop.store(true, Ordering::Relaxed);
+        finish(run.take(), true);
+    }
+}
+
+/// Run one build on a thread of its own, as the run may block.
+fn run_once(args: RunArgs) -> Result<(), String> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| e.to_string())?
+        .block_on(crate::run(args, false))
+        .map(drop)
+        .map_err(|e| e.to_string())
+}
+
+/// Wait for a run to end and say how it went, unless it was `stopped`.
+fn finish(run: Option<JoinHandle<Result<(), String>>>, stopped: bool) {
+    match run.map(JoinHandle::join) {
+        None | Some(Ok(Ok(()))) => {}
+        Some(Ok(Err(_))) if stopped => {}
+        Some(Ok(Err(e))) => eprintln!("Error: {e}"),
+        Some(Err(_)) => eprintln!("Error: the run panicked"),
+    }
+}
+
+/// Notices a file settling on new contents.
+struct Changes {
+    path: PathBuf,
+    seen: Option<String>,
+    pending: Option<String>,
+}
+
+impl Changes {
+    fn new(path: PathBuf) -> Self {
+        let seen = sha256_file(&path).ok();
+        Self {
+            path,
+            seen,
+            pending: None,
+        }
+    }
+
+    /// The file's new hash, once it has read the same twice in a row. A
+    /// missing file is no change.
+    fn poll(&mut self) -> Option<String> {
+        let now = sha256_file(&self.path).ok();
+        if now.is_none() || now == self.seen {
+            self.pending = None;
+            return None;
+        }
+        if now == self.pending {
+            self.seen = now.clone();
+            self.pending = None;
+            return now;
+        }
+        self.pending = now;
+        None
+    }
+}
+
+/// Copy the files under `from` into `to`, keeping their relative paths.
+/// Returns how many were copied.
+fn replay(from: &Path, to: &Path) -> Result<usize, String> {
+    let entries = std::fs::read_dir(from).map_err(|e| format!("{}: {}", from.display(), e))?;
+    let mut copied = 0;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("{}: {}", from.display(), e))?;
+        let (source, target) = (entry.path(), to.join(entry.file_name()));
+        let kind = entry
+            .file_type()
+            .map_err(|e| format!("{}: {}", source.display(), e))?;
+        if kind.is_dir() && entry.file_name() != ".saf" {
+            std::fs::create_dir_all(&target).map_err(|e| format!("{}: {}", target.display(), e))?;
+            copied += replay(&source, &target)?;
+        } else if kind.is_file() {
+            std::fs::copy(&source, &target).map_err(|e| format!("{}: {}", target.display(), e))?;
+            copied += 1;
+        }
+    }
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_settle_and_replays_copy_the_input() {
+        let dir = std::env::temp_dir().join(format!("saf-watch-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("input/docs")).unwrap();
+        std::fs::create_dir_all(dir.join("input/.saf")).unwrap();
+        let component = dir.join("app.wasm");
+        std::fs::write(&component, b"v1").unwrap();
+
+        let mut changes = Changes::new(component.clone());
+        assert_eq!(changes.poll(), None);
+        std::fs::write(&component, b"v2 (partial").unwrap();
+        assert_eq!(changes.poll(), None, "not settled yet");
+        std::fs::write(&component, b"v2").unwrap();
+        assert_eq!(changes.poll(), None, "changed again");
+        assert!(changes.poll().is_some());
+        assert_eq!(changes.poll(), None);
+        std::fs::remove_file(&component).unwrap();
+        assert_eq!(changes.poll(), None);
+
+        std::fs::write(dir.join("input/docs/a.txt"), "fresh").unwrap();
+        std::fs::write(dir.join("input/.saf/audit.log"), "x").unwrap();
+        let workspace = dir.join("ws");
+        std::fs::create_dir_all(workspace.join("docs")).unwrap();
+        std::fs::write(workspace.join("docs/a.txt"), "edited by the last run").unwrap();
+        assert_eq!(replay(&dir.join("input"), &workspace), Ok(1));
+        let a = std::fs::read_to_string(workspace.join("docs/a.txt")).unwrap();
+        assert_eq!(a, "fresh");
+        assert!(!workspace.join(".saf").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,5 +1,16 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use clap::Subcommand;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::elevation::ElevationStore;
+use crate::secrets::SecretStore;
+
 /// Cross-platform workspace picker interface
 pub trait WorkspacePicker {
     /// Pick a workspace directory, returning the path and a persistent token
@@ -9,9 +20,43 @@ pub trait WorkspacePicker {
     fn restore_workspace(&self, token: &str) -> Result<PathBuf, String>;
 }
 
-/// Persistent workspace storage
+/// Persistent workspace storage.
+///
+/// The store is sealed at rest: `workspaces.json` holds the saved
+/// workspaces, tokens included, as AES-256-GCM ciphertext under a key kept
+/// in the broker's [`SecretStore`] as `workspace-store.key`, and is
+/// readable only by the current user. A store an older broker saved in the
+/// clear is sealed the first time it is read, which happens once: when the
+/// key already exists, a store in the clear is refused, not re-sealed.
+///
+/// Sealing obfuscates the store; it does not protect it from the user's
+/// own processes. The key is a file in the same user's data directory, not
+/// in the platform keychain, so anything running as the user can read it
+/// and unseal the store. What sealing does is keep the tokens out of
+/// casual reads, search indexes and copies of `workspaces.json` made
+/// without the secrets directory, and reject a store changed by anything
+/// that does not hold the key.
 pub struct WorkspaceStore {
     store_path: PathBuf,
+    secrets: SecretStore,
+}
+
+/// The saved workspaces by ID, as `workspaces.json` holds them unsealed.
+type Entries = HashMap<String, serde_json::Value>;
+
+/// The [`SecretStore`] entry the store is sealed with.
+const STORE_KEY: &str = "workspace-store.key";
+const STORE_AAD: &[u8] = b"saf workspaces v1";
+
+/// `workspaces.json` as it is kept.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Sealed {
+    version: u32,
+    /// Base64, fresh for each save.
+    nonce: String,
+    /// Base64 of the JSON entries, sealed, with the tag appended.
+    ciphertext: String,
 }
 
 impl WorkspaceStore {
@@ -25,18 +70,19 @@ impl WorkspaceStore {
             std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
         }
 
-        Ok(Self { store_path })
+        Ok(Self::at(store_path, SecretStore::new()?))
+    }
+
+    /// The store at `store_path`, sealed with a key from `secrets`.
+    pub fn at(store_path: PathBuf, secrets: SecretStore) -> Self {
+        Self {
+            store_path,
+            secrets,
+        }
     }
 
     pub fn save_workspace(&self, id: &str, path: &Path, token: &str) -> Result<(), String> {
-        let mut workspaces: std::collections::HashMap<String, serde_json::Value> =
-            if self.store_path.exists() {
-                let content =
-                    std::fs::read_to_string(&self.store_path).map_err(|e| e.to_string())?;
-                serde_json::from_str(&content).unwrap_or_default()
-            } else {
-                std::collections::HashMap::new()
-            };
+        let mut workspaces = self.read()?;
 
         workspaces.insert(
             id.to_string(),
@@ -50,14 +96,11 @@ impl WorkspaceStore {
             }),
         );
 
-        let content = serde_json::to_string_pretty(&workspaces).map_err(|e| e.to_string())?;
-        std::fs::write(&self.store_path, content).map_err(|e| e.to_string())
+        self.write(&workspaces)
     }
 
     pub fn load_workspace(&self, id: &str) -> Result<(PathBuf, String), String> {
-        let content = std::fs::read_to_string(&self.store_path).map_err(|e| e.to_string())?;
-        let workspaces: std::collections::HashMap<String, serde_json::Value> =
-            serde_json::from_str(&content).map_err(|e| e.to_string())?;
+        let workspaces = self.read()?;
 
         let entry = workspaces
             .get(id)
@@ -76,22 +119,320 @@ impl WorkspaceStore {
         Ok((PathBuf::from(path), token.to_string()))
     }
 
-    pub fn list_workspaces(&self) -> Result<Vec<String>, String> {
+    /// Saved workspaces, oldest first.
+    pub fn list_workspaces(&self) -> Result<Vec<SavedWorkspace>, String> {
+        let mut list: Vec<SavedWorkspace> = self
+            .read()?
+            .into_iter()
+            .map(|(id, entry)| SavedWorkspace {
+                id,
+                path: PathBuf::from(entry.get("path").and_then(|v| v.as_str()).unwrap_or("")),
+                created: entry.get("created").and_then(|v| v.as_u64()).unwrap_or(0),
+            })
+            .collect();
+        list.sort_by(|a, b| (a.created, &a.id).cmp(&(b.created, &b.id)));
+        Ok(list)
+    }
+
+    /// Save the directory at `path` as a new workspace, restorable through
+    /// the path itself as its token.
+    pub fn add_workspace(&self, path: &Path) -> Result<SavedWorkspace, String> {
+        let path = path
+            .canonicalize()
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        if !path.is_dir() {
+            return Err(format!("{}: not a directory", path.display()));
+        }
+        let id = format!("workspace_{}", uuid::Uuid::new_v4().simple());
+        self.save_workspace(&id, &path, &path.to_string_lossy())?;
+        Ok(SavedWorkspace {
+            id,
+            path,
+            created: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        })
+    }
+
+    /// Forget the workspace `id`. The directory itself is left alone.
+    pub fn remove_workspace(&self, id: &str) -> Result<PathBuf, String> {
+        let (path, _) = self.load_workspace(id)?;
+        let mut workspaces = self.read()?;
+        workspaces.remove(id);
+        self.write(&workspaces)?;
+        Ok(path)
+    }
+
+    /// The saved workspaces, unsealed; sealing a store found in the clear
+    /// if it has never been sealed.
+    fn read(&self) -> Result<Entries, String> {
         if !self.store_path.exists() {
-            return Ok(Vec::new());
+            return Ok(Entries::new());
+        }
+        let content = std::fs::read(&self.store_path)
+            .map_err(|e| format!("{}: {}", self.store_path.display(), e))?;
+        if let Ok(sealed) = serde_json::from_slice::<Sealed>(&content) {
+            return self.unseal(&sealed);
+        }
+        let workspaces: Entries = serde_json::from_slice(&content)
+            .map_err(|e| format!("{}: {}", self.store_path.display(), e))?;
+        // A key means the store was sealed before, so a clear one now was
+        // put there by something other than the broker.
+        if self.secrets.find(STORE_KEY)?.is_some() {
+            return Err(format!(
+                "{}: not a sealed workspace store, and the store was sealed before (secret {STORE_KEY} exists)",
+                self.store_path.display()
+            ));
+        }
+        self.write(&workspaces)?;
+        Ok(workspaces)
+    }
+
+    fn write(&self, workspaces: &Entries) -> Result<(), String> {
+        let key = self.key(true)?;
+        let rng = SystemRandom::new();
+        let mut nonce = [0u8; NONCE_LEN];
+        rng.fill(&mut nonce)
+            .map_err(|_| "cannot generate a nonce".to_string())?;
+        let mut data = serde_json::to_vec(workspaces).map_err(|e| e.to_string())?;
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(STORE_AAD),
+            &mut data,
+        )
+        .map_err(|_| "cannot seal the workspace store".to_string())?;
+        let sealed = Sealed {
+            version: 1,
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(data),
+        };
+        let content = serde_json::to_vec_pretty(&sealed).map_err(|e| e.to_string())?;
+        // Written afresh and renamed over the store, so a store left
+        // readable to others is replaced rather than rewritten in place.
+        let temp = self.store_path.with_extension("json.tmp");
+        let _ = std::fs::remove_file(&temp);
+        crate::elevation::write_private(&temp, &content)?;
+        std::fs::rename(&temp, &self.store_path)
+            .map_err(|e| format!("{}: {}", self.store_path.display(), e))
+    }
+
+    fn unseal(&self, sealed: &Sealed) -> Result<Entries, String> {
+        let invalid = || {
+            format!(
+                "{}: not a sealed workspace store",
+                self.store_path.display()
+            )
+        };
+        if sealed.version != 1 {
+            return Err(format!(
+                "{}: unsupported store version {}",
+                self.store_path.display(),
+                sealed.version
+            ));
+        }
+        let key = self.key(false)?;
+        let nonce: [u8; NONCE_LEN] = BASE64
+            .decode(&sealed.nonce)
+            .ok()
+            .and_then(|n| n.try_into().ok())
+            .ok_or_else(invalid)?;
+        let mut data = BASE64.decode(&sealed.ciphertext).map_err(|_| invalid())?;
+        let plain = key
+            .open_in_place(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(STORE_AAD),
+                &mut data,
+            )
+            .map_err(|_| {
+                format!(
+                    "{}: cannot be unsealed with secret {STORE_KEY}",
+                    self.store_path.display()
+                )
+            })?;
+        serde_json::from_slice(plain).map_err(|e| format!("{}: {}", self.store_path.display(), e))
+    }
+
+    /// The key the store is sealed with, made on first use if `create`.
+    fn key(&self, create: bool) -> Result<LessSafeKey, String> {
+        let bytes = match self.secrets.find(STORE_KEY)? {
+            Some(bytes) => bytes,
+            None if create => {
+                let mut bytes = vec![0u8; AES_256_GCM.key_len()];
+                SystemRandom::new()
+                    .fill(&mut bytes)
+                    .map_err(|_| "cannot generate the workspace store key".to_string())?;
+                self.secrets.put(STORE_KEY, &bytes)?;
+                bytes
+            }
+            None => {
+                return Err(format!(
+                    "{} is sealed, but its key (secret {STORE_KEY}) is missing",
+                    self.store_path.display()
+                ))
+            }
+        };
+        UnboundKey::new(&AES_256_GCM, &bytes)
+            .map(LessSafeKey::new)
+            .map_err(|_| format!("secret {STORE_KEY} is not an AES-256 key"))
+    }
+}
+
+/// A workspace the broker remembers, as `broker workspace list` shows it.
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedWorkspace {
+    pub id: String,
+    pub path: PathBuf,
+    /// Seconds since the Unix epoch.
+    pub created: u64,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum WorkspaceCommand {
+    /// List saved workspaces, oldest first.
+    List {
+        #[arg(long)]
+        json: bool,
+    },
+    /// Save a directory as a workspace, to open with `--workspace-id`.
+    Add {
+        path: PathBuf,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Forget a saved workspace (needs `broker elevate`); its directory is
+    /// left alone.
+    Remove {
+        id: String,
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// Forget workspace `id` in `store`, which needs an elevated session in
+/// `elevation`: what the broker recorded about it goes too.
+pub fn remove(
+    store: &WorkspaceStore,
+    elevation: &ElevationStore,
+    id: &str,
+) -> Result<PathBuf, String> {
+    let (path, _) = store.load_workspace(id)?;
+    crate::elevation::require_in(elevation, &path, "removing a workspace")?;
+    store.remove_workspace(id)
+}
+
+/// Entry point for `broker workspace`.
+pub fn main(command: WorkspaceCommand) -> Result<(), String> {
+    let store = WorkspaceStore::new()?;
+    match command {
+        WorkspaceCommand::List { json } => {
+            let list = store.list_workspaces()?;
+            if json {
+                let out = serde_json::to_string_pretty(&list).map_err(|e| e.to_string())?;
+                println!("{out}");
+            } else if list.is_empty() {
+                println!("No saved workspaces");
+            } else {
+                for workspace in &list {
+                    println!("{:<42} {}", workspace.id, workspace.path.display());
+                }
+            }
+            Ok(())
         }
+        WorkspaceCommand::Add { path, json } => {
+            let saved = store.add_workspace(&path)?;
+            if json {
+                let out = serde_json::to_string_pretty(&saved).map_err(|e| e.to_string())?;
+                println!("{out}");
+            } else {
+                println!(
+                    "Saved workspace: {} (ID: {})",
+                    saved.path.display(),
+                    saved.id
+                );
+            }
+            Ok(())
+        }
+        WorkspaceCommand::Remove { id, json } => {
+            let path = remove(&store, &ElevationStore::new()?, &id)?;
+            if json {
+                let out = serde_json::to_string_pretty(&serde_json::json!({
+                    "id": id,
+                    "path": path,
+                }))
+                .map_err(|e| e.to_string())?;
+                println!("{out}");
+            } else {
+                println!("Forgot workspace {id} ({})", path.display());
+            }
+            Ok(())
+        }
+    }
+}
 
-        let content = std::fs::read_to_string(&self.store_path).map_err(|e| e.to_string())?;
-        let workspaces: std::collections::HashMap<String, serde_json::Value> =
-            serde_json::from_str(&content).map_err(|e| e.to_string())?;
+/// What the folder choosers ask.
+const TITLE: &str = "Choose a workspace folder";
+/// The error a dismissed chooser gives.
+const CANCELLED: &str = "no workspace folder was chosen";
+/// How to go on without a chooser.
+const NO_CHOOSER: &str = "pass --workspace <path> to use a folder without one";
+
+/// The saved form of a workspace chosen as `path`: the path itself.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn path_token(path: PathBuf) -> (PathBuf, String) {
+    let token = path.to_string_lossy().to_string();
+    (path, token)
+}
 
-        Ok(workspaces.keys().cloned().collect())
+/// The directory a path token names, if it is still there.
+fn restore_path(token: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(token);
+    if path.exists() && path.is_dir() {
+        Ok(path)
+    } else {
+        Err("Workspace directory no longer exists".to_string())
     }
 }
 
+/// Run a blocking dialog on a thread of its own, off the runtime the
+/// broker is running on.
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+fn on_own_thread<T: Send>(dialog: impl FnOnce() -> Result<T, String> + Send) -> Result<T, String> {
+    std::thread::scope(|scope| {
+        scope
+            .spawn(dialog)
+            .join()
+            .map_err(|_| "the folder chooser panicked".to_string())?
+    })
+}
+
+/// Make portal calls from a thread of their own, on a runtime of their own.
+#[cfg(target_os = "linux")]
+fn portal<T: Send, F: std::future::Future<Output = ashpd::Result<T>>>(
+    calls: impl FnOnce() -> F + Send,
+) -> Result<ashpd::Result<T>, String> {
+    on_own_thread(|| {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(runtime.block_on(calls()))
+    })
+}
+
+/// Picks through the XDG desktop portal's file chooser, which the desktop
+/// (GNOME, KDE, ...) shows, sandboxed or not.
+///
+/// The folder is kept in the portal's document store, and its document ID
+/// saved as `doc:<id>`: inside Flatpak or Snap confinement that is how the
+/// broker is let back into the folder on a later run. Outside one, without
+/// a document portal to keep it in, the path itself is saved.
 #[cfg(target_os = "linux")]
 pub struct LinuxPicker;
 
+#[cfg(target_os = "linux")]
+const DOCUMENT: &str = "doc:";
+
 #[cfg(target_os = "linux")]
 impl LinuxPicker {
     pub fn new() -> Self {
@@ -102,25 +443,128 @@ impl LinuxPicker {
 #[cfg(target_os = "linux")]
 impl WorkspacePicker for LinuxPicker {
     fn pick_workspace(&self) -> Result<(PathBuf, String), String> {
-        // For now, use current directory as fallback
-        let path = std::env::current_dir().map_err(|e| e.to_string())?;
-        let token = path.to_string_lossy().to_string();
-        Ok((path, token))
+        use ashpd::desktop::file_chooser::SelectedFiles;
+        use ashpd::desktop::ResponseError;
+
+        let chosen = portal(|| async {
+            SelectedFiles::open_file()
+                .title(TITLE)
+                .accept_label("Use Folder")
+                .directory(true)
+                .modal(true)
+                .send()
+                .await?
+                .response()
+        })?
+        .map_err(|e| match e {
+            ashpd::Error::Response(ResponseError::Cancelled) => CANCELLED.to_string(),
+            e => format!("cannot open the folder chooser ({e}); {NO_CHOOSER}"),
+        })?;
+        let uri = chosen.uris().first().ok_or(CANCELLED)?;
+        let path = uri
+            .to_file_path()
+            .map_err(|()| format!("{uri}: not a local folder"))?;
+        let (sandboxed, exported) =
+            portal(|| async { Ok((ashpd::is_sandboxed().await, documents::export(&path).await)) })?
+                .map_err(|e| e.to_string())?;
+        match exported {
+            Ok(id) => Ok((path, format!("{DOCUMENT}{id}"))),
+            Err(e) if sandboxed => Err(format!(
+                "{}: cannot keep access to it through the document portal: {e}",
+                path.display()
+            )),
+            Err(_) => Ok(path_token(path)),
+        }
     }
 
     fn restore_workspace(&self, token: &str) -> Result<PathBuf, String> {
-        let path = PathBuf::from(token);
-        if path.exists() && path.is_dir() {
-            Ok(path)
-        } else {
-            Err("Workspace directory no longer exists".to_string())
+        let Some(id) = token.strip_prefix(DOCUMENT) else {
+            return restore_path(token);
+        };
+        portal(|| documents::open(id))?
+            .map_err(|e| format!("cannot restore access through the document portal ({e})"))
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod documents {
+    use std::os::fd::AsFd;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::{Component, Path, PathBuf};
+
+    use ashpd::documents::{DocumentFlags, Documents};
+
+    /// Keep the directory at `path` in the document store for good,
+    /// returning its document ID.
+    pub async fn export(path: &Path) -> ashpd::Result<String> {
+        let documents = Documents::new().await?;
+        // Chosen from inside a sandbox, it is in the store already, and the
+        // path is in the store's own mount.
+        let mount = documents.mount_point().await?;
+        if let Ok(rest) = path.strip_prefix(mount.as_ref()) {
+            if let Some(Component::Normal(id)) = rest.components().next() {
+                return Ok(id.to_string_lossy().into_owned());
+            }
         }
+        let dir = std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_PATH | libc::O_DIRECTORY)
+            .open(path)?;
+        let (ids, _) = documents
+            .add_full(
+                &[&dir.as_fd()],
+                DocumentFlags::ReuseExisting
+                    | DocumentFlags::Persistent
+                    | DocumentFlags::ExportDirectory,
+                None,
+                &[],
+            )
+            .await?;
+        ids.into_iter()
+            .next()
+            .map(String::from)
+            .ok_or(ashpd::Error::NoResponse)
+    }
+
+    /// Where the directory kept as `id` can be reached: its own path, or
+    /// inside a sandbox, which may not ask for that, its place in the
+    /// store's mount.
+    pub async fn open(id: &str) -> ashpd::Result<PathBuf> {
+        let documents = Documents::new().await?;
+        if let Ok((path, _)) = documents.info(id).await {
+            let path = path.as_ref().to_path_buf();
+            return if path.is_dir() {
+                Ok(path)
+            } else {
+                Err(std::io::Error::from(std::io::ErrorKind::NotFound).into())
+            };
+        }
+        // An exported directory is the one entry in its document's
+        // directory there.
+        let dir = Path::new(documents.mount_point().await?.as_ref()).join(id);
+        let entry = std::fs::read_dir(dir)?
+            .next()
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))??;
+        Ok(entry.path())
     }
 }
 
+/// Picks through the shell's folder dialog (`IFileOpenDialog`).
+///
+/// A packaged broker keeps the folder in its `FutureAccessList`, which
+/// follows it through renames and moves, and saves the list's token as
+/// `fal:<token>`. Unpackaged, it has no list, and saves the folder's
+/// canonical `\\?\` path, safe past `MAX_PATH`, as `path:<path>`. Either way
+/// a restore opens the folder for listing and adding files, so the
+/// workspace is only restored while the folder's ACL still grants that.
 #[cfg(target_os = "windows")]
 pub struct WindowsPicker;
 
+#[cfg(target_os = "windows")]
+const FUTURE_ACCESS: &str = "fal:";
+#[cfg(target_os = "windows")]
+const CANONICAL: &str = "path:";
+
 #[cfg(target_os = "windows")]
 impl WindowsPicker {
     pub fn new() -> Self {
@@ -131,22 +575,106 @@ impl WindowsPicker {
 #[cfg(target_os = "windows")]
 impl WorkspacePicker for WindowsPicker {
     fn pick_workspace(&self) -> Result<(PathBuf, String), String> {
-        // For now, use current directory as fallback
-        let path = std::env::current_dir().map_err(|e| e.to_string())?;
-        let token = path.to_string_lossy().to_string();
+        // The dialog's thread is a COM apartment of its own.
+        let path = on_own_thread(|| match crate::sandbox::os::choose_folder(TITLE) {
+            Ok(Some(path)) => Ok(path),
+            Ok(None) => Err(CANCELLED.to_string()),
+            Err(e) => Err(format!(
+                "cannot open the folder chooser ({e}); {NO_CHOOSER}"
+            )),
+        })?;
+        let chosen = PathBuf::from(path);
+        let path = chosen
+            .canonicalize()
+            .map_err(|e| format!("{}: {e}", chosen.display()))?;
+        access::check(&path)?;
+        let token = match access::remember(&chosen) {
+            Ok(token) => format!("{FUTURE_ACCESS}{token}"),
+            // Only packaged apps have a future-access list.
+            Err(_) => format!("{CANONICAL}{}", path.to_string_lossy()),
+        };
         Ok((path, token))
     }
 
     fn restore_workspace(&self, token: &str) -> Result<PathBuf, String> {
-        let path = PathBuf::from(token);
-        if path.exists() && path.is_dir() {
-            Ok(path)
+        let (path, canonical) = if let Some(token) = token.strip_prefix(FUTURE_ACCESS) {
+            let path = access::recall(token)?;
+            let canonical = path
+                .canonicalize()
+                .map_err(|e| format!("{}: {e}", path.display()))?;
+            (canonical.clone(), canonical)
+        } else if let Some(saved) = token.strip_prefix(CANONICAL) {
+            let saved = PathBuf::from(saved);
+            let canonical = restore_path(&saved.to_string_lossy())?
+                .canonicalize()
+                .map_err(|e| format!("{}: {e}", saved.display()))?;
+            // A junction or link on the way to it was changed since.
+            if canonical != saved {
+                return Err(format!(
+                    "Workspace directory now resolves to {}",
+                    canonical.display()
+                ));
+            }
+            (saved, canonical)
         } else {
-            Err("Workspace directory no longer exists".to_string())
+            // Saved as a plain path, before tokens had a scheme.
+            let path = restore_path(token)?;
+            let canonical = path
+                .canonicalize()
+                .map_err(|e| format!("{}: {e}", path.display()))?;
+            (path, canonical)
+        };
+        access::check(&canonical)?;
+        Ok(path)
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod access {
+    use std::path::{Path, PathBuf};
+
+    use windows::core::HSTRING;
+    use windows::Storage::AccessCache::StorageApplicationPermissions;
+    use windows::Storage::StorageFolder;
+    use windows::Win32::Storage::FileSystem::{
+        FILE_ADD_FILE, FILE_ADD_SUBDIRECTORY, FILE_LIST_DIRECTORY, FILE_TRAVERSE,
+    };
+
+    /// Keep the folder at `path` in the app's future-access list, returning
+    /// its token there.
+    pub fn remember(path: &Path) -> windows::core::Result<String> {
+        let folder =
+            StorageFolder::GetFolderFromPathAsync(&HSTRING::from(path.as_os_str()))?.get()?;
+        let token = StorageApplicationPermissions::FutureAccessList()?.Add(&folder)?;
+        Ok(token.to_string_lossy())
+    }
+
+    /// Where the folder the future-access list keeps as `token` is now.
+    pub fn recall(token: &str) -> Result<PathBuf, String> {
+        let gone = |e: windows::core::Error| format!("Workspace access was revoked: {e}");
+        let list = StorageApplicationPermissions::FutureAccessList().map_err(gone)?;
+        let token = HSTRING::from(token);
+        if !list.ContainsItem(&token).map_err(gone)? {
+            return Err("Workspace access was revoked".to_string());
         }
+        let folder = list
+            .GetFolderAsync(&token)
+            .and_then(|op| op.get())
+            .map_err(gone)?;
+        let path = folder.Path().map_err(gone)?;
+        Ok(PathBuf::from(path.to_string_lossy()))
+    }
+
+    /// Check that the folder at `path` can be listed and added to, which
+    /// Windows refuses unless its ACL grants it to this user.
+    pub fn check(path: &Path) -> Result<(), String> {
+        let access = FILE_LIST_DIRECTORY | FILE_ADD_FILE | FILE_ADD_SUBDIRECTORY | FILE_TRAVERSE;
+        crate::sandbox::os::try_open_directory(path, access)
+            .map_err(|e| format!("{}: no access to the workspace: {e}", path.display()))
     }
 }
 
+/// Picks through an `NSOpenPanel`.
 #[cfg(target_os = "macos")]
 pub struct MacPicker;
 
@@ -160,22 +688,21 @@ impl MacPicker {
 #[cfg(target_os = "macos")]
 impl WorkspacePicker for MacPicker {
     fn pick_workspace(&self) -> Result<(PathBuf, String), String> {
-        // For now, use current directory as fallback
-        let path = std::env::current_dir().map_err(|e| e.to_string())?;
-        let token = path.to_string_lossy().to_string();
-        Ok((path, token))
+        // The broker's runtime polls `run` on the main thread, which is the
+        // only one AppKit may be used from.
+        match crate::sandbox::os::choose_folder(TITLE) {
+            Ok(Some(path)) => Ok(path_token(PathBuf::from(path))),
+            Ok(None) => Err(CANCELLED.to_string()),
+            Err(e) => Err(format!("{e}; {NO_CHOOSER}")),
+        }
     }
 
     fn restore_workspace(&self, token: &str) -> Result<PathBuf, String> {
-        let path = PathBuf::from(token);
-        if path.exists() && path.is_dir() {
-            Ok(path)
-        } else {
-            Err("Workspace directory no longer exists".to_string())
-        }
+        restore_path(token)
     }
 }
 
+/// For platforms without a folder chooser: a workspace must be named.
 #[cfg(not(any(target_os = "linux", target_os = "windows", target_os = "macos")))]
 pub struct FallbackPicker;
 
@@ -189,18 +716,11 @@ impl FallbackPicker {
 #[cfg(not(any(target_os = "linux", target_os = "windows", target_os = "macos")))]
 impl WorkspacePicker for FallbackPicker {
     fn pick_workspace(&self) -> Result<(PathBuf, String), String> {
-        let path = std::env::current_dir().map_err(|e| e.to_string())?;
-        let token = path.to_string_lossy().to_string();
-        Ok((path, token))
+        Err(format!("no folder chooser on this platform; {NO_CHOOSER}"))
     }
 
     fn restore_workspace(&self, token: &str) -> Result<PathBuf, String> {
-        let path = PathBuf::from(token);
-        if path.exists() && path.is_dir() {
-            Ok(path)
-        } else {
-            Err("Workspace directory no longer exists".to_string())
-        }
+        restore_path(token)
     }
 }
 
@@ -223,3 +743,71 @@ pub fn create_picker() -> Box<dyn WorkspacePicker + Send + Sync> {
         Box::new(FallbackPicker::new())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_store_is_sealed_and_a_clear_one_is_migrated() {
+        let dir = std::env::temp_dir().join(format!("saf-workspaces-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let store_path = dir.join("workspaces.json");
+        let store = || WorkspaceStore::at(store_path.clone(), SecretStore::at(dir.join("secrets")));
+
+        // As an older broker left it: in the clear, readable by others.
+        std::fs::write(
+            &store_path,
+            r#"{"workspace_a": {"path": "/home/u/notes", "token": "/home/u/notes", "created": 1}}"#,
+        )
+        .unwrap();
+        let list = store().list_workspaces().unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].path, PathBuf::from("/home/u/notes"));
+        let sealed = std::fs::read_to_string(&store_path).unwrap();
+        assert!(sealed.contains("ciphertext"), "{sealed}");
+        assert!(!sealed.contains("notes"), "{sealed}");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&store_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        store()
+            .save_workspace("workspace_b", Path::new("/srv/b"), "doc:1a2b")
+            .unwrap();
+        assert_eq!(
+            store().load_workspace("workspace_b").unwrap(),
+            (PathBuf::from("/srv/b"), "doc:1a2b".to_string())
+        );
+        assert_eq!(
+            store().remove_workspace("workspace_a").unwrap(),
+            PathBuf::from("/home/u/notes")
+        );
+        assert_eq!(store().list_workspaces().unwrap().len(), 1);
+
+        // Tampered with, or without its key, it does not open.
+        let mut tampered: Sealed =
+            serde_json::from_slice(&std::fs::read(&store_path).unwrap()).unwrap();
+        tampered.ciphertext = BASE64.encode(b"not the store");
+        std::fs::write(&store_path, serde_json::to_vec(&tampered).unwrap()).unwrap();
+        assert!(store().list_workspaces().is_err());
+
+        // Migration is one-shot: a clear store written after sealing is
+        // refused, and left as it is.
+        let clear = r#"{"workspace_c": {"path": "/tmp/evil", "token": "/tmp/evil", "created": 2}}"#;
+        std::fs::write(&store_path, clear).unwrap();
+        let err = store().list_workspaces().unwrap_err();
+        assert!(err.contains("not a sealed workspace store"), "{err}");
+        assert!(store().load_workspace("workspace_c").is_err());
+        assert_eq!(std::fs::read_to_string(&store_path).unwrap(), clear);
+
+        std::fs::write(&store_path, serde_json::to_vec(&tampered).unwrap()).unwrap();
+        std::fs::remove_dir_all(dir.join("secrets")).unwrap();
+        let err = store().list_workspaces().unwrap_err();
+        assert!(err.contains("is missing"), "{err}");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,330 @@
+#![forbid(unsafe_code)]
+
+//! Stable, machine-readable codes shared by every SAF crate.
+//!
+//! Errors, audit event names, policy decision reasons and UI error events all
+//! key off a [`Code`]. The string form (`fs.read_text`, `policy.domain_not_allowed`)
+//! is part of the public contract: never change an existing string, only add
+//! new codes.
+
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Info,
+    Warn,
+    Error,
+    Security,
+}
+
+impl Severity {
+    pub const ALL: &'static [Severity] = &[Self::Info, Self::Warn, Self::Error, Self::Security];
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Warn => "warn",
+            Self::Error => "error",
+            Self::Security => "security",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Severity> {
+        Self::ALL.iter().copied().find(|v| v.as_str() == s)
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What a code is about, from its prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Category {
+    /// `fs.*`
+    Fs,
+    /// `net.*` and `ws.*`
+    Net,
+    /// `policy.*` and `auth.*`
+    Policy,
+    /// Everything else: the broker, components, the audit log itself.
+    Lifecycle,
+}
+
+impl Category {
+    pub const ALL: &'static [Category] = &[Self::Fs, Self::Net, Self::Policy, Self::Lifecycle];
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Fs => "fs",
+            Self::Net => "net",
+            Self::Policy => "policy",
+            Self::Lifecycle => "lifecycle",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Category> {
+        Self::ALL.iter().copied().find(|v| v.as_str() == s)
+    }
+
+    /// The category of a code string, known or not.
+    pub fn of(code: &str) -> Category {
+        match code.split_once('.').map_or(code, |(prefix, _)| prefix) {
+            "fs" => Self::Fs,
+            "net" | "ws" => Self::Net,
+            "policy" | "auth" => Self::Policy,
+            _ => Self::Lifecycle,
+        }
+    }
+}
+
+impl Display for Category {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+macro_rules! codes {
+    ($($(#[$meta:meta])* $variant:ident => $code:literal, $severity:ident;)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Code {
+            $($(#[$meta])* $variant,)*
+        }
+
+        impl Code {
+            pub const ALL: &'static [Code] = &[$(Code::$variant,)*];
+
+            pub const fn as_str(self) -> &'static str {
+                match self {
+                    $(Code::$variant => $code,)*
+                }
+            }
+
+            pub const fn severity(self) -> Severity {
+                match self {
+                    $(Code::$variant => Severity::$severity,)*
+                }
+            }
+        }
+    };
+}
+
+codes! {
+    // Lifecycle
+    BrokerStart => "broker.start", Info;
+    /// The broker was asked to shut down while the run was going.
+    BrokerShutdown => "broker.shutdown", Warn;
+    ComponentStart => "component.start", Info;
+    ComponentFinish => "component.finish", Info;
+    /// What a run used: fuel, peak memory, host calls and bytes moved.
+    ComponentResources => "component.resources", Info;
+    /// A run's host calls were recorded to a trace, or answered from one.
+    ComponentTrace => "component.trace", Info;
+    /// A run was interrupted for taking longer than its timeout.
+    ComponentTimedOut => "component.timed_out", Error;
+    /// A strict run was ended at the component's first denial.
+    ComponentStrictViolation => "component.strict_violation", Security;
+    /// The process an isolated run was in exited before the run ended.
+    ComponentCrashed => "component.crashed", Error;
+    /// The layers of operating system confinement an isolated run's
+    /// process is under, and any that could not be applied.
+    ComponentSandboxed => "component.sandboxed", Security;
+    /// A component's capability manifest was granted; records the
+    /// interfaces linked.
+    ComponentCapabilities => "component.capabilities", Security;
+    /// A capability manifest asked for more than the policy grants, or
+    /// left out an interface the component imports.
+    ComponentCapabilitiesUnsatisfied => "component.capabilities_unsatisfied", Security;
+    /// Component output audited because the run has no log of its own.
+    ComponentLog => "component.log", Info;
+    /// A line a component wrote to its stdout or stderr.
+    ComponentOutput => "component.output", Info;
+    /// `broker serve` started a run on a schedule.
+    ScheduledRun => "schedule.run", Info;
+    /// A run is a stage of `broker pipeline run`.
+    PipelineStage => "pipeline.stage", Info;
+    /// A rotated audit log's successor starts with a link to it.
+    AuditLinked => "audit.linked", Info;
+    /// The chain hash was recorded and anchored outside the log.
+    AuditCheckpoint => "audit.checkpoint", Info;
+    /// Old segments of the audit log were replaced by a summary.
+    AuditPruned => "audit.pruned", Info;
+    /// Append-only protection could not be set again on the active file
+    /// after a rotation; the file stays writable until the next rotation.
+    AuditUnprotected => "audit.unprotected", Security;
+    /// The audit log continues one handed off from another machine.
+    AuditMigrated => "audit.migrated", Security;
+    /// `broker demo` populated a throwaway workspace.
+    DemoCreated => "demo.created", Info;
+
+    // Filesystem
+    FsListDir => "fs.list_dir", Info;
+    FsReadText => "fs.read_text", Info;
+    FsWriteText => "fs.write_text", Info;
+    FsAppend => "fs.append", Info;
+    /// A directory was mounted into the workspace under a name.
+    FsMount => "fs.mount", Info;
+    /// Path was absolute, escaped the workspace, or was otherwise malformed.
+    FsInvalidPath => "fs.invalid_path", Warn;
+    FsFailed => "fs.failed", Error;
+
+    // Network
+    NetGetText => "net.get_text", Info;
+    /// A mirror rewrite rule changed the request URL.
+    NetRewritten => "net.rewritten", Info;
+    NetFailed => "net.failed", Error;
+    /// A hostname was resolved; records the resolver used and the pinned address.
+    NetResolved => "net.resolved", Info;
+    /// A client certificate was loaded for mutual TLS with a domain.
+    NetClientCert => "net.client_cert", Info;
+    NetClientCertFailed => "net.client_cert_failed", Error;
+    /// Per-domain request rate exceeded; the request was not sent.
+    NetRateLimited => "net.rate_limited", Warn;
+    /// The broker has no transport for this request.
+    NetUnavailable => "net.unavailable", Warn;
+    /// Offline mode is on; no network access is attempted.
+    NetOffline => "net.offline", Warn;
+    NetStreamOpen => "net.stream_open", Info;
+    NetStreamClose => "net.stream_close", Info;
+    WsConnect => "ws.connect", Info;
+    WsClose => "ws.close", Info;
+    /// Messages are audited only when sending or receiving one fails.
+    WsSend => "ws.send", Info;
+    WsReceive => "ws.receive", Info;
+    WsClosedByPeer => "ws.closed_by_peer", Info;
+    WsUnknownConnection => "ws.unknown_connection", Warn;
+
+    // Policy decisions and changes
+    PolicyDomainNotAllowed => "policy.domain_not_allowed", Security;
+    /// Host matched a `denied_domains` rule.
+    PolicyDomainDenied => "policy.domain_denied", Security;
+    /// Domain allowed, but no `url_rules` entry admits the path and method.
+    PolicyRequestNotAllowed => "policy.request_not_allowed", Security;
+    /// The host has `origins` entries and none admits the URL's scheme and
+    /// port.
+    PolicyOriginNotAllowed => "policy.origin_not_allowed", Security;
+    PolicyPathNotAllowed => "policy.path_not_allowed", Security;
+    /// Path matched a `deny` rule.
+    PolicyPathDenied => "policy.path_denied", Security;
+    /// Write to a path matched by a `read_only` rule, or outside `writable`
+    /// under `writable_only`.
+    PolicyPathReadOnly => "policy.path_read_only", Security;
+    PolicySizeLimit => "policy.size_limit", Security;
+    /// A `conditions` rule's `require` expression did not hold, or an
+    /// expression could not be evaluated.
+    PolicyConditionFailed => "policy.condition_failed", Security;
+    /// The run used up its network byte budget.
+    PolicyNetBudget => "policy.net_budget", Security;
+    /// The run used up an operation-count budget from `budgets`.
+    PolicyBudgetExceeded => "policy.budget_exceeded", Security;
+    /// A stream or connection outlived the duration allowed by policy.
+    PolicyTimeLimit => "policy.time_limit", Security;
+    /// The run burned all the fuel `max_fuel` allows and was trapped.
+    PolicyFuelExhausted => "policy.fuel_exhausted", Security;
+    /// The run hit one of the policy's `memory` limits and was trapped.
+    PolicyMemoryLimit => "policy.memory_limit", Security;
+    /// Host resolved to a private, loopback or otherwise denied address.
+    PolicyIpDenied => "policy.ip_denied", Security;
+    PolicyTrialStart => "policy.trial_start", Security;
+    PolicyTrialComplete => "policy.trial_complete", Info;
+    PolicyNarrowed => "policy.narrowed", Security;
+    /// Neither allowed nor denied: the user must be asked. Hosts resolve
+    /// this before it reaches a component.
+    PolicyAsk => "policy.ask", Security;
+    /// The user answered a permission prompt, or a remembered answer was
+    /// applied.
+    PolicyAskAnswered => "policy.ask_answered", Security;
+    /// The user refused a permission prompt.
+    PolicyAskDeclined => "policy.ask_declined", Security;
+    /// A policy file's signature verified against a trusted key.
+    PolicySignatureVerified => "policy.signature_verified", Security;
+    /// A policy file was refused: its signature did not verify, or it was
+    /// unsigned while signatures are required.
+    PolicySignatureRejected => "policy.signature_rejected", Security;
+    /// Dry-run mode let through an operation policy would have denied.
+    PolicyWouldDeny => "policy.would_deny", Warn;
+    /// Policy read from a file rather than built in.
+    PolicyLoaded => "policy.loaded", Security;
+    /// Policy chosen for a component by its identity, or the restrictive default.
+    PolicySelected => "policy.selected", Security;
+    /// The policy file changed and the new policy replaced the running one.
+    PolicyReloaded => "policy.reloaded", Security;
+    /// The policy file changed but failed to parse or validate; the running
+    /// policy stays in force.
+    PolicyReloadFailed => "policy.reload_failed", Security;
+
+    // System info
+    /// A component read an identifying sysinfo field it was granted.
+    SysinfoRead => "sysinfo.read", Info;
+    PolicySysinfoNotGranted => "policy.sysinfo_not_granted", Security;
+
+    // Config values
+    /// A component read a config value its policy grants.
+    ConfigRead => "config.read", Info;
+    PolicyConfigNotAllowed => "policy.config_not_allowed", Security;
+
+    // Elevation
+    /// The user re-authenticated and opened an elevated session.
+    AuthElevated => "auth.elevated", Security;
+    AuthElevationEnded => "auth.elevation_ended", Security;
+    /// A maintenance operation was attempted without an elevated session.
+    AuthElevationRequired => "auth.elevation_required", Security;
+
+    // UI
+    UiFailed => "ui.failed", Error;
+}
+
+impl Code {
+    pub fn parse(s: &str) -> Option<Code> {
+        Self::ALL.iter().copied().find(|c| c.as_str() == s)
+    }
+
+    pub fn category(self) -> Category {
+        Category::of(self.as_str())
+    }
+
+    /// Render `"<code>: <message>"`, the form hosts use for error strings so
+    /// the code survives being passed around as a plain `String`.
+    pub fn with_message(self, message: &str) -> String {
+        format!("{}: {}", self.as_str(), message)
+    }
+
+    /// Recover the code from a string produced by [`Code::with_message`].
+    pub fn from_message(message: &str) -> Option<Code> {
+        let (code, _) = message.split_once(": ")?;
+        Self::parse(code)
+    }
+}
+
+impl Display for Code {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn codes_are_unique_and_round_trip() {
+        let mut seen = HashSet::new();
+        for code in Code::ALL {
+            assert!(seen.insert(code.as_str()), "duplicate code {code}");
+            assert_eq!(Code::parse(code.as_str()), Some(*code));
+        }
+        let msg = Code::PolicyDomainNotAllowed.with_message("blocked by policy");
+        assert_eq!(Code::from_message(&msg), Some(Code::PolicyDomainNotAllowed));
+        assert_eq!(Code::from_message("plain error"), None);
+        assert_eq!(Code::WsConnect.category(), Category::Net);
+        assert_eq!(Code::AuthElevationRequired.category(), Category::Policy);
+        assert_eq!(Category::of("demo.created"), Category::Lifecycle);
+        for category in Category::ALL {
+            assert_eq!(Category::parse(category.as_str()), Some(*category));
+        }
+        assert_eq!(Severity::parse("security"), Some(Severity::Security));
+    }
+}
@@ -14,4 +14,4 @@ impl Guest for Component {
     }
 }
 
-bindings::__export_world_app_cabi!(Component with_types_in bindings);
+bindings::export!(Component with_types_in bindings);
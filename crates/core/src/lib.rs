@@ -1,9 +1,17 @@
 #![forbid(unsafe_code)]
 
-// Collections used within tests; keep non-test code minimal.
+pub use saf_audit::{AuditEvent, AuditRecord, ComponentIdentity, Outcome};
+pub use saf_codes::{Category, Code, Severity};
+use saf_policy::expr::Attributes;
+pub use saf_policy::Denial;
+use saf_policy::{FsAccess, Redaction, SharedPolicy};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::path::{Component, Path};
+use std::sync::atomic::{AtomicU64, Ordering};
+pub use text::{clamp_utf8, TextRange, Utf8Chunks, Utf8Decoder};
+
+pub mod text;
 
 // -----------------------------
 // Errors & Results
@@ -12,16 +20,60 @@ use std::path::{Component, Path};
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CoreError {
     InvalidPath,
+    /// A `deny`, `denied_paths`, `read_only` or size rule refused the
+    /// access; `code` says which.
+    Denied {
+        path: String,
+        code: Code,
+    },
     Fs(String),
     Net(String),
+    RateLimited {
+        domain: String,
+        retry_after_ms: u64,
+    },
+    Offline,
+    /// An operation-count budget from the policy's `budgets` ran out.
+    BudgetExceeded {
+        budget: Budget,
+        limit: u64,
+    },
 }
 
 impl Display for CoreError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::InvalidPath => write!(f, "invalid or unsafe path"),
+            Self::Denied { path, code } => write!(f, "{path}: denied by policy ({code})"),
             Self::Fs(msg) => write!(f, "fs error: {msg}"),
             Self::Net(msg) => write!(f, "net error: {msg}"),
+            Self::RateLimited {
+                domain,
+                retry_after_ms,
+            } => write!(
+                f,
+                "rate limited for {domain}, retry after {retry_after_ms} ms"
+            ),
+            Self::Offline => write!(f, "network access is disabled (offline mode)"),
+            Self::BudgetExceeded { budget, limit } => {
+                write!(f, "budget exceeded: at most {limit} {budget} per run")
+            }
+        }
+    }
+}
+
+impl CoreError {
+    /// Stable code for this error. Host error strings built with
+    /// [`Code::with_message`] keep their original code.
+    pub fn code(&self) -> Code {
+        match self {
+            Self::InvalidPath => Code::FsInvalidPath,
+            Self::Denied { code, .. } => *code,
+            Self::Fs(msg) => Code::from_message(msg).unwrap_or(Code::FsFailed),
+            Self::Net(msg) => Code::from_message(msg).unwrap_or(Code::NetFailed),
+            Self::RateLimited { .. } => Code::NetRateLimited,
+            Self::Offline => Code::NetOffline,
+            Self::BudgetExceeded { .. } => Code::PolicyBudgetExceeded,
         }
     }
 }
@@ -37,28 +89,483 @@ pub type CoreResult<T> = Result<T, CoreError>;
 pub trait FsHost: Send + Sync {
     fn list_dir(&self, path: &str) -> Result<Vec<String>, String>;
     fn read_text(&self, path: &str) -> Result<String, String>;
+    /// Up to `len` raw bytes starting at byte `offset`.
+    fn read_range(&self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>, String> {
+        let text = self.read_text(path)?;
+        let bytes = text.as_bytes();
+        let start = usize::try_from(offset)
+            .unwrap_or(usize::MAX)
+            .min(bytes.len());
+        let end = start.saturating_add(usize::try_from(len).unwrap_or(usize::MAX));
+        Ok(bytes[start..end.min(bytes.len())].to_vec())
+    }
     fn write_text(&self, path: &str, content: &str) -> Result<(), String>;
+    /// Append to a file (creating it if missing) without rewriting existing contents.
+    fn append_text(&self, path: &str, content: &str) -> Result<(), String> {
+        self.append_bytes(path, content.as_bytes())
+    }
+    fn append_bytes(&self, path: &str, content: &[u8]) -> Result<(), String>;
+}
+
+/// Failure of a network host call, typed where components can react to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetError {
+    /// Too many requests to `domain`; the call may be retried after the delay.
+    RateLimited {
+        domain: String,
+        retry_after_ms: u64,
+    },
+    /// Offline mode is on; the request was never attempted.
+    Offline,
+    Failed(String),
+}
+
+impl From<String> for NetError {
+    fn from(msg: String) -> Self {
+        Self::Failed(msg)
+    }
+}
+
+impl From<NetError> for CoreError {
+    fn from(e: NetError) -> Self {
+        match e {
+            NetError::RateLimited {
+                domain,
+                retry_after_ms,
+            } => Self::RateLimited {
+                domain,
+                retry_after_ms,
+            },
+            NetError::Offline => Self::Offline,
+            NetError::Failed(msg) => Self::Net(msg),
+        }
+    }
+}
+
+/// An HTTP response as seen by components. Header names are kept as sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// First value of the header `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
 }
 
 pub trait NetHost: Send + Sync {
-    fn get_text(&self, url: &str) -> Result<String, String>;
+    fn fetch(&self, url: &str) -> Result<HttpResponse, NetError>;
+    /// URL the host will actually contact for `url`, after mirror rewriting.
+    fn effective_url(&self, url: &str) -> String {
+        url.to_string()
+    }
+    /// Whether the host rewrites URLs (e.g. to an internal mirror).
+    fn rewriting_active(&self) -> bool {
+        false
+    }
+    /// Open a streaming GET (SSE, chunked) and return a host-assigned handle.
+    fn open_stream(&self, _url: &str) -> Result<u64, NetError> {
+        Err(NetError::Failed(
+            Code::NetUnavailable.with_message("streaming not supported by this host"),
+        ))
+    }
+    /// Next body chunk, or `None` once the stream has ended.
+    fn next_chunk(&self, _stream: u64) -> Result<Option<Vec<u8>>, NetError> {
+        Err(NetError::Failed(
+            Code::NetFailed.with_message("no such stream"),
+        ))
+    }
+    /// Close the stream and return the total bytes it delivered.
+    fn close_stream(&self, _stream: u64) -> Result<u64, NetError> {
+        Err(NetError::Failed(
+            Code::NetFailed.with_message("no such stream"),
+        ))
+    }
+}
+
+/// WebSocket connections addressed by host-assigned handles.
+pub trait WsHost: Send + Sync {
+    fn connect(&self, url: &str) -> Result<u64, String>;
+    fn send(&self, conn: u64, message: &str) -> Result<(), String>;
+    /// Next message on the connection, or `None` once the peer has closed it.
+    /// A host with no message to give on an open connection returns an
+    /// error, never `None`.
+    fn receive(&self, conn: u64) -> Result<Option<String>, String>;
+    fn close(&self, conn: u64) -> Result<(), String>;
 }
 
 pub trait LogHost: Send + Sync {
-    fn event(&self, message: &str);
+    fn record(&self, record: AuditRecord);
+
+    /// Record a denied operation.
+    fn violation(&self, violation: &Violation<'_>) {
+        self.record(violation.record(Outcome::Denied));
+    }
+
+    /// Record an operation that was let through in dry-run mode but would
+    /// otherwise have been denied.
+    fn would_deny(&self, violation: &Violation<'_>) {
+        self.record(violation.record(Outcome::WouldDeny));
+    }
+}
+
+/// A permission question raised by an `ask` rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PermissionRequest<'a> {
+    pub component: &'a str,
+    pub capability: Capability,
+    /// Host or workspace path the answer will cover.
+    pub subject: &'a str,
+    pub rule_id: &'a str,
+}
+
+/// The user's answer and how long it is remembered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptAnswer {
+    AllowSession,
+    AllowAlways,
+    DenySession,
+    DenyAlways,
+}
+
+impl PromptAnswer {
+    pub const ALL: &'static [PromptAnswer] = &[
+        Self::AllowSession,
+        Self::AllowAlways,
+        Self::DenySession,
+        Self::DenyAlways,
+    ];
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::AllowSession => "allow_session",
+            Self::AllowAlways => "allow_always",
+            Self::DenySession => "deny_session",
+            Self::DenyAlways => "deny_always",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|a| a.as_str() == s)
+    }
+
+    pub const fn allows(self) -> bool {
+        matches!(self, Self::AllowSession | Self::AllowAlways)
+    }
+
+    /// Whether the answer outlives the current run.
+    pub const fn is_permanent(self) -> bool {
+        matches!(self, Self::AllowAlways | Self::DenyAlways)
+    }
+}
+
+/// Puts permission questions to the user: a terminal prompt for headless
+/// runs, a dialog in the UI.
+pub trait PromptHost: Send + Sync {
+    fn ask(&self, request: &PermissionRequest<'_>) -> PromptAnswer;
+}
+
+/// Host capability an operation was attempted through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Fs,
+    Net,
+    Ws,
+    Sysinfo,
+    Config,
+}
+
+impl Capability {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Fs => "fs",
+            Self::Net => "net",
+            Self::Ws => "ws",
+            Self::Sysinfo => "sysinfo",
+            Self::Config => "config",
+        }
+    }
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A denied operation and the rule that denied it. Audited as an
+/// [`AuditEvent::PolicyDenied`] with the denial's code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Violation<'a> {
+    pub code: Code,
+    pub capability: Capability,
+    /// Path, URL, host or field the operation was aimed at.
+    pub target: &'a str,
+    /// Rule that decided, as reported by [`Denial::rule_id`].
+    pub rule_id: &'a str,
+    pub component: &'a ComponentIdentity,
+}
+
+impl<'a> Violation<'a> {
+    pub fn new(
+        denial: &'a Denial,
+        capability: Capability,
+        target: &'a str,
+        component: &'a ComponentIdentity,
+    ) -> Self {
+        Self {
+            code: denial.code,
+            capability,
+            target,
+            rule_id: &denial.rule_id,
+            component,
+        }
+    }
+}
+
+impl Violation<'_> {
+    pub fn record(&self, outcome: Outcome) -> AuditRecord {
+        AuditRecord::new(
+            self.code,
+            AuditEvent::PolicyDenied {
+                capability: self.capability.to_string(),
+                target: self.target.to_string(),
+                rule: self.rule_id.to_string(),
+            },
+        )
+        .with_outcome(outcome)
+        .with_identity(self.component)
+    }
+}
+
+impl Display for Violation<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} capability={} target={} rule={} component={}",
+            self.code, self.capability, self.target, self.rule_id, self.component.name
+        )
+    }
+}
+
+/// A per-run operation budget, named as in the policy's `budgets`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Budget {
+    FsReads,
+    FsWrites,
+    NetRequests,
+    WsMessages,
+}
+
+impl Budget {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::FsReads => "fs_reads",
+            Self::FsWrites => "fs_writes",
+            Self::NetRequests => "net_requests",
+            Self::WsMessages => "ws_messages",
+        }
+    }
+
+    const fn capability(self) -> Capability {
+        match self {
+            Self::FsReads | Self::FsWrites => Capability::Fs,
+            Self::NetRequests => Capability::Net,
+            Self::WsMessages => Capability::Ws,
+        }
+    }
+}
+
+impl Display for Budget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Operations performed so far in a run, counted against [`Budget`]s, and
+/// the bytes they moved.
+#[derive(Debug, Default)]
+pub struct Usage {
+    fs_reads: AtomicU64,
+    fs_writes: AtomicU64,
+    net_requests: AtomicU64,
+    ws_messages: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    bytes_fetched: AtomicU64,
+}
+
+/// Bytes a run has moved through its hosts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Transfer {
+    /// Read from the workspace.
+    pub read: u64,
+    /// Written to the workspace.
+    pub written: u64,
+    /// Received from the network: response bodies, stream chunks and
+    /// WebSocket messages.
+    pub fetched: u64,
+}
+
+impl Usage {
+    fn counter(&self, budget: Budget) -> &AtomicU64 {
+        match budget {
+            Budget::FsReads => &self.fs_reads,
+            Budget::FsWrites => &self.fs_writes,
+            Budget::NetRequests => &self.net_requests,
+            Budget::WsMessages => &self.ws_messages,
+        }
+    }
+
+    pub fn get(&self, budget: Budget) -> u64 {
+        self.counter(budget).load(Ordering::Relaxed)
+    }
+
+    /// Count `n` operations done elsewhere for this run, such as in the
+    /// process it was isolated in.
+    pub fn add(&self, budget: Budget, n: u64) {
+        self.counter(budget).fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn transfer(&self) -> Transfer {
+        Transfer {
+            read: self.bytes_read.load(Ordering::Relaxed),
+            written: self.bytes_written.load(Ordering::Relaxed),
+            fetched: self.bytes_fetched.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Count bytes moved elsewhere for this run, as [`Usage::add`] does
+    /// operations.
+    pub fn add_transfer(&self, transfer: Transfer) {
+        self.bytes_read.fetch_add(transfer.read, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(transfer.written, Ordering::Relaxed);
+        self.bytes_fetched
+            .fetch_add(transfer.fetched, Ordering::Relaxed);
+    }
+
+    fn read(&self, bytes: usize) {
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn written(&self, bytes: usize) {
+        self.bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn fetched(&self, bytes: usize) {
+        self.bytes_fetched
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
 }
 
 #[derive(Clone)]
 pub struct Context<'a> {
     pub fs: &'a dyn FsHost,
     pub net: &'a dyn NetHost,
+    pub ws: &'a dyn WsHost,
     pub log: &'a dyn LogHost,
+    /// Filesystem rules are enforced here, before any host call. The policy
+    /// may be replaced between calls.
+    pub policy: &'a SharedPolicy,
+    /// Who every audited operation is attributed to.
+    pub component: &'a ComponentIdentity,
+    /// Audit policy denials as `policy.would_deny` and let the operation
+    /// proceed, for trying out a policy. Limits are still enforced.
+    pub dry_run: bool,
+    /// Counts against the policy's `budgets` for this run.
+    pub usage: &'a Usage,
+}
+
+impl Context<'_> {
+    /// `target` as the audit log may show it.
+    pub fn audited(&self, capability: Capability, target: &str) -> String {
+        redact(&self.policy.current().redaction, capability, target)
+    }
+
+    /// Audit `event`, attributed to the running component.
+    pub fn audit(&self, code: Code, event: AuditEvent) {
+        self.log
+            .record(AuditRecord::new(code, event).with_identity(self.component));
+    }
+
+    /// Audit `denial` of an operation on `target`.
+    pub fn violation(&self, denial: &Denial, capability: Capability, target: &str) {
+        let target = self.audited(capability, target);
+        self.log
+            .violation(&Violation::new(denial, capability, &target, self.component));
+    }
+
+    /// Apply a policy decision about `target`: audit a denial and return
+    /// it, or in dry-run mode audit what would have been denied and allow.
+    pub fn enforce(
+        &self,
+        decision: Result<(), Denial>,
+        capability: Capability,
+        target: &str,
+    ) -> Result<(), Denial> {
+        match decision {
+            Err(denial) if self.dry_run => {
+                let target = self.audited(capability, target);
+                self.log.would_deny(&Violation::new(
+                    &denial,
+                    capability,
+                    &target,
+                    self.component,
+                ));
+                Ok(())
+            }
+            Err(denial) => {
+                self.violation(&denial, capability, target);
+                Err(denial)
+            }
+            Ok(()) => Ok(()),
+        }
+    }
 }
 
 // -----------------------------
 // Helpers
 // -----------------------------
 
+/// `target` of an operation through `capability` with the policy's
+/// `redaction` rules applied: paths for the filesystem, URLs for the
+/// network. Everything written to the audit log goes through this.
+pub fn redact(redaction: &Redaction, capability: Capability, target: &str) -> String {
+    if redaction.is_empty() {
+        return target.to_string();
+    }
+    match capability {
+        Capability::Fs => redaction.path(target),
+        Capability::Net | Capability::Ws => redaction.url(target),
+        Capability::Sysinfo | Capability::Config => redaction.text(target),
+    }
+}
+
+/// The workspace directory the broker keeps its own state in: the
+/// policy, the audit log, run records. No component reaches it, whatever
+/// the policy says, or it could grant itself access or rewrite its trail.
+pub const RESERVED_DIR: &str = ".saf";
+
+/// Whether `rel`, a sanitized workspace path, is [`RESERVED_DIR`] or under
+/// it. Case is ignored, as it is by the filesystems of macOS and Windows.
+pub fn is_reserved(rel: &str) -> bool {
+    let first = rel.split('/').next().unwrap_or_default();
+    first.eq_ignore_ascii_case(RESERVED_DIR)
+}
+
 fn sanitize_rel_path(path: &str) -> Option<String> {
     // Reject absolute paths and parent traversals; normalize separators.
     let p = Path::new(path);
@@ -82,44 +589,488 @@ fn sanitize_rel_path(path: &str) -> Option<String> {
     Some(parts.join("/"))
 }
 
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Sanitize `path` and apply the policy's filesystem rules and conditions
+/// to it. `size` is the number of bytes being written, if any.
+fn checked_path(
+    ctx: &Context<'_>,
+    path: &str,
+    access: FsAccess,
+    size: Option<usize>,
+) -> CoreResult<String> {
+    let rel = sanitize_rel_path(path).ok_or(CoreError::InvalidPath)?;
+    if is_reserved(&rel) {
+        // Not a policy decision: it holds in dry runs too.
+        let denial = Denial::new(Code::PolicyPathDenied, RESERVED_DIR);
+        ctx.violation(&denial, Capability::Fs, &rel);
+        return Err(CoreError::Denied {
+            path: rel,
+            code: denial.code,
+        });
+    }
+    let policy = ctx.policy.current();
+    let decision = policy.check_fs_access(&rel, access).and_then(|()| {
+        policy.check_conditions(&Attributes {
+            capability: Capability::Fs.as_str(),
+            access: Some(match access {
+                FsAccess::Read => "read",
+                FsAccess::Write => "write",
+            }),
+            path: Some(&rel),
+            size: size.map(|s| s as u64),
+            component: &ctx.component.name,
+            now: unix_now(),
+            ..Attributes::default()
+        })
+    });
+    ctx.enforce(decision, Capability::Fs, &rel)
+        .map_err(|denial| CoreError::Denied {
+            path: rel.clone(),
+            code: denial.code,
+        })?;
+    if access == FsAccess::Write && size.is_some_and(|s| s as u64 > policy.max_write_bytes) {
+        let denial = size_limit(ctx, Capability::Fs, &rel, "max_write_bytes");
+        return Err(CoreError::Denied {
+            path: rel,
+            code: denial.code,
+        });
+    }
+    Ok(rel)
+}
+
+/// Audit an operation over the size limit `rule`. Limits hold in dry runs
+/// too.
+fn size_limit(ctx: &Context<'_>, capability: Capability, target: &str, rule: &str) -> Denial {
+    let denial = Denial::new(Code::PolicySizeLimit, rule);
+    ctx.violation(&denial, capability, target);
+    denial
+}
+
+/// Count one operation against `budget`, refusing it once the policy's
+/// limit is used up.
+fn charge(ctx: &Context<'_>, budget: Budget, target: &str) -> CoreResult<()> {
+    let budgets = ctx.policy.current().budgets;
+    let limit = match budget {
+        Budget::FsReads => budgets.fs_reads,
+        Budget::FsWrites => budgets.fs_writes,
+        Budget::NetRequests => budgets.net_requests,
+        Budget::WsMessages => budgets.ws_messages,
+    };
+    let used = ctx.usage.counter(budget).fetch_add(1, Ordering::Relaxed) + 1;
+    match limit {
+        Some(limit) if used > limit => {
+            // Refused operations do not count.
+            ctx.usage.counter(budget).fetch_sub(1, Ordering::Relaxed);
+            ctx.violation(
+                &Denial::new(Code::PolicyBudgetExceeded, format!("budgets.{budget}")),
+                budget.capability(),
+                target,
+            );
+            Err(CoreError::BudgetExceeded { budget, limit })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Apply the policy's conditions to a network request before the host
+/// sees it. Hosts enforce everything else.
+fn check_net_conditions(
+    ctx: &Context<'_>,
+    capability: Capability,
+    method: Option<&str>,
+    url: &str,
+) -> CoreResult<()> {
+    let decision = ctx.policy.current().check_conditions(&Attributes {
+        capability: capability.as_str(),
+        method,
+        url: Some(url),
+        component: &ctx.component.name,
+        now: unix_now(),
+        ..Attributes::default()
+    });
+    ctx.enforce(decision, capability, url)
+        .map_err(|d| CoreError::Net(d.to_string()))
+}
+
+fn join_rel(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
 // -----------------------------
 // Public API
 // -----------------------------
+//
+// Every operation is audited with its outcome: successes where they
+// complete, denials where they are decided (here or by the host) and any
+// other failure by `operation`.
+
+/// Run `op`, an operation audited under `code` on `target`, auditing the
+/// failure if it fails.
+fn operation<T>(
+    ctx: &Context<'_>,
+    code: Code,
+    capability: Capability,
+    target: &str,
+    op: impl FnOnce() -> CoreResult<T>,
+) -> CoreResult<T> {
+    op().inspect_err(|e| failed(ctx, code, capability, target, e))
+}
+
+/// Audit the failure of `operation` on `target`, unless it was a denial,
+/// which was audited where it was decided.
+fn failed(ctx: &Context<'_>, operation: Code, capability: Capability, target: &str, e: &CoreError) {
+    match e {
+        CoreError::Denied { .. } | CoreError::BudgetExceeded { .. } => {}
+        // Reported by the host as typed errors rather than denials.
+        CoreError::Offline => offline_violation(ctx, target),
+        CoreError::RateLimited {
+            domain,
+            retry_after_ms,
+        } => ctx.audit(
+            Code::NetRateLimited,
+            AuditEvent::NetRateLimited {
+                domain: domain.clone(),
+                retry_after_ms: *retry_after_ms,
+            },
+        ),
+        // Hosts audit their own denials and return them with their code.
+        CoreError::Fs(_) | CoreError::Net(_)
+            if e.code().category() == Category::Policy || e.code() == Code::NetOffline => {}
+        CoreError::InvalidPath | CoreError::Fs(_) | CoreError::Net(_) => {
+            let audited = ctx.audited(capability, target);
+            let redaction = &ctx.policy.current().redaction;
+            // The message may name the target too.
+            let reason = redaction.text(&e.to_string().replace(target, &audited));
+            ctx.log.record(
+                AuditRecord::new(
+                    e.code(),
+                    AuditEvent::OperationFailed {
+                        capability: capability.to_string(),
+                        operation: operation.to_string(),
+                        target: audited,
+                        reason,
+                    },
+                )
+                .with_outcome(Outcome::Failed)
+                .with_identity(ctx.component),
+            );
+        }
+    }
+}
 
 pub fn list_dir(ctx: &Context<'_>, path: &str) -> CoreResult<Vec<String>> {
-    let rel = sanitize_rel_path(path).ok_or(CoreError::InvalidPath)?;
-    let mut entries = ctx.fs.list_dir(&rel).map_err(CoreError::Fs)?;
-    // Sort for stable output
-    entries.sort();
-    entries.dedup();
-    ctx.log.event(&format!("fs.list_dir path={rel}"));
-    Ok(entries)
+    operation(ctx, Code::FsListDir, Capability::Fs, path, || {
+        let rel = checked_path(ctx, path, FsAccess::Read, None)?;
+        charge(ctx, Budget::FsReads, &rel)?;
+        let mut entries = ctx.fs.list_dir(&rel).map_err(CoreError::Fs)?;
+        // Denied entries are not even named, except in a dry run where every
+        // operation goes ahead. The reserved directory never is.
+        let policy = ctx.policy.current();
+        entries.retain(|e| {
+            let path = join_rel(&rel, e);
+            !is_reserved(&path)
+                && (ctx.dry_run || policy.check_fs_access(&path, FsAccess::Read).is_ok())
+        });
+        // Sort for stable output
+        entries.sort();
+        entries.dedup();
+        ctx.audit(
+            Code::FsListDir,
+            AuditEvent::FsList {
+                path: ctx.audited(Capability::Fs, &rel),
+            },
+        );
+        Ok(entries)
+    })
 }
 
 pub fn read_text(ctx: &Context<'_>, path: &str) -> CoreResult<String> {
-    let rel = sanitize_rel_path(path).ok_or(CoreError::InvalidPath)?;
-    let text = ctx.fs.read_text(&rel).map_err(CoreError::Fs)?;
-    ctx.log
-        .event(&format!("fs.read_text path={rel} bytes={}", text.len()));
-    Ok(text)
+    operation(ctx, Code::FsReadText, Capability::Fs, path, || {
+        let rel = checked_path(ctx, path, FsAccess::Read, None)?;
+        charge(ctx, Budget::FsReads, &rel)?;
+        let text = ctx.fs.read_text(&rel).map_err(CoreError::Fs)?;
+        if text.len() as u64 > ctx.policy.current().max_read_bytes {
+            let denial = size_limit(ctx, Capability::Fs, &rel, "max_read_bytes");
+            return Err(CoreError::Denied {
+                path: rel,
+                code: denial.code,
+            });
+        }
+        ctx.usage.read(text.len());
+        ctx.audit(
+            Code::FsReadText,
+            AuditEvent::FsRead {
+                path: ctx.audited(Capability::Fs, &rel),
+                bytes: text.len() as u64,
+                offset: None,
+            },
+        );
+        Ok(text)
+    })
+}
+
+/// Read about `len` bytes of text from `offset`, clamped to character
+/// boundaries so a preview or chunked read never splits a character.
+pub fn read_text_range(
+    ctx: &Context<'_>,
+    path: &str,
+    offset: u64,
+    len: u64,
+) -> CoreResult<TextRange> {
+    operation(ctx, Code::FsReadText, Capability::Fs, path, || {
+        let rel = checked_path(ctx, path, FsAccess::Read, None)?;
+        charge(ctx, Budget::FsReads, &rel)?;
+        let len = len.min(ctx.policy.current().max_read_bytes);
+        let bytes = ctx
+            .fs
+            .read_range(&rel, offset, len)
+            .map_err(CoreError::Fs)?;
+        let range = clamp_utf8(&bytes, offset)
+            .map_err(|e| CoreError::Fs(Code::FsFailed.with_message(&e)))?;
+        ctx.usage.read(bytes.len());
+        ctx.audit(
+            Code::FsReadText,
+            AuditEvent::FsRead {
+                path: ctx.audited(Capability::Fs, &rel),
+                bytes: range.end - range.start,
+                offset: Some(range.start),
+            },
+        );
+        Ok(range)
+    })
 }
 
 pub fn write_text(ctx: &Context<'_>, path: &str, content: &str) -> CoreResult<()> {
-    let rel = sanitize_rel_path(path).ok_or(CoreError::InvalidPath)?;
-    ctx.fs.write_text(&rel, content).map_err(CoreError::Fs)?;
-    ctx.log.event(&format!(
-        "fs.write_text path={rel} bytes={}",
-        content.as_bytes().len()
-    ));
-    Ok(())
+    operation(ctx, Code::FsWriteText, Capability::Fs, path, || {
+        let rel = checked_path(ctx, path, FsAccess::Write, Some(content.len()))?;
+        charge(ctx, Budget::FsWrites, &rel)?;
+        ctx.fs.write_text(&rel, content).map_err(CoreError::Fs)?;
+        ctx.usage.written(content.len());
+        ctx.audit(
+            Code::FsWriteText,
+            AuditEvent::FsWrite {
+                path: ctx.audited(Capability::Fs, &rel),
+                bytes: content.len() as u64,
+                append: false,
+            },
+        );
+        Ok(())
+    })
+}
+
+pub fn append_text(ctx: &Context<'_>, path: &str, content: &str) -> CoreResult<()> {
+    operation(ctx, Code::FsAppend, Capability::Fs, path, || {
+        let rel = checked_path(ctx, path, FsAccess::Write, Some(content.len()))?;
+        charge(ctx, Budget::FsWrites, &rel)?;
+        ctx.fs.append_text(&rel, content).map_err(CoreError::Fs)?;
+        ctx.usage.written(content.len());
+        // Only the appended size is audited; contents may be arbitrary log data.
+        ctx.audit(
+            Code::FsAppend,
+            AuditEvent::FsWrite {
+                path: ctx.audited(Capability::Fs, &rel),
+                bytes: content.len() as u64,
+                append: true,
+            },
+        );
+        Ok(())
+    })
 }
 
+pub fn append_bytes(ctx: &Context<'_>, path: &str, content: &[u8]) -> CoreResult<()> {
+    operation(ctx, Code::FsAppend, Capability::Fs, path, || {
+        let rel = checked_path(ctx, path, FsAccess::Write, Some(content.len()))?;
+        charge(ctx, Budget::FsWrites, &rel)?;
+        ctx.fs.append_bytes(&rel, content).map_err(CoreError::Fs)?;
+        ctx.usage.written(content.len());
+        ctx.audit(
+            Code::FsAppend,
+            AuditEvent::FsWrite {
+                path: ctx.audited(Capability::Fs, &rel),
+                bytes: content.len() as u64,
+                append: true,
+            },
+        );
+        Ok(())
+    })
+}
+
+/// GET `url` and return the full response, whatever its status.
+pub fn fetch(ctx: &Context<'_>, url: &str) -> CoreResult<HttpResponse> {
+    operation(ctx, Code::NetGetText, Capability::Net, url, || {
+        // Conditions are checked here; allowlist and TLS enforcement is left
+        // to the host.
+        check_net_conditions(ctx, Capability::Net, Some("GET"), url)?;
+        charge(ctx, Budget::NetRequests, url)?;
+        let resp = ctx.net.fetch(url)?;
+        ctx.usage.fetched(resp.body.len());
+        if resp.body.len() as u64 > ctx.policy.current().max_response_bytes {
+            let denial = size_limit(ctx, Capability::Net, url, "max_response_bytes");
+            return Err(CoreError::Net(denial.to_string()));
+        }
+        ctx.audit(
+            Code::NetGetText,
+            AuditEvent::NetFetch {
+                url: ctx.audited(Capability::Net, url),
+                status: resp.status,
+                bytes: resp.body.len() as u64,
+            },
+        );
+        Ok(resp)
+    })
+}
+
+/// GET `url` and return the body; non-2xx statuses are errors, audited as
+/// the fetch's status.
 pub fn fetch_json(ctx: &Context<'_>, url: &str) -> CoreResult<String> {
-    // Leave allowlist/TLS enforcement to host; here we just call and log.
-    let body = ctx.net.get_text(url).map_err(CoreError::Net)?;
-    ctx.log
-        .event(&format!("net.get_text url={} bytes={}", url, body.len()));
-    Ok(body)
+    let resp = fetch(ctx, url)?;
+    if !resp.is_success() {
+        return Err(CoreError::Net(
+            Code::NetFailed.with_message(&format!("HTTP {}", resp.status)),
+        ));
+    }
+    Ok(resp.body)
+}
+
+/// Lets components know their requests may be served by a mirror.
+pub fn net_rewriting_active(ctx: &Context<'_>) -> bool {
+    ctx.net.rewriting_active()
+}
+
+/// Offline mode is reported by the host as a typed error rather than a
+/// denial, so the violation is recorded here.
+fn offline_violation(ctx: &Context<'_>, url: &str) {
+    ctx.violation(
+        &Denial::new(Code::NetOffline, "offline"),
+        Capability::Net,
+        url,
+    );
+}
+
+pub fn stream_open(ctx: &Context<'_>, url: &str) -> CoreResult<u64> {
+    operation(ctx, Code::NetStreamOpen, Capability::Net, url, || {
+        check_net_conditions(ctx, Capability::Net, Some("GET"), url)?;
+        charge(ctx, Budget::NetRequests, url)?;
+        let stream = ctx.net.open_stream(url)?;
+        ctx.audit(
+            Code::NetStreamOpen,
+            AuditEvent::NetStreamOpen {
+                url: ctx.audited(Capability::Net, url),
+                stream,
+            },
+        );
+        Ok(stream)
+    })
+}
+
+pub fn stream_next(ctx: &Context<'_>, stream: u64) -> CoreResult<Option<Vec<u8>>> {
+    // Hosts drop a stream that breaches a policy limit, so record the close
+    // here; the error itself carries the reason. Other errors end it too.
+    let chunk = ctx.net.next_chunk(stream).map_err(|e| {
+        let err = CoreError::from(e);
+        let outcome = match err.code().category() {
+            Category::Policy => Outcome::Ok,
+            _ => Outcome::Failed,
+        };
+        ctx.log.record(
+            AuditRecord::new(
+                Code::NetStreamClose,
+                AuditEvent::NetStreamClose {
+                    stream,
+                    bytes: None,
+                    reason: Some(err.code().to_string()),
+                },
+            )
+            .with_outcome(outcome)
+            .with_identity(ctx.component),
+        );
+        err
+    })?;
+    ctx.usage.fetched(chunk.as_ref().map_or(0, Vec::len));
+    Ok(chunk)
+}
+
+pub fn stream_close(ctx: &Context<'_>, stream: u64) -> CoreResult<()> {
+    let target = format!("stream={stream}");
+    operation(ctx, Code::NetStreamClose, Capability::Net, &target, || {
+        let bytes = ctx.net.close_stream(stream)?;
+        ctx.audit(
+            Code::NetStreamClose,
+            AuditEvent::NetStreamClose {
+                stream,
+                bytes: Some(bytes),
+                reason: None,
+            },
+        );
+        Ok(())
+    })
+}
+
+pub fn ws_connect(ctx: &Context<'_>, url: &str) -> CoreResult<u64> {
+    operation(ctx, Code::WsConnect, Capability::Ws, url, || {
+        check_net_conditions(ctx, Capability::Ws, None, url)?;
+        charge(ctx, Budget::NetRequests, url)?;
+        let conn = ctx.ws.connect(url).map_err(CoreError::Net)?;
+        ctx.audit(
+            Code::WsConnect,
+            AuditEvent::WsConnect {
+                url: ctx.audited(Capability::Ws, url),
+                conn,
+            },
+        );
+        Ok(conn)
+    })
+}
+
+/// Messages are not audited, only failures to send them.
+pub fn ws_send(ctx: &Context<'_>, conn: u64, message: &str) -> CoreResult<()> {
+    let target = format!("conn={conn}");
+    operation(ctx, Code::WsSend, Capability::Ws, &target, || {
+        charge(ctx, Budget::WsMessages, &target)?;
+        ctx.ws.send(conn, message).map_err(CoreError::Net)
+    })
+}
+
+pub fn ws_receive(ctx: &Context<'_>, conn: u64) -> CoreResult<Option<String>> {
+    let target = format!("conn={conn}");
+    operation(ctx, Code::WsReceive, Capability::Ws, &target, || {
+        let msg = ctx.ws.receive(conn).map_err(CoreError::Net)?;
+        ctx.usage.fetched(msg.as_ref().map_or(0, String::len));
+        if msg.is_none() {
+            ctx.audit(
+                Code::WsClosedByPeer,
+                AuditEvent::WsClose {
+                    conn,
+                    by_peer: true,
+                },
+            );
+        }
+        Ok(msg)
+    })
+}
+
+pub fn ws_close(ctx: &Context<'_>, conn: u64) -> CoreResult<()> {
+    let target = format!("conn={conn}");
+    operation(ctx, Code::WsClose, Capability::Ws, &target, || {
+        ctx.ws.close(conn).map_err(CoreError::Net)?;
+        ctx.audit(
+            Code::WsClose,
+            AuditEvent::WsClose {
+                conn,
+                by_peer: false,
+            },
+        );
+        Ok(())
+    })
 }
 
 // -----------------------------
@@ -129,11 +1080,47 @@ pub fn fetch_json(ctx: &Context<'_>, url: &str) -> CoreResult<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use saf_policy::{Condition, Policy};
+    // Collections used within tests; keep non-test code minimal.
     use std::collections::{BTreeSet, HashMap};
 
-    struct MemLog;
+    #[derive(Default)]
+    struct MemLog(std::sync::Mutex<Vec<AuditRecord>>);
     impl LogHost for MemLog {
-        fn event(&self, _message: &str) {}
+        fn record(&self, record: AuditRecord) {
+            if let Ok(mut g) = self.0.lock() {
+                g.push(record);
+            }
+        }
+    }
+
+    impl MemLog {
+        /// Whether `event` was audited by `test` with `code` and `outcome`.
+        fn has(&self, code: Code, outcome: Outcome, event: AuditEvent) -> bool {
+            self.0.lock().expect("log").iter().any(|r| {
+                r.code == code.as_str()
+                    && r.outcome == outcome
+                    && r.component == "test"
+                    && r.event == event
+            })
+        }
+    }
+
+    fn failed(operation: Code, target: &str, reason: &str) -> AuditEvent {
+        AuditEvent::OperationFailed {
+            capability: "fs".to_string(),
+            operation: operation.to_string(),
+            target: target.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    fn denied(capability: &str, target: &str, rule: &str) -> AuditEvent {
+        AuditEvent::PolicyDenied {
+            capability: capability.to_string(),
+            target: target.to_string(),
+            rule: rule.to_string(),
+        }
     }
 
     #[derive(Default)]
@@ -154,7 +1141,7 @@ mod tests {
             let parent = Path::new(&normalized)
                 .parent()
                 .map(|p| p.to_string_lossy().into_owned())
-                .unwrap_or_else(|| "".to_string());
+                .unwrap_or_default();
             self.ensure_dir(&parent);
             let name = Path::new(&normalized)
                 .file_name()
@@ -169,7 +1156,7 @@ mod tests {
             let parent = Path::new(&normalized)
                 .parent()
                 .map(|p| p.to_string_lossy().into_owned())
-                .unwrap_or_else(|| "".to_string());
+                .unwrap_or_default();
             self.ensure_dir(&parent);
             let name = Path::new(&normalized)
                 .file_name()
@@ -203,7 +1190,7 @@ mod tests {
             let parent = Path::new(path)
                 .parent()
                 .map(|p| p.to_string_lossy().into_owned())
-                .unwrap_or_else(|| "".to_string());
+                .unwrap_or_default();
             if !self.dirs.contains_key(&parent) {
                 return Err("parent dir missing".to_string());
             }
@@ -217,17 +1204,47 @@ mod tests {
             // SAFETY: None needed; pure Rust.
             Ok(())
         }
+        fn append_bytes(&self, path: &str, _content: &[u8]) -> Result<(), String> {
+            let parent = Path::new(path)
+                .parent()
+                .map(|p| p.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            if !self.dirs.contains_key(&parent) {
+                return Err("parent dir missing".to_string());
+            }
+            Ok(())
+        }
     }
 
     struct MemNet {
-        routes: HashMap<String, String>,
+        routes: HashMap<String, (u16, String)>,
     }
     impl NetHost for MemNet {
-        fn get_text(&self, url: &str) -> Result<String, String> {
+        fn fetch(&self, url: &str) -> Result<HttpResponse, NetError> {
             self.routes
                 .get(url)
-                .cloned()
-                .ok_or_else(|| "blocked or not found".to_string())
+                .map(|(status, body)| HttpResponse {
+                    status: *status,
+                    headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+                    body: body.clone(),
+                })
+                .ok_or_else(|| NetError::Failed("blocked or not found".to_string()))
+        }
+    }
+
+    struct NoWs;
+    impl WsHost for NoWs {
+        fn connect(&self, _url: &str) -> Result<u64, String> {
+            Err("blocked".to_string())
+        }
+        fn send(&self, _conn: u64, _message: &str) -> Result<(), String> {
+            Err("no such connection".to_string())
+        }
+        fn receive(&self, _conn: u64) -> Result<Option<String>, String> {
+            Err("no such connection".to_string())
+        }
+        fn close(&self, _conn: u64) -> Result<(), String> {
+            Err("no such connection".to_string())
         }
     }
 
@@ -245,15 +1262,27 @@ mod tests {
         fs.add_dir("");
         fs.add_dir("docs");
         fs.add_file("docs/readme.txt", "hello");
+        fs.add_dir("secrets");
+        fs.add_file("secrets/key", "k");
+        fs.add_file(".saf/audit.log", "");
 
         let net = MemNet {
             routes: HashMap::new(),
         };
-        let log = MemLog;
+        let log = MemLog::default();
+        let mut policy = Policy::new();
+        policy.read_only = vec!["docs/readme.*".to_string()];
+        policy.deny = vec!["secrets/**".to_string()];
+        let policy = SharedPolicy::new(policy);
         let ctx = Context {
             fs: &fs,
             net: &net,
+            ws: &NoWs,
             log: &log,
+            policy: &policy,
+            component: &ComponentIdentity::new("test"),
+            dry_run: false,
+            usage: &Usage::default(),
         };
 
         let entries = list_dir(&ctx, "docs").expect("list");
@@ -264,6 +1293,173 @@ mod tests {
 
         // write into existing parent dir
         write_text(&ctx, "docs/note.txt", "note").expect("write");
+
+        // append into existing parent dir; traversal is still rejected
+        append_text(&ctx, "docs/app.log", "line\n").expect("append");
+        assert_eq!(
+            append_bytes(&ctx, "../app.log", b"x"),
+            Err(CoreError::InvalidPath)
+        );
+
+        // Only what was moved counts.
+        assert_eq!(
+            ctx.usage.transfer(),
+            Transfer {
+                read: 5,
+                written: 9,
+                fetched: 0
+            }
+        );
+
+        // Failures are audited along with successes and denials.
+        assert!(log.has(
+            Code::FsInvalidPath,
+            Outcome::Failed,
+            failed(Code::FsAppend, "../app.log", "invalid or unsafe path")
+        ));
+        assert!(read_text(&ctx, "docs/missing.txt").is_err());
+        assert!(log.has(
+            Code::FsFailed,
+            Outcome::Failed,
+            failed(
+                Code::FsReadText,
+                "docs/missing.txt",
+                "fs error: no such file"
+            )
+        ));
+
+        // Policy rules are distinct from fs errors.
+        assert_eq!(
+            write_text(&ctx, "docs/readme.txt", "x")
+                .expect_err("read-only")
+                .code(),
+            Code::PolicyPathReadOnly
+        );
+        assert_eq!(
+            read_text(&ctx, "secrets/key").expect_err("denied").code(),
+            Code::PolicyPathDenied
+        );
+        assert!(!list_dir(&ctx, "")
+            .expect("list")
+            .contains(&"secrets".to_string()));
+        assert!(log.has(
+            Code::PolicyPathDenied,
+            Outcome::Denied,
+            denied("fs", "secrets/key", "deny[0]")
+        ));
+        // Denials are not audited again as failures.
+        assert!(!log
+            .0
+            .lock()
+            .expect("log")
+            .iter()
+            .any(|r| r.outcome == Outcome::Failed && r.code.starts_with("policy.")));
+
+        // A dry run lets the read through and says what would have happened.
+        let dry = Context {
+            dry_run: true,
+            ..ctx.clone()
+        };
+        assert_eq!(read_text(&dry, "secrets/key").expect("dry run"), "k");
+        assert!(log.has(
+            Code::PolicyPathDenied,
+            Outcome::WouldDeny,
+            denied("fs", "secrets/key", "deny[0]")
+        ));
+
+        // A replaced policy applies to the next call on the same context.
+        policy.replace(Policy::new());
+        assert_eq!(read_text(&ctx, "secrets/key").expect("reloaded"), "k");
+
+        // The broker's own directory is out of reach whatever the policy
+        // says, dry run or not.
+        for ctx in [&ctx, &dry] {
+            assert_eq!(
+                write_text(ctx, ".saf/policy.toml", "[fs]")
+                    .expect_err("reserved")
+                    .code(),
+                Code::PolicyPathDenied
+            );
+            assert_eq!(
+                read_text(ctx, "./.SAF/audit.log")
+                    .expect_err("reserved")
+                    .code(),
+                Code::PolicyPathDenied
+            );
+        }
+        assert!(log.has(
+            Code::PolicyPathDenied,
+            Outcome::Denied,
+            denied("fs", ".saf/policy.toml", ".saf")
+        ));
+        for ctx in [&ctx, &dry] {
+            assert!(!list_dir(ctx, "")
+                .expect("list")
+                .contains(&".saf".to_string()));
+        }
+
+        // Conditions see the size being written.
+        policy.replace(Policy {
+            conditions: vec![Condition {
+                when: "access == 'write'".to_string(),
+                require: "size <= 4".to_string(),
+            }],
+            ..Policy::new()
+        });
+        write_text(&ctx, "docs/small.txt", "1234").expect("small write");
+        policy.replace(Policy {
+            max_read_bytes: 4,
+            max_write_bytes: 4,
+            ..policy.current().as_ref().clone()
+        });
+        assert_eq!(
+            read_text(&ctx, "docs/readme.txt")
+                .expect_err("too long")
+                .code(),
+            Code::PolicySizeLimit
+        );
+        assert_eq!(
+            read_text_range(&ctx, "docs/readme.txt", 0, 100)
+                .expect("clamped")
+                .text,
+            "hell"
+        );
+        assert_eq!(
+            write_text(&ctx, "docs/big.txt", "12345")
+                .expect_err("too big")
+                .code(),
+            Code::PolicyConditionFailed
+        );
+
+        // Budgets count successful operations and refuse the one past the
+        // limit, even in a dry run.
+        policy.replace(Policy {
+            budgets: saf_policy::Budgets {
+                fs_writes: Some(1),
+                ..Default::default()
+            },
+            ..Policy::new()
+        });
+        let usage = Usage::default();
+        let ctx = Context {
+            usage: &usage,
+            dry_run: true,
+            ..ctx
+        };
+        write_text(&ctx, "docs/a.txt", "a").expect("within budget");
+        assert_eq!(
+            append_text(&ctx, "docs/a.txt", "b"),
+            Err(CoreError::BudgetExceeded {
+                budget: Budget::FsWrites,
+                limit: 1
+            })
+        );
+        assert_eq!(usage.get(Budget::FsWrites), 1);
+        assert!(log.has(
+            Code::PolicyBudgetExceeded,
+            Outcome::Denied,
+            denied("fs", "docs/a.txt", "budgets.fs_writes")
+        ));
     }
 
     #[test]
@@ -272,17 +1468,74 @@ mod tests {
         let mut routes = HashMap::new();
         routes.insert(
             "https://example.org/data.json".to_string(),
-            "{\"k\":\"v\"}".to_string(),
+            (200, "{\"k\":\"v\"}".to_string()),
+        );
+        routes.insert(
+            "https://example.org/data.json?token=s3cret".to_string(),
+            (200, "{\"k\":\"v\"}".to_string()),
+        );
+        routes.insert(
+            "https://example.org/missing".to_string(),
+            (404, "not found".to_string()),
         );
         let net = MemNet { routes };
-        let log = MemLog;
+        let log = MemLog::default();
+        let policy = SharedPolicy::new(Policy::new());
         let ctx = Context {
             fs: &fs,
             net: &net,
+            ws: &NoWs,
             log: &log,
+            policy: &policy,
+            component: &ComponentIdentity::new("test"),
+            dry_run: false,
+            usage: &Usage::default(),
         };
 
         let body = fetch_json(&ctx, "https://example.org/data.json").expect("fetch");
         assert_eq!(body, "{\"k\":\"v\"}");
+
+        // `fetch` surfaces the status; `fetch_json` treats it as an error.
+        let resp = fetch(&ctx, "https://example.org/missing").expect("fetch");
+        assert_eq!(resp.status, 404);
+        assert_eq!(resp.header("content-type"), Some("application/json"));
+        let err = fetch_json(&ctx, "https://example.org/missing").expect_err("404");
+        assert_eq!(err.code(), Code::NetFailed);
+
+        // Redacted values never reach the log, whether the request is made
+        // or refused.
+        policy.replace(Policy {
+            redaction: Redaction {
+                query_params: vec!["token".to_string()],
+                ..Redaction::default()
+            },
+            conditions: vec![Condition {
+                when: "url.contains('/private')".to_string(),
+                require: "false".to_string(),
+            }],
+            ..Policy::new()
+        });
+        fetch(&ctx, "https://example.org/data.json?token=s3cret").expect("fetch");
+        fetch(&ctx, "https://example.org/private?token=s3cret").expect_err("refused");
+        assert!(log.has(
+            Code::NetGetText,
+            Outcome::Ok,
+            AuditEvent::NetFetch {
+                url: "https://example.org/data.json?token=[redacted]".to_string(),
+                status: 200,
+                bytes: 9,
+            }
+        ));
+        assert!(log.has(
+            Code::PolicyConditionFailed,
+            Outcome::Denied,
+            denied(
+                "net",
+                "https://example.org/private?token=[redacted]",
+                "conditions[0]"
+            )
+        ));
+        let log = log.0.lock().expect("log");
+        assert!(!format!("{log:?}").contains("s3cret"), "{log:?}");
     }
 }
@@ -0,0 +1,143 @@
+//! UTF-8 handling for text read in pieces.
+//!
+//! Chunked reads can end in the middle of a multi-byte character. These
+//! helpers only ever hand out whole characters: a decoder that carries a
+//! partial trailing sequence over to the next chunk, and a clamp for byte
+//! ranges that do not start or end on a character boundary.
+
+use std::io::Read;
+
+/// Incremental decoder that never splits a character.
+#[derive(Debug, Default)]
+pub struct Utf8Decoder {
+    pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    /// Decode as much of `pending + bytes` as forms whole characters; an
+    /// incomplete trailing sequence is kept for the next call.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<String, String> {
+        self.pending.extend_from_slice(bytes);
+        let valid = complete_prefix(&self.pending)?;
+        let rest = self.pending.split_off(valid);
+        let text = std::mem::replace(&mut self.pending, rest);
+        String::from_utf8(text).map_err(|e| e.to_string())
+    }
+
+    /// Fails if the input ended inside a character.
+    pub fn finish(self) -> Result<(), String> {
+        if self.pending.is_empty() {
+            Ok(())
+        } else {
+            Err("input ends with a truncated UTF-8 sequence".to_string())
+        }
+    }
+}
+
+/// Length of the longest prefix of `bytes` made of whole characters. Only a
+/// sequence cut off by the end of `bytes` is excluded; invalid bytes anywhere
+/// are an error.
+fn complete_prefix(bytes: &[u8]) -> Result<usize, String> {
+    match std::str::from_utf8(bytes) {
+        Ok(_) => Ok(bytes.len()),
+        Err(e) if e.error_len().is_none() => Ok(e.valid_up_to()),
+        Err(e) => Err(format!("invalid UTF-8 at byte {}", e.valid_up_to())),
+    }
+}
+
+/// Reader adapter yielding text chunks that always end on a character
+/// boundary.
+pub struct Utf8Chunks<R> {
+    reader: R,
+    decoder: Option<Utf8Decoder>,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> Utf8Chunks<R> {
+    pub fn new(reader: R, chunk_size: usize) -> Self {
+        Self {
+            reader,
+            decoder: Some(Utf8Decoder::default()),
+            // Room for at least one whole character.
+            buf: vec![0; chunk_size.max(4)],
+        }
+    }
+}
+
+impl<R: Read> Iterator for Utf8Chunks<R> {
+    type Item = Result<String, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let decoder = self.decoder.as_mut()?;
+            let n = match self.reader.read(&mut self.buf) {
+                Ok(n) => n,
+                Err(e) => return Some(Err(e.to_string())),
+            };
+            if n == 0 {
+                return match self.decoder.take()?.finish() {
+                    Ok(()) => None,
+                    Err(e) => Some(Err(e)),
+                };
+            }
+            match decoder.push(&self.buf[..n]) {
+                Ok(text) if text.is_empty() => continue,
+                other => return Some(other),
+            }
+        }
+    }
+}
+
+/// Text from a byte range, clamped inward to character boundaries. `start`
+/// and `end` are the byte offsets actually covered, so the next range can
+/// begin at `end`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextRange {
+    pub text: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Clamp `bytes`, read from byte `offset` of a file, to whole characters:
+/// leading continuation bytes and a trailing partial sequence are dropped.
+/// Ranges of at least four bytes always make progress.
+pub fn clamp_utf8(bytes: &[u8], offset: u64) -> Result<TextRange, String> {
+    let lead = bytes
+        .iter()
+        .take(3)
+        .take_while(|b| (**b & 0xC0) == 0x80)
+        .count();
+    let body = &bytes[lead..];
+    let len = complete_prefix(body)?;
+    let text = String::from_utf8(body[..len].to_vec()).map_err(|e| e.to_string())?;
+    let start = offset + lead as u64;
+    Ok(TextRange {
+        text,
+        start,
+        end: start + len as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_and_ranges_keep_characters_whole() {
+        let text = "aé€😀b";
+        let chunks: Vec<String> = Utf8Chunks::new(text.as_bytes(), 4)
+            .collect::<Result<_, _>>()
+            .expect("decode");
+        assert_eq!(chunks.concat(), text);
+
+        // "é" is bytes 1..3; starting at 2 lands mid-character.
+        let r = clamp_utf8(&text.as_bytes()[2..8], 2).expect("clamp");
+        assert_eq!(r.text, "€");
+        assert_eq!((r.start, r.end), (3, 6));
+
+        assert!(clamp_utf8(b"a\xffb", 0).is_err());
+        assert!(Utf8Chunks::new(&b"a\xe2\x82"[..], 4)
+            .collect::<Result<Vec<_>, _>>()
+            .is_err());
+    }
+}
@@ -0,0 +1,642 @@
+//! Building policies in code, with diagnostics.
+//!
+//! [`PolicyBuilder`] assembles a [`Policy`] and refuses to build one with
+//! errors; [`Policy::issues`] reports both errors (values that cannot be
+//! meant, like a URL where a bare domain is expected) and warnings (rules
+//! another rule makes unreachable). Policy files go through the same
+//! checks, where only errors stop a load.
+
+use std::fmt::{Display, Formatter};
+
+use saf_codes::{Category, Severity};
+
+use crate::{
+    domain_matches, glob, has_prefix, ipnet, ClientCert, Condition, OriginRule, Policy, RateLimit,
+    UrlRewrite, UrlRule,
+};
+
+/// One problem found in a policy. Only [`Severity::Warn`] and
+/// [`Severity::Error`] are used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyIssue {
+    pub severity: Severity,
+    /// Where the problem is, e.g. `allowed_domains[0]`.
+    pub field: String,
+    pub message: String,
+}
+
+impl PolicyIssue {
+    fn error(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+
+    fn warn(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warn,
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// `issues` on one line, separated by `; `.
+    pub fn join(issues: &[PolicyIssue]) -> String {
+        let issues: Vec<String> = issues.iter().map(ToString::to_string).collect();
+        issues.join("; ")
+    }
+}
+
+impl Display for PolicyIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Assembles a [`Policy`], starting from [`Policy::new`]. List methods add
+/// to what is already there.
+#[derive(Debug, Clone, Default)]
+pub struct PolicyBuilder {
+    policy: Policy,
+}
+
+impl PolicyBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start from an existing policy, e.g. one loaded from a file.
+    pub fn from_policy(policy: Policy) -> Self {
+        Self { policy }
+    }
+
+    pub fn with_allowed_domains<S: Into<String>>(
+        mut self,
+        domains: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.policy
+            .allowed_domains
+            .extend(domains.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn with_denied_domains<S: Into<String>>(
+        mut self,
+        domains: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.policy
+            .denied_domains
+            .extend(domains.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn with_ask_domains<S: Into<String>>(
+        mut self,
+        domains: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.policy
+            .ask_domains
+            .extend(domains.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn with_allowed_paths<S: Into<String>>(
+        mut self,
+        paths: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.policy
+            .allowed_paths
+            .extend(paths.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn with_denied_paths<S: Into<String>>(
+        mut self,
+        paths: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.policy
+            .denied_paths
+            .extend(paths.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn with_ask_paths<S: Into<String>>(mut self, paths: impl IntoIterator<Item = S>) -> Self {
+        self.policy
+            .ask_paths
+            .extend(paths.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn with_read_only<S: Into<String>>(mut self, globs: impl IntoIterator<Item = S>) -> Self {
+        self.policy
+            .read_only
+            .extend(globs.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn with_deny<S: Into<String>>(mut self, globs: impl IntoIterator<Item = S>) -> Self {
+        self.policy.deny.extend(globs.into_iter().map(Into::into));
+        self
+    }
+
+    /// Confine writes to `globs`; see [`Policy::writable_only`].
+    pub fn with_writable<S: Into<String>>(mut self, globs: impl IntoIterator<Item = S>) -> Self {
+        self.policy.writable_only = true;
+        self.policy
+            .writable
+            .extend(globs.into_iter().map(Into::into));
+        self
+    }
+
+    /// Grant reading the `saf:app/config` values named by `keys`.
+    pub fn with_config_keys<S: Into<String>>(mut self, keys: impl IntoIterator<Item = S>) -> Self {
+        self.policy
+            .config_keys
+            .extend(keys.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn with_condition(mut self, when: &str, require: &str) -> Self {
+        self.policy.conditions.push(Condition {
+            when: when.to_string(),
+            require: require.to_string(),
+        });
+        self
+    }
+
+    pub fn with_url_rule(mut self, rule: UrlRule) -> Self {
+        self.policy.url_rules.push(rule);
+        self
+    }
+
+    /// Admit `schemes` on `ports` for `domain`; see [`OriginRule`].
+    pub fn with_origin(mut self, domain: &str, schemes: &[&str], ports: &[u16]) -> Self {
+        self.policy.origins.push(OriginRule {
+            domain: domain.to_string(),
+            schemes: schemes.iter().map(|s| s.to_string()).collect(),
+            ports: ports.to_vec(),
+        });
+        self
+    }
+
+    pub fn with_rate_limit(mut self, domain: &str, limit: RateLimit) -> Self {
+        self.policy.rate_limits.insert(domain.to_string(), limit);
+        self
+    }
+
+    pub fn with_url_rewrite(mut self, from: &str, to: &str) -> Self {
+        self.policy.url_rewrites.push(UrlRewrite {
+            from: from.to_string(),
+            to: to.to_string(),
+        });
+        self
+    }
+
+    pub fn with_client_cert(mut self, domain: &str, cert: &str, key: &str) -> Self {
+        self.policy.client_certs.insert(
+            domain.to_string(),
+            ClientCert {
+                cert: cert.to_string(),
+                key: key.to_string(),
+            },
+        );
+        self
+    }
+
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.policy.offline = offline;
+        self
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.policy.strict = strict;
+        self
+    }
+
+    /// Everything wrong with the policy so far.
+    pub fn issues(&self) -> Vec<PolicyIssue> {
+        self.policy.issues()
+    }
+
+    /// The policy, or every issue if any is an error.
+    pub fn build(self) -> Result<Policy, Vec<PolicyIssue>> {
+        let issues = self.issues();
+        if issues.iter().any(PolicyIssue::is_error) {
+            Err(issues)
+        } else {
+            Ok(self.policy)
+        }
+    }
+}
+
+impl Policy {
+    /// Errors and warnings in field order, then rules other rules make
+    /// unreachable, then each component policy's issues.
+    pub fn issues(&self) -> Vec<PolicyIssue> {
+        let mut out = Vec::new();
+        let host_name = "expected a host name like \"example.org\" or \"*.example.org\"";
+        for (field, domains) in [
+            ("allowed_domains", &self.allowed_domains),
+            ("denied_domains", &self.denied_domains),
+            ("ask_domains", &self.ask_domains),
+        ] {
+            for (i, d) in domains.iter().enumerate() {
+                if !is_domain(d.strip_prefix("*.").unwrap_or(d)) {
+                    out.push(PolicyIssue::error(
+                        format!("{field}[{i}]"),
+                        format!("{d:?}: {host_name}"),
+                    ));
+                }
+            }
+        }
+        for (field, paths) in [
+            ("allowed_paths", &self.allowed_paths),
+            ("denied_paths", &self.denied_paths),
+            ("ask_paths", &self.ask_paths),
+        ] {
+            for (i, p) in paths.iter().enumerate() {
+                let bad = p.is_empty()
+                    || p.starts_with('/')
+                    || p.contains('\\')
+                    || p.split('/').any(|c| c == ".." || c == "." || c.is_empty());
+                if bad {
+                    out.push(PolicyIssue::error(
+                        format!("{field}[{i}]"),
+                        format!("{p:?}: expected a workspace-relative path like \"docs/notes\""),
+                    ));
+                }
+            }
+        }
+        for (field, globs) in [
+            ("read_only", &self.read_only),
+            ("deny", &self.deny),
+            ("writable", &self.writable),
+            ("redaction.paths", &self.redaction.paths),
+        ] {
+            for (i, g) in globs.iter().enumerate() {
+                if g.is_empty()
+                    || g.starts_with('/')
+                    || g.contains('\\')
+                    || g.split('/').any(|c| c == "..")
+                {
+                    out.push(PolicyIssue::error(
+                        format!("{field}[{i}]"),
+                        format!("{g:?}: expected a workspace-relative glob like \"docs/**\""),
+                    ));
+                } else if let Err(e) = glob::Glob::new(g) {
+                    out.push(PolicyIssue::error(format!("{field}[{i}]"), e));
+                } else if g.split('/').any(|c| c.contains("**") && c != "**") {
+                    out.push(PolicyIssue::warn(
+                        format!("{field}[{i}]"),
+                        format!("{g:?}: `**` inside a segment matches like `*`; use `dir/**/name`"),
+                    ));
+                }
+            }
+        }
+        for (i, k) in self.config_keys.iter().enumerate() {
+            if k.is_empty() || k.contains('/') {
+                out.push(PolicyIssue::error(
+                    format!("config_keys[{i}]"),
+                    format!("{k:?}: expected a key name like \"api_url\" or \"smtp.*\""),
+                ));
+            } else if let Err(e) = glob::Glob::new(k) {
+                out.push(PolicyIssue::error(format!("config_keys[{i}]"), e));
+            }
+        }
+        for (i, p) in self.redaction.patterns.iter().enumerate() {
+            if regex::Regex::new(p).is_err() {
+                out.push(PolicyIssue::error(
+                    format!("redaction.patterns[{i}]"),
+                    format!("{p:?}: not a valid regular expression"),
+                ));
+            }
+        }
+        for (category, severity) in &self.audit_min_severity {
+            let field = format!("audit_min_severity.{category}");
+            if Category::parse(category).is_none() {
+                out.push(PolicyIssue::error(
+                    field,
+                    "expected a category: fs, net, policy or lifecycle",
+                ));
+            } else if Severity::parse(severity).is_none() {
+                out.push(PolicyIssue::error(
+                    field,
+                    format!("{severity:?}: expected info, warn, error or security"),
+                ));
+            }
+        }
+        for (i, p) in self.redaction.query_params.iter().enumerate() {
+            if p.is_empty() {
+                out.push(PolicyIssue::error(
+                    format!("redaction.query_params[{i}]"),
+                    "expected a parameter name",
+                ));
+            }
+        }
+        for (i, c) in self.conditions.iter().enumerate() {
+            for (field, src) in [("when", &c.when), ("require", &c.require)] {
+                if let Err(e) = crate::expr::Expr::parse(src) {
+                    out.push(PolicyIssue::error(format!("conditions[{i}].{field}"), e));
+                }
+            }
+        }
+        for (i, r) in self.denied_ip_ranges.iter().enumerate() {
+            if r.parse::<ipnet::IpNet>().is_err() {
+                out.push(PolicyIssue::error(
+                    format!("denied_ip_ranges[{i}]"),
+                    format!("{r:?}: expected CIDR notation like \"10.0.0.0/8\""),
+                ));
+            }
+        }
+        for (i, r) in self.url_rewrites.iter().enumerate() {
+            for (field, url) in [("from", &r.from), ("to", &r.to)] {
+                if !url.starts_with("https://") {
+                    out.push(PolicyIssue::error(
+                        format!("url_rewrites[{i}].{field}"),
+                        format!("{url:?}: expected an https:// URL prefix"),
+                    ));
+                }
+            }
+        }
+        for (i, rule) in self.url_rules.iter().enumerate() {
+            if !is_domain(rule.domain.strip_prefix("*.").unwrap_or(&rule.domain)) {
+                out.push(PolicyIssue::error(
+                    format!("url_rules[{i}].domain"),
+                    format!("{:?}: expected a bare host name", rule.domain),
+                ));
+            }
+            if rule.paths.is_empty() {
+                out.push(PolicyIssue::error(
+                    format!("url_rules[{i}].paths"),
+                    "at least one path glob is required",
+                ));
+            }
+            for p in &rule.paths {
+                if !p.starts_with('/') {
+                    out.push(PolicyIssue::error(
+                        format!("url_rules[{i}].paths"),
+                        format!("{p:?} must start with '/'"),
+                    ));
+                } else if let Err(e) = glob::Glob::new(p) {
+                    out.push(PolicyIssue::error(format!("url_rules[{i}].paths"), e));
+                }
+            }
+            for m in &rule.methods {
+                if !HTTP_METHODS.contains(&m.to_ascii_uppercase().as_str()) {
+                    out.push(PolicyIssue::error(
+                        format!("url_rules[{i}].methods"),
+                        format!("unknown HTTP method {m:?}"),
+                    ));
+                }
+            }
+        }
+        for (i, origin) in self.origins.iter().enumerate() {
+            if !is_domain(origin.domain.strip_prefix("*.").unwrap_or(&origin.domain)) {
+                out.push(PolicyIssue::error(
+                    format!("origins[{i}].domain"),
+                    format!("{:?}: expected a bare host name", origin.domain),
+                ));
+            }
+            for s in &origin.schemes {
+                if !URL_SCHEMES.contains(&s.to_ascii_lowercase().as_str()) {
+                    out.push(PolicyIssue::error(
+                        format!("origins[{i}].schemes"),
+                        format!("{s:?}: expected one of http, https, ws, wss"),
+                    ));
+                }
+            }
+            if origin.ports.contains(&0) {
+                out.push(PolicyIssue::error(
+                    format!("origins[{i}].ports"),
+                    "port 0 cannot be connected to",
+                ));
+            }
+            if origin.schemes.is_empty() && origin.ports.is_empty() {
+                out.push(PolicyIssue::warn(
+                    format!("origins[{i}]"),
+                    "no schemes or ports given, so the entry changes nothing",
+                ));
+            }
+        }
+        for (domain, limit) in &self.rate_limits {
+            if limit.requests_per_minute == 0 || limit.burst == 0 {
+                out.push(PolicyIssue::error(
+                    format!("rate_limits.{domain:?}"),
+                    "requests_per_minute and burst must be at least 1",
+                ));
+            }
+        }
+        if let Some(endpoint) = &self.dns_over_https {
+            if !endpoint.starts_with("https://") {
+                out.push(PolicyIssue::error(
+                    "dns_over_https",
+                    format!("{endpoint:?}: expected an https:// URL"),
+                ));
+            }
+        }
+        for (domain, cert) in &self.client_certs {
+            if !is_domain(domain) {
+                out.push(PolicyIssue::error(
+                    format!("client_certs.{domain:?}"),
+                    "expected a bare host name",
+                ));
+            }
+            if cert.cert.is_empty() || cert.key.is_empty() {
+                out.push(PolicyIssue::error(
+                    format!("client_certs.{domain:?}"),
+                    "cert and key must name secret-store entries",
+                ));
+            }
+        }
+        for (field, value) in [
+            ("max_read_bytes", self.max_read_bytes),
+            ("max_write_bytes", self.max_write_bytes),
+            ("max_response_bytes", self.max_response_bytes),
+            ("max_request_body_bytes", self.max_request_body_bytes),
+            ("max_ws_message_bytes", self.max_ws_message_bytes),
+            ("max_stream_bytes", self.max_stream_bytes),
+            ("max_stream_secs", self.max_stream_secs),
+            ("memory.max_memory_bytes", self.memory.max_memory_bytes),
+            (
+                "memory.max_table_elements",
+                self.memory.max_table_elements.into(),
+            ),
+            ("memory.max_instances", self.memory.max_instances.into()),
+            ("memory.max_tables", self.memory.max_tables.into()),
+            ("memory.max_memories", self.memory.max_memories.into()),
+        ] {
+            if value == 0 {
+                out.push(PolicyIssue::error(field, "must be greater than 0"));
+            }
+        }
+        if self.max_fuel == Some(0) {
+            out.push(PolicyIssue::error(
+                "max_fuel",
+                "must be greater than 0, or left out for no limit",
+            ));
+        }
+        self.contradictions(&mut out);
+        for (id, policy) in &self.components {
+            if !policy.components.is_empty() {
+                out.push(PolicyIssue::error(
+                    format!("components.{id:?}"),
+                    "component policies cannot nest",
+                ));
+            }
+            out.extend(policy.issues().into_iter().map(|issue| PolicyIssue {
+                field: format!("components.{id:?}.{}", issue.field),
+                ..issue
+            }));
+        }
+        out
+    }
+
+    /// Rules that can never take effect because of another rule.
+    fn contradictions(&self, out: &mut Vec<PolicyIssue>) {
+        for (i, d) in self.allowed_domains.iter().enumerate() {
+            if let Some(j) = self.denied_domains.iter().position(|x| covers(x, d)) {
+                out.push(PolicyIssue::warn(
+                    format!("allowed_domains[{i}]"),
+                    format!("{d:?} is always refused by denied_domains[{j}]"),
+                ));
+            }
+        }
+        for (i, d) in self.ask_domains.iter().enumerate() {
+            if let Some(j) = self.allowed_domains.iter().position(|x| covers(x, d)) {
+                out.push(PolicyIssue::warn(
+                    format!("ask_domains[{i}]"),
+                    format!("{d:?} is never asked about: allowed_domains[{j}] admits it"),
+                ));
+            }
+        }
+        for (i, p) in self.allowed_paths.iter().enumerate() {
+            if let Some(j) = self.denied_paths.iter().position(|x| has_prefix(p, x)) {
+                out.push(PolicyIssue::warn(
+                    format!("allowed_paths[{i}]"),
+                    format!("{p:?} is always refused by denied_paths[{j}]"),
+                ));
+            }
+        }
+        for (i, p) in self.ask_paths.iter().enumerate() {
+            if self.allowed_paths.is_empty() {
+                out.push(PolicyIssue::warn(
+                    format!("ask_paths[{i}]"),
+                    format!("{p:?} is never asked about: allowed_paths is empty, which allows the whole workspace"),
+                ));
+            } else if let Some(j) = self.allowed_paths.iter().position(|x| has_prefix(p, x)) {
+                out.push(PolicyIssue::warn(
+                    format!("ask_paths[{i}]"),
+                    format!("{p:?} is never asked about: allowed_paths[{j}] admits it"),
+                ));
+            }
+        }
+        if !self.writable.is_empty() && !self.writable_only {
+            out.push(PolicyIssue::warn(
+                "writable",
+                "has no effect unless writable_only is set",
+            ));
+        }
+        for (i, origin) in self.origins.iter().enumerate() {
+            if !self
+                .allowed_domains
+                .iter()
+                .any(|x| covers(x, &origin.domain))
+                && !self.ask_domains.iter().any(|x| covers(x, &origin.domain))
+            {
+                out.push(PolicyIssue::warn(
+                    format!("origins[{i}]"),
+                    "domain is not allowed, so the entry never applies",
+                ));
+            }
+        }
+        for domain in self.rate_limits.keys() {
+            if !self.allowed_domains.iter().any(|x| covers(x, domain))
+                && !self.ask_domains.iter().any(|x| covers(x, domain))
+            {
+                out.push(PolicyIssue::warn(
+                    format!("rate_limits.{domain:?}"),
+                    "domain is not allowed, so the limit never applies",
+                ));
+            }
+        }
+    }
+}
+
+/// Whether domain pattern `outer` matches everything `inner` does.
+pub(crate) fn covers(outer: &str, inner: &str) -> bool {
+    match inner.strip_prefix("*.") {
+        // A wildcard is covered only by an equal or broader wildcard.
+        Some(suffix) => outer
+            .strip_prefix("*.")
+            .is_some_and(|o| o.eq_ignore_ascii_case(suffix) || domain_matches(outer, suffix)),
+        None => domain_matches(outer, inner),
+    }
+}
+
+const HTTP_METHODS: &[&str] = &["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"];
+
+const URL_SCHEMES: &[&str] = &["http", "https", "ws", "wss"];
+
+pub(crate) fn is_domain(d: &str) -> bool {
+    !d.is_empty()
+        && d.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.'))
+        && !d.starts_with('.')
+        && !d.ends_with('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_valid_policies_and_reports_issues() {
+        let policy = PolicyBuilder::new()
+            .with_allowed_domains(["example.org"])
+            .with_allowed_paths(["docs", "exports"])
+            .with_writable(["exports/**"])
+            .build()
+            .expect("valid");
+        assert!(policy.is_url_allowed("https://example.org/"));
+        assert!(policy.writable_only);
+
+        let issues = PolicyBuilder::new()
+            .with_allowed_domains(["https://example.org/", "api.corp.net"])
+            .with_denied_domains(["*.corp.net"])
+            .with_deny(["docs/**.md"])
+            .build()
+            .unwrap_err();
+        let rendered: Vec<(Severity, String)> =
+            issues.iter().map(|i| (i.severity, i.to_string())).collect();
+        assert_eq!(
+            rendered,
+            [
+                (
+                    Severity::Error,
+                    "allowed_domains[0]: \"https://example.org/\": expected a host name like \"example.org\" or \"*.example.org\"".to_string()
+                ),
+                (
+                    Severity::Warn,
+                    "deny[0]: \"docs/**.md\": `**` inside a segment matches like `*`; use `dir/**/name`".to_string()
+                ),
+                (
+                    Severity::Warn,
+                    "allowed_domains[1]: \"api.corp.net\" is always refused by denied_domains[0]".to_string()
+                ),
+            ]
+        );
+
+        // Warnings alone do not stop a build.
+        assert!(PolicyBuilder::new()
+            .with_ask_domains(["example.org"])
+            .with_allowed_domains(["*.example.org", "example.org"])
+            .build()
+            .is_ok());
+    }
+}
@@ -0,0 +1,365 @@
+//! Policy files layered on a base: `extends = "base.toml"`.
+//!
+//! An overlay names a base policy file (relative to itself, and possibly
+//! extending another) and sets only the fields it changes:
+//!
+//! - restricting lists and maps (`denied_domains`, `deny`, `conditions`,
+//!   `rate_limits`, `redaction`, ...) and `url_rules` are combined, so an
+//!   overlay can add restrictions but never drop one;
+//! - tables such as `budgets` or `sysinfo` are merged key by key;
+//! - anything else the overlay sets replaces the base value.
+//!
+//! The result may not be wider than the base anywhere (an extra domain, a
+//! higher limit, `offline` turned off, a new `components` entry) unless
+//! the overlay lists the field in `loosen`, e.g.
+//! `loosen = ["allowed_domains"]`. Loosened grant lists are added to the
+//! base's rather than replacing them. A grant the base already covers,
+//! such as `wiki.corp.net` under `*.corp.net`, narrows rather than widens.
+//! Changes to `origins`, `client_certs` and `dns_over_https` always count
+//! as widening, as does a `url_rules` entry for a domain the base already
+//! has rules for.
+
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use crate::builder::covers;
+use crate::explain::{diff, Effect, GRANTING_LISTS, MAPS, RESTRICTING};
+use crate::{has_prefix, Policy};
+
+/// Bases deeper than this are refused rather than followed.
+const MAX_DEPTH: usize = 8;
+
+/// Sees the path and bytes of a base file before it is parsed, and may
+/// refuse it.
+pub type BaseCheck<'a> = dyn Fn(&Path, &[u8]) -> Result<(), String> + 'a;
+
+/// Parse a policy file that may extend a base. `content` was read from
+/// `path`; `check` sees the bytes of every base file before it is used
+/// (for signature checks) and may refuse it.
+pub(crate) fn load(
+    path: &Path,
+    content: &str,
+    check: &BaseCheck<'_>,
+    chain: &mut Vec<PathBuf>,
+) -> Result<Policy, String> {
+    let json = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("json"));
+    let parsed: Option<Value> = if json {
+        serde_json::from_str(content).ok()
+    } else {
+        toml::from_str(content).ok()
+    };
+    let Some(Value::Object(mut overlay)) = parsed.filter(|v| v.get("extends").is_some()) else {
+        return if json {
+            Policy::from_json_str(content)
+        } else {
+            Policy::from_toml_str(content)
+        };
+    };
+    let extends = match overlay.remove("extends") {
+        Some(Value::String(s)) => s,
+        _ => return Err("extends: expected a path to a base policy file".to_string()),
+    };
+    let loosen: Vec<String> = match overlay.remove("loosen") {
+        None => Vec::new(),
+        Some(v) => serde_json::from_value(v)
+            .map_err(|_| "loosen: expected a list of field names".to_string())?,
+    };
+    // Typos and type errors are reported against the overlay itself.
+    serde_json::from_value::<Policy>(Value::Object(overlay.clone())).map_err(|e| e.to_string())?;
+    let fields = serde_json::to_value(Policy::new()).map_err(|e| e.to_string())?;
+    if let Some(f) = loosen.iter().find(|f| fields.get(f.as_str()).is_none()) {
+        return Err(format!("loosen: unknown field {f:?}"));
+    }
+
+    let base_path = path.parent().unwrap_or(Path::new("")).join(&extends);
+    let canonical = base_path
+        .canonicalize()
+        .map_err(|e| format!("extends: {}: {}", base_path.display(), e))?;
+    if chain.contains(&canonical) {
+        return Err(format!(
+            "extends: {} is extended in a cycle",
+            base_path.display()
+        ));
+    }
+    if chain.len() >= MAX_DEPTH {
+        return Err(format!("extends: more than {MAX_DEPTH} levels of bases"));
+    }
+    chain.push(canonical);
+    let base_content = std::fs::read_to_string(&base_path)
+        .map_err(|e| format!("extends: cannot read {}: {}", base_path.display(), e))?;
+    check(&base_path, base_content.as_bytes())?;
+    let base = load(&base_path, &base_content, check, chain)
+        .map_err(|e| format!("invalid base policy {}: {}", base_path.display(), e))?;
+
+    let merged = compose(&base, &overlay, &loosen)?;
+    merged.validate()?;
+    let mut widened = Vec::new();
+    widenings(&base, &merged, &loosen, "", &mut widened);
+    if widened.is_empty() {
+        Ok(merged)
+    } else {
+        Err(format!(
+            "widens its base without `loosen`: {}",
+            widened.join("; ")
+        ))
+    }
+}
+
+/// `base` with the fields set in `overlay` merged in.
+fn compose(
+    base: &Policy,
+    overlay: &Map<String, Value>,
+    loosen: &[String],
+) -> Result<Policy, String> {
+    let mut merged = serde_json::to_value(base).map_err(|e| e.to_string())?;
+    let Value::Object(fields) = &mut merged else {
+        return Err("policy is not a table".to_string());
+    };
+    for (key, value) in overlay {
+        // A `url_rules` entry for a domain the base restricts widens it,
+        // which `widenings` catches; combining keeps the base's rules.
+        let combine = RESTRICTING.contains(&key.as_str())
+            || key == "url_rules"
+            || key == "redaction"
+            || (GRANTING_LISTS.contains(&key.as_str()) && loosen.contains(key));
+        match fields.get_mut(key) {
+            Some(old) if key == "components" => merge_components(old, value, loosen)?,
+            Some(old) if combine => union(old, value),
+            Some(Value::Object(old)) if !MAPS.contains(&key.as_str()) => {
+                if let Value::Object(new) = value {
+                    old.extend(new.clone());
+                }
+            }
+            _ => {
+                fields.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    serde_json::from_value(merged).map_err(|e| e.to_string())
+}
+
+/// Entries of `new` added to `old`: lists keep `old`'s order, tables take
+/// `new`'s entry for a shared key, and nested lists combine.
+fn union(old: &mut Value, new: &Value) {
+    match (old, new) {
+        (Value::Array(old), Value::Array(new)) => {
+            for v in new {
+                if !old.contains(v) {
+                    old.push(v.clone());
+                }
+            }
+        }
+        (Value::Object(old), Value::Object(new)) => {
+            for (k, v) in new {
+                match old.get_mut(k) {
+                    Some(o @ Value::Array(_)) => union(o, v),
+                    _ => {
+                        old.insert(k.clone(), v.clone());
+                    }
+                }
+            }
+        }
+        (old, new) => *old = new.clone(),
+    }
+}
+
+/// A component named in both is merged like a policy of its own; one only
+/// the overlay names is taken as written.
+fn merge_components(old: &mut Value, new: &Value, loosen: &[String]) -> Result<(), String> {
+    let (Value::Object(old), Value::Object(new)) = (old, new) else {
+        return Ok(());
+    };
+    for (id, overlay) in new {
+        let merged = match (old.get(id), overlay) {
+            (Some(base), Value::Object(overlay)) => {
+                let base: Policy =
+                    serde_json::from_value(base.clone()).map_err(|e| e.to_string())?;
+                serde_json::to_value(compose(&base, overlay, loosen)?).map_err(|e| e.to_string())?
+            }
+            _ => overlay.clone(),
+        };
+        old.insert(id.clone(), merged);
+    }
+    Ok(())
+}
+
+/// Ways `merged` grants more than `base` in fields not listed in
+/// `loosen`, as `field change` lines prefixed with `prefix`.
+fn widenings(
+    base: &Policy,
+    merged: &Policy,
+    loosen: &[String],
+    prefix: &str,
+    out: &mut Vec<String>,
+) {
+    let loosened = |field: &str| loosen.iter().any(|l| l == field);
+    for change in diff(base, merged) {
+        let field = change.field.split(['.', '[']).next().unwrap_or_default();
+        let widens = match field {
+            "components" => false,
+            _ => change.effect == Effect::Wider && !covered(base, field, &change.change),
+        };
+        if widens && !loosened(field) {
+            out.push(format!("{prefix}{} {}", change.field, change.change));
+        }
+    }
+    for (id, policy) in &merged.components {
+        match base.components.get(id) {
+            Some(old) => widenings(
+                old,
+                policy,
+                loosen,
+                &format!("{prefix}components.{id:?}."),
+                out,
+            ),
+            None if !loosened("components") => {
+                out.push(format!("{prefix}components.{id:?} + new component grant"));
+            }
+            None => {}
+        }
+    }
+}
+
+/// Whether an added grant (`+ "entry"`) falls within what `base` already
+/// grants for `field`.
+fn covered(base: &Policy, field: &str, change: &str) -> bool {
+    let Some(entry) = change
+        .strip_prefix("+ ")
+        .and_then(|e| serde_json::from_str::<String>(e).ok())
+    else {
+        return false;
+    };
+    let domain = |list: &[String]| list.iter().any(|d| covers(d, &entry));
+    let path = |list: &[String]| list.iter().any(|p| has_prefix(&entry, p));
+    match field {
+        "allowed_domains" => domain(&base.allowed_domains),
+        "ask_domains" => domain(&base.allowed_domains) || domain(&base.ask_domains),
+        "allowed_paths" => base.allowed_paths.is_empty() || path(&base.allowed_paths),
+        "ask_paths" => {
+            base.allowed_paths.is_empty() || path(&base.allowed_paths) || path(&base.ask_paths)
+        }
+        "writable" => base.writable.contains(&entry),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, content: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, content).expect("write");
+        path
+    }
+
+    #[test]
+    fn overlays_tighten_unless_loosening_is_marked() {
+        let dir = std::env::temp_dir().join(format!("saf-compose-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("dir");
+        write(
+            &dir,
+            "base.toml",
+            r#"
+            allowed_domains = ["*.corp.net"]
+            denied_domains = ["vault.corp.net"]
+            deny = ["secrets/**"]
+            max_read_bytes = 1000
+
+            [[url_rules]]
+            domain = "api.corp.net"
+            paths = ["/v1/**"]
+
+            [budgets]
+            net_requests = 100
+            "#,
+        );
+        let load_file = |name: &str, content: &str| {
+            let path = write(&dir, name, content);
+            load(&path, content, &|_, _| Ok(()), &mut Vec::new())
+        };
+
+        let app = load_file(
+            "app.toml",
+            r#"
+            extends = "base.toml"
+            allowed_domains = ["wiki.corp.net"]
+            deny = ["keys/**"]
+            max_read_bytes = 500
+
+            [budgets]
+            fs_writes = 10
+            "#,
+        )
+        .expect("tightening overlay");
+        assert_eq!(app.allowed_domains, ["wiki.corp.net"]);
+        assert_eq!(app.denied_domains, ["vault.corp.net"]);
+        assert_eq!(app.deny, ["secrets/**", "keys/**"]);
+        assert_eq!(app.max_read_bytes, 500);
+        assert_eq!(app.budgets.net_requests, Some(100));
+        assert_eq!(app.budgets.fs_writes, Some(10));
+
+        let err = load_file(
+            "wide.toml",
+            r#"
+            extends = "base.toml"
+            allowed_domains = ["example.org"]
+            max_read_bytes = 5000
+            "#,
+        )
+        .unwrap_err();
+        assert!(err.contains("allowed_domains + \"example.org\""), "{err}");
+        assert!(err.contains("max_read_bytes 1000 -> 5000"), "{err}");
+
+        // Another rule for a restricted domain admits more of it.
+        let err = load_file(
+            "rules.toml",
+            r#"
+            extends = "base.toml"
+
+            [[url_rules]]
+            domain = "api.corp.net"
+            paths = ["/admin/**"]
+            "#,
+        )
+        .unwrap_err();
+        assert!(err.contains("url_rules + "), "{err}");
+        let ruled = load_file(
+            "ruled.toml",
+            r#"
+            extends = "base.toml"
+
+            [[url_rules]]
+            domain = "wiki.corp.net"
+            paths = ["/pages/**"]
+            "#,
+        )
+        .expect("a first rule for a domain restricts it");
+        assert_eq!(ruled.url_rules.len(), 2);
+
+        let loosened = load_file(
+            "loose.toml",
+            r#"
+            extends = "base.toml"
+            loosen = ["allowed_domains"]
+            allowed_domains = ["example.org"]
+            "#,
+        )
+        .expect("marked loosening");
+        assert_eq!(loosened.allowed_domains, ["*.corp.net", "example.org"]);
+        assert!(!loosened.is_url_allowed("https://vault.corp.net/"));
+
+        let cycle = load_file("cycle.toml", "extends = \"cycle.toml\"").unwrap_err();
+        assert!(cycle.contains("cycle"), "{cycle}");
+        let typo = load_file(
+            "typo.toml",
+            "extends = \"base.toml\"\nloosen = [\"allowed\"]",
+        )
+        .unwrap_err();
+        assert!(typo.contains("unknown field \"allowed\""), "{typo}");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
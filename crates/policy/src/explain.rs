@@ -0,0 +1,506 @@
+//! Rule tracing and policy comparison for `broker policy explain` and
+//! `broker policy diff`.
+//!
+//! [`Policy::explain_fs`] and [`Policy::explain_request`] list every rule
+//! that matches an operation, in the order the checks consult them; the
+//! first deciding match is what the `check_*` functions report. [`diff`]
+//! compares two policies field by field and says whether each change
+//! widens or narrows what a component may do.
+
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+
+use serde_json::Value;
+
+use crate::expr::{Attributes, Expr};
+use crate::{domain_matches, glob, has_prefix, FsAccess, Policy};
+
+/// A rule that matched, e.g. `deny[0]` with pattern `"secrets/**"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub rule: String,
+    pub detail: String,
+}
+
+impl Match {
+    fn new(rule: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            rule: rule.into(),
+            detail: detail.into(),
+        }
+    }
+}
+
+impl Display for Match {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.rule, self.detail)
+    }
+}
+
+impl Policy {
+    /// Rules matching `access` to the sanitized `path`.
+    pub fn explain_fs(&self, path: &str, access: FsAccess, attrs: &Attributes<'_>) -> Vec<Match> {
+        let mut out = Vec::new();
+        prefixes(&mut out, "denied_paths", &self.denied_paths, path);
+        globs(&mut out, "deny", &self.deny, path);
+        if access == FsAccess::Write {
+            globs(&mut out, "read_only", &self.read_only, path);
+            if self.writable_only {
+                out.push(Match::new("writable_only", "= true"));
+                globs(&mut out, "writable", &self.writable, path);
+            }
+        }
+        if self.allowed_paths.is_empty() {
+            out.push(Match::new("allowed_paths", "(empty: the whole workspace)"));
+        }
+        prefixes(&mut out, "allowed_paths", &self.allowed_paths, path);
+        prefixes(&mut out, "ask_paths", &self.ask_paths, path);
+        self.explain_conditions(&mut out, attrs);
+        out
+    }
+
+    /// Rules matching a request to `url`; `method` is `None` for a
+    /// WebSocket connection.
+    pub fn explain_request(
+        &self,
+        method: Option<&str>,
+        url: &str,
+        attrs: &Attributes<'_>,
+    ) -> Vec<Match> {
+        let mut out = Vec::new();
+        self.explain_conditions(&mut out, attrs);
+        if self.offline {
+            out.push(Match::new("offline", "= true"));
+        }
+        let mut url = url.to_string();
+        if method.is_some() {
+            if let Some((i, rewritten)) = self
+                .url_rewrites
+                .iter()
+                .enumerate()
+                .find_map(|(i, r)| Some((i, r.apply(&url)?)))
+            {
+                let r = &self.url_rewrites[i];
+                url = rewritten;
+                out.push(Match::new(
+                    format!("url_rewrites[{i}]"),
+                    format!("{:?} -> {:?}", r.from, r.to),
+                ));
+            }
+        }
+        let parsed = url::Url::parse(&url).ok();
+        let host = parsed
+            .as_ref()
+            .and_then(|u| u.host_str())
+            .unwrap_or_default();
+        if let Some(parsed) = &parsed {
+            for (i, origin) in self.origins.iter().enumerate() {
+                if domain_matches(&origin.domain, host) {
+                    let verdict = if origin.admits(parsed) {
+                        "admits"
+                    } else {
+                        "does not admit"
+                    };
+                    let port = parsed.port_or_known_default().unwrap_or_default();
+                    out.push(Match::new(
+                        format!("origins[{i}]"),
+                        format!("{:?} {verdict} {}:{port}", origin.domain, parsed.scheme()),
+                    ));
+                }
+            }
+        }
+        for (field, domains) in [
+            ("denied_domains", &self.denied_domains),
+            ("allowed_domains", &self.allowed_domains),
+            ("ask_domains", &self.ask_domains),
+        ] {
+            for (i, d) in domains.iter().enumerate() {
+                if domain_matches(d, host) {
+                    out.push(Match::new(format!("{field}[{i}]"), format!("{d:?}")));
+                }
+            }
+        }
+        if let (Some(method), Some(parsed)) = (method, &parsed) {
+            for (i, rule) in self.url_rules.iter().enumerate() {
+                if domain_matches(&rule.domain, host) {
+                    let verdict = if rule.admits(method, parsed.path()) {
+                        "admits"
+                    } else {
+                        "does not admit"
+                    };
+                    out.push(Match::new(
+                        format!("url_rules[{i}]"),
+                        format!("{:?} {verdict} {method} {}", rule.domain, parsed.path()),
+                    ));
+                }
+            }
+        }
+        if let Some(limit) = self.rate_limits.get(host) {
+            out.push(Match::new(
+                format!("rate_limits.{host:?}"),
+                format!(
+                    "{} per minute, burst {}",
+                    limit.requests_per_minute, limit.burst
+                ),
+            ));
+        }
+        out
+    }
+
+    /// Conditions whose `when` holds, with whether `require` held too.
+    fn explain_conditions(&self, out: &mut Vec<Match>, attrs: &Attributes<'_>) {
+        let holds = |src: &str| Expr::parse(src).and_then(|e| e.eval(attrs));
+        for (i, c) in self.conditions.iter().enumerate() {
+            let detail = match holds(&c.when) {
+                Ok(false) => continue,
+                Ok(true) => match holds(&c.require) {
+                    Ok(true) => format!("when {:?} holds; require holds", c.when),
+                    Ok(false) => format!("when {:?} holds; require {:?} fails", c.when, c.require),
+                    Err(e) => format!("require failed to evaluate: {e}"),
+                },
+                Err(e) => format!("when failed to evaluate: {e}"),
+            };
+            out.push(Match::new(format!("conditions[{i}]"), detail));
+        }
+    }
+}
+
+fn prefixes(out: &mut Vec<Match>, field: &str, prefixes: &[String], path: &str) {
+    for (i, p) in prefixes.iter().enumerate() {
+        if has_prefix(path, p) {
+            out.push(Match::new(format!("{field}[{i}]"), format!("{p:?}")));
+        }
+    }
+}
+
+fn globs(out: &mut Vec<Match>, field: &str, globs: &[String], path: &str) {
+    for (i, g) in globs.iter().enumerate() {
+        if glob::matches_path(g, path) {
+            out.push(Match::new(format!("{field}[{i}]"), format!("{g:?}")));
+        }
+    }
+}
+
+/// Whether a change gives a component more or less than before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    Wider,
+    Narrower,
+    /// Neither, or not decidable from the field alone (e.g. a new mirror).
+    Changed,
+}
+
+impl Effect {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Wider => "wider",
+            Self::Narrower => "narrower",
+            Self::Changed => "changed",
+        }
+    }
+}
+
+/// One difference between two policies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub effect: Effect,
+    /// Field path, e.g. `allowed_domains` or `components."app".offline`.
+    pub field: String,
+    /// `+ value`, `- value` or `old -> new`.
+    pub change: String,
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:<9} {} {}",
+            self.effect.as_str(),
+            self.field,
+            self.change
+        )
+    }
+}
+
+/// Lists where an added entry grants more.
+pub(crate) const GRANTING_LISTS: &[&str] = &[
+    "allowed_domains",
+    "allowed_paths",
+    "ask_domains",
+    "ask_paths",
+    "writable",
+    "config_keys",
+];
+/// Lists and maps where an added entry takes something away.
+pub(crate) const RESTRICTING: &[&str] = &[
+    "denied_domains",
+    "denied_paths",
+    "read_only",
+    "deny",
+    "conditions",
+    "denied_ip_ranges",
+    "rate_limits",
+];
+/// Fields where any change counts as widening: what they grant cannot be
+/// weighed from the entry alone.
+pub(crate) const WIDENING: &[&str] = &["origins", "client_certs", "dns_over_https"];
+
+/// Fields that are maps keyed by domain or component id.
+pub(crate) const MAPS: &[&str] = &["rate_limits", "client_certs", "components"];
+
+/// Differences from `old` to `new`, in field order.
+pub fn diff(old: &Policy, new: &Policy) -> Vec<Change> {
+    let mut out = Vec::new();
+    match (serde_json::to_value(old), serde_json::to_value(new)) {
+        (Ok(old), Ok(new)) => diff_value(&mut out, "", "", &old, &new),
+        _ => out.push(Change {
+            effect: Effect::Changed,
+            field: String::new(),
+            change: "policies could not be compared".to_string(),
+        }),
+    }
+    out
+}
+
+/// Compare `old` and `new` at `path`, which lies within the policy field
+/// `field` (empty for the policy itself).
+fn diff_value(out: &mut Vec<Change>, path: &str, field: &str, old: &Value, new: &Value) {
+    let mut push = |effect, change: String| {
+        out.push(Change {
+            effect,
+            field: path.to_string(),
+            change,
+        })
+    };
+    match (old, new) {
+        (Value::Object(o), Value::Object(n)) => {
+            // Map keys are quoted, as in validation messages.
+            let keyed = path == field && MAPS.contains(&field);
+            let keys: BTreeSet<&String> = o.keys().chain(n.keys()).collect();
+            for key in keys {
+                let (sub_path, sub_field) = match (path.is_empty(), keyed) {
+                    (true, _) => (key.clone(), key.as_str()),
+                    (false, true) => (format!("{path}.{key:?}"), field),
+                    (false, false) => (format!("{path}.{key}"), field),
+                };
+                match (o.get(key), n.get(key)) {
+                    // A component's policy is a whole policy of its own.
+                    (Some(ov), Some(nv)) if keyed && field == "components" => {
+                        let mut nested = Vec::new();
+                        diff_value(&mut nested, "", "", ov, nv);
+                        out.extend(nested.into_iter().map(|c| Change {
+                            field: format!("{sub_path}.{}", c.field),
+                            ..c
+                        }));
+                    }
+                    (Some(ov), Some(nv)) => diff_value(out, &sub_path, sub_field, ov, nv),
+                    (None, Some(nv)) => out.push(Change {
+                        effect: entry_effect(field, true),
+                        field: sub_path,
+                        change: format!("+ {nv}"),
+                    }),
+                    (Some(ov), None) => out.push(Change {
+                        effect: entry_effect(field, false),
+                        field: sub_path,
+                        change: format!("- {ov}"),
+                    }),
+                    (None, None) => {}
+                }
+            }
+        }
+        (Value::Array(o), Value::Array(n)) => {
+            // An empty `allowed_paths` is the whole workspace, so the first
+            // entry narrows and removing the last widens.
+            if field == "allowed_paths" && o.is_empty() != n.is_empty() {
+                let effect = if o.is_empty() {
+                    Effect::Narrower
+                } else {
+                    Effect::Wider
+                };
+                push(effect, format!("{old} -> {new}"));
+                return;
+            }
+            let old_set: BTreeSet<String> = o.iter().map(Value::to_string).collect();
+            let new_set: BTreeSet<String> = n.iter().map(Value::to_string).collect();
+            let effect = |entry: &str, added: bool| {
+                if field == "url_rules" {
+                    url_rule_effect(entry, if added { o } else { n }, added)
+                } else {
+                    entry_effect(field, added)
+                }
+            };
+            for added in new_set.difference(&old_set) {
+                push(effect(added, true), format!("+ {added}"));
+            }
+            for removed in old_set.difference(&new_set) {
+                push(effect(removed, false), format!("- {removed}"));
+            }
+        }
+        (o, n) if o != n => push(scalar_effect(field, o, n), format!("{o} -> {n}")),
+        _ => {}
+    }
+}
+
+fn entry_effect(field: &str, added: bool) -> Effect {
+    let wider = if WIDENING.contains(&field) {
+        true
+    } else if GRANTING_LISTS.contains(&field) {
+        added
+    } else if RESTRICTING.contains(&field) {
+        !added
+    } else {
+        return Effect::Changed;
+    };
+    if wider {
+        Effect::Wider
+    } else {
+        Effect::Narrower
+    }
+}
+
+/// A `url_rules` entry added, weighed `against` the old rules, or removed,
+/// weighed against the new ones. A domain's first rule restricts it, but
+/// another rule for a domain already restricted admits more of it, and
+/// dropping its last rule lifts the restriction.
+fn url_rule_effect(entry: &str, against: &[Value], added: bool) -> Effect {
+    let domain_of = |v: &Value| v.get("domain").and_then(Value::as_str).map(str::to_string);
+    let Some(domain) = serde_json::from_str::<Value>(entry)
+        .ok()
+        .as_ref()
+        .and_then(domain_of)
+    else {
+        return Effect::Wider;
+    };
+    let mut domains = against.iter().filter_map(domain_of);
+    let restricted = if added {
+        domains.any(|d| domain_matches(&d, &domain) || domain_matches(&domain, &d))
+    } else {
+        domains.any(|d| d == domain || domain_matches(&d, &domain))
+    };
+    if restricted == added {
+        Effect::Wider
+    } else {
+        Effect::Narrower
+    }
+}
+
+fn scalar_effect(field: &str, old: &Value, new: &Value) -> Effect {
+    let limit = field.starts_with("max_") || matches!(field, "budgets" | "memory" | "rate_limits");
+    let wider = match (old, new) {
+        _ if WIDENING.contains(&field) => true,
+        (Value::Bool(_), Value::Bool(n))
+            if matches!(field, "offline" | "strict" | "writable_only") =>
+        {
+            !n
+        }
+        (Value::Bool(_), Value::Bool(n)) if field == "sysinfo" => *n,
+        // `None` limits are unlimited.
+        (Value::Number(_), Value::Null) if limit => true,
+        (Value::Null, Value::Number(_)) if limit => false,
+        (Value::Number(o), Value::Number(n)) if limit => n.as_f64() > o.as_f64(),
+        _ => return Effect::Changed,
+    };
+    if wider {
+        Effect::Wider
+    } else {
+        Effect::Narrower
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Condition;
+
+    #[test]
+    fn explains_matches_and_diffs_policies() {
+        let policy = Policy {
+            allowed_paths: vec!["exports".to_string()],
+            read_only: vec!["exports/*.txt".to_string()],
+            conditions: vec![Condition {
+                when: "access == 'write'".to_string(),
+                require: "size != null".to_string(),
+            }],
+            ..Policy::new()
+        }
+        .with_allowed_domains(vec!["*.example.org".to_string()]);
+        let attrs = Attributes {
+            access: Some("write"),
+            ..Attributes::default()
+        };
+        let rules: Vec<String> = policy
+            .explain_fs("exports/a.txt", FsAccess::Write, &attrs)
+            .iter()
+            .map(|m| m.rule.clone())
+            .collect();
+        assert_eq!(rules, ["read_only[0]", "allowed_paths[0]", "conditions[0]"]);
+        let rules: Vec<String> = policy
+            .explain_request(
+                Some("GET"),
+                "https://api.example.org/",
+                &Attributes::default(),
+            )
+            .iter()
+            .map(|m| m.rule.clone())
+            .collect();
+        assert_eq!(rules, ["allowed_domains[0]"]);
+
+        let mut new = policy.clone();
+        new.allowed_domains.push("example.com".to_string());
+        new.deny.push("secrets/**".to_string());
+        new.offline = true;
+        new.strict = true;
+        new.max_read_bytes = 1024;
+        new.budgets.net_requests = Some(10);
+        new.memory.max_memory_bytes = 512 * 1024 * 1024;
+        let changes: Vec<String> = diff(&policy, &new).iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            changes,
+            [
+                "wider     allowed_domains + \"example.com\"",
+                "narrower  budgets.net_requests null -> 10",
+                "narrower  deny + \"secrets/**\"",
+                "narrower  max_read_bytes 10485760 -> 1024",
+                "wider     memory.max_memory_bytes 268435456 -> 536870912",
+                "narrower  offline false -> true",
+                "narrower  strict false -> true",
+            ]
+        );
+        assert_eq!(diff(&policy, &policy), []);
+
+        // A domain's first url rule restricts it; another one admits more.
+        let rule = |domain: &str, path: &str| crate::UrlRule {
+            domain: domain.to_string(),
+            paths: vec![path.to_string()],
+            methods: Vec::new(),
+        };
+        let effects = |old: &Policy, new: &Policy| -> Vec<Effect> {
+            diff(old, new).iter().map(|c| c.effect).collect()
+        };
+        let mut ruled = policy.clone();
+        ruled.url_rules.push(rule("api.example.org", "/v1/**"));
+        assert_eq!(effects(&policy, &ruled), [Effect::Narrower]);
+        assert_eq!(effects(&ruled, &policy), [Effect::Wider]);
+        let mut more = ruled.clone();
+        more.url_rules.push(rule("*.example.org", "/admin/**"));
+        assert_eq!(effects(&ruled, &more), [Effect::Wider]);
+        // Dropping it frees the other subdomains.
+        assert_eq!(effects(&more, &ruled), [Effect::Wider]);
+        let mut fewer = more.clone();
+        fewer.url_rules.remove(0);
+        assert_eq!(effects(&more, &fewer), [Effect::Narrower]);
+
+        // Certificates and resolvers are never a narrowing.
+        let mut certs = policy.clone();
+        certs.client_certs.insert(
+            "api.example.org".to_string(),
+            crate::ClientCert {
+                cert: "a.pem".to_string(),
+                key: "a.key".to_string(),
+            },
+        );
+        certs.dns_over_https = Some("https://dns.example.org/dns-query".to_string());
+        assert_eq!(effects(&policy, &certs), [Effect::Wider, Effect::Wider]);
+        assert_eq!(effects(&certs, &policy), [Effect::Wider, Effect::Wider]);
+    }
+}
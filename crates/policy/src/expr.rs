@@ -0,0 +1,602 @@
+//! Condition expressions for `conditions` rules.
+//!
+//! A small CEL-like language over request attributes:
+//!
+//! ```text
+//! capability == "fs" && access == "write" && path.startsWith("exports/")
+//! hour >= 9 && hour < 17 && size < 5 * 1024 * 1024
+//! host in ["a.example.org", "b.example.org"]
+//! ```
+//!
+//! Values are booleans, integers, strings, lists and `null`. Operators are
+//! `|| && !`, comparisons, `in` (list membership) and integer `+ - *`;
+//! strings have `startsWith`, `endsWith` and `contains`. Attributes are:
+//!
+//! | name         | value                                                  |
+//! |--------------|--------------------------------------------------------|
+//! | `capability` | `"fs"`, `"net"` or `"ws"`                              |
+//! | `access`     | `"read"` or `"write"` for fs, `null` otherwise        |
+//! | `method`     | HTTP method for net, `null` otherwise                  |
+//! | `path`       | workspace-relative path for fs, URL path for net/ws    |
+//! | `url`, `host`| request URL and its host; `null` for fs                |
+//! | `size`       | bytes being written, `null` when not known             |
+//! | `component`  | name of the running component                          |
+//! | `hour`, `minute`, `weekday` | current UTC time; `weekday` 0 is Sunday |
+//!
+//! Time is UTC so the host's time zone setting cannot move a window.
+//! Evaluation is bounded: source length, nesting depth and the work done per
+//! evaluation are capped, and any error (a type mismatch, comparing `null`,
+//! running out of budget) is reported rather than guessed around.
+
+use std::fmt::{Display, Formatter};
+
+const MAX_SOURCE_LEN: usize = 2048;
+const MAX_DEPTH: usize = 32;
+/// Work units per evaluation: one per node visited plus one per 64 bytes of
+/// string handled.
+const MAX_COST: u64 = 10_000;
+
+const ATTRIBUTES: &[&str] = &[
+    "capability",
+    "access",
+    "method",
+    "path",
+    "url",
+    "host",
+    "size",
+    "component",
+    "hour",
+    "minute",
+    "weekday",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    List(Vec<Value>),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::Bool(_) => "bool",
+            Self::Int(_) => "int",
+            Self::Str(_) => "string",
+            Self::List(_) => "list",
+        }
+    }
+}
+
+/// What a condition can see about a request; `None` reads as `null`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Attributes<'a> {
+    pub capability: &'a str,
+    pub access: Option<&'a str>,
+    pub method: Option<&'a str>,
+    pub path: Option<&'a str>,
+    pub url: Option<&'a str>,
+    pub size: Option<u64>,
+    pub component: &'a str,
+    /// Seconds since the Unix epoch.
+    pub now: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    In,
+    Add,
+    Sub,
+    Mul,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Method {
+    StartsWith,
+    EndsWith,
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Lit(Value),
+    Attr(&'static str),
+    Not(Box<Node>),
+    Neg(Box<Node>),
+    Bin(BinOp, Box<Node>, Box<Node>),
+    Call(Box<Node>, Method, Box<Node>),
+    List(Vec<Node>),
+}
+
+/// A parsed condition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    root: Node,
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        if source.len() > MAX_SOURCE_LEN {
+            return Err(format!("longer than {MAX_SOURCE_LEN} bytes"));
+        }
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+            depth: 0,
+        };
+        let root = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(Self { root }),
+            Some(t) => Err(format!("unexpected {t}")),
+        }
+    }
+
+    /// Evaluate to a boolean; any other result is an error.
+    pub fn eval(&self, attrs: &Attributes<'_>) -> Result<bool, String> {
+        let mut eval = Eval {
+            attrs,
+            // Parsed once, however often `host` or `path` is read.
+            url: attrs.url.and_then(|u| url::Url::parse(u).ok()),
+            cost: 0,
+        };
+        match eval.node(&self.root)? {
+            Value::Bool(b) => Ok(b),
+            v => Err(format!("expected a bool result, got {}", v.type_name())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Int(i64),
+    Str(String),
+    Ident(String),
+    Punct(&'static str),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Int(i) => write!(f, "{i}"),
+            Self::Str(s) => write!(f, "{s:?}"),
+            Self::Ident(s) => f.write_str(s),
+            Self::Punct(p) => write!(f, "`{p}`"),
+        }
+    }
+}
+
+const PUNCT: &[&str] = &[
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "!", "+", "-", "*", "(", ")", "[", "]", ",", ".",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let n = rest[..end]
+                .parse()
+                .map_err(|_| format!("integer {} out of range", &rest[..end]))?;
+            tokens.push(Token::Int(n));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if c == '"' || c == '\'' {
+            let mut s = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    None => return Err("unterminated string".to_string()),
+                    Some((i, q)) if q == c => break i + 2,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, 'n')) => s.push('\n'),
+                        Some((_, e @ ('\\' | '"' | '\''))) => s.push(e),
+                        _ => return Err("invalid escape in string".to_string()),
+                    },
+                    Some((_, ch)) => s.push(ch),
+                }
+            };
+            tokens.push(Token::Str(s));
+            rest = &rest[end..];
+        } else if let Some(p) = PUNCT.iter().find(|p| rest.starts_with(**p)) {
+            tokens.push(Token::Punct(p));
+            rest = &rest[p.len()..];
+        } else {
+            return Err(format!("unexpected character {c:?}"));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn eat(&mut self, punct: &str) -> bool {
+        let found = matches!(self.tokens.get(self.pos), Some(Token::Punct(p)) if *p == punct);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), String> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            match self.tokens.get(self.pos) {
+                Some(t) => Err(format!("expected `{punct}`, found {t}")),
+                None => Err(format!("expected `{punct}` at end of input")),
+            }
+        }
+    }
+
+    fn nest(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(format!("nested deeper than {MAX_DEPTH} levels"));
+        }
+        Ok(())
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        let mut lhs = self.and()?;
+        while self.eat("||") {
+            lhs = Node::Bin(BinOp::Or, Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        let mut lhs = self.relation()?;
+        while self.eat("&&") {
+            lhs = Node::Bin(BinOp::And, Box::new(lhs), Box::new(self.relation()?));
+        }
+        Ok(lhs)
+    }
+
+    fn relation(&mut self) -> Result<Node, String> {
+        let lhs = self.sum()?;
+        let op = match self.tokens.get(self.pos) {
+            Some(Token::Punct("==")) => BinOp::Eq,
+            Some(Token::Punct("!=")) => BinOp::Ne,
+            Some(Token::Punct("<")) => BinOp::Lt,
+            Some(Token::Punct("<=")) => BinOp::Le,
+            Some(Token::Punct(">")) => BinOp::Gt,
+            Some(Token::Punct(">=")) => BinOp::Ge,
+            Some(Token::Ident(kw)) if kw == "in" => BinOp::In,
+            _ => return Ok(lhs),
+        };
+        self.pos += 1;
+        Ok(Node::Bin(op, Box::new(lhs), Box::new(self.sum()?)))
+    }
+
+    fn sum(&mut self) -> Result<Node, String> {
+        let mut lhs = self.product()?;
+        loop {
+            let op = if self.eat("+") {
+                BinOp::Add
+            } else if self.eat("-") {
+                BinOp::Sub
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Node::Bin(op, Box::new(lhs), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Node, String> {
+        let mut lhs = self.unary()?;
+        while self.eat("*") {
+            lhs = Node::Bin(BinOp::Mul, Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        self.nest()?;
+        let node = if self.eat("!") {
+            Node::Not(Box::new(self.unary()?))
+        } else if self.eat("-") {
+            Node::Neg(Box::new(self.unary()?))
+        } else {
+            self.postfix()?
+        };
+        self.depth -= 1;
+        Ok(node)
+    }
+
+    fn postfix(&mut self) -> Result<Node, String> {
+        let mut node = self.primary()?;
+        while self.eat(".") {
+            let method = match self.tokens.get(self.pos) {
+                Some(Token::Ident(m)) if m == "startsWith" => Method::StartsWith,
+                Some(Token::Ident(m)) if m == "endsWith" => Method::EndsWith,
+                Some(Token::Ident(m)) if m == "contains" => Method::Contains,
+                Some(t) => return Err(format!("unknown method {t}")),
+                None => return Err("expected a method name".to_string()),
+            };
+            self.pos += 1;
+            self.expect("(")?;
+            let arg = self.or()?;
+            self.expect(")")?;
+            node = Node::Call(Box::new(node), method, Box::new(arg));
+        }
+        Ok(node)
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or("unexpected end of input")?;
+        self.pos += 1;
+        match token {
+            Token::Int(i) => Ok(Node::Lit(Value::Int(i))),
+            Token::Str(s) => Ok(Node::Lit(Value::Str(s))),
+            Token::Ident(id) => match id.as_str() {
+                "true" => Ok(Node::Lit(Value::Bool(true))),
+                "false" => Ok(Node::Lit(Value::Bool(false))),
+                "null" => Ok(Node::Lit(Value::Null)),
+                _ => ATTRIBUTES
+                    .iter()
+                    .find(|a| **a == id)
+                    .map(|a| Node::Attr(a))
+                    .ok_or_else(|| format!("unknown attribute {id:?}")),
+            },
+            Token::Punct("(") => {
+                let node = self.or()?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Token::Punct("[") => {
+                let mut items = Vec::new();
+                if !self.eat("]") {
+                    loop {
+                        items.push(self.or()?);
+                        if self.eat("]") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Ok(Node::List(items))
+            }
+            t => Err(format!("unexpected {t}")),
+        }
+    }
+}
+
+struct Eval<'a, 'b> {
+    attrs: &'a Attributes<'b>,
+    url: Option<url::Url>,
+    cost: u64,
+}
+
+impl Eval<'_, '_> {
+    fn attr(&self, name: &str) -> Value {
+        let a = self.attrs;
+        let str_or_null = |s: Option<&str>| s.map_or(Value::Null, |s| Value::Str(s.to_string()));
+        let secs_of_day = a.now % 86_400;
+        match name {
+            "capability" => Value::Str(a.capability.to_string()),
+            "access" => str_or_null(a.access),
+            "method" => str_or_null(a.method),
+            "path" => str_or_null(a.path.or(self.url.as_ref().map(url::Url::path))),
+            "url" => str_or_null(a.url),
+            "host" => str_or_null(self.url.as_ref().and_then(url::Url::host_str)),
+            "size" => a.size.map_or(Value::Null, |s| {
+                Value::Int(i64::try_from(s).unwrap_or(i64::MAX))
+            }),
+            "component" => Value::Str(a.component.to_string()),
+            "hour" => Value::Int((secs_of_day / 3600) as i64),
+            "minute" => Value::Int((secs_of_day % 3600 / 60) as i64),
+            // 1970-01-01 was a Thursday.
+            "weekday" => Value::Int(((a.now / 86_400 + 4) % 7) as i64),
+            _ => Value::Null,
+        }
+    }
+
+    fn charge(&mut self, units: u64) -> Result<(), String> {
+        self.cost += units;
+        if self.cost > MAX_COST {
+            return Err("evaluation budget exceeded".to_string());
+        }
+        Ok(())
+    }
+
+    fn bool(&mut self, node: &Node) -> Result<bool, String> {
+        match self.node(node)? {
+            Value::Bool(b) => Ok(b),
+            v => Err(format!("expected bool, got {}", v.type_name())),
+        }
+    }
+
+    fn node(&mut self, node: &Node) -> Result<Value, String> {
+        self.charge(1)?;
+        match node {
+            Node::Lit(v) => Ok(v.clone()),
+            Node::Attr(name) => {
+                let v = self.attr(name);
+                if let Value::Str(s) = &v {
+                    self.charge(s.len() as u64 / 64)?;
+                }
+                Ok(v)
+            }
+            Node::Not(inner) => Ok(Value::Bool(!self.bool(inner)?)),
+            Node::Neg(inner) => match self.node(inner)? {
+                Value::Int(i) => i
+                    .checked_neg()
+                    .map(Value::Int)
+                    .ok_or_else(|| "integer overflow".to_string()),
+                v => Err(format!("cannot negate {}", v.type_name())),
+            },
+            Node::List(items) => items
+                .iter()
+                .map(|n| self.node(n))
+                .collect::<Result<_, _>>()
+                .map(Value::List),
+            // Short-circuit, so a guard like `size != null && size > 10`
+            // never compares null.
+            Node::Bin(BinOp::Or, l, r) => Ok(Value::Bool(self.bool(l)? || self.bool(r)?)),
+            Node::Bin(BinOp::And, l, r) => Ok(Value::Bool(self.bool(l)? && self.bool(r)?)),
+            Node::Bin(op, l, r) => {
+                let (l, r) = (self.node(l)?, self.node(r)?);
+                self.binary(*op, l, r)
+            }
+            Node::Call(target, method, arg) => {
+                let (target, arg) = match (self.node(target)?, self.node(arg)?) {
+                    (Value::Str(t), Value::Str(a)) => (t, a),
+                    (t, a) => {
+                        return Err(format!(
+                            "string methods need strings, got {} and {}",
+                            t.type_name(),
+                            a.type_name()
+                        ))
+                    }
+                };
+                self.charge((target.len() + arg.len()) as u64 / 64)?;
+                Ok(Value::Bool(match method {
+                    Method::StartsWith => target.starts_with(&arg),
+                    Method::EndsWith => target.ends_with(&arg),
+                    Method::Contains => target.contains(&arg),
+                }))
+            }
+        }
+    }
+
+    fn binary(&mut self, op: BinOp, l: Value, r: Value) -> Result<Value, String> {
+        let mismatch = |l: &Value, r: &Value| {
+            format!(
+                "cannot apply {op:?} to {} and {}",
+                l.type_name(),
+                r.type_name()
+            )
+        };
+        let overflow = || "integer overflow".to_string();
+        match (op, &l, &r) {
+            (BinOp::Eq, _, _) => Ok(Value::Bool(l == r)),
+            (BinOp::Ne, _, _) => Ok(Value::Bool(l != r)),
+            (BinOp::In, _, Value::List(items)) => {
+                self.charge(items.len() as u64)?;
+                Ok(Value::Bool(items.contains(&l)))
+            }
+            (BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge, _, _) => {
+                let ord = match (&l, &r) {
+                    (Value::Int(a), Value::Int(b)) => a.cmp(b),
+                    (Value::Str(a), Value::Str(b)) => a.cmp(b),
+                    _ => return Err(mismatch(&l, &r)),
+                };
+                Ok(Value::Bool(match op {
+                    BinOp::Lt => ord.is_lt(),
+                    BinOp::Le => ord.is_le(),
+                    BinOp::Gt => ord.is_gt(),
+                    _ => ord.is_ge(),
+                }))
+            }
+            (BinOp::Add, Value::Int(a), Value::Int(b)) => {
+                a.checked_add(*b).map(Value::Int).ok_or_else(overflow)
+            }
+            (BinOp::Sub, Value::Int(a), Value::Int(b)) => {
+                a.checked_sub(*b).map(Value::Int).ok_or_else(overflow)
+            }
+            (BinOp::Mul, Value::Int(a), Value::Int(b)) => {
+                a.checked_mul(*b).map(Value::Int).ok_or_else(overflow)
+            }
+            _ => Err(mismatch(&l, &r)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluates_conditions_over_request_attributes() {
+        // 2024-01-01 was a Monday; 10:30 UTC.
+        let monday_morning = 1_704_067_200 + 10 * 3600 + 30 * 60;
+        let write = Attributes {
+            capability: "fs",
+            access: Some("write"),
+            path: Some("exports/report.csv"),
+            size: Some(4 * 1024 * 1024),
+            component: "app",
+            now: monday_morning,
+            ..Attributes::default()
+        };
+        let eval = |src: &str, attrs: &Attributes<'_>| Expr::parse(src).and_then(|e| e.eval(attrs));
+
+        let office_hours = "hour >= 9 && hour < 17 && weekday in [1, 2, 3, 4, 5] && minute == 30";
+        assert_eq!(eval(office_hours, &write), Ok(true));
+        assert_eq!(
+            eval(
+                "access == 'write' && path.startsWith(\"exports/\") && size < 5 * 1024 * 1024",
+                &write
+            ),
+            Ok(true)
+        );
+        // `||` and `&&` short-circuit past what would be a type error.
+        assert_eq!(
+            eval("!(component != \"app\") || size < 'x'", &write),
+            Ok(true)
+        );
+
+        let fetch = Attributes {
+            capability: "net",
+            method: Some("GET"),
+            url: Some("https://api.example.org/v1/items"),
+            ..Attributes::default()
+        };
+        assert_eq!(
+            eval(
+                "host.endsWith('.example.org') && path.contains('/v1/')",
+                &fetch
+            ),
+            Ok(true)
+        );
+        // `null` can be tested for, but not compared.
+        assert_eq!(eval("size == null || size < 10", &fetch), Ok(true));
+        assert!(eval("size < 10", &fetch).is_err());
+
+        assert!(Expr::parse("sise > 1").unwrap_err().contains("sise"));
+        assert!(Expr::parse("path.matches('x')").is_err());
+        assert!(Expr::parse(&"!".repeat(100)).is_err());
+        assert!(eval("1 + 2", &write).is_err());
+        assert!(Expr::parse(&format!("'{}'", "x".repeat(MAX_SOURCE_LEN))).is_err());
+        // The work done scales with the strings a request brings along.
+        let url = format!("https://example.org/{}", "a".repeat(1_000_000));
+        let huge = Attributes {
+            url: Some(&url),
+            ..fetch
+        };
+        assert_eq!(
+            eval("url.contains('b')", &huge),
+            Err("evaluation budget exceeded".to_string())
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! Policies loaded from disk.
+//!
+//! A policy file is TOML (or JSON when the extension is `.json`) with the
+//! same field names as [`Policy`]; omitted fields keep their defaults and
+//! unknown fields are rejected so a typo cannot silently widen or drop a
+//! restriction. After parsing, [`Policy::validate`] checks values serde
+//! cannot, reporting every problem at once. A file may build on another
+//! with `extends`; see [`crate::compose`].
+
+use std::path::{Path, PathBuf};
+
+use crate::{compose, Policy, PolicyIssue};
+
+impl Policy {
+    pub fn from_file(path: &Path) -> Result<Policy, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read policy {}: {}", path.display(), e))?;
+        Self::from_file_content(path, &content)
+    }
+
+    /// Parse `content` already read from `path`, whose extension picks the
+    /// format.
+    pub fn from_file_content(path: &Path, content: &str) -> Result<Policy, String> {
+        Self::from_file_content_checked(path, content, &|_, _| Ok(()))
+    }
+
+    /// As [`Policy::from_file_content`], but every base file `extends`
+    /// reads is passed to `check` first, which may refuse it.
+    pub fn from_file_content_checked(
+        path: &Path,
+        content: &str,
+        check: &compose::BaseCheck<'_>,
+    ) -> Result<Policy, String> {
+        let mut chain: Vec<PathBuf> = path.canonicalize().into_iter().collect();
+        compose::load(path, content, check, &mut chain)
+            .map_err(|e| format!("invalid policy {}: {}", path.display(), e))
+    }
+
+    pub fn from_toml_str(content: &str) -> Result<Policy, String> {
+        let policy: Policy = toml::from_str(content).map_err(|e| e.to_string())?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    pub fn from_json_str(content: &str) -> Result<Policy, String> {
+        let policy: Policy = serde_json::from_str(content).map_err(|e| e.to_string())?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// Check values that parse but cannot be meant, e.g. a URL where a bare
+    /// domain is expected, reporting every error at once. Warnings from
+    /// [`Policy::issues`] do not fail validation.
+    pub fn validate(&self) -> Result<(), String> {
+        let errors: Vec<PolicyIssue> = self.issues().into_iter().filter(|i| i.is_error()).collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(PolicyIssue::join(&errors))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_toml_and_json_and_reports_every_problem() {
+        let policy = Policy::from_toml_str(
+            r#"
+            allowed_domains = ["example.org"]
+            allowed_paths = ["docs"]
+            max_net_bytes = 1000
+            max_fuel = 5000000
+
+            [rate_limits."example.org"]
+            requests_per_minute = 60
+            burst = 5
+            "#,
+        )
+        .expect("toml");
+        assert!(policy.is_url_allowed("https://example.org/x"));
+        assert_eq!(policy.max_net_bytes, Some(1000));
+        assert_eq!(policy.max_fuel, Some(5_000_000));
+        // Omitted fields keep their defaults.
+        assert_eq!(policy.max_stream_secs, Policy::new().max_stream_secs);
+
+        let json = Policy::from_json_str(r#"{"allowed_domains":["example.org"]}"#).expect("json");
+        assert_eq!(json.allowed_domains, policy.allowed_domains);
+
+        let typo = Policy::from_toml_str("allowed_domain = [\"example.org\"]").unwrap_err();
+        assert!(typo.contains("allowed_domain"), "{typo}");
+
+        let per_component = Policy::from_toml_str(
+            r#"
+            [components."sha256:ab12"]
+            allowed_domains = ["example.org"]
+            "#,
+        )
+        .expect("components");
+        let (p, key) = per_component.for_component(&["sha256:ab12".to_string()]);
+        assert_eq!(key.as_deref(), Some("sha256:ab12"));
+        assert!(p.is_url_allowed("https://example.org/"));
+        let (p, key) = per_component.for_component(&["sha256:ffff".to_string()]);
+        assert_eq!(key, None);
+        assert!(p.offline && p.check_fs_access("a.txt", crate::FsAccess::Read).is_err());
+
+        let err = Policy::from_toml_str(
+            r#"
+            allowed_domains = ["https://example.org/"]
+            allowed_paths = ["../etc"]
+            denied_ip_ranges = ["10.0.0.0/33"]
+            "#,
+        )
+        .unwrap_err();
+        assert!(err.contains("allowed_domains[0]"), "{err}");
+        assert!(err.contains("allowed_paths[0]"), "{err}");
+        assert!(err.contains("denied_ip_ranges[0]"), "{err}");
+    }
+}
@@ -0,0 +1,168 @@
+//! Path matching shared by every rule that names paths: filesystem globs
+//! (`read_only`, `deny`, `writable`, `redaction.paths`), `url_rules`
+//! paths, and the prefix lists (`allowed_paths` and friends). The broker's
+//! capability checks match through here too, so a rule means the same
+//! thing everywhere.
+//!
+//! Patterns are `/`-separated: `*` and `?` match within one path segment,
+//! `**` matches any number of segments (including none, so `docs/**` also
+//! covers `docs` itself), `[abc]` matches one listed character and `{a,b}`
+//! either alternative. Empty segments are ignored on both sides, so a
+//! leading or doubled `/` changes nothing. A `**` inside a segment matches
+//! like `*`. Matching is backed by `globset`; compiled patterns are cached.
+//!
+//! Workspace paths are matched without regard to case where the
+//! filesystem ignores it, on macOS and Windows, so `Secrets/key` is the
+//! file `deny = ["secrets/**"]` names there. URL paths and config keys
+//! always match case-sensitively.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+/// Whether workspace paths match without regard to case: where the
+/// filesystem does by default.
+pub const FOLD_CASE: bool = cfg!(any(target_os = "macos", windows));
+
+/// A compiled pattern.
+#[derive(Debug, Clone)]
+pub struct Glob {
+    matcher: globset::GlobMatcher,
+    /// `dir` for a `dir/**` pattern, which also matches `dir` itself.
+    dir: Option<globset::GlobMatcher>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        Self::build(pattern, false)
+    }
+
+    fn build(pattern: &str, fold: bool) -> Result<Self, String> {
+        let segments: Vec<String> = segments(pattern)
+            .map(|s| {
+                if s.contains("**") && s != "**" {
+                    s.replace("**", "*")
+                } else {
+                    s.to_string()
+                }
+            })
+            .collect();
+        let error = |e: globset::Error| format!("{pattern:?}: {}", e.kind());
+        let matcher = compile(&segments.join("/"), fold).map_err(error)?;
+        let dir = match segments.split_last() {
+            Some((last, rest)) if last == "**" => {
+                Some(compile(&rest.join("/"), fold).map_err(error)?)
+            }
+            _ => None,
+        };
+        Ok(Self { matcher, dir })
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        let path = segments(path).collect::<Vec<_>>().join("/");
+        self.matcher.is_match(&path) || self.dir.as_ref().is_some_and(|d| d.is_match(&path))
+    }
+}
+
+/// Whether `pattern` matches `path`, case-sensitively, as for URL paths.
+/// A pattern that does not compile matches nothing; policy validation
+/// reports it.
+pub fn matches(pattern: &str, path: &str) -> bool {
+    cached(pattern, path, false)
+}
+
+/// [`matches`] for a workspace path, case-insensitive if [`FOLD_CASE`].
+pub fn matches_path(pattern: &str, path: &str) -> bool {
+    cached(pattern, path, FOLD_CASE)
+}
+
+fn cached(pattern: &str, path: &str, fold: bool) -> bool {
+    type Cache = HashMap<(String, bool), Option<Glob>>;
+    static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+    let cache = CACHE.get_or_init(Mutex::default);
+    let glob = {
+        let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache
+            .entry((pattern.to_string(), fold))
+            .or_insert_with(|| Glob::build(pattern, fold).ok())
+            .clone()
+    };
+    glob.is_some_and(|g| g.is_match(path))
+}
+
+/// Whether `path` is `prefix` itself or lies below it, as for
+/// `allowed_paths`; case-insensitive if [`FOLD_CASE`]. Both must be
+/// sanitized.
+pub fn has_prefix(path: &str, prefix: &str) -> bool {
+    prefix_of(path, prefix, FOLD_CASE)
+}
+
+fn prefix_of(path: &str, prefix: &str, fold: bool) -> bool {
+    if fold {
+        return prefix_of(&path.to_lowercase(), &prefix.to_lowercase(), false);
+    }
+    path == prefix || path.starts_with(&format!("{prefix}/"))
+}
+
+fn segments(s: &str) -> impl Iterator<Item = &str> {
+    s.split('/').filter(|s| !s.is_empty())
+}
+
+fn compile(pattern: &str, fold: bool) -> Result<globset::GlobMatcher, globset::Error> {
+    Ok(globset::GlobBuilder::new(pattern)
+        .case_insensitive(fold)
+        .literal_separator(true)
+        .backslash_escape(false)
+        .build()?
+        .compile_matcher())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segment_and_recursive_wildcards() {
+        assert!(matches("docs/**", "docs"));
+        assert!(matches("docs/**", "docs/a/b.md"));
+        assert!(!matches("docs/**", "docsx/a"));
+        assert!(matches("**/*.key", "a/b/server.key"));
+        assert!(matches("**/*.key", "server.key"));
+        assert!(matches("*.key", "server.key"));
+        assert!(!matches("*.key", "certs/server.key"));
+        assert!(matches("data/?.json", "data/1.json"));
+        assert!(!matches("data/?.json", "data/10.json"));
+        assert!(matches("a/**/b", "a/b"));
+        assert!(matches("a/**/b", "a/x/y/b"));
+        assert!(matches("**", ""));
+    }
+
+    #[test]
+    fn url_paths_classes_and_alternatives() {
+        assert!(matches("/api/v1/**", "/api/v1/items"));
+        assert!(matches("/api/v1/**", "/api/v1"));
+        assert!(matches("/api/v1/**", "/api//v1/"));
+        assert!(!matches("/api/v1/*", "/api/v1/a/b"));
+        assert!(matches("/api/{v1,v2}/*", "/api/v2/items"));
+        assert!(matches("logs/[0-9]*.txt", "logs/2024.txt"));
+        assert!(!matches("logs/[0-9]*.txt", "logs/x.txt"));
+        // `**` inside a segment matches like `*`.
+        assert!(matches("docs/**.md", "docs/a.md"));
+        assert!(!matches("docs/**.md", "docs/a/b.md"));
+        // Broken patterns match nothing and report why.
+        assert!(!matches("docs/[a", "docs/[a"));
+        assert!(Glob::new("docs/[a").is_err());
+        assert!(has_prefix("docs/a", "docs") && !has_prefix("docsx", "docs"));
+    }
+
+    #[test]
+    fn paths_fold_case_where_the_filesystem_does() {
+        assert!(cached("secrets/**", "Secrets/KEY", true));
+        assert!(!cached("secrets/**", "Secrets/KEY", false));
+        assert!(prefix_of("Private/notes", "private", true));
+        assert!(!prefix_of("Private/notes", "private", false));
+        assert_eq!(matches_path("secrets/**", "SECRETS/key"), FOLD_CASE);
+        assert_eq!(has_prefix("DOCS/a", "docs"), FOLD_CASE);
+        // URL paths never fold.
+        assert!(!matches("/api/**", "/API/v1"));
+    }
+}
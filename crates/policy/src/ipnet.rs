@@ -0,0 +1,130 @@
+//! Minimal CIDR ranges for the SSRF deny set.
+
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, canonical(*ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Treat IPv4-mapped IPv6 addresses (`::ffff:10.0.0.1`) as the IPv4 address
+/// they carry, so they cannot be used to sidestep IPv4 ranges.
+pub fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(v6),
+        },
+        v4 => v4,
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((a, p)) => (a, Some(p)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid IP range {s:?}"))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| format!("invalid prefix length in {s:?}"))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl Display for IpNet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Loopback, private, link-local, CGNAT, benchmarking, multicast and
+/// reserved ranges, and the IPv6 prefixes that carry an IPv4 address to a
+/// gateway (NAT64, 6to4) or discard traffic.
+pub const DEFAULT_DENIED_RANGES: &[&str] = &[
+    "0.0.0.0/8",
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.0.0.0/24",
+    "192.168.0.0/16",
+    "198.18.0.0/15",
+    "224.0.0.0/4",
+    "240.0.0.0/4",
+    "::/128",
+    "::1/128",
+    "64:ff9b::/96",
+    "100::/64",
+    "2002::/16",
+    "fc00::/7",
+    "fe80::/10",
+    "ff00::/8",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_ranges_and_mapped_addresses() {
+        let net: IpNet = "169.254.0.0/16".parse().expect("range");
+        assert!(net.contains(&"169.254.169.254".parse().expect("ip")));
+        assert!(net.contains(&"::ffff:169.254.1.1".parse().expect("ip")));
+        assert!(!net.contains(&"93.184.216.34".parse().expect("ip")));
+
+        let ula: IpNet = "fc00::/7".parse().expect("range");
+        assert!(ula.contains(&"fd12::1".parse().expect("ip")));
+        assert!("10.0.0.0/33".parse::<IpNet>().is_err());
+
+        let denied = |ip: &str| {
+            let ip = ip.parse().expect("ip");
+            DEFAULT_DENIED_RANGES
+                .iter()
+                .any(|r| r.parse::<IpNet>().expect("range").contains(&ip))
+        };
+        // 10.0.0.1 through a NAT64 gateway, and through 6to4.
+        assert!(denied("64:ff9b::a00:1"));
+        assert!(denied("2002:a00:1::1"));
+        assert!(denied("192.0.0.170"));
+        assert!(denied("198.19.255.1"));
+        assert!(denied("100::1"));
+        assert!(!denied("2606:4700::1111"));
+        assert!(!denied("198.20.0.1"));
+    }
+}
@@ -1,30 +1,965 @@
 #![forbid(unsafe_code)]
 
-#[derive(Debug, Default, Clone)]
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use saf_codes::{Category, Code, Severity};
+use serde::{Deserialize, Serialize};
+
+pub mod builder;
+pub mod compose;
+pub mod explain;
+pub mod expr;
+pub mod file;
+pub mod glob;
+pub mod ipnet;
+pub mod redact;
+mod shared;
+pub mod trial;
+
+pub use builder::{PolicyBuilder, PolicyIssue};
+use glob::has_prefix;
+pub use redact::Redaction;
+pub use shared::SharedPolicy;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Policy {
+    /// Hosts that may be contacted; `*.example.org` covers any subdomain
+    /// (but not `example.org` itself).
     pub allowed_domains: Vec<String>,
-    pub max_bytes: u64,
+    /// Hosts that are refused even when `allowed_domains` covers them, e.g.
+    /// `vault.corp.net` carved out of `*.corp.net`. Same syntax.
+    pub denied_domains: Vec<String>,
+    /// Workspace-relative path prefixes the component may touch; empty grants
+    /// the whole workspace.
+    pub allowed_paths: Vec<String>,
+    /// Path prefixes refused even inside `allowed_paths`.
+    pub denied_paths: Vec<String>,
+    /// Hosts outside `allowed_domains` the user is asked about on first
+    /// use. Same syntax; `denied_domains` still wins.
+    pub ask_domains: Vec<String>,
+    /// Path prefixes outside `allowed_paths` the user is asked about.
+    pub ask_paths: Vec<String>,
+    /// Globs (see [`glob`]) that may be read but not written.
+    pub read_only: Vec<String>,
+    /// Globs that may not be touched at all; these win over `read_only`.
+    pub deny: Vec<String>,
+    /// Refuse every write not matched by a `writable` glob; reads are
+    /// unaffected. For viewer-style apps that should only write where told.
+    pub writable_only: bool,
+    /// Globs writes are confined to when `writable_only` is set.
+    pub writable: Vec<String>,
+    /// Expression rules (see [`expr`]) checked after everything else passes;
+    /// they can only refuse.
+    pub conditions: Vec<Condition>,
+    /// Largest file a component may read in one call; range reads are
+    /// clamped to it.
+    pub max_read_bytes: u64,
+    /// Largest single write, and the size an append may grow a file to.
+    pub max_write_bytes: u64,
+    /// Largest HTTP response body a request may return.
+    pub max_response_bytes: u64,
+    /// Largest HTTP request body a component may send. Requests are
+    /// bodiless GETs for now, so nothing is refused by it yet.
+    pub max_request_body_bytes: u64,
+    /// Largest single WebSocket message, in either direction.
+    pub max_ws_message_bytes: u64,
+    /// Total bytes a single streaming response may deliver.
+    pub max_stream_bytes: u64,
+    /// How long a streaming response may stay open, in seconds.
+    pub max_stream_secs: u64,
+    /// Path and method rules for HTTP requests. A domain with rules only
+    /// admits requests matching one of them; other allowed domains admit
+    /// any path and method.
+    pub url_rules: Vec<UrlRule>,
+    /// Schemes and ports admitted per domain. Hosts with no entry take
+    /// `https` and `wss` on the default port only.
+    pub origins: Vec<OriginRule>,
+    /// Token-bucket limits keyed by domain; unlisted domains are unlimited.
+    pub rate_limits: BTreeMap<String, RateLimit>,
+    /// CIDR ranges the NetHost refuses to connect to after DNS resolution.
+    pub denied_ip_ranges: Vec<String>,
+    /// Mirror rewrites applied before the allowlist check; first match wins.
+    pub url_rewrites: Vec<UrlRewrite>,
+    /// Disable all network access (HTTP and WebSocket) for air-gapped use.
+    pub offline: bool,
+    /// End a component's run at its first denial, rather than handing the
+    /// guest an error it may ignore.
+    pub strict: bool,
+    /// Identifying system details exposed through `saf:app/sysinfo`.
+    pub sysinfo: SysinfoGrants,
+    /// Names of the values a component may read through `saf:app/config`;
+    /// `*` matches any run of characters, as in `smtp.*`. Empty grants none.
+    pub config_keys: Vec<String>,
+    /// Request plus response bytes a component may move per run; `None` is
+    /// unlimited.
+    pub max_net_bytes: Option<u64>,
+    /// Wasm fuel a component may burn per run, about one unit per
+    /// instruction executed; a run that uses it up is trapped. `None` is
+    /// unlimited.
+    pub max_fuel: Option<u64>,
+    /// What a component's wasm instances may allocate.
+    pub memory: MemoryLimits,
+    /// Operations a component may perform per run, by capability.
+    pub budgets: Budgets,
+    /// Paths, query parameters and patterns kept out of the audit log.
+    pub redaction: Redaction,
+    /// Least severe record kept in the audit log, by category (`fs`, `net`,
+    /// `policy`, `lifecycle`): `info`, `warn`, `error` or `security`.
+    /// Unlisted categories keep everything.
+    pub audit_min_severity: BTreeMap<String, String>,
+    /// DNS-over-HTTPS endpoint (JSON API) used instead of the system
+    /// resolver; it must itself be an allowed URL.
+    pub dns_over_https: Option<String>,
+    /// Client certificates for mutual TLS, keyed by domain.
+    pub client_certs: BTreeMap<String, ClientCert>,
+    /// Keep-alive tuning for the NetHost's shared connection pool.
+    pub connection_pool: ConnectionPool,
+    /// Grants for individual components, keyed by `sha256:<hex>` of the
+    /// component bytes, by registry name, or by `<publisher>/<name>` from
+    /// an installed component's manifest. See [`Policy::for_component`].
+    pub components: BTreeMap<String, Policy>,
+}
+
+/// Per-run operation counts; `None` is unlimited. Refused operations do
+/// not count.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Budgets {
+    /// Directory listings and file reads.
+    pub fs_reads: Option<u64>,
+    /// File writes and appends.
+    pub fs_writes: Option<u64>,
+    /// HTTP requests, streams and WebSocket connections opened.
+    pub net_requests: Option<u64>,
+    /// WebSocket messages sent.
+    pub ws_messages: Option<u64>,
+}
+
+/// Caps on what a component's wasm instances may allocate, so a runaway
+/// guest cannot exhaust the broker's memory. Growing a memory or table
+/// past its cap traps the run; a component declaring more instances,
+/// tables or memories than allowed fails to instantiate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MemoryLimits {
+    /// Largest any one linear memory may grow, in bytes (wasm pages are
+    /// 64 KiB).
+    pub max_memory_bytes: u64,
+    /// Most elements any one table may hold.
+    pub max_table_elements: u32,
+    /// Most core module instances a component may create.
+    pub max_instances: u32,
+    pub max_tables: u32,
+    pub max_memories: u32,
+}
+
+impl Default for MemoryLimits {
+    fn default() -> Self {
+        Self {
+            max_memory_bytes: 256 * 1024 * 1024,
+            max_table_elements: 100_000,
+            max_instances: 1_000,
+            max_tables: 1_000,
+            max_memories: 100,
+        }
+    }
+}
+
+/// Connections to remote hosts: how many may be open to one host, and how
+/// many idle ones are kept for reuse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionPool {
+    /// Connections open to one host at once, counting requests in flight
+    /// and streams a component holds. Past this a request is refused
+    /// rather than queued, since the component holding the streams is the
+    /// one waiting.
+    pub max_per_host: usize,
+    pub max_idle: usize,
+    /// Never more than `max_per_host`.
+    pub max_idle_per_host: usize,
+    /// Seconds an idle connection is kept before it is closed.
+    pub idle_timeout_secs: u64,
+}
+
+impl Default for ConnectionPool {
+    fn default() -> Self {
+        Self {
+            max_per_host: 8,
+            max_idle: 16,
+            max_idle_per_host: 4,
+            idle_timeout_secs: 30,
+        }
+    }
+}
+
+/// A failed policy check: the decision code and the rule responsible, such
+/// as `deny[2]`, or `allowed_domains` when no allow entry matched. Checks the
+/// policy does not make configurable are named `builtin:*`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Denial {
+    pub code: Code,
+    pub rule_id: String,
+}
+
+impl Denial {
+    pub fn new(code: Code, rule_id: impl Into<String>) -> Self {
+        Self {
+            code,
+            rule_id: rule_id.into(),
+        }
+    }
+}
+
+impl Display for Denial {
+    /// The `<code>: <message>` form hosts use for error strings.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: blocked by policy rule {}", self.code, self.rule_id)
+    }
+}
+
+/// Kind of access a filesystem call needs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsAccess {
+    Read,
+    Write,
+}
+
+/// Names of broker secret-store entries holding a PEM certificate chain and
+/// its private key. The policy never carries key material itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientCert {
+    pub cert: String,
+    pub key: String,
+}
+
+/// Locale, timezone, OS family and framework version are always visible to
+/// components; these fields opt into anything that identifies the machine or
+/// user.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SysinfoGrants {
+    pub hostname: bool,
+    pub username: bool,
+}
+
+/// Requests to `domain` whose path matches one of `paths` (globs such as
+/// `/api/v1/**`) using one of `methods`; empty `methods` allows any.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UrlRule {
+    pub domain: String,
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub methods: Vec<String>,
+}
+
+impl UrlRule {
+    fn admits(&self, method: &str, path: &str) -> bool {
+        (self.methods.is_empty() || self.methods.iter().any(|m| m.eq_ignore_ascii_case(method)))
+            && self.paths.iter().any(|p| glob::matches(p, path))
+    }
+}
+
+/// Schemes and ports a host matching `domain` may be reached on, e.g.
+/// `http` on port 8080 for a local development server. The host must still
+/// be allowed by the domain rules (and, for a local address, not refused by
+/// `denied_ip_ranges`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OriginRule {
+    pub domain: String,
+    /// Any of `http`, `https`, `ws` and `wss`; empty admits `https` and
+    /// `wss`.
+    #[serde(default)]
+    pub schemes: Vec<String>,
+    /// Empty admits only the scheme's default port.
+    #[serde(default)]
+    pub ports: Vec<u16>,
+}
+
+impl OriginRule {
+    fn admits(&self, url: &url::Url) -> bool {
+        let scheme = if self.schemes.is_empty() {
+            matches!(url.scheme(), "https" | "wss")
+        } else {
+            self.schemes
+                .iter()
+                .any(|s| s.eq_ignore_ascii_case(url.scheme()))
+        };
+        let port = if self.ports.is_empty() {
+            url.port().is_none()
+        } else {
+            url.port_or_known_default()
+                .is_some_and(|p| self.ports.contains(&p))
+        };
+        scheme && port
+    }
+}
+
+/// Requests for which `when` holds are refused unless `require` holds too.
+/// An expression that fails to evaluate refuses the request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    pub when: String,
+    pub require: String,
+}
+
+/// Replace a URL prefix, e.g. `https://crates.io/` with an internal mirror.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UrlRewrite {
+    pub from: String,
+    pub to: String,
+}
+
+impl UrlRewrite {
+    /// `url` with `from` replaced by `to`, if it starts with `from` and
+    /// the match ends at a path, query or fragment boundary: a rule from
+    /// `https://a.com` does not rewrite `https://a.com.evil/`.
+    pub fn apply(&self, url: &str) -> Option<String> {
+        let rest = url.strip_prefix(&self.from)?;
+        let bounded = self.from.ends_with(['/', '?', '#'])
+            || rest.is_empty()
+            || rest.starts_with(['/', '?', '#']);
+        bounded.then(|| format!("{}{}", self.to, rest))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// Sustained refill rate of the bucket.
+    pub requests_per_minute: u32,
+    /// Bucket capacity: requests that may be made back to back.
+    pub burst: u32,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Policy {
     pub fn new() -> Self {
         Self {
             allowed_domains: Vec::new(),
-            max_bytes: 10 * 1024 * 1024,
+            denied_domains: Vec::new(),
+            allowed_paths: Vec::new(),
+            denied_paths: Vec::new(),
+            ask_domains: Vec::new(),
+            ask_paths: Vec::new(),
+            read_only: Vec::new(),
+            deny: Vec::new(),
+            writable_only: false,
+            writable: Vec::new(),
+            conditions: Vec::new(),
+            max_read_bytes: 10 * 1024 * 1024,
+            max_write_bytes: 10 * 1024 * 1024,
+            max_response_bytes: 10 * 1024 * 1024,
+            max_request_body_bytes: 10 * 1024 * 1024,
+            max_ws_message_bytes: 1024 * 1024,
+            max_stream_bytes: 64 * 1024 * 1024,
+            max_stream_secs: 300,
+            url_rules: Vec::new(),
+            origins: Vec::new(),
+            rate_limits: BTreeMap::new(),
+            denied_ip_ranges: ipnet::DEFAULT_DENIED_RANGES
+                .iter()
+                .map(|r| r.to_string())
+                .collect(),
+            url_rewrites: Vec::new(),
+            offline: false,
+            strict: false,
+            sysinfo: SysinfoGrants::default(),
+            config_keys: Vec::new(),
+            max_net_bytes: None,
+            max_fuel: None,
+            memory: MemoryLimits::default(),
+            budgets: Budgets::default(),
+            redaction: Redaction::default(),
+            audit_min_severity: BTreeMap::new(),
+            dns_over_https: None,
+            client_certs: BTreeMap::new(),
+            connection_pool: ConnectionPool::default(),
+            components: BTreeMap::new(),
         }
     }
 
+    /// Whether a record of `severity` in `category` is kept in the audit
+    /// log. An unreadable `audit_min_severity` entry keeps everything;
+    /// validation reports it.
+    pub fn audits(&self, category: Category, severity: Severity) -> bool {
+        self.audit_min_severity
+            .get(category.as_str())
+            .and_then(|min| Severity::parse(min))
+            .is_none_or(|min| severity >= min)
+    }
+
+    /// Grant for components the policy does not name: no network, no
+    /// filesystem and no identifying system details.
+    pub fn restrictive() -> Self {
+        Self {
+            offline: true,
+            deny: vec!["**".to_string()],
+            max_net_bytes: Some(0),
+            ..Self::new()
+        }
+    }
+
+    /// The grant for a component known by any of `ids`, with the key that
+    /// matched; components matching no entry get [`Policy::restrictive`].
+    pub fn for_component(&self, ids: &[String]) -> (Policy, Option<String>) {
+        ids.iter()
+            .find_map(|id| {
+                self.components
+                    .get(id)
+                    .map(|p| (p.clone(), Some(id.clone())))
+            })
+            .unwrap_or_else(|| (Self::restrictive(), None))
+    }
+
     pub fn with_allowed_domains(mut self, domains: Vec<String>) -> Self {
         self.allowed_domains = domains;
         self
     }
 
+    pub fn with_rate_limit(mut self, domain: &str, limit: RateLimit) -> Self {
+        self.rate_limits.insert(domain.to_string(), limit);
+        self
+    }
+
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn with_client_cert(mut self, domain: &str, cert: &str, key: &str) -> Self {
+        self.client_certs.insert(
+            domain.to_string(),
+            ClientCert {
+                cert: cert.to_string(),
+                key: key.to_string(),
+            },
+        );
+        self
+    }
+
+    pub fn with_url_rewrite(mut self, from: &str, to: &str) -> Self {
+        self.url_rewrites.push(UrlRewrite {
+            from: from.to_string(),
+            to: to.to_string(),
+        });
+        self
+    }
+
+    /// The URL after applying the first matching rewrite rule, if any.
+    pub fn rewrite_url(&self, url: &str) -> Option<String> {
+        self.url_rewrites.iter().find_map(|r| r.apply(url))
+    }
+
     pub fn is_url_allowed(&self, url: &str) -> bool {
-        for d in &self.allowed_domains {
-            if url.starts_with(&format!("https://{d}/")) || url == format!("https://{d}") {
-                return true;
+        self.check_url(url).is_ok()
+    }
+
+    /// Parse `url` and match its host against `denied_domains`, then
+    /// `allowed_domains`. The scheme must be `secure` on its default port
+    /// unless `origins` say otherwise for the host; `plain` is the only
+    /// other scheme they can admit. URLs carrying credentials are refused
+    /// outright.
+    fn check_host(&self, url: &str, secure: &str, plain: &str) -> Result<(), Denial> {
+        let builtin = |rule: &str| Denial::new(Code::PolicyDomainNotAllowed, rule);
+        let parsed = url::Url::parse(url).map_err(|_| builtin("builtin:valid-url"))?;
+        let host = parsed
+            .host_str()
+            .ok_or_else(|| builtin("builtin:valid-url"))?;
+        let origins: Vec<(usize, &OriginRule)> = self
+            .origins
+            .iter()
+            .enumerate()
+            .filter(|(_, o)| domain_matches(&o.domain, host))
+            .collect();
+        if parsed.scheme() != secure && (parsed.scheme() != plain || origins.is_empty()) {
+            return Err(builtin(&format!("builtin:{secure}-only")));
+        }
+        if origins.is_empty() && parsed.port().is_some() {
+            return Err(builtin("builtin:default-port"));
+        }
+        if !origins.is_empty() && !origins.iter().any(|(_, o)| o.admits(&parsed)) {
+            let ids: Vec<String> = origins.iter().map(|(i, _)| i.to_string()).collect();
+            return Err(Denial::new(
+                Code::PolicyOriginNotAllowed,
+                format!("origins[{}]", ids.join(",")),
+            ));
+        }
+        if !parsed.username().is_empty() || parsed.password().is_some() {
+            return Err(builtin("builtin:no-credentials"));
+        }
+        if let Some(i) = self
+            .denied_domains
+            .iter()
+            .position(|d| domain_matches(d, host))
+        {
+            return Err(Denial::new(
+                Code::PolicyDomainDenied,
+                format!("denied_domains[{i}]"),
+            ));
+        }
+        if self.allowed_domains.iter().any(|d| domain_matches(d, host)) {
+            return Ok(());
+        }
+        match self
+            .ask_domains
+            .iter()
+            .position(|d| domain_matches(d, host))
+        {
+            Some(i) => Err(Denial::new(Code::PolicyAsk, format!("ask_domains[{i}]"))),
+            None => Err(Denial::new(Code::PolicyDomainNotAllowed, "allowed_domains")),
+        }
+    }
+
+    /// Decision for an HTTP(S) request; the error names the deciding rule.
+    pub fn check_url(&self, url: &str) -> Result<(), Denial> {
+        self.check_host(url, "https", "http")
+    }
+
+    /// Domain allowlist plus `url_rules` for an HTTP request. The path is
+    /// normalized first, so `..` segments cannot step outside a prefix.
+    /// A host needing confirmation is reported as [`Code::PolicyAsk`] only
+    /// once `url_rules` admit the request.
+    pub fn check_request(&self, method: &str, url: &str) -> Result<(), Denial> {
+        let host_decision = self.check_url(url);
+        if host_decision
+            .as_ref()
+            .is_err_and(|d| d.code != Code::PolicyAsk)
+        {
+            return host_decision;
+        }
+        let parsed = url::Url::parse(url)
+            .map_err(|_| Denial::new(Code::PolicyDomainNotAllowed, "builtin:valid-url"))?;
+        let host = parsed.host_str().unwrap_or_default();
+        let rules: Vec<(usize, &UrlRule)> = self
+            .url_rules
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| domain_matches(&r.domain, host))
+            .collect();
+        if rules.is_empty() || rules.iter().any(|(_, r)| r.admits(method, parsed.path())) {
+            return host_decision;
+        }
+        // None of the domain's rules admitted the request; name them all.
+        let ids: Vec<String> = rules.iter().map(|(i, _)| i.to_string()).collect();
+        Err(Denial::new(
+            Code::PolicyRequestNotAllowed,
+            format!("url_rules[{}]", ids.join(",")),
+        ))
+    }
+
+    pub fn check_ws_url(&self, url: &str) -> Result<(), Denial> {
+        self.check_host(url, "wss", "ws")
+    }
+
+    pub fn check_path(&self, path: &str) -> Result<(), Denial> {
+        if let Some(denial) = self.path_denial(path) {
+            Err(denial)
+        } else if self.is_path_allowed(path) {
+            Ok(())
+        } else if let Some(i) = self.ask_paths.iter().position(|p| has_prefix(path, p)) {
+            Err(Denial::new(Code::PolicyAsk, format!("ask_paths[{i}]")))
+        } else {
+            Err(Denial::new(Code::PolicyPathNotAllowed, "allowed_paths"))
+        }
+    }
+
+    /// The `ask_paths` entry covering `path`; the user's answer applies to
+    /// everything below it.
+    pub fn ask_path_prefix(&self, path: &str) -> Option<&str> {
+        self.ask_paths
+            .iter()
+            .find(|p| has_prefix(path, p))
+            .map(String::as_str)
+    }
+
+    /// Whether the `saf:app/config` value `key` may be read.
+    pub fn check_config_key(&self, key: &str) -> Result<(), Denial> {
+        if self.config_keys.iter().any(|g| glob::matches(g, key)) {
+            Ok(())
+        } else {
+            Err(Denial::new(Code::PolicyConfigNotAllowed, "config_keys"))
+        }
+    }
+
+    /// Decision from the `deny`, `denied_paths`, `read_only` and `writable`
+    /// rules for a sanitized path.
+    pub fn check_fs_access(&self, path: &str, access: FsAccess) -> Result<(), Denial> {
+        if let Some(denial) = self.path_denial(path) {
+            return Err(denial);
+        }
+        if access == FsAccess::Write {
+            if let Some(i) = self
+                .read_only
+                .iter()
+                .position(|g| glob::matches_path(g, path))
+            {
+                return Err(Denial::new(
+                    Code::PolicyPathReadOnly,
+                    format!("read_only[{i}]"),
+                ));
+            }
+            if self.writable_only && !self.writable.iter().any(|g| glob::matches_path(g, path)) {
+                return Err(Denial::new(Code::PolicyPathReadOnly, "writable"));
             }
         }
-        false
+        Ok(())
+    }
+
+    /// Reject a resolved address in `denied_ip_ranges`. Unparseable entries
+    /// are treated as matching nothing.
+    pub fn check_ip(&self, ip: &std::net::IpAddr) -> Result<(), Denial> {
+        match self
+            .denied_ip_ranges
+            .iter()
+            .position(|r| r.parse::<ipnet::IpNet>().is_ok_and(|net| net.contains(ip)))
+        {
+            Some(i) => Err(Denial::new(
+                Code::PolicyIpDenied,
+                format!("denied_ip_ranges[{i}]"),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Decision from the `conditions` rules; the first that refuses names
+    /// itself as `conditions[i]`.
+    pub fn check_conditions(&self, attrs: &expr::Attributes<'_>) -> Result<(), Denial> {
+        for (i, c) in self.conditions.iter().enumerate() {
+            let holds = |src: &str| expr::Expr::parse(src).and_then(|e| e.eval(attrs));
+            let admitted = match holds(&c.when) {
+                Ok(false) => true,
+                Ok(true) => holds(&c.require).unwrap_or(false),
+                Err(_) => false,
+            };
+            if !admitted {
+                return Err(Denial::new(
+                    Code::PolicyConditionFailed,
+                    format!("conditions[{i}]"),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// `path` must already be sanitized (relative, `/`-separated, no `..`).
+    pub fn is_path_allowed(&self, path: &str) -> bool {
+        if self.path_denial(path).is_some() {
+            return false;
+        }
+        if self.allowed_paths.is_empty() {
+            return true;
+        }
+        self.allowed_paths.iter().any(|p| has_prefix(path, p))
+    }
+
+    fn path_denial(&self, path: &str) -> Option<Denial> {
+        if let Some(i) = self.denied_paths.iter().position(|p| has_prefix(path, p)) {
+            return Some(Denial::new(
+                Code::PolicyPathDenied,
+                format!("denied_paths[{i}]"),
+            ));
+        }
+        self.deny
+            .iter()
+            .position(|g| glob::matches_path(g, path))
+            .map(|i| Denial::new(Code::PolicyPathDenied, format!("deny[{i}]")))
+    }
+
+    pub fn is_ws_url_allowed(&self, url: &str) -> bool {
+        self.check_ws_url(url).is_ok()
+    }
+}
+
+/// Whether `host` (as parsed from a URL) matches an allowlist entry, either
+/// exactly or, for `*.suffix`, as a subdomain of `suffix`. Comparison is
+/// case-insensitive and ignores a trailing root dot.
+pub fn domain_matches(pattern: &str, host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => host
+            .strip_suffix(suffix)
+            .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+        None => host == pattern,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_rules_restrict_paths_and_methods() {
+        let mut policy =
+            Policy::new().with_allowed_domains(vec!["api.test".into(), "other.test".into()]);
+        policy.url_rules.push(UrlRule {
+            domain: "api.test".into(),
+            paths: vec!["/api/v1/**".into()],
+            methods: vec!["GET".into()],
+        });
+        assert!(policy
+            .check_request("GET", "https://api.test/api/v1/items?q=1")
+            .is_ok());
+        assert_eq!(
+            policy.check_request("POST", "https://api.test/api/v1/items"),
+            Err(Denial::new(Code::PolicyRequestNotAllowed, "url_rules[0]"))
+        );
+        assert_eq!(
+            policy
+                .check_request("GET", "https://api.test/api/v1/../admin")
+                .map_err(|d| d.code),
+            Err(Code::PolicyRequestNotAllowed)
+        );
+        // Domains without rules keep domain-level matching.
+        assert!(policy
+            .check_request("POST", "https://other.test/anything")
+            .is_ok());
+    }
+
+    #[test]
+    fn rewrites_match_whole_origins_and_path_segments() {
+        let policy = Policy::new()
+            .with_url_rewrite("https://a.com", "https://mirror.corp")
+            .with_url_rewrite("https://b.com/api/", "https://mirror.corp/b/");
+        for (url, rewritten) in [
+            ("https://a.com", "https://mirror.corp"),
+            ("https://a.com/x/y", "https://mirror.corp/x/y"),
+            ("https://a.com?q=1", "https://mirror.corp?q=1"),
+            ("https://a.com#top", "https://mirror.corp#top"),
+            ("https://b.com/api/v1", "https://mirror.corp/b/v1"),
+        ] {
+            assert_eq!(policy.rewrite_url(url).as_deref(), Some(rewritten), "{url}");
+        }
+        for url in [
+            "https://a.com.evil/x",
+            "https://a.community/",
+            "https://a.com:8443/",
+            "https://b.com/apis",
+            "http://a.com/",
+        ] {
+            assert_eq!(policy.rewrite_url(url), None, "{url}");
+        }
+        let rules: Vec<String> = policy
+            .explain_request(Some("GET"), "https://a.com.evil/", &Default::default())
+            .iter()
+            .map(|m| m.rule.clone())
+            .collect();
+        assert!(
+            !rules.iter().any(|r| r.starts_with("url_rewrites")),
+            "{rules:?}"
+        );
+    }
+
+    #[test]
+    fn hosts_are_parsed_not_prefix_matched() {
+        let policy =
+            Policy::new().with_allowed_domains(vec!["example.org".into(), "*.corp.net".into()]);
+        for ok in [
+            "https://example.org",
+            "https://example.org/a?b=c",
+            "https://EXAMPLE.org/",
+            "https://example.org./",
+            "https://api.corp.net/",
+            "https://a.b.corp.net/x",
+        ] {
+            assert!(policy.is_url_allowed(ok), "{ok}");
+        }
+        for bad in [
+            "https://example.org.evil.com/",
+            "https://example.org@evil.com/",
+            "https://user:pw@example.org/",
+            "https://evil.com/?https://example.org/",
+            "https://evil.com/#https://example.org/",
+            "https://notexample.org/",
+            "https://example.org:8443/",
+            "http://example.org/",
+            "https://corp.net/",
+            "https://evilcorp.net/",
+            "https://corp.net.evil.com/",
+            "not a url",
+        ] {
+            assert!(!policy.is_url_allowed(bad), "{bad}");
+        }
+        assert!(policy.is_ws_url_allowed("wss://chat.corp.net/socket"));
+        assert!(!policy.is_ws_url_allowed("https://chat.corp.net/socket"));
+    }
+
+    #[test]
+    fn origins_admit_schemes_and_ports_per_host() {
+        let mut policy =
+            Policy::new().with_allowed_domains(vec!["example.org".into(), "127.0.0.1".into()]);
+        policy.origins.push(OriginRule {
+            domain: "127.0.0.1".into(),
+            schemes: vec!["http".into(), "ws".into()],
+            ports: vec![8080],
+        });
+        assert!(policy.check_url("http://127.0.0.1:8080/api").is_ok());
+        assert!(policy.check_ws_url("ws://127.0.0.1:8080/live").is_ok());
+        for bad in [
+            "http://127.0.0.1/",
+            "http://127.0.0.1:8081/",
+            "https://127.0.0.1:8080/",
+        ] {
+            assert_eq!(
+                policy.check_url(bad),
+                Err(Denial::new(Code::PolicyOriginNotAllowed, "origins[0]")),
+                "{bad}"
+            );
+        }
+        // Other hosts keep https on the default port.
+        assert!(policy.check_url("https://example.org/").is_ok());
+        assert_eq!(
+            policy
+                .check_url("http://example.org:8080/")
+                .map_err(|d| d.rule_id),
+            Err("builtin:https-only".to_string())
+        );
+        assert_eq!(
+            policy
+                .check_url("ftp://127.0.0.1:8080/")
+                .map_err(|d| d.rule_id),
+            Err("builtin:https-only".to_string())
+        );
+    }
+
+    #[test]
+    fn deny_rules_win_over_allows() {
+        let mut policy = Policy::new().with_allowed_domains(vec!["*.corp.net".into()]);
+        policy.denied_domains = vec!["vault.corp.net".into(), "*.secret.corp.net".into()];
+        policy.allowed_paths = vec!["docs".into()];
+        policy.denied_paths = vec!["docs/private".into()];
+        assert!(policy.check_url("https://wiki.corp.net/").is_ok());
+        assert_eq!(
+            policy.check_url("https://VAULT.corp.net./v1"),
+            Err(Denial::new(Code::PolicyDomainDenied, "denied_domains[0]"))
+        );
+        assert_eq!(
+            policy.check_ws_url("wss://a.secret.corp.net/"),
+            Err(Denial::new(Code::PolicyDomainDenied, "denied_domains[1]"))
+        );
+        assert_eq!(
+            policy.check_url("https://example.org/"),
+            Err(Denial::new(Code::PolicyDomainNotAllowed, "allowed_domains"))
+        );
+        assert_eq!(
+            policy
+                .check_url("https://wiki.corp.net:8443/")
+                .map_err(|d| d.rule_id),
+            Err("builtin:default-port".to_string())
+        );
+        assert!(policy.check_path("docs/readme.md").is_ok());
+        assert!(policy.check_path("docs/private-notes.md").is_ok());
+        assert_eq!(
+            policy.check_path("docs/private/key.pem"),
+            Err(Denial::new(Code::PolicyPathDenied, "denied_paths[0]"))
+        );
+        assert_eq!(
+            policy.check_path("src/main.rs"),
+            Err(Denial::new(Code::PolicyPathNotAllowed, "allowed_paths"))
+        );
+        assert_eq!(
+            policy
+                .check_fs_access("docs/private", FsAccess::Read)
+                .map_err(|d| d.code),
+            Err(Code::PolicyPathDenied)
+        );
+
+        // `ask` sits between the two: below deny, above the default.
+        policy.ask_domains = vec!["*.corp.net".into(), "example.org".into()];
+        policy.ask_paths = vec!["photos".into(), "docs".into()];
+        policy.allowed_domains.clear();
+        assert_eq!(
+            policy.check_request("GET", "https://example.org/"),
+            Err(Denial::new(Code::PolicyAsk, "ask_domains[1]"))
+        );
+        assert_eq!(
+            policy
+                .check_url("https://vault.corp.net/")
+                .map_err(|d| d.code),
+            Err(Code::PolicyDomainDenied)
+        );
+        assert_eq!(
+            policy.check_path("photos/2024/a.jpg"),
+            Err(Denial::new(Code::PolicyAsk, "ask_paths[0]"))
+        );
+        assert_eq!(policy.ask_path_prefix("photos/2024/a.jpg"), Some("photos"));
+        assert_eq!(
+            policy
+                .check_path("docs/private/key.pem")
+                .map_err(|d| d.code),
+            Err(Code::PolicyPathDenied)
+        );
+    }
+
+    #[test]
+    fn writable_only_denies_writes_outside_writable() {
+        let policy = Policy {
+            writable_only: true,
+            writable: vec!["exports/**".into()],
+            read_only: vec!["exports/locked.txt".into()],
+            ..Policy::new()
+        };
+        assert!(policy.check_fs_access("docs/a.txt", FsAccess::Read).is_ok());
+        assert_eq!(
+            policy.check_fs_access("docs/a.txt", FsAccess::Write),
+            Err(Denial::new(Code::PolicyPathReadOnly, "writable"))
+        );
+        assert!(policy
+            .check_fs_access("exports/a.txt", FsAccess::Write)
+            .is_ok());
+        assert_eq!(
+            policy.check_fs_access("exports/locked.txt", FsAccess::Write),
+            Err(Denial::new(Code::PolicyPathReadOnly, "read_only[0]"))
+        );
+    }
+
+    #[test]
+    fn config_keys_are_granted_by_name_or_glob() {
+        let policy: Policy =
+            toml::from_str("config_keys = [\"api_url\", \"smtp.*\", \"\"]\n").expect("parse");
+        assert!(policy.check_config_key("api_url").is_ok());
+        assert!(policy.check_config_key("smtp.host").is_ok());
+        assert_eq!(
+            policy.check_config_key("db_password"),
+            Err(Denial::new(Code::PolicyConfigNotAllowed, "config_keys"))
+        );
+        assert!(Policy::restrictive().check_config_key("api_url").is_err());
+        assert!(policy
+            .issues()
+            .iter()
+            .any(|i| i.is_error() && i.field == "config_keys[2]"));
+    }
+
+    #[test]
+    fn audit_min_severity_applies_per_category() {
+        let policy: Policy =
+            toml::from_str("[audit_min_severity]\nfs = \"warn\"\nnet = \"loud\"\n").expect("parse");
+        assert!(!policy.audits(Category::Fs, Severity::Info));
+        assert!(policy.audits(Category::Fs, Severity::Security));
+        assert!(policy.audits(Category::Lifecycle, Severity::Info));
+        // Unreadable, so nothing is dropped, but the policy is refused.
+        assert!(policy.audits(Category::Net, Severity::Info));
+        assert!(policy
+            .issues()
+            .iter()
+            .any(|i| i.is_error() && i.field == "audit_min_severity.net"));
     }
 }
@@ -0,0 +1,119 @@
+//! What must never reach the audit log.
+//!
+//! Targets are rewritten before an event is recorded: a path matching one
+//! of `paths` is replaced outright, the values of the named query
+//! parameters in a URL are blanked, and anything matching one of
+//! `patterns` is cut out of what is left. Entries that do not compile are
+//! treated as matching nothing; validation reports them.
+
+use serde::{Deserialize, Serialize};
+
+use crate::glob;
+
+/// Written in place of anything redacted.
+pub const REDACTED: &str = "[redacted]";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Redaction {
+    /// Globs (see [`glob`]) for paths whose names are themselves sensitive.
+    pub paths: Vec<String>,
+    /// Query parameter names whose values are blanked, e.g. `access_token`.
+    /// Compared case-insensitively.
+    pub query_params: Vec<String>,
+    /// Regular expressions cut out of every target, e.g. `ghp_[A-Za-z0-9]+`.
+    pub patterns: Vec<String>,
+}
+
+impl Redaction {
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.query_params.is_empty() && self.patterns.is_empty()
+    }
+
+    /// A sanitized workspace path as it may be audited.
+    pub fn path(&self, path: &str) -> String {
+        if self.paths.iter().any(|g| glob::matches_path(g, path)) {
+            REDACTED.to_string()
+        } else {
+            self.text(path)
+        }
+    }
+
+    /// A URL as it may be audited. Anything that does not parse as a URL
+    /// only has `patterns` applied.
+    pub fn url(&self, url: &str) -> String {
+        let Ok(mut parsed) = url::Url::parse(url) else {
+            return self.text(url);
+        };
+        if let Some(query) = parsed.query() {
+            let query = self.query(query);
+            parsed.set_query(Some(&query));
+        }
+        self.text(parsed.as_str())
+    }
+
+    /// `text` with every `patterns` match replaced.
+    pub fn text(&self, text: &str) -> String {
+        let mut out = text.to_string();
+        for re in self
+            .patterns
+            .iter()
+            .filter_map(|p| regex::Regex::new(p).ok())
+        {
+            out = re.replace_all(&out, REDACTED).into_owned();
+        }
+        out
+    }
+
+    /// A raw query string with the values of `query_params` blanked. Pairs
+    /// are rewritten in place so the rest of the query is untouched.
+    fn query(&self, query: &str) -> String {
+        query
+            .split('&')
+            .map(|pair| {
+                let (name, _) = pair.split_once('=').unwrap_or((pair, ""));
+                let decoded: String = url::form_urlencoded::parse(name.as_bytes())
+                    .map(|(k, _)| k.into_owned())
+                    .collect();
+                if self
+                    .query_params
+                    .iter()
+                    .any(|p| p.eq_ignore_ascii_case(&decoded))
+                {
+                    format!("{name}={REDACTED}")
+                } else {
+                    pair.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_paths_query_values_and_patterns() {
+        let r = Redaction {
+            paths: vec!["**/*.key".into(), "secrets/**".into()],
+            query_params: vec!["access_token".into(), "sig".into()],
+            patterns: vec!["ghp_[A-Za-z0-9]+".into(), "(unclosed".into()],
+        };
+        assert_eq!(r.path("certs/server.key"), REDACTED);
+        assert_eq!(r.path("secrets"), REDACTED);
+        assert_eq!(r.path("docs/a.md"), "docs/a.md");
+        assert_eq!(r.path("notes/ghp_abc123.txt"), "notes/[redacted].txt");
+        assert_eq!(
+            r.url("https://api.example.org/x?page=2&ACCESS_TOKEN=abc&sig=d%3D&q=sig"),
+            "https://api.example.org/x?page=2&ACCESS_TOKEN=[redacted]&sig=[redacted]&q=sig"
+        );
+        assert_eq!(
+            r.url("https://example.org/repos/ghp_xyz/issues"),
+            "https://example.org/repos/[redacted]/issues"
+        );
+        assert_eq!(r.url("conn=3"), "conn=3");
+        assert!(Redaction::default().is_empty());
+    }
+}
@@ -0,0 +1,44 @@
+//! A policy that can be replaced while components are running.
+//!
+//! Hosts hold a [`SharedPolicy`] handle and take a snapshot per call, so a
+//! reload applies to the next host call without restarting anything. A call
+//! already in progress finishes under the policy it started with.
+
+use std::sync::{Arc, RwLock};
+
+use crate::Policy;
+
+#[derive(Debug, Clone)]
+pub struct SharedPolicy {
+    current: Arc<RwLock<Arc<Policy>>>,
+}
+
+impl SharedPolicy {
+    pub fn new(policy: Policy) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(policy))),
+        }
+    }
+
+    /// The policy in force right now.
+    pub fn current(&self) -> Arc<Policy> {
+        // The lock is only held to clone or swap an `Arc`, so a poisoned
+        // lock still guards a complete policy.
+        self.current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Swap in `policy` for every handle, returning the one it replaced.
+    pub fn replace(&self, policy: Policy) -> Arc<Policy> {
+        let mut guard = self.current.write().unwrap_or_else(|e| e.into_inner());
+        std::mem::replace(&mut *guard, Arc::new(policy))
+    }
+}
+
+impl From<Policy> for SharedPolicy {
+    fn from(policy: Policy) -> Self {
+        Self::new(policy)
+    }
+}
@@ -0,0 +1,179 @@
+//! Trial mode for broad grants.
+//!
+//! While a trial is active the broker records which domains and workspace
+//! paths are actually used. Once the configured number of runs has been
+//! observed, [`TrialState::proposal`] suggests a narrower grant covering only
+//! what was used, which the user can accept with a single action.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Policy;
+
+/// Narrowed grant proposed at the end of a trial.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Narrowing {
+    pub allowed_domains: Vec<String>,
+    /// Workspace-relative prefixes; empty keeps the whole workspace.
+    pub allowed_paths: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrialState {
+    pub runs_total: u32,
+    pub runs_observed: u32,
+    pub domains: BTreeSet<String>,
+    pub paths: BTreeSet<String>,
+    /// Narrowing offered to the user once the trial completed.
+    pub proposed: Option<Narrowing>,
+    /// Narrowing the user accepted; applied to the policy on every start.
+    pub accepted: Option<Narrowing>,
+}
+
+impl TrialState {
+    pub fn new(runs: u32) -> Self {
+        Self {
+            runs_total: runs,
+            ..Self::default()
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|e| format!("invalid trial state {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, content).map_err(|e| e.to_string())
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.accepted.is_none() && self.runs_observed < self.runs_total
+    }
+
+    pub fn record_domain(&mut self, domain: &str) {
+        if self.is_active() {
+            self.domains.insert(domain.to_string());
+        }
+    }
+
+    /// Record use of a directory (or root-level file) relative to the workspace.
+    pub fn record_path(&mut self, path: &str) {
+        if self.is_active() {
+            self.paths.insert(path.to_string());
+        }
+    }
+
+    pub fn finish_run(&mut self) {
+        if self.is_active() {
+            self.runs_observed += 1;
+        }
+    }
+
+    /// Accept the pending proposal, returning the narrowing now in force.
+    pub fn accept(&mut self) -> Option<&Narrowing> {
+        let proposed = self.proposed.take()?;
+        self.accepted = Some(proposed);
+        self.accepted.as_ref()
+    }
+
+    /// The narrowed grant, once every trial run has been observed and the
+    /// observed usage is strictly narrower than `policy`.
+    pub fn proposal(&self, policy: &Policy) -> Option<Narrowing> {
+        if self.accepted.is_some() || self.runs_observed < self.runs_total {
+            return None;
+        }
+        let allowed_domains: Vec<String> = policy
+            .allowed_domains
+            .iter()
+            .filter(|d| self.domains.iter().any(|h| crate::domain_matches(d, h)))
+            .cloned()
+            .collect();
+        let allowed_paths = collapse_prefixes(&self.paths);
+        let narrower_domains = allowed_domains.len() < policy.allowed_domains.len();
+        let narrower_paths = policy.allowed_paths.is_empty() && !allowed_paths.is_empty();
+        if !narrower_domains && !narrower_paths {
+            return None;
+        }
+        Some(Narrowing {
+            allowed_domains,
+            allowed_paths,
+        })
+    }
+}
+
+/// Drop prefixes covered by a shorter one; any use of the root keeps the
+/// whole workspace.
+fn collapse_prefixes(paths: &BTreeSet<String>) -> Vec<String> {
+    if paths.contains("") {
+        return Vec::new();
+    }
+    let mut out: Vec<String> = Vec::new();
+    for p in paths {
+        if !out.iter().any(|kept| crate::has_prefix(p, kept)) {
+            out.push(p.clone());
+        }
+    }
+    out
+}
+
+impl Policy {
+    /// Apply an accepted narrowing; never widens the current grant.
+    pub fn narrowed(&self, narrowing: &Narrowing) -> Policy {
+        let mut out = self.clone();
+        out.allowed_domains
+            .retain(|d| narrowing.allowed_domains.contains(d));
+        if !narrowing.allowed_paths.is_empty() {
+            out.allowed_paths = narrowing
+                .allowed_paths
+                .iter()
+                .filter(|p| self.is_path_allowed(p))
+                .cloned()
+                .collect();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proposes_only_what_was_used() {
+        let policy = Policy::new()
+            .with_allowed_domains(vec!["example.org".to_string(), "httpbin.org".to_string()]);
+        let mut trial = TrialState::new(2);
+        trial.record_domain("example.org");
+        trial.record_path("docs");
+        trial.record_path("docs/sub");
+        trial.finish_run();
+        assert_eq!(trial.proposal(&policy), None);
+        trial.record_path("exports");
+        trial.finish_run();
+
+        let narrowing = trial.proposal(&policy).expect("proposal");
+        assert_eq!(narrowing.allowed_domains, vec!["example.org".to_string()]);
+        assert_eq!(
+            narrowing.allowed_paths,
+            vec!["docs".to_string(), "exports".to_string()]
+        );
+
+        let narrowed = policy.narrowed(&narrowing);
+        assert!(narrowed.is_path_allowed("docs/readme.txt"));
+        assert!(!narrowed.is_path_allowed("secrets/key"));
+        assert!(!narrowed.is_url_allowed("https://httpbin.org/json"));
+    }
+}
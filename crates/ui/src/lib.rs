@@ -1,25 +1,72 @@
 #![forbid(unsafe_code)]
 
+use saf_audit::{AuditLog, AuditPage, AuditQuery, AuditReader, AuditRecord, AuditTail};
+use saf_codes::Code;
+use saf_core::{PermissionRequest, PromptAnswer, PromptHost};
+use saf_policy::trial::{Narrowing, TrialState};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use std::sync::Mutex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
 use tauri::{AppHandle, Manager, State};
 
 // Shared state between Tauri commands and the broker
 pub struct AppState {
     pub workspace: Mutex<Option<PathBuf>>,
     pub audit_log_path: Mutex<Option<PathBuf>>,
+    /// Offline mode: the broker refuses all network access.
+    pub offline: bool,
+    /// New audit records are being streamed as `audit-record` events.
+    pub following_audit: AtomicBool,
+    /// The control socket (or pipe) of the broker serving this UI.
+    pub socket: PathBuf,
 }
 
 // UI event types for communication
 #[derive(Serialize, Deserialize, Clone)]
 pub enum UiEvent {
-    WorkspaceSelected { path: String, id: String },
-    FilesListed { entries: Vec<String> },
-    FileRead { path: String, content: String },
-    NetworkFetched { url: String, response: String },
-    AuditEvent { message: String },
-    Error { message: String },
+    WorkspaceSelected {
+        path: String,
+        id: String,
+    },
+    FilesListed {
+        entries: Vec<String>,
+    },
+    FileRead {
+        path: String,
+        content: String,
+    },
+    NetworkFetched {
+        url: String,
+        response: String,
+    },
+    AuditEvent {
+        message: String,
+    },
+    /// An `ask` rule matched; answer with `answer_permission`.
+    PermissionRequested {
+        id: u64,
+        component: String,
+        capability: String,
+        subject: String,
+        rule_id: String,
+    },
+    /// `code` is a stable `saf_codes::Code` string such as `policy.domain_not_allowed`.
+    Error {
+        code: String,
+        message: String,
+    },
+}
+
+impl UiEvent {
+    pub fn error(code: Code, message: impl Into<String>) -> Self {
+        Self::Error {
+            code: code.as_str().to_string(),
+            message: message.into(),
+        }
+    }
 }
 
 // Tauri commands for broker interaction
@@ -84,7 +131,18 @@ async fn read_file(app: AppHandle, path: String) -> Result<String, String> {
 }
 
 #[tauri::command]
-async fn fetch_url(app: AppHandle, url: String) -> Result<String, String> {
+async fn fetch_url(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    url: String,
+) -> Result<String, String> {
+    if state.offline {
+        let message = "network access is disabled (offline mode)";
+        app.emit_all("error", UiEvent::error(Code::NetOffline, message))
+            .map_err(|e| e.to_string())?;
+        return Err(Code::NetOffline.with_message(message));
+    }
+
     // Call broker's fetch_json function
     // For demo, return mock response
     let response = if url.contains("example.org") {
@@ -107,31 +165,441 @@ async fn fetch_url(app: AppHandle, url: String) -> Result<String, String> {
     Ok(response.to_string())
 }
 
+/// How often a followed audit log is checked for new records.
+const AUDIT_FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+fn audit_log_path(state: &State<'_, AppState>) -> Result<PathBuf, String> {
+    match state
+        .audit_log_path
+        .lock()
+        .map_err(|e| e.to_string())?
+        .clone()
+    {
+        Some(path) => Ok(path),
+        None => Ok(current_workspace(state)?.join(".saf").join("audit.log")),
+    }
+}
+
+/// A page of the workspace audit log, newest first, filtered like
+/// `broker audit query`.
 #[tauri::command]
-async fn get_audit_log(app: AppHandle) -> Result<Vec<String>, String> {
-    // Read audit log from broker
-    // For demo, return mock entries
-    let entries = vec![
-        "2024-01-01 10:00:00 | broker.start".to_string(),
-        "2024-01-01 10:00:01 | fs.list_dir path=".to_string(),
-        "2024-01-01 10:00:02 | net.get_text url=https://httpbin.org/json".to_string(),
-    ];
+async fn get_audit_log(
+    state: State<'_, AppState>,
+    since_ms: Option<u64>,
+    code: Option<String>,
+    component: Option<String>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+) -> Result<AuditPage, String> {
+    let path = audit_log_path(&state)?;
+    let mut query = AuditQuery::default()
+        .with_page(offset.unwrap_or(0), limit.unwrap_or(100))
+        .with_newest_first();
+    query.since_ms = since_ms;
+    query.code = code.filter(|c| !c.is_empty());
+    query.component = component.filter(|c| !c.is_empty());
+    AuditReader::open(&path)?.query(&query)
+}
 
-    Ok(entries)
+/// Emit every record appended to the workspace audit log from now on as an
+/// `audit-record` event, whichever process writes it. Calling it again
+/// changes nothing.
+#[tauri::command]
+async fn follow_audit_log(app: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
+    if state.following_audit.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    let mut tail = AuditTail::open(&audit_log_path(&state)?)?;
+    if state.following_audit.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    std::thread::Builder::new()
+        .name("audit-follow".to_string())
+        .spawn(move || loop {
+            std::thread::sleep(AUDIT_FOLLOW_INTERVAL);
+            // A log being rotated may not read cleanly; the next poll
+            // catches up.
+            let Ok(records) = tail.poll() else {
+                continue;
+            };
+            for record in records {
+                if app.emit_all("audit-record", record).is_err() {
+                    return;
+                }
+            }
+        })
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn is_offline(state: State<'_, AppState>) -> Result<bool, String> {
+    Ok(state.offline)
+}
+
+/// New lines of a run's `log.jsonl` since `offset`, for polling from the UI.
+#[derive(Serialize)]
+struct RunLogChunk {
+    lines: Vec<String>,
+    offset: u64,
+    /// The run has written its report; no more lines will follow.
+    finished: bool,
+}
+
+#[tauri::command]
+async fn read_run_log(
+    state: State<'_, AppState>,
+    run_id: String,
+    offset: u64,
+) -> Result<RunLogChunk, String> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    if run_id.is_empty() || run_id.contains(['/', '\\', '.']) {
+        return Err(Code::FsInvalidPath.with_message("invalid run id"));
+    }
+    let run_dir = current_workspace(&state)?
+        .join(".saf")
+        .join("runs")
+        .join(&run_id);
+    let finished = run_dir.join("report.json").exists();
+    let mut file = match std::fs::File::open(run_dir.join("log.jsonl")) {
+        Ok(f) => f,
+        Err(_) => {
+            return Ok(RunLogChunk {
+                lines: Vec::new(),
+                offset,
+                finished,
+            })
+        }
+    };
+    file.seek(SeekFrom::Start(offset))
+        .map_err(|e| e.to_string())?;
+    let mut buf = String::new();
+    file.read_to_string(&mut buf).map_err(|e| e.to_string())?;
+    // Hold back a trailing partial line until it is complete.
+    let complete = buf.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    Ok(RunLogChunk {
+        lines: buf[..complete].lines().map(str::to_string).collect(),
+        offset: offset + complete as u64,
+        finished,
+    })
+}
+
+/// Per-component, per-domain network totals from `.saf/net_stats.json`.
+#[tauri::command]
+async fn get_net_stats(state: State<'_, AppState>) -> Result<serde_json::Value, String> {
+    let path = current_workspace(&state)?
+        .join(".saf")
+        .join("net_stats.json");
+    if !path.exists() {
+        return Ok(serde_json::json!({ "components": {} }));
+    }
+    let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&content).map_err(|e| e.to_string())
+}
+
+#[derive(Serialize)]
+struct TextPreview {
+    text: String,
+    start: u64,
+    end: u64,
+}
+
+/// Most bytes one preview shows; the UI pages through longer files.
+const MAX_PREVIEW_BYTES: u64 = 64 * 1024;
+
+/// A window of a workspace text file for previewing, clamped to character
+/// boundaries so multi-byte text is never shown corrupted. The read goes
+/// through the workspace policy like any component's, so denied paths and
+/// `.saf` stay hidden and the read is audited.
+#[tauri::command]
+async fn preview_text(
+    state: State<'_, AppState>,
+    path: String,
+    offset: u64,
+    len: u64,
+) -> Result<TextPreview, String> {
+    let workspace = current_workspace(&state)?;
+    let policy_file = workspace.join(".saf").join("policy.toml");
+    let policy = if policy_file.exists() {
+        saf_policy::Policy::from_file(&policy_file)?
+    } else {
+        saf_policy::Policy::default()
+    };
+    let log = PreviewLog(Mutex::new(AuditLog::new(&audit_log_path(&state)?)?));
+    let fs = PreviewFs { root: workspace };
+    let ctx = saf_core::Context {
+        fs: &fs,
+        net: &NoNet,
+        ws: &NoNet,
+        log: &log,
+        policy: &saf_policy::SharedPolicy::new(policy),
+        component: &saf_core::ComponentIdentity::new("ui"),
+        dry_run: false,
+        usage: &saf_core::Usage::default(),
+    };
+    let range = saf_core::read_text_range(&ctx, &path, offset, len.min(MAX_PREVIEW_BYTES))
+        .map_err(|e| match e {
+            saf_core::CoreError::Fs(msg) => msg,
+            e => e.code().with_message(&e.to_string()),
+        })?;
+    Ok(TextPreview {
+        text: range.text,
+        start: range.start,
+        end: range.end,
+    })
+}
+
+/// Read-only view of the workspace for previews. Paths arrive already
+/// checked by saf_core; symlinks are refused so none can lead out of the
+/// workspace.
+struct PreviewFs {
+    root: PathBuf,
+}
+
+impl PreviewFs {
+    fn open(&self, rel: &str) -> Result<std::fs::File, String> {
+        let mut path = self.root.clone();
+        for segment in rel.split('/') {
+            path.push(segment);
+            let meta = std::fs::symlink_metadata(&path)
+                .map_err(|e| Code::FsFailed.with_message(&e.to_string()))?;
+            if meta.file_type().is_symlink() {
+                return Err(Code::FsInvalidPath.with_message(&format!("{rel}: symbolic link")));
+            }
+        }
+        std::fs::File::open(&path).map_err(|e| Code::FsFailed.with_message(&e.to_string()))
+    }
+}
+
+impl saf_core::FsHost for PreviewFs {
+    fn list_dir(&self, _path: &str) -> Result<Vec<String>, String> {
+        Err(Code::FsFailed.with_message("previews only read files"))
+    }
+    fn read_text(&self, path: &str) -> Result<String, String> {
+        let bytes = self.read_range(path, 0, MAX_PREVIEW_BYTES)?;
+        String::from_utf8(bytes).map_err(|e| Code::FsFailed.with_message(&e.to_string()))
+    }
+    fn read_range(&self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>, String> {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let mut file = self.open(path)?;
+        file.seek(SeekFrom::Start(offset))
+            .map_err(|e| e.to_string())?;
+        let mut buf = Vec::new();
+        file.take(len)
+            .read_to_end(&mut buf)
+            .map_err(|e| e.to_string())?;
+        Ok(buf)
+    }
+    fn write_text(&self, _path: &str, _content: &str) -> Result<(), String> {
+        Err(Code::FsFailed.with_message("previews are read-only"))
+    }
+    fn append_bytes(&self, _path: &str, _content: &[u8]) -> Result<(), String> {
+        Err(Code::FsFailed.with_message("previews are read-only"))
+    }
+}
+
+/// Previews never touch the network.
+struct NoNet;
+
+impl saf_core::NetHost for NoNet {
+    fn fetch(&self, _url: &str) -> Result<saf_core::HttpResponse, saf_core::NetError> {
+        Err(saf_core::NetError::Failed(
+            Code::NetUnavailable.with_message("previews have no network"),
+        ))
+    }
+}
+
+impl saf_core::WsHost for NoNet {
+    fn connect(&self, _url: &str) -> Result<u64, String> {
+        Err(Code::NetUnavailable.with_message("previews have no network"))
+    }
+    fn send(&self, _conn: u64, _message: &str) -> Result<(), String> {
+        Err(Code::WsUnknownConnection.with_message("no such connection"))
+    }
+    fn receive(&self, _conn: u64) -> Result<Option<String>, String> {
+        Err(Code::WsUnknownConnection.with_message("no such connection"))
+    }
+    fn close(&self, _conn: u64) -> Result<(), String> {
+        Err(Code::WsUnknownConnection.with_message("no such connection"))
+    }
+}
+
+/// Audits previews to the workspace audit log.
+struct PreviewLog(Mutex<AuditLog>);
+
+impl saf_core::LogHost for PreviewLog {
+    fn record(&self, record: AuditRecord) {
+        if let Ok(mut log) = self.0.lock() {
+            let _ = log.record(&record);
+        }
+    }
+}
+
+fn trial_path(workspace: &Path) -> PathBuf {
+    workspace.join(".saf").join("trial.json")
+}
+
+fn current_workspace(state: &State<'_, AppState>) -> Result<PathBuf, String> {
+    state
+        .workspace
+        .lock()
+        .map_err(|e| e.to_string())?
+        .clone()
+        .ok_or_else(|| "no workspace selected".to_string())
+}
+
+#[tauri::command]
+async fn get_trial_proposal(state: State<'_, AppState>) -> Result<Option<Narrowing>, String> {
+    let workspace = current_workspace(&state)?;
+    Ok(TrialState::load(&trial_path(&workspace))?.and_then(|t| t.proposed))
+}
+
+/// Send one request to the broker on `socket` and return its result.
+fn broker_request(socket: &Path, request: serde_json::Value) -> Result<serde_json::Value, String> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let unreachable =
+        |e: std::io::Error| format!("{}: {e} (is `broker serve` running?)", socket.display());
+    #[cfg(unix)]
+    let stream = std::os::unix::net::UnixStream::connect(socket).map_err(unreachable)?;
+    #[cfg(not(unix))]
+    let stream = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(socket)
+        .map_err(unreachable)?;
+    let mut line = request.to_string();
+    line.push('\n');
+    (&stream)
+        .write_all(line.as_bytes())
+        .map_err(|e| e.to_string())?;
+    let mut response = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut response)
+        .map_err(|e| e.to_string())?;
+    let response: serde_json::Value = serde_json::from_str(&response)
+        .map_err(|e| format!("{}: unexpected response: {e}", socket.display()))?;
+    if response["ok"] == true {
+        Ok(response["result"].clone())
+    } else {
+        Err(response["error"]
+            .as_str()
+            .unwrap_or("request failed")
+            .to_string())
+    }
+}
+
+/// Accept the trial's narrowing through the broker, which refuses it
+/// outside an elevated session (`broker elevate`), as the CLI does, and
+/// otherwise changes the grant and audits it with the workspace's writer.
+#[tauri::command]
+async fn accept_trial_proposal(state: State<'_, AppState>) -> Result<Narrowing, String> {
+    let workspace = current_workspace(&state)?;
+    let workspaces = broker_request(
+        &state.socket,
+        serde_json::json!({"command": "workspace.list"}),
+    )?;
+    let id = workspaces
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|w| w["path"].as_str().map(Path::new) == Some(workspace.as_path()))
+        .and_then(|w| w["id"].as_str())
+        .ok_or_else(|| format!("{}: not a workspace the broker knows", workspace.display()))?
+        .to_string();
+    let narrowing = broker_request(
+        &state.socket,
+        serde_json::json!({"command": "trial.accept", "workspace": id}),
+    )?;
+    serde_json::from_value(narrowing).map_err(|e| e.to_string())
+}
+
+/// Permission prompts waiting for the user, by request id.
+#[derive(Default)]
+pub struct PromptState {
+    next_id: AtomicU64,
+    pending: Mutex<HashMap<u64, mpsc::Sender<PromptAnswer>>>,
+}
+
+/// How long a permission dialog may stay unanswered before the request is
+/// refused.
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Asks permission questions through a dialog in the UI.
+pub struct UiPrompt {
+    pub app: AppHandle,
+    pub state: Arc<PromptState>,
+}
+
+impl PromptHost for UiPrompt {
+    fn ask(&self, request: &PermissionRequest<'_>) -> PromptAnswer {
+        let id = self.state.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel();
+        match self.state.pending.lock() {
+            Ok(mut pending) => pending.insert(id, tx),
+            Err(_) => return PromptAnswer::DenySession,
+        };
+        let event = UiEvent::PermissionRequested {
+            id,
+            component: request.component.to_string(),
+            capability: request.capability.to_string(),
+            subject: request.subject.to_string(),
+            rule_id: request.rule_id.to_string(),
+        };
+        let answer = match self.app.emit_all("permission-requested", event) {
+            Ok(()) => rx
+                .recv_timeout(PROMPT_TIMEOUT)
+                .unwrap_or(PromptAnswer::DenySession),
+            Err(_) => PromptAnswer::DenySession,
+        };
+        if let Ok(mut pending) = self.state.pending.lock() {
+            pending.remove(&id);
+        }
+        answer
+    }
+}
+
+#[tauri::command]
+async fn answer_permission(
+    state: State<'_, Arc<PromptState>>,
+    id: u64,
+    answer: String,
+) -> Result<(), String> {
+    let answer =
+        PromptAnswer::parse(&answer).ok_or_else(|| format!("unknown answer {answer:?}"))?;
+    let pending = state.pending.lock().map_err(|e| e.to_string())?;
+    let tx = pending
+        .get(&id)
+        .ok_or_else(|| format!("no pending permission request {id}"))?;
+    tx.send(answer).map_err(|e| e.to_string())
 }
 
-pub fn launch() -> Result<(), String> {
+pub fn launch(offline: bool, socket: PathBuf) -> Result<(), String> {
     tauri::Builder::default()
         .manage(AppState {
             workspace: Mutex::new(None),
             audit_log_path: Mutex::new(None),
+            offline,
+            following_audit: AtomicBool::new(false),
+            socket,
         })
+        .manage(Arc::new(PromptState::default()))
         .invoke_handler(tauri::generate_handler![
             select_workspace,
             list_directory,
             read_file,
             fetch_url,
-            get_audit_log
+            get_audit_log,
+            follow_audit_log,
+            is_offline,
+            read_run_log,
+            get_net_stats,
+            preview_text,
+            get_trial_proposal,
+            accept_trial_proposal,
+            answer_permission
         ])
         .run(tauri::generate_context!())
         .map_err(|e| format!("Failed to launch Tauri app: {}", e))?;